@@ -168,7 +168,6 @@ impl<'a> super::Handler for Handler<'a> {
                     stream_id,
                     headers,
                     fin,
-                    ..
                 } => {
                     if let Some(handler) = self.url_handler.stream_handler(stream_id) {
                         handler.process_header_ready(stream_id, fin, headers);
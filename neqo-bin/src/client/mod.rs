@@ -21,7 +21,11 @@ use futures::{
     future::{select, Either},
     FutureExt, TryFutureExt,
 };
-use neqo_common::{self as common, qdebug, qerror, qinfo, qlog::NeqoQlog, qwarn, Datagram, Role};
+use neqo_common::{
+    self as common, qdebug, qerror, qinfo,
+    qlog::{NeqoQlog, QlogFormat},
+    qwarn, Datagram, Role,
+};
 use neqo_crypto::{
     constants::{TLS_AES_128_GCM_SHA256, TLS_AES_256_GCM_SHA384, TLS_CHACHA20_POLY1305_SHA256},
     init, Cipher, ResumptionToken,
@@ -453,7 +457,8 @@ impl<'a, H: Handler> Runner<'a, H> {
 fn qlog_new(args: &Args, hostname: &str, cid: &ConnectionId) -> Res<NeqoQlog> {
     if let Some(qlog_dir) = &args.shared.qlog_dir {
         let mut qlog_path = qlog_dir.clone();
-        let filename = format!("{hostname}-{cid}.sqlog");
+        let format = QlogFormat::JsonSeq;
+        let filename = format!("{hostname}-{cid}.{}", format.extension());
         qlog_path.push(filename);
 
         let f = OpenOptions::new()
@@ -470,7 +475,7 @@ fn qlog_new(args: &Args, hostname: &str, cid: &ConnectionId) -> Res<NeqoQlog> {
             std::time::Instant::now(),
             common::qlog::new_trace(Role::Client),
             EventImportance::Base,
-            Box::new(f),
+            format.writer(Box::new(f)),
         );
 
         Ok(NeqoQlog::enabled(streamer, qlog_path)?)
@@ -122,6 +122,22 @@ impl<'a> Decoder<'a> {
         }
     }
 
+    /// Get the length of a QUIC varint from its first byte.
+    #[must_use]
+    pub const fn varint_len_hint(first_byte: u8) -> usize {
+        1 << (first_byte >> 6)
+    }
+
+    /// Decodes a QUIC varint without moving the read position.
+    /// This returns the value and the number of bytes it occupies,
+    /// or `None` if the varint is incomplete.
+    #[must_use]
+    pub fn peek_varint(&self) -> Option<(u64, usize)> {
+        let mut dec = Decoder::new(self.as_ref());
+        let v = dec.decode_varint()?;
+        Some((v, dec.offset()))
+    }
+
     /// Decodes the rest of the buffer.  Infallible.
     pub fn decode_remainder(&mut self) -> &'a [u8] {
         let res = &self.buf[self.offset..];
@@ -729,6 +745,8 @@ mod tests {
             assert_eq!(enc, encoded);
 
             let mut dec = encoded.as_decoder();
+            assert_eq!(Decoder::varint_len_hint(encoded.as_ref()[0]), c.b.len() / 2);
+            assert_eq!(dec.peek_varint(), Some((c.v, c.b.len() / 2)));
             let v = dec.decode_varint().expect("should decode");
             assert_eq!(dec.remaining(), 0);
             assert_eq!(v, c.v);
@@ -751,6 +769,8 @@ mod tests {
         for c in &["40", "800000", "c0000000000000"] {
             let encoded = Encoder::from_hex(c);
             let mut dec = encoded.as_decoder();
+            assert!(dec.peek_varint().is_none());
+            assert_eq!(dec.remaining(), encoded.len());
             assert!(dec.decode_varint().is_none());
         }
     }
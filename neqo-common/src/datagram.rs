@@ -34,11 +34,16 @@ impl Datagram {
         }
     }
 
+    /// The local address for datagrams that are sent, or the remote address for
+    /// datagrams that were received.  A datagram that is sent needs to use this
+    /// address, as peers identify paths using the address pair.
     #[must_use]
     pub fn source(&self) -> SocketAddr {
         self.src
     }
 
+    /// The remote address for datagrams that are sent, or the local address for
+    /// datagrams that were received.
     #[must_use]
     pub fn destination(&self) -> SocketAddr {
         self.dst
@@ -136,10 +136,79 @@ impl IncrementalDecoderIgnore {
     }
 }
 
+/// A decoder that can be fed input in pieces, as it arrives.
+/// A varint or length-prefixed field that is only partly present
+/// in one input is retained, so that decoding resumes with the next.
+/// Only as much input as is needed for the current item is consumed.
+#[derive(Clone, Debug, Default)]
+pub struct StatefulDecoder {
+    /// The bytes of a varint that has been partially read.
+    varint: Vec<u8>,
+    /// The content of a length-prefixed field, once the length is known.
+    field: Option<IncrementalDecoderBuffer>,
+}
+
+impl StatefulDecoder {
+    /// The least number of bytes needed to make progress on the current item.
+    #[must_use]
+    pub fn min_remaining(&self) -> usize {
+        if let Some(field) = &self.field {
+            field.min_remaining()
+        } else if let Some(&b) = self.varint.first() {
+            Decoder::varint_len_hint(b) - self.varint.len()
+        } else {
+            1
+        }
+    }
+
+    /// Whether an item has been partially decoded.
+    #[must_use]
+    pub fn decoding_in_progress(&self) -> bool {
+        !self.varint.is_empty() || self.field.is_some()
+    }
+
+    /// Decode a QUIC varint, using any bytes retained from previous calls.
+    /// This returns `None` if the input runs out before the varint is complete.
+    pub fn decode_varint(&mut self, dv: &mut Decoder) -> Option<u64> {
+        if self.varint.is_empty() {
+            if let Some((v, len)) = dv.peek_varint() {
+                dv.skip(len);
+                return Some(v);
+            }
+        }
+        let first = self.varint.first().copied().or_else(|| dv.peek_byte())?;
+        let needed = Decoder::varint_len_hint(first) - self.varint.len();
+        let amount = min(needed, dv.remaining());
+        self.varint.extend_from_slice(dv.decode(amount)?);
+        if amount < needed {
+            return None;
+        }
+        let v = Decoder::from(&self.varint).decode_varint();
+        self.varint.clear();
+        v
+    }
+
+    /// Decode a field with a varint length prefix, using any bytes retained
+    /// from previous calls.  This returns `None` if the input runs out before
+    /// the field is complete.  A length that can't be represented as `usize`
+    /// is treated as being unbounded, so such a field never completes.
+    pub fn decode_vvec(&mut self, dv: &mut Decoder) -> Option<Vec<u8>> {
+        if self.field.is_none() {
+            let len = self.decode_varint(dv)?;
+            let len = usize::try_from(len).unwrap_or(usize::MAX);
+            self.field = Some(IncrementalDecoderBuffer::new(len));
+        }
+        let v = self.field.as_mut()?.consume(dv)?;
+        self.field = None;
+        Some(v)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
         Decoder, IncrementalDecoderBuffer, IncrementalDecoderIgnore, IncrementalDecoderUint,
+        StatefulDecoder,
     };
     use crate::codec::Encoder;
 
@@ -272,4 +341,68 @@ mod tests {
             assert!(res);
         }
     }
+
+    #[test]
+    fn stateful_varint_split() {
+        const V: u64 = 0x1234_5678_9abc_def0 & ((1 << 62) - 1);
+        let mut enc = Encoder::default();
+        enc.encode_varint(V);
+        assert_eq!(enc.len(), 8);
+
+        for split in 0..=enc.len() {
+            let mut dec = StatefulDecoder::default();
+            let mut dv = Decoder::from(&enc.as_ref()[..split]);
+            let res = dec.decode_varint(&mut dv);
+            assert_eq!(dv.remaining(), 0);
+            if split == enc.len() {
+                assert_eq!(res, Some(V));
+                continue;
+            }
+            assert_eq!(res, None);
+            assert_eq!(dec.decoding_in_progress(), split > 0);
+            assert_eq!(dec.min_remaining(), if split == 0 { 1 } else { 8 - split });
+
+            // Add padding so that we can verify that the decoder doesn't over-consume.
+            let mut rest = Encoder::from(&enc.as_ref()[split..]);
+            rest.encode_byte(0xff);
+            let mut dv = rest.as_decoder();
+            assert_eq!(dec.decode_varint(&mut dv), Some(V));
+            assert_eq!(dv.remaining(), 1);
+            assert!(!dec.decoding_in_progress());
+        }
+    }
+
+    #[test]
+    fn stateful_vvec_three_feeds() {
+        let data = [0x5a; 70];
+        let mut enc = Encoder::default();
+        enc.encode_vvec(&data);
+        let enc = enc.as_ref();
+
+        for first in 0..=enc.len() {
+            for second in first..=enc.len() {
+                let mut dec = StatefulDecoder::default();
+                let mut res = None;
+                for feed in [&enc[..first], &enc[first..second], &enc[second..]] {
+                    let mut dv = Decoder::from(feed);
+                    if let Some(v) = dec.decode_vvec(&mut dv) {
+                        assert!(res.is_none());
+                        res = Some(v);
+                    }
+                    assert_eq!(dv.remaining(), 0);
+                }
+                assert_eq!(res.as_deref(), Some(&data[..]));
+                assert!(!dec.decoding_in_progress());
+            }
+        }
+    }
+
+    #[test]
+    fn stateful_vvec_empty() {
+        let enc = Encoder::from_hex("00ff");
+        let mut dec = StatefulDecoder::default();
+        let mut dv = enc.as_decoder();
+        assert_eq!(dec.decode_vvec(&mut dv), Some(Vec::new()));
+        assert_eq!(dv.remaining(), 1);
+    }
 }
@@ -24,11 +24,15 @@ use enum_map::Enum;
 
 #[cfg(feature = "build-fuzzing-corpus")]
 pub use self::fuzz::write_item_to_fuzzing_corpus;
+#[cfg(feature = "tracing")]
+pub use tracing;
 pub use self::{
     codec::{Decoder, Encoder},
     datagram::Datagram,
     header::Header,
-    incrdecoder::{IncrementalDecoderBuffer, IncrementalDecoderIgnore, IncrementalDecoderUint},
+    incrdecoder::{
+        IncrementalDecoderBuffer, IncrementalDecoderIgnore, IncrementalDecoderUint, StatefulDecoder,
+    },
     tos::{IpTos, IpTosDscp, IpTosEcn},
 };
 
@@ -14,6 +14,7 @@ use std::{
 
 use env_logger::Builder;
 
+#[cfg(not(feature = "tracing"))]
 #[macro_export]
 macro_rules! do_log {
     (target: $target:expr, $lvl:expr, $($arg:tt)+) => ({
@@ -34,6 +35,42 @@ macro_rules! do_log {
     ($lvl:expr, $($arg:tt)+) => ($crate::do_log!(target: module_path!(), $lvl, $($arg)+))
 }
 
+/// With the `tracing` feature, log records become `tracing` events.
+#[cfg(feature = "tracing")]
+#[macro_export]
+macro_rules! do_log {
+    (target: $target:expr, $lvl:expr, $($arg:tt)+) => (
+        $crate::tracing_event!(target: $target, $lvl, "{}", format_args!($($arg)+))
+    );
+    ($lvl:expr, $($arg:tt)+) => ($crate::do_log!(target: module_path!(), $lvl, $($arg)+))
+}
+
+/// Emit a `tracing` event at the `tracing` level that matches a `log::Level`.
+#[cfg(feature = "tracing")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! tracing_event {
+    (target: $target:expr, $lvl:expr, $($rest:tt)+) => {
+        match $lvl {
+            ::log::Level::Error => {
+                $crate::tracing::event!(target: $target, $crate::tracing::Level::ERROR, $($rest)+);
+            }
+            ::log::Level::Warn => {
+                $crate::tracing::event!(target: $target, $crate::tracing::Level::WARN, $($rest)+);
+            }
+            ::log::Level::Info => {
+                $crate::tracing::event!(target: $target, $crate::tracing::Level::INFO, $($rest)+);
+            }
+            ::log::Level::Debug => {
+                $crate::tracing::event!(target: $target, $crate::tracing::Level::DEBUG, $($rest)+);
+            }
+            ::log::Level::Trace => {
+                $crate::tracing::event!(target: $target, $crate::tracing::Level::TRACE, $($rest)+);
+            }
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! log_subject {
     ($lvl:expr, $subject:expr) => {{
@@ -81,6 +118,7 @@ pub fn init(level_filter: Option<log::LevelFilter>) {
     });
 }
 
+#[cfg(not(feature = "tracing"))]
 #[macro_export]
 macro_rules! log_invoke {
     ($lvl:expr, $ctx:expr, $($arg:tt)*) => ( {
@@ -88,6 +126,52 @@ macro_rules! log_invoke {
         ::neqo_common::do_log!($lvl, "[{}] {}", $ctx, format!($($arg)*));
     } )
 }
+
+/// With the `tracing` feature, the context is a field of the event,
+/// rather than a prefix on the message.
+#[cfg(feature = "tracing")]
+#[macro_export]
+macro_rules! log_invoke {
+    ($lvl:expr, $ctx:expr, $($arg:tt)*) => (
+        $crate::tracing_event!(
+            target: module_path!(),
+            $lvl,
+            context = %$ctx,
+            "{}",
+            format_args!($($arg)*)
+        )
+    )
+}
+
+/// The guard that `qspan!` returns when the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+pub struct NoSpan;
+
+/// The guard that `qspan!` returns.
+#[cfg(not(feature = "tracing"))]
+pub type SpanGuard = NoSpan;
+#[cfg(feature = "tracing")]
+pub type SpanGuard = tracing::span::EnteredSpan;
+
+/// Enter a span that lasts until the returned guard is dropped.  The context
+/// is recorded as a field of the span, along with any other fields, which use
+/// the `tracing` syntax.  Without the `tracing` feature, this does nothing and
+/// none of the arguments are evaluated.
+#[cfg(not(feature = "tracing"))]
+#[macro_export]
+macro_rules! qspan {
+    ($name:literal, $ctx:expr $(, $($fields:tt)+)?) => {
+        $crate::log::NoSpan
+    };
+}
+
+#[cfg(feature = "tracing")]
+#[macro_export]
+macro_rules! qspan {
+    ($name:literal, $ctx:expr $(, $($fields:tt)+)?) => {
+        $crate::tracing::debug_span!($name, context = %$ctx $(, $($fields)+)?).entered()
+    };
+}
 #[macro_export]
 macro_rules! qerror {
     ([$ctx:expr], $($arg:tt)*) => (::neqo_common::log_invoke!(::log::Level::Error, $ctx, $($arg)*););
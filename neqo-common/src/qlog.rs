@@ -7,13 +7,20 @@
 use std::{
     cell::RefCell,
     fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    mem,
     path::{Path, PathBuf},
     rc::Rc,
+    time::Instant,
 };
 
 use qlog::{
-    streamer::QlogStreamer, CommonFields, Configuration, TraceSeq, VantagePoint, VantagePointType,
+    events::{EventImportance, EventType},
+    streamer::QlogStreamer,
+    CommonFields, Configuration, TraceSeq, VantagePoint, VantagePointType,
 };
+use serde_json::{json, Value};
 
 use crate::Role;
 
@@ -26,6 +33,77 @@ pub struct NeqoQlog {
 pub struct NeqoQlogShared {
     qlog_path: PathBuf,
     streamer: QlogStreamer,
+    filter: QlogFilter,
+}
+
+/// The events that are recorded for a category of qlog events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Importance {
+    /// Record nothing.
+    Off,
+    /// Record only core events.
+    Core,
+    /// Record core and base events.
+    Base,
+    /// Record all events.
+    Extra,
+}
+
+impl Importance {
+    fn admits(self, importance: EventImportance) -> bool {
+        let level = match self {
+            Self::Off => return false,
+            Self::Core => EventImportance::Core,
+            Self::Base => EventImportance::Base,
+            Self::Extra => EventImportance::Extra,
+        };
+        importance.is_contained_in(&level)
+    }
+}
+
+/// Selects which qlog events are recorded, based on their category and importance.
+/// Events in other categories, such as connectivity events, are always recorded.
+/// Events are checked before they are serialized, so this can greatly reduce
+/// the cost of logging, as well as the size of traces.
+///
+/// Independent of this filter, a `QlogStreamer` discards events that are less
+/// important than its own level, which is `Base` for the traces that neqo creates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QlogFilter {
+    /// Transport events, including the events for each packet sent and received.
+    pub transport: Importance,
+    /// Loss recovery and congestion control events.
+    pub recovery: Importance,
+    /// Security events.
+    pub security: Importance,
+    /// HTTP/3 and QPACK events.
+    pub http: Importance,
+}
+
+impl Default for QlogFilter {
+    fn default() -> Self {
+        Self {
+            transport: Importance::Base,
+            recovery: Importance::Base,
+            security: Importance::Base,
+            http: Importance::Base,
+        }
+    }
+}
+
+impl QlogFilter {
+    /// Whether events of type `ty` are recorded.
+    #[must_use]
+    pub fn allows(&self, ty: &EventType) -> bool {
+        let category = match ty {
+            EventType::TransportEventType(_) => self.transport,
+            EventType::RecoveryEventType(_) => self.recovery,
+            EventType::SecurityEventType(_) => self.security,
+            EventType::Http3EventType(_) | EventType::QpackEventType(_) => self.http,
+            _ => return true,
+        };
+        category.admits(EventImportance::from(*ty))
+    }
 }
 
 impl NeqoQlog {
@@ -44,10 +122,82 @@ impl NeqoQlog {
             inner: Rc::new(RefCell::new(Some(NeqoQlogShared {
                 streamer,
                 qlog_path: qlog_path.as_ref().to_owned(),
+                filter: QlogFilter::default(),
             }))),
         })
     }
 
+    /// Create an enabled `NeqoQlog` that writes a trace for `role` to `writer`
+    /// in the given format.
+    /// The log is finished, and `writer` flushed, when the last reference to it is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Will return `qlog::Error` if cannot write to the new log.
+    pub fn enabled_with_writer(
+        role: Role,
+        format: QlogFormat,
+        writer: Box<dyn Write + Send + Sync>,
+        description: impl AsRef<str>,
+    ) -> Result<Self, qlog::Error> {
+        Self::enabled_with_version(role, format, QlogVersion::default(), writer, description)
+    }
+
+    /// Create an enabled `NeqoQlog` like `enabled_with_writer`, that uses the
+    /// event names and fields from `version` of qlog.
+    ///
+    /// # Errors
+    ///
+    /// Will return `qlog::Error` if cannot write to the new log.
+    pub fn enabled_with_version(
+        role: Role,
+        format: QlogFormat,
+        version: QlogVersion,
+        writer: Box<dyn Write + Send + Sync>,
+        description: impl AsRef<str>,
+    ) -> Result<Self, qlog::Error> {
+        Self::start(role, version.writer(format.writer(writer)), description)
+    }
+
+    /// Create an enabled `NeqoQlog` that passes each serialized record of a trace
+    /// for `role` to `f`, starting with the record that describes the trace.
+    /// Unlike a writer, `f` has no way to fail; a callback that cannot deliver
+    /// a record has to drop it.
+    ///
+    /// # Errors
+    ///
+    /// Will return `qlog::Error` if cannot write to the new log.
+    pub fn enabled_with_callback(
+        role: Role,
+        f: impl FnMut(&str) + Send + Sync + 'static,
+        description: impl AsRef<str>,
+    ) -> Result<Self, qlog::Error> {
+        let writer = RecordCallback {
+            f: Box::new(f),
+            records: Records::default(),
+        };
+        Self::start(role, Box::new(writer), description)
+    }
+
+    fn start(
+        role: Role,
+        writer: Box<dyn Write + Send + Sync>,
+        description: impl AsRef<str>,
+    ) -> Result<Self, qlog::Error> {
+        let description = description.as_ref();
+        let streamer = QlogStreamer::new(
+            qlog::QLOG_VERSION.to_string(),
+            Some(description.to_string()),
+            Some(description.to_string()),
+            None,
+            Instant::now(),
+            new_trace(role),
+            EventImportance::Base,
+            writer,
+        );
+        Self::enabled(streamer, description)
+    }
+
     #[must_use]
     pub fn inner(&self) -> Rc<RefCell<Option<NeqoQlogShared>>> {
         Rc::clone(&self.inner)
@@ -59,14 +209,40 @@ impl NeqoQlog {
         Self::default()
     }
 
+    /// Change which events are recorded.  This affects all users of this log.
+    pub fn set_filter(&self, filter: QlogFilter) {
+        if let Some(inner) = self.inner.borrow_mut().as_mut() {
+            inner.filter = filter;
+        }
+    }
+
+    /// The filter that selects the events that are recorded, if logging is enabled.
+    #[must_use]
+    pub fn filter(&self) -> Option<QlogFilter> {
+        self.inner.borrow().as_ref().map(|inner| inner.filter)
+    }
+
+    /// Whether logging is enabled and events of type `ty` are recorded.
+    /// Check this before using `add_event_with_stream` to avoid the work
+    /// of creating an event that is only going to be discarded.
+    #[must_use]
+    pub fn wants(&self, ty: &EventType) -> bool {
+        self.filter().is_some_and(|f| f.allows(ty))
+    }
+
     /// If logging enabled, closure may generate an event to be logged.
     pub fn add_event<F>(&mut self, f: F)
     where
         F: FnOnce() -> Option<qlog::events::Event>,
     {
+        let Some(filter) = self.filter() else {
+            return;
+        };
         self.add_event_with_stream(|s| {
             if let Some(evt) = f() {
-                s.add_event(evt)?;
+                if filter.allows(&EventType::from(&evt.data)) {
+                    s.add_event(evt)?;
+                }
             }
             Ok(())
         });
@@ -77,9 +253,14 @@ impl NeqoQlog {
     where
         F: FnOnce() -> Option<qlog::events::EventData>,
     {
+        let Some(filter) = self.filter() else {
+            return;
+        };
         self.add_event_with_stream(|s| {
             if let Some(ev_data) = f() {
-                s.add_event_data_now(ev_data)?;
+                if filter.allows(&EventType::from(&ev_data)) {
+                    s.add_event_data_now(ev_data)?;
+                }
             }
             Ok(())
         });
@@ -91,15 +272,22 @@ impl NeqoQlog {
     where
         F: FnOnce(&mut QlogStreamer) -> Result<(), qlog::Error>,
     {
-        if let Some(inner) = self.inner.borrow_mut().as_mut() {
-            if let Err(e) = f(&mut inner.streamer) {
-                crate::do_log!(
-                    ::log::Level::Error,
-                    "Qlog event generation failed with error {}; closing qlog.",
-                    e
-                );
-                *self.inner.borrow_mut() = None;
-            }
+        let res = match self.inner.borrow_mut().as_mut() {
+            // The streamer reports `Done` for events that are less important
+            // than its level; those are skipped rather than being errors.
+            Some(inner) => f(&mut inner.streamer).or_else(|e| match e {
+                qlog::Error::Done => Ok(()),
+                e => Err(e),
+            }),
+            None => return,
+        };
+        if let Err(e) = res {
+            crate::do_log!(
+                ::log::Level::Error,
+                "Qlog event generation failed with error {}; closing qlog.",
+                e
+            );
+            self.inner.borrow_mut().take();
         }
     }
 }
@@ -118,6 +306,507 @@ impl Drop for NeqoQlogShared {
     }
 }
 
+/// How a qlog trace is serialized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QlogFormat {
+    /// A single JSON document.  This is only written once the trace is finished,
+    /// so nothing is written if the process exits before then.
+    Json,
+    /// JSON Text Sequences (RFC 7464), with a record for each event.
+    /// Each record is written out and flushed as soon as it is complete.
+    #[default]
+    JsonSeq,
+}
+
+impl QlogFormat {
+    /// The file extension for traces in this format.
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "qlog",
+            Self::JsonSeq => "sqlog",
+        }
+    }
+
+    /// Wrap `writer` so that the output of a `QlogStreamer`, which is always JSON-SEQ,
+    /// is written to it in this format.
+    #[must_use]
+    pub fn writer(self, writer: Box<dyn Write + Send + Sync>) -> Box<dyn Write + Send + Sync> {
+        let records = Records::default();
+        match self {
+            Self::Json => Box::new(JsonWriter {
+                inner: writer,
+                records,
+                header: None,
+                events: Vec::new(),
+            }),
+            Self::JsonSeq => Box::new(SeqWriter {
+                inner: writer,
+                records,
+            }),
+        }
+    }
+}
+
+/// The version of qlog that a trace follows, which determines the names of
+/// events and some of their fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QlogVersion {
+    /// qlog 0.3, which is what qvis understands.
+    #[default]
+    V0_3,
+    /// The vocabulary of the -10 IETF drafts, where QUIC events share a `quic`
+    /// category and HTTP/3 events are in `http3`.
+    Draft10,
+}
+
+impl QlogVersion {
+    /// The value of the `qlog_version` field in the header of a trace.
+    #[must_use]
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::V0_3 => qlog::QLOG_VERSION,
+            Self::Draft10 => "draft-10",
+        }
+    }
+
+    /// Wrap `writer` so that the output of a `QlogStreamer`, which is always qlog 0.3,
+    /// is written to it using this version.  This needs to come before `QlogFormat::writer`.
+    #[must_use]
+    pub fn writer(self, writer: Box<dyn Write + Send + Sync>) -> Box<dyn Write + Send + Sync> {
+        match self {
+            Self::V0_3 => writer,
+            Self::Draft10 => Box::new(VersionWriter {
+                inner: writer,
+                records: Records::default(),
+                version: self,
+                started: false,
+            }),
+        }
+    }
+
+    /// The name of an event in this version, given its name in qlog 0.3.
+    fn event_name(self, name: &str) -> Option<String> {
+        if self == Self::V0_3 {
+            return None;
+        }
+        let (category, event) = name.split_once(':')?;
+        let event = match (category, event) {
+            ("transport", "data_moved") => "stream_data_moved",
+            ("recovery", "metrics_updated") => "recovery_metrics_updated",
+            ("recovery", "parameters_set") => "recovery_parameters_set",
+            ("security", "key_retired") => "key_discarded",
+            (_, event) => event,
+        };
+        let category = match category {
+            "connectivity" | "transport" | "recovery" | "security" => "quic",
+            "http" => "http3",
+            category => category,
+        };
+        Some(format!("{category}:{event}"))
+    }
+
+    /// The trigger for a dropped packet in this version, given its value in qlog 0.3.
+    fn drop_trigger(self, trigger: &str) -> Option<&'static str> {
+        if self == Self::V0_3 {
+            return None;
+        }
+        Some(match trigger {
+            "invalid" => "header_parse_error",
+            "unsupported" => "unsupported_version",
+            "connection_unknown" => "unknown_connection_id",
+            "decryption_failure" => "payload_decrypt_error",
+            "key_unavailable" => "key_unavailable",
+            _ => "general",
+        })
+    }
+
+    /// Change an event from qlog 0.3 to this version.
+    fn translate(self, ev: &mut Value) {
+        let Some(name) = ev["name"].as_str() else {
+            return;
+        };
+        let dropped = name == "transport:packet_dropped";
+        if let Some(name) = self.event_name(name) {
+            ev["name"] = name.into();
+        }
+        if dropped {
+            if let Some(trigger) = ev["data"]["trigger"]
+                .as_str()
+                .and_then(|t| self.drop_trigger(t))
+            {
+                ev["data"]["trigger"] = trigger.into();
+            }
+        }
+    }
+}
+
+/// Rewrites the records from a `QlogStreamer` for a different version of qlog.
+struct VersionWriter {
+    inner: Box<dyn Write + Send + Sync>,
+    records: Records,
+    version: QlogVersion,
+    /// Whether the header has been written.
+    started: bool,
+}
+
+impl VersionWriter {
+    fn record(&mut self, record: &[u8]) -> io::Result<()> {
+        let mut v = serde_json::from_slice::<Value>(Records::unframe(record))?;
+        if self.started {
+            self.version.translate(&mut v);
+        } else {
+            v["qlog_version"] = self.version.label().into();
+            self.started = true;
+        }
+        self.inner.write_all(format!("\x1e{v}\n").as_bytes())
+    }
+}
+
+impl Write for VersionWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.records.push(buf);
+        while let Some(record) = self.records.next() {
+            self.record(&record)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let rest = self.records.rest();
+        if !rest.is_empty() {
+            self.record(&rest)?;
+        }
+        self.inner.flush()
+    }
+}
+
+/// What to do when a trace reaches `QlogLimits::max_trace_bytes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QlogFull {
+    /// Add an event that marks the trace as truncated, then stop recording.
+    #[default]
+    Stop,
+    /// Continue the trace in a new file, `{name}.1.{ext}`, then `{name}.2.{ext}`, and so on.
+    /// Each file starts with the header of the trace, so it can be read on its own.
+    /// Only the newest `keep` files are kept.
+    /// This only applies to traces that are written to files; other traces stop.
+    Rotate { keep: usize },
+}
+
+/// Limits on the size of JSON-SEQ qlog traces.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QlogLimits {
+    /// The most bytes to write to a trace, or to each file of a rotated trace.
+    /// There is no limit if this is `None`.
+    pub max_trace_bytes: Option<u64>,
+    /// What to do when the limit is reached.
+    pub on_full: QlogFull,
+}
+
+impl QlogLimits {
+    /// Space reserved for the event that marks a trace as truncated.
+    const MARKER_RESERVE: u64 = 128;
+
+    /// Wrap `writer` so that the JSON-SEQ trace written to it stops at the limit.
+    /// `QlogFull::Rotate` is treated as `QlogFull::Stop`, as there is nowhere to rotate to.
+    #[must_use]
+    pub fn writer(self, writer: Box<dyn Write + Send + Sync>) -> Box<dyn Write + Send + Sync> {
+        self.capped(writer, None)
+    }
+
+    /// Create the file at `path` for a JSON-SEQ trace that is limited in size.
+    /// This fails if the file exists.
+    ///
+    /// # Errors
+    ///
+    /// When the file cannot be created.
+    pub fn create_file(self, path: &Path) -> io::Result<Box<dyn Write + Send + Sync>> {
+        let file = Box::new(Rotation::create(path)?);
+        let rotation = match self.on_full {
+            QlogFull::Rotate { keep } => Some(Rotation {
+                path: path.to_owned(),
+                keep: keep.max(1),
+                index: 0,
+            }),
+            QlogFull::Stop => None,
+        };
+        Ok(self.capped(file, rotation))
+    }
+
+    fn capped(
+        self,
+        out: Box<dyn Write + Send + Sync>,
+        rotation: Option<Rotation>,
+    ) -> Box<dyn Write + Send + Sync> {
+        let Some(max) = self.max_trace_bytes else {
+            return out;
+        };
+        Box::new(CappedWriter {
+            out,
+            rotation,
+            records: Records::default(),
+            max,
+            written: 0,
+            header: None,
+            stopped: false,
+        })
+    }
+}
+
+/// The files that a rotated trace is written to.
+struct Rotation {
+    /// The first file.
+    path: PathBuf,
+    /// How many files to keep.
+    keep: usize,
+    /// The index of the current file.
+    index: usize,
+}
+
+impl Rotation {
+    fn create(path: &Path) -> io::Result<File> {
+        // Don't overwrite existing files, as file names can depend on values from peers.
+        OpenOptions::new().write(true).create_new(true).open(path)
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        if index == 0 {
+            return self.path.clone();
+        }
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match self.path.extension() {
+            Some(ext) => format!("{stem}.{index}.{}", ext.to_string_lossy()),
+            None => format!("{stem}.{index}"),
+        };
+        self.path.with_file_name(name)
+    }
+
+    fn next(&mut self) -> io::Result<File> {
+        self.index += 1;
+        if let Some(old) = self.index.checked_sub(self.keep) {
+            match fs::remove_file(self.path(old)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Self::create(&self.path(self.index))
+    }
+}
+
+/// Limits the size of a JSON-SEQ trace, either by stopping or by rotating files.
+struct CappedWriter {
+    out: Box<dyn Write + Send + Sync>,
+    rotation: Option<Rotation>,
+    records: Records,
+    max: u64,
+    written: u64,
+    /// The first record, which describes the trace.
+    header: Option<Vec<u8>>,
+    stopped: bool,
+}
+
+impl CappedWriter {
+    fn record(&mut self, record: &[u8]) -> io::Result<()> {
+        if self.stopped {
+            return Ok(());
+        }
+        let len = u64::try_from(record.len()).unwrap_or(u64::MAX);
+        if self.header.is_none() {
+            self.header = Some(record.to_vec());
+        } else if self
+            .written
+            .saturating_add(len)
+            .saturating_add(QlogLimits::MARKER_RESERVE)
+            > self.max
+            && !self.rotate()?
+        {
+            self.stopped = true;
+            let time = serde_json::from_slice::<Value>(Records::unframe(record))
+                .ok()
+                .and_then(|ev| ev["time"].as_f64())
+                .unwrap_or_default();
+            let marker = json!({
+                "time": time,
+                "name": "neqo:trace_truncated",
+                "data": { "max_trace_bytes": self.max },
+            });
+            self.out.write_all(format!("\x1e{marker}\n").as_bytes())?;
+            return self.out.flush();
+        }
+        self.out.write_all(record)?;
+        self.written = self.written.saturating_add(len);
+        Ok(())
+    }
+
+    /// Move a rotated trace to its next file.
+    /// Returns `false` if the trace is not rotated, or if the next file
+    /// cannot be created, in which case the trace stops instead.
+    fn rotate(&mut self) -> io::Result<bool> {
+        let Some(rotation) = self.rotation.as_mut() else {
+            return Ok(false);
+        };
+        let file = match rotation.next() {
+            Ok(file) => file,
+            Err(e) => {
+                crate::do_log!(
+                    ::log::Level::Warn,
+                    "Unable to rotate qlog trace to {}: {}; stopping.",
+                    rotation.path(rotation.index).display(),
+                    e
+                );
+                self.rotation = None;
+                return Ok(false);
+            }
+        };
+        self.out.flush()?;
+        self.out = Box::new(file);
+        let header = self.header.as_deref().unwrap_or_default();
+        self.out.write_all(header)?;
+        self.written = u64::try_from(header.len()).unwrap_or(u64::MAX);
+        Ok(true)
+    }
+}
+
+impl Write for CappedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.records.push(buf);
+        while let Some(record) = self.records.next() {
+            self.record(&record)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let rest = self.records.rest();
+        if !rest.is_empty() {
+            self.record(&rest)?;
+        }
+        self.out.flush()
+    }
+}
+
+/// Collects the output of a `QlogStreamer`, which writes each JSON-SEQ record in pieces.
+#[derive(Default)]
+struct Records {
+    buf: Vec<u8>,
+}
+
+impl Records {
+    fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Take the next complete record, including its framing.
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let end = self.buf.iter().position(|&b| b == b'\n')?;
+        Some(self.buf.drain(..=end).collect())
+    }
+
+    /// Take anything left over, which is an incomplete record.
+    fn rest(&mut self) -> Vec<u8> {
+        mem::take(&mut self.buf)
+    }
+
+    /// Remove the record separator and line feed from around a record.
+    fn unframe(record: &[u8]) -> &[u8] {
+        let record = record.strip_prefix(b"\x1e").unwrap_or(record);
+        record.strip_suffix(b"\n").unwrap_or(record)
+    }
+}
+
+/// Passes each record to a callback.
+struct RecordCallback {
+    f: Box<dyn FnMut(&str) + Send + Sync>,
+    records: Records,
+}
+
+impl RecordCallback {
+    fn deliver(&mut self, record: &[u8]) -> io::Result<()> {
+        let record = Records::unframe(record);
+        if !record.is_empty() {
+            let record = std::str::from_utf8(record)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            (self.f)(record);
+        }
+        Ok(())
+    }
+}
+
+impl Write for RecordCallback {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.records.push(buf);
+        while let Some(record) = self.records.next() {
+            self.deliver(&record)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let record = self.records.rest();
+        self.deliver(&record)
+    }
+}
+
+/// Writes JSON-SEQ one whole record at a time, so that the output
+/// is usable even if the process exits without finishing the trace.
+struct SeqWriter {
+    inner: Box<dyn Write + Send + Sync>,
+    records: Records,
+}
+
+impl Write for SeqWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.records.push(buf);
+        while let Some(record) = self.records.next() {
+            self.inner.write_all(&record)?;
+            self.inner.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.records.rest())?;
+        self.inner.flush()
+    }
+}
+
+/// Collects a trace and writes it as a single JSON document when it is flushed,
+/// which `QlogStreamer` only does when the trace is finished.
+struct JsonWriter {
+    inner: Box<dyn Write + Send + Sync>,
+    records: Records,
+    header: Option<Value>,
+    events: Vec<Value>,
+}
+
+impl Write for JsonWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.records.push(buf);
+        while let Some(record) = self.records.next() {
+            let v = serde_json::from_slice(Records::unframe(&record))?;
+            if self.header.is_none() {
+                self.header = Some(v);
+            } else {
+                self.events.push(v);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // The JSON-SEQ header has a single trace; a JSON document has a list of them.
+        if let Some(Value::Object(mut doc)) = self.header.take() {
+            let mut trace = doc.remove("trace").unwrap_or_default();
+            trace["events"] = Value::Array(mem::take(&mut self.events));
+            doc.insert("qlog_format".to_string(), "JSON".into());
+            doc.insert("traces".to_string(), Value::Array(vec![trace]));
+            serde_json::to_writer(&mut self.inner, &doc)?;
+        }
+        self.inner.flush()
+    }
+}
+
 #[must_use]
 pub fn new_trace(role: Role) -> qlog::TraceSeq {
     TraceSeq {
@@ -152,8 +841,25 @@ pub fn new_trace(role: Role) -> qlog::TraceSeq {
 
 #[cfg(test)]
 mod test {
-    use qlog::events::Event;
-    use test_fixture::EXPECTED_LOG_HEADER;
+    use std::{
+        fs,
+        io::{self, Write},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        time::Instant,
+    };
+
+    use qlog::{
+        events::{Event, EventImportance},
+        streamer::QlogStreamer,
+    };
+    use serde_json::Value;
+    use test_fixture::{SharedVec, EXPECTED_LOG_HEADER};
+
+    use super::{new_trace, NeqoQlog, QlogFormat, QlogFull, QlogLimits};
+    use crate::Role;
 
     const EV_DATA: qlog::events::EventData =
         qlog::events::EventData::SpinBitUpdated(qlog::events::connectivity::SpinBitUpdated {
@@ -166,6 +872,32 @@ mod test {
         "\n"
     );
 
+    /// A writer that returns an error for every write once `fail` is set.
+    #[derive(Clone, Default)]
+    struct FailingWriter {
+        fail: Arc<AtomicBool>,
+    }
+
+    impl FailingWriter {
+        fn fail(&self) {
+            self.fail.store(true, Ordering::Relaxed);
+        }
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.fail.load(Ordering::Relaxed) {
+                Err(io::Error::other("write failed"))
+            } else {
+                Ok(buf.len())
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.write(&[]).map(|_| ())
+        }
+    }
+
     #[test]
     fn new_neqo_qlog() {
         let (_log, contents) = test_fixture::new_neqo_qlog();
@@ -184,4 +916,262 @@ mod test {
             )
         );
     }
+
+    /// An error from the writer disables the log, rather than panicking.
+    #[test]
+    fn write_error() {
+        let writer = FailingWriter::default();
+        let streamer = QlogStreamer::new(
+            qlog::QLOG_VERSION.to_string(),
+            None,
+            None,
+            None,
+            Instant::now(),
+            new_trace(Role::Client),
+            EventImportance::Base,
+            Box::new(writer.clone()),
+        );
+        let mut log = NeqoQlog::enabled(streamer, "").unwrap();
+        writer.fail();
+        log.add_event(|| Some(Event::with_time(1.1, EV_DATA)));
+        assert!(log.filter().is_none());
+        // Nothing more is written.
+        log.add_event_data(|| panic!("the log is disabled"));
+    }
+
+    /// A writer that fails disables the log, whether that happens
+    /// when the trace is started or later.
+    #[test]
+    fn writer_error() {
+        let writer = FailingWriter::default();
+        writer.fail();
+        assert!(NeqoQlog::enabled_with_writer(
+            Role::Client,
+            QlogFormat::JsonSeq,
+            Box::new(writer),
+            "fail"
+        )
+        .is_err());
+
+        let writer = FailingWriter::default();
+        let mut log = NeqoQlog::enabled_with_writer(
+            Role::Client,
+            QlogFormat::JsonSeq,
+            Box::new(writer.clone()),
+            "fail",
+        )
+        .unwrap();
+        log.add_event(|| Some(Event::with_time(1.1, EV_DATA)));
+        assert!(log.filter().is_some());
+        writer.fail();
+        log.add_event(|| Some(Event::with_time(1.2, EV_DATA)));
+        assert!(log.filter().is_none());
+        log.add_event_data(|| panic!("the log is disabled"));
+    }
+
+    #[test]
+    fn callback() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let r = Arc::clone(&records);
+        let mut log = NeqoQlog::enabled_with_callback(
+            Role::Client,
+            move |rec| r.lock().unwrap().push(rec.to_string()),
+            "callback",
+        )
+        .unwrap();
+        log.add_event(|| Some(Event::with_time(1.1, EV_DATA)));
+        drop(log);
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[0]
+            .starts_with(r#"{"qlog_version":"0.3","qlog_format":"JSON-SEQ","title":"callback""#));
+        assert_eq!(
+            records[1],
+            EXPECTED_LOG_EVENT
+                .trim_matches(['\u{1e}', '\n'])
+                .replace("\"time\":0.0,", "\"time\":1.1,")
+        );
+    }
+
+    #[test]
+    fn json_seq() {
+        let contents = SharedVec::default();
+        let mut log = NeqoQlog::enabled_with_writer(
+            Role::Client,
+            QlogFormat::JsonSeq,
+            Box::new(contents.clone()),
+            "seq",
+        )
+        .unwrap();
+        log.add_event(|| Some(Event::with_time(1.1, EV_DATA)));
+
+        // Each record is available before the log is finished.
+        let text = contents.to_string();
+        let records = text
+            .split_terminator('\n')
+            .map(|r| {
+                let r = r.strip_prefix('\u{1e}').expect("record separator");
+                serde_json::from_str::<Value>(r).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["qlog_format"], "JSON-SEQ");
+        assert_eq!(records[0]["trace"]["vantage_point"]["type"], "client");
+        assert_eq!(records[1]["name"], "connectivity:spin_bit_updated");
+        assert_eq!(records[1]["data"]["state"], true);
+
+        drop(log);
+        assert_eq!(contents.to_string(), text);
+    }
+
+    #[test]
+    fn json() {
+        let contents = SharedVec::default();
+        let mut log = NeqoQlog::enabled_with_writer(
+            Role::Server,
+            QlogFormat::Json,
+            Box::new(contents.clone()),
+            "json",
+        )
+        .unwrap();
+        log.add_event(|| Some(Event::with_time(1.1, EV_DATA)));
+        // Nothing is written until the log is finished.
+        assert!(contents.to_string().is_empty());
+        drop(log);
+
+        let doc = serde_json::from_str::<Value>(&contents.to_string()).unwrap();
+        assert_eq!(doc["qlog_format"], "JSON");
+        assert_eq!(doc["qlog_version"], "0.3");
+        assert_eq!(doc["title"], "json");
+        assert!(doc.get("trace").is_none());
+        let trace = &doc["traces"][0];
+        assert_eq!(trace["vantage_point"]["type"], "server");
+        assert_eq!(trace["common_fields"]["time_format"], "relative");
+        assert_eq!(trace["events"][0]["time"], 1.1);
+        assert_eq!(trace["events"][0]["name"], "connectivity:spin_bit_updated");
+    }
+
+    /// Parse the records of a JSON-SEQ trace, checking that the first is a header.
+    fn parse_seq(trace: &str) -> Vec<Value> {
+        let records = trace
+            .split_terminator('\n')
+            .map(|r| serde_json::from_str::<Value>(r.strip_prefix('\u{1e}').unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records[0]["qlog_format"], "JSON-SEQ");
+        records
+    }
+
+    #[test]
+    fn size_limit_stop() {
+        const MAX: u64 = 1000;
+        let contents = SharedVec::default();
+        let limits = QlogLimits {
+            max_trace_bytes: Some(MAX),
+            on_full: QlogFull::Stop,
+        };
+        let mut log = NeqoQlog::enabled_with_writer(
+            Role::Client,
+            QlogFormat::JsonSeq,
+            limits.writer(Box::new(contents.clone())),
+            "stop",
+        )
+        .unwrap();
+        for _ in 0..100 {
+            log.add_event(|| Some(Event::with_time(1.1, EV_DATA)));
+        }
+        drop(log);
+
+        let trace = contents.to_string();
+        assert!(u64::try_from(trace.len()).unwrap() <= MAX);
+        let records = parse_seq(&trace);
+        assert!(records.len() > 2);
+        let last = records.last().unwrap();
+        assert_eq!(last["name"], "neqo:trace_truncated");
+        assert_eq!(last["data"]["max_trace_bytes"], MAX);
+    }
+
+    #[test]
+    fn size_limit_rotate() {
+        const MAX: u64 = 1000;
+        let dir = std::env::temp_dir().join(format!("neqo-qlog-rotate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let limits = QlogLimits {
+            max_trace_bytes: Some(MAX),
+            on_full: QlogFull::Rotate { keep: 2 },
+        };
+        let path = dir.join("trace.sqlog");
+        let mut log = NeqoQlog::enabled_with_writer(
+            Role::Client,
+            QlogFormat::JsonSeq,
+            limits.create_file(&path).unwrap(),
+            "rotate",
+        )
+        .unwrap();
+        // Add events until there have been two rotations.
+        let rotated = (0..1000).any(|_| {
+            log.add_event(|| Some(Event::with_time(1.1, EV_DATA)));
+            dir.join("trace.2.sqlog").exists()
+        });
+        assert!(rotated);
+        drop(log);
+
+        // The oldest file is removed.
+        let mut files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|f| f.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, ["trace.1.sqlog", "trace.2.sqlog"]);
+        for f in files {
+            let trace = fs::read_to_string(dir.join(f)).unwrap();
+            assert!(u64::try_from(trace.len()).unwrap() <= MAX);
+            let records = parse_seq(&trace);
+            assert_eq!(records[0]["title"], "rotate");
+            assert!(records[1..]
+                .iter()
+                .all(|r| r["name"] == "connectivity:spin_bit_updated"));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A trace stops if the file that it would rotate to already exists.
+    #[test]
+    fn size_limit_rotate_exists() {
+        const MAX: u64 = 1000;
+        let dir =
+            std::env::temp_dir().join(format!("neqo-qlog-rotate-exists-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("trace.1.sqlog"), "old").unwrap();
+        let limits = QlogLimits {
+            max_trace_bytes: Some(MAX),
+            on_full: QlogFull::Rotate { keep: 2 },
+        };
+        let path = dir.join("trace.sqlog");
+        let mut log = NeqoQlog::enabled_with_writer(
+            Role::Client,
+            QlogFormat::JsonSeq,
+            limits.create_file(&path).unwrap(),
+            "rotate",
+        )
+        .unwrap();
+        for _ in 0..100 {
+            log.add_event(|| Some(Event::with_time(1.1, EV_DATA)));
+        }
+        assert!(log.filter().is_some());
+        drop(log);
+
+        let trace = fs::read_to_string(&path).unwrap();
+        assert!(u64::try_from(trace.len()).unwrap() <= MAX);
+        let records = parse_seq(&trace);
+        let last = records.last().unwrap();
+        assert_eq!(last["name"], "neqo:trace_truncated");
+        assert_eq!(last["data"]["max_trace_bytes"], MAX);
+        // The existing file is left alone.
+        assert_eq!(
+            fs::read_to_string(dir.join("trace.1.sqlog")).unwrap(),
+            "old"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
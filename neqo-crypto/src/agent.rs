@@ -9,7 +9,7 @@ use std::{
     ffi::{CStr, CString},
     mem::{self, MaybeUninit},
     ops::{Deref, DerefMut},
-    os::raw::{c_uint, c_void},
+    os::raw::{c_int, c_uint, c_void},
     pin::Pin,
     ptr::{null, null_mut},
     rc::Rc,
@@ -27,13 +27,15 @@ use crate::{
     assert_initialized,
     auth::AuthenticationStatus,
     constants::{
-        Alert, Cipher, Epoch, Extension, Group, SignatureScheme, Version, TLS_VERSION_1_3,
+        Alert, Cipher, Epoch, Extension, Group, SignatureScheme, Version, TLS_AES_128_GCM_SHA256,
+        TLS_AES_256_GCM_SHA384, TLS_CHACHA20_POLY1305_SHA256, TLS_VERSION_1_3,
     },
     ech,
     err::{is_blocked, secstatus_to_res, Error, PRErrorCode, Res},
+    experimental_api,
     ext::{ExtensionHandler, ExtensionTracker},
-    null_safe_slice,
-    p11::{self, PrivateKey, PublicKey},
+    hkdf, null_safe_slice,
+    p11::{self, PK11SymKey, PrivateKey, PublicKey},
     prio,
     replay::AntiReplay,
     secrets::SecretHolder,
@@ -41,6 +43,14 @@ use crate::{
     time::{Time, TimeHolder},
 };
 
+experimental_api!(SSL_AddExternalPsk(
+    fd: *mut ssl::PRFileDesc,
+    psk: *mut PK11SymKey,
+    identity: *const u8,
+    identity_len: c_uint,
+    hash: ssl::SSLHashType::Type,
+));
+
 /// The maximum number of tickets to remember for a given connection.
 const MAX_TICKETS: usize = 4;
 
@@ -504,6 +514,30 @@ impl SecretAgent {
         self.set_option(ssl::Opt::SuppressEndOfEarlyData, true)
     }
 
+    /// Configure an external pre-shared key (PSK) with the given identity.
+    /// The hash function of `cipher` is used with the key, so the handshake
+    /// can only select cipher suites that use the same hash function.
+    /// A client offers this PSK; a server uses it to authenticate the handshake,
+    /// without a certificate, if a client offers the same identity.
+    /// NSS supports only one external PSK for each agent.
+    ///
+    /// # Errors
+    ///
+    /// If `cipher` is not supported, if a PSK was already added, or if the key
+    /// cannot be imported.
+    pub fn add_external_psk(&mut self, identity: &[u8], key: &[u8], cipher: Cipher) -> Res<()> {
+        let hash = match cipher {
+            TLS_AES_128_GCM_SHA256 | TLS_CHACHA20_POLY1305_SHA256 => {
+                ssl::SSLHashType::ssl_hash_sha256
+            }
+            TLS_AES_256_GCM_SHA384 => ssl::SSLHashType::ssl_hash_sha384,
+            _ => return Err(Error::UnsupportedCipher),
+        };
+        let psk = hkdf::import_key(TLS_VERSION_1_3, key)?;
+        let identity_len = c_uint::try_from(identity.len())?;
+        unsafe { SSL_AddExternalPsk(self.fd, *psk, identity.as_ptr(), identity_len, hash) }
+    }
+
     /// `set_alpn` sets a list of preferred protocols, starting with the most preferred.
     /// Though ALPN [RFC7301] permits octet sequences, this only allows for UTF-8-encoded
     /// strings.
@@ -845,6 +879,9 @@ pub struct Client {
     server_name: String,
     /// Records the resumption tokens we've received.
     resumption: Pin<Box<Vec<ResumptionToken>>>,
+    /// The nickname of the certificate to use if the server asks for one.
+    /// NSS holds a pointer to this, so it needs to live as long as the socket.
+    client_certificate: Option<CString>,
 }
 
 impl Client {
@@ -863,6 +900,7 @@ impl Client {
             agent,
             server_name,
             resumption: Box::pin(Vec::new()),
+            client_certificate: None,
         };
         client.ready()?;
         Ok(client)
@@ -982,6 +1020,25 @@ impl Client {
             }
         }
     }
+
+    /// Set the certificate that is used if the server requests client authentication.
+    /// The certificate and its private key are found using the given nickname.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the nickname is invalid or the underlying NSS functions fail.
+    pub fn set_client_certificate(&mut self, nickname: impl Into<String>) -> Res<()> {
+        let nickname = CString::new(nickname.into())?;
+        secstatus_to_res(unsafe {
+            ssl::SSL_GetClientAuthDataHook(
+                self.agent.fd,
+                Some(ssl::NSS_GetClientAuthData),
+                nickname.as_ptr().cast_mut().cast(),
+            )
+        })?;
+        self.client_certificate = Some(nickname);
+        Ok(())
+    }
 }
 
 impl Deref for Client {
@@ -1053,6 +1110,10 @@ pub struct Server {
     agent: SecretAgent,
     /// This holds the HRR callback context.
     zero_rtt_check: Option<Pin<Box<ZeroRttCheckState>>>,
+    /// The trust anchors for client certificates, if client authentication is required.
+    client_auth_cas: Option<p11::CertList>,
+    /// The server name indication (SNI) from the client.
+    server_name: Pin<Box<Option<String>>>,
 }
 
 impl Server {
@@ -1080,12 +1141,41 @@ impl Server {
         }
 
         agent.ready(true, true)?;
+        let mut server_name: Pin<Box<Option<String>>> = Box::pin(None);
+        secstatus_to_res(unsafe {
+            ssl::SSL_SNISocketConfigHook(agent.fd, Some(Self::sni_cb), as_c_void(&mut server_name))
+        })?;
         Ok(Self {
             agent,
             zero_rtt_check: None,
+            client_auth_cas: None,
+            server_name,
         })
     }
 
+    unsafe extern "C" fn sni_cb(
+        fd: *mut ssl::PRFileDesc,
+        names: *const ssl::SECItem,
+        count: c_uint,
+        arg: *mut c_void,
+    ) -> c_int {
+        let server_name = arg.cast::<Option<String>>().as_mut().unwrap();
+        if count > 0 {
+            let name = names.as_ref().unwrap();
+            // Only a host name is defined, so the first entry is the only one that matters.
+            *server_name = String::from_utf8(null_safe_slice(name.data, name.len).to_vec()).ok();
+            qdebug!([format!("{fd:p}")], "SNI {:?}", server_name);
+        }
+        // The same certificates are used for every name.
+        ssl::SSL_SNI_CURRENT_CONFIG_IS_USED
+    }
+
+    /// The server name that the client indicated, if any.
+    #[must_use]
+    pub fn server_name(&self) -> Option<&str> {
+        (*self.server_name).as_deref()
+    }
+
     unsafe extern "C" fn hello_retry_cb(
         first_hello: PRBool,
         client_token: *const u8,
@@ -1191,6 +1281,85 @@ impl Server {
         self.ech_config = cfg;
         Ok(())
     }
+
+    unsafe extern "C" fn client_auth_cb(
+        arg: *mut c_void,
+        fd: *mut ssl::PRFileDesc,
+        _check_sig: PRBool,
+        _is_server: PRBool,
+    ) -> ssl::SECStatus {
+        let Ok(cert) = p11::Certificate::from_ptr(ssl::SSL_PeerCertificate(fd).cast()) else {
+            return ssl::SECFailure;
+        };
+        let mut params_in: [p11::CERTValInParam; 2] = mem::zeroed();
+        params_in[0].type_ = p11::CERTValParamInType::cert_pi_trustAnchors;
+        params_in[0].value.pointer.chain = arg.cast();
+        params_in[1].type_ = p11::CERTValParamInType::cert_pi_end;
+        let mut params_out: [p11::CERTValOutParam; 1] = mem::zeroed();
+        params_out[0].type_ = p11::CERTValParamOutType::cert_po_end;
+        // On failure, NSS records the reason, which determines the alert that is sent.
+        let rv = p11::CERT_PKIXVerifyCert(
+            *cert,
+            p11::SECCertificateUsage::from(p11::certificateUsageSSLClient),
+            params_in.as_mut_ptr(),
+            params_out.as_mut_ptr(),
+            null_mut(),
+        );
+        if secstatus_to_res(rv).is_ok() {
+            qdebug!([format!("{fd:p}")], "Client certificate accepted");
+            ssl::SECSuccess
+        } else {
+            qwarn!([format!("{fd:p}")], "Client certificate rejected");
+            ssl::SECFailure
+        }
+    }
+
+    /// Refuse to resume sessions, so that every handshake is a full handshake.
+    /// Session tickets that clients present are ignored.
+    ///
+    /// # Errors
+    ///
+    /// See `set_option`.
+    pub fn disable_resumption(&mut self) -> Res<()> {
+        self.agent.set_option(ssl::Opt::Cache, false)
+    }
+
+    /// Require that clients authenticate with a certificate that chains to
+    /// one of the certificate authorities with the given nicknames.
+    /// The handshake fails if the client has no certificate or if the
+    /// certificate can't be validated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a certificate can't be found or the underlying NSS functions fail.
+    pub fn require_client_auth(&mut self, cas: &[impl AsRef<str>]) -> Res<()> {
+        // Set these first so that a failure below can't leave client authentication
+        // optional.  Without the hook, the handshake blocks when the client certificate
+        // arrives.
+        self.agent.set_option(ssl::Opt::RequestCertificate, true)?;
+        self.agent.set_option(ssl::Opt::RequireCertificate, true)?;
+
+        let anchors = p11::CertList::from_ptr(unsafe { p11::CERT_NewCertList() })?;
+        for n in cas {
+            let c = CString::new(n.as_ref())?;
+            let cert_ptr = unsafe { p11::PK11_FindCertFromNickname(c.as_ptr(), null_mut()) };
+            let Ok(cert) = p11::Certificate::from_ptr(cert_ptr) else {
+                return Err(Error::CertificateLoading);
+            };
+            secstatus_to_res(unsafe { p11::CERT_AddCertToListTail(*anchors, *cert) })?;
+            // The list now owns the certificate.
+            mem::forget(cert);
+        }
+        secstatus_to_res(unsafe {
+            ssl::SSL_AuthCertificateHook(
+                self.agent.fd,
+                Some(Self::client_auth_cb),
+                (*anchors).cast(),
+            )
+        })?;
+        self.client_auth_cas = Some(anchors);
+        Ok(())
+    }
 }
 
 impl Deref for Server {
@@ -54,7 +54,7 @@ pub use self::{
     err::{Error, PRErrorCode, Res},
     ext::{ExtensionHandler, ExtensionHandlerResult, ExtensionWriterResult},
     p11::{random, randomize, PrivateKey, PublicKey, SymKey},
-    replay::AntiReplay,
+    replay::{AntiReplay, AntiReplayWindow},
     secrets::SecretDirection,
     ssl::Opt,
 };
@@ -41,6 +41,18 @@ scoped_ptr!(
     SSL_ReleaseAntiReplayContext
 );
 
+/// The period over which an anti-replay context detects replayed ClientHellos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AntiReplayWindow {
+    /// The configured size of the window.
+    pub window: Duration,
+    /// The start of the previous window, which is the oldest time for which
+    /// ClientHellos are remembered.
+    pub low: Instant,
+    /// The end of the current window.
+    pub high: Instant,
+}
+
 /// `AntiReplay` is used by servers when processing 0-RTT handshakes.
 /// It limits the exposure of servers to replay attack by rejecting 0-RTT
 /// if it appears to be a replay.  There is a false-positive rate that can be
@@ -48,6 +60,9 @@ scoped_ptr!(
 #[allow(clippy::module_name_repetitions)]
 pub struct AntiReplay {
     ctx: AntiReplayContext,
+    /// When the context was created.
+    start: Instant,
+    window: Duration,
 }
 
 impl AntiReplay {
@@ -72,9 +87,41 @@ impl AntiReplay {
 
         Ok(Self {
             ctx: AntiReplayContext::from_ptr(ctx)?,
+            start: now,
+            window,
         })
     }
 
+    /// The size of the window, as configured.
+    #[must_use]
+    pub const fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// The bounds of the window at `now`.  The context keeps two filters that
+    /// each cover one window, so ClientHellos are remembered from the start of
+    /// the previous window, but never from before the context was created.
+    #[must_use]
+    pub fn bounds(&self, now: Instant) -> AntiReplayWindow {
+        let now = now.max(self.start);
+        let into_current = if self.window.is_zero() {
+            Duration::ZERO
+        } else {
+            let elapsed = now.duration_since(self.start).as_nanos();
+            Duration::from_nanos(
+                u64::try_from(elapsed % self.window.as_nanos()).unwrap_or_default(),
+            )
+        };
+        let current = now - into_current;
+        AntiReplayWindow {
+            window: self.window,
+            low: current
+                .checked_sub(self.window)
+                .map_or(self.start, |low| low.max(self.start)),
+            high: current + self.window,
+        }
+    }
+
     /// Configure the provided socket with this anti-replay context.
     pub(crate) fn config_socket(&self, fd: *mut PRFileDesc) -> Res<()> {
         unsafe { SSL_SetAntiReplayContext(fd, *self.ctx) }
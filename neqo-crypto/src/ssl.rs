@@ -40,6 +40,7 @@ pub const SECFailure: SECStatus = _SECStatus_SECFailure;
 #[derive(Debug, Copy, Clone)]
 pub enum Opt {
     Locking,
+    Cache,
     Tickets,
     OcspStapling,
     Alpn,
@@ -51,6 +52,8 @@ pub enum Opt {
     HelloDowngradeCheck,
     SuppressEndOfEarlyData,
     Grease,
+    RequestCertificate,
+    RequireCertificate,
 }
 
 impl Opt {
@@ -59,6 +62,7 @@ impl Opt {
     pub(crate) fn as_int(self) -> PRInt32 {
         let i = match self {
             Self::Locking => SSLOption::SSL_NO_LOCKS,
+            Self::Cache => SSLOption::SSL_NO_CACHE,
             Self::Tickets => SSLOption::SSL_ENABLE_SESSION_TICKETS,
             Self::OcspStapling => SSLOption::SSL_ENABLE_OCSP_STAPLING,
             Self::Alpn => SSLOption::SSL_ENABLE_ALPN,
@@ -70,6 +74,8 @@ impl Opt {
             Self::HelloDowngradeCheck => SSLOption::SSL_ENABLE_HELLO_DOWNGRADE_CHECK,
             Self::SuppressEndOfEarlyData => SSLOption::SSL_SUPPRESS_END_OF_EARLY_DATA,
             Self::Grease => SSLOption::SSL_ENABLE_GREASE,
+            Self::RequestCertificate => SSLOption::SSL_REQUEST_CERTIFICATE,
+            Self::RequireCertificate => SSLOption::SSL_REQUIRE_CERTIFICATE,
         };
         i as PRInt32
     }
@@ -77,7 +83,7 @@ impl Opt {
     // Some options are backwards, like SSL_NO_LOCKS, so use this to manage that.
     fn map_enabled(self, enabled: bool) -> PRIntn {
         let v = match self {
-            Self::Locking => !enabled,
+            Self::Locking | Self::Cache => !enabled,
             _ => enabled,
         };
         PRIntn::from(v)
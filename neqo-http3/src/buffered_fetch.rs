@@ -0,0 +1,294 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Fetches whose response is collected in memory, for `Http3Client::fetch_buffered`.
+
+use std::{cell::RefCell, rc::Rc};
+
+use neqo_common::Header;
+use neqo_transport::StreamId;
+
+use crate::{
+    CloseType, Error, Http3StreamInfo, HttpRecvStreamEvents, Priority, RecvStreamEvents, Res,
+    SendStreamEvents,
+};
+
+/// The default limit on the size of a buffered response body.
+const DEFAULT_MAX_BODY_SIZE: usize = 1 << 20;
+
+/// A request for `Http3Client::fetch_buffered`.
+#[derive(Debug, Clone)]
+pub struct RequestParams {
+    pub(crate) method: String,
+    pub(crate) scheme: String,
+    pub(crate) authority: String,
+    pub(crate) path: String,
+    pub(crate) headers: Vec<Header>,
+    pub(crate) body: Vec<u8>,
+    pub(crate) priority: Priority,
+    pub(crate) max_body_size: usize,
+}
+
+impl RequestParams {
+    #[must_use]
+    pub fn new(
+        method: impl Into<String>,
+        scheme: impl Into<String>,
+        authority: impl Into<String>,
+        path: impl Into<String>,
+    ) -> Self {
+        Self {
+            method: method.into(),
+            scheme: scheme.into(),
+            authority: authority.into(),
+            path: path.into(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            priority: Priority::default(),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    #[must_use]
+    pub fn headers(mut self, headers: &[Header]) -> Self {
+        self.headers = headers.to_vec();
+        self
+    }
+
+    /// The request body.  It is sent as flow control allows, and the request is
+    /// complete once all of it is sent.
+    #[must_use]
+    pub fn body(mut self, body: &[u8]) -> Self {
+        self.body = body.to_vec();
+        self
+    }
+
+    #[must_use]
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// The largest response body that is kept, 1 MiB by default.  A larger body
+    /// cancels the fetch, which then fails with `Error::ResponseBodyTooLarge`.
+    #[must_use]
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+}
+
+/// A complete response to a buffered fetch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferedResponse {
+    /// The final response headers.  Interim responses are not kept.
+    pub headers: Vec<Header>,
+    pub body: Vec<u8>,
+    pub trailers: Option<Vec<Header>>,
+}
+
+impl BufferedResponse {
+    /// The status code, if the `:status` header is valid.
+    #[must_use]
+    pub fn status(&self) -> Option<u16> {
+        self.headers
+            .iter()
+            .find(|h| h.name() == ":status")
+            .and_then(|h| h.value().parse().ok())
+    }
+}
+
+/// What is known about a buffered fetch.  The stream listeners record what happens
+/// on the stream, and `Http3Client` reads the body when `readable` is set.
+#[derive(Debug, Default)]
+struct FetchState {
+    headers: Option<Vec<Header>>,
+    body: Vec<u8>,
+    trailers: Option<Vec<Header>>,
+    readable: bool,
+    writable: bool,
+    done: bool,
+    /// The result, until it is taken with `FetchHandle::poll_complete`.
+    outcome: Option<Res<BufferedResponse>>,
+}
+
+impl FetchState {
+    fn complete(&mut self, outcome: Res<BufferedResponse>) {
+        if !self.done {
+            self.done = true;
+            self.outcome = Some(outcome);
+        }
+    }
+
+    fn finish(&mut self) {
+        let response = self
+            .headers
+            .take()
+            .map_or(Err(Error::HttpMessageError), |headers| {
+                Ok(BufferedResponse {
+                    headers,
+                    body: std::mem::take(&mut self.body),
+                    trailers: self.trailers.take(),
+                })
+            });
+        self.complete(response);
+    }
+}
+
+/// The listener for the streams of a buffered fetch.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FetchListener(Rc<RefCell<FetchState>>);
+
+impl RecvStreamEvents for FetchListener {
+    fn data_readable(&self, _stream_info: Http3StreamInfo) {
+        self.0.borrow_mut().readable = true;
+    }
+
+    fn recv_closed(&self, _stream_info: Http3StreamInfo, close_type: CloseType) {
+        let error = match close_type {
+            CloseType::Done => return,
+            CloseType::ResetRemote(error) => Error::StreamAborted(error),
+            CloseType::ResetApp(_) => Error::HttpRequestCancelled,
+            CloseType::LocalError(_) => Error::HttpGeneralProtocolStream,
+        };
+        self.0.borrow_mut().complete(Err(error));
+    }
+}
+
+impl HttpRecvStreamEvents for FetchListener {
+    fn header_ready(
+        &self,
+        _stream_info: Http3StreamInfo,
+        headers: Vec<Header>,
+        interim: bool,
+        fin: bool,
+    ) {
+        if interim {
+            return;
+        }
+        let mut state = self.0.borrow_mut();
+        state.headers = Some(headers);
+        if fin {
+            state.finish();
+        }
+    }
+
+    fn trailers_ready(&self, _stream_info: Http3StreamInfo, headers: Vec<Header>) {
+        self.0.borrow_mut().trailers = Some(headers);
+    }
+}
+
+impl SendStreamEvents for FetchListener {
+    fn data_writable(&self, _stream_info: Http3StreamInfo) {
+        self.0.borrow_mut().writable = true;
+    }
+}
+
+/// A handle for a fetch started with `Http3Client::fetch_buffered`.  The response
+/// is collected while the client is processed as usual.
+#[derive(Debug, Clone)]
+pub struct FetchHandle {
+    stream_id: StreamId,
+    state: Rc<RefCell<FetchState>>,
+}
+
+impl FetchHandle {
+    #[must_use]
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    /// The outcome of the fetch, once it is complete.  The outcome is only
+    /// returned once; after that, this returns `None` again.
+    ///
+    /// # Errors
+    ///
+    /// `ResponseBodyTooLarge` if the response body is larger than
+    /// `RequestParams::max_body_size`,
+    /// `StreamAborted` if the server reset the response stream,
+    /// `AlreadyClosed` if the connection closed before the response was complete,
+    /// or any other error that reading the response produced.
+    #[must_use]
+    pub fn poll_complete(&self) -> Option<Res<BufferedResponse>> {
+        self.state.borrow_mut().outcome.take()
+    }
+}
+
+/// The part of a buffered fetch that `Http3Client` drives.
+#[derive(Debug)]
+pub(crate) struct BufferedFetch {
+    stream_id: StreamId,
+    state: Rc<RefCell<FetchState>>,
+    /// The part of the request body that has not been sent yet.
+    request_body: Vec<u8>,
+    max_body_size: usize,
+}
+
+impl BufferedFetch {
+    pub fn new(
+        stream_id: StreamId,
+        listener: &FetchListener,
+        request_body: Vec<u8>,
+        max_body_size: usize,
+    ) -> Self {
+        Self {
+            stream_id,
+            state: Rc::clone(&listener.0),
+            request_body,
+            max_body_size,
+        }
+    }
+
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    pub fn handle(&self) -> FetchHandle {
+        FetchHandle {
+            stream_id: self.stream_id,
+            state: Rc::clone(&self.state),
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.state.borrow().done
+    }
+
+    pub fn complete(&self, outcome: Res<BufferedResponse>) {
+        self.state.borrow_mut().complete(outcome);
+    }
+
+    pub fn finish(&self) {
+        self.state.borrow_mut().finish();
+    }
+
+    pub fn request_body(&self) -> &[u8] {
+        &self.request_body
+    }
+
+    pub fn body_sent(&mut self, amount: usize) {
+        self.request_body.drain(..amount);
+    }
+
+    /// Whether more of the request body can be sent, since the last time this was called.
+    pub fn take_writable(&self) -> bool {
+        std::mem::take(&mut self.state.borrow_mut().writable)
+    }
+
+    pub fn take_readable(&self) -> bool {
+        std::mem::take(&mut self.state.borrow_mut().readable)
+    }
+
+    /// Add to the response body.  This returns `false` if the body gets too large.
+    pub fn append_body(&self, data: &[u8]) -> bool {
+        let mut state = self.state.borrow_mut();
+        if state.body.len() + data.len() > self.max_body_size {
+            return false;
+        }
+        state.body.extend_from_slice(data);
+        true
+    }
+}
@@ -40,21 +40,56 @@ pub enum WebTransportEvent {
         session_id: StreamId,
         datagram: Vec<u8>,
     },
+    /// A datagram was not sent, because it was too big for the session.
+    DatagramDroppedTooBig {
+        session_id: StreamId,
+    },
+    /// The peer asked for the session to be drained.  No new streams or
+    /// datagrams should be started and the session should be closed soon.
+    SessionDraining {
+        stream_id: StreamId,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Http3ClientEvent {
-    /// Response headers are received.
+    /// The final response headers are received. Informational (1xx) responses
+    /// are reported with `InterimResponse` instead.
     HeaderReady {
         stream_id: StreamId,
         headers: Vec<Header>,
-        interim: bool,
         fin: bool,
     },
+    /// An informational (1xx) response is received, e.g. 103 Early Hints. More interim
+    /// responses or the final response may follow.
+    InterimResponse {
+        stream_id: StreamId,
+        status: u16,
+        headers: Vec<Header>,
+    },
+    /// Response trailers are received. This follows all the `DataReadable` events
+    /// for the response body.
+    Trailers {
+        stream_id: StreamId,
+        headers: Vec<Header>,
+    },
     /// A stream can accept new data.
     DataWritable { stream_id: StreamId },
     /// New bytes available for reading.
     DataReadable { stream_id: StreamId },
+    /// A UDP payload arrived through the CONNECT-UDP tunnel on `stream_id`.
+    ConnectUdpDatagram {
+        stream_id: StreamId,
+        datagram: Vec<u8>,
+    },
+    /// A capsule arrived on a stream that uses the capsule protocol, see
+    /// `Http3Client::capsule_protocol`.  Capsules of unknown types are reported
+    /// as well.
+    CapsuleReceived {
+        stream_id: StreamId,
+        capsule_type: u64,
+        payload: Vec<u8>,
+    },
     /// Peer reset the stream or there was an parsing error.
     Reset {
         stream_id: StreamId,
@@ -98,8 +133,9 @@ pub enum Http3ClientEvent {
     ResumptionToken(ResumptionToken),
     /// Zero Rtt has been rejected.
     ZeroRttRejected,
-    /// Client has received a GOAWAY frame
-    GoawayReceived,
+    /// Client has received a GOAWAY frame.  Requests on streams at or above
+    /// `stream_id` were not processed by the server and can be retried elsewhere.
+    GoawayReceived { stream_id: StreamId },
     /// Connection state change.
     StateChange(Http3State),
     /// `WebTransport` events
@@ -155,11 +191,40 @@ impl HttpRecvStreamEvents for Http3ClientEvents {
         interim: bool,
         fin: bool,
     ) {
-        self.insert(Http3ClientEvent::HeaderReady {
+        if interim {
+            // The status has already been validated by `is_interim`.
+            let status = headers
+                .first()
+                .and_then(|h| h.value().parse().ok())
+                .unwrap_or_default();
+            self.insert(Http3ClientEvent::InterimResponse {
+                stream_id: stream_info.stream_id(),
+                status,
+                headers,
+            });
+        } else {
+            self.insert(Http3ClientEvent::HeaderReady {
+                stream_id: stream_info.stream_id(),
+                headers,
+                fin,
+            });
+        }
+    }
+
+    /// Add a new `Trailers` event.
+    fn trailers_ready(&self, stream_info: Http3StreamInfo, headers: Vec<Header>) {
+        self.insert(Http3ClientEvent::Trailers {
             stream_id: stream_info.stream_id(),
             headers,
-            interim,
-            fin,
+        });
+    }
+
+    /// Add a new `CapsuleReceived` event.
+    fn capsule_received(&self, stream_info: Http3StreamInfo, capsule_type: u64, payload: Vec<u8>) {
+        self.insert(Http3ClientEvent::CapsuleReceived {
+            stream_id: stream_info.stream_id(),
+            capsule_type,
+            payload,
         });
     }
 }
@@ -237,6 +302,20 @@ impl ExtendedConnectEvents for Http3ClientEvents {
             },
         ));
     }
+
+    fn datagram_dropped_too_big(&self, session_id: StreamId) {
+        self.insert(Http3ClientEvent::WebTransport(
+            WebTransportEvent::DatagramDroppedTooBig { session_id },
+        ));
+    }
+
+    fn session_draining(&self, session_id: StreamId) {
+        self.insert(Http3ClientEvent::WebTransport(
+            WebTransportEvent::SessionDraining {
+                stream_id: session_id,
+            },
+        ));
+    }
 }
 
 impl Http3ClientEvents {
@@ -248,6 +327,13 @@ impl Http3ClientEvents {
         });
     }
 
+    pub fn connect_udp_datagram(&self, stream_id: StreamId, datagram: Vec<u8>) {
+        self.insert(Http3ClientEvent::ConnectUdpDatagram {
+            stream_id,
+            datagram,
+        });
+    }
+
     pub fn push_canceled(&self, push_id: u64) {
         self.remove_events_for_push_id(push_id);
         self.insert(Http3ClientEvent::PushCanceled { push_id });
@@ -286,9 +372,9 @@ impl Http3ClientEvents {
     }
 
     /// Add a new `GoawayReceived` event.
-    pub(crate) fn goaway_received(&self) {
+    pub(crate) fn goaway_received(&self, stream_id: StreamId) {
         self.remove(|evt| matches!(evt, Http3ClientEvent::RequestsCreatable));
-        self.insert(Http3ClientEvent::GoawayReceived);
+        self.insert(Http3ClientEvent::GoawayReceived { stream_id });
     }
 
     pub fn insert(&self, event: Http3ClientEvent) {
@@ -322,7 +408,11 @@ impl Http3ClientEvents {
         self.remove(|evt| {
             matches!(evt,
                 Http3ClientEvent::HeaderReady { stream_id: x, .. }
+                | Http3ClientEvent::InterimResponse { stream_id: x, .. }
                 | Http3ClientEvent::DataReadable { stream_id: x }
+                | Http3ClientEvent::Trailers { stream_id: x, .. }
+                | Http3ClientEvent::ConnectUdpDatagram { stream_id: x, .. }
+                | Http3ClientEvent::CapsuleReceived { stream_id: x, .. }
                 | Http3ClientEvent::PushPromise { request_stream_id: x, .. }
                 | Http3ClientEvent::Reset { stream_id: x, .. } if *x == stream_id)
         });
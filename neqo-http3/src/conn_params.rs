@@ -4,17 +4,37 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::cmp::min;
+use std::{cmp::min, time::Duration};
 
 use neqo_qpack::QpackSettings;
 use neqo_transport::ConnectionParameters;
 
+use crate::settings::is_additional_setting_allowed;
+
 const QPACK_MAX_TABLE_SIZE_DEFAULT: u64 = 65536;
 const QPACK_TABLE_SIZE_LIMIT: u64 = (1 << 30) - 1;
 const QPACK_MAX_BLOCKED_STREAMS_DEFAULT: u16 = 20;
 const MAX_PUSH_STREAM_DEFAULT: u64 = 0;
 const WEBTRANSPORT_DEFAULT: bool = false;
 const HTTP3_DATAGRAM_DEFAULT: bool = false;
+const QLOG_REDACTED_HEADERS_DEFAULT: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+/// How long a server waits for a client to send each part of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTimeouts {
+    /// The time from opening the request stream until the headers are complete.
+    pub headers: Duration,
+    /// The longest time that the request body may stall.  Time during which the
+    /// application has not read all of the body that arrived is not counted.
+    pub body_idle: Duration,
+    /// The longest time that a request may take, including sending the response.
+    pub total: Option<Duration>,
+}
 
 #[derive(Debug, Clone)]
 pub struct Http3Parameters {
@@ -23,6 +43,14 @@ pub struct Http3Parameters {
     max_concurrent_push_streams: u64,
     webtransport: bool,
     http3_datagram: bool,
+    extended_connect_protocols: Vec<String>,
+    connect_udp: bool,
+    additional_settings: Vec<(u64, u64)>,
+    max_field_section_size: Option<u64>,
+    discard_cancelled_data: bool,
+    request_timeouts: Option<RequestTimeouts>,
+    qlog_http_frames: bool,
+    qlog_redacted_headers: Vec<String>,
 }
 
 impl Default for Http3Parameters {
@@ -37,6 +65,17 @@ impl Default for Http3Parameters {
             max_concurrent_push_streams: MAX_PUSH_STREAM_DEFAULT,
             webtransport: WEBTRANSPORT_DEFAULT,
             http3_datagram: HTTP3_DATAGRAM_DEFAULT,
+            extended_connect_protocols: Vec::new(),
+            connect_udp: false,
+            additional_settings: Vec::new(),
+            max_field_section_size: None,
+            discard_cancelled_data: false,
+            request_timeouts: None,
+            qlog_http_frames: false,
+            qlog_redacted_headers: QLOG_REDACTED_HEADERS_DEFAULT
+                .iter()
+                .map(|h| (*h).to_string())
+                .collect(),
         }
     }
 }
@@ -133,4 +172,144 @@ impl Http3Parameters {
     pub fn get_http3_datagram(&self) -> bool {
         self.http3_datagram
     }
+
+    /// Set the `:protocol` values, other than `webtransport`, that a server accepts in
+    /// an Extended CONNECT request (RFC 9220).  If this is not empty, the server sends
+    /// `SETTINGS_ENABLE_CONNECT_PROTOCOL` and passes such requests to the application
+    /// as ordinary requests.  Requests for other protocols are answered with a 501.
+    #[must_use]
+    pub fn extended_connect_protocols(mut self, protocols: &[&str]) -> Self {
+        self.extended_connect_protocols = protocols.iter().map(|p| (*p).to_string()).collect();
+        self
+    }
+
+    #[must_use]
+    pub fn get_extended_connect_protocols(&self) -> &[String] {
+        &self.extended_connect_protocols
+    }
+
+    /// Accept CONNECT-UDP requests (RFC 9298) on a server.  This enables Extended
+    /// CONNECT and HTTP Datagrams; the transport also needs
+    /// `ConnectionParameters::datagram_size` to be set for any datagrams to flow.
+    /// A client only needs `http3_datagram` to use `Http3Client::connect_udp`.
+    #[must_use]
+    pub fn connect_udp(mut self, connect_udp: bool) -> Self {
+        self.connect_udp = connect_udp;
+        if connect_udp {
+            self.http3_datagram = true;
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn get_connect_udp(&self) -> bool {
+        self.connect_udp
+    }
+
+    /// Add a setting to the SETTINGS frame, for extensions that neqo does not
+    /// implement or for experiments.  The peer's settings, including any that
+    /// neqo does not know, are available from `Http3Client::peer_settings`.
+    ///
+    /// # Panics
+    ///
+    /// When `id` is reserved from HTTP/2, is a setting that neqo sends itself, or
+    /// was already added; or when `id` or `value` is too large to encode.
+    #[must_use]
+    pub fn additional_setting(mut self, id: u64, value: u64) -> Self {
+        assert!(is_additional_setting_allowed(id));
+        assert!(!self.additional_settings.iter().any(|&(i, _)| i == id));
+        assert!(id < (1 << 62) && value < (1 << 62));
+        self.additional_settings.push((id, value));
+        self
+    }
+
+    #[must_use]
+    pub fn get_additional_settings(&self) -> &[(u64, u64)] {
+        &self.additional_settings
+    }
+
+    /// Limit the size of header sections that are accepted from the peer, counted
+    /// as in RFC 9114, Section 4.2.2, and advertise the limit in
+    /// `SETTINGS_MAX_FIELD_SECTION_SIZE`.  A request or response with a larger
+    /// header section is reset with `H3_EXCESSIVE_LOAD`.  By default there is no
+    /// limit and the setting is not sent.
+    ///
+    /// # Panics
+    ///
+    /// When `size` is too large to encode.
+    #[must_use]
+    pub fn max_field_section_size(mut self, size: u64) -> Self {
+        assert!(size < (1 << 62));
+        self.max_field_section_size = Some(size);
+        self
+    }
+
+    #[must_use]
+    pub fn get_max_field_section_size(&self) -> Option<u64> {
+        self.max_field_section_size
+    }
+
+    /// When a client resets a request stream, have a server drop the `Data` and
+    /// `Trailers` events for the request that the application has not taken yet.
+    /// By default, they are delivered before the `RequestCancelled` event.
+    #[must_use]
+    pub fn discard_cancelled_data(mut self, discard: bool) -> Self {
+        self.discard_cancelled_data = discard;
+        self
+    }
+
+    #[must_use]
+    pub fn get_discard_cancelled_data(&self) -> bool {
+        self.discard_cancelled_data
+    }
+
+    /// Have a server give up on requests that the client is too slow to send.
+    /// A request whose headers are incomplete is cancelled with
+    /// `H3_REQUEST_CANCELLED`; otherwise it is answered with a 408 response if
+    /// the response has not started.  Either way, the application gets a
+    /// `RequestTimedOut` event.
+    #[must_use]
+    pub fn request_timeout(mut self, timeouts: RequestTimeouts) -> Self {
+        self.request_timeouts = Some(timeouts);
+        self
+    }
+
+    #[must_use]
+    pub fn get_request_timeout(&self) -> Option<RequestTimeouts> {
+        self.request_timeouts
+    }
+
+    /// Log the HTTP/3 frames that are sent and received as `http:frame_created`
+    /// and `http:frame_parsed` qlog events.  This is off by default, because
+    /// HEADERS frames are logged with the full header list.
+    #[must_use]
+    pub fn qlog_http_frames(mut self, enable: bool) -> Self {
+        self.qlog_http_frames = enable;
+        self
+    }
+
+    #[must_use]
+    pub fn get_qlog_http_frames(&self) -> bool {
+        self.qlog_http_frames
+    }
+
+    /// The headers whose values are replaced with `[redacted]` when HEADERS
+    /// and PUSH_PROMISE frames are logged.  Names are compared without regard
+    /// to case.  By default, these are `authorization`, `proxy-authorization`,
+    /// `cookie`, and `set-cookie`.
+    #[must_use]
+    pub fn qlog_redacted_headers(mut self, names: &[&str]) -> Self {
+        self.qlog_redacted_headers = names.iter().map(|n| n.to_ascii_lowercase()).collect();
+        self
+    }
+
+    #[must_use]
+    pub fn get_qlog_redacted_headers(&self) -> &[String] {
+        &self.qlog_redacted_headers
+    }
+
+    /// Whether `SETTINGS_ENABLE_CONNECT_PROTOCOL` is sent.
+    pub(crate) fn extended_connect_enabled(&self) -> bool {
+        self.connect_udp || !self.extended_connect_protocols.is_empty()
+    }
 }
@@ -8,13 +8,15 @@
 
 use std::{
     cell::RefCell,
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeSet, HashMap, HashSet},
     fmt::Debug,
     mem,
     rc::Rc,
 };
 
-use neqo_common::{qdebug, qerror, qinfo, qtrace, qwarn, Decoder, Header, MessageType, Role};
+use neqo_common::{
+    qdebug, qerror, qinfo, qtrace, qwarn, Decoder, Encoder, Header, MessageType, Role,
+};
 use neqo_qpack::{decoder::QPackDecoder, encoder::QPackEncoder};
 use neqo_transport::{
     streams::SendOrder, AppError, CloseReason, Connection, DatagramTracking, State, StreamId,
@@ -25,13 +27,18 @@ use crate::{
     client_events::Http3ClientEvents,
     control_stream_local::ControlStreamLocal,
     control_stream_remote::ControlStreamRemote,
-    features::extended_connect::{
-        webtransport_session::WebTransportSession,
-        webtransport_streams::{WebTransportRecvStream, WebTransportSendStream},
-        ExtendedConnectEvents, ExtendedConnectFeature, ExtendedConnectType,
+    features::{
+        connect_udp,
+        extended_connect::{
+            webtransport_session::WebTransportSession,
+            webtransport_streams::{WebTransportRecvStream, WebTransportSendStream},
+            ExtendedConnectEvents, ExtendedConnectFeature, ExtendedConnectType,
+            WebTransportSessionStats,
+        },
     },
-    frames::HFrame,
+    frames::{Capsule, HFrame},
     push_controller::PushController,
+    qlog::FrameQlog,
     qpack_decoder_receiver::DecoderRecvStream,
     qpack_encoder_receiver::EncoderRecvStream,
     recv_message::{RecvMessage, RecvMessageInfo},
@@ -178,7 +185,8 @@ There are the following types of streams:
 - `NewStream`: there is only a receiver stream of this type and the handler is
                `NewStreamHeadReader`.
 - `Http`: `SendMessage` and `RecvMessage` handlers are responsible for this type of streams.
-- `Push`: `RecvMessage` is responsible for this type of streams.
+- `Push`: `RecvMessage` is responsible for this type of streams on the client-side and
+          `SendMessage` on the server-side.
 - `ExtendedConnect`: `WebTransportSession` is responsible sender and receiver handler.
 - `WebTransport(StreamId)`: `WebTransportSendStream` and `WebTransportRecvStream` are responsible
                             sender and receiver handler.
@@ -310,6 +318,9 @@ pub(crate) struct Http3Connection {
     pub send_streams: HashMap<StreamId, Box<dyn SendStream>>,
     pub recv_streams: HashMap<StreamId, Box<dyn RecvStream>>,
     webtransport: ExtendedConnectFeature,
+    /// Request streams that carry CONNECT-UDP datagrams.
+    connect_udp_streams: HashSet<StreamId>,
+    pub frame_qlog: FrameQlog,
 }
 
 impl ::std::fmt::Display for Http3Connection {
@@ -321,16 +332,15 @@ impl ::std::fmt::Display for Http3Connection {
 impl Http3Connection {
     /// Create a new connection.
     pub fn new(conn_params: Http3Parameters, role: Role) -> Self {
+        let frame_qlog = FrameQlog::new(&conn_params);
         Self {
             state: Http3State::Initializing,
-            control_stream_local: ControlStreamLocal::new(),
+            control_stream_local: ControlStreamLocal::new(frame_qlog.clone()),
             qpack_encoder: Rc::new(RefCell::new(QPackEncoder::new(
                 conn_params.get_qpack_settings(),
                 true,
             ))),
-            qpack_decoder: Rc::new(RefCell::new(QPackDecoder::new(
-                conn_params.get_qpack_settings(),
-            ))),
+            qpack_decoder: Rc::new(RefCell::new(Self::new_qpack_decoder(&conn_params))),
             webtransport: ExtendedConnectFeature::new(
                 ExtendedConnectType::WebTransport,
                 conn_params.get_webtransport(),
@@ -340,6 +350,8 @@ impl Http3Connection {
             streams_with_pending_data: BTreeSet::new(),
             send_streams: HashMap::new(),
             recv_streams: HashMap::new(),
+            connect_udp_streams: HashSet::new(),
+            frame_qlog,
             role,
         }
     }
@@ -475,9 +487,11 @@ impl Http3Connection {
 
         if let Some(recv_stream) = self.recv_streams.get_mut(&stream_id) {
             let res = recv_stream.receive(conn);
-            return self
+            let output = self
                 .handle_stream_manipulation_output(res, stream_id, conn)
                 .map(|(output, _)| output);
+            self.maybe_connect_udp_established(stream_id);
+            return output;
         }
         Ok(ReceiveOutput::NoOutput)
     }
@@ -496,6 +510,7 @@ impl Http3Connection {
                     .header_unblocked(conn);
                 let res = self.handle_stream_manipulation_output(res, stream_id, conn)?;
                 debug_assert!(matches!(res, (ReceiveOutput::NoOutput, _)));
+                self.maybe_connect_udp_established(stream_id);
             }
         }
         Ok(())
@@ -629,19 +644,25 @@ impl Http3Connection {
         }
     }
 
+    fn new_qpack_decoder(conn_params: &Http3Parameters) -> QPackDecoder {
+        let mut decoder = QPackDecoder::new(conn_params.get_qpack_settings());
+        if let Some(size) = conn_params.get_max_field_section_size() {
+            decoder.set_max_field_section_size(size);
+        }
+        decoder
+    }
+
     /// This is called when 0RTT has been reset to clear `send_streams`, `recv_streams` and
     /// settings.
     pub fn handle_zero_rtt_rejected(&mut self) -> Res<()> {
         if self.state == Http3State::ZeroRtt {
             self.state = Http3State::Initializing;
-            self.control_stream_local = ControlStreamLocal::new();
+            self.control_stream_local = ControlStreamLocal::new(self.frame_qlog.clone());
             self.qpack_encoder = Rc::new(RefCell::new(QPackEncoder::new(
                 self.local_params.get_qpack_settings(),
                 true,
             )));
-            self.qpack_decoder = Rc::new(RefCell::new(QPackDecoder::new(
-                self.local_params.get_qpack_settings(),
-            )));
+            self.qpack_decoder = Rc::new(RefCell::new(Self::new_qpack_decoder(&self.local_params)));
             self.settings_state = Http3RemoteSettingsState::NotReceived;
             self.streams_with_pending_data.clear();
             // TODO: investigate whether this code can automatically retry failed transactions.
@@ -654,15 +675,92 @@ impl Http3Connection {
         }
     }
 
-    pub fn handle_datagram(&mut self, datagram: &[u8]) {
+    /// Pass a datagram to the `WebTransport` session that it belongs to.  A datagram for a
+    /// CONNECT-UDP request is returned with the request stream ID instead, so that the
+    /// caller can report it.
+    pub fn handle_datagram(&mut self, datagram: &[u8]) -> Option<(StreamId, Vec<u8>)> {
         let mut decoder = Decoder::new(datagram);
-        let session = decoder
-            .decode_varint()
-            .and_then(|id| self.recv_streams.get_mut(&StreamId::from(id * 4)))
-            .and_then(|stream| stream.webtransport());
-        if let Some(s) = session {
+        let stream_id = StreamId::from(decoder.decode_varint()? * 4);
+        if self.connect_udp_streams.contains(&stream_id) {
+            if !self.recv_streams.contains_key(&stream_id) {
+                return None;
+            }
+            // Datagrams with an unknown context ID are dropped.
+            return (decoder.decode_varint()? == connect_udp::CONTEXT_ID)
+                .then(|| (stream_id, decoder.decode_remainder().to_vec()));
+        }
+        if let Some(s) = self
+            .recv_streams
+            .get_mut(&stream_id)
+            .and_then(|stream| stream.webtransport())
+        {
             s.borrow_mut().datagram(decoder.decode_remainder().to_vec());
         }
+        None
+    }
+
+    /// Wait for the response to the CONNECT-UDP request on `stream_id`.  Datagrams
+    /// are only associated with the request once the response has a 2xx status.
+    pub fn connect_udp_request(&mut self, stream_id: StreamId) -> Res<()> {
+        self.recv_streams
+            .get_mut(&stream_id)
+            .ok_or(Error::InvalidStreamId)?
+            .http_stream()
+            .ok_or(Error::InvalidStreamId)?
+            .connect_udp();
+        Ok(())
+    }
+
+    /// Register the CONNECT-UDP request on `stream_id` after its 2xx response arrived.
+    fn maybe_connect_udp_established(&mut self, stream_id: StreamId) {
+        if self.connect_udp_streams.contains(&stream_id) {
+            return;
+        }
+        if self
+            .recv_streams
+            .get_mut(&stream_id)
+            .and_then(|s| s.http_stream())
+            .is_some_and(|s| s.connect_udp_established())
+        {
+            self.connect_udp_register(stream_id);
+        }
+    }
+
+    /// Associate datagrams with the CONNECT-UDP request on `stream_id`.
+    pub fn connect_udp_register(&mut self, stream_id: StreamId) {
+        self.connect_udp_streams
+            .retain(|id| self.recv_streams.contains_key(id) || self.send_streams.contains_key(id));
+        self.connect_udp_streams.insert(stream_id);
+    }
+
+    pub fn connect_udp_send_datagram(
+        &mut self,
+        stream_id: StreamId,
+        conn: &mut Connection,
+        buf: &[u8],
+        id: impl Into<DatagramTracking>,
+    ) -> Res<()> {
+        if !self.connect_udp_streams.contains(&stream_id)
+            || !self.send_streams.contains_key(&stream_id)
+        {
+            return Err(Error::InvalidStreamId);
+        }
+        let mut dgram_data = Encoder::default();
+        dgram_data
+            .encode_varint(stream_id.as_u64() / 4)
+            .encode_varint(connect_udp::CONTEXT_ID)
+            .encode(buf);
+        Ok(conn.send_datagram(dgram_data.as_ref(), id)?)
+    }
+
+    /// The largest UDP payload that a CONNECT-UDP request on `stream_id` can send
+    /// in a datagram right now.
+    pub fn connect_udp_max_datagram_size(stream_id: StreamId, conn: &Connection) -> Res<u64> {
+        let overhead = Encoder::varint_len(stream_id.as_u64() / 4)
+            + Encoder::varint_len(connect_udp::CONTEXT_ID);
+        Ok(conn
+            .max_datagram_size()?
+            .saturating_sub(u64::try_from(overhead).unwrap()))
     }
 
     fn check_stream_exists(&self, stream_type: Http3StreamType) -> Res<()> {
@@ -691,8 +789,10 @@ impl Http3Connection {
         match stream_type {
             NewStreamType::Control => {
                 self.check_stream_exists(Http3StreamType::Control)?;
-                self.recv_streams
-                    .insert(stream_id, Box::new(ControlStreamRemote::new(stream_id)));
+                self.recv_streams.insert(
+                    stream_id,
+                    Box::new(ControlStreamRemote::new(stream_id, self.frame_qlog.clone())),
+                );
             }
 
             NewStreamType::Push(push_id) => {
@@ -836,6 +936,9 @@ impl Http3Connection {
         }
 
         final_headers.extend_from_slice(request.headers);
+        if !request.headers.iter().any(|h| h.name() == "priority") {
+            final_headers.extend(request.priority.header());
+        }
         Ok(final_headers)
     }
 
@@ -856,6 +959,8 @@ impl Http3Connection {
             request.method,
             request.target,
         );
+        // Check the size before a stream is used for a request that the peer would reject.
+        self.check_field_section_size(&Http3Connection::create_fetch_headers(request)?)?;
         let id = self.create_bidi_transport_stream(conn)?;
         self.fetch_with_stream(id, conn, send_events, recv_events, push_handler, request)?;
         Ok(id)
@@ -898,36 +1003,98 @@ impl Http3Connection {
         } else {
             Http3StreamType::Http
         };
+        let recv_message = RecvMessage::new(
+            &RecvMessageInfo {
+                message_type: MessageType::Response,
+                stream_type,
+                stream_id,
+                header_frame_type_read: false,
+            },
+            Rc::clone(&self.qpack_decoder),
+            recv_events,
+            push_handler,
+            PriorityHandler::new(false, request.priority),
+        )
+        .frame_qlog(self.frame_qlog.clone());
+        self.open_request(
+            stream_id,
+            conn,
+            stream_type,
+            send_events,
+            recv_message,
+            &final_headers,
+        )
+    }
+
+    /// Open a CONNECT tunnel to `authority` ([RFC 9114, Section 4.4][1]).  Unlike other
+    /// requests, the request headers do not include `:scheme` and `:path`.
+    ///
+    /// [1]: https://www.rfc-editor.org/rfc/rfc9114#section-4.4
+    pub fn connect_tunnel(
+        &mut self,
+        conn: &mut Connection,
+        send_events: Box<dyn SendStreamEvents>,
+        recv_events: Box<dyn HttpRecvStreamEvents>,
+        authority: &str,
+        headers: &[Header],
+    ) -> Res<StreamId> {
+        qinfo!([self], "CONNECT authority={}", authority);
+        let mut final_headers = vec![
+            Header::new(":method", "CONNECT"),
+            Header::new(":authority", authority),
+        ];
+        final_headers.extend_from_slice(headers);
+        self.check_field_section_size(&final_headers)?;
+        let stream_id = self.create_bidi_transport_stream(conn)?;
+        let recv_message = RecvMessage::new(
+            &RecvMessageInfo {
+                message_type: MessageType::Response,
+                stream_type: Http3StreamType::Http,
+                stream_id,
+                header_frame_type_read: false,
+            },
+            Rc::clone(&self.qpack_decoder),
+            recv_events,
+            None,
+            PriorityHandler::new(false, Priority::default()),
+        )
+        .connect_tunnel()
+        .frame_qlog(self.frame_qlog.clone());
+        self.open_request(
+            stream_id,
+            conn,
+            Http3StreamType::Http,
+            send_events,
+            recv_message,
+            &final_headers,
+        )?;
+        Ok(stream_id)
+    }
 
+    fn open_request(
+        &mut self,
+        stream_id: StreamId,
+        conn: &mut Connection,
+        stream_type: Http3StreamType,
+        send_events: Box<dyn SendStreamEvents>,
+        recv_message: RecvMessage,
+        headers: &[Header],
+    ) -> Res<()> {
         let mut send_message = SendMessage::new(
             MessageType::Request,
             stream_type,
             stream_id,
             self.qpack_encoder.clone(),
             send_events,
-        );
+        )
+        .frame_qlog(self.frame_qlog.clone());
 
         send_message
             .http_stream()
             .unwrap()
-            .send_headers(&final_headers, conn)?;
+            .send_headers(headers, conn)?;
 
-        self.add_streams(
-            stream_id,
-            Box::new(send_message),
-            Box::new(RecvMessage::new(
-                &RecvMessageInfo {
-                    message_type: MessageType::Response,
-                    stream_type,
-                    stream_id,
-                    header_frame_type_read: false,
-                },
-                Rc::clone(&self.qpack_decoder),
-                recv_events,
-                push_handler,
-                PriorityHandler::new(false, request.priority),
-            )),
-        );
+        self.add_streams(stream_id, Box::new(send_message), Box::new(recv_message));
 
         // Call immediately send so that at least headers get sent. This will make Firefox faster,
         // since it can send request body immediately in most cases and does not need to do
@@ -961,6 +1128,56 @@ impl Http3Connection {
         self.handle_stream_manipulation_output(res, stream_id, conn)
     }
 
+    /// Switch the receiving side of a request stream to the capsule protocol.  From
+    /// now on the body is decoded into capsules instead of being passed on as data.
+    ///
+    /// # Errors
+    ///
+    /// It returns `InvalidStreamId` if the stream does not exist or is not a request
+    /// stream, and `InvalidInput` if the stream is not receiving a body.
+    pub fn capsule_protocol(&mut self, conn: &mut Connection, stream_id: StreamId) -> Res<()> {
+        qdebug!([self], "capsule_protocol on stream {}.", stream_id);
+        let res = self
+            .recv_streams
+            .get_mut(&stream_id)
+            .ok_or(Error::InvalidStreamId)?
+            .http_stream()
+            .ok_or(Error::InvalidStreamId)?
+            .capsule_protocol(conn);
+        self.handle_stream_manipulation_output(res, stream_id, conn)?;
+        Ok(())
+    }
+
+    /// Send a capsule on a request stream.  The capsule is buffered as a whole, so
+    /// it is sent even if it is larger than what flow control currently allows.
+    ///
+    /// # Errors
+    ///
+    /// It returns `InvalidStreamId` if the stream does not exist and `InvalidInput` if
+    /// the headers have not been sent yet or the sending side is already closed.
+    pub fn send_capsule(
+        &mut self,
+        conn: &mut Connection,
+        stream_id: StreamId,
+        capsule_type: u64,
+        payload: &[u8],
+    ) -> Res<()> {
+        qtrace!(
+            [self],
+            "send_capsule on stream {} type={}.",
+            stream_id,
+            capsule_type
+        );
+        let mut enc = Encoder::default();
+        Capsule::new(capsule_type, payload).encode(&mut enc);
+        self.send_streams
+            .get_mut(&stream_id)
+            .ok_or(Error::InvalidStreamId)?
+            .send_data_atomic(conn, enc.as_ref())?;
+        self.stream_has_pending_data(stream_id);
+        Ok(())
+    }
+
     /// This is called when an application resets a stream.
     /// The application reset will close both sides.
     pub fn stream_reset_send(
@@ -1131,6 +1348,7 @@ impl Http3Connection {
             self.role,
             Rc::clone(&self.qpack_encoder),
             Rc::clone(&self.qpack_decoder),
+            self.frame_qlog.clone(),
         )));
         self.add_streams(
             id,
@@ -1398,6 +1616,20 @@ impl Http3Connection {
             .send_datagram(conn, buf, id)
     }
 
+    pub fn webtransport_session_stats(
+        &self,
+        session_id: StreamId,
+    ) -> Res<WebTransportSessionStats> {
+        Ok(self
+            .recv_streams
+            .get(&session_id)
+            .ok_or(Error::InvalidStreamId)?
+            .webtransport()
+            .ok_or(Error::InvalidStreamId)?
+            .borrow()
+            .stats())
+    }
+
     /// If the control stream has received frames `MaxPushId`, `Goaway`, `PriorityUpdateRequest` or
     /// `PriorityUpdateRequestPush` which handling is specific to the client and server, we must
     /// give them to the specific client/server handler.
@@ -1444,10 +1676,13 @@ impl Http3Connection {
             Http3RemoteSettingsState::ZeroRtt(settings) => {
                 self.webtransport.handle_settings(&new_settings);
                 let mut qpack_changed = false;
+                // Requests in 0-RTT might depend on any of these, so none can be reduced.
                 for st in &[
                     HSettingType::MaxHeaderListSize,
                     HSettingType::MaxTableCapacity,
                     HSettingType::BlockedStreams,
+                    HSettingType::EnableConnectProtocol,
+                    HSettingType::EnableH3Datagram,
                 ] {
                     let zero_rtt_value = settings.get(*st);
                     let new_value = new_settings.get(*st);
@@ -1475,7 +1710,9 @@ impl Http3Connection {
                         HSettingType::BlockedStreams => qpack_changed = true,
                         HSettingType::MaxHeaderListSize
                         | HSettingType::EnableWebTransport
-                        | HSettingType::EnableH3Datagram => (),
+                        | HSettingType::EnableH3Datagram
+                        | HSettingType::EnableConnectProtocol
+                        | HSettingType::Unknown(_) => (),
                     }
                 }
                 if qpack_changed {
@@ -1633,4 +1870,55 @@ impl Http3Connection {
     pub fn webtransport_enabled(&self) -> bool {
         self.webtransport.enabled()
     }
+
+    /// The settings of the peer, or those remembered from a previous connection
+    /// while 0-RTT is in use.  `None` until either is available.
+    pub fn peer_settings(&self) -> Option<&HSettings> {
+        match &self.settings_state {
+            Http3RemoteSettingsState::Received(settings)
+            | Http3RemoteSettingsState::ZeroRtt(settings) => Some(settings),
+            Http3RemoteSettingsState::NotReceived => None,
+        }
+    }
+
+    /// Check that the peer accepts a header section with `headers`, according to the
+    /// `SETTINGS_MAX_FIELD_SECTION_SIZE` that it sent, if any.
+    pub(crate) fn check_field_section_size(&self, headers: &[Header]) -> Res<()> {
+        let Some(settings) = self.peer_settings() else {
+            return Ok(());
+        };
+        // The size of a field section is defined in RFC 9114, Section 4.2.2.
+        let size = headers
+            .iter()
+            .map(|h| u64::try_from(h.name().len() + h.value().len()).unwrap() + 32)
+            .sum::<u64>();
+        if size > settings.get(HSettingType::MaxHeaderListSize) {
+            return Err(Error::FieldSectionTooLarge);
+        }
+        Ok(())
+    }
+
+    /// Whether the peer sent `SETTINGS_ENABLE_CONNECT_PROTOCOL`.
+    pub fn extended_connect_enabled(&self) -> bool {
+        match &self.settings_state {
+            Http3RemoteSettingsState::Received(settings)
+            | Http3RemoteSettingsState::ZeroRtt(settings) => {
+                settings.get(HSettingType::EnableConnectProtocol) == 1
+            }
+            Http3RemoteSettingsState::NotReceived => false,
+        }
+    }
+
+    /// Whether HTTP Datagrams are enabled locally and the peer sent
+    /// `SETTINGS_H3_DATAGRAM`.
+    pub fn http3_datagram_enabled(&self) -> bool {
+        self.local_params.get_http3_datagram()
+            && match &self.settings_state {
+                Http3RemoteSettingsState::Received(settings)
+                | Http3RemoteSettingsState::ZeroRtt(settings) => {
+                    settings.get(HSettingType::EnableH3Datagram) == 1
+                }
+                Http3RemoteSettingsState::NotReceived => false,
+            }
+    }
 }
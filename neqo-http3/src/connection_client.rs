@@ -26,13 +26,18 @@ use neqo_transport::{
 };
 
 use crate::{
+    buffered_fetch::{BufferedFetch, FetchHandle, FetchListener, RequestParams},
     client_events::{Http3ClientEvent, Http3ClientEvents},
     connection::{Http3Connection, Http3State, RequestDescription},
+    features::{
+        connect_udp,
+        extended_connect::{ExtendedConnectType, WebTransportSessionStats},
+    },
     frames::HFrame,
     push_controller::{PushController, RecvPushEvents},
     recv_message::{RecvMessage, RecvMessageInfo},
     request_target::AsRequestTarget,
-    settings::HSettings,
+    settings::{HSettingType, HSettings},
     Error, Http3Parameters, Http3StreamType, NewStreamType, Priority, PriorityHandler,
     ReceiveOutput, Res,
 };
@@ -137,11 +142,10 @@ fn alpn_from_quic_version(version: Version) -> &'static str {
 ///
 ///     while let Some(event) = client.next_event() {
 ///         match event {
-///             Http3ClientEvent::HeaderReady { stream_id, headers, interim, fin } => {
-///                 println!("New response headers received for stream {:?} [fin={?}, interim={:?}]: {:?}",
+///             Http3ClientEvent::HeaderReady { stream_id, headers, fin } => {
+///                 println!("New response headers received for stream {:?} [fin={?}]: {:?}",
 ///                     stream_id,
 ///                     fin,
-///                     interim,
 ///                     headers,
 ///                 );
 ///             }
@@ -291,6 +295,8 @@ pub struct Http3Client {
     base_handler: Http3Connection,
     events: Http3ClientEvents,
     push_handler: Rc<RefCell<PushController>>,
+    /// Fetches started with `fetch_buffered` that are not complete.
+    buffered_fetches: Vec<BufferedFetch>,
 }
 
 impl Display for Http3Client {
@@ -349,6 +355,7 @@ impl Http3Client {
             events: events.clone(),
             push_handler: Rc::new(RefCell::new(PushController::new(push_streams, events))),
             base_handler,
+            buffered_fetches: Vec::new(),
         }
     }
 
@@ -524,6 +531,8 @@ impl Http3Client {
     /// # Errors
     ///
     /// If a new stream cannot be created an error will be return.
+    /// `FieldSectionTooLarge` if the request headers are larger than the server accepts,
+    /// according to its `SETTINGS_MAX_FIELD_SECTION_SIZE`.
     ///
     /// # Panics
     ///
@@ -560,6 +569,273 @@ impl Http3Client {
         output
     }
 
+    /// Fetch a resource and collect the response in memory.  The request is sent
+    /// and closed, and the response is read as the client is processed, without
+    /// `Http3ClientEvent`s for the request.  Use `FetchHandle::poll_complete` to get
+    /// the response once it is complete.
+    ///
+    /// # Errors
+    ///
+    /// Any error that `fetch` returns.
+    pub fn fetch_buffered(&mut self, now: Instant, request: RequestParams) -> Res<FetchHandle> {
+        let RequestParams {
+            method,
+            scheme,
+            authority,
+            path,
+            headers,
+            body,
+            priority,
+            max_body_size,
+        } = request;
+        let listener = FetchListener::default();
+        let output = self.base_handler.fetch(
+            &mut self.conn,
+            Box::new(listener.clone()),
+            Box::new(listener.clone()),
+            Some(Rc::clone(&self.push_handler)),
+            &RequestDescription {
+                method: &method,
+                connect_type: None,
+                target: &(scheme, authority, path),
+                headers: &headers,
+                priority,
+            },
+        );
+        let stream_id = match output {
+            Ok(stream_id) => stream_id,
+            Err(e) => {
+                if e.connection_error() {
+                    self.close(now, e.code(), "");
+                }
+                return Err(e);
+            }
+        };
+        let mut fetch = BufferedFetch::new(stream_id, &listener, body, max_body_size);
+        self.send_buffered_request(&mut fetch);
+        let handle = fetch.handle();
+        self.buffered_fetches.push(fetch);
+        Ok(handle)
+    }
+
+    /// Send as much of the body of a buffered fetch as flow control allows, and
+    /// close the request once all of it is sent.
+    fn send_buffered_request(&mut self, fetch: &mut BufferedFetch) {
+        if !fetch.request_body().is_empty() {
+            match self.send_data(fetch.stream_id(), fetch.request_body()) {
+                Ok(amount) => fetch.body_sent(amount),
+                // The server does not want the rest of the body.
+                Err(_) => fetch.body_sent(fetch.request_body().len()),
+            }
+            if !fetch.request_body().is_empty() {
+                return;
+            }
+        }
+        // The server may have stopped the request already.
+        mem::drop(self.stream_close_send(fetch.stream_id()));
+    }
+
+    /// Read the responses of buffered fetches, and send more of their requests.
+    /// This returns whether the fetch is still in progress.
+    fn process_buffered_fetch(&mut self, fetch: &mut BufferedFetch, now: Instant) -> bool {
+        if matches!(
+            self.base_handler.state(),
+            Http3State::Closing(_) | Http3State::Closed(_)
+        ) {
+            fetch.complete(Err(Error::AlreadyClosed));
+        }
+        if fetch.done() {
+            return false;
+        }
+        if fetch.take_writable() && !fetch.request_body().is_empty() {
+            self.send_buffered_request(fetch);
+        }
+        if fetch.take_readable() {
+            let mut buf = [0; 4096];
+            loop {
+                match self.read_data(now, fetch.stream_id(), &mut buf) {
+                    Ok((amount, fin)) => {
+                        if !fetch.append_body(&buf[..amount]) {
+                            fetch.complete(Err(Error::ResponseBodyTooLarge));
+                            mem::drop(self.cancel_fetch(
+                                fetch.stream_id(),
+                                Error::HttpRequestCancelled.code(),
+                            ));
+                            return false;
+                        }
+                        if fin {
+                            fetch.finish();
+                            return false;
+                        }
+                        if amount == 0 {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        fetch.complete(Err(e));
+                        return false;
+                    }
+                }
+            }
+        }
+        let stream_id = fetch.stream_id();
+        if !self.base_handler.recv_streams.contains_key(&stream_id)
+            && !self.base_handler.send_streams.contains_key(&stream_id)
+        {
+            // The request was dropped without a response, e.g. when 0-RTT was rejected.
+            fetch.complete(Err(Error::HttpRequestRejected));
+        }
+        !fetch.done()
+    }
+
+    fn process_buffered_fetches(&mut self, now: Instant) {
+        let mut fetches = mem::take(&mut self.buffered_fetches);
+        fetches.retain_mut(|f| self.process_buffered_fetch(f, now));
+        self.buffered_fetches = fetches;
+    }
+
+    /// Open a CONNECT tunnel to `authority`, e.g. `example.com:443` ([RFC 9114][1]).
+    /// If the proxy answers with a 2xx status, the stream is a tunnel: `send_data` and
+    /// `read_data` carry the tunneled bytes, `stream_close_send` closes the sending
+    /// direction like a TCP half-close and `cancel_fetch` with `Error::HttpConnect`
+    /// aborts the tunnel like a TCP reset.  Any other status is an ordinary response.
+    ///
+    /// # Errors
+    ///
+    /// If a new stream cannot be created an error will be return.
+    ///
+    /// [1]: https://www.rfc-editor.org/rfc/rfc9114#section-4.4
+    pub fn connect(&mut self, now: Instant, authority: &str, headers: &[Header]) -> Res<StreamId> {
+        let output = self.base_handler.connect_tunnel(
+            &mut self.conn,
+            Box::new(self.events.clone()),
+            Box::new(self.events.clone()),
+            authority,
+            headers,
+        );
+        if let Err(e) = &output {
+            if e.connection_error() {
+                self.close(now, e.code(), "");
+            }
+        }
+        output
+    }
+
+    /// Open an Extended CONNECT request ([RFC 9220][1]) for `protocol`, e.g. `websocket`.
+    /// Once the server responds with a 2xx status, the stream carries data in both
+    /// directions using `send_data` and `read_data`.  For `WebTransport` use
+    /// `webtransport_create_session` instead.
+    ///
+    /// # Errors
+    ///
+    /// `Unavailable` if the server has not sent `SETTINGS_ENABLE_CONNECT_PROTOCOL`,
+    /// `InvalidInput` if `protocol` is `webtransport`,
+    /// or any error that `fetch` returns.
+    ///
+    /// [1]: https://www.rfc-editor.org/rfc/rfc9220
+    pub fn extended_connect<'x, 't: 'x, T>(
+        &mut self,
+        now: Instant,
+        target: &'t T,
+        protocol: &str,
+        headers: &[Header],
+    ) -> Res<StreamId>
+    where
+        T: AsRequestTarget<'x> + ?Sized + Debug,
+    {
+        if protocol == ExtendedConnectType::WebTransport.string() {
+            return Err(Error::InvalidInput);
+        }
+        if !self.base_handler.extended_connect_enabled() {
+            return Err(Error::Unavailable);
+        }
+        let mut connect_headers = vec![Header::new(":protocol", protocol)];
+        connect_headers.extend_from_slice(headers);
+        let output = self.base_handler.fetch(
+            &mut self.conn,
+            Box::new(self.events.clone()),
+            Box::new(self.events.clone()),
+            None,
+            &RequestDescription {
+                method: "CONNECT",
+                connect_type: None,
+                target,
+                headers: &connect_headers,
+                priority: Priority::default(),
+            },
+        );
+        if let Err(e) = &output {
+            if e.connection_error() {
+                self.close(now, e.code(), "");
+            }
+        }
+        output
+    }
+
+    /// Ask the proxy at `proxy_authority` to forward UDP to `target_host` and
+    /// `target_port` ([RFC 9298][1]).  After the proxy answers with a 2xx status,
+    /// `connect_udp_send_datagram` sends UDP payloads and received payloads are
+    /// reported with `Http3ClientEvent::ConnectUdpDatagram`.  The request stream
+    /// carries the capsule protocol: from the 2xx response on, the response body is
+    /// reported with `Http3ClientEvent::CapsuleReceived` events.  The stream is
+    /// closed to end the tunnel.
+    ///
+    /// # Errors
+    ///
+    /// `Unavailable` if HTTP Datagrams are not enabled with `Http3Parameters::http3_datagram`
+    /// or if the proxy does not support Extended CONNECT and HTTP Datagrams,
+    /// or any error that `fetch` returns.
+    ///
+    /// [1]: https://www.rfc-editor.org/rfc/rfc9298
+    pub fn connect_udp(
+        &mut self,
+        now: Instant,
+        proxy_authority: &str,
+        target_host: &str,
+        target_port: u16,
+    ) -> Res<StreamId> {
+        if !self.base_handler.http3_datagram_enabled() {
+            return Err(Error::Unavailable);
+        }
+        let path = connect_udp::target_path(target_host, target_port);
+        let stream_id = self.extended_connect(
+            now,
+            &("https", proxy_authority, path.as_str()),
+            connect_udp::PROTOCOL,
+            &[connect_udp::capsule_protocol_header()],
+        )?;
+        self.base_handler.connect_udp_request(stream_id)?;
+        Ok(stream_id)
+    }
+
+    /// Send a UDP payload through the CONNECT-UDP tunnel on `stream_id`.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if `stream_id` is not an open CONNECT-UDP request that the
+    /// proxy has accepted.  The function returns `TooMuchData` if the payload is bigger than
+    /// `connect_udp_max_datagram_size`.
+    pub fn connect_udp_send_datagram(
+        &mut self,
+        stream_id: StreamId,
+        buf: &[u8],
+        id: impl Into<DatagramTracking>,
+    ) -> Res<()> {
+        qtrace!([self], "connect_udp_send_datagram stream:{}", stream_id);
+        self.base_handler
+            .connect_udp_send_datagram(stream_id, &mut self.conn, buf, id)
+    }
+
+    /// Returns the current max size of a UDP payload that fits into a datagram
+    /// of the CONNECT-UDP request on `stream_id`.
+    ///
+    /// # Errors
+    ///
+    /// The function returns `NotAvailable` if datagrams are not enabled.
+    pub fn connect_udp_max_datagram_size(&self, stream_id: StreamId) -> Res<u64> {
+        Http3Connection::connect_udp_max_datagram_size(stream_id, &self.conn)
+    }
+
     /// Send an [`PRIORITY_UPDATE`-frame][1] on next `Http3Client::process_output()` call.
     /// Returns if the priority got changed.
     ///
@@ -573,15 +849,47 @@ impl Http3Client {
     }
 
     /// An application may cancel a stream(request).
-    /// Both sides, the receiviing and sending side, sending and receiving side, will be closed.
+    /// The sides of the stream that are still open are closed: `RESET_STREAM` is sent
+    /// if the request is still being sent and `STOP_SENDING` if the response is still
+    /// being received.  `error` defaults to `H3_REQUEST_CANCELLED`.
+    ///
+    /// If the response was not complete, the QPACK decoder tells the peer that the
+    /// stream was cancelled, any pushes that were promised on the request are
+    /// cancelled, and a single `Http3ClientEvent::Reset` with `local` set replaces
+    /// any events for the stream that were not yet taken.
     ///
     /// # Errors
     ///
     /// An error will be return if a stream does not exist.
-    pub fn cancel_fetch(&mut self, stream_id: StreamId, error: AppError) -> Res<()> {
+    pub fn cancel_fetch(
+        &mut self,
+        stream_id: StreamId,
+        error: impl Into<Option<AppError>>,
+    ) -> Res<()> {
+        let error = error
+            .into()
+            .unwrap_or_else(|| Error::HttpRequestCancelled.code());
         qinfo!([self], "reset_stream {} error={}.", stream_id, error);
+        let response_pending = self
+            .base_handler
+            .recv_streams
+            .get(&stream_id)
+            .is_some_and(|s| s.stream_type() == Http3StreamType::Http);
         self.base_handler
-            .cancel_fetch(stream_id, error, &mut self.conn)
+            .cancel_fetch(stream_id, error, &mut self.conn)?;
+        if response_pending {
+            self.push_handler.borrow_mut().cancel_request_pushes(
+                stream_id,
+                &mut self.conn,
+                &mut self.base_handler,
+            );
+            self.events.insert(Http3ClientEvent::Reset {
+                stream_id,
+                error,
+                local: true,
+            });
+        }
+        Ok(())
     }
 
     /// This is call when application is done sending a request.
@@ -639,6 +947,43 @@ impl Http3Client {
             .send_data(&mut self.conn, buf)
     }
 
+    /// The number of bytes that `send_data` would accept for a stream right now.  When this is
+    /// 0, wait for a `DataWritable` event before trying again.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the stream does not exist.
+    pub fn send_data_avail(&self, stream_id: StreamId) -> Res<usize> {
+        self.base_handler
+            .send_streams
+            .get(&stream_id)
+            .ok_or(Error::InvalidStreamId)?
+            .send_data_avail(&self.conn)
+    }
+
+    /// Send a trailer section on a request. This must be called after all the request body has
+    /// been supplied with `send_data` and before `stream_close_send`.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the stream does not exist,
+    /// `InvalidInput` if the request headers have not been sent or trailers have already been
+    /// sent, `InvalidHeader` if the trailers contain a pseudo-header, and
+    /// `FieldSectionTooLarge` if the server does not accept trailers of that size.
+    pub fn send_trailers(&mut self, stream_id: StreamId, headers: &[Header]) -> Res<()> {
+        qinfo!([self], "send_trailers on stream {}.", stream_id);
+        self.base_handler.check_field_section_size(headers)?;
+        self.base_handler
+            .send_streams
+            .get_mut(&stream_id)
+            .ok_or(Error::InvalidStreamId)?
+            .http_stream()
+            .ok_or(Error::InvalidStreamId)?
+            .send_trailers(headers, &mut self.conn)?;
+        self.base_handler.stream_has_pending_data(stream_id);
+        Ok(())
+    }
+
     /// Response data are read directly into a buffer supplied as a parameter of this function to
     /// avoid copying data.
     ///
@@ -662,6 +1007,45 @@ impl Http3Client {
         res
     }
 
+    /// Read the rest of the response body as capsules ([RFC 9297]).  Each capsule is
+    /// reported with a `CapsuleReceived` event, including capsules of unknown types,
+    /// and `read_data` does not return body data anymore.  A `DataReadable` event
+    /// signals the end of the stream.
+    ///
+    /// [RFC 9297]: https://www.rfc-editor.org/rfc/rfc9297
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the stream does not exist and `InvalidInput` if the
+    /// response headers have not been received yet.
+    pub fn capsule_protocol(&mut self, now: Instant, stream_id: StreamId) -> Res<()> {
+        let res = self
+            .base_handler
+            .capsule_protocol(&mut self.conn, stream_id);
+        if let Err(e) = &res {
+            if e.connection_error() {
+                self.close(now, e.code(), "");
+            }
+        }
+        res
+    }
+
+    /// Send a capsule on a request stream, after the request headers.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the stream does not exist and `InvalidInput` if the sending
+    /// side of the stream is already closed.
+    pub fn send_capsule(
+        &mut self,
+        stream_id: StreamId,
+        capsule_type: u64,
+        payload: &[u8],
+    ) -> Res<()> {
+        self.base_handler
+            .send_capsule(&mut self.conn, stream_id, capsule_type, payload)
+    }
+
     // API: Push streams
 
     /// Cancel a push
@@ -782,6 +1166,18 @@ impl Http3Client {
             .webtransport_send_datagram(session_id, &mut self.conn, buf, id)
     }
 
+    /// Get the counters for a `WebTransport` session.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if the session does not exist anymore.
+    pub fn webtransport_session_stats(
+        &self,
+        session_id: StreamId,
+    ) -> Res<WebTransportSessionStats> {
+        self.base_handler.webtransport_session_stats(session_id)
+    }
+
     /// Returns the current max size of a datagram that can fit into a packet.
     /// The value will change over time depending on the encoded size of the
     /// packet number, ack frames, etc.
@@ -900,14 +1296,14 @@ impl Http3Client {
         match self.base_handler.state() {
             Http3State::ZeroRtt | Http3State::Connected | Http3State::GoingAway(..) => {
                 let res = self.check_connection_events();
-                if self.check_result(now, &res) {
-                    return;
+                if !self.check_result(now, &res) {
+                    self.process_buffered_fetches(now);
+                    self.push_handler
+                        .borrow_mut()
+                        .maybe_send_max_push_id_frame(&mut self.base_handler);
+                    let res = self.base_handler.process_sending(&mut self.conn);
+                    self.check_result(now, &res);
                 }
-                self.push_handler
-                    .borrow_mut()
-                    .maybe_send_max_push_id_frame(&mut self.base_handler);
-                let res = self.base_handler.process_sending(&mut self.conn);
-                self.check_result(now, &res);
             }
             Http3State::Closed { .. } => {}
             _ => {
@@ -915,6 +1311,12 @@ impl Http3Client {
                 _ = self.check_result(now, &res);
             }
         }
+        if matches!(
+            self.base_handler.state(),
+            Http3State::Closing(_) | Http3State::Closed(_)
+        ) {
+            self.process_buffered_fetches(now);
+        }
     }
 
     /// The function should be called to check if there is a new UDP packet to be sent. It should
@@ -1057,11 +1459,14 @@ impl Http3Client {
                     }
                 }
                 ConnectionEvent::Datagram(dgram) => {
-                    self.base_handler.handle_datagram(&dgram);
+                    if let Some((stream_id, datagram)) = self.base_handler.handle_datagram(&dgram) {
+                        self.events.connect_udp_datagram(stream_id, datagram);
+                    }
                 }
                 ConnectionEvent::SendStreamComplete { .. }
                 | ConnectionEvent::OutgoingDatagramOutcome { .. }
-                | ConnectionEvent::IncomingDatagramDropped => {}
+                | ConnectionEvent::IncomingDatagramDropped
+                | ConnectionEvent::PathAbandoned { .. } => {}
             }
         }
         Ok(())
@@ -1157,19 +1562,22 @@ impl Http3Client {
 
         self.base_handler.add_recv_stream(
             stream_id,
-            Box::new(RecvMessage::new(
-                &RecvMessageInfo {
-                    message_type: MessageType::Response,
-                    stream_type: Http3StreamType::Push,
-                    stream_id,
-                    header_frame_type_read: false,
-                },
-                Rc::clone(&self.base_handler.qpack_decoder),
-                Box::new(RecvPushEvents::new(push_id, Rc::clone(&self.push_handler))),
-                None,
-                // TODO: think about the right prority for the push streams.
-                PriorityHandler::new(true, Priority::default()),
-            )),
+            Box::new(
+                RecvMessage::new(
+                    &RecvMessageInfo {
+                        message_type: MessageType::Response,
+                        stream_type: Http3StreamType::Push,
+                        stream_id,
+                        header_frame_type_read: false,
+                    },
+                    Rc::clone(&self.base_handler.qpack_decoder),
+                    Box::new(RecvPushEvents::new(push_id, Rc::clone(&self.push_handler))),
+                    None,
+                    // TODO: think about the right prority for the push streams.
+                    PriorityHandler::new(true, Priority::default()),
+                )
+                .frame_qlog(self.base_handler.frame_qlog.clone()),
+            ),
         );
         let res = self
             .base_handler
@@ -1230,7 +1638,7 @@ impl Http3Client {
             ));
         }
 
-        self.events.goaway_received();
+        self.events.goaway_received(goaway_stream_id);
 
         Ok(())
     }
@@ -1265,6 +1673,27 @@ impl Http3Client {
     pub fn webtransport_enabled(&self) -> bool {
         self.base_handler.webtransport_enabled()
     }
+
+    /// All settings that the server sent, as pairs of identifier and value, in
+    /// the order that they were received.  This includes settings that neqo
+    /// does not understand.  While 0-RTT is in use, these are the settings that
+    /// were remembered from the previous connection.  The list is empty until
+    /// the settings are known.
+    #[must_use]
+    pub fn peer_settings(&self) -> Vec<(u64, u64)> {
+        self.base_handler
+            .peer_settings()
+            .map(HSettings::to_pairs)
+            .unwrap_or_default()
+    }
+
+    /// The value of a setting from the server, or `None` if the server's settings
+    /// are not known yet.  Settings that the server did not send have their
+    /// default value.
+    #[must_use]
+    pub fn peer_setting(&self, setting: HSettingType) -> Option<u64> {
+        self.base_handler.peer_settings().map(|s| s.get(setting))
+    }
 }
 
 impl EventProvider for Http3Client {
@@ -1766,6 +2195,9 @@ mod tests {
 
     const HTTP_HEADER_FRAME_0: &[u8] = &[0x01, 0x06, 0x00, 0x00, 0xd9, 0x54, 0x01, 0x30];
 
+    // A trailer section with a single field, cache-control: no-cache.
+    const HTTP_TRAILER_FRAME: &[u8] = &[0x01, 0x03, 0x00, 0x00, 0xe7];
+
     // The response header from HTTP_HEADER_FRAME (0x01, 0x06, 0x00, 0x00, 0xd9, 0x54, 0x01, 0x30)
     // are decoded into:
     fn check_response_header_0(header: &[Header]) {
@@ -2101,13 +2533,11 @@ mod tests {
                 Http3ClientEvent::HeaderReady {
                     stream_id,
                     headers,
-                    interim,
                     fin,
                 } => {
                     assert_eq!(stream_id, response_stream_id);
                     check_response_header_2(&headers);
                     assert!(!fin);
-                    assert!(!interim);
                 }
                 Http3ClientEvent::DataReadable { stream_id } => {
                     assert_eq!(stream_id, response_stream_id);
@@ -2556,13 +2986,11 @@ mod tests {
                 Http3ClientEvent::HeaderReady {
                     stream_id,
                     headers,
-                    interim,
                     fin,
                 } => {
                     assert_eq!(stream_id, request_stream_id);
                     check_response_header_1(&headers);
                     assert!(!fin);
-                    assert!(!interim);
                 }
                 Http3ClientEvent::DataReadable { stream_id } => {
                     assert_eq!(stream_id, request_stream_id);
@@ -2585,6 +3013,27 @@ mod tests {
         client.close(now(), 0, "");
     }
 
+    /// A request with headers that are larger than the server's
+    /// `SETTINGS_MAX_FIELD_SECTION_SIZE` fails without using a stream.
+    #[test]
+    fn fetch_field_section_too_large() {
+        let (mut client, _server) = connect();
+        // The test server allows 10000 bytes.
+        let big = Header::new("big", "x".repeat(10_000));
+        assert_eq!(
+            client.fetch(
+                now(),
+                "GET",
+                "https://something.com/",
+                &[big],
+                Priority::default()
+            ),
+            Err(Error::FieldSectionTooLarge)
+        );
+        assert_eq!(client.state(), Http3State::Connected);
+        assert_eq!(make_request(&mut client, true, &[]), StreamId::new(0));
+    }
+
     /// Force both endpoints into an idle state.
     /// Do this by opening unidirectional streams at both endpoints and sending
     /// a partial unidirectional stream type (which the receiver has to buffer),
@@ -2637,13 +3086,11 @@ mod tests {
                 Http3ClientEvent::HeaderReady {
                     stream_id,
                     headers,
-                    interim,
                     fin,
                 } => {
                     assert_eq!(stream_id, request_stream_id);
                     check_response_header_2(&headers);
                     assert!(!fin);
-                    assert!(!interim);
                 }
                 Http3ClientEvent::DataReadable { stream_id } => {
                     assert_eq!(stream_id, request_stream_id);
@@ -2993,13 +3440,11 @@ mod tests {
                 Http3ClientEvent::HeaderReady {
                     stream_id,
                     headers,
-                    interim,
                     fin,
                 } => {
                     assert_eq!(stream_id, request_stream_id);
                     check_response_header_2(&headers);
                     assert!(!fin);
-                    assert!(!interim);
                     response_headers = true;
                 }
                 Http3ClientEvent::DataReadable { stream_id } => {
@@ -3641,14 +4086,12 @@ mod tests {
         if let Http3ClientEvent::HeaderReady {
             stream_id,
             headers,
-            interim,
             fin,
         } = e
         {
             assert_eq!(stream_id, request_stream_id);
             check_response_header_2(&headers);
             assert!(fin);
-            assert!(!interim);
         } else {
             panic!("wrong event type");
         }
@@ -3681,13 +4124,11 @@ mod tests {
                 Http3ClientEvent::HeaderReady {
                     stream_id,
                     headers,
-                    interim,
                     fin,
                 } => {
                     assert_eq!(stream_id, request_stream_id);
                     check_response_header_2(&headers);
                     assert!(!fin);
-                    assert!(!interim);
                 }
                 Http3ClientEvent::DataReadable { .. } => {
                     panic!("We should not receive a DataGeadable event!");
@@ -3755,13 +4196,11 @@ mod tests {
                 Http3ClientEvent::HeaderReady {
                     stream_id,
                     headers,
-                    interim,
                     fin,
                 } => {
                     assert_eq!(stream_id, request_stream_id);
                     check_response_header_2(&headers);
                     assert!(!fin);
-                    assert!(!interim);
                 }
                 Http3ClientEvent::DataReadable { stream_id } => {
                     assert_eq!(stream_id, request_stream_id);
@@ -3806,13 +4245,11 @@ mod tests {
                 Http3ClientEvent::HeaderReady {
                     stream_id,
                     headers,
-                    interim,
                     fin,
                 } => {
                     assert_eq!(stream_id, request_stream_id);
                     check_response_header_2(&headers);
                     assert!(!fin);
-                    assert!(!interim);
                 }
                 Http3ClientEvent::DataReadable { .. } => {
                     panic!("We should not receive a DataGeadable event!");
@@ -3871,13 +4308,11 @@ mod tests {
                 Http3ClientEvent::HeaderReady {
                     stream_id,
                     headers,
-                    interim,
                     fin,
                 } => {
                     assert_eq!(stream_id, request_stream_id);
                     check_response_header_2(&headers);
                     assert!(!fin);
-                    assert!(!interim);
                 }
                 Http3ClientEvent::DataReadable { stream_id } => {
                     assert_eq!(stream_id, request_stream_id);
@@ -4115,14 +4550,12 @@ mod tests {
             if let Http3ClientEvent::HeaderReady {
                 stream_id,
                 headers,
-                interim,
                 fin,
             } = e
             {
                 assert_eq!(stream_id, request_stream_id);
                 assert_eq!(headers.as_ref(), sent_headers);
                 assert!(fin);
-                assert!(!interim);
                 recv_header = true;
             } else {
                 panic!("event {e:?}");
@@ -4574,6 +5007,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn zero_rtt_new_server_setting_connect_protocol_disabled() {
+        // Requests in 0-RTT might have used Extended CONNECT, so this can't be withdrawn.
+        zero_rtt_change_settings(
+            &[
+                HSetting::new(HSettingType::MaxTableCapacity, 100),
+                HSetting::new(HSettingType::BlockedStreams, 100),
+                HSetting::new(HSettingType::EnableConnectProtocol, 1),
+            ],
+            &[
+                HSetting::new(HSettingType::MaxTableCapacity, 100),
+                HSetting::new(HSettingType::BlockedStreams, 100),
+            ],
+            &Http3State::Closing(CloseReason::Application(265)),
+            ENCODER_STREAM_DATA_WITH_CAP_INSTRUCTION,
+        );
+    }
+
+    #[test]
+    fn zero_rtt_new_server_setting_connect_protocol_enabled() {
+        zero_rtt_change_settings(
+            &[
+                HSetting::new(HSettingType::MaxTableCapacity, 100),
+                HSetting::new(HSettingType::BlockedStreams, 100),
+            ],
+            &[
+                HSetting::new(HSettingType::MaxTableCapacity, 100),
+                HSetting::new(HSettingType::BlockedStreams, 100),
+                HSetting::new(HSettingType::EnableConnectProtocol, 1),
+            ],
+            &Http3State::Connected,
+            ENCODER_STREAM_DATA_WITH_CAP_INSTRUCTION,
+        );
+    }
+
+    #[test]
+    fn zero_rtt_new_server_setting_h3_datagram_disabled() {
+        zero_rtt_change_settings(
+            &[
+                HSetting::new(HSettingType::MaxTableCapacity, 100),
+                HSetting::new(HSettingType::BlockedStreams, 100),
+                HSetting::new(HSettingType::EnableH3Datagram, 1),
+            ],
+            &[
+                HSetting::new(HSettingType::MaxTableCapacity, 100),
+                HSetting::new(HSettingType::BlockedStreams, 100),
+            ],
+            &Http3State::Closing(CloseReason::Application(265)),
+            ENCODER_STREAM_DATA_WITH_CAP_INSTRUCTION,
+        );
+    }
+
     #[test]
     fn zero_rtt_max_table_size_first_omitted() {
         // send server original settings without MaxTableCapacity
@@ -4651,14 +5136,12 @@ mod tests {
             if let Http3ClientEvent::HeaderReady {
                 stream_id,
                 headers,
-                interim,
                 fin,
             } = e
             {
                 assert_eq!(stream_id, request_stream_id);
                 check_response_header_0(&headers);
                 assert!(!fin);
-                assert!(!interim);
                 response_headers = true;
             }
         }
@@ -4669,7 +5152,7 @@ mod tests {
             &mut client,
             &mut server,
             request_stream_id,
-            HTTP_HEADER_FRAME_0,
+            HTTP_TRAILER_FRAME,
             true,
         );
 
@@ -4680,6 +5163,11 @@ mod tests {
             |e| matches!(*e, Http3ClientEvent::HeaderReady { .. });
         assert!(!events.iter().any(header_ready));
 
+        assert!(events.contains(&Http3ClientEvent::Trailers {
+            stream_id: request_stream_id,
+            headers: vec![Header::new("cache-control", "no-cache")],
+        }));
+
         // Check that we have a DataReady event. Reading from the stream will return fin=true.
         let data_readable: fn(&Http3ClientEvent) -> _ =
             |e| matches!(*e, Http3ClientEvent::DataReadable { .. });
@@ -4712,14 +5200,12 @@ mod tests {
             if let Http3ClientEvent::HeaderReady {
                 stream_id,
                 headers,
-                interim,
                 fin,
             } = e
             {
                 assert_eq!(stream_id, request_stream_id);
                 check_response_header_0(&headers);
                 assert!(!fin);
-                assert!(!interim);
                 response_headers = true;
             }
         }
@@ -4730,7 +5216,7 @@ mod tests {
             &mut client,
             &mut server,
             request_stream_id,
-            HTTP_HEADER_FRAME_0,
+            HTTP_TRAILER_FRAME,
             false,
         );
 
@@ -4782,14 +5268,12 @@ mod tests {
             if let Http3ClientEvent::HeaderReady {
                 stream_id,
                 headers,
-                interim,
                 fin,
             } = e
             {
                 assert_eq!(stream_id, request_stream_id);
                 check_response_header_0(&headers);
                 assert!(!fin);
-                assert!(!interim);
                 response_headers = true;
             }
         }
@@ -4800,7 +5284,7 @@ mod tests {
             &mut client,
             &mut server,
             request_stream_id,
-            HTTP_HEADER_FRAME_0,
+            HTTP_TRAILER_FRAME,
             false,
         );
 
@@ -4820,6 +5304,43 @@ mod tests {
         assert_closed(&client, &Error::HttpFrameUnexpected);
     }
 
+    #[test]
+    fn test_trailers_with_pseudo_header() {
+        // Make a new connection.
+        let (mut client, mut server, request_stream_id) = connect_and_send_request(true);
+
+        // Send HEADER frame.
+        server_send_response_and_exchange_packet(
+            &mut client,
+            &mut server,
+            request_stream_id,
+            HTTP_HEADER_FRAME_0,
+            false,
+        );
+        let header_ready = |e| matches!(e, Http3ClientEvent::HeaderReady { .. });
+        assert!(client.events().any(header_ready));
+
+        // Send trailers that contain `:status`.
+        server_send_response_and_exchange_packet(
+            &mut client,
+            &mut server,
+            request_stream_id,
+            HTTP_HEADER_FRAME_0,
+            true,
+        );
+
+        let events: Vec<Http3ClientEvent> = client.events().collect();
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, Http3ClientEvent::Trailers { .. })));
+        assert!(events.contains(&Http3ClientEvent::Reset {
+            stream_id: request_stream_id,
+            error: Error::HttpMessageError.code(),
+            local: true,
+        }));
+        assert_eq!(client.state(), Http3State::Connected);
+    }
+
     #[test]
     fn transport_stream_readable_event_after_all_data() {
         let (mut client, mut server, request_stream_id) = connect_and_send_request(false);
@@ -6332,6 +6853,127 @@ mod tests {
         assert_eq!(server.encoder.borrow_mut().stats().stream_cancelled_recv, 1);
     }
 
+    /// Cancel a request with the default error and check the outcome.  The server
+    /// always receives `STOP_SENDING`, and `RESET_STREAM` if `reset_expected`, both
+    /// with `H3_REQUEST_CANCELLED`.  The QPACK decoder cancels the stream, and the
+    /// only event left for the request is a single local `Reset`.
+    fn cancel_fetch_and_check(
+        client: &mut Http3Client,
+        server: &mut TestServer,
+        request_stream_id: StreamId,
+        reset_expected: bool,
+    ) {
+        let cancelled = Error::HttpRequestCancelled.code();
+        assert_eq!(server.encoder.borrow_mut().stats().stream_cancelled_recv, 0);
+        client.cancel_fetch(request_stream_id, None).unwrap();
+
+        let request_events = client
+            .events()
+            .filter(|e| {
+                matches!(
+                    e,
+                    Http3ClientEvent::HeaderReady { .. }
+                        | Http3ClientEvent::DataReadable { .. }
+                        | Http3ClientEvent::Reset { .. }
+                        | Http3ClientEvent::StopSending { .. }
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            request_events,
+            [Http3ClientEvent::Reset {
+                stream_id: request_stream_id,
+                error: cancelled,
+                local: true,
+            }]
+        );
+
+        let out = client.process(None, now());
+        server
+            .conn
+            .process_input(out.as_dgram_ref().unwrap(), now());
+        let (mut reset, mut stop_sending) = (false, false);
+        while let Some(e) = server.conn.next_event() {
+            match e {
+                ConnectionEvent::RecvStreamReset {
+                    stream_id,
+                    app_error,
+                } => {
+                    assert_eq!((stream_id, app_error), (request_stream_id, cancelled));
+                    reset = true;
+                }
+                ConnectionEvent::SendStreamStopSending {
+                    stream_id,
+                    app_error,
+                } => {
+                    assert_eq!((stream_id, app_error), (request_stream_id, cancelled));
+                    stop_sending = true;
+                }
+                _ => {}
+            }
+        }
+        assert!(stop_sending);
+        if reset_expected {
+            assert!(reset);
+        }
+        mem::drop(server.encoder_receiver.receive(&mut server.conn).unwrap());
+        assert_eq!(server.encoder.borrow_mut().stats().stream_cancelled_recv, 1);
+
+        // The request is gone, so there is nothing left to cancel.
+        assert_eq!(
+            client.cancel_fetch(request_stream_id, None),
+            Err(Error::InvalidStreamId)
+        );
+    }
+
+    #[test]
+    fn cancel_fetch_before_headers() {
+        let (mut client, mut server, request_stream_id) = connect_and_send_request(false);
+        setup_server_side_encoder(&mut client, &mut server);
+        cancel_fetch_and_check(&mut client, &mut server, request_stream_id, true);
+    }
+
+    #[test]
+    fn cancel_fetch_after_headers() {
+        let (mut client, mut server, request_stream_id) = connect_and_send_request(true);
+        setup_server_side_encoder(&mut client, &mut server);
+        server_send_response_and_exchange_packet(
+            &mut client,
+            &mut server,
+            request_stream_id,
+            HTTP_RESPONSE_HEADER_ONLY_1,
+            false,
+        );
+        let header_ready_event = |e| matches!(e, Http3ClientEvent::HeaderReady { .. });
+        assert!(client.events().any(header_ready_event));
+        cancel_fetch_and_check(&mut client, &mut server, request_stream_id, false);
+    }
+
+    #[test]
+    fn cancel_fetch_after_partial_body() {
+        let (mut client, mut server, request_stream_id) = connect_and_send_request(true);
+        setup_server_side_encoder(&mut client, &mut server);
+        server_send_response_and_exchange_packet(
+            &mut client,
+            &mut server,
+            request_stream_id,
+            [
+                HTTP_RESPONSE_HEADER_ONLY_1,
+                HTTP_RESPONSE_DATA_FRAME_1_ONLY_1,
+            ]
+            .concat(),
+            false,
+        );
+        let data_readable_event = |e| matches!(e, Http3ClientEvent::DataReadable { .. });
+        assert!(client.events().any(data_readable_event));
+        let mut buf = [0; 10];
+        let (amount, fin) = client
+            .read_data(now(), request_stream_id, &mut buf)
+            .unwrap();
+        assert_eq!((&buf[..amount], fin), (&b"abc"[..], false));
+        cancel_fetch_and_check(&mut client, &mut server, request_stream_id, false);
+    }
+
     fn send_headers_using_encoder(
         client: &mut Http3Client,
         server: &mut TestServer,
@@ -6656,18 +7298,14 @@ mod tests {
             true,
         );
 
-        let mut events = client.events().filter_map(|e| {
-            if let Http3ClientEvent::HeaderReady {
-                stream_id,
-                interim,
-                headers,
-                ..
-            } = e
-            {
-                Some((stream_id, interim, headers))
-            } else {
-                None
-            }
+        let mut events = client.events().filter_map(|e| match e {
+            Http3ClientEvent::InterimResponse {
+                stream_id, headers, ..
+            } => Some((stream_id, true, headers)),
+            Http3ClientEvent::HeaderReady {
+                stream_id, headers, ..
+            } => Some((stream_id, false, headers)),
+            _ => None,
         });
         let (stream_id_1xx_rec, interim1xx_rec, headers1xx_rec) = events.next().unwrap();
         assert_eq!(
@@ -6985,7 +7623,6 @@ mod tests {
                     Header::new(":status", "200"),
                     Header::new("content-type", "text/plain")
                 ],
-                interim: false,
                 fin: false,
             }
         );
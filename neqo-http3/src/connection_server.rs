@@ -4,7 +4,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{rc::Rc, time::Instant};
+use std::{cmp::max, collections::HashMap, mem, rc::Rc, time::Instant};
 
 use neqo_common::{event::Provider, qdebug, qinfo, qtrace, Header, MessageType, Role};
 use neqo_transport::{
@@ -13,19 +13,116 @@ use neqo_transport::{
 
 use crate::{
     connection::{Http3Connection, Http3State, WebTransportSessionAcceptAction},
+    features::{connect_udp, extended_connect::WebTransportSessionStats},
     frames::HFrame,
     recv_message::{RecvMessage, RecvMessageInfo},
     send_message::SendMessage,
     server_connection_events::{Http3ServerConnEvent, Http3ServerConnEvents},
-    Error, Http3Parameters, Http3StreamType, NewStreamType, Priority, PriorityHandler,
-    ReceiveOutput, Res,
+    Error, Http3Parameters, Http3StreamInfo, Http3StreamType, NewStreamType, Priority,
+    PriorityHandler, ReceiveOutput, RequestTimeouts, Res,
 };
 
+/// The state of server push for a connection.
+#[derive(Debug, Default)]
+struct ServerPush {
+    /// The largest push ID that the client allows, once it has sent `MAX_PUSH_ID`.
+    max_push_id: Option<u64>,
+    /// The push ID that the next `PUSH_PROMISE` uses.
+    next_push_id: u64,
+    /// Pushes that have been promised and are not yet complete or canceled.
+    /// The value is the push stream, which is only opened when the response starts.
+    active: HashMap<u64, Option<StreamId>>,
+}
+
+/// The first `GOAWAY` of a graceful shutdown uses the largest client-initiated
+/// bidirectional stream ID, so that requests that are in flight are not rejected.
+const GOAWAY_ANY_REQUEST: u64 = (1 << 62) - 4;
+
+/// The progress of a graceful shutdown.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Shutdown {
+    #[default]
+    Active,
+    /// A shutdown was requested, but the connection is not ready to send `GOAWAY`.
+    Requested,
+    /// A `GOAWAY` that allows any request was sent.  The final `GOAWAY` is sent at
+    /// this time, a round trip later.
+    Announced(Instant),
+    /// The final `GOAWAY` was sent.  Requests on streams at or above this ID are rejected.
+    Draining(StreamId),
+    /// The final `GOAWAY` was sent and all requests are complete.
+    Drained(StreamId),
+}
+
+impl Shutdown {
+    fn rejects(self, stream_id: StreamId) -> bool {
+        match self {
+            Self::Draining(bound) | Self::Drained(bound) => stream_id >= bound,
+            _ => false,
+        }
+    }
+}
+
+/// The progress of a request, for `Http3Parameters::request_timeout`.
+#[derive(Debug)]
+struct RequestTimer {
+    opened: Instant,
+    headers_done: bool,
+    /// The number of bytes that had arrived on the stream the last time it was
+    /// checked, and the time at which the request last made progress.
+    received: u64,
+    progress: Instant,
+}
+
+impl RequestTimer {
+    fn new(now: Instant) -> Self {
+        Self {
+            opened: now,
+            headers_done: false,
+            received: 0,
+            progress: now,
+        }
+    }
+
+    /// The time at which the request times out.  `body_open` is whether the
+    /// request body is still being received.
+    fn deadline(&self, timeouts: &RequestTimeouts, body_open: bool) -> Option<Instant> {
+        let phase = if !self.headers_done {
+            Some(self.opened + timeouts.headers)
+        } else if body_open {
+            Some(self.progress + timeouts.body_idle)
+        } else {
+            None
+        };
+        phase
+            .into_iter()
+            .chain(timeouts.total.map(|t| self.opened + t))
+            .min()
+    }
+}
+
 #[derive(Debug)]
 pub struct Http3ServerHandler {
     base_handler: Http3Connection,
     events: Http3ServerConnEvents,
     needs_processing: bool,
+    push_enabled: bool,
+    push: ServerPush,
+    shutdown: Shutdown,
+    /// The largest request stream that was accepted.
+    largest_request: Option<StreamId>,
+    /// The `:protocol` values that Extended CONNECT requests may use.
+    extended_connect_protocols: Vec<String>,
+    /// The priorities of requests, from the `priority` header or a `PRIORITY_UPDATE`.
+    /// An update can arrive before the request it applies to.
+    priorities: HashMap<StreamId, Priority>,
+    /// Requests that the client reset, with the error code.
+    reset_by_peer: HashMap<StreamId, AppError>,
+    /// Requests that the client sent `STOP_SENDING` for, with the error code.
+    stopped_by_peer: HashMap<StreamId, AppError>,
+    request_timeouts: Option<RequestTimeouts>,
+    /// Requests that are subject to `request_timeouts`.
+    request_timers: HashMap<StreamId, RequestTimer>,
 }
 
 impl ::std::fmt::Display for Http3ServerHandler {
@@ -37,9 +134,62 @@ impl ::std::fmt::Display for Http3ServerHandler {
 impl Http3ServerHandler {
     pub(crate) fn new(http3_parameters: Http3Parameters) -> Self {
         Self {
+            push_enabled: http3_parameters.get_max_concurrent_push_streams() > 0,
+            extended_connect_protocols: http3_parameters
+                .get_extended_connect_protocols()
+                .iter()
+                .cloned()
+                .chain(
+                    http3_parameters
+                        .get_connect_udp()
+                        .then(|| connect_udp::PROTOCOL.to_string()),
+                )
+                .collect(),
+            priorities: HashMap::new(),
+            request_timeouts: http3_parameters.get_request_timeout(),
+            request_timers: HashMap::new(),
             base_handler: Http3Connection::new(http3_parameters, Role::Server),
             events: Http3ServerConnEvents::default(),
             needs_processing: false,
+            push: ServerPush::default(),
+            shutdown: Shutdown::default(),
+            largest_request: None,
+            reset_by_peer: HashMap::new(),
+            stopped_by_peer: HashMap::new(),
+        }
+    }
+
+    /// Whether `stream_id` is a request stream that is still open in either direction.
+    fn is_request(&self, stream_id: StreamId) -> bool {
+        let is_http = |t: Http3StreamType| t == Http3StreamType::Http;
+        self.base_handler
+            .recv_streams
+            .get(&stream_id)
+            .map_or(false, |s| is_http(s.stream_type()))
+            || self
+                .base_handler
+                .send_streams
+                .get(&stream_id)
+                .map_or(false, |s| is_http(s.stream_type()))
+    }
+
+    /// Report `StreamAborted` instead of `InvalidStreamId` when the client
+    /// already stopped sending on the request, or asked for its response to stop.
+    fn check_aborted(&self, stream_id: StreamId, send: bool) -> Res<()> {
+        let (streams_contain, aborted) = if send {
+            (
+                self.base_handler.send_streams.contains_key(&stream_id),
+                &self.stopped_by_peer,
+            )
+        } else {
+            (
+                self.base_handler.recv_streams.contains_key(&stream_id),
+                &self.reset_by_peer,
+            )
+        };
+        match aborted.get(&stream_id) {
+            Some(&error) if !streams_contain => Err(Error::StreamAborted(error)),
+            _ => Ok(()),
         }
     }
 
@@ -64,6 +214,7 @@ impl Http3ServerHandler {
         data: &[u8],
         conn: &mut Connection,
     ) -> Res<usize> {
+        self.check_aborted(stream_id, true)?;
         let n = self
             .base_handler
             .send_streams
@@ -77,6 +228,23 @@ impl Http3ServerHandler {
         Ok(n)
     }
 
+    /// The number of bytes of response data that `send_data` would accept now.
+    pub(crate) fn send_data_avail(&self, stream_id: StreamId, conn: &Connection) -> Res<usize> {
+        self.base_handler
+            .send_streams
+            .get(&stream_id)
+            .ok_or(Error::InvalidStreamId)?
+            .send_data_avail(conn)
+    }
+
+    /// The type of a stream that the server can send on, if it exists.
+    pub(crate) fn send_stream_type(&self, stream_id: StreamId) -> Option<Http3StreamType> {
+        self.base_handler
+            .send_streams
+            .get(&stream_id)
+            .map(|s| s.stream_type())
+    }
+
     /// Supply response heeaders for a request.
     pub(crate) fn send_headers(
         &mut self,
@@ -84,6 +252,8 @@ impl Http3ServerHandler {
         headers: &[Header],
         conn: &mut Connection,
     ) -> Res<()> {
+        self.check_aborted(stream_id, true)?;
+        self.base_handler.check_field_section_size(headers)?;
         self.base_handler
             .send_streams
             .get_mut(&stream_id)
@@ -96,6 +266,282 @@ impl Http3ServerHandler {
         Ok(())
     }
 
+    /// Supply response trailers for a request.
+    pub(crate) fn send_trailers(
+        &mut self,
+        stream_id: StreamId,
+        headers: &[Header],
+        conn: &mut Connection,
+    ) -> Res<()> {
+        self.check_aborted(stream_id, true)?;
+        self.base_handler.check_field_section_size(headers)?;
+        self.base_handler
+            .send_streams
+            .get_mut(&stream_id)
+            .ok_or(Error::InvalidStreamId)?
+            .http_stream()
+            .ok_or(Error::InvalidStreamId)?
+            .send_trailers(headers, conn)?;
+        self.base_handler.stream_has_pending_data(stream_id);
+        self.needs_processing = true;
+        Ok(())
+    }
+
+    /// Answer an Extended CONNECT request with a 501 if its `:protocol` was not
+    /// registered with `Http3Parameters::extended_connect_protocols`, or with a 400
+    /// if it is a CONNECT-UDP request without a valid target.
+    /// Returns `true` if the request was rejected and must not reach the application.
+    pub(crate) fn reject_extended_connect(
+        &mut self,
+        stream_id: StreamId,
+        headers: &[Header],
+        conn: &mut Connection,
+    ) -> bool {
+        let is_connect = headers
+            .iter()
+            .any(|h| h.name() == ":method" && h.value() == "CONNECT");
+        let Some(protocol) = headers.iter().find(|h| h.name() == ":protocol") else {
+            return false;
+        };
+        if !is_connect {
+            return false;
+        }
+        let status = if !self
+            .extended_connect_protocols
+            .iter()
+            .any(|p| p == protocol.value())
+        {
+            "501"
+        } else if protocol.value() == connect_udp::PROTOCOL
+            && connect_udp::request_target(headers).is_none()
+        {
+            "400"
+        } else {
+            return false;
+        };
+        qdebug!(
+            [self],
+            "Reject Extended CONNECT {} for protocol {} with {}.",
+            stream_id,
+            protocol.value(),
+            status
+        );
+        // The request may already be complete, or the stream reset, so ignore errors.
+        mem::drop(self.send_headers(stream_id, &[Header::new(":status", status)], conn));
+        mem::drop(self.stream_close_send(stream_id, conn));
+        mem::drop(self.stream_stop_sending(stream_id, Error::HttpNoError.code(), conn));
+        true
+    }
+
+    /// Take the priority of a request from its `priority` header, unless a
+    /// `PRIORITY_UPDATE` for it has already arrived, and use it to schedule the response.
+    pub(crate) fn request_priority(
+        &mut self,
+        stream_id: StreamId,
+        headers: &[Header],
+        conn: &mut Connection,
+    ) {
+        let priority = *self.priorities.entry(stream_id).or_insert_with(|| {
+            headers
+                .iter()
+                .find(|h| h.name() == "priority")
+                .and_then(|h| Priority::from_bytes(h.value().as_bytes()).ok())
+                .unwrap_or_default()
+        });
+        Self::apply_priority(stream_id, priority, conn);
+    }
+
+    /// The priority of a request.  This is the default priority if the request
+    /// did not set one.
+    pub(crate) fn stream_priority(&self, stream_id: StreamId) -> Priority {
+        self.priorities.get(&stream_id).copied().unwrap_or_default()
+    }
+
+    fn update_priority(&mut self, stream_id: StreamId, priority: Priority, conn: &mut Connection) {
+        self.priorities.insert(stream_id, priority);
+        if self.base_handler.send_streams.contains_key(&stream_id) {
+            Self::apply_priority(stream_id, priority, conn);
+        }
+    }
+
+    fn apply_priority(stream_id: StreamId, priority: Priority, conn: &mut Connection) {
+        // The response might be complete already, so ignore errors.
+        mem::drop(Http3Connection::stream_set_sendorder(
+            conn,
+            stream_id,
+            Some(priority.sendorder()),
+        ));
+    }
+
+    /// Forget the priorities of requests that are done.  Keep those that
+    /// are for requests that have not been opened yet.
+    fn prune_priorities(&mut self) {
+        let largest_request = self.largest_request;
+        let send_streams = &self.base_handler.send_streams;
+        let recv_streams = &self.base_handler.recv_streams;
+        self.priorities.retain(|id, _| {
+            send_streams.contains_key(id)
+                || recv_streams.contains_key(id)
+                || largest_request.map_or(true, |l| *id > l)
+        });
+    }
+
+    /// Promise a push on the response to the request on `stream_id`.  `headers` are
+    /// the headers of the promised request.  This returns the push ID that is used to
+    /// send the pushed response with `push_headers`, `push_data` and `push_close`.
+    ///
+    /// # Errors
+    ///
+    /// `Unavailable` if push is disabled or the client's `MAX_PUSH_ID` does not allow
+    /// another push,
+    /// `InvalidStreamId` if the request stream does not exist,
+    /// `InvalidInput` if the response is already complete.
+    pub(crate) fn push_promise(
+        &mut self,
+        stream_id: StreamId,
+        headers: &[Header],
+        conn: &mut Connection,
+    ) -> Res<u64> {
+        let push_id = self.push.next_push_id;
+        if !self.push_enabled || self.push.max_push_id.map_or(true, |max| push_id > max) {
+            qdebug!([self], "Push is not available push_id={}.", push_id);
+            return Err(Error::Unavailable);
+        }
+        self.base_handler
+            .send_streams
+            .get_mut(&stream_id)
+            .ok_or(Error::InvalidStreamId)?
+            .http_stream()
+            .ok_or(Error::InvalidStreamId)?
+            .send_push_promise(push_id, headers, conn)?;
+        self.base_handler.stream_has_pending_data(stream_id);
+        self.push.next_push_id += 1;
+        self.push.active.insert(push_id, None);
+        self.needs_processing = true;
+        Ok(push_id)
+    }
+
+    /// Supply the response headers for a promised push.  This opens the push stream.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the push is not active, e.g. if the client canceled it.
+    pub(crate) fn push_headers(
+        &mut self,
+        push_id: u64,
+        headers: &[Header],
+        conn: &mut Connection,
+    ) -> Res<()> {
+        let stream_id = match self.push.active.get(&push_id) {
+            None => return Err(Error::InvalidStreamId),
+            Some(Some(stream_id)) => *stream_id,
+            Some(None) => {
+                let stream_id = conn
+                    .stream_create(StreamType::UniDi)
+                    .map_err(|e| Error::map_stream_create_errors(&e))?;
+                qdebug!(
+                    [self],
+                    "Open push stream {} push_id={}.",
+                    stream_id,
+                    push_id
+                );
+                self.base_handler.send_streams.insert(
+                    stream_id,
+                    Box::new(
+                        SendMessage::new_push(
+                            push_id,
+                            stream_id,
+                            self.base_handler.qpack_encoder.clone(),
+                            Box::new(self.events.clone()),
+                        )
+                        .frame_qlog(self.base_handler.frame_qlog.clone()),
+                    ),
+                );
+                self.push.active.insert(push_id, Some(stream_id));
+                stream_id
+            }
+        };
+        self.send_headers(stream_id, headers, conn)
+    }
+
+    fn push_stream_id(&self, push_id: u64) -> Res<StreamId> {
+        self.push
+            .active
+            .get(&push_id)
+            .copied()
+            .flatten()
+            .ok_or(Error::InvalidStreamId)
+    }
+
+    /// Supply data for a pushed response.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the push is not active or its headers have not been sent.
+    pub(crate) fn push_data(
+        &mut self,
+        push_id: u64,
+        data: &[u8],
+        conn: &mut Connection,
+    ) -> Res<usize> {
+        let stream_id = self.push_stream_id(push_id)?;
+        self.send_data(stream_id, data, conn)
+    }
+
+    /// Complete a pushed response.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the push is not active or its headers have not been sent.
+    pub(crate) fn push_close(&mut self, push_id: u64, conn: &mut Connection) -> Res<()> {
+        let stream_id = self.push_stream_id(push_id)?;
+        self.stream_close_send(stream_id, conn)?;
+        self.push.active.remove(&push_id);
+        Ok(())
+    }
+
+    /// The client canceled a push with `CANCEL_PUSH`.  Stop sending the push stream,
+    /// if there is one.
+    fn handle_cancel_push(&mut self, push_id: u64, conn: &mut Connection) -> Res<()> {
+        if self.push.max_push_id.map_or(true, |max| push_id > max) {
+            return Err(Error::HttpId);
+        }
+        qdebug!([self], "Push canceled push_id={}.", push_id);
+        if let Some(Some(stream_id)) = self.push.active.remove(&push_id) {
+            self.base_handler.send_streams.remove(&stream_id);
+            // The stream may be closed already, so ignore errors.
+            mem::drop(conn.stream_reset_send(stream_id, Error::HttpRequestCancelled.code()));
+            self.needs_processing = true;
+        }
+        Ok(())
+    }
+
+    /// Send a capsule on a response.
+    pub(crate) fn send_capsule(
+        &mut self,
+        stream_id: StreamId,
+        capsule_type: u64,
+        payload: &[u8],
+        conn: &mut Connection,
+    ) -> Res<()> {
+        self.check_aborted(stream_id, true)?;
+        self.base_handler
+            .send_capsule(conn, stream_id, capsule_type, payload)?;
+        self.needs_processing = true;
+        Ok(())
+    }
+
+    /// Read the rest of a request body as capsules.
+    pub(crate) fn capsule_protocol(
+        &mut self,
+        stream_id: StreamId,
+        conn: &mut Connection,
+    ) -> Res<()> {
+        self.check_aborted(stream_id, false)?;
+        self.needs_processing = true;
+        self.base_handler.capsule_protocol(conn, stream_id)
+    }
+
     /// This is called when application is done sending a request.
     ///
     /// # Errors
@@ -103,6 +549,7 @@ impl Http3ServerHandler {
     /// An error will be returned if stream does not exist.
     pub fn stream_close_send(&mut self, stream_id: StreamId, conn: &mut Connection) -> Res<()> {
         qdebug!([self], "Close sending side stream={}.", stream_id);
+        self.check_aborted(stream_id, true)?;
         self.base_handler.stream_close_send(conn, stream_id)?;
         self.needs_processing = true;
         Ok(())
@@ -213,6 +660,206 @@ impl Http3ServerHandler {
             .webtransport_send_datagram(session_id, conn, buf, id)
     }
 
+    /// Answer a CONNECT-UDP request.  If `accept` is true, this sends a 200 response
+    /// and datagrams start to flow; otherwise it sends a 403 response and closes the stream.
+    pub(crate) fn connect_udp_response(
+        &mut self,
+        conn: &mut Connection,
+        stream_id: StreamId,
+        accept: bool,
+    ) -> Res<()> {
+        if accept {
+            self.send_headers(
+                stream_id,
+                &[
+                    Header::new(":status", "200"),
+                    connect_udp::capsule_protocol_header(),
+                ],
+                conn,
+            )?;
+            self.base_handler.connect_udp_register(stream_id);
+            Ok(())
+        } else {
+            self.send_headers(stream_id, &[Header::new(":status", "403")], conn)?;
+            self.stream_close_send(stream_id, conn)
+        }
+    }
+
+    pub fn connect_udp_send_datagram(
+        &mut self,
+        conn: &mut Connection,
+        stream_id: StreamId,
+        buf: &[u8],
+        id: impl Into<DatagramTracking>,
+    ) -> Res<()> {
+        self.needs_processing = true;
+        self.base_handler
+            .connect_udp_send_datagram(stream_id, conn, buf, id)
+    }
+
+    pub fn webtransport_session_stats(
+        &self,
+        session_id: StreamId,
+    ) -> Res<WebTransportSessionStats> {
+        self.base_handler.webtransport_session_stats(session_id)
+    }
+
+    /// Start a graceful shutdown.  This sends a `GOAWAY` frame that allows any request
+    /// and, after a round trip, a second one that rejects requests that were not
+    /// yet received.
+    pub(crate) fn graceful_shutdown(&mut self) {
+        if self.shutdown == Shutdown::Active {
+            qinfo!([self], "Graceful shutdown.");
+            self.shutdown = Shutdown::Requested;
+            self.needs_processing = true;
+        }
+    }
+
+    /// Close the connection at the end of a graceful shutdown.
+    pub(crate) fn shutdown_timeout(&mut self, conn: &mut Connection, now: Instant) {
+        if self.base_handler.state().active() {
+            qinfo!([self], "Close after the drain timeout.");
+            self.close(conn, now, &Error::HttpNoError);
+        }
+    }
+
+    fn has_requests(&self) -> bool {
+        let is_request = |t: Http3StreamType| {
+            matches!(t, Http3StreamType::Http | Http3StreamType::ExtendedConnect)
+        };
+        self.base_handler
+            .send_streams
+            .values()
+            .any(|s| is_request(s.stream_type()))
+            || self
+                .base_handler
+                .recv_streams
+                .values()
+                .any(|s| is_request(s.stream_type()))
+    }
+
+    fn check_shutdown(&mut self, conn: &Connection, now: Instant) {
+        if !self.base_handler.state().active() {
+            return;
+        }
+        match self.shutdown {
+            Shutdown::Requested => {
+                self.base_handler.queue_control_frame(&HFrame::Goaway {
+                    stream_id: StreamId::new(GOAWAY_ANY_REQUEST),
+                });
+                self.shutdown = Shutdown::Announced(now + conn.recovery_stats().smoothed_rtt);
+            }
+            Shutdown::Announced(t) if now >= t => {
+                let bound = self
+                    .largest_request
+                    .map_or(StreamId::new(0), |id| StreamId::new(id.as_u64() + 4));
+                qdebug!([self], "Send the final GOAWAY {}.", bound);
+                self.base_handler
+                    .queue_control_frame(&HFrame::Goaway { stream_id: bound });
+                self.shutdown = Shutdown::Draining(bound);
+            }
+            _ => {}
+        }
+        if let Shutdown::Draining(bound) = self.shutdown {
+            if !self.has_requests() {
+                qinfo!([self], "All requests are complete.");
+                self.shutdown = Shutdown::Drained(bound);
+                self.events.drained();
+            }
+        }
+    }
+
+    /// The headers of a request are complete.  CONNECT requests are tunnels that
+    /// stay open for as long as they are used, so they are not timed.
+    pub(crate) fn request_headers_received(&mut self, stream_id: StreamId, headers: &[Header]) {
+        let is_connect = headers
+            .iter()
+            .any(|h| h.name() == ":method" && h.value() == "CONNECT");
+        if is_connect {
+            self.request_timers.remove(&stream_id);
+        } else if let Some(timer) = self.request_timers.get_mut(&stream_id) {
+            timer.headers_done = true;
+        }
+    }
+
+    /// The time at which the next request times out.
+    pub(crate) fn request_timer(&self) -> Option<Instant> {
+        let timeouts = self.request_timeouts.as_ref()?;
+        self.request_timers
+            .iter()
+            .filter(|(id, _)| self.is_request(**id))
+            .filter_map(|(id, timer)| {
+                timer.deadline(timeouts, self.base_handler.recv_streams.contains_key(id))
+            })
+            .min()
+    }
+
+    fn check_request_timeouts(&mut self, conn: &mut Connection, now: Instant) {
+        let Some(timeouts) = self.request_timeouts else {
+            return;
+        };
+        let ids: Vec<StreamId> = self.request_timers.keys().copied().collect();
+        for stream_id in ids {
+            // Requests that are complete, or were reset, are no longer timed.
+            if !self.is_request(stream_id) {
+                self.request_timers.remove(&stream_id);
+                continue;
+            }
+            let body_open = self.base_handler.recv_streams.contains_key(&stream_id);
+            let Some(timer) = self.request_timers.get_mut(&stream_id) else {
+                continue;
+            };
+            if body_open {
+                if let Ok(stats) = conn.recv_stream_stats(stream_id) {
+                    // Data that the application has not read is not the client's delay.
+                    if stats.bytes_received() != timer.received
+                        || stats.bytes_read() < stats.bytes_received()
+                    {
+                        timer.received = stats.bytes_received();
+                        timer.progress = now;
+                    }
+                }
+            }
+            let headers_done = timer.headers_done;
+            if timer
+                .deadline(&timeouts, body_open)
+                .is_some_and(|t| now >= t)
+            {
+                self.request_timed_out(stream_id, headers_done, conn);
+            }
+        }
+    }
+
+    fn request_timed_out(
+        &mut self,
+        stream_id: StreamId,
+        headers_done: bool,
+        conn: &mut Connection,
+    ) {
+        qinfo!([self], "Request {} timed out.", stream_id);
+        self.request_timers.remove(&stream_id);
+        let error = Error::HttpRequestCancelled.code();
+        // Response headers are refused once the response has started, because they
+        // are taken as trailers, which cannot carry `:status`.
+        let responded = headers_done
+            && self
+                .send_headers(stream_id, &[Header::new(":status", "408")], conn)
+                .is_ok();
+        if responded {
+            // The request body may be complete already, so ignore errors.
+            mem::drop(self.stream_close_send(stream_id, conn));
+            mem::drop(
+                self.base_handler
+                    .stream_stop_sending(conn, stream_id, error),
+            );
+        } else {
+            mem::drop(self.base_handler.cancel_fetch(stream_id, error, conn));
+        }
+        self.events
+            .request_timed_out(Http3StreamInfo::new(stream_id, Http3StreamType::Http));
+        self.needs_processing = true;
+    }
+
     /// Process HTTTP3 layer.
     pub fn process_http3(&mut self, conn: &mut Connection, now: Instant) {
         qtrace!([self], "Process http3 internal.");
@@ -220,8 +867,11 @@ impl Http3ServerHandler {
             return;
         }
 
+        self.check_request_timeouts(conn, now);
         let res = self.check_connection_events(conn, now);
+        self.prune_priorities();
         if !self.check_result(conn, now, &res) && self.base_handler.state().active() {
+            self.check_shutdown(conn, now);
             let res = self.base_handler.process_sending(conn);
             self.check_result(conn, now, &res);
         }
@@ -232,8 +882,30 @@ impl Http3ServerHandler {
         self.events.next_event()
     }
 
+    /// Take the trailers of a request if they are waiting to be reported.
+    pub(crate) fn take_trailers(&mut self, stream_info: Http3StreamInfo) -> Option<Vec<Header>> {
+        self.events.take_trailers(stream_info)
+    }
+
     /// Whether this connection has events to process or data to send.
-    pub(crate) fn should_be_processed(&mut self) -> bool {
+    /// The time at which the next step of a graceful shutdown is due.
+    pub(crate) fn shutdown_timer(&self) -> Option<Instant> {
+        if let Shutdown::Announced(t) = self.shutdown {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn should_be_processed(&mut self, now: Instant) -> bool {
+        if self
+            .shutdown_timer()
+            .into_iter()
+            .chain(self.request_timer())
+            .any(|t| now >= t)
+        {
+            return true;
+        }
         if self.needs_processing {
             self.needs_processing = false;
             return true;
@@ -271,21 +943,28 @@ impl Http3ServerHandler {
                     self.base_handler.add_new_stream(stream_id);
                 }
                 ConnectionEvent::RecvStreamReadable { stream_id } => {
-                    self.handle_stream_readable(conn, stream_id)?;
+                    self.handle_stream_readable(conn, stream_id, now)?;
                 }
                 ConnectionEvent::RecvStreamReset {
                     stream_id,
                     app_error,
                 } => {
+                    if self.is_request(stream_id) {
+                        self.reset_by_peer.insert(stream_id, app_error);
+                    }
                     self.base_handler
                         .handle_stream_reset(stream_id, app_error, conn)?;
                 }
                 ConnectionEvent::SendStreamStopSending {
                     stream_id,
                     app_error,
-                } => self
-                    .base_handler
-                    .handle_stream_stop_sending(stream_id, app_error, conn)?,
+                } => {
+                    if self.is_request(stream_id) {
+                        self.stopped_by_peer.insert(stream_id, app_error);
+                    }
+                    self.base_handler
+                        .handle_stream_stop_sending(stream_id, app_error, conn)?;
+                }
                 ConnectionEvent::StateChange(state) => {
                     if self.base_handler.handle_state_change(conn, &state)? {
                         if self.base_handler.state() == Http3State::Connected {
@@ -301,7 +980,14 @@ impl Http3ServerHandler {
                         s.stream_writable();
                     }
                 }
-                ConnectionEvent::Datagram(dgram) => self.base_handler.handle_datagram(&dgram),
+                ConnectionEvent::Datagram(dgram) => {
+                    if let Some((stream_id, datagram)) = self.base_handler.handle_datagram(&dgram) {
+                        self.events.connect_udp_datagram(
+                            Http3StreamInfo::new(stream_id, Http3StreamType::Http),
+                            datagram,
+                        );
+                    }
+                }
                 ConnectionEvent::AuthenticationNeeded
                 | ConnectionEvent::EchFallbackAuthenticationNeeded { .. }
                 | ConnectionEvent::ZeroRttRejected
@@ -309,37 +995,66 @@ impl Http3ServerHandler {
                 ConnectionEvent::SendStreamComplete { .. }
                 | ConnectionEvent::SendStreamCreatable { .. }
                 | ConnectionEvent::OutgoingDatagramOutcome { .. }
-                | ConnectionEvent::IncomingDatagramDropped => {}
+                | ConnectionEvent::IncomingDatagramDropped
+                | ConnectionEvent::PathAbandoned { .. } => {}
             }
         }
         Ok(())
     }
 
-    fn handle_stream_readable(&mut self, conn: &mut Connection, stream_id: StreamId) -> Res<()> {
+    fn handle_stream_readable(
+        &mut self,
+        conn: &mut Connection,
+        stream_id: StreamId,
+        now: Instant,
+    ) -> Res<()> {
         match self.base_handler.handle_stream_readable(conn, stream_id)? {
             ReceiveOutput::NewStream(NewStreamType::Push(_)) => Err(Error::HttpStreamCreation),
             ReceiveOutput::NewStream(NewStreamType::Http) => {
+                if self.shutdown.rejects(stream_id) {
+                    qdebug!([self], "Reject request {} after GOAWAY.", stream_id);
+                    self.base_handler.recv_streams.remove(&stream_id);
+                    let error = Error::HttpRequestRejected.code();
+                    // The stream might be closed already, so ignore errors.
+                    mem::drop(conn.stream_stop_sending(stream_id, error));
+                    mem::drop(conn.stream_reset_send(stream_id, error));
+                    return Ok(());
+                }
+                self.largest_request = Some(
+                    self.largest_request
+                        .map_or(stream_id, |id| max(id, stream_id)),
+                );
+                if self.request_timeouts.is_some() {
+                    self.request_timers
+                        .insert(stream_id, RequestTimer::new(now));
+                }
                 self.base_handler.add_streams(
                     stream_id,
-                    Box::new(SendMessage::new(
-                        MessageType::Response,
-                        Http3StreamType::Http,
-                        stream_id,
-                        self.base_handler.qpack_encoder.clone(),
-                        Box::new(self.events.clone()),
-                    )),
-                    Box::new(RecvMessage::new(
-                        &RecvMessageInfo {
-                            message_type: MessageType::Request,
-                            stream_type: Http3StreamType::Http,
+                    Box::new(
+                        SendMessage::new(
+                            MessageType::Response,
+                            Http3StreamType::Http,
                             stream_id,
-                            header_frame_type_read: true,
-                        },
-                        Rc::clone(&self.base_handler.qpack_decoder),
-                        Box::new(self.events.clone()),
-                        None,
-                        PriorityHandler::new(false, Priority::default()),
-                    )),
+                            self.base_handler.qpack_encoder.clone(),
+                            Box::new(self.events.clone()),
+                        )
+                        .frame_qlog(self.base_handler.frame_qlog.clone()),
+                    ),
+                    Box::new(
+                        RecvMessage::new(
+                            &RecvMessageInfo {
+                                message_type: MessageType::Request,
+                                stream_type: Http3StreamType::Http,
+                                stream_id,
+                                header_frame_type_read: true,
+                            },
+                            Rc::clone(&self.base_handler.qpack_decoder),
+                            Box::new(self.events.clone()),
+                            None,
+                            PriorityHandler::new(false, Priority::default()),
+                        )
+                        .frame_qlog(self.base_handler.frame_qlog.clone()),
+                    ),
                 );
                 let res = self.base_handler.handle_stream_readable(conn, stream_id)?;
                 assert_eq!(ReceiveOutput::NoOutput, res);
@@ -359,13 +1074,15 @@ impl Http3ServerHandler {
             ReceiveOutput::ControlFrames(control_frames) => {
                 for f in control_frames {
                     match f {
-                        HFrame::MaxPushId { .. } => {
-                            // TODO implement push
+                        HFrame::MaxPushId { push_id } => {
+                            if self.push.max_push_id.map_or(false, |max| push_id < max) {
+                                return Err(Error::HttpId);
+                            }
+                            self.push.max_push_id = Some(push_id);
                             Ok(())
                         }
-                        HFrame::Goaway { .. } | HFrame::CancelPush { .. } => {
-                            Err(Error::HttpFrameUnexpected)
-                        }
+                        HFrame::CancelPush { push_id } => self.handle_cancel_push(push_id, conn),
+                        HFrame::Goaway { .. } => Err(Error::HttpFrameUnexpected),
                         HFrame::PriorityUpdatePush { element_id, priority } => {
                             // TODO: check if the element_id references a promised push stream or
                             // is greater than the maximum Push ID.
@@ -383,6 +1100,7 @@ impl Http3ServerHandler {
                                 return Err(Error::HttpId)
                             }
 
+                            self.update_priority(element_stream_id, priority, conn);
                             self.events.priority_update(element_stream_id, priority);
                             Ok(())
                         }
@@ -412,6 +1130,7 @@ impl Http3ServerHandler {
         buf: &mut [u8],
     ) -> Res<(usize, bool)> {
         qdebug!([self], "read_data from stream {}.", stream_id);
+        self.check_aborted(stream_id, false)?;
         let res = self.base_handler.read_data(conn, stream_id, buf);
         if let Err(e) = &res {
             if e.connection_error() {
@@ -8,8 +8,9 @@ use std::collections::{HashMap, VecDeque};
 
 use neqo_common::{qtrace, Encoder};
 use neqo_transport::{Connection, StreamId, StreamType};
+use qlog::events::EventData;
 
-use crate::{frames::HFrame, BufferedStream, Http3StreamType, RecvStream, Res};
+use crate::{frames::HFrame, qlog::FrameQlog, BufferedStream, Http3StreamType, RecvStream, Res};
 
 pub const HTTP3_UNI_STREAM_TYPE_CONTROL: u64 = 0x0;
 
@@ -19,6 +20,9 @@ pub(crate) struct ControlStreamLocal {
     stream: BufferedStream,
     /// `stream_id`s of outstanding request streams
     outstanding_priority_update: VecDeque<StreamId>,
+    frame_qlog: FrameQlog,
+    /// Events for the queued frames, which are logged when the frames are sent.
+    queued_qlog: Vec<EventData>,
 }
 
 impl ::std::fmt::Display for ControlStreamLocal {
@@ -28,10 +32,12 @@ impl ::std::fmt::Display for ControlStreamLocal {
 }
 
 impl ControlStreamLocal {
-    pub fn new() -> Self {
+    pub fn new(frame_qlog: FrameQlog) -> Self {
         Self {
             stream: BufferedStream::default(),
             outstanding_priority_update: VecDeque::new(),
+            frame_qlog,
+            queued_qlog: Vec::new(),
         }
     }
 
@@ -39,6 +45,12 @@ impl ControlStreamLocal {
     pub fn queue_frame(&mut self, f: &HFrame) {
         let mut enc = Encoder::default();
         f.encode(&mut enc);
+        if let Some(stream_id) = self.stream_id() {
+            self.queued_qlog.extend(
+                self.frame_qlog
+                    .created_event(stream_id, f, enc.as_ref(), &[]),
+            );
+        }
         self.stream.buffer(enc.as_ref());
     }
 
@@ -53,6 +65,9 @@ impl ControlStreamLocal {
         recv_conn: &mut HashMap<StreamId, Box<dyn RecvStream>>,
     ) -> Res<()> {
         self.stream.send_buffer(conn)?;
+        for ev in self.queued_qlog.drain(..) {
+            conn.qlog_mut().add_event_data(|| Some(ev));
+        }
         self.send_priority_update(conn, recv_conn)
     }
 
@@ -80,6 +95,15 @@ impl ControlStreamLocal {
                 let mut enc = Encoder::new();
                 hframe.encode(&mut enc);
                 if self.stream.send_atomic(conn, enc.as_ref())? {
+                    if let Some(stream_id) = self.stream_id() {
+                        self.frame_qlog.created(
+                            conn.qlog_mut(),
+                            stream_id,
+                            &hframe,
+                            enc.as_ref(),
+                            &[],
+                        );
+                    }
                     stream.priority_update_sent();
                 } else {
                     self.outstanding_priority_update.push_front(update_id);
@@ -9,6 +9,7 @@ use neqo_transport::{Connection, StreamId};
 
 use crate::{
     frames::{FrameReader, HFrame, StreamReaderConnectionWrapper},
+    qlog::FrameQlog,
     CloseType, Error, Http3StreamType, ReceiveOutput, RecvStream, Res, Stream,
 };
 
@@ -18,6 +19,7 @@ use crate::{
 pub(crate) struct ControlStreamRemote {
     stream_id: StreamId,
     frame_reader: FrameReader,
+    frame_qlog: FrameQlog,
 }
 
 impl ::std::fmt::Display for ControlStreamRemote {
@@ -27,25 +29,28 @@ impl ::std::fmt::Display for ControlStreamRemote {
 }
 
 impl ControlStreamRemote {
-    pub fn new(stream_id: StreamId) -> Self {
+    pub fn new(stream_id: StreamId, frame_qlog: FrameQlog) -> Self {
         Self {
             stream_id,
             frame_reader: FrameReader::new(),
+            frame_qlog,
         }
     }
 
     /// Check if a stream is the control stream and read received data.
     pub fn receive_single(&mut self, conn: &mut Connection) -> Res<Option<HFrame>> {
         qdebug!([self], "Receiving data.");
-        match self
-            .frame_reader
-            .receive(&mut StreamReaderConnectionWrapper::new(
-                conn,
-                self.stream_id,
-            ))? {
+        match self.frame_reader.receive(
+            &mut StreamReaderConnectionWrapper::new(conn, self.stream_id)
+                .frame_qlog(&self.frame_qlog),
+        )? {
             (_, true) => Err(Error::HttpClosedCriticalStream),
             (s, false) => {
                 qdebug!([self], "received {:?}", s);
+                if let Some(f) = &s {
+                    self.frame_qlog
+                        .parsed(conn.qlog_mut(), self.stream_id, f, &[]);
+                }
                 Ok(s)
             }
         }
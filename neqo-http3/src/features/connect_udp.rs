@@ -0,0 +1,117 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Proxying UDP in HTTP ([RFC 9298][1]).
+//!
+//! A CONNECT-UDP request is an Extended CONNECT request with `:protocol` set to
+//! `connect-udp`.  The target is carried in the path, using the default URI template
+//! `/.well-known/masque/udp/{target_host}/{target_port}/`.  Once the proxy answers
+//! with a 2xx status, UDP payloads are exchanged as HTTP Datagrams ([RFC 9297][2])
+//! that are associated with the request stream and use context ID 0.
+//!
+//! [1]: https://www.rfc-editor.org/rfc/rfc9298
+//! [2]: https://www.rfc-editor.org/rfc/rfc9297
+
+use std::fmt::Write;
+
+use neqo_common::Header;
+
+/// The `:protocol` value of a CONNECT-UDP request.
+pub(crate) const PROTOCOL: &str = "connect-udp";
+
+/// The context ID of datagrams that carry UDP payloads.
+pub(crate) const CONTEXT_ID: u64 = 0;
+
+const PATH_PREFIX: &str = "/.well-known/masque/udp/";
+
+/// The header that tells the peer that the stream uses the capsule protocol.
+pub(crate) fn capsule_protocol_header() -> Header {
+    Header::new("capsule-protocol", "?1")
+}
+
+/// The target of a CONNECT-UDP request, or `None` if `headers` are not a
+/// CONNECT-UDP request or the path does not follow the default URI template.
+pub(crate) fn request_target(headers: &[Header]) -> Option<(String, u16)> {
+    let value = |name: &str| headers.iter().find(|h| h.name() == name).map(Header::value);
+    if value(":method") != Some("CONNECT") || value(":protocol") != Some(PROTOCOL) {
+        return None;
+    }
+    parse_target(value(":path")?)
+}
+
+/// Build the path of a request for `target_host` and `target_port`.
+/// Characters that are not allowed in a path segment, such as the colons of
+/// an IPv6 address, are percent-encoded.
+pub(crate) fn target_path(target_host: &str, target_port: u16) -> String {
+    let mut path = String::from(PATH_PREFIX);
+    for b in target_host.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            path.push(char::from(b));
+        } else {
+            write!(path, "%{b:02X}").unwrap();
+        }
+    }
+    write!(path, "/{target_port}/").unwrap();
+    path
+}
+
+/// Extract the target host and port from the path of a request.
+/// Returns `None` if the path does not follow the default URI template.
+pub(crate) fn parse_target(path: &str) -> Option<(String, u16)> {
+    let mut parts = path.strip_prefix(PATH_PREFIX)?.split('/');
+    let host = percent_decode(parts.next()?)?;
+    let port = parts.next()?.parse::<u16>().ok()?;
+    if host.is_empty() || port == 0 || parts.next() != Some("") || parts.next().is_some() {
+        return None;
+    }
+    Some((host, port))
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            out.push(b);
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_target, target_path};
+
+    #[test]
+    fn target_round_trip() {
+        for (host, port) in [("192.0.2.6", 443), ("example.com", 53), ("2001:db8::42", 8)] {
+            let path = target_path(host, port);
+            assert_eq!(parse_target(&path), Some((host.to_string(), port)));
+        }
+        assert_eq!(
+            target_path("2001:db8::42", 8),
+            "/.well-known/masque/udp/2001%3Adb8%3A%3A42/8/"
+        );
+    }
+
+    #[test]
+    fn bad_target() {
+        for path in [
+            "/",
+            "/.well-known/masque/udp/example.com/443",
+            "/.well-known/masque/udp/example.com/0/",
+            "/.well-known/masque/udp/example.com/65536/",
+            "/.well-known/masque/udp//443/",
+            "/.well-known/masque/udp/example.com/443/more/",
+            "/.well-known/masque/udp/bad%2/443/",
+        ] {
+            assert_eq!(parse_target(path), None, "{path}");
+        }
+    }
+}
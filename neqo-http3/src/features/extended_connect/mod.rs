@@ -60,6 +60,26 @@ pub(crate) trait ExtendedConnectEvents: Debug {
     );
     fn extended_connect_new_stream(&self, stream_info: Http3StreamInfo);
     fn new_datagram(&self, session_id: StreamId, datagram: Vec<u8>);
+    fn datagram_dropped_too_big(&self, session_id: StreamId);
+    fn session_draining(&self, session_id: StreamId);
+}
+
+/// Counters for a single `WebTransport` session.  Datagrams and streams that
+/// belong to other sessions on the same connection are not included.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WebTransportSessionStats {
+    /// Datagrams that were queued for sending.
+    pub datagrams_sent: usize,
+    /// Datagrams that were received.
+    pub datagrams_received: usize,
+    /// Datagrams that were not sent, because they were larger than the session allows.
+    pub datagrams_dropped_too_big: usize,
+    /// Bytes that were written to the session's streams.
+    pub stream_bytes_sent: u64,
+    /// Bytes that were read from the session's streams.
+    pub stream_bytes_received: u64,
+    /// Streams that are open in at least one direction.
+    pub open_streams: usize,
 }
 
 #[derive(Debug, PartialEq, Copy, Clone, Eq)]
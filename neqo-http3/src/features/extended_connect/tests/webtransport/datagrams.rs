@@ -5,16 +5,19 @@
 // except according to those terms.
 
 use neqo_common::Encoder;
-use neqo_transport::Error as TransportError;
+use neqo_transport::{Error as TransportError, StreamType};
 
 use crate::{
-    features::extended_connect::tests::webtransport::{
-        wt_default_parameters, WtTest, DATAGRAM_SIZE,
+    features::extended_connect::{
+        tests::webtransport::{wt_default_parameters, WtTest, DATAGRAM_SIZE},
+        WebTransportSessionStats,
     },
-    Error, Http3Parameters, WebTransportRequest,
+    Error, Http3ClientEvent, Http3Parameters, Http3ServerEvent, WebTransportEvent,
+    WebTransportRequest, WebTransportServerEvent,
 };
 
 const DGRAM: &[u8] = &[0, 100];
+const BUF: &[u8] = &[0; 10];
 
 #[test]
 fn no_datagrams() {
@@ -142,3 +145,101 @@ fn datagrams_multiple_session() {
     let mut wt_session_2 = wt.create_wt_session();
     do_datagram_test(&mut wt, &mut wt_session_2);
 }
+
+#[test]
+fn session_stats() {
+    let mut wt = WtTest::new();
+    let wt_session1 = wt.create_wt_session();
+    let mut wt_session2 = wt.create_wt_session();
+
+    // The client sends two datagrams and some stream data on the first session.
+    assert_eq!(wt.send_datagram(wt_session1.stream_id(), DGRAM), Ok(()));
+    assert_eq!(wt.send_datagram(wt_session1.stream_id(), DGRAM), Ok(()));
+    let wt_stream = wt.create_wt_stream_client(wt_session1.stream_id(), StreamType::UniDi);
+    wt.send_data_client(wt_stream, BUF);
+    wt.receive_data_server(wt_stream, true, BUF, false);
+
+    // The server sends one datagram on the second session.
+    assert_eq!(wt_session2.send_datagram(DGRAM, None), Ok(()));
+    wt.exchange_packets();
+
+    let bytes = u64::try_from(BUF.len()).unwrap();
+    assert_eq!(
+        wt.client
+            .webtransport_session_stats(wt_session1.stream_id())
+            .unwrap(),
+        WebTransportSessionStats {
+            datagrams_sent: 2,
+            stream_bytes_sent: bytes,
+            open_streams: 1,
+            ..WebTransportSessionStats::default()
+        }
+    );
+    assert_eq!(
+        wt_session1.stats().unwrap(),
+        WebTransportSessionStats {
+            datagrams_received: 2,
+            stream_bytes_received: bytes,
+            open_streams: 1,
+            ..WebTransportSessionStats::default()
+        }
+    );
+    assert_eq!(
+        wt.client
+            .webtransport_session_stats(wt_session2.stream_id())
+            .unwrap(),
+        WebTransportSessionStats {
+            datagrams_received: 1,
+            ..WebTransportSessionStats::default()
+        }
+    );
+    assert_eq!(
+        wt_session2.stats().unwrap(),
+        WebTransportSessionStats {
+            datagrams_sent: 1,
+            ..WebTransportSessionStats::default()
+        }
+    );
+}
+
+#[test]
+fn datagram_too_big_for_session() {
+    let mut wt = WtTest::new();
+    let mut wt_session1 = wt.create_wt_session();
+    let wt_session2 = wt.create_wt_session();
+
+    // This would fit in a QUIC datagram, but not once the session ID is added.
+    let too_big = vec![0; usize::try_from(wt_session1.max_datagram_size().unwrap()).unwrap() + 1];
+    assert!(too_big.len() <= usize::try_from(DATAGRAM_SIZE).unwrap());
+
+    assert_eq!(
+        wt_session1.send_datagram(&too_big, None),
+        Err(Error::TransportError(TransportError::TooMuchData))
+    );
+    wt.exchange_packets();
+    assert!(wt.server.events().any(|e| matches!(
+        e,
+        Http3ServerEvent::WebTransport(WebTransportServerEvent::DatagramDroppedTooBig { session })
+            if session.stream_id() == wt_session1.stream_id()
+    )));
+    assert_eq!(wt_session1.stats().unwrap().datagrams_dropped_too_big, 1);
+    assert_eq!(wt_session2.stats().unwrap().datagrams_dropped_too_big, 0);
+
+    assert_eq!(
+        wt.send_datagram(wt_session2.stream_id(), &too_big),
+        Err(Error::TransportError(TransportError::TooMuchData))
+    );
+    assert!(wt.client.events().any(|e| e
+        == Http3ClientEvent::WebTransport(WebTransportEvent::DatagramDroppedTooBig {
+            session_id: wt_session2.stream_id()
+        })));
+    let client_stats = |id| wt.client.webtransport_session_stats(id).unwrap();
+    assert_eq!(
+        client_stats(wt_session1.stream_id()).datagrams_dropped_too_big,
+        0
+    );
+    assert_eq!(
+        client_stats(wt_session2.stream_id()).datagrams_dropped_too_big,
+        1
+    );
+}
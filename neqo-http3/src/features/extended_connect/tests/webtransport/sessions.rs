@@ -288,6 +288,67 @@ fn wt_session_close_frame_server() {
     );
 }
 
+#[test]
+fn wt_session_drain_client() {
+    let mut wt = WtTest::new();
+    let mut wt_session = wt.create_wt_session();
+
+    let mut enc = Encoder::default();
+    WebTransportFrame::DrainSession.encode(&mut enc);
+    wt.client
+        .send_data(wt_session.stream_id(), enc.as_ref())
+        .unwrap();
+    wt.exchange_packets();
+
+    let draining = |e| {
+        matches!(
+            e,
+            Http3ServerEvent::WebTransport(WebTransportServerEvent::SessionDraining { session })
+                if session.stream_id() == wt_session.stream_id()
+        )
+    };
+    assert!(wt.server.events().any(draining));
+
+    // The session stays open until it is closed.
+    wt.session_close_frame_client(wt_session.stream_id(), 0, "");
+    wt.exchange_packets();
+    wt.check_session_closed_event_server(
+        &mut wt_session,
+        &SessionCloseReason::Clean {
+            error: 0,
+            message: String::new(),
+        },
+    );
+}
+
+#[test]
+fn wt_session_drain_server() {
+    let mut wt = WtTest::new();
+    let mut wt_session = wt.create_wt_session();
+
+    let mut enc = Encoder::default();
+    WebTransportFrame::DrainSession.encode(&mut enc);
+    wt_session.send_data(enc.as_ref()).unwrap();
+    wt.exchange_packets();
+
+    let draining = Http3ClientEvent::WebTransport(WebTransportEvent::SessionDraining {
+        stream_id: wt_session.stream_id(),
+    });
+    assert!(wt.client.events().any(|e| e == draining));
+
+    // The session stays open until it is closed.
+    WtTest::session_close_frame_server(&mut wt_session, 0, "");
+    wt.exchange_packets();
+    wt.check_session_closed_event_client(
+        wt_session.stream_id(),
+        &SessionCloseReason::Clean {
+            error: 0,
+            message: String::new(),
+        },
+        &None,
+    );
+}
+
 #[test]
 fn wt_unknown_session_frame_client() {
     const UNKNOWN_FRAME_LEN: usize = 832;
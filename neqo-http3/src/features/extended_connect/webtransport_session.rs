@@ -8,11 +8,14 @@ use std::{cell::RefCell, collections::BTreeSet, mem, rc::Rc};
 
 use neqo_common::{qtrace, Encoder, Header, MessageType, Role};
 use neqo_qpack::{QPackDecoder, QPackEncoder};
-use neqo_transport::{Connection, DatagramTracking, StreamId};
+use neqo_transport::{Connection, DatagramTracking, Error as TransportError, StreamId};
 
-use super::{ExtendedConnectEvents, ExtendedConnectType, SessionCloseReason};
+use super::{
+    ExtendedConnectEvents, ExtendedConnectType, SessionCloseReason, WebTransportSessionStats,
+};
 use crate::{
-    frames::{FrameReader, StreamReaderRecvStreamWrapper, WebTransportFrame},
+    frames::{Capsule, FrameReader, StreamReaderRecvStreamWrapper, WebTransportFrame},
+    qlog::FrameQlog,
     recv_message::{RecvMessage, RecvMessageInfo},
     send_message::SendMessage,
     CloseType, Error, HFrame, Http3StreamInfo, Http3StreamType, HttpRecvStream,
@@ -46,6 +49,7 @@ pub(crate) struct WebTransportSession {
     send_streams: BTreeSet<StreamId>,
     recv_streams: BTreeSet<StreamId>,
     role: Role,
+    stats: WebTransportSessionStats,
 }
 
 impl ::std::fmt::Display for WebTransportSession {
@@ -62,28 +66,35 @@ impl WebTransportSession {
         role: Role,
         qpack_encoder: Rc<RefCell<QPackEncoder>>,
         qpack_decoder: Rc<RefCell<QPackDecoder>>,
+        frame_qlog: FrameQlog,
     ) -> Self {
         let stream_event_listener = Rc::new(RefCell::new(WebTransportSessionListener::default()));
         Self {
-            control_stream_recv: Box::new(RecvMessage::new(
-                &RecvMessageInfo {
-                    message_type: MessageType::Response,
-                    stream_type: Http3StreamType::ExtendedConnect,
-                    stream_id: session_id,
-                    header_frame_type_read: false,
-                },
-                qpack_decoder,
-                Box::new(stream_event_listener.clone()),
-                None,
-                PriorityHandler::new(false, Priority::default()),
-            )),
-            control_stream_send: Box::new(SendMessage::new(
-                MessageType::Request,
-                Http3StreamType::ExtendedConnect,
-                session_id,
-                qpack_encoder,
-                Box::new(stream_event_listener.clone()),
-            )),
+            control_stream_recv: Box::new(
+                RecvMessage::new(
+                    &RecvMessageInfo {
+                        message_type: MessageType::Response,
+                        stream_type: Http3StreamType::ExtendedConnect,
+                        stream_id: session_id,
+                        header_frame_type_read: false,
+                    },
+                    qpack_decoder,
+                    Box::new(stream_event_listener.clone()),
+                    None,
+                    PriorityHandler::new(false, Priority::default()),
+                )
+                .frame_qlog(frame_qlog.clone()),
+            ),
+            control_stream_send: Box::new(
+                SendMessage::new(
+                    MessageType::Request,
+                    Http3StreamType::ExtendedConnect,
+                    session_id,
+                    qpack_encoder,
+                    Box::new(stream_event_listener.clone()),
+                )
+                .frame_qlog(frame_qlog),
+            ),
             stream_event_listener,
             session_id,
             state: SessionState::Negotiating,
@@ -92,6 +103,7 @@ impl WebTransportSession {
             send_streams: BTreeSet::new(),
             recv_streams: BTreeSet::new(),
             role,
+            stats: WebTransportSessionStats::default(),
         }
     }
 
@@ -127,6 +139,7 @@ impl WebTransportSession {
             send_streams: BTreeSet::new(),
             recv_streams: BTreeSet::new(),
             role,
+            stats: WebTransportSessionStats::default(),
         }
     }
 
@@ -328,6 +341,22 @@ impl WebTransportSession {
         matches!(self.state, SessionState::Active)
     }
 
+    #[must_use]
+    pub fn stats(&self) -> WebTransportSessionStats {
+        WebTransportSessionStats {
+            open_streams: self.send_streams.union(&self.recv_streams).count(),
+            ..self.stats
+        }
+    }
+
+    pub fn stream_data_sent(&mut self, amount: usize) {
+        self.stats.stream_bytes_sent += u64::try_from(amount).unwrap();
+    }
+
+    pub fn stream_data_received(&mut self, amount: usize) {
+        self.stats.stream_bytes_received += u64::try_from(amount).unwrap();
+    }
+
     pub fn take_sub_streams(&mut self) -> (BTreeSet<StreamId>, BTreeSet<StreamId>) {
         (
             mem::take(&mut self.recv_streams),
@@ -339,14 +368,34 @@ impl WebTransportSession {
     ///
     /// It may return an error if the frame is not correctly decoded.
     pub fn read_control_stream(&mut self, conn: &mut Connection) -> Res<()> {
-        let (f, fin) = self
-            .frame_reader
-            .receive::<WebTransportFrame>(&mut StreamReaderRecvStreamWrapper::new(
-                conn,
-                &mut self.control_stream_recv,
-            ))
-            .map_err(|_| Error::HttpGeneralProtocolStream)?;
-        qtrace!([self], "Received frame: {:?} fin={}", f, fin);
+        loop {
+            let (capsule, fin) = self
+                .frame_reader
+                .receive::<Capsule>(&mut StreamReaderRecvStreamWrapper::new(
+                    conn,
+                    &mut self.control_stream_recv,
+                ))
+                .map_err(|_| Error::HttpGeneralProtocolStream)?;
+            let f = capsule
+                .as_ref()
+                .map(WebTransportFrame::from_capsule)
+                .transpose()
+                .map_err(|_| Error::HttpGeneralProtocolStream)?
+                .flatten();
+            qtrace!([self], "Received frame: {:?} fin={}", f, fin);
+            if f == Some(WebTransportFrame::DrainSession) && !fin {
+                self.events.session_draining(self.session_id);
+                continue;
+            }
+            if capsule.is_some() && f.is_none() && !fin {
+                // Unknown capsules are ignored.
+                continue;
+            }
+            return self.handle_control_frame(f, fin);
+        }
+    }
+
+    fn handle_control_frame(&mut self, f: Option<WebTransportFrame>, fin: bool) -> Res<()> {
         if let Some(WebTransportFrame::CloseSession { error, message }) = f {
             self.events.session_end(
                 ExtendedConnectType::WebTransport,
@@ -405,7 +454,7 @@ impl WebTransportSession {
     ///
     /// Returns an error if the datagram exceeds the remote datagram size limit.
     pub fn send_datagram(
-        &self,
+        &mut self,
         conn: &mut Connection,
         buf: &[u8],
         id: impl Into<DatagramTracking>,
@@ -415,7 +464,18 @@ impl WebTransportSession {
             let mut dgram_data = Encoder::default();
             dgram_data.encode_varint(self.session_id.as_u64() / 4);
             dgram_data.encode(buf);
-            conn.send_datagram(dgram_data.as_ref(), id)?;
+            match conn.send_datagram(dgram_data.as_ref(), id) {
+                Ok(()) => self.stats.datagrams_sent += 1,
+                Err(TransportError::TooMuchData) => {
+                    // The session ID takes some of the space, so this can happen even
+                    // when the datagram would fit on its own.
+                    qtrace!([self], "datagram of {} bytes is too big", buf.len());
+                    self.stats.datagrams_dropped_too_big += 1;
+                    self.events.datagram_dropped_too_big(self.session_id);
+                    return Err(Error::TransportError(TransportError::TooMuchData));
+                }
+                Err(e) => return Err(e.into()),
+            }
         } else {
             debug_assert!(false);
             return Err(Error::Unavailable);
@@ -425,6 +485,7 @@ impl WebTransportSession {
 
     pub fn datagram(&mut self, datagram: Vec<u8>) {
         if let SessionState::Active = self.state {
+            self.stats.datagrams_received += 1;
             self.events.new_datagram(self.session_id, datagram);
         }
     }
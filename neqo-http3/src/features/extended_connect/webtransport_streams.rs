@@ -71,8 +71,10 @@ impl RecvStream for WebTransportRecvStream {
     fn read_data(&mut self, conn: &mut Connection, buf: &mut [u8]) -> Res<(usize, bool)> {
         let (amount, fin) = conn.stream_recv(self.stream_id, buf)?;
         self.fin = fin;
+        let mut session = self.session.borrow_mut();
+        session.stream_data_received(amount);
         if fin {
-            self.session.borrow_mut().remove_recv_stream(self.stream_id);
+            session.remove_recv_stream(self.stream_id);
         }
         Ok((amount, fin))
     }
@@ -205,10 +207,19 @@ impl SendStream for WebTransportSendStream {
         self.state == WebTransportSenderStreamState::Done
     }
 
+    fn send_data_avail(&self, conn: &Connection) -> Res<usize> {
+        if self.state == WebTransportSenderStreamState::SendingData {
+            Ok(conn.stream_avail_send_space(self.stream_id)?)
+        } else {
+            Ok(0)
+        }
+    }
+
     fn send_data(&mut self, conn: &mut Connection, buf: &[u8]) -> Res<usize> {
         self.send(conn)?;
         if self.state == WebTransportSenderStreamState::SendingData {
             let sent = conn.stream_send(self.stream_id, buf)?;
+            self.session.borrow_mut().stream_data_sent(sent);
             Ok(sent)
         } else {
             Ok(0)
@@ -13,6 +13,7 @@ use crate::{
     settings::{HSettingType, HSettings},
 };
 
+pub(crate) mod connect_udp;
 pub mod extended_connect;
 
 /// States:
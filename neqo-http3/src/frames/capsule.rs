@@ -0,0 +1,56 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The Capsule Protocol ([RFC 9297][1]).
+//!
+//! Capsules are carried in the payload of DATA frames, so a capsule can be split
+//! across any number of DATA frames and a DATA frame can hold several capsules.
+//! The encoding is the same as that of an HTTP/3 frame: a type and a length, both
+//! varints, followed by the payload.
+//!
+//! [1]: https://www.rfc-editor.org/rfc/rfc9297#section-3
+
+use neqo_common::Encoder;
+
+use crate::{frames::reader::FrameDecoder, Error, Res};
+
+/// Capsules with a larger payload are treated as a malformed message.
+const MAX_CAPSULE_PAYLOAD: u64 = 1 << 20;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) struct Capsule {
+    pub capsule_type: u64,
+    pub payload: Vec<u8>,
+}
+
+impl Capsule {
+    pub fn new(capsule_type: u64, payload: &[u8]) -> Self {
+        Self {
+            capsule_type,
+            payload: payload.to_vec(),
+        }
+    }
+
+    pub fn encode(&self, enc: &mut Encoder) {
+        enc.encode_varint(self.capsule_type);
+        enc.encode_vvec(&self.payload);
+    }
+}
+
+impl FrameDecoder<Capsule> for Capsule {
+    fn decode(capsule_type: u64, capsule_len: u64, data: Option<&[u8]>) -> Res<Option<Capsule>> {
+        if capsule_len > MAX_CAPSULE_PAYLOAD {
+            return Err(Error::HttpMessageError);
+        }
+        Ok(data.map(|payload| Capsule::new(capsule_type, payload)))
+    }
+
+    /// Capsules of all types are passed on, so that applications can implement
+    /// extensions that neqo does not know about.
+    fn is_known_type(_capsule_type: u64) -> bool {
+        true
+    }
+}
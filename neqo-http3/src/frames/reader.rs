@@ -10,11 +10,11 @@ use std::fmt::Debug;
 
 use neqo_common::{
     hex_with_len, qtrace, Decoder, IncrementalDecoderBuffer, IncrementalDecoderIgnore,
-    IncrementalDecoderUint,
+    StatefulDecoder,
 };
 use neqo_transport::{Connection, StreamId};
 
-use crate::{Error, RecvStream, Res};
+use crate::{qlog::FrameQlog, Error, RecvStream, Res};
 
 const MAX_READ_SIZE: usize = 4096;
 
@@ -40,16 +40,31 @@ pub(crate) trait StreamReader {
     /// Return an error if the stream was closed on the transport layer, but that information is not
     /// yet consumed on the  http/3 layer.
     fn read_data(&mut self, buf: &mut [u8]) -> Res<(usize, bool)>;
+
+    /// Called when a frame of an unknown type is skipped.
+    fn unknown_frame(&mut self, _frame_type: u64, _len: u64) {}
 }
 
 pub(crate) struct StreamReaderConnectionWrapper<'a> {
     conn: &'a mut Connection,
     stream_id: StreamId,
+    frame_qlog: Option<&'a FrameQlog>,
 }
 
 impl<'a> StreamReaderConnectionWrapper<'a> {
     pub fn new(conn: &'a mut Connection, stream_id: StreamId) -> Self {
-        Self { conn, stream_id }
+        Self {
+            conn,
+            stream_id,
+            frame_qlog: None,
+        }
+    }
+
+    /// Log the unknown frames that are skipped with `frame_qlog`.
+    #[must_use]
+    pub fn frame_qlog(mut self, frame_qlog: &'a FrameQlog) -> Self {
+        self.frame_qlog = Some(frame_qlog);
+        self
     }
 }
 
@@ -61,6 +76,12 @@ impl<'a> StreamReader for StreamReaderConnectionWrapper<'a> {
         let res = self.conn.stream_recv(self.stream_id, buf)?;
         Ok(res)
     }
+
+    fn unknown_frame(&mut self, frame_type: u64, len: u64) {
+        if let Some(frame_qlog) = self.frame_qlog {
+            frame_qlog.unknown_parsed(self.conn.qlog_mut(), self.stream_id, frame_type, len);
+        }
+    }
 }
 
 pub(crate) struct StreamReaderRecvStreamWrapper<'a> {
@@ -85,8 +106,8 @@ impl<'a> StreamReader for StreamReaderRecvStreamWrapper<'a> {
 
 #[derive(Clone, Debug)]
 enum FrameReaderState {
-    GetType { decoder: IncrementalDecoderUint },
-    GetLength { decoder: IncrementalDecoderUint },
+    GetType { decoder: StatefulDecoder },
+    GetLength { decoder: StatefulDecoder },
     GetData { decoder: IncrementalDecoderBuffer },
     UnknownFrameDischargeData { decoder: IncrementalDecoderIgnore },
 }
@@ -97,6 +118,8 @@ pub(crate) struct FrameReader {
     state: FrameReaderState,
     frame_type: u64,
     frame_len: u64,
+    /// The type and length of an unknown frame that was just skipped.
+    skipped: Option<(u64, u64)>,
 }
 
 impl Default for FrameReader {
@@ -110,10 +133,11 @@ impl FrameReader {
     pub fn new() -> Self {
         Self {
             state: FrameReaderState::GetType {
-                decoder: IncrementalDecoderUint::default(),
+                decoder: StatefulDecoder::default(),
             },
             frame_type: 0,
             frame_len: 0,
+            skipped: None,
         }
     }
 
@@ -121,16 +145,17 @@ impl FrameReader {
     pub fn new_with_type(frame_type: u64) -> Self {
         Self {
             state: FrameReaderState::GetLength {
-                decoder: IncrementalDecoderUint::default(),
+                decoder: StatefulDecoder::default(),
             },
             frame_type,
             frame_len: 0,
+            skipped: None,
         }
     }
 
     fn reset(&mut self) {
         self.state = FrameReaderState::GetType {
-            decoder: IncrementalDecoderUint::default(),
+            decoder: StatefulDecoder::default(),
         };
     }
 
@@ -144,7 +169,7 @@ impl FrameReader {
         }
     }
 
-    fn decoding_in_progress(&self) -> bool {
+    pub fn decoding_in_progress(&self) -> bool {
         if let FrameReaderState::GetType { decoder } = &self.state {
             decoder.decoding_in_progress()
         } else {
@@ -172,7 +197,11 @@ impl FrameReader {
                 (0, f) => (None, false, f),
                 (amount, f) => {
                     qtrace!("FrameReader::receive: reading {} byte, fin={}", amount, f);
-                    (self.consume::<T>(Decoder::from(&buf[..amount]))?, true, f)
+                    let output = self.consume::<T>(Decoder::from(&buf[..amount]))?;
+                    if let Some((frame_type, len)) = self.skipped.take() {
+                        stream_reader.unknown_frame(frame_type, len);
+                    }
+                    (output, true, f)
                 }
             };
 
@@ -200,13 +229,13 @@ impl FrameReader {
     fn consume<T: FrameDecoder<T>>(&mut self, mut input: Decoder) -> Res<Option<T>> {
         match &mut self.state {
             FrameReaderState::GetType { decoder } => {
-                if let Some(v) = decoder.consume(&mut input) {
+                if let Some(v) = decoder.decode_varint(&mut input) {
                     qtrace!("FrameReader::receive: read frame type {}", v);
                     self.frame_type_decoded::<T>(v)?;
                 }
             }
             FrameReaderState::GetLength { decoder } => {
-                if let Some(len) = decoder.consume(&mut input) {
+                if let Some(len) = decoder.decode_varint(&mut input) {
                     qtrace!(
                         "FrameReader::receive: frame type {} length {}",
                         self.frame_type,
@@ -240,7 +269,7 @@ impl FrameReader {
         T::frame_type_allowed(frame_type)?;
         self.frame_type = frame_type;
         self.state = FrameReaderState::GetLength {
-            decoder: IncrementalDecoderUint::default(),
+            decoder: StatefulDecoder::default(),
         };
         Ok(())
     }
@@ -261,8 +290,10 @@ impl FrameReader {
                 ),
             };
         } else if self.frame_len == 0 {
+            self.skipped = Some((self.frame_type, 0));
             self.reset();
         } else {
+            self.skipped = Some((self.frame_type, len));
             self.state = FrameReaderState::UnknownFrameDischargeData {
                 decoder: IncrementalDecoderIgnore::new(
                     usize::try_from(len).or(Err(Error::HttpFrame))?,
@@ -12,7 +12,7 @@ use super::enc_dec_hframe;
 use crate::{
     frames::HFrame,
     settings::{HSetting, HSettingType, HSettings},
-    Priority,
+    Error, Priority,
 };
 
 #[test]
@@ -43,6 +43,26 @@ fn test_settings_frame4() {
     enc_dec_hframe(&f, "04020604", 0);
 }
 
+#[test]
+fn test_settings_frame_unknown() {
+    let f = HFrame::Settings {
+        settings: HSettings::new(&[
+            HSetting::new(HSettingType::MaxHeaderListSize, 4),
+            HSetting::new(HSettingType::Unknown(0x21), 5),
+        ]),
+    };
+    enc_dec_hframe(&f, "040406042105", 0);
+}
+
+#[test]
+fn test_settings_duplicate() {
+    let mut settings = HSettings::default();
+    assert_eq!(
+        settings.decode_frame_contents(&mut Decoder::from(&[0x06, 0x04, 0x06, 0x05][..])),
+        Err(Error::HttpSettings)
+    );
+}
+
 #[test]
 fn test_push_promise_frame4() {
     let f = HFrame::PushPromise {
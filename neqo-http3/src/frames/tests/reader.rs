@@ -12,7 +12,8 @@ use test_fixture::{connect, now};
 
 use crate::{
     frames::{
-        reader::FrameDecoder, FrameReader, HFrame, StreamReaderConnectionWrapper, WebTransportFrame,
+        reader::FrameDecoder, Capsule, FrameReader, HFrame, StreamReaderConnectionWrapper,
+        WebTransportFrame,
     },
     settings::{HSetting, HSettingType, HSettings},
     Error,
@@ -173,8 +174,9 @@ fn test_frame_reading_with_stream_wt_close_session() {
     }
     let frame = fr.process::<WebTransportFrame>(&[0x6f]);
 
-    assert!(frame.is_some());
-    let WebTransportFrame::CloseSession { error, message } = frame.unwrap();
+    let Some(WebTransportFrame::CloseSession { error, message }) = frame else {
+        panic!("expected a CloseSession frame");
+    };
     assert_eq!(error, 5);
     assert_eq!(message, "Hello".to_string());
 }
@@ -198,12 +200,28 @@ fn test_unknown_wt_frame() {
     let frame = fr.process(&[
         0x68, 0x43, 0x09, 0x00, 0x00, 0x00, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f,
     ]);
-    assert!(frame.is_some());
-    let WebTransportFrame::CloseSession { error, message } = frame.unwrap();
+    let Some(WebTransportFrame::CloseSession { error, message }) = frame else {
+        panic!("expected a CloseSession frame");
+    };
     assert_eq!(error, 5);
     assert_eq!(message, "Hello".to_string());
 }
 
+// Capsules of any type are passed on, even if they are split into many reads.
+#[test]
+fn test_unknown_capsule() {
+    let mut fr = FrameReaderTest::new();
+
+    let capsule = Capsule::new(1028, &[0x42; 100]);
+    let mut enc = Encoder::default();
+    capsule.encode(&mut enc);
+    let (last, buf) = enc.as_ref().split_last().unwrap();
+    for chunk in buf.chunks(7) {
+        assert!(fr.process::<Capsule>(chunk).is_none());
+    }
+    assert_eq!(fr.process::<Capsule>(&[*last]), Some(capsule));
+}
+
 enum FrameReadingTestSend {
     OnlyData,
     DataWithFin,
@@ -472,6 +490,19 @@ fn test_complete_and_incomplete_wt_frames() {
     test_complete_and_incomplete_frame::<WebTransportFrame>(&buf, buf.len());
 }
 
+#[test]
+fn test_complete_and_incomplete_capsules() {
+    let mut enc = Encoder::default();
+    Capsule::new(0, &[]).encode(&mut enc);
+    let buf: Vec<_> = enc.into();
+    test_complete_and_incomplete_frame::<Capsule>(&buf, buf.len());
+
+    let mut enc = Encoder::default();
+    Capsule::new(0x1234, b"capsule payload").encode(&mut enc);
+    let buf: Vec<_> = enc.into();
+    test_complete_and_incomplete_frame::<Capsule>(&buf, buf.len());
+}
+
 // Test closing a stream before any frame is sent should not cause an error.
 #[test]
 fn test_frame_reading_when_stream_is_closed_before_sending_data() {
@@ -15,3 +15,8 @@ fn test_wt_close_session() {
     };
     enc_dec_wtframe(&f, "6843090000000548656c6c6f", 0);
 }
+
+#[test]
+fn test_wt_drain_session() {
+    enc_dec_wtframe(&WebTransportFrame::DrainSession, "800078ae00", 0);
+}
@@ -6,55 +6,90 @@
 
 use neqo_common::{Decoder, Encoder};
 
-use crate::{frames::reader::FrameDecoder, Error, Res};
+use crate::{
+    frames::{reader::FrameDecoder, Capsule},
+    Error, Res,
+};
 
 pub(crate) type WebTransportFrameType = u64;
 
 const WT_FRAME_CLOSE_SESSION: WebTransportFrameType = 0x2843;
+const WT_FRAME_DRAIN_SESSION: WebTransportFrameType = 0x78ae;
 const WT_FRAME_CLOSE_MAX_MESSAGE_SIZE: u64 = 1024;
 
+/// The capsules that `WebTransport` uses on the session stream.
 #[derive(PartialEq, Eq, Debug)]
 pub enum WebTransportFrame {
     CloseSession { error: u32, message: String },
+    DrainSession,
 }
 
 impl WebTransportFrame {
     pub fn encode(&self, enc: &mut Encoder) {
-        enc.encode_varint(WT_FRAME_CLOSE_SESSION);
-        let WebTransportFrame::CloseSession { error, message } = &self;
-        enc.encode_varint(4 + message.len() as u64);
-        enc.encode_uint(4, *error);
-        enc.encode(message.as_bytes());
+        self.capsule().encode(enc);
     }
-}
 
-impl FrameDecoder<WebTransportFrame> for WebTransportFrame {
-    fn decode(
-        frame_type: u64,
-        frame_len: u64,
-        data: Option<&[u8]>,
-    ) -> Res<Option<WebTransportFrame>> {
-        if let Some(payload) = data {
-            let mut dec = Decoder::from(payload);
-            if frame_type == WT_FRAME_CLOSE_SESSION {
-                if frame_len > WT_FRAME_CLOSE_MAX_MESSAGE_SIZE + 4 {
+    fn capsule(&self) -> Capsule {
+        match self {
+            Self::CloseSession { error, message } => {
+                let mut payload = Encoder::with_capacity(4 + message.len());
+                payload.encode_uint(4, *error);
+                payload.encode(message.as_bytes());
+                Capsule::new(WT_FRAME_CLOSE_SESSION, payload.as_ref())
+            }
+            Self::DrainSession => Capsule::new(WT_FRAME_DRAIN_SESSION, &[]),
+        }
+    }
+
+    /// Interpret a capsule that was received on the session stream.
+    /// Capsules of other types are ignored.
+    ///
+    /// # Errors
+    ///
+    /// `HttpMessageError` if the capsule is malformed.
+    pub fn from_capsule(capsule: &Capsule) -> Res<Option<Self>> {
+        match capsule.capsule_type {
+            WT_FRAME_CLOSE_SESSION => {
+                if capsule.payload.len() as u64 > WT_FRAME_CLOSE_MAX_MESSAGE_SIZE + 4 {
                     return Err(Error::HttpMessageError);
                 }
+                let mut dec = Decoder::from(&capsule.payload[..]);
                 let error =
                     u32::try_from(dec.decode_uint(4).ok_or(Error::HttpMessageError)?).unwrap();
                 let Ok(message) = String::from_utf8(dec.decode_remainder().to_vec()) else {
                     return Err(Error::HttpMessageError);
                 };
-                Ok(Some(WebTransportFrame::CloseSession { error, message }))
-            } else {
-                Ok(None)
+                Ok(Some(Self::CloseSession { error, message }))
+            }
+            WT_FRAME_DRAIN_SESSION => {
+                if !capsule.payload.is_empty() {
+                    return Err(Error::HttpMessageError);
+                }
+                Ok(Some(Self::DrainSession))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl FrameDecoder<WebTransportFrame> for WebTransportFrame {
+    fn decode(
+        frame_type: u64,
+        frame_len: u64,
+        data: Option<&[u8]>,
+    ) -> Res<Option<WebTransportFrame>> {
+        if frame_type == WT_FRAME_CLOSE_SESSION && frame_len > WT_FRAME_CLOSE_MAX_MESSAGE_SIZE + 4 {
+            return Err(Error::HttpMessageError);
+        }
+        match data {
+            Some(payload) if Self::is_known_type(frame_type) => {
+                Self::from_capsule(&Capsule::new(frame_type, payload))
             }
-        } else {
-            Ok(None)
+            _ => Ok(None),
         }
     }
 
     fn is_known_type(frame_type: u64) -> bool {
-        frame_type == WT_FRAME_CLOSE_SESSION
+        matches!(frame_type, WT_FRAME_CLOSE_SESSION | WT_FRAME_DRAIN_SESSION)
     }
 }
@@ -134,6 +134,7 @@ while let Some(event) = client.next_event() {
 
 */
 
+mod buffered_fetch;
 mod buffered_send_stream;
 mod client_events;
 mod conn_params;
@@ -161,9 +162,10 @@ mod stream_type_reader;
 
 use std::{cell::RefCell, fmt::Debug, rc::Rc};
 
+pub use buffered_fetch::{BufferedResponse, FetchHandle, RequestParams};
 use buffered_send_stream::BufferedStream;
 pub use client_events::{Http3ClientEvent, WebTransportEvent};
-pub use conn_params::Http3Parameters;
+pub use conn_params::{Http3Parameters, RequestTimeouts};
 pub use connection::{Http3State, WebTransportSessionAcceptAction};
 pub use connection_client::Http3Client;
 use features::extended_connect::WebTransportSession;
@@ -180,6 +182,7 @@ pub use server::Http3Server;
 pub use server_events::{
     Http3OrWebTransportStream, Http3ServerEvent, WebTransportRequest, WebTransportServerEvent,
 };
+pub use settings::HSettingType;
 use stream_type_reader::NewStreamType;
 
 use crate::priority::PriorityHandler;
@@ -200,6 +203,8 @@ pub enum Error {
     HttpFrameUnexpected,
     HttpFrame,
     HttpExcessiveLoad,
+    HttpExcessiveLoadStream, /* this is the same as the above but it should only close a
+                              * stream not a connection. */
     HttpId,
     HttpSettings,
     HttpMissingSettings,
@@ -216,6 +221,8 @@ pub enum Error {
     AlreadyInitialized,
     DecodingFrame,
     FatalError,
+    /// A header section is larger than the peer's `SETTINGS_MAX_FIELD_SECTION_SIZE`.
+    FieldSectionTooLarge,
     HttpGoaway,
     Internal,
     InvalidHeader,
@@ -226,7 +233,11 @@ pub enum Error {
     InvalidStreamId,
     NoMoreData,
     NotEnoughData,
+    /// A buffered response body is larger than `RequestParams::max_body_size`.
+    ResponseBodyTooLarge,
     StreamLimitError,
+    /// The peer reset the stream, or asked for sending on it to stop, with this error code.
+    StreamAborted(AppError),
     TransportError(TransportError),
     TransportStreamDoesNotExist,
     Unavailable,
@@ -246,7 +257,7 @@ impl Error {
             Self::HttpClosedCriticalStream => 0x104,
             Self::HttpFrameUnexpected => 0x105,
             Self::HttpFrame => 0x106,
-            Self::HttpExcessiveLoad => 0x107,
+            Self::HttpExcessiveLoad | Self::HttpExcessiveLoadStream => 0x107,
             Self::HttpId => 0x108,
             Self::HttpSettings => 0x109,
             Self::HttpMissingSettings => 0x10a,
@@ -282,7 +293,13 @@ impl Error {
 
     #[must_use]
     pub fn stream_reset_error(&self) -> bool {
-        matches!(self, Self::HttpGeneralProtocolStream | Self::InvalidHeader)
+        matches!(
+            self,
+            Self::HttpGeneralProtocolStream
+                | Self::HttpExcessiveLoadStream
+                | Self::InvalidHeader
+                | Self::HttpMessageError
+        )
     }
 
     /// # Panics
@@ -371,6 +388,7 @@ impl From<QpackError> for Error {
     fn from(err: QpackError) -> Self {
         match err {
             QpackError::ClosedCriticalStream => Error::HttpClosedCriticalStream,
+            QpackError::FieldSectionTooLarge => Error::HttpExcessiveLoadStream,
             e => Self::QpackError(e),
         }
     }
@@ -503,6 +521,26 @@ trait HttpRecvStream: RecvStream {
     fn extended_connect_wait_for_response(&self) -> bool {
         false
     }
+
+    /// Read the rest of the body as a sequence of capsules, which are reported
+    /// with `HttpRecvStreamEvents::capsule_received`.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidInput` if the message headers have not been received yet or the body
+    /// has already ended.
+    fn capsule_protocol(&mut self, _conn: &mut Connection) -> Res<(ReceiveOutput, bool)> {
+        Err(Error::InvalidStreamId)
+    }
+
+    /// Mark the stream as the response to a CONNECT-UDP request.  A 2xx response
+    /// switches the body to the capsule protocol.
+    fn connect_udp(&mut self) {}
+
+    /// Whether a 2xx response to a CONNECT-UDP request has been received.
+    fn connect_udp_established(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -553,7 +591,15 @@ trait HttpRecvStreamEvents: RecvStreamEvents {
         interim: bool,
         fin: bool,
     );
+    fn trailers_ready(&self, _stream_info: Http3StreamInfo, _headers: Vec<Header>) {}
     fn extended_connect_new_session(&self, _stream_id: StreamId, _headers: Vec<Header>) {}
+    fn capsule_received(
+        &self,
+        _stream_info: Http3StreamInfo,
+        _capsule_type: u64,
+        _payload: Vec<u8>,
+    ) {
+    }
 }
 
 trait SendStream: Stream {
@@ -570,6 +616,15 @@ trait SendStream: Stream {
     /// Error may occur during sending data, e.g. protocol error, etc.
     fn send_data(&mut self, _conn: &mut Connection, _buf: &[u8]) -> Res<usize>;
 
+    /// How many bytes `send_data` would accept right now.
+    ///
+    /// # Errors
+    ///
+    /// It may happen that the transport stream is already closed.
+    fn send_data_avail(&self, _conn: &Connection) -> Res<usize> {
+        Err(Error::Unavailable)
+    }
+
     /// # Errors
     ///
     /// It may happen that the transport stream is already closed. This is unlikely.
@@ -616,6 +671,25 @@ trait HttpSendStream: SendStream {
     ///
     /// This can also return an error if the underlying stream is closed.
     fn send_headers(&mut self, headers: &[Header], conn: &mut Connection) -> Res<()>;
+    /// This function is used to supply a trailer section after all the data of a message.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidInput` if the message headers have not been sent yet or the message is already
+    /// finished, and `InvalidHeader` if the trailers contain a pseudo-header.
+    fn send_trailers(&mut self, headers: &[Header], conn: &mut Connection) -> Res<()>;
+    /// This function is used to send a `PUSH_PROMISE` frame on a response.
+    /// `headers` are the headers of the promised request.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidInput` if this is not a response or the response is complete.
+    fn send_push_promise(
+        &mut self,
+        push_id: u64,
+        headers: &[Header],
+        conn: &mut Connection,
+    ) -> Res<()>;
     fn set_new_listener(&mut self, _conn_events: Box<dyn SendStreamEvents>) {}
 }
 
@@ -6,7 +6,7 @@
 
 use std::fmt;
 
-use neqo_transport::StreamId;
+use neqo_transport::{streams::SendOrder, StreamId};
 use sfv::{BareItem, Item, ListEntry, Parser};
 
 use crate::{frames::HFrame, Error, Header, Res};
@@ -39,6 +39,23 @@ impl Priority {
         }
     }
 
+    #[must_use]
+    pub fn urgency(self) -> u8 {
+        self.urgency
+    }
+
+    #[must_use]
+    pub fn incremental(self) -> bool {
+        self.incremental
+    }
+
+    /// The transport send order for a response with this priority.  A lower urgency
+    /// value is more important, and a higher send order is sent first.  Responses with
+    /// the same urgency share the available bandwidth.
+    pub(crate) fn sendorder(self) -> SendOrder {
+        SendOrder::from(7 - self.urgency)
+    }
+
     /// Returns a header if required to send
     #[must_use]
     pub fn header(self) -> Option<Header> {
@@ -37,6 +37,7 @@ enum PushState {
     Init,
     PushPromise {
         headers: Vec<Header>,
+        request_stream_id: StreamId,
     },
     OnlyPushStream {
         stream_id: StreamId,
@@ -45,6 +46,7 @@ enum PushState {
     Active {
         stream_id: StreamId,
         headers: Vec<Header>,
+        request_stream_id: StreamId,
     },
     Closed,
 }
@@ -206,10 +208,11 @@ impl PushController {
                         .push_promise(push_id, ref_stream_id, new_headers.clone());
                     *push_state = PushState::PushPromise {
                         headers: new_headers,
+                        request_stream_id: ref_stream_id,
                     };
                     Ok(())
                 }
-                PushState::PushPromise { headers } | PushState::Active { headers, .. } => {
+                PushState::PushPromise { headers, .. } | PushState::Active { headers, .. } => {
                     if new_headers != *headers {
                         return Err(Error::HttpGeneralProtocol);
                     }
@@ -228,6 +231,7 @@ impl PushController {
                     *push_state = PushState::Active {
                         stream_id: stream_id_tmp,
                         headers: new_headers,
+                        request_stream_id: ref_stream_id,
                     };
                     Ok(())
                 }
@@ -258,11 +262,16 @@ impl PushController {
                     };
                     Ok(true)
                 }
-                PushState::PushPromise { headers } => {
+                PushState::PushPromise {
+                    headers,
+                    request_stream_id,
+                } => {
                     let tmp = mem::take(headers);
+                    let request_stream_id = *request_stream_id;
                     *push_state = PushState::Active {
                         stream_id,
                         headers: tmp,
+                        request_stream_id,
                     };
                     Ok(true)
                 }
@@ -377,6 +386,35 @@ impl PushController {
         }
     }
 
+    /// Cancel the pushes that were promised on a request that the application cancelled.
+    /// The application did not cancel these pushes itself, so `PushCanceled` is posted for each.
+    pub fn cancel_request_pushes(
+        &mut self,
+        request_stream_id: StreamId,
+        conn: &mut Connection,
+        base_handler: &mut Http3Connection,
+    ) {
+        let push_ids = (self.push_streams.first_push_id..)
+            .zip(&self.push_streams.push_streams)
+            .filter_map(|(push_id, state)| match state {
+                PushState::PushPromise {
+                    request_stream_id: r,
+                    ..
+                }
+                | PushState::Active {
+                    request_stream_id: r,
+                    ..
+                } if *r == request_stream_id => Some(push_id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for push_id in push_ids {
+            if self.cancel(push_id, conn, base_handler).is_ok() {
+                self.conn_events.push_canceled(push_id);
+            }
+        }
+    }
+
     pub fn push_stream_reset(&mut self, push_id: u64, close_type: CloseType) {
         qtrace!("Push stream has been reset, push_id={}", push_id);
 
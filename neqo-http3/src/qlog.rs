@@ -6,9 +6,18 @@
 
 // Functions that handle capturing QLOG traces.
 
-use neqo_common::qlog::NeqoQlog;
+use std::rc::Rc;
+
+use neqo_common::{qlog::NeqoQlog, Decoder, Header};
 use neqo_transport::StreamId;
-use qlog::events::{DataRecipient, EventData};
+use qlog::events::{
+    h3::{
+        H3FrameCreated, H3FrameParsed, H3PriorityTargetStreamType, Http3Frame, HttpHeader, Setting,
+    },
+    DataRecipient, EventData,
+};
+
+use crate::{frames::HFrame, settings::HSettingType, Http3Parameters};
 
 pub fn h3_data_moved_up(qlog: &mut NeqoQlog, stream_id: StreamId, amount: usize) {
     qlog.add_event_data(|| {
@@ -39,3 +48,232 @@ pub fn h3_data_moved_down(qlog: &mut NeqoQlog, stream_id: StreamId, amount: usiz
         Some(ev_data)
     });
 }
+
+/// The value that replaces redacted header values.
+const REDACTED: &str = "[redacted]";
+
+/// Logs HTTP/3 frames, if `Http3Parameters::qlog_http_frames` is set.
+/// This holds the names of the headers whose values are redacted.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FrameQlog(Option<Rc<[String]>>);
+
+impl FrameQlog {
+    pub fn new(params: &Http3Parameters) -> Self {
+        Self(
+            params
+                .get_qlog_http_frames()
+                .then(|| params.get_qlog_redacted_headers().into()),
+        )
+    }
+
+    /// Log a frame that is sent on `stream_id`.  `encoded` is the frame as it
+    /// is written to the stream, excluding any DATA payload.  HEADERS and
+    /// PUSH_PROMISE frames are logged with `headers`.
+    pub fn created(
+        &self,
+        qlog: &mut NeqoQlog,
+        stream_id: StreamId,
+        frame: &HFrame,
+        encoded: &[u8],
+        headers: &[Header],
+    ) {
+        if let Some(ev) = self.created_event(stream_id, frame, encoded, headers) {
+            qlog.add_event_data(|| Some(ev));
+        }
+    }
+
+    /// The event for `created`, for frames that are logged later, when they
+    /// are written to the stream.
+    pub fn created_event(
+        &self,
+        stream_id: StreamId,
+        frame: &HFrame,
+        encoded: &[u8],
+        headers: &[Header],
+    ) -> Option<EventData> {
+        self.0.as_ref()?;
+        let (length, frame) = self.frame(frame, headers, encoded);
+        Some(EventData::H3FrameCreated(H3FrameCreated {
+            stream_id: stream_id.as_u64(),
+            length,
+            frame,
+            raw: None,
+        }))
+    }
+
+    /// Log a frame that was received on `stream_id`.  PUSH_PROMISE frames are
+    /// logged before their header block is decoded, without headers.
+    pub fn parsed(
+        &self,
+        qlog: &mut NeqoQlog,
+        stream_id: StreamId,
+        frame: &HFrame,
+        headers: &[Header],
+    ) {
+        self.add(qlog, || {
+            let (length, frame) = self.frame(frame, headers, &[]);
+            EventData::H3FrameParsed(H3FrameParsed {
+                stream_id: stream_id.as_u64(),
+                length,
+                frame,
+                raw: None,
+            })
+        });
+    }
+
+    /// Log a HEADERS frame that was received on `stream_id`, once its header
+    /// block of `length` bytes is decoded into `headers`.
+    pub fn headers_parsed(
+        &self,
+        qlog: &mut NeqoQlog,
+        stream_id: StreamId,
+        length: usize,
+        headers: &[Header],
+    ) {
+        self.add(qlog, || {
+            EventData::H3FrameParsed(H3FrameParsed {
+                stream_id: stream_id.as_u64(),
+                length: Some(u64::try_from(length).unwrap()),
+                frame: Http3Frame::Headers {
+                    headers: self.headers(headers),
+                },
+                raw: None,
+            })
+        });
+    }
+
+    /// Log a frame of a type that is not known, which was skipped.
+    pub fn unknown_parsed(
+        &self,
+        qlog: &mut NeqoQlog,
+        stream_id: StreamId,
+        frame_type: u64,
+        length: u64,
+    ) {
+        self.add(qlog, || {
+            EventData::H3FrameParsed(H3FrameParsed {
+                stream_id: stream_id.as_u64(),
+                length: Some(length),
+                frame: Http3Frame::Unknown {
+                    frame_type_value: frame_type,
+                    raw: None,
+                },
+                raw: None,
+            })
+        });
+    }
+
+    fn add(&self, qlog: &mut NeqoQlog, f: impl FnOnce() -> EventData) {
+        if self.0.is_some() {
+            qlog.add_event_data(|| Some(f()));
+        }
+    }
+
+    fn headers(&self, headers: &[Header]) -> Vec<HttpHeader> {
+        let redacted = self.0.as_deref().unwrap_or_default();
+        headers
+            .iter()
+            .map(|h| HttpHeader {
+                name: h.name().to_string(),
+                value: if redacted.iter().any(|r| h.name().eq_ignore_ascii_case(r)) {
+                    REDACTED.to_string()
+                } else {
+                    h.value().to_string()
+                },
+            })
+            .collect()
+    }
+
+    /// The payload length and the qlog form of a frame.
+    fn frame(
+        &self,
+        frame: &HFrame,
+        headers: &[Header],
+        encoded: &[u8],
+    ) -> (Option<u64>, Http3Frame) {
+        let len = |l: usize| Some(u64::try_from(l).unwrap());
+        match frame {
+            HFrame::Data { len } => (Some(*len), Http3Frame::Data { raw: None }),
+            HFrame::Headers { header_block } => (
+                len(header_block.len()),
+                Http3Frame::Headers {
+                    headers: self.headers(headers),
+                },
+            ),
+            HFrame::CancelPush { push_id } => (None, Http3Frame::CancelPush { push_id: *push_id }),
+            HFrame::Settings { settings } => (
+                None,
+                Http3Frame::Settings {
+                    settings: settings
+                        .iter()
+                        .map(|s| Setting {
+                            name: setting_name(s.setting_type),
+                            value: s.value,
+                        })
+                        .collect(),
+                },
+            ),
+            HFrame::PushPromise {
+                push_id,
+                header_block,
+            } => (
+                len(header_block.len()),
+                Http3Frame::PushPromise {
+                    push_id: *push_id,
+                    headers: self.headers(headers),
+                },
+            ),
+            HFrame::Goaway { stream_id } => (
+                None,
+                Http3Frame::Goaway {
+                    id: stream_id.as_u64(),
+                },
+            ),
+            HFrame::MaxPushId { push_id } => (None, Http3Frame::MaxPushId { push_id: *push_id }),
+            HFrame::PriorityUpdateRequest {
+                element_id,
+                priority,
+            }
+            | HFrame::PriorityUpdatePush {
+                element_id,
+                priority,
+            } => (
+                None,
+                Http3Frame::PriorityUpdate {
+                    target_stream_type: if matches!(frame, HFrame::PriorityUpdateRequest { .. }) {
+                        H3PriorityTargetStreamType::Request
+                    } else {
+                        H3PriorityTargetStreamType::Push
+                    },
+                    prioritized_element_id: *element_id,
+                    priority_field_value: priority.to_string(),
+                },
+            ),
+            HFrame::Grease => {
+                // The type of a grease frame is chosen when it is encoded.
+                let mut dec = Decoder::from(encoded);
+                let frame_type_value = dec.decode_varint().unwrap_or_default();
+                let length = dec.decode_varint();
+                (
+                    length,
+                    Http3Frame::Unknown {
+                        frame_type_value,
+                        raw: None,
+                    },
+                )
+            }
+        }
+    }
+}
+
+fn setting_name(setting_type: HSettingType) -> String {
+    match setting_type {
+        HSettingType::MaxHeaderListSize => "max_field_section_size".to_string(),
+        HSettingType::MaxTableCapacity => "qpack_max_table_capacity".to_string(),
+        HSettingType::BlockedStreams => "qpack_blocked_streams".to_string(),
+        HSettingType::EnableWebTransport => "enable_webtransport".to_string(),
+        HSettingType::EnableH3Datagram => "h3_datagram".to_string(),
+        HSettingType::EnableConnectProtocol => "enable_connect_protocol".to_string(),
+        HSettingType::Unknown(id) => format!("{id:#x}"),
+    }
+}
@@ -11,11 +11,15 @@ use neqo_qpack::decoder::QPackDecoder;
 use neqo_transport::{Connection, StreamId};
 
 use crate::{
-    frames::{FrameReader, HFrame, StreamReaderConnectionWrapper, H3_FRAME_TYPE_HEADERS},
-    headers_checks::{headers_valid, is_interim},
+    frames::{
+        Capsule, FrameReader, HFrame, StreamReader, StreamReaderConnectionWrapper,
+        H3_FRAME_TYPE_HEADERS,
+    },
+    headers_checks::{headers_valid, is_interim, trailers_valid},
     priority::PriorityHandler,
     push_controller::PushController,
-    qlog, CloseType, Error, Http3StreamInfo, Http3StreamType, HttpRecvStream, HttpRecvStreamEvents,
+    qlog::{self, FrameQlog},
+    CloseType, Error, Http3StreamInfo, Http3StreamType, HttpRecvStream, HttpRecvStreamEvents,
     MessageType, Priority, ReceiveOutput, RecvStream, Res, Stream,
 };
 
@@ -39,9 +43,16 @@ pub(crate) struct RecvMessageInfo {
  *    ReadingData : we got a DATA frame, now we letting the app read payload.
  *                  From here we will go back to WaitingForData state to wait
  *                  for more data frames or to CLosed state
+ *    DecodingTrailers : we got a HEADERS frame carrying trailers. As with
+ *                       DecodingHeaders, the stream may be blocked here.
+ *    WaitingForFinAfterTrailers : trailers have been delivered, only the end
+ *                                 of the stream may follow.
  *    ClosePending : waiting for app to pick up data, after that we can delete
  * the TransactionClient.
  *    Closed
+ *    A CONNECT request (without :protocol) turns the stream into a tunnel once
+ *    the request, or a 2xx response to it, has been received.  After that only
+ *    DATA frames are allowed and trailers are treated as a connection error.
  *    ExtendedConnect: this request is for a WebTransport session. In this
  *                         state RecvMessage will not be treated as a HTTP
  *                         stream anymore. It is waiting to be transformed
@@ -53,6 +64,7 @@ enum RecvMessageState {
     DecodingHeaders { header_block: Vec<u8>, fin: bool },
     WaitingForData { frame_reader: FrameReader },
     ReadingData { remaining_data_len: usize },
+    DecodingTrailers { header_block: Vec<u8>, fin: bool },
     WaitingForFinAfterTrailers { frame_reader: FrameReader },
     ClosePending, // Close must first be read by application
     Closed,
@@ -76,6 +88,16 @@ pub(crate) struct RecvMessage {
     stream_id: StreamId,
     priority_handler: PriorityHandler,
     blocked_push_promise: VecDeque<PushInfo>,
+    /// This stream carries the response to a CONNECT request.
+    connect: bool,
+    /// The CONNECT request was accepted and the stream is a tunnel.
+    tunnel: bool,
+    /// This stream carries the response to a CONNECT-UDP request.
+    connect_udp: bool,
+    /// Set when the body is read as a sequence of capsules, instead of being
+    /// passed to the application as it is.
+    capsule_reader: Option<FrameReader>,
+    frame_qlog: FrameQlog,
 }
 
 impl ::std::fmt::Display for RecvMessage {
@@ -108,9 +130,28 @@ impl RecvMessage {
             stream_id: message_info.stream_id,
             priority_handler,
             blocked_push_promise: VecDeque::new(),
+            connect: false,
+            tunnel: false,
+            connect_udp: false,
+            capsule_reader: None,
+            frame_qlog: FrameQlog::default(),
         }
     }
 
+    /// Log the frames that are received with `frame_qlog`.
+    #[must_use]
+    pub fn frame_qlog(mut self, frame_qlog: FrameQlog) -> Self {
+        self.frame_qlog = frame_qlog;
+        self
+    }
+
+    /// Mark this as the response stream of a CONNECT request.
+    #[must_use]
+    pub fn connect_tunnel(mut self) -> Self {
+        self.connect = true;
+        self
+    }
+
     fn handle_headers_frame(&mut self, header_block: Vec<u8>, fin: bool) -> Res<()> {
         match self.state {
             RecvMessageState::WaitingForResponseHeaders {..} => {
@@ -120,8 +161,10 @@ impl RecvMessage {
                     self.state = RecvMessageState::DecodingHeaders { header_block, fin };
              }
             RecvMessageState::WaitingForData { ..} => {
-                // TODO implement trailers, for now just ignore them.
-                self.state = RecvMessageState::WaitingForFinAfterTrailers{frame_reader: FrameReader::new()};
+                if self.tunnel {
+                    return Err(Error::HttpFrameUnexpected);
+                }
+                self.state = RecvMessageState::DecodingTrailers { header_block, fin };
             }
             RecvMessageState::WaitingForFinAfterTrailers {..} => {
                 return Err(Error::HttpFrameUnexpected);
@@ -173,6 +216,23 @@ impl RecvMessage {
             && headers
                 .iter()
                 .any(|h| h.name() == ":protocol" && h.value() == "webtransport");
+        match self.message_type {
+            MessageType::Request => {
+                self.tunnel = headers
+                    .iter()
+                    .any(|h| h.name() == ":method" && h.value() == "CONNECT")
+                    && !headers.iter().any(|h| h.name() == ":protocol");
+            }
+            MessageType::Response => {
+                let success = headers
+                    .iter()
+                    .any(|h| h.name() == ":status" && h.value().starts_with('2'));
+                self.tunnel = self.connect && success;
+                if self.connect_udp && success && !fin {
+                    self.capsule_reader = Some(FrameReader::new());
+                }
+            }
+        }
         if is_web_transport {
             self.conn_events
                 .extended_connect_new_session(self.stream_id, headers);
@@ -200,11 +260,30 @@ impl RecvMessage {
         Ok(())
     }
 
+    fn add_trailers(&mut self, headers: Vec<Header>) -> Res<()> {
+        qtrace!([self], "Add trailers");
+        trailers_valid(&headers).map_err(|_| Error::HttpMessageError)?;
+        self.conn_events
+            .trailers_ready(self.get_stream_info(), headers);
+        self.state = RecvMessageState::WaitingForFinAfterTrailers {
+            frame_reader: FrameReader::new(),
+        };
+        Ok(())
+    }
+
     fn set_state_to_close_pending(&mut self, post_readable_event: bool) -> Res<()> {
         // Stream has received fin. Depending on headers state set header_ready
         // or data_readable event so that app can pick up the fin.
         qtrace!([self], "set_state_to_close_pending: state={:?}", self.state);
 
+        if self
+            .capsule_reader
+            .as_ref()
+            .is_some_and(FrameReader::decoding_in_progress)
+        {
+            // The body ended in the middle of a capsule.
+            return Err(Error::HttpMessageError);
+        }
         match self.state {
             RecvMessageState::WaitingForResponseHeaders { .. } => {
                 return Err(Error::HttpGeneralProtocolStream);
@@ -262,10 +341,10 @@ impl RecvMessage {
                 RecvMessageState::WaitingForResponseHeaders { frame_reader }
                 | RecvMessageState::WaitingForData { frame_reader }
                 | RecvMessageState::WaitingForFinAfterTrailers { frame_reader } => {
-                    match frame_reader.receive(&mut StreamReaderConnectionWrapper::new(
-                        conn,
-                        self.stream_id,
-                    ))? {
+                    match frame_reader.receive(
+                        &mut StreamReaderConnectionWrapper::new(conn, self.stream_id)
+                            .frame_qlog(&self.frame_qlog),
+                    )? {
                         (None, true) => {
                             break self.set_state_to_close_pending(post_readable_event);
                         }
@@ -278,6 +357,15 @@ impl RecvMessage {
                                 self.state,
                                 fin,
                             );
+                            if !matches!(frame, HFrame::Headers { .. }) {
+                                // HEADERS frames are logged once they are decoded.
+                                self.frame_qlog.parsed(
+                                    conn.qlog_mut(),
+                                    self.stream_id,
+                                    &frame,
+                                    &[],
+                                );
+                            }
                             match frame {
                                 HFrame::Headers { header_block } => {
                                     self.handle_headers_frame(header_block, fin)?;
@@ -293,7 +381,11 @@ impl RecvMessage {
                                 break Ok(());
                             }
                             if fin
-                                && !matches!(self.state, RecvMessageState::DecodingHeaders { .. })
+                                && !matches!(
+                                    self.state,
+                                    RecvMessageState::DecodingHeaders { .. }
+                                        | RecvMessageState::DecodingTrailers { .. }
+                                )
                             {
                                 break self.set_state_to_close_pending(post_readable_event);
                             }
@@ -317,11 +409,18 @@ impl RecvMessage {
                         break Ok(());
                     }
                     let done = *fin;
+                    let length = header_block.len();
                     let d_headers = self
                         .qpack_decoder
                         .borrow_mut()
                         .decode_header_block(header_block, self.stream_id)?;
                     if let Some(headers) = d_headers {
+                        self.frame_qlog.headers_parsed(
+                            conn.qlog_mut(),
+                            self.stream_id,
+                            length,
+                            &headers,
+                        );
                         self.add_headers(headers, done)?;
                         if matches!(
                             self.state,
@@ -334,8 +433,49 @@ impl RecvMessage {
                         break Ok(());
                     }
                 }
+                RecvMessageState::DecodingTrailers {
+                    ref header_block,
+                    fin,
+                } => {
+                    if self
+                        .qpack_decoder
+                        .borrow()
+                        .refers_dynamic_table(header_block)?
+                        && !self.blocked_push_promise.is_empty()
+                    {
+                        qinfo!(
+                            [self],
+                            "decoding trailers is blocked waiting for a push_promise header block."
+                        );
+                        break Ok(());
+                    }
+                    let done = *fin;
+                    let length = header_block.len();
+                    let d_headers = self
+                        .qpack_decoder
+                        .borrow_mut()
+                        .decode_header_block(header_block, self.stream_id)?;
+                    if let Some(headers) = d_headers {
+                        self.frame_qlog.headers_parsed(
+                            conn.qlog_mut(),
+                            self.stream_id,
+                            length,
+                            &headers,
+                        );
+                        self.add_trailers(headers)?;
+                        if done {
+                            break self.set_state_to_close_pending(post_readable_event);
+                        }
+                    } else {
+                        qinfo!([self], "decoding trailers is blocked.");
+                        break Ok(());
+                    }
+                }
                 RecvMessageState::ReadingData { .. } => {
                     if post_readable_event {
+                        if self.capsule_reader.is_some() {
+                            break self.read_capsules(conn);
+                        }
                         self.conn_events.data_readable(self.get_stream_info());
                     }
                     break Ok(());
@@ -352,6 +492,96 @@ impl RecvMessage {
         }
     }
 
+    /// Decode capsules from the body for as long as there is data.  A capsule may
+    /// span any number of DATA frames, `FrameReader` keeps the partial capsule
+    /// between calls.  The end of the stream is left for the application to read.
+    fn read_capsules(&mut self, conn: &mut Connection) -> Res<()> {
+        let Some(mut reader) = self.capsule_reader.take() else {
+            return Ok(());
+        };
+        let res = loop {
+            match reader.receive::<Capsule>(&mut CapsuleStreamReader { stream: self, conn }) {
+                Ok((Some(capsule), fin)) => {
+                    qtrace!([self], "Capsule type={} received.", capsule.capsule_type);
+                    self.conn_events.capsule_received(
+                        self.get_stream_info(),
+                        capsule.capsule_type,
+                        capsule.payload,
+                    );
+                    if fin {
+                        break Ok(true);
+                    }
+                }
+                Ok((None, fin)) => break Ok(fin),
+                Err(Error::HttpFrame) => break Err(Error::HttpMessageError),
+                Err(e) => break Err(e),
+            }
+        };
+        self.capsule_reader = Some(reader);
+        if res? {
+            self.conn_events.data_readable(self.get_stream_info());
+        }
+        Ok(())
+    }
+
+    /// Like `read_data`, but the stream is not closed when the fin is read, so
+    /// that the application can still pick it up.
+    fn read_body(&mut self, conn: &mut Connection, buf: &mut [u8]) -> Res<(usize, bool)> {
+        self.read_data_internal(conn, buf, false)
+    }
+
+    fn read_data_internal(
+        &mut self,
+        conn: &mut Connection,
+        buf: &mut [u8],
+        close: bool,
+    ) -> Res<(usize, bool)> {
+        let mut written = 0;
+        loop {
+            match self.state {
+                RecvMessageState::ReadingData {
+                    ref mut remaining_data_len,
+                } => {
+                    let to_read = min(*remaining_data_len, buf.len() - written);
+                    let (amount, fin) = conn
+                        .stream_recv(self.stream_id, &mut buf[written..written + to_read])
+                        .map_err(|e| Error::map_stream_recv_errors(&Error::from(e)))?;
+                    qlog::h3_data_moved_up(conn.qlog_mut(), self.stream_id, amount);
+
+                    debug_assert!(amount <= to_read);
+                    *remaining_data_len -= amount;
+                    written += amount;
+
+                    if fin {
+                        if *remaining_data_len > 0 {
+                            return Err(Error::HttpFrame);
+                        }
+                        if close {
+                            self.set_closed();
+                        } else {
+                            self.state = RecvMessageState::ClosePending;
+                        }
+                        break Ok((written, fin));
+                    } else if *remaining_data_len == 0 {
+                        self.state = RecvMessageState::WaitingForData {
+                            frame_reader: FrameReader::new(),
+                        };
+                        self.receive_internal(conn, false)?;
+                    } else {
+                        break Ok((written, false));
+                    }
+                }
+                RecvMessageState::ClosePending => {
+                    if close {
+                        self.set_closed();
+                    }
+                    break Ok((written, true));
+                }
+                _ => break Ok((written, false)),
+            }
+        }
+    }
+
     fn set_closed(&mut self) {
         if !self.blocked_push_promise.is_empty() {
             self.qpack_decoder
@@ -403,44 +633,11 @@ impl RecvStream for RecvMessage {
     }
 
     fn read_data(&mut self, conn: &mut Connection, buf: &mut [u8]) -> Res<(usize, bool)> {
-        let mut written = 0;
-        loop {
-            match self.state {
-                RecvMessageState::ReadingData {
-                    ref mut remaining_data_len,
-                } => {
-                    let to_read = min(*remaining_data_len, buf.len() - written);
-                    let (amount, fin) = conn
-                        .stream_recv(self.stream_id, &mut buf[written..written + to_read])
-                        .map_err(|e| Error::map_stream_recv_errors(&Error::from(e)))?;
-                    qlog::h3_data_moved_up(conn.qlog_mut(), self.stream_id, amount);
-
-                    debug_assert!(amount <= to_read);
-                    *remaining_data_len -= amount;
-                    written += amount;
-
-                    if fin {
-                        if *remaining_data_len > 0 {
-                            return Err(Error::HttpFrame);
-                        }
-                        self.set_closed();
-                        break Ok((written, fin));
-                    } else if *remaining_data_len == 0 {
-                        self.state = RecvMessageState::WaitingForData {
-                            frame_reader: FrameReader::new(),
-                        };
-                        self.receive_internal(conn, false)?;
-                    } else {
-                        break Ok((written, false));
-                    }
-                }
-                RecvMessageState::ClosePending => {
-                    self.set_closed();
-                    break Ok((written, true));
-                }
-                _ => break Ok((written, false)),
-            }
+        if self.capsule_reader.is_some() && !self.closing() {
+            // The body is consumed by the capsule reader.
+            return Ok((0, false));
         }
+        self.read_data_internal(conn, buf, true)
     }
 
     fn http_stream(&mut self) -> Option<&mut dyn HttpRecvStream> {
@@ -492,4 +689,37 @@ impl HttpRecvStream for RecvMessage {
     fn extended_connect_wait_for_response(&self) -> bool {
         matches!(self.state, RecvMessageState::ExtendedConnect)
     }
+
+    fn capsule_protocol(&mut self, conn: &mut Connection) -> Res<(ReceiveOutput, bool)> {
+        if !matches!(
+            self.state,
+            RecvMessageState::WaitingForData { .. } | RecvMessageState::ReadingData { .. }
+        ) || self.tunnel
+        {
+            return Err(Error::InvalidInput);
+        }
+        if self.capsule_reader.is_none() {
+            self.capsule_reader = Some(FrameReader::new());
+        }
+        self.receive(conn)
+    }
+
+    fn connect_udp(&mut self) {
+        self.connect_udp = true;
+    }
+
+    fn connect_udp_established(&self) -> bool {
+        self.connect_udp && self.capsule_reader.is_some()
+    }
+}
+
+struct CapsuleStreamReader<'a> {
+    stream: &'a mut RecvMessage,
+    conn: &'a mut Connection,
+}
+
+impl<'a> StreamReader for CapsuleStreamReader<'a> {
+    fn read_data(&mut self, buf: &mut [u8]) -> Res<(usize, bool)> {
+        self.stream.read_body(self.conn, buf)
+    }
 }
@@ -13,6 +13,8 @@ use neqo_transport::{Connection, StreamId};
 use crate::{
     frames::HFrame,
     headers_checks::{headers_valid, is_interim, trailers_valid},
+    qlog::FrameQlog,
+    stream_type_reader::HTTP3_UNI_STREAM_TYPE_PUSH,
     BufferedStream, CloseType, Error, Http3StreamInfo, Http3StreamType, HttpSendStream, Res,
     SendStream, SendStreamEvents, Stream,
 };
@@ -24,6 +26,26 @@ const MAX_DATA_HEADER_SIZE_3: usize = (1 << 14) - 1; // Maximal amount of data w
 const MAX_DATA_HEADER_SIZE_3_LIMIT: usize = MAX_DATA_HEADER_SIZE_3 + 5; // 16383 + 5 (size of the next buffer data frame header)
 const MAX_DATA_HEADER_SIZE_5: usize = (1 << 30) - 1; // Maximal amount of data with DATA frame header size 3
 const MAX_DATA_HEADER_SIZE_5_LIMIT: usize = MAX_DATA_HEADER_SIZE_5 + 9; // 1073741823 + 9 (size of the next buffer data frame header)
+/// Once a stream could not take all of the data that it was given, it is only reported as
+/// writable again when it can take this much, or the rest of that data if that is less.
+/// This keeps small increases of flow control credit from waking the application.
+const DATA_WRITABLE_LOW_WATERMARK: usize = 1024;
+
+/// How much of `len` bytes fits into a DATA frame when `available` bytes can be sent.
+fn data_frame_payload(available: usize, len: usize) -> usize {
+    if available <= MAX_DATA_HEADER_SIZE_2_LIMIT {
+        // 63 + 3
+        min(min(len, available - 2), MAX_DATA_HEADER_SIZE_2)
+    } else if available <= MAX_DATA_HEADER_SIZE_3_LIMIT {
+        // 16383 + 5
+        min(min(len, available - 3), MAX_DATA_HEADER_SIZE_3)
+    } else if available <= MAX_DATA_HEADER_SIZE_5 {
+        // 1073741823 + 9
+        min(min(len, available - 5), MAX_DATA_HEADER_SIZE_5_LIMIT)
+    } else {
+        min(len, available - 9)
+    }
+}
 
 /// A HTTP message, request and response, consists of headers, optional data and an optional
 /// trailer header block. This state machine does not reflect what was already sent to the
@@ -79,6 +101,15 @@ impl MessageState {
         }
     }
 
+    fn new_trailers(&mut self, headers: &[Header]) -> Res<()> {
+        if &Self::WaitingForData != self {
+            return Err(Error::InvalidInput);
+        }
+        trailers_valid(headers)?;
+        *self = Self::TrailersSet;
+        Ok(())
+    }
+
     fn new_data(&self) -> Res<()> {
         if &Self::WaitingForData == self {
             Ok(())
@@ -110,6 +141,7 @@ pub(crate) struct SendMessage {
     stream: BufferedStream,
     encoder: Rc<RefCell<QPackEncoder>>,
     conn_events: Box<dyn SendStreamEvents>,
+    frame_qlog: FrameQlog,
 }
 
 impl SendMessage {
@@ -128,9 +160,39 @@ impl SendMessage {
             stream: BufferedStream::new(stream_id),
             encoder,
             conn_events,
+            frame_qlog: FrameQlog::default(),
         }
     }
 
+    /// Log the frames that are sent with `frame_qlog`.
+    #[must_use]
+    pub fn frame_qlog(mut self, frame_qlog: FrameQlog) -> Self {
+        self.frame_qlog = frame_qlog;
+        self
+    }
+
+    /// Create a server push stream.  The stream type and push ID are buffered
+    /// so that they are sent ahead of the response.
+    pub fn new_push(
+        push_id: u64,
+        stream_id: StreamId,
+        encoder: Rc<RefCell<QPackEncoder>>,
+        conn_events: Box<dyn SendStreamEvents>,
+    ) -> Self {
+        let mut push = Self::new(
+            MessageType::Response,
+            Http3StreamType::Push,
+            stream_id,
+            encoder,
+            conn_events,
+        );
+        let mut enc = Encoder::default();
+        enc.encode_varint(HTTP3_UNI_STREAM_TYPE_PUSH);
+        enc.encode_varint(push_id);
+        push.stream.buffer(enc.as_ref());
+        push
+    }
+
     /// # Errors
     ///
     /// `ClosedCriticalStream` if the encoder stream is closed.
@@ -140,6 +202,7 @@ impl SendMessage {
         headers: &[Header],
         conn: &mut Connection,
         stream_id: StreamId,
+        frame_qlog: &FrameQlog,
     ) -> Vec<u8> {
         qdebug!("Encoding headers");
         let header_block = encoder.encode_header_block(conn, headers, stream_id);
@@ -148,6 +211,7 @@ impl SendMessage {
         };
         let mut d = Encoder::default();
         hframe.encode(&mut d);
+        frame_qlog.created(conn.qlog_mut(), stream_id, &hframe, d.as_ref(), headers);
         d.into()
     }
 
@@ -158,6 +222,18 @@ impl SendMessage {
     fn get_stream_info(&self) -> Http3StreamInfo {
         Http3StreamInfo::new(self.stream_id(), Http3StreamType::Http)
     }
+
+    /// Ask the transport for a writable event once a DATA frame with the `remaining`
+    /// bytes that did not fit, or with `DATA_WRITABLE_LOW_WATERMARK` bytes, can be sent.
+    fn set_writable_low_watermark(&self, conn: &mut Connection, remaining: usize) -> Res<()> {
+        let frame = remaining.saturating_add(1 + Encoder::varint_len(remaining as u64));
+        let watermark = min(frame, DATA_WRITABLE_LOW_WATERMARK).max(MIN_DATA_FRAME_SIZE);
+        conn.stream_set_writable_event_low_watermark(
+            self.stream_id(),
+            NonZeroUsize::new(watermark).unwrap(),
+        )?;
+        Ok(())
+    }
 }
 
 impl Stream for SendMessage {
@@ -179,27 +255,13 @@ impl SendStream for SendMessage {
             .stream_avail_send_space(self.stream_id())
             .map_err(|e| Error::map_stream_send_errors(&e.into()))?;
         if available < MIN_DATA_FRAME_SIZE {
-            // Setting this once, instead of every time the available send space
-            // is exhausted, would suffice. That said, function call should be
-            // cheap, thus not worth optimizing.
-            conn.stream_set_writable_event_low_watermark(
-                self.stream_id(),
-                NonZeroUsize::new(MIN_DATA_FRAME_SIZE).unwrap(),
-            )?;
+            self.set_writable_low_watermark(conn, buf.len())?;
             return Ok(0);
         }
-        let to_send = if available <= MAX_DATA_HEADER_SIZE_2_LIMIT {
-            // 63 + 3
-            min(min(buf.len(), available - 2), MAX_DATA_HEADER_SIZE_2)
-        } else if available <= MAX_DATA_HEADER_SIZE_3_LIMIT {
-            // 16383 + 5
-            min(min(buf.len(), available - 3), MAX_DATA_HEADER_SIZE_3)
-        } else if available <= MAX_DATA_HEADER_SIZE_5 {
-            // 1073741823 + 9
-            min(min(buf.len(), available - 5), MAX_DATA_HEADER_SIZE_5_LIMIT)
-        } else {
-            min(buf.len(), available - 9)
-        };
+        let to_send = data_frame_payload(available, buf.len());
+        if to_send < buf.len() {
+            self.set_writable_low_watermark(conn, buf.len() - to_send)?;
+        }
 
         qdebug!(
             [self],
@@ -218,6 +280,13 @@ impl SendStream for SendMessage {
             .send_atomic(conn, enc.as_ref())
             .map_err(|e| Error::map_stream_send_errors(&e))?;
         debug_assert!(sent_fh);
+        self.frame_qlog.created(
+            conn.qlog_mut(),
+            self.stream_id(),
+            &data_frame,
+            enc.as_ref(),
+            &[],
+        );
 
         let sent = self
             .stream
@@ -227,6 +296,19 @@ impl SendStream for SendMessage {
         Ok(to_send)
     }
 
+    fn send_data_avail(&self, conn: &Connection) -> Res<usize> {
+        if self.state.new_data().is_err() || self.stream.has_buffered_data() {
+            return Ok(0);
+        }
+        let available = conn
+            .stream_avail_send_space(self.stream_id())
+            .map_err(|e| Error::map_stream_send_errors(&e.into()))?;
+        if available < MIN_DATA_FRAME_SIZE {
+            return Ok(0);
+        }
+        Ok(data_frame_payload(available, usize::MAX))
+    }
+
     fn done(&self) -> bool {
         !self.stream.has_buffered_data() && self.state.done()
     }
@@ -299,11 +381,19 @@ impl SendStream for SendMessage {
     }
 
     fn send_data_atomic(&mut self, conn: &mut Connection, buf: &[u8]) -> Res<()> {
+        self.state.new_data()?;
         let data_frame = HFrame::Data {
             len: buf.len() as u64,
         };
         let mut enc = Encoder::default();
         data_frame.encode(&mut enc);
+        self.frame_qlog.created(
+            conn.qlog_mut(),
+            self.stream_id(),
+            &data_frame,
+            enc.as_ref(),
+            &[],
+        );
         self.stream.buffer(enc.as_ref());
         self.stream.buffer(buf);
         _ = self.stream.send_buffer(conn)?;
@@ -319,11 +409,59 @@ impl HttpSendStream for SendMessage {
             headers,
             conn,
             self.stream_id(),
+            &self.frame_qlog,
         );
         self.stream.buffer(&buf);
         Ok(())
     }
 
+    fn send_trailers(&mut self, headers: &[Header], conn: &mut Connection) -> Res<()> {
+        self.state.new_trailers(headers)?;
+        let buf = SendMessage::encode(
+            &mut self.encoder.borrow_mut(),
+            headers,
+            conn,
+            self.stream_id(),
+            &self.frame_qlog,
+        );
+        self.stream.buffer(&buf);
+        Ok(())
+    }
+
+    fn send_push_promise(
+        &mut self,
+        push_id: u64,
+        headers: &[Header],
+        conn: &mut Connection,
+    ) -> Res<()> {
+        if self.message_type != MessageType::Response
+            || self.stream_type != Http3StreamType::Http
+            || self.state.done()
+        {
+            return Err(Error::InvalidInput);
+        }
+        debug_assert!(headers_valid(headers, MessageType::Request).is_ok());
+        let header_block =
+            self.encoder
+                .borrow_mut()
+                .encode_header_block(conn, headers, self.stream_id());
+        let hframe = HFrame::PushPromise {
+            push_id,
+            header_block: header_block.to_vec(),
+        };
+        let mut d = Encoder::default();
+        hframe.encode(&mut d);
+        self.frame_qlog.created(
+            conn.qlog_mut(),
+            self.stream_id(),
+            &hframe,
+            d.as_ref(),
+            headers,
+        );
+        self.stream.buffer(d.as_ref());
+        Ok(())
+    }
+
     fn set_new_listener(&mut self, conn_events: Box<dyn SendStreamEvents>) {
         self.stream_type = Http3StreamType::ExtendedConnect;
         self.conn_events = conn_events;
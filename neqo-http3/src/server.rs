@@ -8,22 +8,24 @@
 
 use std::{
     cell::{RefCell, RefMut},
+    cmp::min,
     collections::HashMap,
     path::PathBuf,
     rc::Rc,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-use neqo_common::{qtrace, Datagram};
+use neqo_common::{qinfo, qtrace, Datagram, Header};
 use neqo_crypto::{AntiReplay, Cipher, PrivateKey, PublicKey, ZeroRttChecker};
 use neqo_transport::{
     server::{ActiveConnectionRef, Server, ValidateAddress},
-    ConnectionIdGenerator, Output,
+    ConnectionIdGenerator, Output, StreamId,
 };
 
 use crate::{
     connection::Http3State,
     connection_server::Http3ServerHandler,
+    features::connect_udp,
     server_connection_events::Http3ServerConnEvent,
     server_events::{
         Http3OrWebTransportStream, Http3ServerEvent, Http3ServerEvents, WebTransportRequest,
@@ -35,12 +37,18 @@ use crate::{
 type HandlerRef = Rc<RefCell<Http3ServerHandler>>;
 
 const MAX_EVENT_DATA_SIZE: usize = 1024;
+/// How long connections are given to finish their requests after a graceful shutdown.
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct Http3Server {
     server: Server,
     http3_parameters: Http3Parameters,
     http3_handlers: HashMap<ActiveConnectionRef, HandlerRef>,
     events: Http3ServerEvents,
+    drain_timeout: Duration,
+    /// When a graceful shutdown is in progress, the time at which remaining
+    /// connections are closed.
+    shutdown_deadline: Option<Instant>,
 }
 
 impl ::std::fmt::Display for Http3Server {
@@ -77,6 +85,8 @@ impl Http3Server {
             http3_parameters,
             http3_handlers: HashMap::new(),
             events: Http3ServerEvents::default(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            shutdown_deadline: None,
         })
     }
 
@@ -113,31 +123,84 @@ impl Http3Server {
         self.server.ech_config()
     }
 
+    /// Set how long connections are given to complete their requests after
+    /// `graceful_shutdown` before they are closed.
+    pub fn set_drain_timeout(&mut self, timeout: Duration) {
+        self.drain_timeout = timeout;
+    }
+
+    /// Stop accepting new requests.  Every connection is sent a `GOAWAY` frame, requests
+    /// that are already in progress are allowed to complete, and a
+    /// `Http3ServerEvent::ConnectionDrained` event is produced once a connection has
+    /// no requests left.  Connections that remain after the drain timeout are closed.
+    pub fn graceful_shutdown(&mut self, now: Instant) {
+        qinfo!([self], "Graceful shutdown.");
+        if self.shutdown_deadline.is_none() {
+            self.shutdown_deadline = Some(now + self.drain_timeout);
+        }
+        for handler in self.http3_handlers.values() {
+            handler.borrow_mut().graceful_shutdown();
+        }
+    }
+
     pub fn process(&mut self, dgram: Option<&Datagram>, now: Instant) -> Output {
         qtrace!([self], "Process.");
         let out = self.server.process(dgram, now);
         self.process_http3(now);
         // If we do not that a dgram already try again after process_http3.
-        match out {
+        let out = match out {
             Output::Datagram(d) => {
                 qtrace!([self], "Send packet: {:?}", d);
-                Output::Datagram(d)
+                return Output::Datagram(d);
             }
             _ => self.server.process(Option::<&Datagram>::None, now),
+        };
+        match (out, self.next_timer()) {
+            (Output::Callback(t), Some(s)) => {
+                Output::Callback(min(t, s.saturating_duration_since(now)))
+            }
+            (Output::None, Some(s)) => Output::Callback(s.saturating_duration_since(now)),
+            (out, _) => out,
         }
     }
 
+    /// The earliest time at which a graceful shutdown or a request timeout
+    /// needs attention.
+    fn next_timer(&self) -> Option<Instant> {
+        self.http3_handlers
+            .values()
+            .flat_map(|h| {
+                let h = h.borrow();
+                h.shutdown_timer().into_iter().chain(h.request_timer())
+            })
+            .chain(self.shutdown_deadline)
+            .min()
+    }
+
     /// Process HTTP3 layer.
     fn process_http3(&mut self, now: Instant) {
         qtrace!([self], "Process http3 internal.");
         let mut active_conns = self.server.active_connections();
 
+        if self.shutdown_deadline.is_some_and(|t| now >= t) {
+            self.shutdown_deadline = None;
+            for (conn, handler) in &self.http3_handlers {
+                let mut conn = conn.clone();
+                handler
+                    .borrow_mut()
+                    .shutdown_timeout(&mut conn.borrow_mut(), now);
+                if !active_conns.contains(&conn) {
+                    active_conns.push(conn);
+                }
+            }
+        }
+
         // We need to find connections that needs to be process on http3 level.
         let mut http3_active: Vec<ActiveConnectionRef> = self
             .http3_handlers
             .iter()
             .filter_map(|(conn, handler)| {
-                if handler.borrow_mut().should_be_processed() && !active_conns.contains(conn) {
+                if handler.borrow_mut().should_be_processed(now) && !active_conns.contains(conn) {
                     Some(conn)
                 } else {
                     None
@@ -160,11 +223,15 @@ impl Http3Server {
     fn process_events(&mut self, conn: &mut ActiveConnectionRef, now: Instant) {
         let mut remove = false;
         let http3_parameters = &self.http3_parameters;
+        let shutting_down = self.shutdown_deadline.is_some();
+        let discard_cancelled_data = http3_parameters.get_discard_cancelled_data();
         {
             let handler = self.http3_handlers.entry(conn.clone()).or_insert_with(|| {
-                Rc::new(RefCell::new(Http3ServerHandler::new(
-                    http3_parameters.clone(),
-                )))
+                let mut handler = Http3ServerHandler::new(http3_parameters.clone());
+                if shutting_down {
+                    handler.graceful_shutdown();
+                }
+                Rc::new(RefCell::new(handler))
             });
             handler
                 .borrow_mut()
@@ -176,11 +243,36 @@ impl Http3Server {
                         stream_info,
                         headers,
                         fin,
-                    } => self.events.headers(
-                        Http3OrWebTransportStream::new(conn.clone(), handler.clone(), stream_info),
-                        headers,
-                        fin,
-                    ),
+                    } => {
+                        let stream_id = stream_info.stream_id();
+                        handler_borrowed.request_headers_received(stream_id, &headers);
+                        if !handler_borrowed.reject_extended_connect(
+                            stream_id,
+                            &headers,
+                            &mut conn.borrow_mut(),
+                        ) {
+                            handler_borrowed.request_priority(
+                                stream_id,
+                                &headers,
+                                &mut conn.borrow_mut(),
+                            );
+                            let stream = Http3OrWebTransportStream::new(
+                                conn.clone(),
+                                handler.clone(),
+                                stream_info,
+                            );
+                            if let Some((target_host, target_port)) =
+                                connect_udp::request_target(&headers)
+                            {
+                                self.events
+                                    .connect_udp(stream, target_host, target_port, headers);
+                            } else if let Some(authority) = connect_authority(&headers) {
+                                self.events.connect_request(stream, authority, headers);
+                            } else {
+                                self.events.headers(stream, headers, fin);
+                            }
+                        }
+                    }
                     Http3ServerConnEvent::DataReadable { stream_info } => {
                         prepare_data(
                             stream_info,
@@ -191,6 +283,13 @@ impl Http3Server {
                             &mut self.events,
                         );
                     }
+                    Http3ServerConnEvent::Trailers {
+                        stream_info,
+                        headers,
+                    } => {
+                        self.events
+                            .trailers(conn.clone(), handler.clone(), stream_info, headers);
+                    }
                     Http3ServerConnEvent::DataWritable { stream_info } => self
                         .events
                         .data_writable(conn.clone(), handler.clone(), stream_info),
@@ -198,6 +297,22 @@ impl Http3Server {
                         self.events
                             .stream_reset(conn.clone(), handler.clone(), stream_info, error);
                     }
+                    Http3ServerConnEvent::RequestCancelled { stream_info, error } => {
+                        if discard_cancelled_data {
+                            self.events
+                                .remove_request_data(conn, stream_info.stream_id());
+                        }
+                        self.events.request_cancelled(
+                            conn.clone(),
+                            handler.clone(),
+                            stream_info,
+                            error,
+                        );
+                    }
+                    Http3ServerConnEvent::RequestTimedOut { stream_info } => {
+                        self.events
+                            .request_timed_out(conn.clone(), handler.clone(), stream_info);
+                    }
                     Http3ServerConnEvent::StreamStopSending { stream_info, error } => {
                         self.events.stream_stop_sending(
                             conn.clone(),
@@ -219,6 +334,9 @@ impl Http3Server {
                     } => {
                         self.events.priority_update(stream_id, priority);
                     }
+                    Http3ServerConnEvent::Drained => {
+                        self.events.connection_drained(conn.clone());
+                    }
                     Http3ServerConnEvent::ExtendedConnect { stream_id, headers } => {
                         self.events.webtransport_new_session(
                             WebTransportRequest::new(conn.clone(), handler.clone(), stream_id),
@@ -249,6 +367,35 @@ impl Http3Server {
                         WebTransportRequest::new(conn.clone(), handler.clone(), session_id),
                         datagram,
                     ),
+                    Http3ServerConnEvent::ExtendedConnectDatagramDroppedTooBig { session_id } => {
+                        self.events.webtransport_datagram_dropped_too_big(
+                            WebTransportRequest::new(conn.clone(), handler.clone(), session_id),
+                        );
+                    }
+                    Http3ServerConnEvent::ExtendedConnectDraining { session_id } => {
+                        self.events
+                            .webtransport_session_draining(WebTransportRequest::new(
+                                conn.clone(),
+                                handler.clone(),
+                                session_id,
+                            ));
+                    }
+                    Http3ServerConnEvent::ConnectUdpDatagram {
+                        stream_info,
+                        datagram,
+                    } => self.events.connect_udp_datagram(
+                        Http3OrWebTransportStream::new(conn.clone(), handler.clone(), stream_info),
+                        datagram,
+                    ),
+                    Http3ServerConnEvent::CapsuleReceived {
+                        stream_info,
+                        capsule_type,
+                        payload,
+                    } => self.events.capsule_received(
+                        Http3OrWebTransportStream::new(conn.clone(), handler.clone(), stream_info),
+                        capsule_type,
+                        payload,
+                    ),
                 }
             }
         }
@@ -257,6 +404,24 @@ impl Http3Server {
         }
     }
 
+    /// Get the handle of a stream that the server can still send on, e.g. to resume a
+    /// response when a `DataWritable` event arrives for a stream whose handle was not
+    /// kept.  `conn` is the connection of an earlier handle for the same request.
+    #[must_use]
+    pub fn stream(
+        &self,
+        conn: &ActiveConnectionRef,
+        stream_id: StreamId,
+    ) -> Option<Http3OrWebTransportStream> {
+        let handler = self.http3_handlers.get(conn)?;
+        let stream_type = handler.borrow().send_stream_type(stream_id)?;
+        Some(Http3OrWebTransportStream::new(
+            conn.clone(),
+            handler.clone(),
+            Http3StreamInfo::new(stream_id, stream_type),
+        ))
+    }
+
     /// Get all current events. Best used just in debug/testing code, use
     /// `next_event` instead.
     pub fn events(&mut self) -> impl Iterator<Item = Http3ServerEvent> {
@@ -276,6 +441,20 @@ impl Http3Server {
         self.events.next_event()
     }
 }
+/// The authority of a CONNECT request that opens a tunnel, i.e. one without `:protocol`.
+fn connect_authority(headers: &[Header]) -> Option<String> {
+    let is_connect = headers
+        .iter()
+        .any(|h| h.name() == ":method" && h.value() == "CONNECT");
+    if !is_connect || headers.iter().any(|h| h.name() == ":protocol") {
+        return None;
+    }
+    headers
+        .iter()
+        .find(|h| h.name() == ":authority")
+        .map(|h| h.value().to_string())
+}
+
 fn prepare_data(
     stream_info: Http3StreamInfo,
     handler_borrowed: &mut RefMut<Http3ServerHandler>,
@@ -293,12 +472,29 @@ fn prepare_data(
             &mut data,
         );
         if let Ok((amount, fin)) = res {
-            if amount > 0 || fin {
+            // Trailers that were read together with the end of the request are reported
+            // before the final `Data` event.
+            let trailers = if fin {
+                handler_borrowed.take_trailers(stream_info)
+            } else {
+                None
+            };
+            if amount > 0 || (fin && trailers.is_none()) {
                 if amount < MAX_EVENT_DATA_SIZE {
                     data.resize(amount, 0);
                 }
 
-                events.data(conn.clone(), handler.clone(), stream_info, data, fin);
+                events.data(
+                    conn.clone(),
+                    handler.clone(),
+                    stream_info,
+                    data,
+                    fin && trailers.is_none(),
+                );
+            }
+            if let Some(headers) = trailers {
+                events.trailers(conn.clone(), handler.clone(), stream_info, headers);
+                events.data(conn.clone(), handler.clone(), stream_info, Vec::new(), true);
             }
             if amount < MAX_EVENT_DATA_SIZE || fin {
                 break;
@@ -323,15 +519,18 @@ mod tests {
     use neqo_crypto::{AuthenticationStatus, ZeroRttCheckResult, ZeroRttChecker};
     use neqo_qpack::{encoder::QPackEncoder, QpackSettings};
     use neqo_transport::{
-        CloseReason, Connection, ConnectionEvent, State, StreamId, StreamType, ZeroRttState,
+        AppError, CloseReason, Connection, ConnectionEvent, State, StreamId, StreamType,
+        ZeroRttState,
     };
     use test_fixture::{
         anti_replay, default_client, fixture_init, now, CountingConnectionIdGenerator,
         DEFAULT_ALPN, DEFAULT_KEYS,
     };
 
-    use super::{Http3Server, Http3ServerEvent, Http3State, Rc, RefCell};
-    use crate::{Error, HFrame, Header, Http3Parameters, Priority};
+    use super::{
+        Duration, Http3Server, Http3ServerEvent, Http3State, Instant, Output, Rc, RefCell,
+    };
+    use crate::{Error, HFrame, Header, Http3Parameters, Priority, RequestTimeouts};
 
     const DEFAULT_SETTINGS: QpackSettings = QpackSettings {
         max_table_size_encoder: 100,
@@ -541,7 +740,15 @@ mod tests {
 
     // Connect transport, send and receive settings.
     fn connect_to(server: &mut Http3Server) -> PeerConnection {
-        let mut neqo_trans_conn = connect_and_receive_settings_with_server(server);
+        let neqo_trans_conn = connect_and_receive_settings_with_server(server);
+        open_peer_streams(server, neqo_trans_conn)
+    }
+
+    // Send the settings and QPACK streams of a client that is connected to `server`.
+    fn open_peer_streams(
+        server: &mut Http3Server,
+        mut neqo_trans_conn: Connection,
+    ) -> PeerConnection {
         let control_stream = neqo_trans_conn.stream_create(StreamType::UniDi).unwrap();
         let mut sent = neqo_trans_conn.stream_send(
             control_stream,
@@ -927,10 +1134,18 @@ mod tests {
                     data_received += 1;
                 }
                 Http3ServerEvent::DataWritable { .. }
+                | Http3ServerEvent::Trailers { .. }
                 | Http3ServerEvent::StreamReset { .. }
+                | Http3ServerEvent::RequestCancelled { .. }
+                | Http3ServerEvent::RequestTimedOut { .. }
                 | Http3ServerEvent::StreamStopSending { .. }
                 | Http3ServerEvent::StateChange { .. }
                 | Http3ServerEvent::PriorityUpdate { .. }
+                | Http3ServerEvent::ConnectionDrained { .. }
+                | Http3ServerEvent::ConnectRequest { .. }
+                | Http3ServerEvent::ConnectUdp { .. }
+                | Http3ServerEvent::ConnectUdpDatagram { .. }
+                | Http3ServerEvent::CapsuleReceived { .. }
                 | Http3ServerEvent::WebTransport(_) => {}
             }
         }
@@ -978,10 +1193,18 @@ mod tests {
                     panic!("We should not have a Data event");
                 }
                 Http3ServerEvent::DataWritable { .. }
+                | Http3ServerEvent::Trailers { .. }
                 | Http3ServerEvent::StreamReset { .. }
+                | Http3ServerEvent::RequestCancelled { .. }
+                | Http3ServerEvent::RequestTimedOut { .. }
                 | Http3ServerEvent::StreamStopSending { .. }
                 | Http3ServerEvent::StateChange { .. }
                 | Http3ServerEvent::PriorityUpdate { .. }
+                | Http3ServerEvent::ConnectionDrained { .. }
+                | Http3ServerEvent::ConnectRequest { .. }
+                | Http3ServerEvent::ConnectUdp { .. }
+                | Http3ServerEvent::ConnectUdpDatagram { .. }
+                | Http3ServerEvent::CapsuleReceived { .. }
                 | Http3ServerEvent::WebTransport(_) => {}
             }
         }
@@ -1005,16 +1228,282 @@ mod tests {
                     panic!("We should not have a Data event");
                 }
                 Http3ServerEvent::DataWritable { .. }
+                | Http3ServerEvent::Trailers { .. }
                 | Http3ServerEvent::StreamReset { .. }
+                | Http3ServerEvent::RequestCancelled { .. }
+                | Http3ServerEvent::RequestTimedOut { .. }
                 | Http3ServerEvent::StreamStopSending { .. }
                 | Http3ServerEvent::StateChange { .. }
                 | Http3ServerEvent::PriorityUpdate { .. }
+                | Http3ServerEvent::ConnectionDrained { .. }
+                | Http3ServerEvent::ConnectRequest { .. }
+                | Http3ServerEvent::ConnectUdp { .. }
+                | Http3ServerEvent::ConnectUdpDatagram { .. }
+                | Http3ServerEvent::CapsuleReceived { .. }
                 | Http3ServerEvent::WebTransport(_) => {}
             }
         }
         assert_eq!(headers_frames, 1);
     }
 
+    // Server: the client cancels a request after sending its headers.
+    #[test]
+    fn test_server_request_cancelled() {
+        let (mut hconn, mut peer_conn) = connect();
+
+        let stream_id = peer_conn.stream_create(StreamType::BiDi).unwrap();
+        peer_conn
+            .stream_send(stream_id, &REQUEST_WITH_BODY[..20])
+            .unwrap();
+        let out = peer_conn.process(None, now());
+        hconn.process(out.as_dgram_ref(), now());
+        assert!(hconn
+            .events()
+            .any(|e| matches!(e, Http3ServerEvent::Headers { .. })));
+
+        peer_conn
+            .stream_reset_send(stream_id, Error::HttpRequestCancelled.code())
+            .unwrap();
+        let out = peer_conn.process(None, now());
+        hconn.process(out.as_dgram_ref(), now());
+
+        let events = hconn.events().collect::<Vec<_>>();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            &events[0],
+            Http3ServerEvent::RequestCancelled { stream, error }
+                if stream.stream_id() == stream_id && *error == Error::HttpRequestCancelled.code()
+        ));
+    }
+
+    /// Send the request headers and the first part of the body, then reset the
+    /// request stream with `error`.  Returns the stream and the server events
+    /// from before the reset.
+    fn reset_mid_upload(
+        hconn: &mut Http3Server,
+        peer_conn: &mut PeerConnection,
+        error: AppError,
+        stop_sending: bool,
+    ) -> (StreamId, Vec<Http3ServerEvent>) {
+        let stream_id = peer_conn.stream_create(StreamType::BiDi).unwrap();
+        peer_conn
+            .stream_send(stream_id, &REQUEST_WITH_BODY[..23])
+            .unwrap();
+        let out = peer_conn.process(None, now());
+        hconn.process(out.as_dgram_ref(), now());
+
+        peer_conn.stream_reset_send(stream_id, error).unwrap();
+        if stop_sending {
+            peer_conn.stream_stop_sending(stream_id, error).unwrap();
+        }
+        let out = peer_conn.process(None, now());
+        hconn.process(out.as_dgram_ref(), now());
+        (stream_id, hconn.events().collect())
+    }
+
+    #[test]
+    fn test_server_request_reset_mid_upload() {
+        let (mut hconn, mut peer_conn) = connect();
+        let error = Error::HttpRequestIncomplete.code();
+        let (stream_id, events) = reset_mid_upload(&mut hconn, &mut peer_conn, error, false);
+
+        // The body that arrived before the reset is still delivered.
+        assert_eq!(events.len(), 3);
+        let Http3ServerEvent::Headers { stream, .. } = &events[0] else {
+            panic!("expected request headers");
+        };
+        assert!(matches!(
+            &events[1],
+            Http3ServerEvent::Data { data, fin: false, .. } if data == &REQUEST_BODY[..3]
+        ));
+        assert!(matches!(
+            &events[2],
+            Http3ServerEvent::RequestCancelled { stream, error: e }
+                if stream.stream_id() == stream_id && *e == error
+        ));
+
+        // Only the client's half of the stream is closed, so a response can be sent.
+        let mut stream = stream.clone();
+        stream
+            .send_headers(&[Header::new(":status", "200")])
+            .unwrap();
+        assert_eq!(stream.send_data(RESPONSE_BODY), Ok(RESPONSE_BODY.len()));
+        stream.stream_close_send().unwrap();
+    }
+
+    #[test]
+    fn test_server_request_reset_and_stop_sending() {
+        let (mut hconn, mut peer_conn) = connect();
+        let error = Error::HttpRequestCancelled.code();
+        let (stream_id, events) = reset_mid_upload(&mut hconn, &mut peer_conn, error, true);
+
+        let mut stream = match &events[0] {
+            Http3ServerEvent::Headers { stream, .. } => stream.clone(),
+            _ => panic!("expected request headers"),
+        };
+        let cancelled = events
+            .iter()
+            .filter(
+                |e| matches!(e, Http3ServerEvent::RequestCancelled { error: e, .. } if *e == error),
+            )
+            .count();
+        let stopped = events
+            .iter()
+            .filter(|e| matches!(e, Http3ServerEvent::StreamStopSending { error: e, .. } if *e == error))
+            .count();
+        assert_eq!((cancelled, stopped), (1, 1));
+
+        // The response can't be sent either.
+        assert_eq!(stream.stream_id(), stream_id);
+        assert_eq!(
+            stream.send_headers(&[Header::new(":status", "200")]),
+            Err(Error::StreamAborted(error))
+        );
+        assert_eq!(
+            stream.send_data(RESPONSE_BODY),
+            Err(Error::StreamAborted(error))
+        );
+        assert_eq!(stream.stream_close_send(), Err(Error::StreamAborted(error)));
+    }
+
+    #[test]
+    fn test_server_request_reset_discard_data() {
+        let mut hconn = create_server(http3params(DEFAULT_SETTINGS).discard_cancelled_data(true));
+        let mut peer_conn = connect_to(&mut hconn);
+        let error = Error::HttpRequestCancelled.code();
+        let (_, events) = reset_mid_upload(&mut hconn, &mut peer_conn, error, false);
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], Http3ServerEvent::Headers { .. }));
+        assert!(matches!(
+            &events[1],
+            Http3ServerEvent::RequestCancelled { error: e, .. } if *e == error
+        ));
+    }
+
+    const REQUEST_TIMEOUTS: RequestTimeouts = RequestTimeouts {
+        headers: Duration::from_secs(1),
+        body_idle: Duration::from_secs(1),
+        total: Some(Duration::from_secs(10)),
+    };
+
+    fn connect_with_request_timeouts() -> (Http3Server, PeerConnection) {
+        let mut hconn =
+            create_server(http3params(DEFAULT_SETTINGS).request_timeout(REQUEST_TIMEOUTS));
+        let peer_conn = connect_to(&mut hconn);
+        (hconn, peer_conn)
+    }
+
+    /// Send what the peer has to the server at `now`, and the server's answer back.
+    fn exchange_at(hconn: &mut Http3Server, peer_conn: &mut PeerConnection, now: Instant) {
+        let out = peer_conn.process(None, now);
+        let out = hconn.process(out.as_dgram_ref(), now);
+        peer_conn.process(out.as_dgram_ref(), now);
+    }
+
+    fn timed_out(hconn: &mut Http3Server) -> Option<StreamId> {
+        hconn.events().find_map(|e| match e {
+            Http3ServerEvent::RequestTimedOut { stream } => Some(stream.stream_id()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn request_timeout_incomplete_headers() {
+        let (mut hconn, mut peer_conn) = connect_with_request_timeouts();
+        let stream_id = peer_conn.stream_create(StreamType::BiDi).unwrap();
+        peer_conn
+            .stream_send(stream_id, &REQUEST_WITH_BODY[..10])
+            .unwrap();
+        exchange_at(&mut hconn, &mut peer_conn, now());
+
+        // The server asks to be called back by the time the headers are due.
+        let mut out = hconn.process(None, now());
+        while let Output::Datagram(d) = out {
+            peer_conn.process_input(&d, now());
+            out = hconn.process(None, now());
+        }
+        assert!(matches!(out, Output::Callback(t) if t <= REQUEST_TIMEOUTS.headers));
+
+        let before = now() + REQUEST_TIMEOUTS.headers - Duration::from_millis(1);
+        exchange_at(&mut hconn, &mut peer_conn, before);
+        assert_eq!(timed_out(&mut hconn), None);
+
+        exchange_at(&mut hconn, &mut peer_conn, now() + REQUEST_TIMEOUTS.headers);
+        assert_eq!(timed_out(&mut hconn), Some(stream_id));
+        let error = Error::HttpRequestCancelled.code();
+        assert!(peer_conn.events().any(|e| matches!(
+            e,
+            ConnectionEvent::RecvStreamReset { stream_id: id, app_error }
+                if id == stream_id && app_error == error
+        )));
+    }
+
+    #[test]
+    fn request_timeout_body_idle() {
+        let (mut hconn, mut peer_conn) = connect_with_request_timeouts();
+        let stream_id = peer_conn.stream_create(StreamType::BiDi).unwrap();
+        peer_conn
+            .stream_send(stream_id, &REQUEST_WITH_BODY[..21])
+            .unwrap();
+        exchange_at(&mut hconn, &mut peer_conn, now());
+        assert!(hconn
+            .events()
+            .any(|e| matches!(e, Http3ServerEvent::Headers { .. })));
+
+        // The body stalls after its first byte, so the request is answered with a 408.
+        exchange_at(
+            &mut hconn,
+            &mut peer_conn,
+            now() + REQUEST_TIMEOUTS.body_idle,
+        );
+        assert_eq!(timed_out(&mut hconn), Some(stream_id));
+        let error = Error::HttpRequestCancelled.code();
+        assert!(peer_conn.events().any(|e| matches!(
+            e,
+            ConnectionEvent::SendStreamStopSending { stream_id: id, app_error }
+                if id == stream_id && app_error == error
+        )));
+        let mut buf = [0; 100];
+        let (amount, fin) = peer_conn.stream_recv(stream_id, &mut buf).unwrap();
+        assert!(amount > 0);
+        assert!(fin);
+    }
+
+    #[test]
+    fn request_timeout_active_transfer() {
+        let (mut hconn, mut peer_conn) = connect_with_request_timeouts();
+        let stream_id = peer_conn.stream_create(StreamType::BiDi).unwrap();
+        peer_conn
+            .stream_send(stream_id, &REQUEST_WITH_BODY[..18])
+            .unwrap();
+        // A DATA frame whose payload trickles in.
+        peer_conn.stream_send(stream_id, &[0x0, 0x0a]).unwrap();
+        exchange_at(&mut hconn, &mut peer_conn, now());
+
+        // Well past the headers timeout, but never idle for `body_idle`.
+        let mut body = Vec::new();
+        let mut t = now();
+        for _ in 0..10 {
+            t += REQUEST_TIMEOUTS.body_idle / 2;
+            peer_conn.stream_send(stream_id, b"a").unwrap();
+            exchange_at(&mut hconn, &mut peer_conn, t);
+            for e in hconn.events() {
+                match e {
+                    Http3ServerEvent::Data { data, .. } => body.extend_from_slice(&data),
+                    Http3ServerEvent::RequestTimedOut { .. } => panic!("the request timed out"),
+                    _ => {}
+                }
+            }
+        }
+        peer_conn.stream_close_send(stream_id).unwrap();
+        exchange_at(&mut hconn, &mut peer_conn, t);
+        assert!(hconn
+            .events()
+            .any(|e| matches!(e, Http3ServerEvent::Data { fin: true, .. })));
+        assert_eq!(body, [b'a'; 10]);
+    }
+
     #[test]
     fn test_server_request_with_body_server_reset() {
         let (mut hconn, mut peer_conn) = connect();
@@ -1049,10 +1538,18 @@ mod tests {
                     panic!("We should not have a Data event");
                 }
                 Http3ServerEvent::DataWritable { .. }
+                | Http3ServerEvent::Trailers { .. }
                 | Http3ServerEvent::StreamReset { .. }
+                | Http3ServerEvent::RequestCancelled { .. }
+                | Http3ServerEvent::RequestTimedOut { .. }
                 | Http3ServerEvent::StreamStopSending { .. }
                 | Http3ServerEvent::StateChange { .. }
                 | Http3ServerEvent::PriorityUpdate { .. }
+                | Http3ServerEvent::ConnectionDrained { .. }
+                | Http3ServerEvent::ConnectRequest { .. }
+                | Http3ServerEvent::ConnectUdp { .. }
+                | Http3ServerEvent::ConnectUdpDatagram { .. }
+                | Http3ServerEvent::CapsuleReceived { .. }
                 | Http3ServerEvent::WebTransport(_) => {}
             }
         }
@@ -1082,6 +1579,74 @@ mod tests {
         assert_eq!(stop_sending, 1);
     }
 
+    // Server: a request with headers over the limit is reset with H3_EXCESSIVE_LOAD,
+    // while the connection stays open.
+    #[test]
+    fn field_section_too_large() {
+        let mut hconn = create_server(http3params(DEFAULT_SETTINGS).max_field_section_size(1000));
+        let mut client = default_client();
+        connect_transport(&mut hconn, &mut client, false);
+        let mut peer_conn = open_peer_streams(&mut hconn, client);
+
+        // The limit is advertised as SETTINGS_MAX_FIELD_SECTION_SIZE.
+        let mut buf = [0; 100];
+        let (amount, _) = peer_conn
+            .stream_recv(SERVER_SIDE_CONTROL_STREAM_ID, &mut buf)
+            .unwrap();
+        assert!(buf[..amount].windows(3).any(|w| w == [0x6, 0x43, 0xe8]));
+
+        let request_stream_id = peer_conn.stream_create(StreamType::BiDi).unwrap();
+        let headers = [
+            Header::new(":method", "GET"),
+            Header::new(":scheme", "https"),
+            Header::new(":authority", "something.com"),
+            Header::new(":path", "/"),
+            Header::new("big", "x".repeat(1000)),
+        ];
+        let mut encoder = QPackEncoder::new(
+            &QpackSettings {
+                max_table_size_encoder: 0,
+                max_table_size_decoder: 0,
+                max_blocked_streams: 0,
+            },
+            true,
+        );
+        let header_block = encoder.encode_header_block(&mut peer_conn, &headers, request_stream_id);
+        let mut enc = Encoder::default();
+        HFrame::Headers {
+            header_block: header_block.to_vec(),
+        }
+        .encode(&mut enc);
+        peer_conn
+            .stream_send(request_stream_id, enc.as_ref())
+            .unwrap();
+        peer_conn.stream_close_send(request_stream_id).unwrap();
+
+        let out = peer_conn.process(None, now());
+        let out = hconn.process(out.as_dgram_ref(), now());
+        let headers_or_closing = |e| {
+            matches!(
+                e,
+                Http3ServerEvent::Headers { .. }
+                    | Http3ServerEvent::StateChange {
+                        state: Http3State::Closing(..),
+                        ..
+                    }
+            )
+        };
+        assert!(!hconn.events().any(headers_or_closing));
+        peer_conn.process_input(out.as_dgram_ref().unwrap(), now());
+        let stop_sending = |e| {
+            matches!(
+                e,
+                ConnectionEvent::SendStreamStopSending { stream_id, app_error }
+                    if stream_id == request_stream_id
+                        && app_error == Error::HttpExcessiveLoadStream.code()
+            )
+        };
+        assert!(peer_conn.events().any(stop_sending));
+    }
+
     // Server: Test that the connection will be closed if the local control stream
     // has been reset.
     #[test]
@@ -1236,6 +1801,15 @@ mod tests {
         );
     }
 
+    /// A limit on the size of field sections that wasn't there before prevents 0-RTT.
+    #[test]
+    fn zero_rtt_smaller_field_section_size() {
+        zero_rtt_with_settings(
+            http3params(DEFAULT_SETTINGS).max_field_section_size(10_000),
+            ZeroRttState::Rejected,
+        );
+    }
+
     /// The size of the encoder table is local and therefore doesn't prevent 0-RTT.
     #[test]
     fn zero_rtt_smaller_encoder_table() {
@@ -1278,10 +1852,18 @@ mod tests {
                     assert!(requests.contains_key(&stream.stream_id()));
                 }
                 Http3ServerEvent::DataWritable { .. }
+                | Http3ServerEvent::Trailers { .. }
                 | Http3ServerEvent::StreamReset { .. }
+                | Http3ServerEvent::RequestCancelled { .. }
+                | Http3ServerEvent::RequestTimedOut { .. }
                 | Http3ServerEvent::StreamStopSending { .. }
                 | Http3ServerEvent::StateChange { .. }
                 | Http3ServerEvent::PriorityUpdate { .. }
+                | Http3ServerEvent::ConnectionDrained { .. }
+                | Http3ServerEvent::ConnectRequest { .. }
+                | Http3ServerEvent::ConnectUdp { .. }
+                | Http3ServerEvent::ConnectUdpDatagram { .. }
+                | Http3ServerEvent::CapsuleReceived { .. }
                 | Http3ServerEvent::WebTransport(_) => {}
             }
         }
@@ -12,7 +12,8 @@ use neqo_transport::{AppError, StreamId};
 use crate::{
     connection::Http3State,
     features::extended_connect::{ExtendedConnectEvents, ExtendedConnectType, SessionCloseReason},
-    CloseType, Http3StreamInfo, HttpRecvStreamEvents, Priority, RecvStreamEvents, SendStreamEvents,
+    CloseType, Http3StreamInfo, Http3StreamType, HttpRecvStreamEvents, Priority, RecvStreamEvents,
+    SendStreamEvents,
 };
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -31,6 +32,11 @@ pub(crate) enum Http3ServerConnEvent {
     DataReadable {
         stream_info: Http3StreamInfo,
     },
+    /// Request trailers are ready.
+    Trailers {
+        stream_info: Http3StreamInfo,
+        headers: Vec<Header>,
+    },
     DataWritable {
         stream_info: Http3StreamInfo,
     },
@@ -38,12 +44,23 @@ pub(crate) enum Http3ServerConnEvent {
         stream_info: Http3StreamInfo,
         error: AppError,
     },
+    /// The client reset a request stream.
+    RequestCancelled {
+        stream_info: Http3StreamInfo,
+        error: AppError,
+    },
     StreamStopSending {
         stream_info: Http3StreamInfo,
         error: AppError,
     },
+    /// The server gave up on a request after one of its `RequestTimeouts`.
+    RequestTimedOut {
+        stream_info: Http3StreamInfo,
+    },
     /// Connection state change.
     StateChange(Http3State),
+    /// All requests are complete after a graceful shutdown.
+    Drained,
     ExtendedConnect {
         stream_id: StreamId,
         headers: Vec<Header>,
@@ -59,6 +76,23 @@ pub(crate) enum Http3ServerConnEvent {
         session_id: StreamId,
         datagram: Vec<u8>,
     },
+    ExtendedConnectDatagramDroppedTooBig {
+        session_id: StreamId,
+    },
+    ExtendedConnectDraining {
+        session_id: StreamId,
+    },
+    /// A UDP payload for a CONNECT-UDP request.
+    ConnectUdpDatagram {
+        stream_info: Http3StreamInfo,
+        datagram: Vec<u8>,
+    },
+    /// A capsule arrived on a request stream that uses the capsule protocol.
+    CapsuleReceived {
+        stream_info: Http3StreamInfo,
+        capsule_type: u64,
+        payload: Vec<u8>,
+    },
 }
 
 #[derive(Debug, Default, Clone)]
@@ -88,12 +122,18 @@ impl RecvStreamEvents for Http3ServerConnEvents {
     }
 
     fn recv_closed(&self, stream_info: Http3StreamInfo, close_type: CloseType) {
-        if close_type != CloseType::Done {
-            self.remove_events_for_stream_id(stream_info);
-            self.insert(Http3ServerConnEvent::StreamReset {
-                stream_info,
-                error: close_type.error().unwrap(),
-            });
+        match close_type {
+            CloseType::Done => (),
+            CloseType::ResetRemote(error) if stream_info.stream_type() == Http3StreamType::Http => {
+                self.remove_events_for_stream_id(stream_info);
+                self.insert(Http3ServerConnEvent::RequestCancelled { stream_info, error });
+            }
+            CloseType::ResetRemote(error)
+            | CloseType::ResetApp(error)
+            | CloseType::LocalError(error) => {
+                self.remove_events_for_stream_id(stream_info);
+                self.insert(Http3ServerConnEvent::StreamReset { stream_info, error });
+            }
         }
     }
 }
@@ -114,9 +154,25 @@ impl HttpRecvStreamEvents for Http3ServerConnEvents {
         });
     }
 
+    /// Add a new `Trailers` event.
+    fn trailers_ready(&self, stream_info: Http3StreamInfo, headers: Vec<Header>) {
+        self.insert(Http3ServerConnEvent::Trailers {
+            stream_info,
+            headers,
+        });
+    }
+
     fn extended_connect_new_session(&self, stream_id: StreamId, headers: Vec<Header>) {
         self.insert(Http3ServerConnEvent::ExtendedConnect { stream_id, headers });
     }
+
+    fn capsule_received(&self, stream_info: Http3StreamInfo, capsule_type: u64, payload: Vec<u8>) {
+        self.insert(Http3ServerConnEvent::CapsuleReceived {
+            stream_info,
+            capsule_type,
+            payload,
+        });
+    }
 }
 
 impl ExtendedConnectEvents for Http3ServerConnEvents {
@@ -154,6 +210,14 @@ impl ExtendedConnectEvents for Http3ServerConnEvents {
             datagram,
         });
     }
+
+    fn datagram_dropped_too_big(&self, session_id: StreamId) {
+        self.insert(Http3ServerConnEvent::ExtendedConnectDatagramDroppedTooBig { session_id });
+    }
+
+    fn session_draining(&self, session_id: StreamId) {
+        self.insert(Http3ServerConnEvent::ExtendedConnectDraining { session_id });
+    }
 }
 
 impl Http3ServerConnEvents {
@@ -176,10 +240,42 @@ impl Http3ServerConnEvents {
         self.events.borrow_mut().pop_front()
     }
 
+    pub fn take_trailers(&self, stream_info: Http3StreamInfo) -> Option<Vec<Header>> {
+        let mut events = self.events.borrow_mut();
+        let i = events.iter().position(
+            |e| matches!(e, Http3ServerConnEvent::Trailers { stream_info: x, .. } if *x == stream_info),
+        )?;
+        match events.remove(i) {
+            Some(Http3ServerConnEvent::Trailers { headers, .. }) => Some(headers),
+            _ => None,
+        }
+    }
+
     pub fn connection_state_change(&self, state: Http3State) {
         self.insert(Http3ServerConnEvent::StateChange(state));
     }
 
+    pub fn connect_udp_datagram(&self, stream_info: Http3StreamInfo, datagram: Vec<u8>) {
+        self.insert(Http3ServerConnEvent::ConnectUdpDatagram {
+            stream_info,
+            datagram,
+        });
+    }
+
+    /// Report a request that timed out instead of the closing of its stream.
+    pub fn request_timed_out(&self, stream_info: Http3StreamInfo) {
+        self.remove(|evt| {
+            matches!(evt,
+                Http3ServerConnEvent::StreamReset { stream_info: x, .. } | Http3ServerConnEvent::StreamStopSending { stream_info: x, .. } if *x == stream_info)
+        });
+        self.remove_events_for_stream_id(stream_info);
+        self.insert(Http3ServerConnEvent::RequestTimedOut { stream_info });
+    }
+
+    pub fn drained(&self) {
+        self.insert(Http3ServerConnEvent::Drained);
+    }
+
     pub fn priority_update(&self, stream_id: StreamId, priority: Priority) {
         self.insert(Http3ServerConnEvent::PriorityUpdate {
             stream_id,
@@ -190,7 +286,7 @@ impl Http3ServerConnEvents {
     fn remove_events_for_stream_id(&self, stream_info: Http3StreamInfo) {
         self.remove(|evt| {
             matches!(evt,
-                Http3ServerConnEvent::Headers { stream_info: x, .. } | Http3ServerConnEvent::DataReadable { stream_info: x, .. } if *x == stream_info)
+                Http3ServerConnEvent::Headers { stream_info: x, .. } | Http3ServerConnEvent::DataReadable { stream_info: x, .. } | Http3ServerConnEvent::Trailers { stream_info: x, .. } | Http3ServerConnEvent::ConnectUdpDatagram { stream_info: x, .. } | Http3ServerConnEvent::CapsuleReceived { stream_info: x, .. } if *x == stream_info)
         });
     }
 }
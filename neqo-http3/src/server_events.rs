@@ -19,10 +19,10 @@ use neqo_transport::{
 };
 
 use crate::{
-    connection::{Http3State, WebTransportSessionAcceptAction},
+    connection::{Http3Connection, Http3State, WebTransportSessionAcceptAction},
     connection_server::Http3ServerHandler,
-    features::extended_connect::SessionCloseReason,
-    Http3StreamInfo, Http3StreamType, Priority, Res,
+    features::extended_connect::{SessionCloseReason, WebTransportSessionStats},
+    Error, Http3StreamInfo, Http3StreamType, Priority, Res,
 };
 
 #[derive(Debug, Clone)]
@@ -60,6 +60,14 @@ impl StreamHandler {
         self.stream_info.stream_id()
     }
 
+    /// The priority of the request, from its `priority` header or the latest
+    /// `PRIORITY_UPDATE` from the client.  This decides the order in which
+    /// responses are sent.
+    #[must_use]
+    pub fn priority(&self) -> Priority {
+        self.handler.borrow().stream_priority(self.stream_id())
+    }
+
     /// Supply a response header to a request.
     ///
     /// # Errors
@@ -84,6 +92,140 @@ impl StreamHandler {
             .send_data(self.stream_id(), buf, &mut self.conn.borrow_mut())
     }
 
+    /// The number of bytes of response data that `send_data` would accept now.  When
+    /// this is 0, wait for a `DataWritable` event before sending more.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if a stream does not exist anymore.
+    pub fn send_data_avail(&self) -> Res<usize> {
+        self.handler
+            .borrow()
+            .send_data_avail(self.stream_id(), &self.conn.borrow())
+    }
+
+    /// Send an informational (1xx) response, e.g. 103 Early Hints. This can be called
+    /// multiple times before the final response is supplied with `send_headers`.
+    /// `headers` must not contain pseudo-header fields, `:status` is added from `status`.
+    ///
+    /// # Errors
+    ///
+    /// It returns `InvalidInput` if `status` is not in the 1xx range and `InvalidHeader` if
+    /// the status is 101, which HTTP/3 does not allow, if `headers` contains a pseudo-header
+    /// or if the final response has already been sent. It may return `InvalidStreamId` if a
+    /// stream does not exist anymore.
+    pub fn send_interim_response(&mut self, status: u16, headers: &[Header]) -> Res<()> {
+        if !(100..200).contains(&status) {
+            return Err(Error::InvalidInput);
+        }
+        if headers.iter().any(|h| h.name().starts_with(':')) {
+            return Err(Error::InvalidHeader);
+        }
+        let mut response = vec![Header::new(":status", status.to_string())];
+        response.extend_from_slice(headers);
+        self.send_headers(&response)
+    }
+
+    /// Supply response trailers to a request, after all the response data.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if a stream does not exist anymore, `InvalidInput` if
+    /// the response headers have not been sent or the trailers have already been sent, and
+    /// `InvalidHeader` if the trailers contain a pseudo-header.
+    pub fn send_trailers(&mut self, headers: &[Header]) -> Res<()> {
+        self.handler.borrow_mut().send_trailers(
+            self.stream_id(),
+            headers,
+            &mut self.conn.borrow_mut(),
+        )
+    }
+
+    /// Promise a server push on the response to this request.  `headers` are the
+    /// headers of the promised request.  The returned push ID is used to send the
+    /// pushed response with `push_headers`, `push_data` and `push_close`.
+    ///
+    /// # Errors
+    ///
+    /// It returns `Unavailable` if push is disabled or the client does not allow
+    /// another push, and `InvalidStreamId` if a stream does not exist anymore.
+    pub fn push_promise(&mut self, headers: &[Header]) -> Res<u64> {
+        self.handler.borrow_mut().push_promise(
+            self.stream_id(),
+            headers,
+            &mut self.conn.borrow_mut(),
+        )
+    }
+
+    /// Supply the response headers for a promised push.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if the push is not active anymore, e.g. if the
+    /// client canceled it.
+    pub fn push_headers(&mut self, push_id: u64, headers: &[Header]) -> Res<()> {
+        self.handler
+            .borrow_mut()
+            .push_headers(push_id, headers, &mut self.conn.borrow_mut())
+    }
+
+    /// Supply response data for a promised push.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if the push is not active anymore.
+    pub fn push_data(&mut self, push_id: u64, buf: &[u8]) -> Res<usize> {
+        self.handler
+            .borrow_mut()
+            .push_data(push_id, buf, &mut self.conn.borrow_mut())
+    }
+
+    /// Complete the response for a promised push.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if the push is not active anymore.
+    pub fn push_close(&mut self, push_id: u64) -> Res<()> {
+        self.handler
+            .borrow_mut()
+            .push_close(push_id, &mut self.conn.borrow_mut())
+    }
+
+    /// Send a capsule ([RFC 9297]) on the response, after the response headers.
+    /// The capsule is sent as a whole, even if it does not fit into the flow
+    /// control window.
+    ///
+    /// [RFC 9297]: https://www.rfc-editor.org/rfc/rfc9297
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if a stream does not exist anymore and
+    /// `InvalidInput` if the response headers have not been sent or the response
+    /// is already complete.
+    pub fn send_capsule(&mut self, capsule_type: u64, payload: &[u8]) -> Res<()> {
+        self.handler.borrow_mut().send_capsule(
+            self.stream_id(),
+            capsule_type,
+            payload,
+            &mut self.conn.borrow_mut(),
+        )
+    }
+
+    /// Read the rest of the request body as capsules.  Each capsule, including
+    /// those of unknown types, is reported with a `CapsuleReceived` event instead
+    /// of `Data` events.  The end of the body is still reported with a `Data`
+    /// event that has `fin` set.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if a stream does not exist anymore and
+    /// `InvalidInput` if the request body has already ended.
+    pub fn capsule_protocol(&mut self) -> Res<()> {
+        self.handler
+            .borrow_mut()
+            .capsule_protocol(self.stream_id(), &mut self.conn.borrow_mut())
+    }
+
     /// Bytes sendable on stream at the QUIC layer.
     ///
     /// Note that this does not yet account for HTTP3 frame headers.
@@ -215,6 +357,74 @@ impl Http3OrWebTransportStream {
         qdebug!([self], "Set new response.");
         self.stream_handler.stream_close_send()
     }
+
+    /// Accept a CONNECT request with a 200 response.  After this the stream is a
+    /// tunnel: `send_data` and `Data` events carry the tunneled bytes and the end of
+    /// the stream in either direction is a half-close.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if a stream does not exist anymore.
+    pub fn accept_connect(&mut self) -> Res<()> {
+        qdebug!([self], "Accept CONNECT request.");
+        self.stream_handler
+            .send_headers(&[Header::new(":status", "200")])
+    }
+
+    /// Answer a CONNECT-UDP request.  Accepting it sends a 200 response, after which
+    /// `connect_udp_send_datagram` can be used; rejecting it sends a 403 response.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if a stream does not exist anymore.
+    pub fn connect_udp_response(&mut self, accept: bool) -> Res<()> {
+        qdebug!([self], "Answer CONNECT-UDP request accept={}.", accept);
+        self.stream_handler
+            .handler
+            .borrow_mut()
+            .connect_udp_response(
+                &mut self.stream_handler.conn.borrow_mut(),
+                self.stream_handler.stream_id(),
+                accept,
+            )
+    }
+
+    /// Send a UDP payload to the client of an accepted CONNECT-UDP request.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if the request was not accepted or the stream
+    /// does not exist anymore.
+    /// The function returns `TooMuchData` if the payload is bigger than
+    /// `connect_udp_max_datagram_size`.
+    pub fn connect_udp_send_datagram(
+        &mut self,
+        buf: &[u8],
+        id: impl Into<DatagramTracking>,
+    ) -> Res<()> {
+        let stream_id = self.stream_handler.stream_id();
+        self.stream_handler
+            .handler
+            .borrow_mut()
+            .connect_udp_send_datagram(
+                &mut self.stream_handler.conn.borrow_mut(),
+                stream_id,
+                buf,
+                id,
+            )
+    }
+
+    /// Returns the current max size of a UDP payload that fits into a datagram.
+    ///
+    /// # Errors
+    ///
+    /// The function returns `NotAvailable` if datagrams are not enabled.
+    pub fn connect_udp_max_datagram_size(&self) -> Res<u64> {
+        Http3Connection::connect_udp_max_datagram_size(
+            self.stream_handler.stream_id(),
+            &self.stream_handler.conn.borrow(),
+        )
+    }
 }
 
 impl Deref for Http3OrWebTransportStream {
@@ -360,6 +570,18 @@ impl WebTransportRequest {
             )
     }
 
+    /// Get the counters for this session.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if the session does not exist anymore.
+    pub fn stats(&self) -> Res<WebTransportSessionStats> {
+        self.stream_handler
+            .handler
+            .borrow()
+            .webtransport_session_stats(self.stream_handler.stream_id())
+    }
+
     #[must_use]
     pub fn remote_datagram_size(&self) -> u64 {
         self.stream_handler.conn.borrow().remote_datagram_size()
@@ -431,6 +653,15 @@ pub enum WebTransportServerEvent {
         session: WebTransportRequest,
         datagram: Vec<u8>,
     },
+    /// A datagram was not sent, because it was too big for the session.
+    DatagramDroppedTooBig {
+        session: WebTransportRequest,
+    },
+    /// The client asked for the session to be drained.  No new streams or
+    /// datagrams should be started and the session should be closed soon.
+    SessionDraining {
+        session: WebTransportRequest,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -447,6 +678,12 @@ pub enum Http3ServerEvent {
         data: Vec<u8>,
         fin: bool,
     },
+    /// Request trailers are ready. They follow all the `Data` events of the request body,
+    /// the `Data` event with `fin` set comes after them.
+    Trailers {
+        stream: Http3OrWebTransportStream,
+        headers: Vec<Header>,
+    },
     DataWritable {
         stream: Http3OrWebTransportStream,
     },
@@ -454,10 +691,22 @@ pub enum Http3ServerEvent {
         stream: Http3OrWebTransportStream,
         error: AppError,
     },
+    /// The client cancelled a request by resetting its stream.  This is reported
+    /// instead of `StreamReset` for requests.  If the client also sent `STOP_SENDING`,
+    /// that is reported separately with `StreamStopSending`.
+    RequestCancelled {
+        stream: Http3OrWebTransportStream,
+        error: AppError,
+    },
     StreamStopSending {
         stream: Http3OrWebTransportStream,
         error: AppError,
     },
+    /// The client took too long to send a request, see `Http3Parameters::request_timeout`.
+    /// The request was answered with a 408 response or cancelled, and its stream is closed.
+    RequestTimedOut {
+        stream: Http3OrWebTransportStream,
+    },
     /// When individual connection change state. It is only used for tests.
     StateChange {
         conn: ActiveConnectionRef,
@@ -467,6 +716,38 @@ pub enum Http3ServerEvent {
         stream_id: StreamId,
         priority: Priority,
     },
+    /// A connection has no remaining requests after `Http3Server::graceful_shutdown`.
+    ConnectionDrained {
+        conn: ActiveConnectionRef,
+    },
+    /// A CONNECT request for a tunnel to `authority`.  Accept it with
+    /// `Http3OrWebTransportStream::accept_connect`, or send an ordinary response
+    /// to refuse it.
+    ConnectRequest {
+        stream: Http3OrWebTransportStream,
+        authority: String,
+        headers: Vec<Header>,
+    },
+    /// A CONNECT-UDP request for `target_host` and `target_port`.  Answer it with
+    /// `Http3OrWebTransportStream::connect_udp_response`.
+    ConnectUdp {
+        stream: Http3OrWebTransportStream,
+        target_host: String,
+        target_port: u16,
+        headers: Vec<Header>,
+    },
+    /// A UDP payload arrived for an accepted CONNECT-UDP request.
+    ConnectUdpDatagram {
+        stream: Http3OrWebTransportStream,
+        datagram: Vec<u8>,
+    },
+    /// A capsule arrived on a request that uses the capsule protocol, see
+    /// `StreamHandler::capsule_protocol`.
+    CapsuleReceived {
+        stream: Http3OrWebTransportStream,
+        capsule_type: u64,
+        payload: Vec<u8>,
+    },
     WebTransport(WebTransportServerEvent),
 }
 
@@ -514,6 +795,59 @@ impl Http3ServerEvents {
         self.insert(Http3ServerEvent::StateChange { conn, state });
     }
 
+    pub(crate) fn connect_request(
+        &self,
+        stream: Http3OrWebTransportStream,
+        authority: String,
+        headers: Vec<Header>,
+    ) {
+        self.insert(Http3ServerEvent::ConnectRequest {
+            stream,
+            authority,
+            headers,
+        });
+    }
+
+    pub(crate) fn connect_udp(
+        &self,
+        stream: Http3OrWebTransportStream,
+        target_host: String,
+        target_port: u16,
+        headers: Vec<Header>,
+    ) {
+        self.insert(Http3ServerEvent::ConnectUdp {
+            stream,
+            target_host,
+            target_port,
+            headers,
+        });
+    }
+
+    pub(crate) fn connect_udp_datagram(
+        &self,
+        stream: Http3OrWebTransportStream,
+        datagram: Vec<u8>,
+    ) {
+        self.insert(Http3ServerEvent::ConnectUdpDatagram { stream, datagram });
+    }
+
+    pub(crate) fn capsule_received(
+        &self,
+        stream: Http3OrWebTransportStream,
+        capsule_type: u64,
+        payload: Vec<u8>,
+    ) {
+        self.insert(Http3ServerEvent::CapsuleReceived {
+            stream,
+            capsule_type,
+            payload,
+        });
+    }
+
+    pub(crate) fn connection_drained(&self, conn: ActiveConnectionRef) {
+        self.insert(Http3ServerEvent::ConnectionDrained { conn });
+    }
+
     /// Insert a `Data` event.
     pub(crate) fn data(
         &self,
@@ -530,6 +864,19 @@ impl Http3ServerEvents {
         });
     }
 
+    pub(crate) fn trailers(
+        &self,
+        conn: ActiveConnectionRef,
+        handler: Rc<RefCell<Http3ServerHandler>>,
+        stream_info: Http3StreamInfo,
+        headers: Vec<Header>,
+    ) {
+        self.insert(Http3ServerEvent::Trailers {
+            stream: Http3OrWebTransportStream::new(conn, handler, stream_info),
+            headers,
+        });
+    }
+
     pub(crate) fn data_writable(
         &self,
         conn: ActiveConnectionRef,
@@ -554,6 +901,43 @@ impl Http3ServerEvents {
         });
     }
 
+    /// Remove the `Data` and `Trailers` events for a request that are waiting
+    /// to be taken.
+    pub(crate) fn remove_request_data(&self, conn: &ActiveConnectionRef, stream_id: StreamId) {
+        self.events.borrow_mut().retain(|e| match e {
+            Http3ServerEvent::Data { stream, .. }
+            | Http3ServerEvent::Trailers { stream, .. }
+            | Http3ServerEvent::CapsuleReceived { stream, .. } => {
+                stream.conn != *conn || stream.stream_id() != stream_id
+            }
+            _ => true,
+        });
+    }
+
+    pub(crate) fn request_cancelled(
+        &self,
+        conn: ActiveConnectionRef,
+        handler: Rc<RefCell<Http3ServerHandler>>,
+        stream_info: Http3StreamInfo,
+        error: AppError,
+    ) {
+        self.insert(Http3ServerEvent::RequestCancelled {
+            stream: Http3OrWebTransportStream::new(conn, handler, stream_info),
+            error,
+        });
+    }
+
+    pub(crate) fn request_timed_out(
+        &self,
+        conn: ActiveConnectionRef,
+        handler: Rc<RefCell<Http3ServerHandler>>,
+        stream_info: Http3StreamInfo,
+    ) {
+        self.insert(Http3ServerEvent::RequestTimedOut {
+            stream: Http3OrWebTransportStream::new(conn, handler, stream_info),
+        });
+    }
+
     pub(crate) fn stream_stop_sending(
         &self,
         conn: ActiveConnectionRef,
@@ -610,4 +994,16 @@ impl Http3ServerEvents {
             WebTransportServerEvent::Datagram { session, datagram },
         ));
     }
+
+    pub(crate) fn webtransport_datagram_dropped_too_big(&self, session: WebTransportRequest) {
+        self.insert(Http3ServerEvent::WebTransport(
+            WebTransportServerEvent::DatagramDroppedTooBig { session },
+        ));
+    }
+
+    pub(crate) fn webtransport_session_draining(&self, session: WebTransportRequest) {
+        self.insert(Http3ServerEvent::WebTransport(
+            WebTransportServerEvent::SessionDraining { session },
+        ));
+    }
 }
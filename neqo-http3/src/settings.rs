@@ -17,11 +17,12 @@ type SettingsType = u64;
 
 /// Increment this version number if a new setting is added and that might
 /// cause 0-RTT to be accepted where shouldn't be.
-const SETTINGS_ZERO_RTT_VERSION: u64 = 1;
+const SETTINGS_ZERO_RTT_VERSION: u64 = 2;
 
 const SETTINGS_MAX_HEADER_LIST_SIZE: SettingsType = 0x6;
 const SETTINGS_QPACK_MAX_TABLE_CAPACITY: SettingsType = 0x1;
 const SETTINGS_QPACK_BLOCKED_STREAMS: SettingsType = 0x7;
+const SETTINGS_ENABLE_CONNECT_PROTOCOL: SettingsType = 0x8;
 const SETTINGS_ENABLE_WEB_TRANSPORT: SettingsType = 0x2b60_3742;
 // draft-ietf-masque-h3-datagram-04.
 // We also use this old value because the current web-platform test only supports
@@ -39,6 +40,44 @@ pub enum HSettingType {
     BlockedStreams,
     EnableWebTransport,
     EnableH3Datagram,
+    EnableConnectProtocol,
+    /// A setting that neqo does not interpret, with its identifier.
+    Unknown(u64),
+}
+
+impl HSettingType {
+    /// The identifier that is used for this setting on the wire.
+    /// HTTP Datagrams are also announced using an older draft identifier;
+    /// this returns the identifier from RFC 9297.
+    #[must_use]
+    pub fn id(self) -> u64 {
+        match self {
+            Self::MaxHeaderListSize => SETTINGS_MAX_HEADER_LIST_SIZE,
+            Self::MaxTableCapacity => SETTINGS_QPACK_MAX_TABLE_CAPACITY,
+            Self::BlockedStreams => SETTINGS_QPACK_BLOCKED_STREAMS,
+            Self::EnableWebTransport => SETTINGS_ENABLE_WEB_TRANSPORT,
+            Self::EnableH3Datagram => SETTINGS_H3_DATAGRAM,
+            Self::EnableConnectProtocol => SETTINGS_ENABLE_CONNECT_PROTOCOL,
+            Self::Unknown(id) => id,
+        }
+    }
+}
+
+/// Whether an application can send a setting with this identifier.
+/// Identifiers that are reserved from HTTP/2 are not allowed, nor are those
+/// that neqo sets itself.
+pub(crate) fn is_additional_setting_allowed(id: SettingsType) -> bool {
+    !H3_RESERVED_SETTINGS.contains(&id)
+        && ![
+            SETTINGS_MAX_HEADER_LIST_SIZE,
+            SETTINGS_QPACK_MAX_TABLE_CAPACITY,
+            SETTINGS_QPACK_BLOCKED_STREAMS,
+            SETTINGS_ENABLE_CONNECT_PROTOCOL,
+            SETTINGS_ENABLE_WEB_TRANSPORT,
+            SETTINGS_H3_DATAGRAM_DRAFT04,
+            SETTINGS_H3_DATAGRAM,
+        ]
+        .contains(&id)
 }
 
 fn hsetting_default(setting_type: HSettingType) -> u64 {
@@ -47,7 +86,9 @@ fn hsetting_default(setting_type: HSettingType) -> u64 {
         HSettingType::MaxTableCapacity
         | HSettingType::BlockedStreams
         | HSettingType::EnableWebTransport
-        | HSettingType::EnableH3Datagram => 0,
+        | HSettingType::EnableH3Datagram
+        | HSettingType::EnableConnectProtocol
+        | HSettingType::Unknown(_) => 0,
     }
 }
 
@@ -88,6 +129,15 @@ impl HSettings {
         }
     }
 
+    /// The settings as pairs of identifier and value.
+    #[must_use]
+    pub fn to_pairs(&self) -> Vec<(u64, u64)> {
+        self.settings
+            .iter()
+            .map(|s| (s.setting_type.id(), s.value))
+            .collect()
+    }
+
     pub fn encode_frame_contents(&self, enc: &mut Encoder) {
         enc.encode_vvec_with(|enc_inner| {
             for iter in &self.settings {
@@ -116,6 +166,16 @@ impl HSettings {
                             enc_inner.encode_varint(iter.value);
                         }
                     }
+                    HSettingType::EnableConnectProtocol => {
+                        if iter.value == 1 {
+                            enc_inner.encode_varint(SETTINGS_ENABLE_CONNECT_PROTOCOL);
+                            enc_inner.encode_varint(iter.value);
+                        }
+                    }
+                    HSettingType::Unknown(id) => {
+                        enc_inner.encode_varint(id);
+                        enc_inner.encode_varint(iter.value);
+                    }
                 }
             }
         });
@@ -123,16 +183,19 @@ impl HSettings {
 
     /// # Errors
     ///
-    /// Returns an error if settings types are reserved of settings value are not permitted.
+    /// Returns an error if settings types are reserved or repeated, or if settings
+    /// values are not permitted.
     pub fn decode_frame_contents(&mut self, dec: &mut Decoder) -> Res<()> {
+        let mut seen = Vec::new();
         while dec.remaining() > 0 {
             let t = dec.decode_varint();
             let v = dec.decode_varint();
 
             if let Some(settings_type) = t {
-                if H3_RESERVED_SETTINGS.contains(&settings_type) {
+                if H3_RESERVED_SETTINGS.contains(&settings_type) || seen.contains(&settings_type) {
                     return Err(Error::HttpSettings);
                 }
+                seen.push(settings_type);
             }
             match (t, v) {
                 (Some(SETTINGS_MAX_HEADER_LIST_SIZE), Some(value)) => self
@@ -177,8 +240,17 @@ impl HSettings {
                             .push(HSetting::new(HSettingType::EnableH3Datagram, value));
                     }
                 }
+                (Some(SETTINGS_ENABLE_CONNECT_PROTOCOL), Some(value)) => {
+                    if value > 1 {
+                        return Err(Error::HttpSettings);
+                    }
+                    self.settings
+                        .push(HSetting::new(HSettingType::EnableConnectProtocol, value));
+                }
                 // other supported settings here
-                (Some(_), Some(_)) => {} // ignore unknown setting, it is fine.
+                (Some(t), Some(value)) => self
+                    .settings
+                    .push(HSetting::new(HSettingType::Unknown(t), value)),
                 _ => return Err(Error::NotEnoughData),
             };
         }
@@ -195,7 +267,7 @@ impl Deref for HSettings {
 
 impl From<&Http3Parameters> for HSettings {
     fn from(conn_param: &Http3Parameters) -> Self {
-        Self {
+        let mut settings = Self {
             settings: vec![
                 HSetting {
                     setting_type: HSettingType::MaxTableCapacity,
@@ -213,8 +285,24 @@ impl From<&Http3Parameters> for HSettings {
                     setting_type: HSettingType::EnableH3Datagram,
                     value: u64::from(conn_param.get_http3_datagram()),
                 },
+                HSetting {
+                    setting_type: HSettingType::EnableConnectProtocol,
+                    value: u64::from(conn_param.extended_connect_enabled()),
+                },
             ],
+        };
+        if let Some(size) = conn_param.get_max_field_section_size() {
+            settings
+                .settings
+                .push(HSetting::new(HSettingType::MaxHeaderListSize, size));
         }
+        settings.settings.extend(
+            conn_param
+                .get_additional_settings()
+                .iter()
+                .map(|&(id, value)| HSetting::new(HSettingType::Unknown(id), value)),
+        );
+        settings
     }
 }
 
@@ -239,6 +327,10 @@ impl HttpZeroRttChecker {
             .encode_varint(settings.get_max_table_size_decoder())
             .encode_varint(SETTINGS_QPACK_BLOCKED_STREAMS)
             .encode_varint(settings.get_max_blocked_streams());
+        if let Some(size) = settings.get_max_field_section_size() {
+            enc.encode_varint(SETTINGS_MAX_HEADER_LIST_SIZE)
+                .encode_varint(size);
+        }
         if settings.get_webtransport() {
             enc.encode_varint(SETTINGS_ENABLE_WEB_TRANSPORT)
                 .encode_varint(true);
@@ -246,6 +338,10 @@ impl HttpZeroRttChecker {
         if settings.get_http3_datagram() {
             enc.encode_varint(SETTINGS_H3_DATAGRAM).encode_varint(true);
         }
+        if settings.extended_connect_enabled() {
+            enc.encode_varint(SETTINGS_ENABLE_CONNECT_PROTOCOL)
+                .encode_varint(true);
+        }
         enc.into()
     }
 }
@@ -268,6 +364,14 @@ impl ZeroRttChecker for HttpZeroRttChecker {
         if settings.decode_frame_contents(&mut dec).is_err() {
             return ZeroRttCheckResult::Fail;
         }
+        // An absent limit means that the client could have sent field sections of any size.
+        let max_field_section_size = self
+            .settings
+            .get_max_field_section_size()
+            .unwrap_or(u64::MAX);
+        if max_field_section_size < settings.get(HSettingType::MaxHeaderListSize) {
+            return ZeroRttCheckResult::Reject;
+        }
         if settings.iter().all(|setting| match setting.setting_type {
             HSettingType::BlockedStreams => {
                 u64::from(self.settings.get_max_blocked_streams()) >= setting.value
@@ -289,7 +393,14 @@ impl ZeroRttChecker for HttpZeroRttChecker {
                 let value = setting.value == 1;
                 self.settings.get_http3_datagram() || !value
             }
-            HSettingType::MaxHeaderListSize => true,
+            HSettingType::EnableConnectProtocol => {
+                if setting.value > 1 {
+                    return false;
+                }
+                let value = setting.value == 1;
+                self.settings.extended_connect_enabled() || !value
+            }
+            HSettingType::MaxHeaderListSize | HSettingType::Unknown(_) => true,
         }) {
             ZeroRttCheckResult::Accept
         } else {
@@ -0,0 +1,152 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use neqo_common::{event::Provider, Header};
+use neqo_http3::{
+    Error, FetchHandle, Http3Client, Http3ClientEvent, Http3OrWebTransportStream, Http3Server,
+    Http3ServerEvent, RequestParams,
+};
+use test_fixture::{
+    default_http3_client, default_http3_server, http3_connect, http3_exchange_packets, now,
+};
+
+fn connect() -> (Http3Client, Http3Server) {
+    let mut client = default_http3_client();
+    let mut server = default_http3_server();
+    http3_connect(&mut client, &mut server);
+    (client, server)
+}
+
+/// Start a buffered fetch with `request` and return the request on the server,
+/// with the request body.
+fn fetch(
+    client: &mut Http3Client,
+    server: &mut Http3Server,
+    request: RequestParams,
+) -> (FetchHandle, Http3OrWebTransportStream, Vec<u8>) {
+    let handle = client.fetch_buffered(now(), request).unwrap();
+    http3_exchange_packets(client, server);
+
+    let mut stream = None;
+    let mut body = Vec::new();
+    for e in server.events() {
+        match e {
+            Http3ServerEvent::Headers { stream: s, .. } => stream = Some(s),
+            Http3ServerEvent::Data { data, .. } => body.extend_from_slice(&data),
+            _ => {}
+        }
+    }
+    // The client does not see events for the request.
+    assert!(!client.events().any(|e| matches!(
+        e,
+        Http3ClientEvent::HeaderReady { .. } | Http3ClientEvent::DataReadable { .. }
+    )));
+    (handle, stream.expect("a request"), body)
+}
+
+fn get() -> RequestParams {
+    RequestParams::new("GET", "https", "something.com", "/")
+}
+
+#[test]
+fn buffered_fetch_multiple_chunks() {
+    let (mut client, mut server) = connect();
+    let request = RequestParams::new("POST", "https", "something.com", "/upload")
+        .headers(&[Header::new("content-type", "text/plain")])
+        .body(b"request body");
+    let (handle, mut stream, request_body) = fetch(&mut client, &mut server, request);
+    assert_eq!(request_body, b"request body");
+
+    stream
+        .send_headers(&[Header::new(":status", "200")])
+        .unwrap();
+    for chunk in [&b"first "[..], b"second ", b"third"] {
+        stream.send_data(chunk).unwrap();
+        http3_exchange_packets(&mut client, &mut server);
+        assert!(handle.poll_complete().is_none());
+    }
+    stream
+        .send_trailers(&[Header::new("checksum", "1234")])
+        .unwrap();
+    stream.stream_close_send().unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+
+    let response = handle.poll_complete().unwrap().unwrap();
+    assert_eq!(response.status(), Some(200));
+    assert_eq!(response.body, b"first second third");
+    assert_eq!(
+        response.trailers,
+        Some(vec![Header::new("checksum", "1234")])
+    );
+    // The outcome is only reported once.
+    assert!(handle.poll_complete().is_none());
+}
+
+#[test]
+fn buffered_fetch_error_response() {
+    let (mut client, mut server) = connect();
+    let (handle, mut stream, _) = fetch(&mut client, &mut server, get());
+
+    stream
+        .send_headers(&[Header::new(":status", "404")])
+        .unwrap();
+    stream.send_data(b"not found").unwrap();
+    stream.stream_close_send().unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+
+    let response = handle.poll_complete().unwrap().unwrap();
+    assert_eq!(response.status(), Some(404));
+    assert_eq!(response.body, b"not found");
+    assert_eq!(response.trailers, None);
+}
+
+#[test]
+fn buffered_fetch_reset() {
+    let (mut client, mut server) = connect();
+    let (handle, mut stream, _) = fetch(&mut client, &mut server, get());
+
+    stream
+        .send_headers(&[Header::new(":status", "200")])
+        .unwrap();
+    stream.send_data(b"partial").unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert!(handle.poll_complete().is_none());
+
+    let error = Error::HttpInternal(0).code();
+    stream.stream_reset_send(error).unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(
+        handle.poll_complete(),
+        Some(Err(Error::StreamAborted(error)))
+    );
+}
+
+#[test]
+fn buffered_fetch_body_too_large() {
+    let (mut client, mut server) = connect();
+    let (handle, mut stream, _) = fetch(&mut client, &mut server, get().max_body_size(100));
+
+    stream
+        .send_headers(&[Header::new(":status", "200")])
+        .unwrap();
+    stream.send_data(&[0; 60]).unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert!(handle.poll_complete().is_none());
+
+    stream.send_data(&[0; 60]).unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(
+        handle.poll_complete(),
+        Some(Err(Error::ResponseBodyTooLarge))
+    );
+
+    // The fetch is cancelled.
+    let cancelled = Error::HttpRequestCancelled.code();
+    assert!(server.events().any(|e| matches!(
+        e,
+        Http3ServerEvent::StreamStopSending { error, .. } if error == cancelled
+    )));
+}
@@ -0,0 +1,203 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use neqo_common::{event::Provider, Encoder, Header};
+use neqo_http3::{
+    Error, Http3Client, Http3ClientEvent, Http3OrWebTransportStream, Http3Parameters, Http3Server,
+    Http3ServerEvent, Priority,
+};
+use neqo_transport::{ConnectionParameters, StreamId, StreamType};
+use test_fixture::{
+    http3_client_with_params, http3_connect, http3_exchange_packets, http3_server_with_params, now,
+};
+
+/// A small stream flow control window, so that large capsules need several of them.
+const STREAM_WINDOW: u64 = 4096;
+const CAPSULE_TYPE: u64 = 0x2b_6a7c;
+/// A reserved capsule type, which no implementation knows.
+const GREASE_CAPSULE_TYPE: u64 = 0x1f * 17 + 0x29;
+
+fn parameters() -> Http3Parameters {
+    Http3Parameters::default().connection_parameters(
+        ConnectionParameters::default()
+            .max_stream_data(StreamType::BiDi, false, STREAM_WINDOW)
+            .max_stream_data(StreamType::BiDi, true, STREAM_WINDOW),
+    )
+}
+
+fn connect() -> (Http3Client, Http3Server) {
+    let mut client = http3_client_with_params(parameters());
+    let mut server = http3_server_with_params(parameters());
+    http3_connect(&mut client, &mut server);
+    (client, server)
+}
+
+/// Open a request that uses the capsule protocol in both directions.
+fn capsule_request(
+    client: &mut Http3Client,
+    server: &mut Http3Server,
+) -> (StreamId, Http3OrWebTransportStream) {
+    let stream_id = client
+        .fetch(
+            now(),
+            "POST",
+            &("https", "something.com", "/"),
+            &[Header::new("capsule-protocol", "?1")],
+            Priority::default(),
+        )
+        .unwrap();
+    http3_exchange_packets(client, server);
+
+    let mut stream = server
+        .events()
+        .find_map(|e| match e {
+            Http3ServerEvent::Headers { stream, .. } => Some(stream),
+            _ => None,
+        })
+        .expect("a request");
+    stream.capsule_protocol().unwrap();
+    stream
+        .send_headers(&[
+            Header::new(":status", "200"),
+            Header::new("capsule-protocol", "?1"),
+        ])
+        .unwrap();
+    http3_exchange_packets(client, server);
+
+    assert!(client.events().any(|e| matches!(
+        e,
+        Http3ClientEvent::HeaderReady { stream_id: id, .. } if id == stream_id
+    )));
+    client.capsule_protocol(now(), stream_id).unwrap();
+    (stream_id, stream)
+}
+
+fn server_capsules(server: &mut Http3Server) -> Vec<(u64, Vec<u8>)> {
+    server
+        .events()
+        .filter_map(|e| match e {
+            Http3ServerEvent::CapsuleReceived {
+                capsule_type,
+                payload,
+                ..
+            } => Some((capsule_type, payload)),
+            Http3ServerEvent::Data { data, .. } => {
+                assert!(data.is_empty(), "the body must not be reported as data");
+                None
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn client_capsules(client: &mut Http3Client) -> Vec<(u64, Vec<u8>)> {
+    client
+        .events()
+        .filter_map(|e| match e {
+            Http3ClientEvent::CapsuleReceived {
+                capsule_type,
+                payload,
+                ..
+            } => Some((capsule_type, payload)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| u8::try_from(i % 251).unwrap()).collect()
+}
+
+#[test]
+fn capsules_round_trip() {
+    let (mut client, mut server) = connect();
+    let (stream_id, mut stream) = capsule_request(&mut client, &mut server);
+
+    for len in [0, 1, 1000, 4096, 20_000] {
+        let sent = payload(len);
+        client.send_capsule(stream_id, CAPSULE_TYPE, &sent).unwrap();
+        http3_exchange_packets(&mut client, &mut server);
+        assert_eq!(server_capsules(&mut server), [(CAPSULE_TYPE, sent.clone())]);
+
+        stream.send_capsule(CAPSULE_TYPE, &sent).unwrap();
+        http3_exchange_packets(&mut client, &mut server);
+        assert_eq!(client_capsules(&mut client), [(CAPSULE_TYPE, sent)]);
+    }
+
+    // The end of the body is still reported.
+    client.stream_close_send(stream_id).unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert!(server
+        .events()
+        .any(|e| matches!(e, Http3ServerEvent::Data { data, fin: true, .. } if data.is_empty())));
+}
+
+#[test]
+fn capsule_split_across_data_frames() {
+    let (mut client, mut server) = connect();
+    let (stream_id, _stream) = capsule_request(&mut client, &mut server);
+
+    let sent = payload(3 * usize::try_from(STREAM_WINDOW).unwrap());
+    let mut enc = Encoder::default();
+    enc.encode_varint(CAPSULE_TYPE);
+    enc.encode_vvec(&sent);
+
+    // Every call to `send_data` makes a DATA frame of its own.
+    let mut offset = 0;
+    while offset < enc.len() {
+        let end = usize::min(offset + 100, enc.len());
+        offset += client
+            .send_data(stream_id, &enc.as_ref()[offset..end])
+            .unwrap();
+        http3_exchange_packets(&mut client, &mut server);
+        if offset < enc.len() {
+            assert!(server_capsules(&mut server).is_empty());
+        }
+    }
+    assert_eq!(server_capsules(&mut server), [(CAPSULE_TYPE, sent)]);
+}
+
+#[test]
+fn unknown_capsule_type() {
+    let (mut client, mut server) = connect();
+    let (stream_id, mut stream) = capsule_request(&mut client, &mut server);
+
+    let sent = b"unknown capsule".to_vec();
+    client
+        .send_capsule(stream_id, GREASE_CAPSULE_TYPE, &sent)
+        .unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(
+        server_capsules(&mut server),
+        [(GREASE_CAPSULE_TYPE, sent.clone())]
+    );
+
+    stream.send_capsule(GREASE_CAPSULE_TYPE, &sent).unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(client_capsules(&mut client), [(GREASE_CAPSULE_TYPE, sent)]);
+}
+
+#[test]
+fn truncated_capsule() {
+    let (mut client, mut server) = connect();
+    let (stream_id, _stream) = capsule_request(&mut client, &mut server);
+
+    let mut enc = Encoder::default();
+    enc.encode_varint(CAPSULE_TYPE);
+    enc.encode_varint(100_u64);
+    enc.encode(&[0; 50]);
+    assert_eq!(
+        client.send_data(stream_id, enc.as_ref()).unwrap(),
+        enc.len()
+    );
+    client.stream_close_send(stream_id).unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+
+    assert!(server.events().any(|e| matches!(
+        e,
+        Http3ServerEvent::StreamReset { error, .. } if error == Error::HttpMessageError.code()
+    )));
+}
@@ -0,0 +1,174 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use neqo_common::{event::Provider, Header};
+use neqo_http3::{
+    Http3Client, Http3ClientEvent, Http3OrWebTransportStream, Http3Server, Http3ServerEvent,
+};
+use neqo_transport::StreamId;
+use test_fixture::{
+    default_http3_client, default_http3_server, http3_connect, http3_exchange_packets, now,
+};
+
+const AUTHORITY: &str = "example.com:443";
+
+fn connect() -> (Http3Client, Http3Server) {
+    let mut client = default_http3_client();
+    let mut server = default_http3_server();
+    http3_connect(&mut client, &mut server);
+    (client, server)
+}
+
+/// Send a CONNECT request and wait for it on the server.
+fn open_tunnel(
+    client: &mut Http3Client,
+    server: &mut Http3Server,
+) -> (StreamId, Http3OrWebTransportStream) {
+    let stream_id = client.connect(now(), AUTHORITY, &[]).unwrap();
+    http3_exchange_packets(client, server);
+    while let Some(event) = server.next_event() {
+        if let Http3ServerEvent::ConnectRequest {
+            stream,
+            authority,
+            headers,
+        } = event
+        {
+            assert_eq!(authority, AUTHORITY);
+            assert!(!headers
+                .iter()
+                .any(|h| h.name() == ":scheme" || h.name() == ":path"));
+            return (stream_id, stream);
+        }
+    }
+    panic!("no CONNECT request");
+}
+
+/// Read the response status on the client.
+fn receive_status(client: &mut Http3Client, stream_id: StreamId) -> (String, bool) {
+    while let Some(event) = client.next_event() {
+        if let Http3ClientEvent::HeaderReady {
+            stream_id: id,
+            headers,
+            fin,
+        } = event
+        {
+            assert_eq!(id, stream_id);
+            let status = headers
+                .iter()
+                .find(|h| h.name() == ":status")
+                .unwrap()
+                .value()
+                .to_string();
+            return (status, fin);
+        }
+    }
+    panic!("no response");
+}
+
+/// Everything the tunnel delivered so far, in each direction.
+#[derive(Default)]
+struct Received {
+    on_server: Vec<u8>,
+    server_fin: bool,
+    on_client: Vec<u8>,
+    client_fin: bool,
+}
+
+impl Received {
+    fn collect(&mut self, client: &mut Http3Client, server: &mut Http3Server, stream_id: StreamId) {
+        while let Some(event) = server.next_event() {
+            if let Http3ServerEvent::Data { data, fin, .. } = event {
+                assert!(!self.server_fin);
+                self.on_server.extend_from_slice(&data);
+                self.server_fin |= fin;
+            }
+        }
+        while let Some(event) = client.next_event() {
+            if let Http3ClientEvent::DataReadable { stream_id: id } = event {
+                assert_eq!(id, stream_id);
+                loop {
+                    let mut buf = [0; 4096];
+                    let (amount, fin) = client.read_data(now(), stream_id, &mut buf).unwrap();
+                    self.on_client.extend_from_slice(&buf[..amount]);
+                    self.client_fin |= fin;
+                    if amount == 0 || fin {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn connect_tunnel() {
+    const LEN: usize = 300_000;
+    let (mut client, mut server) = connect();
+    let (stream_id, mut stream) = open_tunnel(&mut client, &mut server);
+    stream.accept_connect().unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(
+        receive_status(&mut client, stream_id),
+        ("200".to_string(), false)
+    );
+
+    let up = (0..LEN)
+        .map(|i| u8::try_from(i % 241).unwrap())
+        .collect::<Vec<_>>();
+    let down = (0..LEN)
+        .map(|i| u8::try_from(i % 239).unwrap())
+        .collect::<Vec<_>>();
+    let (mut sent_up, mut sent_down) = (0, 0);
+    let mut received = Received::default();
+
+    // Send in both directions at the same time.  The client closes its side
+    // first, while the server keeps sending.
+    while !received.server_fin || !received.client_fin {
+        if sent_up < LEN {
+            sent_up += client.send_data(stream_id, &up[sent_up..]).unwrap();
+            if sent_up == LEN {
+                client.stream_close_send(stream_id).unwrap();
+            }
+        }
+        if received.server_fin && sent_down < LEN {
+            sent_down += stream.send_data(&down[sent_down..]).unwrap();
+            if sent_down == LEN {
+                stream.stream_close_send().unwrap();
+            }
+        } else if sent_down < LEN / 2 {
+            sent_down += stream.send_data(&down[sent_down..LEN / 2]).unwrap();
+        }
+        http3_exchange_packets(&mut client, &mut server);
+        received.collect(&mut client, &mut server, stream_id);
+    }
+    assert_eq!(received.on_server, up);
+    assert_eq!(received.on_client, down);
+}
+
+#[test]
+fn connect_refused() {
+    let (mut client, mut server) = connect();
+    let (stream_id, mut stream) = open_tunnel(&mut client, &mut server);
+    stream
+        .send_headers(&[
+            Header::new(":status", "407"),
+            Header::new("content-length", "6"),
+        ])
+        .unwrap();
+    stream.send_data(b"denied").unwrap();
+    stream.stream_close_send().unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+
+    // The refusal is an ordinary response.
+    assert_eq!(
+        receive_status(&mut client, stream_id),
+        ("407".to_string(), false)
+    );
+    let mut received = Received::default();
+    received.collect(&mut client, &mut server, stream_id);
+    assert_eq!(received.on_client, b"denied");
+    assert!(received.client_fin);
+}
@@ -0,0 +1,225 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use neqo_common::event::Provider;
+use neqo_http3::{
+    Error, Http3Client, Http3ClientEvent, Http3OrWebTransportStream, Http3Parameters, Http3Server,
+    Http3ServerEvent,
+};
+use neqo_transport::{ConnectionParameters, StreamId};
+use test_fixture::{
+    http3_client_with_params, http3_connect, http3_exchange_packets, http3_server_with_params, now,
+};
+
+const DATAGRAM_SIZE: u64 = 1200;
+const PROXY: &str = "proxy.example.com";
+const TARGET_HOST: &str = "2001:db8::53";
+const TARGET_PORT: u16 = 53;
+
+fn datagram_parameters() -> Http3Parameters {
+    Http3Parameters::default()
+        .connection_parameters(ConnectionParameters::default().datagram_size(DATAGRAM_SIZE))
+}
+
+fn connect(
+    client_params: Http3Parameters,
+    server_params: Http3Parameters,
+) -> (Http3Client, Http3Server) {
+    let mut client = http3_client_with_params(client_params);
+    let mut server = http3_server_with_params(server_params);
+    http3_connect(&mut client, &mut server);
+    (client, server)
+}
+
+fn connect_default() -> (Http3Client, Http3Server) {
+    connect(
+        datagram_parameters().http3_datagram(true),
+        datagram_parameters().connect_udp(true),
+    )
+}
+
+/// Send a CONNECT-UDP request and wait for it on the server.
+fn open_tunnel(
+    client: &mut Http3Client,
+    server: &mut Http3Server,
+) -> (StreamId, Http3OrWebTransportStream) {
+    let stream_id = client
+        .connect_udp(now(), PROXY, TARGET_HOST, TARGET_PORT)
+        .unwrap();
+    http3_exchange_packets(client, server);
+    while let Some(event) = server.next_event() {
+        if let Http3ServerEvent::ConnectUdp {
+            stream,
+            target_host,
+            target_port,
+            headers,
+        } = event
+        {
+            assert_eq!(target_host, TARGET_HOST);
+            assert_eq!(target_port, TARGET_PORT);
+            assert!(headers
+                .iter()
+                .any(|h| h.name() == "capsule-protocol" && h.value() == "?1"));
+            return (stream_id, stream);
+        }
+    }
+    panic!("no CONNECT-UDP request");
+}
+
+/// Read the response status on the client.
+fn receive_status(client: &mut Http3Client, stream_id: StreamId) -> String {
+    while let Some(event) = client.next_event() {
+        if let Http3ClientEvent::HeaderReady {
+            stream_id: id,
+            headers,
+            ..
+        } = event
+        {
+            assert_eq!(id, stream_id);
+            return headers
+                .iter()
+                .find(|h| h.name() == ":status")
+                .unwrap()
+                .value()
+                .to_string();
+        }
+    }
+    panic!("no response");
+}
+
+/// Act as the proxy in front of a UDP echo server: every payload from the client is
+/// sent back to it.  Returns the number of payloads that were echoed.
+fn echo_on_server(server: &mut Http3Server) -> usize {
+    let mut echoed = 0;
+    while let Some(event) = server.next_event() {
+        if let Http3ServerEvent::ConnectUdpDatagram {
+            mut stream,
+            datagram,
+        } = event
+        {
+            stream.connect_udp_send_datagram(&datagram, None).unwrap();
+            echoed += 1;
+        }
+    }
+    echoed
+}
+
+fn datagrams_on_client(client: &mut Http3Client, stream_id: StreamId) -> Vec<Vec<u8>> {
+    client
+        .events()
+        .filter_map(|e| match e {
+            Http3ClientEvent::ConnectUdpDatagram {
+                stream_id: id,
+                datagram,
+            } => {
+                assert_eq!(id, stream_id);
+                Some(datagram)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn connect_udp_echo() {
+    let (mut client, mut server) = connect_default();
+    let (stream_id, mut stream) = open_tunnel(&mut client, &mut server);
+
+    // Datagrams only flow after the proxy accepts the request.
+    assert_eq!(
+        client.connect_udp_send_datagram(stream_id, b"too early", None),
+        Err(Error::InvalidStreamId)
+    );
+
+    stream.connect_udp_response(true).unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(receive_status(&mut client, stream_id), "200");
+
+    let max = usize::try_from(client.connect_udp_max_datagram_size(stream_id).unwrap()).unwrap();
+    let payloads = [
+        Vec::new(),
+        b"a DNS query".to_vec(),
+        (0..max).map(|i| u8::try_from(i % 251).unwrap()).collect(),
+    ];
+    for payload in &payloads {
+        client
+            .connect_udp_send_datagram(stream_id, payload, None)
+            .unwrap();
+    }
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(echo_on_server(&mut server), payloads.len());
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(datagrams_on_client(&mut client, stream_id), payloads);
+
+    // Payloads that exceed the datagram size limit of the proxy are refused.
+    let too_big = vec![0; usize::try_from(DATAGRAM_SIZE).unwrap()];
+    assert_eq!(
+        client.connect_udp_send_datagram(stream_id, &too_big, None),
+        Err(Error::TransportError(neqo_transport::Error::TooMuchData))
+    );
+}
+
+#[test]
+fn connect_udp_rejected() {
+    let (mut client, mut server) = connect_default();
+    let (stream_id, mut stream) = open_tunnel(&mut client, &mut server);
+    stream.connect_udp_response(false).unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(receive_status(&mut client, stream_id), "403");
+
+    // Neither side accepts datagrams for a rejected request.
+    assert_eq!(
+        stream.connect_udp_send_datagram(b"nope", None),
+        Err(Error::InvalidStreamId)
+    );
+    assert_eq!(
+        client.connect_udp_send_datagram(stream_id, b"nope", None),
+        Err(Error::InvalidStreamId)
+    );
+}
+
+#[test]
+fn connect_udp_capsules() {
+    const CAPSULE_TYPE: u64 = 0x2a;
+    let (mut client, mut server) = connect_default();
+    let (stream_id, mut stream) = open_tunnel(&mut client, &mut server);
+    stream.connect_udp_response(true).unwrap();
+    stream.send_capsule(CAPSULE_TYPE, b"capsule").unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(receive_status(&mut client, stream_id), "200");
+
+    // The response body is read as capsules without asking for it.
+    let capsule = Http3ClientEvent::CapsuleReceived {
+        stream_id,
+        capsule_type: CAPSULE_TYPE,
+        payload: b"capsule".to_vec(),
+    };
+    assert!(client.events().any(|e| e == capsule));
+}
+
+#[test]
+fn connect_udp_not_supported_by_server() {
+    let (mut client, _server) = connect(
+        datagram_parameters().http3_datagram(true),
+        datagram_parameters().http3_datagram(true),
+    );
+    assert_eq!(
+        client.connect_udp(now(), PROXY, TARGET_HOST, TARGET_PORT),
+        Err(Error::Unavailable)
+    );
+}
+
+#[test]
+fn connect_udp_without_datagrams() {
+    let (mut client, _server) = connect(
+        datagram_parameters(),
+        datagram_parameters().connect_udp(true),
+    );
+    assert_eq!(
+        client.connect_udp(now(), PROXY, TARGET_HOST, TARGET_PORT),
+        Err(Error::Unavailable)
+    );
+}
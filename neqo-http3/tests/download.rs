@@ -0,0 +1,119 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp::min;
+
+use neqo_common::{event::Provider, Header};
+use neqo_http3::{
+    Http3Client, Http3ClientEvent, Http3Parameters, Http3Server, Http3ServerEvent, Priority,
+};
+use neqo_transport::{ConnectionParameters, StreamType};
+use test_fixture::{
+    default_http3_server, http3_client_with_params, http3_connect, http3_exchange_packets_paced,
+    now,
+};
+
+/// The flow control window that the client gives each response.
+const WINDOW: u64 = 64 * 1024;
+/// How much the client reads each time it looks at the response.
+const READ_SIZE: usize = 16 * 1024;
+
+fn body_byte(offset: usize) -> u8 {
+    u8::try_from(offset % 251).unwrap()
+}
+
+fn connect() -> (Http3Client, Http3Server) {
+    let mut client = http3_client_with_params(Http3Parameters::default().connection_parameters(
+        ConnectionParameters::default().max_stream_data(StreamType::BiDi, false, WINDOW),
+    ));
+    let mut server = default_http3_server();
+    http3_connect(&mut client, &mut server);
+    (client, server)
+}
+
+#[test]
+fn download_driven_by_data_writable() {
+    const BODY_LEN: usize = 50 * 1024 * 1024;
+    let mut t = now();
+    let (mut client, mut server) = connect();
+
+    let stream_id = client
+        .fetch(
+            t,
+            "GET",
+            &("https", "something.com", "/large"),
+            &[],
+            Priority::default(),
+        )
+        .unwrap();
+    client.stream_close_send(stream_id).unwrap();
+    http3_exchange_packets_paced(&mut client, &mut server, &mut t);
+
+    // Answer the request, keeping only the connection and the stream ID.
+    let (conn, request_id) = server
+        .events()
+        .find_map(|e| match e {
+            Http3ServerEvent::Headers { mut stream, .. } => {
+                stream
+                    .send_headers(&[
+                        Header::new(":status", "200"),
+                        Header::new("content-length", BODY_LEN.to_string()),
+                    ])
+                    .unwrap();
+                Some((stream.conn.clone(), stream.stream_id()))
+            }
+            _ => None,
+        })
+        .unwrap();
+
+    let (mut sent, mut received) = (0, 0);
+    let mut readable = false;
+    let mut fin = false;
+    let mut rounds = 0;
+    while !fin {
+        // The server only writes when it is told that there is room.
+        let writable = server.events().any(|e| {
+            matches!(e, Http3ServerEvent::DataWritable { stream } if stream.stream_id() == request_id)
+        });
+        if writable && sent < BODY_LEN {
+            let mut stream = server.stream(&conn, request_id).unwrap();
+            let avail = stream.send_data_avail().unwrap();
+            assert!(avail > 0);
+            let chunk = (sent..min(sent + avail, BODY_LEN))
+                .map(body_byte)
+                .collect::<Vec<_>>();
+            sent += stream.send_data(&chunk).unwrap();
+            if sent == BODY_LEN {
+                stream.stream_close_send().unwrap();
+            }
+        }
+
+        http3_exchange_packets_paced(&mut client, &mut server, &mut t);
+
+        // The client is slow and reads only a little at a time.
+        readable |= client.events().any(
+            |e| matches!(e, Http3ClientEvent::DataReadable { stream_id: id } if id == stream_id),
+        );
+        if readable {
+            let mut buf = [0; READ_SIZE];
+            let (amount, f) = client.read_data(t, stream_id, &mut buf).unwrap();
+            assert!(buf[..amount]
+                .iter()
+                .enumerate()
+                .all(|(i, &b)| b == body_byte(received + i)));
+            received += amount;
+            fin = f;
+            readable = amount == READ_SIZE;
+        }
+
+        // The server never holds more than the client is willing to receive.
+        assert!(sent - received <= usize::try_from(WINDOW).unwrap());
+        rounds += 1;
+        assert!(rounds < BODY_LEN / 1000, "download does not progress");
+    }
+    assert_eq!(sent, BODY_LEN);
+    assert_eq!(received, BODY_LEN);
+}
@@ -0,0 +1,169 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use neqo_common::{event::Provider, Header};
+use neqo_http3::{
+    Error, Http3Client, Http3ClientEvent, Http3OrWebTransportStream, Http3Parameters, Http3Server,
+    Http3ServerEvent,
+};
+use neqo_transport::StreamId;
+use test_fixture::{
+    default_http3_client, http3_connect, http3_exchange_packets, http3_server_with_params, now,
+};
+
+const ECHO: &str = "echo";
+const TARGET: &(&str, &str, &str) = &("https", "something.com", "/echo");
+
+fn connect(server_params: Http3Parameters) -> (Http3Client, Http3Server) {
+    let mut client = default_http3_client();
+    let mut server = http3_server_with_params(server_params);
+    http3_connect(&mut client, &mut server);
+    (client, server)
+}
+
+/// Wait for the Extended CONNECT request on the server.
+fn receive_connect(server: &mut Http3Server) -> Http3OrWebTransportStream {
+    while let Some(event) = server.next_event() {
+        if let Http3ServerEvent::Headers {
+            stream,
+            headers,
+            fin,
+        } = event
+        {
+            assert!(headers
+                .iter()
+                .any(|h| h.name() == ":method" && h.value() == "CONNECT"));
+            assert!(headers
+                .iter()
+                .any(|h| h.name() == ":protocol" && h.value() == ECHO));
+            assert!(!fin);
+            return stream;
+        }
+    }
+    panic!("no Extended CONNECT request");
+}
+
+/// Read the response status on the client.
+fn receive_status(client: &mut Http3Client, stream_id: StreamId) -> String {
+    while let Some(event) = client.next_event() {
+        if let Http3ClientEvent::HeaderReady {
+            stream_id: id,
+            headers,
+            ..
+        } = event
+        {
+            assert_eq!(id, stream_id);
+            return headers
+                .iter()
+                .find(|h| h.name() == ":status")
+                .unwrap()
+                .value()
+                .to_string();
+        }
+    }
+    panic!("no response");
+}
+
+/// Echo whatever the server received on `stream` back to the client.
+fn echo_on_server(server: &mut Http3Server, stream: &mut Http3OrWebTransportStream) -> bool {
+    let mut done = false;
+    while let Some(event) = server.next_event() {
+        if let Http3ServerEvent::Data { data, fin, .. } = event {
+            if !data.is_empty() {
+                stream.send_data(&data).unwrap();
+            }
+            if fin {
+                stream.stream_close_send().unwrap();
+                done = true;
+            }
+        }
+    }
+    done
+}
+
+fn read_on_client(client: &mut Http3Client, stream_id: StreamId) -> (Vec<u8>, bool) {
+    let mut received = Vec::new();
+    let mut fin = false;
+    while let Some(event) = client.next_event() {
+        if let Http3ClientEvent::DataReadable { stream_id: id } = event {
+            assert_eq!(id, stream_id);
+            let mut buf = [0; 100];
+            let (amount, f) = client.read_data(now(), stream_id, &mut buf).unwrap();
+            received.extend_from_slice(&buf[..amount]);
+            fin |= f;
+        }
+    }
+    (received, fin)
+}
+
+#[test]
+fn extended_connect_echo() {
+    let (mut client, mut server) =
+        connect(Http3Parameters::default().extended_connect_protocols(&[ECHO]));
+
+    let stream_id = client.extended_connect(now(), TARGET, ECHO, &[]).unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+
+    let mut stream = receive_connect(&mut server);
+    stream
+        .send_headers(&[Header::new(":status", "200")])
+        .unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(receive_status(&mut client, stream_id), "200");
+
+    // The stream stays open in both directions after the response.
+    for msg in [&b"ping"[..], &b"pong pong"[..]] {
+        assert_eq!(client.send_data(stream_id, msg).unwrap(), msg.len());
+        http3_exchange_packets(&mut client, &mut server);
+        assert!(!echo_on_server(&mut server, &mut stream));
+        http3_exchange_packets(&mut client, &mut server);
+        assert_eq!(
+            read_on_client(&mut client, stream_id),
+            (msg.to_vec(), false)
+        );
+    }
+
+    client.stream_close_send(stream_id).unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert!(echo_on_server(&mut server, &mut stream));
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(read_on_client(&mut client, stream_id), (Vec::new(), true));
+}
+
+#[test]
+fn extended_connect_unregistered_protocol() {
+    let (mut client, mut server) =
+        connect(Http3Parameters::default().extended_connect_protocols(&[ECHO]));
+
+    let stream_id = client
+        .extended_connect(now(), TARGET, "websocket", &[])
+        .unwrap();
+    http3_exchange_packets(&mut client, &mut server);
+    assert!(!server
+        .events()
+        .any(|e| matches!(e, Http3ServerEvent::Headers { .. })));
+    http3_exchange_packets(&mut client, &mut server);
+    assert_eq!(receive_status(&mut client, stream_id), "501");
+}
+
+#[test]
+fn extended_connect_not_enabled() {
+    let (mut client, _server) = connect(Http3Parameters::default());
+    assert_eq!(
+        client.extended_connect(now(), TARGET, ECHO, &[]),
+        Err(Error::Unavailable)
+    );
+}
+
+#[test]
+fn extended_connect_webtransport() {
+    let (mut client, _server) =
+        connect(Http3Parameters::default().extended_connect_protocols(&[ECHO]));
+    assert_eq!(
+        client.extended_connect(now(), TARGET, "webtransport", &[]),
+        Err(Error::InvalidInput)
+    );
+}
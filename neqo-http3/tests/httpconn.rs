@@ -17,10 +17,12 @@ use neqo_http3::{
     Header, Http3Client, Http3ClientEvent, Http3OrWebTransportStream, Http3Parameters, Http3Server,
     Http3ServerEvent, Http3State, Priority,
 };
-use neqo_transport::{CloseReason, ConnectionParameters, Error, Output, StreamType};
+use neqo_transport::{CloseReason, ConnectionParameters, Error, Output, StreamId, StreamType};
 use test_fixture::*;
 
 const RESPONSE_DATA: &[u8] = &[0x61, 0x62, 0x63];
+const PUSH_DATA: &[u8] = &[0x64, 0x65, 0x66];
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
 
 fn receive_request(server: &mut Http3Server) -> Option<Http3OrWebTransportStream> {
     while let Some(event) = server.next_event() {
@@ -223,22 +225,38 @@ fn test_103_response() {
     mem::drop(hconn_c.process(out.as_dgram_ref(), now()));
     let mut request = receive_request(&mut hconn_s).unwrap();
 
-    let info_headers = [
-        Header::new(":status", "103"),
-        Header::new("link", "</style.css>; rel=preload; as=style"),
-    ];
-    // Send 103
-    request.send_headers(&info_headers).unwrap();
+    let link = Header::new("link", "</style.css>; rel=preload; as=style");
+    // Send 100 and 103.
+    request.send_interim_response(100, &[]).unwrap();
+    request.send_interim_response(103, &[link.clone()]).unwrap();
+    // The response body can only follow the final response.
+    assert_eq!(
+        request.send_data(RESPONSE_DATA),
+        Err(neqo_http3::Error::InvalidInput)
+    );
     let out = hconn_s.process(None, now());
 
     mem::drop(hconn_c.process(out.as_dgram_ref(), now()));
 
-    let info_headers_event = |e| {
-        matches!(e, Http3ClientEvent::HeaderReady { headers,
-                    interim,
-                    fin, .. } if !fin && interim && headers.as_ref() == info_headers)
-    };
-    assert!(hconn_c.events().any(info_headers_event));
+    let interim: Vec<_> = hconn_c
+        .events()
+        .filter_map(|e| match e {
+            Http3ClientEvent::InterimResponse {
+                stream_id,
+                status,
+                headers,
+            } => Some((stream_id, status, headers)),
+            Http3ClientEvent::HeaderReady { .. } => panic!("unexpected final response"),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        interim,
+        [
+            (req, 100, vec![Header::new(":status", "100")]),
+            (req, 103, vec![Header::new(":status", "103"), link]),
+        ]
+    );
 
     set_response(&mut request);
     let out = hconn_s.process(None, now());
@@ -246,6 +264,257 @@ fn test_103_response() {
     process_client_events(&mut hconn_c);
 }
 
+#[test]
+fn interim_response_status() {
+    let (mut hconn_c, mut hconn_s, dgram) = connect();
+    let req = fetch(&mut hconn_c).unwrap();
+    hconn_c.stream_close_send(req).unwrap();
+    exchange_packets(&mut hconn_c, &mut hconn_s, dgram);
+    let mut request = receive_request(&mut hconn_s).unwrap();
+
+    for status in [99, 200, 404] {
+        assert_eq!(
+            request.send_interim_response(status, &[]),
+            Err(neqo_http3::Error::InvalidInput)
+        );
+    }
+    // HTTP/3 does not allow 101.
+    assert_eq!(
+        request.send_interim_response(101, &[]),
+        Err(neqo_http3::Error::InvalidHeader)
+    );
+    assert_eq!(
+        request.send_interim_response(103, &[Header::new(":status", "200")]),
+        Err(neqo_http3::Error::InvalidHeader)
+    );
+
+    // No interim response after the final response.
+    set_response(&mut request);
+    assert!(request.send_interim_response(103, &[]).is_err());
+    exchange_packets(&mut hconn_c, &mut hconn_s, None);
+    process_client_events(&mut hconn_c);
+}
+
+fn push_request_headers() -> Vec<Header> {
+    vec![
+        Header::new(":method", "GET"),
+        Header::new(":scheme", "https"),
+        Header::new(":authority", "something.com"),
+        Header::new(":path", "/style.css"),
+    ]
+}
+
+/// Send a request from the client and return the server side of it.
+fn start_request(
+    hconn_c: &mut Http3Client,
+    hconn_s: &mut Http3Server,
+    dgram: Option<Datagram>,
+) -> Http3OrWebTransportStream {
+    let req = hconn_c
+        .fetch(
+            now(),
+            "GET",
+            &("https", "something.com", "/"),
+            &[],
+            Priority::default(),
+        )
+        .unwrap();
+    hconn_c.stream_close_send(req).unwrap();
+    let out = hconn_c.process(dgram.as_ref(), now());
+    let out = hconn_s.process(out.as_dgram_ref(), now());
+    mem::drop(hconn_c.process(out.as_dgram_ref(), now()));
+    receive_request(hconn_s).unwrap()
+}
+
+#[test]
+fn server_push() {
+    let (mut hconn_c, mut hconn_s, dgram) = connect();
+    let mut request = start_request(&mut hconn_c, &mut hconn_s, dgram);
+
+    let push_id = request.push_promise(&push_request_headers()).unwrap();
+    set_response(&mut request);
+    request
+        .push_headers(
+            push_id,
+            &[
+                Header::new(":status", "200"),
+                Header::new("content-length", "3"),
+            ],
+        )
+        .unwrap();
+    assert_eq!(
+        request.push_data(push_id, PUSH_DATA).unwrap(),
+        PUSH_DATA.len()
+    );
+    request.push_close(push_id).unwrap();
+    exchange_packets(&mut hconn_c, &mut hconn_s, None);
+
+    let mut promise_found = false;
+    let mut push_data_found = false;
+    while let Some(event) = hconn_c.next_event() {
+        match event {
+            Http3ClientEvent::PushPromise {
+                push_id: id,
+                request_stream_id,
+                headers,
+            } => {
+                assert_eq!(id, push_id);
+                assert_eq!(request_stream_id, request.stream_id());
+                assert_eq!(headers, push_request_headers());
+                promise_found = true;
+            }
+            Http3ClientEvent::PushDataReadable { push_id: id } => {
+                assert_eq!(id, push_id);
+                let mut buf = [0u8; 100];
+                let (amount, fin) = hconn_c.push_read_data(now(), id, &mut buf).unwrap();
+                assert!(fin);
+                assert_eq!(&buf[..amount], PUSH_DATA);
+                push_data_found = true;
+            }
+            _ => {}
+        }
+    }
+    assert!(promise_found);
+    assert!(push_data_found);
+}
+
+#[test]
+fn server_push_without_max_push_id() {
+    // This client never sends MAX_PUSH_ID.
+    let mut hconn_c =
+        http3_client_with_params(Http3Parameters::default().max_concurrent_push_streams(0));
+    let mut hconn_s = default_http3_server();
+    let dgram = connect_peers(&mut hconn_c, &mut hconn_s);
+    let mut request = start_request(&mut hconn_c, &mut hconn_s, dgram);
+
+    assert_eq!(
+        request.push_promise(&push_request_headers()),
+        Err(neqo_http3::Error::Unavailable)
+    );
+
+    // The request is unaffected.
+    set_response(&mut request);
+    exchange_packets(&mut hconn_c, &mut hconn_s, None);
+    process_client_events(&mut hconn_c);
+}
+
+#[test]
+fn server_push_canceled() {
+    let (mut hconn_c, mut hconn_s, dgram) = connect();
+    let mut request = start_request(&mut hconn_c, &mut hconn_s, dgram);
+
+    let push_id = request.push_promise(&push_request_headers()).unwrap();
+    request
+        .push_headers(push_id, &[Header::new(":status", "200")])
+        .unwrap();
+    exchange_packets(&mut hconn_c, &mut hconn_s, None);
+
+    hconn_c.cancel_push(push_id).unwrap();
+    exchange_packets(&mut hconn_c, &mut hconn_s, None);
+    assert_eq!(
+        request.push_data(push_id, PUSH_DATA),
+        Err(neqo_http3::Error::InvalidStreamId)
+    );
+}
+
+fn fetch(hconn_c: &mut Http3Client) -> Result<StreamId, neqo_http3::Error> {
+    hconn_c.fetch(
+        now(),
+        "GET",
+        &("https", "something.com", "/"),
+        &[],
+        Priority::default(),
+    )
+}
+
+/// Run the server until the client receives the final `GOAWAY` frame.
+fn graceful_shutdown(hconn_c: &mut Http3Client, hconn_s: &mut Http3Server) -> Instant {
+    hconn_s.graceful_shutdown(now());
+    let mut now = now();
+    let mut goaways = Vec::new();
+    while goaways.len() < 2 {
+        match hconn_s.process(None, now) {
+            Output::Datagram(d) => mem::drop(hconn_c.process(Some(&d), now)),
+            Output::Callback(t) => now += t,
+            Output::None => panic!("the server should send GOAWAY"),
+        }
+        goaways.extend(hconn_c.events().filter_map(|e| match e {
+            Http3ClientEvent::GoawayReceived { stream_id } => Some(stream_id),
+            _ => None,
+        }));
+    }
+    // Both requests are allowed to complete.
+    assert_eq!(goaways, [StreamId::new((1 << 62) - 4), StreamId::new(8)]);
+    now
+}
+
+fn exchange_packets_at(
+    client: &mut Http3Client,
+    server: &mut Http3Server,
+    out_ex: Option<Datagram>,
+    now: Instant,
+) {
+    let mut out = out_ex;
+    loop {
+        out = client.process(out.as_ref(), now).dgram();
+        out = server.process(out.as_ref(), now).dgram();
+        if out.is_none() {
+            break;
+        }
+    }
+}
+
+#[test]
+fn graceful_shutdown_timeout() {
+    let (mut hconn_c, mut hconn_s, dgram) = connect();
+    hconn_s.set_drain_timeout(DRAIN_TIMEOUT);
+    let mut first = start_request(&mut hconn_c, &mut hconn_s, dgram);
+    let _second = start_request(&mut hconn_c, &mut hconn_s, None);
+
+    // This request reaches the server only after the final GOAWAY.
+    let late = fetch(&mut hconn_c).unwrap();
+    hconn_c.stream_close_send(late).unwrap();
+    let late_dgram = hconn_c.process(None, now()).dgram();
+    assert!(late_dgram.is_some());
+
+    let now = graceful_shutdown(&mut hconn_c, &mut hconn_s);
+    assert_eq!(fetch(&mut hconn_c), Err(neqo_http3::Error::AlreadyClosed));
+
+    // The server rejects the late request instead of handing it to the application.
+    mem::drop(hconn_s.process(late_dgram.as_ref(), now));
+    assert!(receive_request(&mut hconn_s).is_none());
+
+    set_response(&mut first);
+    exchange_packets_at(&mut hconn_c, &mut hconn_s, None, now);
+    let drained = |e| matches!(e, Http3ServerEvent::ConnectionDrained { .. });
+    assert!(!hconn_s.events().any(drained));
+
+    // The remaining request is cut short by the drain timeout.
+    let out = hconn_s.process(None, now + DRAIN_TIMEOUT);
+    mem::drop(hconn_c.process(out.as_dgram_ref(), now + DRAIN_TIMEOUT));
+    assert!(hconn_c.events().any(|e| matches!(
+        e,
+        Http3ClientEvent::StateChange(Http3State::Closing(CloseReason::Application(0x100)))
+    )));
+}
+
+#[test]
+fn graceful_shutdown_drained() {
+    let (mut hconn_c, mut hconn_s, dgram) = connect();
+    let mut first = start_request(&mut hconn_c, &mut hconn_s, dgram);
+    let mut second = start_request(&mut hconn_c, &mut hconn_s, None);
+
+    let now = graceful_shutdown(&mut hconn_c, &mut hconn_s);
+    set_response(&mut first);
+    set_response(&mut second);
+    exchange_packets_at(&mut hconn_c, &mut hconn_s, None, now);
+
+    assert!(hconn_s
+        .events()
+        .any(|e| matches!(e, Http3ServerEvent::ConnectionDrained { .. })));
+    assert_eq!(hconn_c.state(), Http3State::GoingAway(StreamId::new(8)));
+}
+
 /// Test [`neqo_http3::SendMessage::send_data`] to set
 /// [`neqo_transport::SendStream::set_writable_event_low_watermark`].
 #[allow(clippy::cast_possible_truncation)]
@@ -536,3 +805,128 @@ fn fetch_noresponse_will_idletimeout() {
         }
     }
 }
+
+#[test]
+fn trailers() {
+    const REQUEST_DATA: &[u8] = &[0x67, 0x68, 0x69];
+    let request_trailers = [Header::new("x-request-checksum", "1234")];
+    let response_trailers = [Header::new("grpc-status", "0")];
+
+    let (mut hconn_c, mut hconn_s, dgram) = connect();
+    let req = hconn_c
+        .fetch(
+            now(),
+            "POST",
+            &("https", "something.com", "/"),
+            &[],
+            Priority::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        hconn_c.send_data(req, REQUEST_DATA).unwrap(),
+        REQUEST_DATA.len()
+    );
+    hconn_c.send_trailers(req, &request_trailers).unwrap();
+    // No more data or trailers can follow the trailers.
+    assert_eq!(
+        hconn_c.send_data(req, REQUEST_DATA),
+        Err(neqo_http3::Error::InvalidInput)
+    );
+    assert_eq!(
+        hconn_c.send_trailers(req, &request_trailers),
+        Err(neqo_http3::Error::InvalidInput)
+    );
+    hconn_c.stream_close_send(req).unwrap();
+    exchange_packets(&mut hconn_c, &mut hconn_s, dgram);
+
+    let mut order = Vec::new();
+    let mut request = None;
+    while let Some(event) = hconn_s.next_event() {
+        match event {
+            Http3ServerEvent::Headers { stream, fin, .. } => {
+                assert!(!fin);
+                request = Some(stream);
+                order.push("headers");
+            }
+            Http3ServerEvent::Data { data, fin, .. } => {
+                if !data.is_empty() {
+                    assert_eq!(data, REQUEST_DATA);
+                    order.push("data");
+                }
+                if fin {
+                    order.push("fin");
+                }
+            }
+            Http3ServerEvent::Trailers { headers, .. } => {
+                assert_eq!(headers, request_trailers);
+                order.push("trailers");
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(order, ["headers", "data", "trailers", "fin"]);
+
+    let mut request = request.unwrap();
+    // Trailers cannot be sent before the response headers.
+    assert_eq!(
+        request.send_trailers(&response_trailers),
+        Err(neqo_http3::Error::InvalidInput)
+    );
+    request
+        .send_headers(&[Header::new(":status", "200")])
+        .unwrap();
+    request.send_data(RESPONSE_DATA).unwrap();
+    request.send_trailers(&response_trailers).unwrap();
+    request.stream_close_send().unwrap();
+    exchange_packets(&mut hconn_c, &mut hconn_s, None);
+
+    // The client learns about the end of the response from `read_data`, the trailers
+    // are reported after all the data.
+    let mut order = Vec::new();
+    let mut fin_read = false;
+    while let Some(event) = hconn_c.next_event() {
+        match event {
+            Http3ClientEvent::HeaderReady { stream_id, fin, .. } => {
+                assert_eq!(stream_id, req);
+                assert!(!fin);
+                order.push("headers");
+            }
+            Http3ClientEvent::DataReadable { stream_id } => {
+                let mut buf = [0; 100];
+                let (amount, fin) = hconn_c.read_data(now(), stream_id, &mut buf).unwrap();
+                if amount > 0 {
+                    assert_eq!(&buf[..amount], RESPONSE_DATA);
+                    order.push("data");
+                }
+                fin_read |= fin;
+            }
+            Http3ClientEvent::Trailers { stream_id, headers } => {
+                assert_eq!(stream_id, req);
+                assert_eq!(headers, response_trailers);
+                order.push("trailers");
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(order, ["headers", "data", "trailers"]);
+    assert!(fin_read);
+}
+
+#[test]
+fn trailers_with_pseudo_header() {
+    let (mut hconn_c, mut hconn_s, dgram) = connect();
+    let req = fetch(&mut hconn_c).unwrap();
+    hconn_c.stream_close_send(req).unwrap();
+    exchange_packets(&mut hconn_c, &mut hconn_s, dgram);
+
+    let mut request = receive_request(&mut hconn_s).unwrap();
+    request
+        .send_headers(&[Header::new(":status", "200")])
+        .unwrap();
+    request.send_data(RESPONSE_DATA).unwrap();
+    // Pseudo-header fields are not allowed in trailers.
+    assert_eq!(
+        request.send_trailers(&[Header::new(":status", "200")]),
+        Err(neqo_http3::Error::InvalidHeader)
+    );
+}
@@ -4,15 +4,18 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::time::Instant;
+use std::{cmp::min, collections::HashMap, time::Instant};
 
 use neqo_common::event::Provider;
 use neqo_crypto::AuthenticationStatus;
 use neqo_http3::{
     Header, Http3Client, Http3ClientEvent, Http3Server, Http3ServerEvent, Http3State, Priority,
 };
+use neqo_transport::StreamId;
 use test_fixture::*;
 
+const RESPONSE_SIZE: usize = 100_000;
+
 fn exchange_packets(client: &mut Http3Client, server: &mut Http3Server) {
     let mut out = None;
     loop {
@@ -146,3 +149,148 @@ fn priority_update_dont_send_for_cancelled_stream() {
         }
     }
 }
+
+/// The progress of responses on the client.
+#[derive(Default)]
+struct Responses {
+    received: HashMap<StreamId, usize>,
+    complete: Vec<StreamId>,
+}
+
+impl Responses {
+    fn read(&mut self, client: &mut Http3Client, now: Instant) {
+        while let Some(event) = client.next_event() {
+            if let Http3ClientEvent::DataReadable { stream_id } = event {
+                let mut buf = [0; 4096];
+                loop {
+                    let (amount, fin) = client.read_data(now, stream_id, &mut buf).unwrap();
+                    *self.received.entry(stream_id).or_default() += amount;
+                    if fin {
+                        self.complete.push(stream_id);
+                        break;
+                    }
+                    if amount == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Exchange packets, letting time pass when neither side has anything to send,
+/// until `done` is satisfied.  This paces the responses on a link that is limited
+/// by congestion control.
+fn transfer(
+    client: &mut Http3Client,
+    server: &mut Http3Server,
+    now: &mut Instant,
+    responses: &mut Responses,
+    done: impl Fn(&Responses) -> bool,
+) {
+    let mut dgram = None;
+    for _ in 0..100_000 {
+        responses.read(client, *now);
+        if done(responses) {
+            return;
+        }
+        let out = client.process(dgram.as_ref(), *now);
+        let client_wait = out.callback();
+        dgram = out.dgram();
+        let out = server.process(dgram.as_ref(), *now);
+        let server_wait = out.callback();
+        dgram = out.dgram();
+        if dgram.is_none() {
+            *now += min(client_wait, server_wait);
+        }
+    }
+    panic!("the transfer did not finish");
+}
+
+/// Send two requests with the given priorities, and have the server start large
+/// responses to both in the order that the requests were made.
+fn start_responses(
+    client: &mut Http3Client,
+    server: &mut Http3Server,
+    priorities: [Priority; 2],
+) -> [StreamId; 2] {
+    let ids = priorities.map(|priority| {
+        let id = client
+            .fetch(
+                now(),
+                "GET",
+                &("https", "something.com", "/"),
+                &[],
+                priority,
+            )
+            .unwrap();
+        client.stream_close_send(id).unwrap();
+        id
+    });
+    exchange_packets(client, server);
+
+    let mut requests = Vec::new();
+    while let Some(event) = server.next_event() {
+        if let Http3ServerEvent::Headers { stream, .. } = event {
+            requests.push(stream);
+        }
+    }
+    assert_eq!(requests.len(), 2);
+    for (request, priority) in requests.iter_mut().zip(priorities) {
+        assert_eq!(request.priority(), priority);
+        request
+            .send_headers(&[Header::new(":status", "200")])
+            .unwrap();
+        assert_eq!(
+            request.send_data(&[0; RESPONSE_SIZE]).unwrap(),
+            RESPONSE_SIZE
+        );
+        request.stream_close_send().unwrap();
+    }
+    ids
+}
+
+#[test]
+fn responses_in_urgency_order() {
+    let (mut client, mut server) = connect();
+    let [low, high] = start_responses(
+        &mut client,
+        &mut server,
+        [Priority::new(6, false), Priority::new(1, false)],
+    );
+
+    let mut now = now();
+    let mut responses = Responses::default();
+    transfer(&mut client, &mut server, &mut now, &mut responses, |r| {
+        r.complete.len() == 2
+    });
+    assert_eq!(responses.complete, [high, low]);
+    assert_eq!(responses.received[&high], RESPONSE_SIZE);
+    assert_eq!(responses.received[&low], RESPONSE_SIZE);
+}
+
+#[test]
+fn priority_update_reorders_responses() {
+    let (mut client, mut server) = connect();
+    let [first, second] = start_responses(
+        &mut client,
+        &mut server,
+        [Priority::new(1, false), Priority::new(6, false)],
+    );
+
+    let mut now = now();
+    let mut responses = Responses::default();
+    transfer(&mut client, &mut server, &mut now, &mut responses, |r| {
+        r.received
+            .get(&first)
+            .is_some_and(|&n| n > RESPONSE_SIZE / 10)
+    });
+    assert!(responses.complete.is_empty());
+
+    let update = Priority::new(0, false);
+    assert!(client.priority_update(second, update).unwrap());
+    transfer(&mut client, &mut server, &mut now, &mut responses, |r| {
+        r.complete.len() == 2
+    });
+    assert_eq!(responses.complete, [second, first]);
+}
@@ -0,0 +1,164 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use neqo_common::{event::Provider, Header};
+use neqo_http3::{Http3Client, Http3Parameters, Http3Server, Http3ServerEvent, Priority};
+use serde_json::Value;
+use test_fixture::{
+    default_http3_server, http3_client_with_params, http3_connect, http3_exchange_packets,
+    new_neqo_qlog, now, SharedVec,
+};
+
+const SECRET: &str = "Bearer not-for-the-log";
+
+/// Connect a client that logs HTTP/3 frames, with `params`.
+fn connect(params: Http3Parameters) -> (Http3Client, Http3Server, SharedVec) {
+    let mut client = http3_client_with_params(params.qlog_http_frames(true));
+    let (log, contents) = new_neqo_qlog();
+    client.set_qlog(log);
+    let mut server = default_http3_server();
+    http3_connect(&mut client, &mut server);
+    (client, server, contents)
+}
+
+/// Send a POST request with a body and an `authorization` header, and respond to it.
+fn request(client: &mut Http3Client, server: &mut Http3Server) -> u64 {
+    let stream_id = client
+        .fetch(
+            now(),
+            "POST",
+            &("https", "something.com", "/"),
+            &[Header::new("authorization", SECRET)],
+            Priority::default(),
+        )
+        .unwrap();
+    client.send_data(stream_id, b"request body").unwrap();
+    client.stream_close_send(stream_id).unwrap();
+    http3_exchange_packets(client, server);
+
+    let mut stream = server
+        .events()
+        .find_map(|e| match e {
+            Http3ServerEvent::Headers { stream, .. } => Some(stream),
+            _ => None,
+        })
+        .unwrap();
+    stream
+        .send_headers(&[Header::new(":status", "200")])
+        .unwrap();
+    stream.send_data(b"response body").unwrap();
+    stream.stream_close_send().unwrap();
+    http3_exchange_packets(client, server);
+    stream_id.as_u64()
+}
+
+fn http_frame_events(contents: &SharedVec) -> Vec<Value> {
+    contents
+        .to_string()
+        .split_terminator('\n')
+        .skip(1) // The header.
+        .map(|r| serde_json::from_str::<Value>(r.strip_prefix('\u{1e}').unwrap()).unwrap())
+        .filter(|ev| ev["name"] == "http:frame_created" || ev["name"] == "http:frame_parsed")
+        .collect()
+}
+
+/// The name and frame type of the events for `stream_id`.
+fn frames_on(events: &[Value], stream_id: u64) -> Vec<(String, String)> {
+    events
+        .iter()
+        .filter(|ev| ev["data"]["stream_id"] == stream_id)
+        .map(|ev| {
+            (
+                ev["name"].as_str().unwrap().to_string(),
+                ev["data"]["frame"]["frame_type"]
+                    .as_str()
+                    .unwrap()
+                    .to_string(),
+            )
+        })
+        .collect()
+}
+
+/// The value of the `authorization` header in each HEADERS frame that has one.
+fn authorization(events: &[Value]) -> Vec<String> {
+    events
+        .iter()
+        .filter_map(|ev| ev["data"]["frame"]["headers"].as_array())
+        .flatten()
+        .filter(|h| h["name"] == "authorization")
+        .map(|h| h["value"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn qlog_http_frames() {
+    let (mut client, mut server, contents) = connect(Http3Parameters::default());
+    let stream_id = request(&mut client, &mut server);
+    let events = http_frame_events(&contents);
+
+    let created = |t: &str| ("http:frame_created".to_string(), t.to_string());
+    let parsed = |t: &str| ("http:frame_parsed".to_string(), t.to_string());
+    assert_eq!(
+        frames_on(&events, stream_id),
+        [
+            created("headers"),
+            created("data"),
+            parsed("headers"),
+            parsed("data"),
+        ]
+    );
+    let request_data = events
+        .iter()
+        .find(|ev| {
+            ev["name"] == "http:frame_created" && ev["data"]["frame"]["frame_type"] == "data"
+        })
+        .unwrap();
+    assert_eq!(request_data["data"]["length"], 12);
+
+    // Each side sends SETTINGS and a grease frame on its control stream.
+    for ev in [
+        created("settings"),
+        created("unknown"),
+        parsed("settings"),
+        parsed("unknown"),
+    ] {
+        assert!(events.iter().any(
+            |e| e["name"] == ev.0.as_str() && e["data"]["frame"]["frame_type"] == ev.1.as_str()
+        ));
+    }
+
+    // The request headers are logged, except for the value of `authorization`.
+    let request_headers = &events
+        .iter()
+        .find(|ev| ev["data"]["stream_id"] == stream_id)
+        .unwrap()["data"]["frame"]["headers"];
+    assert!(request_headers
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|h| h["name"] == ":method" && h["value"] == "POST"));
+    assert_eq!(authorization(&events), ["[redacted]"]);
+    assert!(!contents.to_string().contains(SECRET));
+}
+
+#[test]
+fn qlog_http_frames_no_redaction() {
+    let (mut client, mut server, contents) =
+        connect(Http3Parameters::default().qlog_redacted_headers(&[]));
+    request(&mut client, &mut server);
+    assert_eq!(authorization(&http_frame_events(&contents)), [SECRET]);
+}
+
+#[test]
+fn qlog_http_frames_disabled() {
+    let mut client = http3_client_with_params(Http3Parameters::default());
+    let (log, contents) = new_neqo_qlog();
+    client.set_qlog(log);
+    let mut server = default_http3_server();
+    http3_connect(&mut client, &mut server);
+    request(&mut client, &mut server);
+    assert!(http_frame_events(&contents).is_empty());
+}
@@ -0,0 +1,80 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use neqo_http3::{HSettingType, Http3Parameters};
+use test_fixture::{default_http3_client, http3_connect, http3_server_with_params};
+
+/// A reserved identifier, which peers are required to ignore.
+const GREASE: u64 = 0x1f * 7 + 0x21;
+const CUSTOM: u64 = 0x4e45;
+
+#[test]
+fn additional_settings() {
+    let mut client = default_http3_client();
+    let mut server = http3_server_with_params(
+        Http3Parameters::default()
+            .additional_setting(GREASE, 0)
+            .additional_setting(CUSTOM, 42),
+    );
+    assert!(client.peer_settings().is_empty());
+    assert_eq!(client.peer_setting(HSettingType::Unknown(CUSTOM)), None);
+
+    http3_connect(&mut client, &mut server);
+    let settings = client.peer_settings();
+    assert!(settings.contains(&(GREASE, 0)));
+    assert!(settings.contains(&(CUSTOM, 42)));
+    assert_eq!(client.peer_setting(HSettingType::Unknown(CUSTOM)), Some(42));
+    assert_eq!(
+        client.peer_setting(HSettingType::Unknown(CUSTOM + 1)),
+        Some(0)
+    );
+
+    // The settings that neqo understands are not affected.
+    let defaults = Http3Parameters::default();
+    assert_eq!(
+        client.peer_setting(HSettingType::MaxTableCapacity),
+        Some(defaults.get_max_table_size_decoder())
+    );
+    assert_eq!(
+        client.peer_setting(HSettingType::BlockedStreams),
+        Some(u64::from(defaults.get_max_blocked_streams()))
+    );
+    assert_eq!(
+        client.peer_setting(HSettingType::EnableConnectProtocol),
+        Some(0)
+    );
+    assert!(!client.webtransport_enabled());
+}
+
+#[test]
+fn known_settings_only() {
+    let mut client = default_http3_client();
+    let mut server =
+        http3_server_with_params(Http3Parameters::default().extended_connect_protocols(&["echo"]));
+    http3_connect(&mut client, &mut server);
+    assert!(client
+        .peer_settings()
+        .iter()
+        .all(|&(id, _)| id != GREASE && id != CUSTOM));
+    assert_eq!(
+        client.peer_setting(HSettingType::EnableConnectProtocol),
+        Some(1)
+    );
+}
+
+#[test]
+#[should_panic(expected = "is_additional_setting_allowed")]
+fn additional_setting_reserved() {
+    // 0x2 is SETTINGS_ENABLE_PUSH from HTTP/2.
+    let _params = Http3Parameters::default().additional_setting(0x2, 0);
+}
+
+#[test]
+#[should_panic(expected = "is_additional_setting_allowed")]
+fn additional_setting_known() {
+    // 0x6 is SETTINGS_MAX_FIELD_SECTION_SIZE, which is managed by neqo.
+    let _params = Http3Parameters::default().additional_setting(0x6, 100);
+}
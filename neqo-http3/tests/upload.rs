@@ -0,0 +1,141 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::time::Instant;
+
+use neqo_common::event::Provider;
+use neqo_http3::{
+    Http3Client, Http3ClientEvent, Http3OrWebTransportStream, Http3Parameters, Http3Server,
+    Http3ServerEvent, Priority,
+};
+use neqo_transport::{ConnectionParameters, StreamId, StreamType};
+use test_fixture::{
+    default_http3_client, http3_connect, http3_exchange_packets_paced, http3_server_with_params,
+    now,
+};
+
+/// The flow control window that the server gives each request stream.
+const WINDOW: u64 = 16 * 1024;
+
+fn connect() -> (Http3Client, Http3Server) {
+    let mut client = default_http3_client();
+    let mut server = http3_server_with_params(Http3Parameters::default().connection_parameters(
+        ConnectionParameters::default().max_stream_data(StreamType::BiDi, true, WINDOW),
+    ));
+    http3_connect(&mut client, &mut server);
+    (client, server)
+}
+
+/// Start a POST request and wait for it on the server.
+fn post(
+    client: &mut Http3Client,
+    server: &mut Http3Server,
+    t: &mut Instant,
+) -> (StreamId, Http3OrWebTransportStream) {
+    let stream_id = client
+        .fetch(
+            *t,
+            "POST",
+            &("https", "something.com", "/upload"),
+            &[],
+            Priority::default(),
+        )
+        .unwrap();
+    http3_exchange_packets_paced(client, server, t);
+    while let Some(event) = server.next_event() {
+        if let Http3ServerEvent::Headers { stream, fin, .. } = event {
+            assert!(!fin);
+            return (stream_id, stream);
+        }
+    }
+    panic!("no request");
+}
+
+fn data_writable(client: &mut Http3Client, stream_id: StreamId) -> bool {
+    let mut writable = false;
+    while let Some(event) = client.next_event() {
+        writable |=
+            matches!(event, Http3ClientEvent::DataWritable { stream_id: id } if id == stream_id);
+    }
+    writable
+}
+
+/// Collect the request body on the server.  Returns true once it is complete.
+fn receive_body(server: &mut Http3Server, body: &mut Vec<u8>) -> bool {
+    let mut done = false;
+    while let Some(event) = server.next_event() {
+        if let Http3ServerEvent::Data { data, fin, .. } = event {
+            assert!(!done);
+            body.extend_from_slice(&data);
+            done |= fin;
+        }
+    }
+    done
+}
+
+#[test]
+fn upload_driven_by_data_writable() {
+    const BODY_LEN: usize = 10 * 1024 * 1024;
+    let mut t = now();
+    let (mut client, mut server) = connect();
+    let (stream_id, _request) = post(&mut client, &mut server, &mut t);
+
+    let body = (0..BODY_LEN)
+        .map(|i| u8::try_from(i % 251).unwrap())
+        .collect::<Vec<_>>();
+    let mut sent = 0;
+    let mut writable_events = 0;
+    let mut received = Vec::new();
+    // Sending the request headers makes the stream writable.
+    let mut writable = data_writable(&mut client, stream_id);
+    loop {
+        // Only write when the client says that there is room.
+        if writable && sent < BODY_LEN {
+            writable_events += 1;
+            let avail = client.send_data_avail(stream_id).unwrap();
+            let n = client.send_data(stream_id, &body[sent..]).unwrap();
+            assert!(n > 0 && n <= avail);
+            sent += n;
+            if sent == BODY_LEN {
+                // The last write can be a partial one.
+                client.stream_close_send(stream_id).unwrap();
+            } else {
+                assert_eq!(client.send_data_avail(stream_id).unwrap(), 0);
+            }
+        }
+        http3_exchange_packets_paced(&mut client, &mut server, &mut t);
+        if receive_body(&mut server, &mut received) {
+            break;
+        }
+        writable = data_writable(&mut client, stream_id);
+        assert!(writable_events < BODY_LEN, "upload does not progress");
+    }
+    assert_eq!(sent, BODY_LEN);
+    assert!(received == body);
+
+    // The stream is only reported as writable when there is room for a good
+    // amount of data, not on every small increase of the window.
+    assert!(writable_events <= BODY_LEN / 4096);
+}
+
+#[test]
+fn close_after_partial_write() {
+    let mut t = now();
+    let (mut client, mut server) = connect();
+    let (stream_id, _request) = post(&mut client, &mut server, &mut t);
+
+    let body = vec![0x42; usize::try_from(WINDOW).unwrap() * 2];
+    let sent = client.send_data(stream_id, &body).unwrap();
+    assert!(sent > 0 && sent < body.len());
+    client.stream_close_send(stream_id).unwrap();
+
+    // What was accepted is delivered, followed by the end of the request.
+    let mut received = Vec::new();
+    http3_exchange_packets_paced(&mut client, &mut server, &mut t);
+    assert!(receive_body(&mut server, &mut received));
+    assert_eq!(received, &body[..sent]);
+    assert!(client.send_data(stream_id, &body[sent..]).is_err());
+}
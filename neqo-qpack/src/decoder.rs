@@ -31,6 +31,7 @@ pub struct QPackDecoder {
     max_table_size: u64,
     max_blocked_streams: usize,
     blocked_streams: Vec<(StreamId, u64)>, // stream_id and requested inserts count.
+    max_field_section_size: u64,
     stats: Stats,
 }
 
@@ -53,6 +54,7 @@ impl QPackDecoder {
             max_table_size: qpack_settings.max_table_size_decoder,
             max_blocked_streams: usize::from(qpack_settings.max_blocked_streams),
             blocked_streams: Vec::new(),
+            max_field_section_size: u64::MAX,
             stats: Stats::default(),
         }
     }
@@ -75,6 +77,12 @@ impl QPackDecoder {
         u16::try_from(self.max_blocked_streams).unwrap()
     }
 
+    /// Limit the size of header blocks that `decode_header_block` accepts, as defined
+    /// in RFC 9114, Section 4.2.2.  There is no limit by default.
+    pub fn set_max_field_section_size(&mut self, size: u64) {
+        self.max_field_section_size = size;
+    }
+
     /// returns a list of unblocked streams
     ///
     /// # Errors
@@ -204,7 +212,8 @@ impl QPackDecoder {
     ///
     /// # Errors
     ///
-    /// May return `DecompressionFailed` if header block is incorrect or incomplete.
+    /// May return `DecompressionFailed` if header block is incorrect or incomplete,
+    /// or `FieldSectionTooLarge` if it exceeds the limit on the size of field sections.
     ///
     /// # Panics
     ///
@@ -215,7 +224,8 @@ impl QPackDecoder {
         stream_id: StreamId,
     ) -> Res<Option<Vec<Header>>> {
         qdebug!([self], "decode header block.");
-        let mut decoder = HeaderDecoder::new(buf);
+        let mut decoder =
+            HeaderDecoder::new(buf).max_field_section_size(self.max_field_section_size);
 
         match decoder.decode_header_block(&self.table, self.max_entries, self.table.base()) {
             Ok(HeaderDecoderResult::Blocked(req_insert_cnt)) => {
@@ -243,6 +253,7 @@ impl QPackDecoder {
                 }
                 Ok(Some(h))
             }
+            Err(Error::FieldSectionTooLarge) => Err(Error::FieldSectionTooLarge),
             Err(_) => Err(Error::DecompressionFailed),
         }
     }
@@ -820,4 +831,31 @@ mod tests {
 
         decode_headers(&mut decoder, HEADER_BLOCK, &headers, STREAM_0);
     }
+
+    #[test]
+    fn max_field_section_size() {
+        const HEADER_BLOCK: &[u8] = &[
+            0x00, 0x01, 0xd1, 0x51, 0x0a, 0x2f, 0x73, 0x6f, 0x6d, 0x65, 0x77, 0x68, 0x65, 0x72,
+            0x65, 0x50, 0x0b, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x63, 0x6f, 0x6d,
+            0xd7,
+        ];
+        // Each field counts its name and value plus 32 bytes of overhead.
+        const SIZE: u64 = (7 + 3 + 32) + (5 + 10 + 32) + (10 + 11 + 32) + (7 + 5 + 32);
+
+        let mut decoder = connect();
+        decoder.decoder.set_max_field_section_size(SIZE);
+        let headers = vec![
+            Header::new(":method", "GET"),
+            Header::new(":path", "/somewhere"),
+            Header::new(":authority", "example.com"),
+            Header::new(":scheme", "https"),
+        ];
+        decode_headers(&mut decoder, HEADER_BLOCK, &headers, STREAM_0);
+
+        decoder.decoder.set_max_field_section_size(SIZE - 1);
+        assert_eq!(
+            decoder.decoder.decode_header_block(HEADER_BLOCK, STREAM_0),
+            Err(Error::FieldSectionTooLarge)
+        );
+    }
 }
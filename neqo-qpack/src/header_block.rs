@@ -176,6 +176,7 @@ pub(crate) struct HeaderDecoder<'a> {
     buf: ReceiverBufferWrapper<'a>,
     base: u64,
     req_insert_cnt: u64,
+    max_field_section_size: u64,
 }
 
 impl<'a> ::std::fmt::Display for HeaderDecoder<'a> {
@@ -196,9 +197,16 @@ impl<'a> HeaderDecoder<'a> {
             buf: ReceiverBufferWrapper::new(buf),
             base: 0,
             req_insert_cnt: 0,
+            max_field_section_size: u64::MAX,
         }
     }
 
+    /// Stop decoding with `FieldSectionTooLarge` once the decoded fields exceed `size`.
+    pub fn max_field_section_size(mut self, size: u64) -> Self {
+        self.max_field_section_size = size;
+        self
+    }
+
     pub fn refers_dynamic_table(
         &mut self,
         max_entries: u64,
@@ -231,47 +239,53 @@ impl<'a> HeaderDecoder<'a> {
             return Ok(HeaderDecoderResult::Blocked(self.req_insert_cnt));
         }
         let mut h: Vec<Header> = Vec::new();
+        let mut size = 0_u64;
 
         while !self.buf.done() {
             let b = Error::map_error(self.buf.peek(), Error::DecompressionFailed)?;
-            if HEADER_FIELD_INDEX_STATIC.cmp_prefix(b) {
-                h.push(Error::map_error(
-                    self.read_indexed_static(),
-                    Error::DecompressionFailed,
-                )?);
+            let header = if HEADER_FIELD_INDEX_STATIC.cmp_prefix(b) {
+                Error::map_error(self.read_indexed_static(), Error::DecompressionFailed)?
             } else if HEADER_FIELD_INDEX_DYNAMIC.cmp_prefix(b) {
-                h.push(Error::map_error(
-                    self.read_indexed_dynamic(table),
-                    Error::DecompressionFailed,
-                )?);
+                Error::map_error(self.read_indexed_dynamic(table), Error::DecompressionFailed)?
             } else if HEADER_FIELD_INDEX_DYNAMIC_POST.cmp_prefix(b) {
-                h.push(Error::map_error(
+                Error::map_error(
                     self.read_indexed_dynamic_post(table),
                     Error::DecompressionFailed,
-                )?);
+                )?
             } else if HEADER_FIELD_LITERAL_NAME_REF_STATIC.cmp_prefix(b) {
-                h.push(Error::map_error(
+                Error::map_error(
                     self.read_literal_with_name_ref_static(),
                     Error::DecompressionFailed,
-                )?);
+                )?
             } else if HEADER_FIELD_LITERAL_NAME_REF_DYNAMIC.cmp_prefix(b) {
-                h.push(Error::map_error(
+                Error::map_error(
                     self.read_literal_with_name_ref_dynamic(table),
                     Error::DecompressionFailed,
-                )?);
+                )?
             } else if HEADER_FIELD_LITERAL_NAME_LITERAL.cmp_prefix(b) {
-                h.push(Error::map_error(
+                Error::map_error(
                     self.read_literal_with_name_literal(),
                     Error::DecompressionFailed,
-                )?);
+                )?
             } else if HEADER_FIELD_LITERAL_NAME_REF_DYNAMIC_POST.cmp_prefix(b) {
-                h.push(Error::map_error(
+                Error::map_error(
                     self.read_literal_with_name_ref_dynamic_post(table),
                     Error::DecompressionFailed,
-                )?);
+                )?
             } else {
                 unreachable!("All prefixes are covered");
+            };
+            // The size of a field section is defined in RFC 9114, Section 4.2.2.
+            size += u64::try_from(header.name().len() + header.value().len()).unwrap() + 32;
+            if size > self.max_field_section_size {
+                qtrace!(
+                    [self],
+                    "field section exceeds {}",
+                    self.max_field_section_size
+                );
+                return Err(Error::FieldSectionTooLarge);
             }
+            h.push(header);
         }
 
         qtrace!([self], "done decoding header block.");
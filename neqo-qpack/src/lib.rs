@@ -57,6 +57,9 @@ pub enum Error {
     Decoding, // Decoding internal error that is not one of the above.
     EncoderStreamBlocked,
     Internal,
+    /// A header block decodes to more than the limit that was set with
+    /// `QPackDecoder::set_max_field_section_size`.
+    FieldSectionTooLarge,
 
     TransportError(neqo_transport::Error),
     QlogError,
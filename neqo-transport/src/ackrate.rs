@@ -12,7 +12,7 @@ use neqo_common::qtrace;
 
 use crate::{
     connection::params::ACK_RATIO_SCALE, frame::FRAME_TYPE_ACK_FREQUENCY, packet::PacketBuilder,
-    recovery::RecoveryToken, stats::FrameStats,
+    recovery::RecoveryToken,
 };
 
 #[derive(Debug, Clone)]
@@ -102,12 +102,7 @@ impl FlexibleAckRate {
         }
     }
 
-    fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         if !self.frame_outstanding
             && self.current.needs_update(&self.target)
             && self.target.write_frame(builder, self.next_frame_seqno)
@@ -116,7 +111,6 @@ impl FlexibleAckRate {
             self.frame_outstanding = true;
             self.next_frame_seqno += 1;
             tokens.push(RecoveryToken::AckFrequency(self.target.clone()));
-            stats.ack_frequency += 1;
         }
     }
 
@@ -168,14 +162,9 @@ impl PeerAckDelay {
         ))
     }
 
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         if let Self::Flexible(rate) = self {
-            rate.write_frames(builder, tokens, stats);
+            rate.write_frames(builder, tokens);
         }
     }
 
@@ -18,9 +18,7 @@ use neqo_crypto::{
 };
 use smallvec::SmallVec;
 
-use crate::{
-    cid::ConnectionId, packet::PacketBuilder, recovery::RecoveryToken, stats::FrameStats, Res,
-};
+use crate::{cid::ConnectionId, packet::PacketBuilder, recovery::RecoveryToken, Res};
 
 /// A prefix we add to Retry tokens to distinguish them from `NEW_TOKEN` tokens.
 const TOKEN_IDENTIFIER_RETRY: &[u8] = &[0x52, 0x65, 0x74, 0x72, 0x79];
@@ -36,6 +34,8 @@ const MAX_NEW_TOKEN: usize = 4;
 /// This is based on how many might be received over a period where could be
 /// retransmissions.  It should be at least `MAX_NEW_TOKEN`.
 const MAX_SAVED_TOKENS: usize = 8;
+/// How long a Retry token remains valid, unless configured otherwise.
+const DEFAULT_RETRY_TOKEN_LIFETIME: Duration = Duration::from_secs(5);
 
 /// `ValidateAddress` determines what sort of address validation is performed.
 /// In short, this determines when a Retry packet is sent.
@@ -63,6 +63,8 @@ pub struct AddressValidation {
     self_encrypt: SelfEncrypt,
     /// When this object was created.
     start_time: Instant,
+    /// How long Retry tokens remain valid after they are generated.
+    retry_token_lifetime: Duration,
 }
 
 impl AddressValidation {
@@ -71,9 +73,21 @@ impl AddressValidation {
             validation,
             self_encrypt: SelfEncrypt::new(TLS_VERSION_1_3, TLS_AES_128_GCM_SHA256)?,
             start_time: now,
+            retry_token_lifetime: DEFAULT_RETRY_TOKEN_LIFETIME,
         })
     }
 
+    /// Set how long Retry tokens remain valid.  This only applies to tokens
+    /// that are generated after this is called.
+    pub fn set_retry_token_lifetime(&mut self, lifetime: Duration) {
+        qtrace!(
+            "AddressValidation {:p}: Retry token lifetime {:?}",
+            self,
+            lifetime
+        );
+        self.retry_token_lifetime = lifetime;
+    }
+
     fn encode_aad(peer_address: SocketAddr, retry: bool) -> Encoder {
         // Let's be "clever" by putting the peer's address in the AAD.
         // We don't need to encode these into the token as they should be
@@ -106,7 +120,6 @@ impl AddressValidation {
         peer_address: SocketAddr,
         now: Instant,
     ) -> Res<Vec<u8>> {
-        const EXPIRATION_RETRY: Duration = Duration::from_secs(5);
         const EXPIRATION_NEW_TOKEN: Duration = Duration::from_secs(60 * 60 * 24);
 
         // TODO(mt) rotate keys on a fixed schedule.
@@ -114,7 +127,7 @@ impl AddressValidation {
         let mut data = Encoder::default();
         let end = now
             + if retry {
-                EXPIRATION_RETRY
+                self.retry_token_lifetime
             } else {
                 EXPIRATION_NEW_TOKEN
             };
@@ -344,14 +357,9 @@ impl NewTokenState {
 
     /// If this is a server, maybe send a frame.
     /// If this is a client, do nothing.
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         if let Self::Server(ref mut sender) = self {
-            sender.write_frames(builder, tokens, stats);
+            sender.write_frames(builder, tokens);
         }
     }
 
@@ -418,12 +426,7 @@ impl NewTokenSender {
         self.next_seqno += 1;
     }
 
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         for t in &mut self.tokens {
             if t.needs_sending && t.len() <= builder.remaining() {
                 t.needs_sending = false;
@@ -432,7 +435,6 @@ impl NewTokenSender {
                 builder.encode_vvec(&t.token);
 
                 tokens.push(RecoveryToken::NewToken(t.seqno));
-                stats.new_token += 1;
             }
         }
     }
@@ -453,12 +455,18 @@ impl NewTokenSender {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use neqo_common::Role;
+    use test_fixture::{fixture_init, now, DEFAULT_ADDR};
 
-    use super::NewTokenState;
+    use super::{AddressValidation, AddressValidationResult, NewTokenState, ValidateAddress};
+    use crate::ConnectionId;
 
     const ONE: &[u8] = &[1, 2, 3];
     const TWO: &[u8] = &[4, 5];
+    const ODCID: &[u8] = &[0x0c; 8];
+    const RETRY_LIFETIME: Duration = Duration::from_secs(30);
 
     #[test]
     fn duplicate_saved() {
@@ -501,4 +509,29 @@ mod tests {
         assert!(!tokens.has_token());
         assert!(tokens.take_token().is_none());
     }
+
+    fn retry_token() -> (AddressValidation, Vec<u8>) {
+        fixture_init();
+        let mut av = AddressValidation::new(now(), ValidateAddress::Always).unwrap();
+        av.set_retry_token_lifetime(RETRY_LIFETIME);
+        let token = av
+            .generate_retry_token(&ConnectionId::from(ODCID), DEFAULT_ADDR, now())
+            .unwrap();
+        (av, token)
+    }
+
+    #[test]
+    fn retry_token_within_lifetime() {
+        let (av, token) = retry_token();
+        let res = av.validate(&token, DEFAULT_ADDR, now() + RETRY_LIFETIME);
+        assert!(matches!(res, AddressValidationResult::ValidRetry(cid) if &cid[..] == ODCID));
+    }
+
+    #[test]
+    fn retry_token_expired() {
+        let (av, token) = retry_token();
+        let late = now() + RETRY_LIFETIME + Duration::from_millis(1);
+        let res = av.validate(&token, DEFAULT_ADDR, late);
+        assert!(matches!(res, AddressValidationResult::Invalid));
+    }
 }
@@ -12,7 +12,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use super::CongestionControl;
+use super::{CongestionControl, CongestionPhase};
 use crate::{
     cc::MAX_DATAGRAM_SIZE,
     packet::PacketNumber,
@@ -72,6 +72,14 @@ impl State {
         };
     }
 
+    pub fn phase(self) -> CongestionPhase {
+        match self {
+            Self::SlowStart | Self::PersistentCongestion => CongestionPhase::SlowStart,
+            Self::CongestionAvoidance => CongestionPhase::CongestionAvoidance,
+            Self::Recovery | Self::RecoveryStart => CongestionPhase::Recovery,
+        }
+    }
+
     pub fn to_qlog(self) -> &'static str {
         match self {
             Self::SlowStart | Self::PersistentCongestion => "slow_start",
@@ -159,6 +167,31 @@ impl<T: WindowAdjustment> CongestionControl for ClassicCongestionControl<T> {
         self.congestion_window.saturating_sub(self.bytes_in_flight)
     }
 
+    #[must_use]
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+
+    #[must_use]
+    fn phase(&self) -> CongestionPhase {
+        self.state.phase()
+    }
+
+    fn app_limited(&self) -> bool {
+        if self.bytes_in_flight >= self.congestion_window {
+            false
+        } else if self.state.in_slow_start() {
+            // Allow for potential doubling of the congestion window during slow start.
+            // That is, the application might not have been able to send enough to respond
+            // to increases to the congestion window.
+            self.bytes_in_flight < self.congestion_window / 2
+        } else {
+            // We're not limited if the in-flight data is within a single burst of the
+            // congestion window.
+            (self.bytes_in_flight + MAX_DATAGRAM_SIZE * PACING_BURST_SIZE) < self.congestion_window
+        }
+    }
+
     // Multi-packet version of OnPacketAckedCC
     fn on_packets_acked(&mut self, acked_pkts: &[SentPacket], rtt_est: &RttEstimate, now: Instant) {
         let mut is_app_limited = true;
@@ -318,6 +351,17 @@ impl<T: WindowAdjustment> CongestionControl for ClassicCongestionControl<T> {
         }
     }
 
+    fn discard_lost(&mut self, lost_packets: &[SentPacket]) {
+        for pkt in lost_packets.iter().filter(|pkt| pkt.cc_in_flight()) {
+            qtrace!([self], "Ignore lost pkt with size {}", pkt.len());
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+        }
+        qlog::metrics_updated(
+            &mut self.qlog,
+            &[QlogMetric::BytesInFlight(self.bytes_in_flight)],
+        );
+    }
+
     fn discard_in_flight(&mut self) {
         self.bytes_in_flight = 0;
         qlog::metrics_updated(
@@ -378,12 +422,6 @@ impl<T: WindowAdjustment> ClassicCongestionControl<T> {
         }
     }
 
-    #[cfg(test)]
-    #[must_use]
-    pub fn ssthresh(&self) -> usize {
-        self.ssthresh
-    }
-
     #[cfg(test)]
     pub fn set_ssthresh(&mut self, v: usize) {
         self.ssthresh = v;
@@ -525,21 +563,6 @@ impl<T: WindowAdjustment> ClassicCongestionControl<T> {
         self.set_state(State::RecoveryStart);
         true
     }
-
-    fn app_limited(&self) -> bool {
-        if self.bytes_in_flight >= self.congestion_window {
-            false
-        } else if self.state.in_slow_start() {
-            // Allow for potential doubling of the congestion window during slow start.
-            // That is, the application might not have been able to send enough to respond
-            // to increases to the congestion window.
-            self.bytes_in_flight < self.congestion_window / 2
-        } else {
-            // We're not limited if the in-flight data is within a single burst of the
-            // congestion window.
-            (self.bytes_in_flight + MAX_DATAGRAM_SIZE * PACING_BURST_SIZE) < self.congestion_window
-        }
-    }
 }
 
 #[cfg(test)]
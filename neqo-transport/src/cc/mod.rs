@@ -42,6 +42,16 @@ pub trait CongestionControl: Display + Debug {
     #[must_use]
     fn cwnd_avail(&self) -> usize;
 
+    #[must_use]
+    fn ssthresh(&self) -> usize;
+
+    #[must_use]
+    fn phase(&self) -> CongestionPhase;
+
+    /// Whether the sender is not sending enough to use the congestion window.
+    #[must_use]
+    fn app_limited(&self) -> bool;
+
     fn on_packets_acked(&mut self, acked_pkts: &[SentPacket], rtt_est: &RttEstimate, now: Instant);
 
     /// Returns true if the congestion window was reduced.
@@ -61,17 +71,49 @@ pub trait CongestionControl: Display + Debug {
 
     fn discard(&mut self, pkt: &SentPacket);
 
+    /// Remove lost packets from the bytes in flight without treating their
+    /// loss as a sign of congestion.
+    fn discard_lost(&mut self, lost_packets: &[SentPacket]);
+
     fn on_packet_sent(&mut self, pkt: &SentPacket);
 
     fn discard_in_flight(&mut self);
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum CongestionControlAlgorithm {
     NewReno,
     Cubic,
 }
 
+/// The phase of congestion control.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CongestionPhase {
+    SlowStart,
+    CongestionAvoidance,
+    Recovery,
+}
+
+/// The values that congestion control uses for a path.
+///
+/// These are all read at the same time, so they are consistent with each other.
+/// Taking a snapshot is cheap and does not allocate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CcSnapshot {
+    pub algorithm: CongestionControlAlgorithm,
+    pub phase: CongestionPhase,
+    /// The congestion window, in bytes.
+    pub cwnd: usize,
+    /// The slow start threshold, in bytes.  This is `usize::MAX` until the first
+    /// congestion event.
+    pub ssthresh: usize,
+    pub bytes_in_flight: usize,
+    /// The rate at which the pacer allows packets to be sent, in bytes per second.
+    /// This is `None` if pacing is disabled.
+    pub pacing_rate: Option<u64>,
+    pub app_limited: bool,
+}
+
 // A `FromStr` implementation so that this can be used in command-line interfaces.
 impl FromStr for CongestionControlAlgorithm {
     type Err = Error;
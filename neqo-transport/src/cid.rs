@@ -14,13 +14,12 @@ use std::{
     rc::Rc,
 };
 
-use neqo_common::{hex, hex_with_len, qinfo, Decoder, Encoder};
+use neqo_common::{hex, hex_with_len, qinfo, qwarn, Decoder, Encoder};
 use neqo_crypto::{random, randomize};
 use smallvec::{smallvec, SmallVec};
 
 use crate::{
-    frame::FRAME_TYPE_NEW_CONNECTION_ID, packet::PacketBuilder, recovery::RecoveryToken,
-    stats::FrameStats, Error, Res,
+    frame::FRAME_TYPE_NEW_CONNECTION_ID, packet::PacketBuilder, recovery::RecoveryToken, Error, Res,
 };
 
 pub const MAX_CONNECTION_ID_LEN: usize = 20;
@@ -179,6 +178,28 @@ pub trait ConnectionIdGenerator: ConnectionIdDecoder {
     fn as_decoder(&self) -> &dyn ConnectionIdDecoder;
 }
 
+/// Generate a connection ID and check that it is usable.
+///
+/// # Errors
+///
+/// `Error::ConnectionIdsExhausted` if the generator has no more connection IDs.
+/// `Error::InvalidConnectionIdLength` if the connection ID is longer than
+/// `MAX_CONNECTION_ID_LEN`, or if it is empty when the generator doesn't
+/// claim to produce empty connection IDs (or the reverse).
+pub(crate) fn generate_checked_cid(generator: &mut dyn ConnectionIdGenerator) -> Res<ConnectionId> {
+    let cid = generator
+        .generate_cid()
+        .ok_or(Error::ConnectionIdsExhausted)?;
+    if cid.len() > MAX_CONNECTION_ID_LEN || cid.is_empty() != generator.generates_empty_cids() {
+        qwarn!(
+            "Connection ID generator produced an invalid connection ID of length {}",
+            cid.len()
+        );
+        return Err(Error::InvalidConnectionIdLength);
+    }
+    Ok(cid)
+}
+
 /// An `EmptyConnectionIdGenerator` generates empty connection IDs.
 #[derive(Default)]
 pub struct EmptyConnectionIdGenerator {}
@@ -239,7 +260,8 @@ impl ConnectionIdGenerator for RandomConnectionIdGenerator {
 
 /// A single connection ID, as saved from `NEW_CONNECTION_ID`.
 /// This is templated so that the connection ID entries from a peer can be
-/// saved with a stateless reset token.  Local entries don't need that.
+/// saved with a stateless reset token.  Local entries only have a token
+/// if one was issued for them.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ConnectionIdEntry<SRT: Clone + PartialEq> {
     /// The sequence number.
@@ -300,7 +322,7 @@ impl ConnectionIdEntry<[u8; 16]> {
 
     /// Write the entry out in a `NEW_CONNECTION_ID` frame.
     /// Returns `true` if the frame was written, `false` if there is insufficient space.
-    pub fn write(&self, builder: &mut PacketBuilder, stats: &mut FrameStats) -> bool {
+    pub fn write(&self, builder: &mut PacketBuilder) -> bool {
         let len = 1 + Encoder::varint_len(self.seqno) + 1 + 1 + self.cid.len() + 16;
         if builder.remaining() < len {
             return false;
@@ -311,15 +333,14 @@ impl ConnectionIdEntry<[u8; 16]> {
         builder.encode_varint(0u64);
         builder.encode_vec(1, &self.cid);
         builder.encode(&self.srt);
-        stats.new_connection_id += 1;
         true
     }
 }
 
-impl ConnectionIdEntry<()> {
+impl ConnectionIdEntry<Option<[u8; 16]>> {
     /// Create an initial entry.
     pub fn initial_local(cid: ConnectionId) -> Self {
-        Self::new(0, cid, ())
+        Self::new(0, cid, None)
     }
 }
 
@@ -416,8 +437,8 @@ impl ConnectionIdStore<[u8; 16]> {
     }
 }
 
-impl ConnectionIdStore<()> {
-    fn add_local(&mut self, entry: ConnectionIdEntry<()>) {
+impl ConnectionIdStore<Option<[u8; 16]>> {
+    fn add_local(&mut self, entry: ConnectionIdEntry<Option<[u8; 16]>>) {
         self.cids.push(entry);
     }
 }
@@ -444,7 +465,7 @@ pub struct ConnectionIdManager {
     /// This includes any we advertise in `NEW_CONNECTION_ID` that haven't been bound to a path
     /// yet. During the handshake at the server, it also includes the randomized DCID pick by
     /// the client.
-    connection_ids: ConnectionIdStore<()>,
+    connection_ids: ConnectionIdStore<Option<[u8; 16]>>,
     /// The maximum number of connection IDs this will accept.  This is at least 2 and won't
     /// be more than `LOCAL_ACTIVE_CID_LIMIT`.
     limit: usize,
@@ -489,18 +510,39 @@ impl ConnectionIdManager {
         if self.generator.deref().borrow().generates_empty_cids() {
             return Err(Error::ConnectionIdsExhausted);
         }
-        if let Some(cid) = self.generator.borrow_mut().generate_cid() {
-            assert_ne!(cid.len(), 0);
-            debug_assert_eq!(self.next_seqno, CONNECTION_ID_SEQNO_PREFERRED);
-            self.connection_ids
-                .add_local(ConnectionIdEntry::new(self.next_seqno, cid.clone(), ()));
-            self.next_seqno += 1;
-
-            let srt = ConnectionIdEntry::random_srt();
-            Ok((cid, srt))
-        } else {
-            Err(Error::ConnectionIdsExhausted)
+        let cid = generate_checked_cid(&mut *self.generator.borrow_mut())?;
+        debug_assert_eq!(self.next_seqno, CONNECTION_ID_SEQNO_PREFERRED);
+        let srt = ConnectionIdEntry::random_srt();
+        self.connection_ids.add_local(ConnectionIdEntry::new(
+            self.next_seqno,
+            cid.clone(),
+            Some(srt),
+        ));
+        self.next_seqno += 1;
+        Ok((cid, srt))
+    }
+
+    /// Issue a stateless reset token for the connection ID used during the handshake.
+    pub fn initial_reset_token(&mut self) -> [u8; 16] {
+        let srt = ConnectionIdEntry::random_srt();
+        if let Some(entry) = self
+            .connection_ids
+            .cids
+            .iter_mut()
+            .find(|e| e.seqno == CONNECTION_ID_SEQNO_INITIAL)
+        {
+            entry.srt = Some(srt);
         }
+        srt
+    }
+
+    /// The connection IDs that are still valid, with any stateless reset token
+    /// that was issued for them.
+    pub fn local_cids(&self) -> impl Iterator<Item = (&ConnectionId, Option<&[u8; 16]>)> {
+        self.connection_ids
+            .cids
+            .iter()
+            .map(|e| (&e.cid, e.srt.as_ref()))
     }
 
     pub fn is_valid(&self, cid: ConnectionIdRef) -> bool {
@@ -519,7 +561,7 @@ impl ConnectionIdManager {
     /// Note that this is only done *after* an Initial packet from the client is
     /// successfully processed.
     pub fn add_odcid(&mut self, cid: ConnectionId) {
-        let entry = ConnectionIdEntry::new(CONNECTION_ID_SEQNO_ODCID, cid, ());
+        let entry = ConnectionIdEntry::new(CONNECTION_ID_SEQNO_ODCID, cid, None);
         self.connection_ids.add_local(entry);
     }
 
@@ -536,19 +578,14 @@ impl ConnectionIdManager {
         );
     }
 
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         if self.generator.deref().borrow().generates_empty_cids() {
             debug_assert_eq!(self.generator.borrow_mut().generate_cid().unwrap().len(), 0);
             return;
         }
 
         while let Some(entry) = self.lost_new_connection_id.pop() {
-            if entry.write(builder, stats) {
+            if entry.write(builder) {
                 tokens.push(RecoveryToken::NewConnectionId(entry));
             } else {
                 // This shouldn't happen often.
@@ -561,21 +598,22 @@ impl ConnectionIdManager {
         // and while there is room for more.  This uses the longest connection ID
         // length to simplify (assuming Retire Prior To is just 1 byte).
         while self.connection_ids.len() < self.limit && builder.remaining() >= 47 {
-            let maybe_cid = self.generator.borrow_mut().generate_cid();
-            if let Some(cid) = maybe_cid {
-                assert_ne!(cid.len(), 0);
-                // TODO: generate the stateless reset tokens from the connection ID and a key.
-                let srt = ConnectionIdEntry::random_srt();
-
-                let seqno = self.next_seqno;
-                self.next_seqno += 1;
-                self.connection_ids
-                    .add_local(ConnectionIdEntry::new(seqno, cid.clone(), ()));
-
-                let entry = ConnectionIdEntry::new(seqno, cid, srt);
-                entry.write(builder, stats);
-                tokens.push(RecoveryToken::NewConnectionId(entry));
-            }
+            // Stop if the generator can't produce a usable connection ID;
+            // the peer can make do with what it already has.
+            let Ok(cid) = generate_checked_cid(&mut *self.generator.borrow_mut()) else {
+                break;
+            };
+            // TODO: generate the stateless reset tokens from the connection ID and a key.
+            let srt = ConnectionIdEntry::random_srt();
+
+            let seqno = self.next_seqno;
+            self.next_seqno += 1;
+            self.connection_ids
+                .add_local(ConnectionIdEntry::new(seqno, cid.clone(), Some(srt)));
+
+            let entry = ConnectionIdEntry::new(seqno, cid, srt);
+            entry.write(builder);
+            tokens.push(RecoveryToken::NewConnectionId(entry));
         }
     }
 
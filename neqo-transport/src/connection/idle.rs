@@ -29,6 +29,8 @@ pub struct IdleTimeout {
     timeout: Duration,
     state: IdleTimeoutState,
     keep_alive_outstanding: bool,
+    /// When set, PING is sent on a connection that is idle for this long.
+    keep_alive_interval: Option<Duration>,
 }
 
 impl IdleTimeout {
@@ -37,6 +39,7 @@ impl IdleTimeout {
             timeout,
             state: IdleTimeoutState::Init,
             keep_alive_outstanding: false,
+            keep_alive_interval: None,
         }
     }
 }
@@ -90,10 +93,28 @@ impl IdleTimeout {
         now >= self.expiry(now, pto)
     }
 
+    pub fn set_keep_alive_interval(&mut self, interval: Option<Duration>) {
+        self.keep_alive_interval = interval;
+    }
+
+    /// Whether the connection sends a PING when it is idle, even if no stream needs it.
+    pub fn keep_alive_enabled(&self) -> bool {
+        self.keep_alive_interval.is_some()
+    }
+
     fn keep_alive_timeout(&self, now: Instant, pto: Duration) -> Instant {
         // For a keep-alive timer, wait for half the timeout interval, but be sure
         // not to wait too little or we will send many unnecessary probes.
-        self.start(now) + max(self.timeout / 2, pto)
+        let delay = self
+            .keep_alive_interval
+            .map_or(self.timeout / 2, |i| min(i, self.timeout / 2));
+        self.start(now) + max(delay, pto)
+    }
+
+    /// The time at which a PING is next due, if the keep-alive interval is set.
+    pub fn next_keep_alive(&self, now: Instant, pto: Duration) -> Option<Instant> {
+        (self.keep_alive_enabled() && !self.keep_alive_outstanding)
+            .then(|| self.keep_alive_timeout(now, pto))
     }
 
     pub fn send_keep_alive(
@@ -8,7 +8,8 @@
 
 use std::{
     cell::RefCell,
-    cmp::{max, min},
+    cmp::min,
+    collections::BTreeSet,
     fmt::{self, Debug},
     iter, mem,
     net::{IpAddr, SocketAddr},
@@ -19,8 +20,12 @@ use std::{
 };
 
 use neqo_common::{
-    event::Provider as EventProvider, hex, hex_snip_middle, hrtime, qdebug, qerror, qinfo,
-    qlog::NeqoQlog, qtrace, qwarn, Datagram, Decoder, Encoder, Role,
+    event::Provider as EventProvider,
+    hex, hex_snip_middle, hrtime,
+    log::SpanGuard,
+    qdebug, qerror, qinfo,
+    qlog::{NeqoQlog, QlogFilter},
+    qspan, qtrace, qwarn, Datagram, Decoder, Encoder, IpTosDscp, IpTosEcn, Role,
 };
 use neqo_crypto::{
     agent::CertificateInfo, Agent, AntiReplay, AuthenticationStatus, Cipher, Client, Group,
@@ -31,26 +36,31 @@ use smallvec::SmallVec;
 
 use crate::{
     addr_valid::{AddressValidation, NewTokenState},
+    cc::CcSnapshot,
     cid::{
-        ConnectionId, ConnectionIdEntry, ConnectionIdGenerator, ConnectionIdManager,
-        ConnectionIdRef, ConnectionIdStore, LOCAL_ACTIVE_CID_LIMIT,
+        generate_checked_cid, ConnectionId, ConnectionIdEntry, ConnectionIdGenerator,
+        ConnectionIdManager, ConnectionIdRef, ConnectionIdStore, LOCAL_ACTIVE_CID_LIMIT,
     },
-    crypto::{Crypto, CryptoDxState, CryptoSpace},
+    crypto::{Crypto, CryptoDxState, CryptoSpace, KeyUpdatePolicy},
     ecn::EcnCount,
     events::{ConnectionEvent, ConnectionEvents, OutgoingDatagramOutcome},
+    fc::FlowControlState,
     frame::{
         CloseError, Frame, FrameType, FRAME_TYPE_CONNECTION_CLOSE_APPLICATION,
         FRAME_TYPE_CONNECTION_CLOSE_TRANSPORT,
     },
-    packet::{DecryptedPacket, PacketBuilder, PacketNumber, PacketType, PublicPacket},
-    path::{Path, PathRef, Paths},
-    qlog,
-    quic_datagrams::{DatagramTracking, QuicDatagrams},
-    recovery::{LossRecovery, RecoveryToken, SendProfile, SentPacket},
+    packet::{
+        DecryptedPacket, PacketBuilder, PacketNumber, PacketType, PublicPacket,
+        MIN_INITIAL_PACKET_SIZE,
+    },
+    path::{canonical_address, Path, PathInfo, PathRef, Paths},
+    qlog::{self, PathTrigger, QlogMetric},
+    quic_datagrams::{DatagramOptions, DatagramTracking, QuicDatagrams},
+    recovery::{LossRecovery, RecoveryToken, SendProfile, SentPacket, StreamRecoveryToken},
     recv_stream::RecvStreamStats,
     rtt::{RttEstimate, GRANULARITY},
     send_stream::SendStream,
-    stats::{Stats, StatsCell},
+    stats::{DatagramStats, PacketDropReason, ProcessDiag, RecoveryStats, Stats, StatsCell},
     stream_id::StreamType,
     streams::{SendOrder, Streams},
     tparams::{
@@ -59,16 +69,19 @@ use crate::{
     },
     tracking::{AckTracker, PacketNumberSpace, RecvdPackets},
     version::{Version, WireVersion},
-    AppError, CloseReason, Error, Res, StreamId,
+    AppError, CloseReason, Error, Res, StreamId, TransportError,
 };
 
 mod dump;
 mod idle;
 pub mod params;
 mod saved;
+mod spin;
 mod state;
 #[cfg(test)]
 pub mod test_internal;
+#[cfg(feature = "test-network")]
+mod test_network;
 
 use dump::dump_packet;
 use idle::IdleTimeout;
@@ -77,8 +90,9 @@ use params::PreferredAddressConfig;
 #[cfg(test)]
 pub use params::ACK_RATIO_SCALE;
 use saved::SavedDatagrams;
+use spin::SpinBit;
 use state::StateSignaling;
-pub use state::{ClosingFrame, State};
+pub use state::{ClosingFrame, EchState, HandshakePhase, State};
 
 pub use crate::send_stream::{RetransmissionPriority, SendStreamStats, TransmissionPriority};
 
@@ -103,6 +117,11 @@ pub enum Output {
     /// Connection requires no action.
     None,
     /// Connection requires the datagram be sent.
+    /// The datagram has to be sent from its [`Datagram::source`] address, which is
+    /// the local address of the path it belongs to.  After a migration, this
+    /// is not the address that the last datagram was received on, so an application
+    /// that uses multiple local addresses needs to select the socket or set the
+    /// source address (e.g., with `IP_PKTINFO`) for each datagram.
     Datagram(Datagram),
     /// Connection requires `process_input()` be called when the `Duration`
     /// elapses.
@@ -229,6 +248,10 @@ pub struct Connection {
     role: Role,
     version: Version,
     state: State,
+    /// How far the handshake has progressed.
+    handshake_phase: HandshakePhase,
+    /// What happened with Encrypted Client Hello.
+    ech_state: EchState,
     tps: Rc<RefCell<TransportParametersHandler>>,
     /// What we are doing with 0-RTT.
     zero_rtt_state: ZeroRttState,
@@ -266,24 +289,38 @@ pub struct Connection {
     pub(crate) crypto: Crypto,
     pub(crate) acks: AckTracker,
     idle_timeout: IdleTimeout,
+    /// The latency spin bit for short header packets.
+    spin: SpinBit,
     streams: Streams,
     state_signaling: StateSignaling,
     loss_recovery: LossRecovery,
     events: ConnectionEvents,
     new_token: NewTokenState,
     stats: StatsCell,
+    /// What happened to the packets passed to the last `process_multiple_input`.
+    last_input_diag: ProcessDiag,
+    /// The DATAGRAM counters at the last call to `reset_datagram_stats`.
+    datagram_stats_base: DatagramStats,
     qlog: NeqoQlog,
     /// A session ticket was received without `NEW_TOKEN`,
     /// this is when that turns into an event without `NEW_TOKEN`.
     release_resumption_token_timer: Option<Instant>,
+    /// Set on a server that doesn't resume sessions, so that it sends no tickets.
+    resumption_disabled: bool,
     conn_params: ConnectionParameters,
     hrtime: hrtime::Handle,
+    /// Streams that a write was refused for because the output queue was full.
+    output_queue_blocked: BTreeSet<StreamId>,
 
     /// For testing purposes it is sometimes necessary to inject frames that wouldn't
     /// otherwise be sent, just to see how a connection handles them.  Inserting them
     /// into packets proper mean that the frames follow the entire processing path.
     #[cfg(test)]
     pub test_frame_writer: Option<Box<dyn test_internal::FrameWriter>>,
+
+    /// A simulated network that drops and delays the datagrams this sends.
+    #[cfg(feature = "test-network")]
+    test_network: Option<test_network::TestNetwork>,
 }
 
 impl Debug for Connection {
@@ -329,6 +366,7 @@ impl Connection {
             &dcid,
         );
         c.original_destination_cid = Some(dcid);
+        c.stats.borrow_mut().timings.start(now);
         let path = Path::temporary(
             local_addr,
             remote_addr,
@@ -367,10 +405,7 @@ impl Connection {
         conn_params: ConnectionParameters,
     ) -> Res<Self> {
         // Setup the local connection ID.
-        let local_initial_source_cid = cid_generator
-            .borrow_mut()
-            .generate_cid()
-            .ok_or(Error::ConnectionIdsExhausted)?;
+        let local_initial_source_cid = generate_checked_cid(&mut *cid_generator.borrow_mut())?;
         let mut cid_manager =
             ConnectionIdManager::new(cid_generator, local_initial_source_cid.clone());
         let mut tps = conn_params.create_transport_parameter(role, &mut cid_manager)?;
@@ -400,7 +435,9 @@ impl Connection {
             role,
             version: conn_params.get_versions().initial(),
             state: State::Init,
-            paths: Paths::default(),
+            handshake_phase: HandshakePhase::Start,
+            ech_state: EchState::NotAttempted,
+            paths: Paths::new(&conn_params, events.clone()),
             cid_manager,
             tps: tphandler.clone(),
             zero_rtt_state: ZeroRttState::Init,
@@ -413,6 +450,7 @@ impl Connection {
             crypto,
             acks: AckTracker::default(),
             idle_timeout: IdleTimeout::new(conn_params.get_idle_timeout()),
+            spin: SpinBit::new(conn_params.spin_bit_enabled()),
             streams: Streams::new(tphandler, role, events.clone()),
             connection_ids: ConnectionIdStore::default(),
             state_signaling: StateSignaling::Idle,
@@ -420,13 +458,19 @@ impl Connection {
             events,
             new_token: NewTokenState::new(role),
             stats,
+            last_input_diag: ProcessDiag::default(),
+            datagram_stats_base: DatagramStats::default(),
             qlog: NeqoQlog::disabled(),
             release_resumption_token_timer: None,
+            resumption_disabled: false,
             conn_params,
             hrtime: hrtime::Time::get(Self::LOOSE_TIMER_RESOLUTION),
+            output_queue_blocked: BTreeSet::new(),
             quic_datagrams,
             #[cfg(test)]
             test_frame_writer: None,
+            #[cfg(feature = "test-network")]
+            test_network: None,
         };
         c.stats.borrow_mut().init(format!("{c}"));
         Ok(c)
@@ -455,6 +499,16 @@ impl Connection {
         self.crypto.server_enable_ech(config, public_name, sk, pk)
     }
 
+    /// Configure an external pre-shared key.  A client offers it in the handshake;
+    /// a server that has a key with the same identity completes the handshake
+    /// with that key instead of a certificate.  Call this before the handshake starts.
+    ///
+    /// # Errors
+    /// If `cipher` is not supported, or if a key was already added.
+    pub fn add_external_psk(&mut self, identity: &[u8], key: &[u8], cipher: Cipher) -> Res<()> {
+        self.crypto.add_external_psk(identity, key, cipher)
+    }
+
     /// Get the active ECH configuration, which is empty if ECH is disabled.
     #[must_use]
     pub fn ech_config(&self) -> &[u8] {
@@ -467,6 +521,83 @@ impl Connection {
         self.crypto.client_enable_ech(ech_config_list)
     }
 
+    /// Do not resume sessions.  Tickets that the client presents are ignored, so
+    /// the handshake is a full handshake without 0-RTT, and `send_ticket` only
+    /// sends a `NEW_TOKEN` frame.
+    ///
+    /// # Errors
+    /// When the operation fails.
+    pub fn server_disable_resumption(&mut self) -> Res<()> {
+        self.crypto.server_disable_resumption()?;
+        self.resumption_disabled = true;
+        Ok(())
+    }
+
+    /// Require the client to present a certificate that chains to one of the
+    /// certificate authorities with the given nicknames.
+    ///
+    /// # Errors
+    /// When the operation fails.
+    pub fn server_require_client_auth(&mut self, cas: &[impl AsRef<str>]) -> Res<()> {
+        self.crypto.server_require_client_auth(cas)
+    }
+
+    /// Set the nickname of the certificate to present if the server asks
+    /// for client authentication.
+    ///
+    /// # Errors
+    /// When the operation fails.
+    pub fn client_set_certificate(&mut self, nickname: &str) -> Res<()> {
+        self.crypto.client_set_certificate(nickname)
+    }
+
+    /// The server name (SNI) that the client sent.  This is always `None` for a client,
+    /// and for a server until the client's handshake has been received.
+    #[must_use]
+    pub fn server_name(&self) -> Option<&str> {
+        self.crypto.server_name()
+    }
+
+    /// Get the outcome of Encrypted Client Hello.  This is `NotAttempted`
+    /// until the outcome is known.
+    #[must_use]
+    pub fn ech_state(&self) -> EchState {
+        self.ech_state
+    }
+
+    fn set_ech_state(&mut self, ech_state: EchState) {
+        if self.ech_state == ech_state {
+            return;
+        }
+        qdebug!([self], "ECH state {:?} -> {:?}", self.ech_state, ech_state);
+        self.ech_state = ech_state;
+        qlog::ech_updated(
+            &mut self.qlog,
+            self.role,
+            ech_state,
+            self.crypto.ech_config(),
+        );
+    }
+
+    /// Look at what the TLS stack says about ECH once the `ClientHello` is processed.
+    /// A client learns about rejection from `EchFallbackAuthenticationPending` or
+    /// `Error::EchRetry` instead.
+    fn check_ech(&mut self) {
+        if self.ech_state != EchState::NotAttempted {
+            return;
+        }
+        let Ok(accepted) = self.crypto.tls.preinfo().map(|info| info.ech_accepted()) else {
+            return;
+        };
+        match accepted {
+            Some(true) => self.set_ech_state(EchState::Accepted),
+            Some(false) if self.role == Role::Server && !self.crypto.ech_config().is_empty() => {
+                self.set_ech_state(EchState::RejectedWithRetry);
+            }
+            _ => (),
+        }
+    }
+
     /// Set or clear the qlog for this connection.
     pub fn set_qlog(&mut self, qlog: NeqoQlog) {
         self.loss_recovery.set_qlog(qlog.clone());
@@ -474,6 +605,43 @@ impl Connection {
         self.qlog = qlog;
     }
 
+    /// Start a qlog trace on a connection that is already running.  This replaces
+    /// any existing trace.  Because the new trace misses everything that came
+    /// before, it starts with events that describe the current state of the
+    /// connection: its path, transport parameters, state, and congestion metrics.
+    pub fn start_qlog(&mut self, qlog: NeqoQlog) {
+        self.set_qlog(qlog);
+        let path = self.paths.primary();
+        if let Some(path) = &path {
+            if self.role == Role::Server {
+                qlog::server_connection_started(&mut self.qlog, path);
+            } else {
+                qlog::client_connection_started(&mut self.qlog, path);
+            }
+        }
+        if self.tps.borrow().remote.is_some() {
+            qlog::connection_tparams_set(&mut self.qlog, &self.tps.borrow());
+        }
+        qlog::connection_state_updated(&mut self.qlog, &self.state);
+        if let Some(path) = path {
+            let path = path.borrow();
+            qlog::metrics_updated(
+                &mut self.qlog,
+                &[
+                    QlogMetric::MinRtt(path.rtt().minimum()),
+                    QlogMetric::SmoothedRtt(path.rtt().estimate()),
+                    QlogMetric::CongestionWindow(path.sender().cwnd()),
+                ],
+            );
+        }
+    }
+
+    /// Change which events are recorded in the qlog for this connection.
+    /// This has no effect if there is no qlog.
+    pub fn set_qlog_filter(&mut self, filter: QlogFilter) {
+        self.qlog.set_filter(filter);
+    }
+
     /// Get the qlog (if any) for this connection.
     pub fn qlog_mut(&mut self) -> &mut NeqoQlog {
         &mut self.qlog
@@ -772,6 +940,7 @@ impl Connection {
     }
 
     /// Send a TLS session ticket AND a `NEW_TOKEN` frame (if possible).
+    /// No session ticket is sent if resumption is disabled.
     /// # Errors
     /// When the operation fails, which is usually due to bad inputs or bad connection state.
     pub fn send_ticket(&mut self, now: Instant, extra: &[u8]) -> Res<()> {
@@ -780,7 +949,9 @@ impl Connection {
         }
 
         let tps = &self.tps;
-        if let Agent::Server(ref mut s) = self.crypto.tls {
+        if self.resumption_disabled {
+            qdebug!([self], "resumption disabled, not sending a session ticket");
+        } else if let Agent::Server(ref mut s) = self.crypto.tls {
             let mut enc = Encoder::default();
             enc.encode_vvec_with(|enc_inner| {
                 tps.borrow().local.encode(enc_inner);
@@ -809,6 +980,14 @@ impl Connection {
         }
     }
 
+    /// The stateless reset tokens that were issued for connection IDs
+    /// that are still valid.
+    pub(crate) fn reset_tokens(&self) -> impl Iterator<Item = (&ConnectionId, &[u8; 16])> {
+        self.cid_manager
+            .local_cids()
+            .filter_map(|(cid, srt)| srt.map(|srt| (cid, srt)))
+    }
+
     #[must_use]
     pub fn tls_info(&self) -> Option<&SecretAgentInfo> {
         self.crypto.tls.info()
@@ -834,6 +1013,7 @@ impl Connection {
     /// exchanged, it's not OK.
     pub fn authenticated(&mut self, status: AuthenticationStatus, now: Instant) {
         qdebug!([self], "Authenticated {:?}", status);
+        self.events.set_now(now);
         self.crypto.tls.authenticated(status);
         let res = self.handshake(now, self.version, PacketNumberSpace::Handshake, None);
         self.absorb_error(now, res);
@@ -852,6 +1032,33 @@ impl Connection {
         &self.state
     }
 
+    /// Get the furthest phase that the handshake has reached.
+    #[must_use]
+    pub fn handshake_phase(&self) -> HandshakePhase {
+        self.handshake_phase
+    }
+
+    /// While the handshake is underway, enter a span for the current phase.
+    fn handshake_span(&self) -> Option<SpanGuard> {
+        if self.handshake_phase < HandshakePhase::Confirmed {
+            Some(qspan!("handshake", self, phase = ?self.handshake_phase))
+        } else {
+            None
+        }
+    }
+
+    fn advance_handshake_phase(&mut self, phase: HandshakePhase) {
+        if phase > self.handshake_phase {
+            qdebug!(
+                [self],
+                "Handshake phase change from {:?} -> {:?}",
+                self.handshake_phase,
+                phase
+            );
+            self.handshake_phase = phase;
+        }
+    }
+
     /// The QUIC version in use.
     #[must_use]
     pub fn version(&self) -> Version {
@@ -864,6 +1071,79 @@ impl Connection {
         self.zero_rtt_state
     }
 
+    /// Set the DSCP value that is used to mark all outgoing datagrams.
+    /// The ECN codepoint in the low bits of the TOS byte is not affected.
+    pub fn set_dscp(&mut self, dscp: IpTosDscp) {
+        self.paths.set_dscp(dscp);
+    }
+
+    /// Get a snapshot of the network paths that the connection is tracking.
+    /// The paths that are currently in use are marked as `active`.
+    #[must_use]
+    pub fn paths(&self) -> Vec<PathInfo> {
+        self.paths.info()
+    }
+
+    /// The address of the peer on the primary path, if there is one.
+    #[must_use]
+    pub fn peer_address(&self) -> Option<SocketAddr> {
+        self.paths
+            .primary()
+            .map(|path| path.borrow().remote_address())
+    }
+
+    /// Whether both endpoints offered multipath, so that the peer can use more
+    /// than one path at a time.  See `ConnectionParameters::multipath`.
+    #[must_use]
+    pub fn multipath_enabled(&self) -> bool {
+        self.conn_params.get_max_paths() > 1
+            && self
+                .tps
+                .borrow()
+                .remote
+                .as_ref()
+                .map_or(false, |r| r.get_empty(tparams::ENABLE_MULTIPATH))
+    }
+
+    /// The value of the latency spin bit in short header packets that this
+    /// connection sends.  See `ConnectionParameters::spin_bit`.
+    #[must_use]
+    pub const fn spin_bit(&self) -> bool {
+        self.spin.value()
+    }
+
+    /// The number of consecutive times that the PTO timer has fired without
+    /// an acknowledgment arriving.  A persistently high value indicates a
+    /// path that is losing everything.
+    #[must_use]
+    pub fn pto_count(&self) -> u32 {
+        u32::try_from(self.loss_recovery.pto_count()).unwrap_or(u32::MAX)
+    }
+
+    /// How long the pacer holds back the next packet, relative to `now`.
+    /// This is zero if the pacer would let a packet go now, even if congestion
+    /// or flow control prevents one from being sent.
+    #[must_use]
+    pub fn pacing_delay(&self, now: Instant) -> Duration {
+        self.paths.primary().map_or(Duration::ZERO, |p| {
+            let path = p.borrow();
+            path.sender()
+                .next_paced(path.rtt().estimate())
+                .map_or(Duration::ZERO, |t| t.saturating_duration_since(now))
+        })
+    }
+
+    /// Drop a fraction (`loss`, from 0 to 1) of the datagrams that this connection
+    /// sends at random and delay the rest by `added_latency`.  This is only for testing.
+    ///
+    /// # Panics
+    ///
+    /// If `loss` is not between 0 and 1.
+    #[cfg(feature = "test-network")]
+    pub fn set_test_network(&mut self, loss: f64, added_latency: Duration) {
+        self.test_network = Some(test_network::TestNetwork::new(loss, added_latency));
+    }
+
     /// Get a snapshot of collected statistics.
     #[must_use]
     pub fn stats(&self) -> Stats {
@@ -872,10 +1152,51 @@ impl Connection {
             let p = p.borrow();
             v.rtt = p.rtt().estimate();
             v.rttvar = p.rtt().rttvar();
+            v.pmtu = p.mtu();
+            v.cwnd = p.sender().cwnd();
+            v.bytes_in_flight = p.sender().bytes_in_flight();
+            v.ecn.validation = p.ecn_outcome();
         }
         v
     }
 
+    /// Get the statistics for loss recovery, along with the congestion control
+    /// and RTT values for the primary path.
+    #[must_use]
+    pub fn recovery_stats(&self) -> RecoveryStats {
+        let stats = self.stats.borrow();
+        let mut v = RecoveryStats {
+            packets_sent: stats.packets_tx,
+            packets_acked: stats.acked,
+            packets_lost: stats.lost,
+            spurious_losses: stats.late_ack,
+            pto_count: stats.pto_count,
+            ..RecoveryStats::default()
+        };
+        if let Some(p) = self.paths.primary() {
+            let p = p.borrow();
+            let cc = p.sender().snapshot(p.rtt().estimate());
+            v.cwnd = cc.cwnd;
+            v.ssthresh = cc.ssthresh;
+            v.bytes_in_flight = cc.bytes_in_flight;
+            v.latest_rtt = p.rtt().latest();
+            v.min_rtt = p.rtt().minimum();
+            v.smoothed_rtt = p.rtt().estimate();
+            v.rttvar = p.rtt().rttvar();
+        }
+        v
+    }
+
+    /// Get the current state of congestion control for the primary path.
+    /// This is cheap enough to call frequently.
+    /// Returns `None` if there is no primary path yet.
+    #[must_use]
+    pub fn cc_snapshot(&self) -> Option<CcSnapshot> {
+        let path = self.paths.primary()?;
+        let path = path.borrow();
+        Some(path.sender().snapshot(path.rtt().estimate()))
+    }
+
     // This function wraps a call to another function and sets the connection state
     // properly if that call fails.
     fn capture_error<T>(
@@ -1000,12 +1321,43 @@ impl Connection {
         if dgrams.peek().is_none() {
             return;
         }
+        let _span = qspan!("Connection::process_input", self);
+        let _handshake = self.handshake_span();
 
+        self.events.set_now(now);
+        self.last_input_diag = self.stats.borrow_mut().take_diag();
         for d in dgrams {
+            self.stats.borrow_mut().datagrams_rx += 1;
             self.input(d, now, now);
         }
         self.process_saved(now);
         self.streams.cleanup_closed_streams();
+        self.last_input_diag = self.stats.borrow_mut().take_diag();
+    }
+
+    /// What happened to the packets given to the most recent call to
+    /// `process_input` or `process_multiple_input`.  This includes any saved
+    /// packets that could be processed as a result.
+    #[must_use]
+    pub fn last_input_diag(&self) -> &ProcessDiag {
+        &self.last_input_diag
+    }
+
+    /// Counters for the DATAGRAM frames that this connection sent, received,
+    /// or dropped since it was created or since the last call to
+    /// `reset_datagram_stats`.
+    #[must_use]
+    pub fn datagram_stats(&self) -> DatagramStats {
+        self.stats
+            .borrow()
+            .datagram_tx
+            .delta(&self.datagram_stats_base)
+    }
+
+    /// Start counting DATAGRAM frames from zero.  This does not change the
+    /// totals in `stats()`.
+    pub fn reset_datagram_stats(&mut self) {
+        self.datagram_stats_base = self.stats.borrow().datagram_tx.clone();
     }
 
     /// Get the time that we next need to be called back, relative to `now`.
@@ -1018,8 +1370,12 @@ impl Connection {
             return timeout.duration_since(now);
         }
 
-        let mut delays = SmallVec::<[_; 6]>::new();
-        if let Some(ack_time) = self.acks.ack_time(now) {
+        let mut delays = SmallVec::<[_; 7]>::new();
+        let rtt = self.paths.primary().map_or_else(
+            || RttEstimate::default().estimate(),
+            |p| p.borrow().rtt().estimate(),
+        );
+        if let Some(ack_time) = self.acks.ack_time(now, rtt) {
             qtrace!([self], "Delayed ACK timer {:?}", ack_time);
             delays.push(ack_time);
         }
@@ -1033,6 +1389,17 @@ impl Connection {
             qtrace!([self], "Idle/keepalive timer {:?}", idle_time);
             delays.push(idle_time);
 
+            if self.state.connected() {
+                if let Some(keep_alive) = self
+                    .idle_timeout
+                    .next_keep_alive(now, pto)
+                    .filter(|t| *t > now)
+                {
+                    qtrace!([self], "Keep-alive timer {:?}", keep_alive);
+                    delays.push(keep_alive);
+                }
+            }
+
             if let Some(lr_time) = self.loss_recovery.next_timeout(rtt) {
                 qtrace!([self], "Loss recovery timer {:?}", lr_time);
                 delays.push(lr_time);
@@ -1046,7 +1413,7 @@ impl Connection {
             }
 
             if let Some(path_time) = self.paths.next_timeout(pto) {
-                qtrace!([self], "Path probe timer {:?}", path_time);
+                qtrace!([self], "Path timer {:?}", path_time);
                 delays.push(path_time);
             }
         }
@@ -1077,7 +1444,17 @@ impl Connection {
     /// even if no incoming packets.
     #[must_use = "Output of the process_output function must be handled"]
     pub fn process_output(&mut self, now: Instant) -> Output {
+        #[cfg(feature = "test-network")]
+        if let Some(mut net) = self.test_network.take() {
+            let output = net.process(now, |now| self.process_output(now));
+            self.test_network = Some(net);
+            return output;
+        }
+
+        let _span = qspan!("Connection::process_output", self);
+        let _handshake = self.handshake_span();
         qtrace!([self], "process_output {:?} {:?}", self.state, now);
+        self.events.set_now(now);
 
         match (&self.state, self.role) {
             (State::Init, Role::Client) => {
@@ -1093,7 +1470,10 @@ impl Connection {
         }
 
         match self.output(now) {
-            SendOption::Yes(dgram) => Output::Datagram(dgram),
+            SendOption::Yes(dgram) => {
+                self.output_queue_unblock();
+                Output::Datagram(dgram)
+            }
             SendOption::No(paced) => match self.state {
                 State::Init | State::Closed(_) => Output::None,
                 State::Closing { timeout, .. } | State::Draining { timeout, .. } => {
@@ -1107,7 +1487,10 @@ impl Connection {
     /// Process input and generate output.
     #[must_use = "Output of the process function must be handled"]
     pub fn process(&mut self, dgram: Option<&Datagram>, now: Instant) -> Output {
+        let _span = qspan!("Connection::process", self);
         if let Some(d) = dgram {
+            self.events.set_now(now);
+            self.stats.borrow_mut().datagrams_rx += 1;
             self.input(d, now, now);
             self.process_saved(now);
         }
@@ -1125,17 +1508,21 @@ impl Connection {
     fn handle_retry(&mut self, packet: &PublicPacket, now: Instant) {
         qinfo!([self], "received Retry");
         if matches!(self.address_validation, AddressValidationInfo::Retry { .. }) {
-            self.stats.borrow_mut().pkt_dropped("Extra Retry");
+            self.stats
+                .borrow_mut()
+                .pkt_dropped(PacketDropReason::ExtraRetry);
             return;
         }
         if packet.token().is_empty() {
-            self.stats.borrow_mut().pkt_dropped("Retry without a token");
+            self.stats
+                .borrow_mut()
+                .pkt_dropped(PacketDropReason::RetryWithoutToken);
             return;
         }
         if !packet.is_valid_retry(self.original_destination_cid.as_ref().unwrap()) {
             self.stats
                 .borrow_mut()
-                .pkt_dropped("Retry with bad integrity tag");
+                .pkt_dropped(PacketDropReason::RetryIntegrity);
             return;
         }
         // At this point, we should only have the connection ID that we generated.
@@ -1143,7 +1530,7 @@ impl Connection {
         let Some(path) = self.paths.primary() else {
             self.stats
                 .borrow_mut()
-                .pkt_dropped("Retry without an existing path");
+                .pkt_dropped(PacketDropReason::RetryWithoutPath);
             return;
         };
 
@@ -1240,7 +1627,7 @@ impl Connection {
             d.clone()
         };
         self.saved_datagrams.save(cspace, d, now);
-        self.stats.borrow_mut().saved_datagrams += 1;
+        self.stats.borrow_mut().datagram_saved();
     }
 
     /// Perform version negotiation.
@@ -1301,7 +1688,7 @@ impl Connection {
         if dcid.map_or(false, |d| d != &packet.dcid()) {
             self.stats
                 .borrow_mut()
-                .pkt_dropped("Coalesced packet has different DCID");
+                .pkt_dropped(PacketDropReason::CoalescedDcidMismatch);
             return Ok(PreprocessResult::Next);
         }
 
@@ -1322,7 +1709,9 @@ impl Connection {
                 if !packet.is_valid_initial()
                     || !self.conn_params.get_versions().all().contains(&version)
                 {
-                    self.stats.borrow_mut().pkt_dropped("Invalid Initial");
+                    self.stats
+                        .borrow_mut()
+                        .pkt_dropped(PacketDropReason::InvalidInitial);
                     return Ok(PreprocessResult::Next);
                 }
                 qinfo!(
@@ -1359,12 +1748,16 @@ impl Connection {
                         // Ignore VersionNegotiation packets that contain the current version.
                         // Or don't have the right connection ID.
                         // Or are received after a Retry.
-                        self.stats.borrow_mut().pkt_dropped("Invalid VN");
+                        self.stats
+                            .borrow_mut()
+                            .pkt_dropped(PacketDropReason::InvalidVersionNegotiation);
                     } else {
                         self.version_negotiation(&versions, now)?;
                     }
                 } else {
-                    self.stats.borrow_mut().pkt_dropped("VN with no versions");
+                    self.stats
+                        .borrow_mut()
+                        .pkt_dropped(PacketDropReason::EmptyVersionNegotiation);
                 };
                 return Ok(PreprocessResult::End);
             }
@@ -1386,9 +1779,10 @@ impl Connection {
                 }
             }
             (PacketType::VersionNegotiation | PacketType::Retry | PacketType::OtherVersion, ..) => {
+                qdebug!([self], "Unexpected {:?} packet", packet.packet_type());
                 self.stats
                     .borrow_mut()
-                    .pkt_dropped(format!("{:?}", packet.packet_type()));
+                    .pkt_dropped(PacketDropReason::UnexpectedType);
                 return Ok(PreprocessResult::Next);
             }
             _ => {}
@@ -1398,7 +1792,7 @@ impl Connection {
             State::Init => {
                 self.stats
                     .borrow_mut()
-                    .pkt_dropped("Received while in Init state");
+                    .pkt_dropped(PacketDropReason::NotStarted);
                 PreprocessResult::Next
             }
             State::WaitInitial => PreprocessResult::Continue,
@@ -1410,9 +1804,10 @@ impl Connection {
                     }
                     PreprocessResult::Continue
                 } else {
+                    qdebug!([self], "Invalid DCID {:?}", packet.dcid());
                     self.stats
                         .borrow_mut()
-                        .pkt_dropped(format!("Invalid DCID {:?}", packet.dcid()));
+                        .pkt_dropped(PacketDropReason::UnknownConnectionId);
                     PreprocessResult::Next
                 }
             }
@@ -1426,7 +1821,7 @@ impl Connection {
                 // Do nothing.
                 self.stats
                     .borrow_mut()
-                    .pkt_dropped(format!("State {:?}", self.state));
+                    .pkt_dropped(PacketDropReason::Closed);
                 PreprocessResult::Next
             }
         };
@@ -1445,6 +1840,10 @@ impl Connection {
         let space = PacketNumberSpace::from(packet.packet_type());
         if let Some(space) = self.acks.get_mut(space) {
             *space.ecn_marks() += d.tos().into();
+            self.stats.borrow_mut().ecn.rx += d.tos().into();
+            if IpTosEcn::from(d.tos()) == IpTosEcn::Ce {
+                space.ce_received(now);
+            }
         } else {
             qtrace!("Not tracking ECN for dropped packet number space");
         }
@@ -1469,7 +1868,7 @@ impl Connection {
     /// This takes two times: when the datagram was received, and the current time.
     fn input(&mut self, d: &Datagram, received: Instant, now: Instant) {
         // First determine the path.
-        let path = self.paths.find_path_with_rebinding(
+        let path = self.paths.find_path(
             d.destination(),
             d.source(),
             self.conn_params.get_cc_algorithm(),
@@ -1477,6 +1876,8 @@ impl Connection {
             now,
         );
         path.borrow_mut().add_received(d.len());
+        path.borrow_mut().used(now);
+        self.stats.borrow_mut().timings.start(now);
         let res = self.input_path(&path, d, received);
         self.capture_error(Some(path), now, 0, res).ok();
     }
@@ -1486,7 +1887,9 @@ impl Connection {
         let mut dcid = None;
 
         qtrace!([self], "{} input {}", path.borrow(), hex(&**d));
-        let pto = path.borrow().rtt().pto(PacketNumberSpace::ApplicationData);
+        // Old read keys are retained for some number of PTOs after a key update.
+        let read_key_retention = path.borrow().rtt().pto(PacketNumberSpace::ApplicationData)
+            * self.conn_params.get_read_key_retention();
 
         // Handle each packet in the datagram.
         while !slc.is_empty() {
@@ -1497,7 +1900,9 @@ impl Connection {
                     Err(e) => {
                         qinfo!([self], "Garbage packet: {}", e);
                         qtrace!([self], "Garbage packet contents: {}", hex(slc));
-                        self.stats.borrow_mut().pkt_dropped("Garbage packet");
+                        self.stats
+                            .borrow_mut()
+                            .pkt_dropped(PacketDropReason::Undecodable);
                         break;
                     }
                 };
@@ -1509,7 +1914,7 @@ impl Connection {
 
             qtrace!([self], "Received unverified packet {:?}", packet);
 
-            match packet.decrypt(&mut self.crypto.states, now + pto) {
+            match packet.decrypt(&mut self.crypto.states, now + read_key_retention) {
                 Ok(payload) => {
                     // OK, we have a valid packet.
                     self.idle_timeout.on_packet_received(now);
@@ -1535,12 +1940,25 @@ impl Connection {
 
                     qlog::packet_received(&mut self.qlog, &packet, &payload);
                     let space = PacketNumberSpace::from(payload.packet_type());
+                    {
+                        let mut stats = self.stats.borrow_mut();
+                        stats.bytes_rx.add(space, packet.len());
+                        if space == PacketNumberSpace::Initial {
+                            stats.timings.initial_received(now);
+                        }
+                    }
                     if self.acks.get_mut(space).unwrap().is_duplicate(payload.pn()) {
                         qdebug!([self], "Duplicate packet {}-{}", space, payload.pn());
-                        self.stats.borrow_mut().dups_rx += 1;
+                        self.stats.borrow_mut().pkt_duplicate();
                     } else {
                         match self.process_packet(path, &payload, now) {
-                            Ok(migrate) => self.postprocess_packet(path, d, &packet, migrate, now),
+                            Ok(migrate) => {
+                                self.stats.borrow_mut().pkt_processed();
+                                if packet.packet_type() == PacketType::Short {
+                                    self.spin.received(self.role, payload.pn(), packet.spin());
+                                }
+                                self.postprocess_packet(path, d, &packet, migrate, now);
+                            }
                             Err(e) => {
                                 self.ensure_error_path(path, &packet, now);
                                 return Err(e);
@@ -1567,13 +1985,21 @@ impl Connection {
                             self.received_untracked |=
                                 self.role == Role::Client && cspace == CryptoSpace::Initial;
                         }
+                        Error::KeyPhaseAmbiguous => {
+                            self.stats.borrow_mut().key_phase_failures_rx += 1;
+                        }
+                        Error::DecryptError | Error::CryptoError(_) => {
+                            self.stats.borrow_mut().decrypt_failures_rx += 1;
+                        }
                         _ => (),
                     }
                     // Decryption failure, or not having keys is not fatal.
                     // If the state isn't available, or we can't decrypt the packet, drop
                     // the rest of the datagram on the floor, but don't generate an error.
                     self.check_stateless_reset(path, d, dcid.is_none(), now)?;
-                    self.stats.borrow_mut().pkt_dropped("Decryption failure");
+                    self.stats
+                        .borrow_mut()
+                        .pkt_dropped(PacketDropReason::DecryptionFailure);
                     qlog::packet_dropped(&mut self.qlog, &packet);
                 }
             }
@@ -1600,15 +2026,41 @@ impl Connection {
         // on the assert for doesn't exist.
         // OK, we have a valid packet.
 
-        let mut ack_eliciting = false;
-        let mut probing = true;
+        // Decode all of the frames before acting on any of them.  That way, a
+        // malformed frame causes the entire packet to be rejected, rather than
+        // leaving the effects of the frames that preceded it in place.
+        let mut frames = SmallVec::<[Frame; 8]>::new();
         let mut d = Decoder::from(&packet[..]);
         while d.remaining() > 0 {
-            #[cfg(feature = "build-fuzzing-corpus")]
             let pos = d.offset();
-            let f = Frame::decode(&mut d)?;
-            #[cfg(feature = "build-fuzzing-corpus")]
-            neqo_common::write_item_to_fuzzing_corpus("frame", &packet[pos..d.offset()]);
+            match Frame::decode(&mut d) {
+                Ok(f) => {
+                    #[cfg(feature = "build-fuzzing-corpus")]
+                    neqo_common::write_item_to_fuzzing_corpus("frame", &packet[pos..d.offset()]);
+                    frames.push(f);
+                }
+                Err(e) => {
+                    if e == Error::UnknownFrameType {
+                        self.stats.borrow_mut().frame_rx.add_unknown();
+                    }
+                    let t = Frame::peek_type(&packet[pos..]);
+                    qinfo!([self], "Unable to decode frame of type {:x}: {:?}", t, e);
+                    return self.capture_error(Some(Rc::clone(path)), now, t, Err(e));
+                }
+            }
+        }
+
+        match packet.packet_type() {
+            PacketType::Initial => self.advance_handshake_phase(HandshakePhase::InitialReceived),
+            PacketType::Handshake => {
+                self.advance_handshake_phase(HandshakePhase::HandshakeReceived);
+            }
+            _ => {}
+        }
+
+        let mut ack_eliciting = false;
+        let mut probing = true;
+        for f in frames {
             ack_eliciting |= f.ack_eliciting();
             probing &= f.path_probing();
             let t = f.get_type();
@@ -1656,23 +2108,28 @@ impl Connection {
                     .unwrap()
                     .clone(),
             ),
+            PathTrigger::Handshake,
         );
         path.borrow_mut().set_valid(now);
     }
 
     /// If the path isn't permanent, assign it a connection ID to make it so.
-    fn ensure_permanent(&mut self, path: &PathRef) -> Res<()> {
+    fn ensure_permanent(&mut self, path: &PathRef, trigger: PathTrigger) -> Res<()> {
         if self.paths.is_temporary(path) {
             // If there isn't a connection ID to use for this path, the packet
             // will be processed, but it won't be attributed to a path.  That means
             // no path probes or PATH_RESPONSE.  But it's not fatal.
             if let Some(cid) = self.connection_ids.next() {
-                self.paths.make_permanent(path, None, cid);
+                self.paths.make_permanent(path, None, cid, trigger);
                 Ok(())
             } else if let Some(primary) = self.paths.primary() {
                 if primary.borrow().remote_cid().is_empty() {
-                    self.paths
-                        .make_permanent(path, None, ConnectionIdEntry::empty_remote());
+                    self.paths.make_permanent(
+                        path,
+                        None,
+                        ConnectionIdEntry::empty_remote(),
+                        trigger,
+                    );
                     Ok(())
                 } else {
                     qtrace!([self], "Unable to make path permanent: {}", path.borrow());
@@ -1699,7 +2156,7 @@ impl Connection {
                 self.setup_handshake_path(path, now);
             } else {
                 // Otherwise try to get a usable connection ID.
-                mem::drop(self.ensure_permanent(path));
+                mem::drop(self.ensure_permanent(path, PathTrigger::PeerAddressChange));
             }
         }
     }
@@ -1757,6 +2214,27 @@ impl Connection {
         remote: Option<SocketAddr>,
         force: bool,
         now: Instant,
+    ) -> Res<()> {
+        if self.state().connected()
+            && self
+                .tps
+                .borrow()
+                .remote()
+                .get_empty(tparams::DISABLE_MIGRATION)
+        {
+            qinfo!([self], "Peer does not permit migration");
+            return Err(Error::InvalidMigration);
+        }
+        self.migrate_path(local, remote, force, PathTrigger::Application, now)
+    }
+
+    fn migrate_path(
+        &mut self,
+        local: Option<SocketAddr>,
+        remote: Option<SocketAddr>,
+        force: bool,
+        trigger: PathTrigger,
+        now: Instant,
     ) -> Res<()> {
         if self.role != Role::Client {
             return Err(Error::InvalidMigration);
@@ -1796,14 +2274,14 @@ impl Connection {
             self.conn_params.pacing_enabled(),
             now,
         );
-        self.ensure_permanent(&path)?;
+        self.ensure_permanent(&path, trigger)?;
         qinfo!(
             [self],
             "Migrate to {} probe {}",
             path.borrow(),
             if force { "now" } else { "after" }
         );
-        if self.paths.migrate(&path, force, now) {
+        if self.paths.migrate(&path, force, trigger, now) {
             self.loss_recovery.migrate();
         }
         Ok(())
@@ -1845,7 +2323,17 @@ impl Connection {
                     return Ok(());
                 }
 
-                if self.migrate(None, Some(remote), false, now).is_err() {
+                // The peer asking us not to migrate doesn't apply here.
+                if self
+                    .migrate_path(
+                        None,
+                        Some(remote),
+                        false,
+                        PathTrigger::PreferredAddress,
+                        now,
+                    )
+                    .is_err()
+                {
                     qwarn!([self], "Ignoring bad preferred address: {}", remote);
                 }
             } else {
@@ -1855,6 +2343,22 @@ impl Connection {
         Ok(())
     }
 
+    /// Whether the peer is allowed to move the connection to `path`.
+    /// If we asked the peer not to migrate, the only path it can move to
+    /// is one to the preferred address that we offered.
+    fn migration_permitted(&self, path: &PathRef) -> bool {
+        if !self.conn_params.migration_disabled() {
+            return true;
+        }
+        let PreferredAddressConfig::Address(spa) = self.conn_params.get_preferred_address() else {
+            return false;
+        };
+        match canonical_address(path.borrow().local_address()) {
+            SocketAddr::V4(local) => spa.ipv4() == Some(local),
+            SocketAddr::V6(local) => spa.ipv6() == Some(local),
+        }
+    }
+
     fn handle_migration(&mut self, path: &PathRef, d: &Datagram, migrate: bool, now: Instant) {
         if !migrate {
             return;
@@ -1863,8 +2367,32 @@ impl Connection {
             return;
         }
 
-        if self.ensure_permanent(path).is_ok() {
-            self.paths.handle_migration(path, d.source(), now);
+        if self
+            .ensure_permanent(path, PathTrigger::PeerAddressChange)
+            .is_ok()
+        {
+            if self.multipath_enabled()
+                && self.paths.add_active(
+                    path,
+                    d.source(),
+                    usize::from(self.conn_params.get_max_paths()),
+                    now,
+                )
+            {
+                qtrace!([self], "{} Packet on an additional path", path.borrow());
+            } else if self.migration_permitted(path) {
+                self.paths.handle_migration(
+                    path,
+                    d.source(),
+                    self.conn_params.fast_rebinding_enabled(),
+                    now,
+                );
+            } else if self
+                .paths
+                .handle_unpermitted_migration(path, d.source(), now)
+            {
+                self.loss_recovery.migrate();
+            }
         } else {
             qinfo!(
                 [self],
@@ -1920,6 +2448,7 @@ impl Connection {
         address_validation: &AddressValidationInfo,
         version: Version,
         grease_quic_bit: bool,
+        spin: bool,
     ) -> (PacketType, PacketBuilder) {
         let pt = PacketType::from(cspace);
         let mut builder = if pt == PacketType::Short {
@@ -1937,6 +2466,9 @@ impl Connection {
         };
         if builder.remaining() > 0 {
             builder.scramble(grease_quic_bit);
+            if pt == PacketType::Short {
+                builder.set_spin(spin);
+            }
             if pt == PacketType::Initial {
                 builder.initial_token(address_validation.token());
             }
@@ -1986,12 +2518,9 @@ impl Connection {
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
     ) {
-        let stats = &mut self.stats.borrow_mut();
-        let frame_stats = &mut stats.frame_tx;
         if self.role == Role::Server {
             if let Some(t) = self.state_signaling.write_done(builder) {
                 tokens.push(t);
-                frame_stats.handshake_done += 1;
             }
         }
 
@@ -1999,58 +2528,52 @@ impl Connection {
             TransmissionPriority::Critical,
             TransmissionPriority::Important,
         ] {
-            self.streams
-                .write_frames(prio, builder, tokens, frame_stats);
+            self.streams.write_frames(prio, builder, tokens);
             if builder.is_full() {
                 return;
             }
         }
 
         // NEW_CONNECTION_ID, RETIRE_CONNECTION_ID, and ACK_FREQUENCY.
-        self.cid_manager.write_frames(builder, tokens, frame_stats);
+        self.cid_manager.write_frames(builder, tokens);
         if builder.is_full() {
             return;
         }
 
-        self.paths.write_frames(builder, tokens, frame_stats);
+        self.paths.write_frames(builder, tokens);
         if builder.is_full() {
             return;
         }
 
         for prio in [TransmissionPriority::High, TransmissionPriority::Normal] {
-            self.streams
-                .write_frames(prio, builder, tokens, &mut stats.frame_tx);
+            self.streams.write_frames(prio, builder, tokens);
             if builder.is_full() {
                 return;
             }
         }
 
         // Datagrams are best-effort and unreliable.  Let streams starve them for now.
-        self.quic_datagrams.write_frames(builder, tokens, stats);
+        self.quic_datagrams
+            .write_frames(builder, tokens, &mut self.stats.borrow_mut());
         if builder.is_full() {
             return;
         }
 
         // CRYPTO here only includes NewSessionTicket, plus NEW_TOKEN.
         // Both of these are only used for resumption and so can be relatively low priority.
-        let frame_stats = &mut stats.frame_tx;
-        self.crypto.write_frame(
-            PacketNumberSpace::ApplicationData,
-            builder,
-            tokens,
-            frame_stats,
-        );
+        self.crypto
+            .write_frame(PacketNumberSpace::ApplicationData, builder, tokens);
         if builder.is_full() {
             return;
         }
 
-        self.new_token.write_frames(builder, tokens, frame_stats);
+        self.new_token.write_frames(builder, tokens);
         if builder.is_full() {
             return;
         }
 
         self.streams
-            .write_frames(TransmissionPriority::Low, builder, tokens, frame_stats);
+            .write_frames(TransmissionPriority::Low, builder, tokens);
 
         #[cfg(test)]
         if let Some(w) = &mut self.test_frame_writer {
@@ -2087,7 +2610,7 @@ impl Connection {
                 // The packet only contains an ACK.  Check whether we want to
                 // force an ACK with a PING so we can stop tracking packets.
                 self.loss_recovery.should_probe(pto, now)
-            } else if self.streams.need_keep_alive() {
+            } else if self.streams.need_keep_alive() || self.idle_timeout.keep_alive_enabled() {
                 // We need to keep the connection alive, including sending
                 // a PING again.
                 self.idle_timeout.send_keep_alive(now, pto, tokens)
@@ -2099,9 +2622,6 @@ impl Connection {
             // Nothing ack-eliciting and we need to probe; send PING.
             debug_assert_ne!(builder.remaining(), 0);
             builder.encode_varint(crate::frame::FRAME_TYPE_PING);
-            let stats = &mut self.stats.borrow_mut().frame_tx;
-            stats.ping += 1;
-            stats.all += 1;
         }
         probe
     }
@@ -2122,14 +2642,12 @@ impl Connection {
         let mut ack_eliciting = false;
 
         if primary {
-            let stats = &mut self.stats.borrow_mut().frame_tx;
             self.acks.write_frame(
                 space,
                 now,
                 path.borrow().rtt().estimate(),
                 builder,
                 &mut tokens,
-                stats,
             );
         }
         let ack_end = builder.len();
@@ -2140,12 +2658,7 @@ impl Connection {
         if space == PacketNumberSpace::ApplicationData && self.state.connected() {
             // Probes should only be padded if the full MTU is available.
             // The probing code needs to know so it can track that.
-            if path.borrow_mut().write_frames(
-                builder,
-                &mut self.stats.borrow_mut().frame_tx,
-                full_mtu,
-                now,
-            ) {
+            if path.borrow_mut().write_frames(builder, full_mtu, now) {
                 builder.enable_padding(true);
             }
         }
@@ -2159,8 +2672,7 @@ impl Connection {
             if space == PacketNumberSpace::ApplicationData {
                 self.write_appdata_frames(builder, &mut tokens);
             } else {
-                let stats = &mut self.stats.borrow_mut().frame_tx;
-                self.crypto.write_frame(space, builder, &mut tokens, stats);
+                self.crypto.write_frame(space, builder, &mut tokens);
             }
         }
 
@@ -2170,20 +2682,18 @@ impl Connection {
         // If this is not the primary path, this should be ack-eliciting.
         debug_assert!(primary || ack_eliciting);
 
+        // This packet is going to be sent anyway, so include an ACK if
+        // one is pending, even if the delayed ACK timer hasn't expired.
+        if primary && ack_eliciting {
+            self.acks
+                .write_piggyback_frame(space, now, builder, &mut tokens);
+        }
+
         // Add padding.  Only pad 1-RTT packets so that we don't prevent coalescing.
         // And avoid padding packets that otherwise only contain ACK because adding PADDING
         // causes those packets to consume congestion window, which is not tracked (yet).
         // And avoid padding if we don't have a full MTU available.
-        let stats = &mut self.stats.borrow_mut().frame_tx;
-        let padded = if ack_eliciting && full_mtu && builder.pad() {
-            stats.padding += 1;
-            stats.all += 1;
-            true
-        } else {
-            false
-        };
-
-        stats.all += tokens.len();
+        let padded = ack_eliciting && full_mtu && builder.pad();
         (tokens, ack_eliciting, padded)
     }
 
@@ -2201,14 +2711,8 @@ impl Connection {
             let limit = builder.limit();
             builder.set_limit(limit - ClosingFrame::MIN_LENGTH);
             self.acks.immediate_ack(now);
-            self.acks.write_frame(
-                space,
-                now,
-                path.borrow().rtt().estimate(),
-                builder,
-                tokens,
-                &mut self.stats.borrow_mut().frame_tx,
-            );
+            self.acks
+                .write_frame(space, now, path.borrow().rtt().estimate(), builder, tokens);
             builder.set_limit(limit);
         }
         // CloseReason::Application is only allowed at 1RTT.
@@ -2218,7 +2722,42 @@ impl Connection {
             close.sanitize()
         };
         sanitized.as_ref().unwrap_or(close).write_frame(builder);
-        self.stats.borrow_mut().frame_tx.connection_close += 1;
+    }
+
+    /// Determine whether the next packet should be a PMTUD probe and how large it
+    /// should be.  Probes are only sent on their own, once the handshake is
+    /// confirmed, and only when the congestion window has room for them.
+    fn pmtud_probe_size(
+        &self,
+        path: &PathRef,
+        space: PacketNumberSpace,
+        profile: &SendProfile,
+        header_start: usize,
+        now: Instant,
+    ) -> Option<usize> {
+        if space != PacketNumberSpace::ApplicationData
+            || self.state != State::Confirmed
+            || header_start != 0
+            || profile.ack_only(space)
+            || profile.should_probe(space)
+        {
+            return None;
+        }
+        let mut path = path.borrow_mut();
+        if !path.is_primary() || !path.pmtud_mut().needs_probe(now) {
+            return None;
+        }
+        let size = path.pmtud().probe_size();
+        (path.sender().cwnd_avail() >= size).then_some(size)
+    }
+
+    /// Write a PMTUD probe, which is a PING padded to the size of the packet.
+    /// Probes don't carry anything else, so nothing needs to be repaired if they are lost.
+    fn write_pmtud_probe(&mut self, builder: &mut PacketBuilder) {
+        builder.encode_varint(crate::frame::FRAME_TYPE_PING);
+        builder.enable_padding(true);
+        let padded = builder.pad();
+        debug_assert!(padded);
     }
 
     /// Build a datagram, possibly from multiple packets (for different PN
@@ -2234,9 +2773,13 @@ impl Connection {
         let mut needs_padding = false;
         let grease_quic_bit = self.can_grease_quic_bit();
         let version = self.version();
+        // Forget about any datagram marking from a previous attempt to send.
+        mem::drop(self.quic_datagrams.take_dscp());
 
         // Determine how we are sending packets (PTO, etc..).
         let mtu = path.borrow().mtu();
+        // PMTUD probes are allowed to exceed the MTU.
+        let mut datagram_limit = mtu;
         let profile = self.loss_recovery.send_profile(&path.borrow(), now);
         qdebug!([self], "output_path send_profile {:?}", profile);
 
@@ -2258,6 +2801,7 @@ impl Connection {
                 &self.address_validation,
                 version,
                 grease_quic_bit,
+                self.spin.value(),
             );
             let pn = Self::add_packet_number(
                 &mut builder,
@@ -2285,6 +2829,14 @@ impl Connection {
             let (mut tokens, mut ack_eliciting, mut padded) = (Vec::new(), false, false);
             if let Some(ref close) = closing_frame {
                 self.write_closing_frames(close, &mut builder, *space, now, path, &mut tokens);
+            } else if let Some(size) =
+                self.pmtud_probe_size(path, *space, &profile, header_start, now)
+            {
+                builder.set_limit(size - aead_expansion);
+                self.write_pmtud_probe(&mut builder);
+                path.borrow_mut().pmtud_mut().probe_sent();
+                (ack_eliciting, padded) = (true, true);
+                datagram_limit = size;
             } else {
                 (tokens, ack_eliciting, padded) =
                     self.write_frames(path, *space, &profile, &mut builder, now);
@@ -2312,11 +2864,50 @@ impl Connection {
                 &builder.as_ref()[payload_start..],
             );
 
-            self.stats.borrow_mut().packets_tx += 1;
+            let ack_only = !ack_eliciting
+                && closing_frame.is_none()
+                && tokens.iter().any(|t| matches!(t, RecoveryToken::Ack(_)));
+            {
+                let mut stats = self.stats.borrow_mut();
+                stats.packets_tx += 1;
+                stats.ack_only_tx += usize::from(ack_only);
+            }
             let tx = self.crypto.states.tx_mut(self.version, cspace).unwrap();
+            // Every frame that is sent is counted here, including any PADDING
+            // that header protection needs.
+            builder.pad_for_crypto(tx);
+            self.stats
+                .borrow_mut()
+                .frame_tx
+                .add_payload(&builder.as_ref()[payload_start..]);
             encoder = builder.build(tx)?;
-            debug_assert!(encoder.len() <= mtu);
+            {
+                let mut stats = self.stats.borrow_mut();
+                stats.bytes_tx.add(*space, encoder.len() - header_start);
+                if pt == PacketType::Initial {
+                    stats.timings.initial_sent(now);
+                }
+                if tokens
+                    .iter()
+                    .any(|t| matches!(t, RecoveryToken::Stream(StreamRecoveryToken::Stream(_))))
+                {
+                    stats.timings.stream_sent(now);
+                }
+            }
+            match pt {
+                PacketType::Initial => self.advance_handshake_phase(HandshakePhase::InitialSent),
+                PacketType::Handshake => {
+                    self.advance_handshake_phase(HandshakePhase::HandshakeSent);
+                }
+                _ => {}
+            }
+            debug_assert!(encoder.len() <= datagram_limit);
             self.crypto.states.auto_update()?;
+            if self.crypto.states.key_update_due() {
+                // This fails if an earlier update hasn't been acknowledged yet;
+                // in that case, try again after the next packet.
+                mem::drop(self.initiate_key_update());
+            }
 
             if ack_eliciting {
                 self.idle_timeout.on_packet_sent(now);
@@ -2334,14 +2925,12 @@ impl Connection {
                 needs_padding = false;
                 self.loss_recovery.on_packet_sent(path, sent);
             } else if pt == PacketType::Initial && (self.role == Role::Client || ack_eliciting) {
-                // Packets containing Initial packets might need padding, and we want to
-                // track that padding along with the Initial packet.  So defer tracking.
+                // Datagrams containing Initial packets need padding, no matter what other
+                // packets are coalesced with the Initial (RFC 9000, Section 14.1).  We want
+                // to track that padding along with the Initial packet.  So defer tracking.
                 initial_sent = Some(sent);
                 needs_padding = true;
             } else {
-                if pt == PacketType::Handshake && self.role == Role::Client {
-                    needs_padding = false;
-                }
                 self.loss_recovery.on_packet_sent(path, sent);
             }
 
@@ -2374,13 +2963,49 @@ impl Connection {
                     // packet, which is why we don't increase `frame_tx.padding` count here.
                     packets.resize(mtu, 0);
                 }
+                debug_assert!(
+                    packets.len() >= MIN_INITIAL_PACKET_SIZE,
+                    "datagram with Initial packet is too small: {}",
+                    packets.len()
+                );
                 self.loss_recovery.on_packet_sent(path, initial);
             }
             path.borrow_mut().add_sent(packets.len());
-            Ok(SendOption::Yes(path.borrow_mut().datagram(packets)))
+            path.borrow_mut().used(now);
+            let mut d = path.borrow_mut().datagram(packets, now);
+            if let Some(dscp) = self.quic_datagrams.take_dscp() {
+                let mut tos = d.tos();
+                tos.set_dscp(dscp);
+                d.set_tos(tos);
+            }
+            let mut stats = self.stats.borrow_mut();
+            stats.datagrams_tx += 1;
+            if IpTosEcn::from(d.tos()) != IpTosEcn::NotEct {
+                stats.ecn.tx_marked += 1;
+            }
+            Ok(SendOption::Yes(d))
         }
     }
 
+    /// Send a PING when the connection has been idle for `interval`, so that
+    /// middleboxes keep state for a connection that is quiet for long periods.
+    /// An interval longer than half the idle timeout is shortened to that, so
+    /// that the connection does not time out.
+    /// `None`, the default, only sends PING when a stream needs it, see
+    /// `stream_keep_alive`.
+    pub fn set_keep_alive(&mut self, interval: Option<Duration>) {
+        self.idle_timeout.set_keep_alive_interval(interval);
+    }
+
+    /// Update 1-RTT keys automatically after sending `after_packets` packets or
+    /// `after_bytes` bytes with the current keys, whichever comes first.
+    pub fn set_key_update_policy(&mut self, after_packets: Option<u64>, after_bytes: Option<u64>) {
+        self.crypto.states.set_key_update_policy(KeyUpdatePolicy {
+            after_packets,
+            after_bytes,
+        });
+    }
+
     /// # Errors
     /// When connection state is not valid.
     pub fn initiate_key_update(&mut self) -> Res<()> {
@@ -2427,10 +3052,39 @@ impl Connection {
 
     /// Close the connection.
     pub fn close(&mut self, now: Instant, app_error: AppError, msg: impl AsRef<str>) {
-        let error = CloseReason::Application(app_error);
+        self.close_with_reason(now, CloseReason::Application(app_error), 0, msg);
+    }
+
+    /// Close the connection with a transport error code rather than an
+    /// application error code, for when the application finds that the peer
+    /// is misbehaving at the protocol layer.  `frame_type` is the type of the
+    /// frame that caused the error, if there was one.
+    pub fn close_with_transport_error(
+        &mut self,
+        now: Instant,
+        error: TransportError,
+        frame_type: Option<FrameType>,
+        msg: impl AsRef<str>,
+    ) {
+        self.close_with_reason(
+            now,
+            CloseReason::Transport(Error::LocalError(error)),
+            frame_type.unwrap_or(0),
+            msg,
+        );
+    }
+
+    fn close_with_reason(
+        &mut self,
+        now: Instant,
+        error: CloseReason,
+        frame_type: FrameType,
+        msg: impl AsRef<str>,
+    ) {
         let timeout = self.get_closing_period_time(now);
         if let Some(path) = self.paths.primary() {
-            self.state_signaling.close(path, error.clone(), 0, msg);
+            self.state_signaling
+                .close(path, error.clone(), frame_type, msg);
             self.set_state(State::Closing { error, timeout });
         } else {
             self.set_state(State::Closed(error));
@@ -2664,16 +3318,25 @@ impl Connection {
         let was_authentication_pending =
             *self.crypto.tls.state() == HandshakeState::AuthenticationPending;
         let try_update = data.is_some();
-        match self.crypto.handshake(now, space, data)? {
+        let hs_state = match self.crypto.handshake(now, space, data) {
+            Err(e @ Error::EchRetry(_)) => {
+                self.set_ech_state(EchState::RejectedWithRetry);
+                return Err(e);
+            }
+            res => res?,
+        };
+        match hs_state {
             HandshakeState::Authenticated(_) | HandshakeState::InProgress => (),
             HandshakeState::AuthenticationPending => {
                 if !was_authentication_pending {
                     self.events.authentication_needed();
                 }
             }
-            HandshakeState::EchFallbackAuthenticationPending(public_name) => self
-                .events
-                .ech_fallback_authentication_needed(public_name.clone()),
+            HandshakeState::EchFallbackAuthenticationPending(public_name) => {
+                self.events
+                    .ech_fallback_authentication_needed(public_name.clone());
+                self.set_ech_state(EchState::FallbackPublicName);
+            }
             HandshakeState::Complete(_) => {
                 if !self.state.connected() {
                     self.set_connected(now)?;
@@ -2683,6 +3346,7 @@ impl Connection {
                 unreachable!("Crypto state should not be new or failed after successful handshake")
             }
         }
+        self.check_ech();
 
         // There is a chance that this could be called less often, but getting the
         // conditions right is a little tricky, so call whenever CRYPTO data is used.
@@ -2718,21 +3382,16 @@ impl Connection {
             qinfo!("frame not allowed: {:?} {:?}", frame, packet_type);
             return Err(Error::ProtocolViolation);
         }
-        self.stats.borrow_mut().frame_rx.all += 1;
+        self.stats.borrow_mut().frame_rx.add(&frame);
         let space = PacketNumberSpace::from(packet_type);
         if frame.is_stream() {
-            return self
-                .streams
-                .input_frame(&frame, &mut self.stats.borrow_mut().frame_rx);
+            return self.streams.input_frame(&frame);
         }
         match frame {
-            Frame::Padding(length) => {
-                self.stats.borrow_mut().frame_rx.padding += usize::from(length);
-            }
+            Frame::Padding(_) => {}
             Frame::Ping => {
                 // If we get a PING and there are outstanding CRYPTO frames,
                 // prepare to resend them.
-                self.stats.borrow_mut().frame_rx.ping += 1;
                 self.crypto.resend_unacked(space);
                 if space == PacketNumberSpace::ApplicationData {
                     // Send an ACK immediately if we might not otherwise do so.
@@ -2765,7 +3424,6 @@ impl Connection {
                     offset,
                     &data
                 );
-                self.stats.borrow_mut().frame_rx.crypto += 1;
                 self.crypto.streams.inbound_frame(space, offset, data)?;
                 if self.crypto.streams.data_ready(space) {
                     let mut buf = Vec::new();
@@ -2779,7 +3437,6 @@ impl Connection {
                 }
             }
             Frame::NewToken { token } => {
-                self.stats.borrow_mut().frame_rx.new_token += 1;
                 self.new_token.save_token(token.to_vec());
                 self.create_resumption_token(now);
             }
@@ -2789,7 +3446,6 @@ impl Connection {
                 stateless_reset_token,
                 retire_prior,
             } => {
-                self.stats.borrow_mut().frame_rx.new_connection_id += 1;
                 self.connection_ids.add_remote(ConnectionIdEntry::new(
                     sequence_number,
                     ConnectionId::from(connection_id),
@@ -2803,18 +3459,15 @@ impl Connection {
                 }
             }
             Frame::RetireConnectionId { sequence_number } => {
-                self.stats.borrow_mut().frame_rx.retire_connection_id += 1;
                 self.cid_manager.retire(sequence_number);
             }
             Frame::PathChallenge { data } => {
-                self.stats.borrow_mut().frame_rx.path_challenge += 1;
                 // If we were challenged, try to make the path permanent.
                 // Report an error if we don't have enough connection IDs.
-                self.ensure_permanent(path)?;
+                self.ensure_permanent(path, PathTrigger::PeerAddressChange)?;
                 path.borrow_mut().challenged(data);
             }
             Frame::PathResponse { data } => {
-                self.stats.borrow_mut().frame_rx.path_response += 1;
                 if self.paths.path_response(data, now) {
                     // This PATH_RESPONSE enabled migration; tell loss recovery.
                     self.loss_recovery.migrate();
@@ -2825,7 +3478,6 @@ impl Connection {
                 frame_type,
                 reason_phrase,
             } => {
-                self.stats.borrow_mut().frame_rx.connection_close += 1;
                 qinfo!(
                     [self],
                     "ConnectionClose received. Error code: {:?} frame type {:x} reason {}",
@@ -2855,11 +3507,11 @@ impl Connection {
                 });
             }
             Frame::HandshakeDone => {
-                self.stats.borrow_mut().frame_rx.handshake_done += 1;
                 if self.role == Role::Server || !self.state.connected() {
                     return Err(Error::ProtocolViolation);
                 }
                 self.set_state(State::Confirmed);
+                self.note_confirmed(now);
                 self.discard_keys(PacketNumberSpace::Handshake, now);
                 self.migrate_to_preferred_address(now)?;
             }
@@ -2869,7 +3521,6 @@ impl Connection {
                 delay,
                 ignore_order,
             } => {
-                self.stats.borrow_mut().frame_rx.ack_frequency += 1;
                 let delay = Duration::from_micros(delay);
                 if delay < GRANULARITY {
                     return Err(Error::ProtocolViolation);
@@ -2878,7 +3529,6 @@ impl Connection {
                     .ack_freq(seqno, tolerance - 1, delay, ignore_order);
             }
             Frame::Datagram { data, .. } => {
-                self.stats.borrow_mut().frame_rx.datagram += 1;
                 self.quic_datagrams
                     .handle_datagram(data, &mut self.stats.borrow_mut())?;
             }
@@ -2955,7 +3605,12 @@ impl Connection {
         for acked in acked_packets {
             for token in acked.tokens() {
                 match token {
-                    RecoveryToken::Stream(stream_token) => self.streams.acked(stream_token),
+                    RecoveryToken::Stream(stream_token) => {
+                        if matches!(stream_token, StreamRecoveryToken::Stream(_)) {
+                            self.stats.borrow_mut().timings.stream_acked(now);
+                        }
+                        self.streams.acked(stream_token);
+                    }
                     RecoveryToken::Ack(at) => self.acks.acked(at),
                     RecoveryToken::Crypto(ct) => self.crypto.acked(ct),
                     RecoveryToken::NewToken(seqno) => self.new_token.acked(*seqno),
@@ -2973,9 +3628,6 @@ impl Connection {
         }
         self.handle_lost_packets(&lost_packets);
         qlog::packets_lost(&mut self.qlog, &lost_packets);
-        let stats = &mut self.stats.borrow_mut().frame_rx;
-        stats.ack += 1;
-        stats.largest_acknowledged = max(stats.largest_acknowledged, largest_acknowledged);
     }
 
     /// When the server rejects 0-RTT we need to drop a bunch of stuff.
@@ -3027,6 +3679,7 @@ impl Connection {
             .install_application_keys(self.version, now + pto)?;
         self.process_tps()?;
         self.set_state(State::Connected);
+        self.stats.borrow_mut().timings.handshake_completed(now);
         self.create_resumption_token(now);
         self.saved_datagrams
             .make_available(CryptoSpace::ApplicationData);
@@ -3034,11 +3687,35 @@ impl Connection {
         if self.role == Role::Server {
             self.state_signaling.handshake_done();
             self.set_state(State::Confirmed);
+            self.note_confirmed(now);
         }
         qinfo!([self], "Connection established");
         Ok(())
     }
 
+    fn note_confirmed(&self, now: Instant) {
+        let mut stats = self.stats.borrow_mut();
+        let pto_count = stats.pto_count;
+        stats.timings.handshake_confirmed(now, pto_count);
+    }
+
+    /// Start path MTU discovery on the primary path, if it is enabled.
+    fn start_pmtud(&mut self) {
+        if !self.conn_params.pmtud_enabled() {
+            return;
+        }
+        if let Some(path) = self.paths.primary() {
+            let max_payload = self
+                .tps
+                .borrow()
+                .remote()
+                .get_integer(tparams::MAX_UDP_PAYLOAD_SIZE);
+            path.borrow_mut()
+                .pmtud_mut()
+                .start(usize::try_from(max_payload).unwrap_or(usize::MAX));
+        }
+    }
+
     fn set_state(&mut self, state: State) {
         if state > self.state {
             qdebug!([self], "State change from {:?} -> {:?}", self.state, state);
@@ -3046,6 +3723,17 @@ impl Connection {
             if self.state.closed() {
                 self.streams.clear_streams();
             }
+            match self.state {
+                State::Connected => {
+                    self.advance_handshake_phase(HandshakePhase::Connected);
+                    self.raise_zero_rtt_limits();
+                }
+                State::Confirmed => {
+                    self.advance_handshake_phase(HandshakePhase::Confirmed);
+                    self.start_pmtud();
+                }
+                _ => {}
+            }
             self.events.connection_state_change(state);
             qlog::connection_state_updated(&mut self.qlog, &self.state);
         } else if mem::discriminant(&state) != mem::discriminant(&self.state) {
@@ -3059,6 +3747,32 @@ impl Connection {
         }
     }
 
+    /// A server that advertised lower flow control limits for 0-RTT raises them
+    /// to the configured values once the handshake is done.
+    fn raise_zero_rtt_limits(&mut self) {
+        if self.role != Role::Server || self.conn_params.get_zero_rtt_flow_control().is_none() {
+            return;
+        }
+        let p = &self.conn_params;
+        self.streams.raise_recv_limits(
+            p.get_max_data(),
+            &[
+                (
+                    tparams::INITIAL_MAX_STREAM_DATA_BIDI_LOCAL,
+                    p.get_max_stream_data(StreamType::BiDi, false),
+                ),
+                (
+                    tparams::INITIAL_MAX_STREAM_DATA_BIDI_REMOTE,
+                    p.get_max_stream_data(StreamType::BiDi, true),
+                ),
+                (
+                    tparams::INITIAL_MAX_STREAM_DATA_UNI,
+                    p.get_max_stream_data(StreamType::UniDi, true),
+                ),
+            ],
+        );
+    }
+
     /// Create a stream.
     /// Returns new stream id
     ///
@@ -3147,7 +3861,44 @@ impl Connection {
     /// `InvalidInput` if length of `data` is zero,
     /// `FinalSizeError` if the stream has already been closed.
     pub fn stream_send(&mut self, stream_id: StreamId, data: &[u8]) -> Res<usize> {
-        self.streams.get_send_stream_mut(stream_id)?.send(data)
+        let space = self.output_queue_space();
+        if space < data.len() {
+            self.output_queue_blocked.insert(stream_id);
+            if space == 0 {
+                self.streams.get_send_stream(stream_id)?;
+                return Ok(0);
+            }
+        }
+        self.streams
+            .get_send_stream_mut(stream_id)?
+            .send(&data[..min(space, data.len())])
+    }
+
+    /// How much more stream data can be written before the output queue is full.
+    /// See `ConnectionParameters::max_output_queue`.
+    fn output_queue_space(&self) -> usize {
+        let Some(packets) = self.conn_params.get_max_output_queue() else {
+            return usize::MAX;
+        };
+        let mtu = self
+            .paths
+            .primary()
+            .map_or(MIN_INITIAL_PACKET_SIZE, |p| p.borrow().mtu());
+        let unsent = usize::try_from(self.streams.send_bytes_unsent()).unwrap_or(usize::MAX);
+        packets.saturating_mul(mtu).saturating_sub(unsent)
+    }
+
+    /// Tell the streams that were refused a write that they can be written again,
+    /// once something has been sent from a full output queue.
+    fn output_queue_unblock(&mut self) {
+        if self.output_queue_blocked.is_empty() || self.output_queue_space() == 0 {
+            return;
+        }
+        for stream_id in mem::take(&mut self.output_queue_blocked) {
+            if self.streams.get_send_stream(stream_id).is_ok() {
+                self.events.send_stream_writable(stream_id);
+            }
+        }
     }
 
     /// Send all data or nothing on a stream. May cause `DATA_BLOCKED` or
@@ -3160,6 +3911,11 @@ impl Connection {
     /// `InvalidInput` if length of `data` is zero,
     /// `FinalSizeError` if the stream has already been closed.
     pub fn stream_send_atomic(&mut self, stream_id: StreamId, data: &[u8]) -> Res<bool> {
+        if self.output_queue_space() < data.len() {
+            self.streams.get_send_stream(stream_id)?;
+            self.output_queue_blocked.insert(stream_id);
+            return Ok(false);
+        }
         let val = self
             .streams
             .get_send_stream_mut(stream_id)?
@@ -3181,7 +3937,28 @@ impl Connection {
     /// # Errors
     /// When the stream ID is invalid.
     pub fn stream_avail_send_space(&self, stream_id: StreamId) -> Res<usize> {
-        Ok(self.streams.get_send_stream(stream_id)?.avail())
+        Ok(min(
+            self.streams.get_send_stream(stream_id)?.avail(),
+            self.output_queue_space(),
+        ))
+    }
+
+    /// Report whether sending is blocked by the peer's flow control limits,
+    /// either at the connection level or for individual streams.
+    /// A limit is only considered blocking once an attempt is made to send
+    /// more than it allows; this is also when a `DATA_BLOCKED` or
+    /// `STREAM_DATA_BLOCKED` frame is sent.
+    #[must_use]
+    pub fn flow_control_blocked(&self) -> FlowControlState {
+        self.streams.flow_control_blocked()
+    }
+
+    /// List the streams that have received data that the application can't
+    /// read yet, because some data before it has not arrived.  Each entry has
+    /// the offset of the first missing byte on that stream.
+    #[must_use]
+    pub fn reassembly_gaps(&self) -> Vec<(StreamId, u64)> {
+        self.streams.reassembly_gaps()
     }
 
     /// Set low watermark for [`ConnectionEvent::SendStreamWritable`] event.
@@ -3252,6 +4029,30 @@ impl Connection {
         Ok(())
     }
 
+    /// Start keeping a list of the streams that the peer opens,
+    /// which can be retrieved with `take_new_streams`.
+    pub(crate) fn track_new_streams(&mut self) {
+        self.streams.track_new_streams();
+    }
+
+    /// Take the streams that the peer opened since the last call.
+    pub(crate) fn take_new_streams(&mut self) -> Vec<StreamId> {
+        self.streams.take_new_streams()
+    }
+
+    /// Refuse a stream that the peer opened by resetting it in each direction
+    /// that the peer can use.  Events for the stream are discarded, so the
+    /// application never sees it.
+    pub(crate) fn refuse_stream(&mut self, stream_id: StreamId, err: AppError) {
+        if let Ok(stream) = self.streams.get_recv_stream_mut(stream_id) {
+            stream.stop_sending(err);
+        }
+        if let Ok(stream) = self.streams.get_send_stream_mut(stream_id) {
+            stream.reset(err);
+        }
+        self.events.remove_stream_events(stream_id);
+    }
+
     /// Increases `max_stream_data` for a `stream_id`.
     ///
     /// # Errors
@@ -3296,6 +4097,25 @@ impl Connection {
         if max_dgram_size == 0 {
             return Err(Error::NotAvailable);
         }
+        let overhead = self.packet_overhead()?;
+        let mtu = self
+            .paths
+            .primary()
+            .ok_or(Error::NotAvailable)?
+            .borrow()
+            .mtu();
+        let data_len_possible = u64::try_from(mtu.saturating_sub(overhead + 1)).unwrap();
+        Ok(min(data_len_possible, max_dgram_size))
+    }
+
+    /// The number of bytes in the next application data packet that are taken
+    /// by the packet header and the AEAD tag, rather than frames.  This depends on the
+    /// length of the connection ID and the packet number, so it can change over time.
+    ///
+    /// # Errors
+    ///
+    /// `NotAvailable` if the connection cannot send application data yet.
+    pub fn packet_overhead(&self) -> Res<usize> {
         let version = self.version();
         let Some((cspace, tx)) = self
             .crypto
@@ -3305,8 +4125,7 @@ impl Connection {
             return Err(Error::NotAvailable);
         };
         let path = self.paths.primary().ok_or(Error::NotAvailable)?;
-        let mtu = path.borrow().mtu();
-        let encoder = Encoder::with_capacity(mtu);
+        let encoder = Encoder::with_capacity(path.borrow().mtu());
 
         let (_, mut builder) = Self::build_packet_header(
             &path.borrow(),
@@ -3316,6 +4135,7 @@ impl Connection {
             &self.address_validation,
             version,
             false,
+            self.spin.value(),
         );
         _ = Self::add_packet_number(
             &mut builder,
@@ -3323,10 +4143,7 @@ impl Connection {
             self.loss_recovery
                 .largest_acknowledged_pn(PacketNumberSpace::ApplicationData),
         );
-
-        let data_len_possible =
-            u64::try_from(mtu.saturating_sub(tx.expansion() + builder.len() + 1)).unwrap();
-        Ok(min(data_len_possible, max_dgram_size))
+        Ok(builder.len() + tx.expansion())
     }
 
     /// Queue a datagram for sending.
@@ -3342,8 +4159,34 @@ impl Connection {
     /// `max_datagram_size` is just a current estimate and will change over
     /// time depending on the encoded size of the packet number, ack frames, etc.
     pub fn send_datagram(&mut self, buf: &[u8], id: impl Into<DatagramTracking>) -> Res<()> {
+        self.send_datagram_with(buf, id, DatagramOptions::default())
+    }
+
+    /// Queue a datagram for sending, with options that apply to just this datagram.
+    ///
+    /// # Errors
+    ///
+    /// As for `send_datagram`.
+    pub fn send_datagram_with(
+        &mut self,
+        buf: &[u8],
+        id: impl Into<DatagramTracking>,
+        options: DatagramOptions,
+    ) -> Res<()> {
         self.quic_datagrams
-            .add_datagram(buf, id.into(), &mut self.stats.borrow_mut())
+            .add_datagram(buf, id.into(), options, &mut self.stats.borrow_mut())
+    }
+
+    /// Get the next event, along with the `now` that was passed to the
+    /// `process` call that generated it.  Events that are generated outside
+    /// of a `process` call carry the time of the most recent call.
+    pub fn next_event_timed(&mut self) -> Option<(Instant, ConnectionEvent)> {
+        self.events.next_event_timed()
+    }
+
+    /// Construct an iterator that produces all events with their timestamps.
+    pub fn events_timed(&mut self) -> impl Iterator<Item = (Instant, ConnectionEvent)> + '_ {
+        iter::from_fn(move || self.next_event_timed())
     }
 }
 
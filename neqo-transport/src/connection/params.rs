@@ -4,7 +4,10 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{cmp::max, time::Duration};
+use std::{
+    cmp::{max, min},
+    time::Duration,
+};
 
 pub use crate::recovery::FAST_PTO_SCALE;
 use crate::{
@@ -29,6 +32,10 @@ const DEFAULT_ACK_RATIO: u8 = 4 * ACK_RATIO_SCALE;
 /// The local value for the idle timeout period.
 const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
 const MAX_QUEUED_DATAGRAMS_DEFAULT: usize = 10;
+/// The minimum number of PTOs to retain old read keys after a key update.
+const MIN_READ_KEY_RETENTION: u32 = 3;
+/// The anti-amplification limit from RFC 9000.
+pub(crate) const DEFAULT_AMPLIFICATION_FACTOR: u8 = 3;
 
 /// What to do with preferred addresses.
 #[derive(Debug, Clone)]
@@ -77,8 +84,35 @@ pub struct ConnectionParameters {
     outgoing_datagram_queue: usize,
     incoming_datagram_queue: usize,
     fast_pto: u8,
+    /// The number of PTOs that read keys from before a key update are retained.
+    read_key_retention: u32,
     grease: bool,
     pacing: bool,
+    /// Whether to search for a larger path MTU once the handshake is confirmed.
+    pmtud: bool,
+    /// Whether to keep congestion state when a peer only changes its port.
+    fast_rebinding: bool,
+    /// Whether a server provides a stateless reset token for its handshake connection ID.
+    reset_token: bool,
+    /// Whether to ask the peer not to migrate with the `disable_active_migration`
+    /// transport parameter.
+    disable_migration: bool,
+    /// How long a validated path that isn't the primary path is kept without use.
+    path_idle_timeout: Option<Duration>,
+    /// Whether addresses in qlog path events have their last octet zeroed.
+    redact_qlog_addresses: bool,
+    /// Whether the latency spin bit is used.
+    spin_bit: bool,
+    /// How many times the bytes received on a path can be sent on that path
+    /// before it is validated.
+    amplification_factor: u8,
+    /// How many paths can be in use at the same time.  Multipath is only
+    /// offered to the peer if this is more than one.
+    max_paths: u8,
+    /// How many packets worth of unsent stream data can be buffered.
+    max_output_queue: Option<usize>,
+    /// The connection and stream data limits that a server advertises for 0-RTT.
+    zero_rtt_flow_control: Option<(u64, u64)>,
 }
 
 impl Default for ConnectionParameters {
@@ -99,8 +133,20 @@ impl Default for ConnectionParameters {
             outgoing_datagram_queue: MAX_QUEUED_DATAGRAMS_DEFAULT,
             incoming_datagram_queue: MAX_QUEUED_DATAGRAMS_DEFAULT,
             fast_pto: FAST_PTO_SCALE,
+            read_key_retention: MIN_READ_KEY_RETENTION,
             grease: true,
             pacing: true,
+            pmtud: false,
+            fast_rebinding: true,
+            reset_token: false,
+            disable_migration: false,
+            path_idle_timeout: None,
+            redact_qlog_addresses: false,
+            spin_bit: false,
+            amplification_factor: DEFAULT_AMPLIFICATION_FACTOR,
+            max_paths: 1,
+            max_output_queue: None,
+            zero_rtt_flow_control: None,
         }
     }
 }
@@ -295,6 +341,20 @@ impl ConnectionParameters {
         self
     }
 
+    #[must_use]
+    pub fn get_read_key_retention(&self) -> u32 {
+        self.read_key_retention
+    }
+
+    /// Set how long, in multiples of the PTO, read keys from before a key update
+    /// are kept.  Packets that are reordered across a key update can only be
+    /// decrypted while these keys are kept.  Values less than 3 are raised to 3.
+    #[must_use]
+    pub fn read_key_retention(mut self, ptos: u32) -> Self {
+        self.read_key_retention = max(ptos, MIN_READ_KEY_RETENTION);
+        self
+    }
+
     #[must_use]
     pub fn get_fast_pto(&self) -> u8 {
         self.fast_pto
@@ -344,6 +404,180 @@ impl ConnectionParameters {
         self
     }
 
+    #[must_use]
+    pub fn pmtud_enabled(&self) -> bool {
+        self.pmtud
+    }
+
+    /// Enable or disable path MTU discovery (RFC 8899).  When enabled, the
+    /// connection probes for a larger path MTU once the handshake is confirmed
+    /// and falls back to a smaller one if large packets stop getting through.
+    #[must_use]
+    pub fn pmtud(mut self, pmtud: bool) -> Self {
+        self.pmtud = pmtud;
+        self
+    }
+
+    #[must_use]
+    pub fn fast_rebinding_enabled(&self) -> bool {
+        self.fast_rebinding
+    }
+
+    /// When a peer migrates by changing only its port, as happens when a NAT
+    /// rebinds, keep the congestion controller and RTT estimate from the old path.
+    /// The new path is still validated.  A change of IP address always starts
+    /// with fresh congestion state.  This is enabled by default.
+    #[must_use]
+    pub fn fast_rebinding(mut self, fast_rebinding: bool) -> Self {
+        self.fast_rebinding = fast_rebinding;
+        self
+    }
+
+    #[must_use]
+    pub fn migration_disabled(&self) -> bool {
+        self.disable_migration
+    }
+
+    /// Send the `disable_active_migration` transport parameter, which asks the
+    /// peer not to migrate.  A peer that moves to a new address anyway, which
+    /// might be the result of NAT rebinding, is only followed once the new path
+    /// is validated.  This does not apply to a preferred address.
+    /// This is disabled by default.
+    #[must_use]
+    pub fn disable_migration(mut self, disable_migration: bool) -> Self {
+        self.disable_migration = disable_migration;
+        self
+    }
+
+    #[must_use]
+    pub fn get_path_idle_timeout(&self) -> Option<Duration> {
+        self.path_idle_timeout
+    }
+
+    /// Abandon validated paths that are not in use once no packets have been
+    /// sent or received on them for `timeout`.  The primary path is never
+    /// abandoned this way.  By default, a path is kept for a few PTOs after it
+    /// was last validated, but only checked when some other timer fires.
+    #[must_use]
+    pub fn path_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.path_idle_timeout = Some(timeout);
+        self
+    }
+
+    #[must_use]
+    pub fn qlog_addresses_redacted(&self) -> bool {
+        self.redact_qlog_addresses
+    }
+
+    /// Zero the last octet of IP addresses that are logged in qlog path events,
+    /// for privacy.  Ports are still logged.  This is disabled by default.
+    #[must_use]
+    pub fn redact_qlog_addresses(mut self, redact: bool) -> Self {
+        self.redact_qlog_addresses = redact;
+        self
+    }
+
+    #[must_use]
+    pub fn spin_bit_enabled(&self) -> bool {
+        self.spin_bit
+    }
+
+    #[must_use]
+    pub fn get_amplification_factor(&self) -> u8 {
+        self.amplification_factor
+    }
+
+    /// Set the anti-amplification factor: before a path is validated, no more
+    /// than this many times the bytes received on the path are sent on it
+    /// (RFC 9000, Section 8).  The default is 3, which is the most the
+    /// specification permits.  Only tests can use a larger value.
+    ///
+    /// # Panics
+    ///
+    /// If `factor` is zero, or larger than 3 outside of tests.
+    #[must_use]
+    pub fn amplification_factor(mut self, factor: u8) -> Self {
+        assert!(factor > 0, "amplification factor must not be zero");
+        assert!(
+            cfg!(test) || factor <= DEFAULT_AMPLIFICATION_FACTOR,
+            "amplification factor must not exceed {DEFAULT_AMPLIFICATION_FACTOR}"
+        );
+        self.amplification_factor = factor;
+        self
+    }
+
+    /// Use the latency spin bit, which lets observers on the path measure
+    /// round-trip time.  This is disabled by default, in which case the bit
+    /// is set to a random value that does not change for the connection.
+    #[must_use]
+    pub fn spin_bit(mut self, spin_bit: bool) -> Self {
+        self.spin_bit = spin_bit;
+        self
+    }
+
+    #[must_use]
+    pub fn get_max_paths(&self) -> u8 {
+        self.max_paths
+    }
+
+    /// Offer experimental multipath support with the `enable_multipath` transport
+    /// parameter.  If the peer also offers it, a peer that sends from a new address
+    /// adds a path that is used alongside the existing one, up to `max_paths`
+    /// paths in total, rather than migrating.  A value of 0 or 1 disables multipath,
+    /// which is the default.
+    #[must_use]
+    pub fn multipath(mut self, max_paths: u8) -> Self {
+        self.max_paths = max(max_paths, 1);
+        self
+    }
+
+    #[must_use]
+    pub fn get_max_output_queue(&self) -> Option<usize> {
+        self.max_output_queue
+    }
+
+    /// Limit the stream data that has been written but not yet sent to `packets`
+    /// full-sized packets.  Once the limit is reached, writes to streams accept
+    /// nothing until enough of that data is sent, at which point a
+    /// `SendStreamWritable` event is emitted for the streams that were refused.
+    /// By default, only flow control and the send buffer of each stream limit writes.
+    #[must_use]
+    pub fn max_output_queue(mut self, packets: usize) -> Self {
+        self.max_output_queue = Some(max(packets, 1));
+        self
+    }
+
+    #[must_use]
+    pub fn get_zero_rtt_flow_control(&self) -> Option<(u64, u64)> {
+        self.zero_rtt_flow_control
+    }
+
+    /// Have a server advertise no more than `max_data` for the connection and
+    /// `max_stream_data` for each stream in its transport parameters.  These are
+    /// the limits that a client remembers and applies to 0-RTT when it resumes,
+    /// so they bound how much replayable data can arrive.  Once the handshake is
+    /// done, the server raises the limits to the other values that are configured
+    /// here with `MAX_DATA` and `MAX_STREAM_DATA` frames.
+    ///
+    /// # Panics
+    ///
+    /// If either value is 2^62 or more.
+    #[must_use]
+    pub fn zero_rtt_flow_control(mut self, max_data: u64, max_stream_data: u64) -> Self {
+        assert!(max_data < (1 << 62) && max_stream_data < (1 << 62));
+        self.zero_rtt_flow_control = Some((max_data, max_stream_data));
+        self
+    }
+
+    /// Have a server provide a stateless reset token for the connection ID that
+    /// it uses during the handshake.  This is only useful if something sends
+    /// stateless resets for connections after they are gone.
+    #[must_use]
+    pub(crate) fn reset_token(mut self, reset_token: bool) -> Self {
+        self.reset_token = reset_token;
+        self
+    }
+
     /// # Errors
     /// When a connection ID cannot be obtained.
     /// # Panics
@@ -359,8 +593,13 @@ impl ConnectionParameters {
             tparams::ACTIVE_CONNECTION_ID_LIMIT,
             u64::try_from(LOCAL_ACTIVE_CID_LIMIT).unwrap(),
         );
-        tps.local.set_empty(tparams::DISABLE_MIGRATION);
+        if self.disable_migration {
+            tps.local.set_empty(tparams::DISABLE_MIGRATION);
+        }
         tps.local.set_empty(tparams::GREASE_QUIC_BIT);
+        if self.max_paths > 1 {
+            tps.local.set_empty(tparams::ENABLE_MULTIPATH);
+        }
         tps.local.set_integer(
             tparams::MAX_ACK_DELAY,
             u64::try_from(DEFAULT_ACK_DELAY.as_millis()).unwrap(),
@@ -371,19 +610,24 @@ impl ConnectionParameters {
         );
 
         // set configurable parameters
+        // A server that limits 0-RTT advertises the lower limits, and raises them later.
+        let (max_data, max_stream_data) = match self.zero_rtt_flow_control {
+            Some(limits) if role == Role::Server => limits,
+            _ => (u64::MAX, u64::MAX),
+        };
         tps.local
-            .set_integer(tparams::INITIAL_MAX_DATA, self.max_data);
+            .set_integer(tparams::INITIAL_MAX_DATA, min(self.max_data, max_data));
         tps.local.set_integer(
             tparams::INITIAL_MAX_STREAM_DATA_BIDI_LOCAL,
-            self.max_stream_data_bidi_local,
+            min(self.max_stream_data_bidi_local, max_stream_data),
         );
         tps.local.set_integer(
             tparams::INITIAL_MAX_STREAM_DATA_BIDI_REMOTE,
-            self.max_stream_data_bidi_remote,
+            min(self.max_stream_data_bidi_remote, max_stream_data),
         );
         tps.local.set_integer(
             tparams::INITIAL_MAX_STREAM_DATA_UNI,
-            self.max_stream_data_uni,
+            min(self.max_stream_data_uni, max_stream_data),
         );
         tps.local
             .set_integer(tparams::INITIAL_MAX_STREAMS_BIDI, self.max_streams_bidi);
@@ -407,6 +651,12 @@ impl ConnectionParameters {
                 );
             }
         }
+        if role == Role::Server && self.reset_token {
+            tps.local.set_bytes(
+                tparams::STATELESS_RESET_TOKEN,
+                cid_manager.initial_reset_token().to_vec(),
+            );
+        }
         tps.local
             .set_integer(tparams::MAX_DATAGRAM_FRAME_SIZE, self.datagram_size);
         Ok(tps)
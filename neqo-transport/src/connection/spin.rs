@@ -0,0 +1,48 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use neqo_common::Role;
+use neqo_crypto::random;
+
+use crate::packet::PacketNumber;
+
+/// The value of the latency spin bit (RFC 9000, Section 17.4) that is sent
+/// in short header packets.
+pub struct SpinBit {
+    enabled: bool,
+    value: bool,
+    /// The largest packet number of a short header packet that was received.
+    largest_pn: Option<PacketNumber>,
+}
+
+impl SpinBit {
+    /// When the spin bit is disabled, a random value is picked here and held
+    /// for the life of the connection, so that the bit carries no information.
+    pub fn new(enabled: bool) -> Self {
+        let value = !enabled && random::<1>()[0] & 1 == 1;
+        Self {
+            enabled,
+            value,
+            largest_pn: None,
+        }
+    }
+
+    /// The value to send in the next short header packet.
+    pub const fn value(&self) -> bool {
+        self.value
+    }
+
+    /// Record the spin bit from a short header packet that was processed.
+    /// Only the packet with the largest packet number counts.  A server
+    /// reflects what it receives and a client inverts it.
+    pub fn received(&mut self, role: Role, pn: PacketNumber, spin: bool) {
+        if !self.enabled || self.largest_pn.map_or(false, |largest| pn <= largest) {
+            return;
+        }
+        self.largest_pn = Some(pn);
+        self.value = if role == Role::Server { spin } else { !spin };
+    }
+}
@@ -113,6 +113,65 @@ impl Ord for State {
     }
 }
 
+/// How far the handshake has progressed.  This is more granular than `State`,
+/// so that it is possible to tell where a handshake stalled.  The phase only
+/// ever advances; it is not affected by the connection closing.
+///
+/// Not every connection passes through every phase: a client never reports
+/// `HandshakeSent` because it receives the server's Handshake packets before
+/// it sends any; a server might go straight from `HandshakeReceived` to
+/// `Confirmed`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HandshakePhase {
+    /// Nothing has been sent or received.
+    Start,
+    /// An Initial packet has been sent.
+    InitialSent,
+    /// An Initial packet has been received and processed.
+    InitialReceived,
+    /// A Handshake packet has been sent.
+    HandshakeSent,
+    /// A Handshake packet has been received and processed.
+    HandshakeReceived,
+    /// The handshake is complete, but not yet confirmed.
+    Connected,
+    /// The handshake is confirmed.
+    Confirmed,
+}
+
+/// The outcome of Encrypted Client Hello (ECH) for a connection.
+///
+/// A client that sends GREASE ECH is not attempting ECH, so it reports
+/// `NotAttempted`.  A server cannot tell GREASE apart from a configuration
+/// that it doesn't recognize; in both cases, it sends retry configurations
+/// and reports `RejectedWithRetry`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EchState {
+    /// ECH was not used.
+    NotAttempted,
+    /// The server accepted the ECH configuration that the client used.
+    Accepted,
+    /// The server rejected ECH and provided retry configurations.  A client
+    /// ends up closed with `Error::EchRetry`, which holds the configurations.
+    RejectedWithRetry,
+    /// The server rejected ECH and the handshake fell back to authenticating
+    /// the public name without obtaining retry configurations.
+    FallbackPublicName,
+}
+
+impl EchState {
+    /// The name used for this state in qlog.
+    #[must_use]
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::NotAttempted => "not_attempted",
+            Self::Accepted => "accepted",
+            Self::RejectedWithRetry => "rejected_with_retry",
+            Self::FallbackPublicName => "fallback_public_name",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ClosingFrame {
     path: PathRef,
@@ -141,6 +200,11 @@ impl ClosingFrame {
         &self.path
     }
 
+    #[cfg(test)]
+    pub fn frame_type(&self) -> FrameType {
+        self.frame_type
+    }
+
     pub fn sanitize(&self) -> Option<Self> {
         if let CloseReason::Application(_) = self.error {
             // The default CONNECTION_CLOSE frame that is sent when an application
@@ -0,0 +1,76 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// A simulated network that is applied to the datagrams a connection sends.
+
+use std::{
+    cmp::min,
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use neqo_common::{qtrace, Datagram};
+use neqo_crypto::random;
+
+use super::Output;
+
+#[derive(Debug)]
+pub struct TestNetwork {
+    /// The probability that a datagram is dropped, from 0 to 1.
+    loss: f64,
+    /// How long each datagram is held before it is released.
+    latency: Duration,
+    /// Datagrams that are waiting to be released, with the time of release.
+    delayed: VecDeque<(Instant, Datagram)>,
+}
+
+impl TestNetwork {
+    pub fn new(loss: f64, latency: Duration) -> Self {
+        assert!((0.0..=1.0).contains(&loss), "loss must be between 0 and 1");
+        Self {
+            loss,
+            latency,
+            delayed: VecDeque::new(),
+        }
+    }
+
+    fn lose(&self) -> bool {
+        let r = u32::from_ne_bytes(random::<4>());
+        f64::from(r) < self.loss * f64::from(u32::MAX)
+    }
+
+    /// Take everything that the connection wants to send, using `f`.  Drop some of
+    /// those datagrams and hold the rest until their latency has passed.
+    pub fn process<F>(&mut self, now: Instant, mut f: F) -> Output
+    where
+        F: FnMut(Instant) -> Output,
+    {
+        let out = loop {
+            match f(now) {
+                Output::Datagram(d) => {
+                    if self.lose() {
+                        qtrace!("test network dropped {}", d.len());
+                    } else {
+                        self.delayed.push_back((now + self.latency, d));
+                    }
+                }
+                out => break out,
+            }
+        };
+
+        let Some(&(release, _)) = self.delayed.front() else {
+            return out;
+        };
+        if release <= now {
+            return Output::Datagram(self.delayed.pop_front().unwrap().1);
+        }
+        let wait = release - now;
+        match out {
+            Output::Callback(t) => Output::Callback(min(t, wait)),
+            _ => Output::Callback(wait),
+        }
+    }
+}
@@ -192,3 +192,68 @@ fn migrate_ack_delay() {
     assert!(af.is_some());
     assert_eq!(client.stats().frame_tx.ack_frequency, ad_before + 1);
 }
+
+/// A second ack-eliciting packet is acknowledged immediately.
+#[test]
+fn ack_two_packets_immediately() {
+    let mut client = default_client();
+    let mut server = default_server();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+    let ack_only = server.stats().ack_only_tx;
+
+    let d1 = send_something(&mut client, now);
+    let d2 = send_something(&mut client, now);
+    server.process_input(&d1, now);
+    let ack = server.process(Some(&d2), now).dgram();
+    assert!(ack.is_some());
+    assert_eq!(server.stats().ack_only_tx, ack_only + 1);
+}
+
+/// A single ack-eliciting packet is only acknowledged once the delayed ACK timer expires.
+#[test]
+fn ack_one_packet_delayed() {
+    let mut client = default_client();
+    let mut server = default_server();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+
+    // Acknowledge something first so that the next ACK isn't overdue.
+    let d1 = send_something(&mut client, now);
+    let d2 = send_something(&mut client, now);
+    server.process_input(&d1, now);
+    assert!(server.process(Some(&d2), now).dgram().is_some());
+    let ack_only = server.stats().ack_only_tx;
+
+    let d3 = send_something(&mut client, now);
+    let delay = server.process(Some(&d3), now).callback();
+    assert!(delay > Duration::ZERO && delay < DEFAULT_RTT);
+    assert!(server.process_output(now + delay / 2).dgram().is_none());
+    assert_eq!(server.stats().ack_only_tx, ack_only);
+
+    let ack = server.process_output(now + delay).dgram();
+    assert!(ack.is_some());
+    assert_eq!(server.stats().ack_only_tx, ack_only + 1);
+}
+
+/// A pending ACK is sent along with other frames before its timer expires.
+#[test]
+fn ack_piggybacks_on_data() {
+    let mut client = default_client();
+    let mut server = default_server();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+
+    let d1 = send_something(&mut client, now);
+    let d2 = send_something(&mut client, now);
+    server.process_input(&d1, now);
+    assert!(server.process(Some(&d2), now).dgram().is_some());
+    let ack_only = server.stats().ack_only_tx;
+    let acks = server.stats().frame_tx.ack;
+
+    // The ACK for this is delayed...
+    let d3 = send_something(&mut client, now);
+    assert!(server.process(Some(&d3), now).callback() > Duration::ZERO);
+
+    // ...until the server has something else to send.
+    mem::drop(send_something(&mut server, now));
+    assert_eq!(server.stats().frame_tx.ack, acks + 1);
+    assert_eq!(server.stats().ack_only_tx, ack_only);
+}
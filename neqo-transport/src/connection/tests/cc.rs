@@ -14,12 +14,13 @@ use super::{
     CLIENT_HANDSHAKE_1RTT_PACKETS, DEFAULT_RTT, POST_HANDSHAKE_CWND,
 };
 use crate::{
-    cc::MAX_DATAGRAM_SIZE,
+    cc::{CongestionPhase, MAX_DATAGRAM_SIZE},
     packet::PacketNumber,
     recovery::{ACK_ONLY_SIZE_LIMIT, PACKET_THRESHOLD},
     sender::PACING_BURST_SIZE,
     stream_id::StreamType,
     tracking::DEFAULT_ACK_PACKET_TOLERANCE,
+    ConnectionParameters,
 };
 
 #[test]
@@ -76,6 +77,10 @@ fn cc_slow_start_to_cong_avoidance_recovery_period(congestion_signal: Congestion
         flight1_largest
     );
     let cwnd_before_cong = cwnd(&client);
+    let snapshot = client.cc_snapshot().unwrap();
+    assert_eq!(snapshot.phase, CongestionPhase::SlowStart);
+    assert_eq!(snapshot.ssthresh, usize::MAX);
+    assert_eq!(snapshot.cwnd, cwnd_before_cong);
 
     // Client: send more
     let (mut c_tx_dgrams, mut now) = fill_cwnd(&mut client, stream_id, now);
@@ -107,6 +112,16 @@ fn cc_slow_start_to_cong_avoidance_recovery_period(congestion_signal: Congestion
         flight2_largest
     );
     assert!(cwnd(&client) < cwnd_before_cong);
+
+    let snapshot = client.cc_snapshot().unwrap();
+    assert_eq!(
+        snapshot.algorithm,
+        ConnectionParameters::default().get_cc_algorithm()
+    );
+    assert_eq!(snapshot.phase, CongestionPhase::Recovery);
+    assert_eq!(snapshot.cwnd, cwnd(&client));
+    assert_eq!(snapshot.ssthresh, snapshot.cwnd);
+    assert!(snapshot.pacing_rate.is_some());
 }
 
 #[test]
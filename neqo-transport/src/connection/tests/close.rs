@@ -6,15 +6,18 @@
 
 use std::time::Duration;
 
+use neqo_common::{event::Provider, Encoder};
 use test_fixture::{datagram, now};
 
 use super::{
-    super::{Connection, Output, State},
-    connect, connect_force_idle, default_client, default_server, send_something,
+    super::{Connection, Output, State, StateSignaling},
+    assert_error, connect, connect_force_idle, default_client, default_server, send_something,
 };
 use crate::{
+    frame::{FrameType, FRAME_TYPE_MAX_STREAMS_BIDI, FRAME_TYPE_NEW_CONNECTION_ID},
+    packet::PacketBuilder,
     tparams::{self, TransportParameter},
-    AppError, CloseReason, Error, ERROR_APPLICATION_CLOSE,
+    AppError, CloseReason, ConnectionEvent, Error, ERROR_APPLICATION_CLOSE,
 };
 
 fn assert_draining(c: &Connection, expected: &Error) {
@@ -221,3 +224,72 @@ fn stateless_reset_client() {
     client.process_input(&datagram(vec![77; 21]), now());
     assert_draining(&client, &Error::StatelessReset);
 }
+
+/// Writes the given bytes as though they were a frame.
+struct MalformedFrameWriter(&'static str);
+
+impl crate::connection::test_internal::FrameWriter for MalformedFrameWriter {
+    fn write_frames(&mut self, builder: &mut PacketBuilder) {
+        builder.encode(Encoder::from_hex(self.0).as_ref());
+    }
+}
+
+/// Get the frame type that is reported in the CONNECTION_CLOSE that `c` sends.
+fn closing_frame_type(c: &Connection) -> FrameType {
+    match &c.state_signaling {
+        StateSignaling::Closing(f) | StateSignaling::CloseSent(Some(f)) => f.frame_type(),
+        _ => panic!("not closing: {:?}", c.state_signaling),
+    }
+}
+
+/// Have the server send a packet with some stream data that is followed by
+/// `frame`, then check that the client closes the connection with `error`,
+/// blaming a frame of type `frame_type`.
+fn malformed_frame(frame: &'static str, frame_type: FrameType, error: &Error) {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    server.test_frame_writer = Some(Box::new(MalformedFrameWriter(frame)));
+    let dgram = send_something(&mut server, now());
+    server.test_frame_writer = None;
+
+    client.process_input(&dgram, now());
+    assert_error(&client, &CloseReason::Transport(error.clone()));
+    assert_eq!(closing_frame_type(&client), frame_type);
+
+    // The STREAM frame that preceded the malformed frame was not processed.
+    assert!(!client.events().any(|e| matches!(
+        e,
+        ConnectionEvent::NewStream { .. } | ConnectionEvent::RecvStreamReadable { .. }
+    )));
+
+    // The server sees the right error code.
+    let close = client.process_output(now()).dgram();
+    server.process_input(&close.unwrap(), now());
+    assert_draining(&server, &Error::PeerError(error.code()));
+}
+
+#[test]
+fn malformed_max_streams() {
+    malformed_frame(
+        "12d000000000000001",
+        FRAME_TYPE_MAX_STREAMS_BIDI,
+        &Error::FrameEncodingError,
+    );
+}
+
+#[test]
+fn malformed_new_connection_id() {
+    // A connection ID that is 21 bytes long.
+    malformed_frame(
+        "18010015000102030405060708090a0b0c0d0e0f1011121314000102030405060708090a0b0c0d0e0f",
+        FRAME_TYPE_NEW_CONNECTION_ID,
+        &Error::FrameEncodingError,
+    );
+}
+
+#[test]
+fn malformed_unknown_frame_type() {
+    malformed_frame("21", 0x21, &Error::UnknownFrameType);
+}
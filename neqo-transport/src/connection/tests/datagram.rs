@@ -6,7 +6,7 @@
 
 use std::{cell::RefCell, rc::Rc};
 
-use neqo_common::event::Provider;
+use neqo_common::{event::Provider, IpTosDscp, IpTosEcn};
 use test_fixture::now;
 
 use super::{
@@ -17,7 +17,7 @@ use crate::{
     events::{ConnectionEvent, OutgoingDatagramOutcome},
     frame::FRAME_TYPE_DATAGRAM,
     packet::PacketBuilder,
-    quic_datagrams::MAX_QUIC_DATAGRAM,
+    quic_datagrams::{DatagramOptions, MAX_QUIC_DATAGRAM},
     send_stream::{RetransmissionPriority, TransmissionPriority},
     CloseReason, Connection, ConnectionParameters, Error, StreamType, MIN_INITIAL_PACKET_SIZE,
 };
@@ -616,3 +616,42 @@ fn datagram_fill() {
     send_datagram(&mut client, &mut server, &buf[..buf.len() - 4]);
     assert!(*called.borrow());
 }
+
+/// A datagram can be sent with a different DSCP value to the rest of the connection.
+/// Datagrams with different DSCP values are sent in different packets.
+#[test]
+fn datagram_dscp() {
+    let mut client =
+        new_client(ConnectionParameters::default().datagram_size(DATAGRAM_LEN_SMALLER_THAN_MTU));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    server.set_dscp(IpTosDscp::Af11);
+
+    let ef = DatagramOptions::default().dscp(IpTosDscp::Ef);
+    let cs1 = DatagramOptions::default().dscp(IpTosDscp::Cs1);
+    server
+        .send_datagram_with(DATA_SMALLER_THAN_MTU_2, Some(1), ef)
+        .unwrap();
+    server
+        .send_datagram_with(DATA_SMALLER_THAN_MTU_2, Some(2), cs1)
+        .unwrap();
+    server
+        .send_datagram(DATA_SMALLER_THAN_MTU_2, Some(3))
+        .unwrap();
+
+    let out = server.process_output(now()).dgram().unwrap();
+    assert_eq!(IpTosDscp::from(out.tos()), IpTosDscp::Ef);
+    assert_eq!(IpTosEcn::from(out.tos()), IpTosEcn::Ect0);
+    assert_eq!(server.stats().frame_tx.datagram, 1);
+
+    // The datagram without an override joins the one with an override.
+    let out = server.process_output(now()).dgram().unwrap();
+    assert_eq!(IpTosDscp::from(out.tos()), IpTosDscp::Cs1);
+    assert_eq!(server.stats().frame_tx.datagram, 3);
+
+    // Other packets use the DSCP value for the connection.
+    let stream_id = server.stream_create(StreamType::UniDi).unwrap();
+    server.stream_send(stream_id, &[1, 2, 3]).unwrap();
+    let out = server.process_output(now()).dgram().unwrap();
+    assert_eq!(IpTosDscp::from(out.tos()), IpTosDscp::Af11);
+}
@@ -4,9 +4,9 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use neqo_common::{Datagram, IpTos, IpTosEcn};
+use neqo_common::{Datagram, IpTos, IpTosDscp, IpTosEcn};
 use test_fixture::{
     assertions::{assert_v4_path, assert_v6_path},
     fixture_init, now, DEFAULT_ADDR_V4,
@@ -15,11 +15,11 @@ use test_fixture::{
 use super::send_something_with_modifier;
 use crate::{
     connection::tests::{
-        connect_force_idle, connect_force_idle_with_modifier, default_client, default_server,
+        connect_force_idle, connect_force_idle_with_modifier, cwnd, default_client, default_server,
         migration::get_cid, new_client, new_server, send_something,
     },
-    ecn::ECN_TEST_COUNT,
-    ConnectionId, ConnectionParameters, StreamType,
+    ecn::{ECN_RETEST_INTERVAL, ECN_TEST_COUNT},
+    Connection, ConnectionId, ConnectionParameters, EcnValidationOutcome, StreamType,
 };
 
 fn assert_ecn_enabled(tos: IpTos) {
@@ -87,6 +87,109 @@ fn disables_on_loss() {
     assert_ecn_disabled(client_pkt.tos());
 }
 
+/// Connect, then send enough packets over a path that modifies packets via `modifier`
+/// to conclude ECN validation at the client.  Returns the connections and the time.
+/// Setting a DSCP value marks outgoing datagrams without changing their ECN codepoint.
+#[test]
+fn dscp_preserves_ecn() {
+    let now = now();
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    let client_pkt = send_something(&mut client, now);
+    assert_eq!(IpTosDscp::from(client_pkt.tos()), IpTosDscp::Cs0);
+    assert_ecn_enabled(client_pkt.tos());
+
+    client.set_dscp(IpTosDscp::Af41);
+    let client_pkt = send_something(&mut client, now);
+    assert_eq!(IpTosDscp::from(client_pkt.tos()), IpTosDscp::Af41);
+    assert_eq!(IpTosEcn::from(client_pkt.tos()), IpTosEcn::Ect0);
+
+    // Once ECN is disabled, the DSCP value remains.
+    for _ in 0..ECN_TEST_COUNT {
+        send_something(&mut client, now);
+    }
+    let client_pkt = send_something(&mut client, now);
+    assert_eq!(IpTosDscp::from(client_pkt.tos()), IpTosDscp::Af41);
+    assert_ecn_disabled(client_pkt.tos());
+}
+
+fn validate_with_modifier(
+    params: ConnectionParameters,
+    modifier: fn(Datagram) -> Option<Datagram>,
+) -> (Connection, Connection, Instant) {
+    let mut client = new_client(params.clone());
+    let mut server = new_server(params);
+    connect_force_idle_with_modifier(&mut client, &mut server, modifier);
+    let now = now();
+
+    for _ in 0..ECN_TEST_COUNT {
+        let client_pkt = send_something_with_modifier(&mut client, now, modifier);
+        server.process_input(&client_pkt, now);
+    }
+    let ack = server.process_output(now).dgram().unwrap();
+    client.process_input(&ack, now);
+    (client, server, now)
+}
+
+#[test]
+fn validation_capable() {
+    let (mut client, server, now) = validate_with_modifier(ConnectionParameters::default(), noop());
+    let stats = client.stats();
+    assert_eq!(stats.ecn.validation, EcnValidationOutcome::Capable);
+    assert!(stats.ecn.tx_marked >= ECN_TEST_COUNT);
+    let server_stats = server.stats();
+    assert!(server_stats.ecn.rx[IpTosEcn::Ect0] >= ECN_TEST_COUNT as u64);
+    assert_eq!(server_stats.ecn.rx[IpTosEcn::Ce], 0);
+
+    // Packets remain marked once validation succeeds.
+    let client_pkt = send_something(&mut client, now);
+    assert_ecn_enabled(client_pkt.tos());
+}
+
+#[test]
+fn validation_failed_bleached() {
+    let (mut client, server, now) = validate_with_modifier(
+        ConnectionParameters::default().idle_timeout(ECN_RETEST_INTERVAL * 2),
+        bleach(),
+    );
+    assert_eq!(client.stats().ecn.validation, EcnValidationOutcome::Failed);
+    assert_eq!(server.stats().ecn.rx[IpTosEcn::Ect0], 0);
+
+    // Subsequent packets are not marked.
+    let tx_marked = client.stats().ecn.tx_marked;
+    let client_pkt = send_something(&mut client, now);
+    assert_ecn_disabled(client_pkt.tos());
+    assert_eq!(client.stats().ecn.tx_marked, tx_marked);
+
+    // After a while, the path is tested again.
+    let client_pkt = send_something(&mut client, now + ECN_RETEST_INTERVAL);
+    assert_ecn_enabled(client_pkt.tos());
+    assert_eq!(client.stats().ecn.validation, EcnValidationOutcome::Testing);
+}
+
+#[test]
+fn validation_ce_reduces_cwnd() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle_with_modifier(&mut client, &mut server, ce());
+    let now = now();
+    let cwnd_before = cwnd(&client);
+
+    for _ in 0..ECN_TEST_COUNT {
+        let client_pkt = send_something_with_modifier(&mut client, now, ce());
+        server.process_input(&client_pkt, now);
+    }
+    assert!(server.stats().ecn.rx[IpTosEcn::Ce] > 0);
+    let ack = server.process_output(now).dgram().unwrap();
+    client.process_input(&ack, now);
+
+    // CE marks are acceptable for validation, but they are also a congestion signal.
+    assert_eq!(client.stats().ecn.validation, EcnValidationOutcome::Capable);
+    assert!(cwnd(&client) < cwnd_before);
+}
+
 /// This function performs a handshake over a path that modifies packets via `orig_path_modifier`.
 /// It then sends `burst` packets on that path, and then migrates to a new path that
 /// modifies packets via `new_path_modifier`.  It sends `burst` packets on the new path.
@@ -12,30 +12,35 @@ use std::{
     time::Duration,
 };
 
-use neqo_common::{event::Provider, qdebug, Datagram};
+use neqo_common::{event::Provider, qdebug, Datagram, Decoder};
 use neqo_crypto::{
     constants::TLS_CHACHA20_POLY1305_SHA256, generate_ech_keys, AuthenticationStatus,
 };
+use serde_json::{json, Value};
 #[cfg(not(feature = "disable-encryption"))]
 use test_fixture::datagram;
 use test_fixture::{
-    assertions, assertions::assert_coalesced_0rtt, fixture_init, now, split_datagram, DEFAULT_ADDR,
+    assertions, assertions::assert_coalesced_0rtt, fixture_init, new_neqo_qlog, now,
+    split_datagram, SharedVec, DEFAULT_ADDR,
 };
 
 use super::{
-    super::{Connection, Output, State},
+    super::{Connection, EchState, Output, State},
     assert_error, connect, connect_force_idle, connect_with_rtt, default_client, default_server,
-    get_tokens, handshake, maybe_authenticate, resumed_server, send_something,
+    get_tokens, handshake, maybe_authenticate, new_server, resumed_server, send_something,
     CountingConnectionIdGenerator, AT_LEAST_PTO, DEFAULT_RTT, DEFAULT_STREAM_DATA,
 };
 use crate::{
+    cid::MAX_CONNECTION_ID_LEN,
     connection::AddressValidation,
     events::ConnectionEvent,
     path::PATH_MTU_V6,
     server::ValidateAddress,
     tparams::{TransportParameter, MIN_ACK_DELAY},
     tracking::DEFAULT_ACK_DELAY,
-    CloseReason, ConnectionParameters, EmptyConnectionIdGenerator, Error, StreamType, Version,
+    CloseReason, ConnectionId, ConnectionIdDecoder, ConnectionIdGenerator, ConnectionIdRef,
+    ConnectionParameters, EmptyConnectionIdGenerator, Error, RandomConnectionIdGenerator,
+    StreamType, Version,
 };
 
 const ECH_CONFIG_ID: u8 = 7;
@@ -80,6 +85,47 @@ fn full_handshake() {
     assert_eq!(*client.state(), State::Confirmed);
 }
 
+/// Events carry the `now` of the call that generated them, not the time they are drained.
+#[test]
+fn event_timestamps() {
+    let start = now();
+    let mut client = default_client();
+    let mut server = default_server();
+
+    let out = client.process(None, start);
+    let out = server.process(out.as_dgram_ref(), start + Duration::from_millis(10));
+    let out = client.process(out.as_dgram_ref(), start + Duration::from_millis(20));
+    let out = server.process(out.as_dgram_ref(), start + Duration::from_millis(30));
+    assert!(out.as_dgram_ref().is_none());
+
+    let auth_time = start + Duration::from_millis(40);
+    let auth = client
+        .events_timed()
+        .find(|(_, e)| matches!(e, ConnectionEvent::AuthenticationNeeded));
+    assert_eq!(
+        auth.map(|(t, _)| t),
+        Some(start + Duration::from_millis(20))
+    );
+    client.authenticated(AuthenticationStatus::Ok, auth_time);
+    assert_eq!(*client.state(), State::Connected);
+
+    let out = client.process(None, start + Duration::from_millis(50));
+    let connected =
+        |(_, e): &(_, ConnectionEvent)| matches!(e, ConnectionEvent::StateChange(State::Connected));
+    assert_eq!(
+        client.events_timed().find(connected).map(|(t, _)| t),
+        Some(auth_time)
+    );
+
+    let server_time = start + Duration::from_millis(60);
+    _ = server.process(out.as_dgram_ref(), server_time);
+    _ = server.process_output(start + Duration::from_millis(70));
+    assert_eq!(
+        server.events_timed().find(connected).map(|(t, _)| t),
+        Some(server_time)
+    );
+}
+
 #[test]
 fn handshake_failed_authentication() {
     qdebug!("---- client: generate CH");
@@ -258,6 +304,93 @@ fn chacha20poly1305() {
     connect_force_idle(&mut client, &mut server);
 }
 
+fn connect_with_cid_lengths(client_len: usize, server_len: usize) {
+    fixture_init();
+    let mut client = Connection::new_client(
+        test_fixture::DEFAULT_SERVER_NAME,
+        test_fixture::DEFAULT_ALPN,
+        Rc::new(RefCell::new(RandomConnectionIdGenerator::new(client_len))),
+        DEFAULT_ADDR,
+        DEFAULT_ADDR,
+        ConnectionParameters::default(),
+        now(),
+    )
+    .expect("create a client");
+    let mut server = Connection::new_server(
+        test_fixture::DEFAULT_KEYS,
+        test_fixture::DEFAULT_ALPN,
+        Rc::new(RefCell::new(RandomConnectionIdGenerator::new(server_len))),
+        ConnectionParameters::default(),
+    )
+    .expect("create a server");
+    connect(&mut client, &mut server);
+
+    // Short header packets in both directions use the new connection IDs.
+    let readable = |c: &mut Connection| {
+        c.events()
+            .any(|e| matches!(e, ConnectionEvent::RecvStreamReadable { .. }))
+    };
+    let dgram = send_something(&mut client, now());
+    server.process_input(&dgram, now());
+    assert!(readable(&mut server));
+    let dgram = send_something(&mut server, now());
+    client.process_input(&dgram, now());
+    assert!(readable(&mut client));
+}
+
+#[test]
+fn cid_short_client_long_server() {
+    connect_with_cid_lengths(1, MAX_CONNECTION_ID_LEN);
+}
+
+#[test]
+fn cid_long_client_short_server() {
+    connect_with_cid_lengths(MAX_CONNECTION_ID_LEN, 1);
+}
+
+/// Produces connection IDs that are one byte longer than is allowed.
+struct OversizedConnectionIdGenerator {}
+
+impl ConnectionIdDecoder for OversizedConnectionIdGenerator {
+    fn decode_cid<'a>(&self, dec: &mut Decoder<'a>) -> Option<ConnectionIdRef<'a>> {
+        dec.decode(MAX_CONNECTION_ID_LEN + 1)
+            .map(ConnectionIdRef::from)
+    }
+}
+
+impl ConnectionIdGenerator for OversizedConnectionIdGenerator {
+    fn generate_cid(&mut self) -> Option<ConnectionId> {
+        Some(ConnectionId::from(&[0xcc; MAX_CONNECTION_ID_LEN + 1]))
+    }
+
+    fn as_decoder(&self) -> &dyn ConnectionIdDecoder {
+        self
+    }
+}
+
+#[test]
+fn cid_too_long() {
+    fixture_init();
+    let res = Connection::new_client(
+        test_fixture::DEFAULT_SERVER_NAME,
+        test_fixture::DEFAULT_ALPN,
+        Rc::new(RefCell::new(OversizedConnectionIdGenerator {})),
+        DEFAULT_ADDR,
+        DEFAULT_ADDR,
+        ConnectionParameters::default(),
+        now(),
+    );
+    assert_eq!(res.unwrap_err(), Error::InvalidConnectionIdLength);
+
+    let res = Connection::new_server(
+        test_fixture::DEFAULT_KEYS,
+        test_fixture::DEFAULT_ALPN,
+        Rc::new(RefCell::new(OversizedConnectionIdGenerator {})),
+        ConnectionParameters::default(),
+    );
+    assert_eq!(res.unwrap_err(), Error::InvalidConnectionIdLength);
+}
+
 /// Test that a server can send 0.5 RTT application data.
 #[test]
 fn send_05rtt() {
@@ -806,6 +939,33 @@ fn anti_amplification() {
     assert_eq!(*server.state(), State::Confirmed);
 }
 
+#[test]
+fn anti_amplification_factor() {
+    let mut client = default_client();
+    // Without pacing, the server would send a third datagram right away.
+    let mut server = new_server(
+        ConnectionParameters::default()
+            .pacing(false)
+            .amplification_factor(2),
+    );
+    let mut now = now();
+
+    // As above, the handshake can't be completed within the limit.
+    let very_big = TransportParameter::Bytes(vec![0; PATH_MTU_V6 * 3]);
+    server.set_local_tparam(0xce16, very_big).unwrap();
+
+    let c_init = client.process_output(now).dgram().unwrap();
+    assert_eq!(c_init.len(), PATH_MTU_V6);
+    now += DEFAULT_RTT / 2;
+    let s_init1 = server.process(Some(&c_init), now).dgram().unwrap();
+    let s_init2 = server.process_output(now).dgram().unwrap();
+    assert_eq!(s_init1.len() + s_init2.len(), 2 * c_init.len());
+
+    // Having sent twice what it received, the server is blocked.
+    let cb = server.process_output(now).callback();
+    assert_ne!(cb, Duration::new(0, 0));
+}
+
 #[cfg(not(feature = "disable-encryption"))]
 #[test]
 fn garbage_initial() {
@@ -872,6 +1032,33 @@ fn drop_handshake_packet_from_wrong_address() {
     assert!(out.as_dgram_ref().is_none());
 }
 
+/// Attach a new qlog to the connection.
+fn ech_qlog(c: &mut Connection) -> SharedVec {
+    let (log, contents) = new_neqo_qlog();
+    c.set_qlog(log);
+    contents
+}
+
+/// The data from each ECH event in a qlog.
+fn ech_events(contents: &SharedVec) -> Vec<Value> {
+    contents
+        .to_string()
+        .split_terminator('\n')
+        .skip(1) // The header.
+        .map(|r| serde_json::from_str::<Value>(r.strip_prefix('\u{1e}').unwrap()).unwrap())
+        .filter(|ev| ev["name"] == "security:ech_updated")
+        .map(|ev| ev["data"].clone())
+        .collect()
+}
+
+fn ech_event(state: EchState, config_id: u8, retry_configs: Option<&str>) -> Value {
+    json!({
+        "state": state.label(),
+        "config_id": config_id,
+        "retry_configs": retry_configs,
+    })
+}
+
 #[test]
 fn ech() {
     let mut server = default_server();
@@ -879,9 +1066,11 @@ fn ech() {
     server
         .server_enable_ech(ECH_CONFIG_ID, ECH_PUBLIC_NAME, &sk, &pk)
         .unwrap();
+    let server_log = ech_qlog(&mut server);
 
     let mut client = default_client();
     client.client_enable_ech(server.ech_config()).unwrap();
+    let client_log = ech_qlog(&mut client);
 
     connect(&mut client, &mut server);
 
@@ -889,6 +1078,34 @@ fn ech() {
     assert!(server.tls_info().unwrap().ech_accepted());
     assert!(client.tls_preinfo().unwrap().ech_accepted().unwrap());
     assert!(server.tls_preinfo().unwrap().ech_accepted().unwrap());
+
+    let accepted = ech_event(EchState::Accepted, ECH_CONFIG_ID, None);
+    assert_eq!(client.ech_state(), EchState::Accepted);
+    assert_eq!(ech_events(&client_log), [accepted.clone()]);
+    assert_eq!(server.ech_state(), EchState::Accepted);
+    assert_eq!(ech_events(&server_log), [accepted]);
+}
+
+/// A configuration that can't be used leaves ECH disabled.
+#[test]
+fn ech_bad_config() {
+    let mut server = default_server();
+    let (sk, pk) = generate_ech_keys().unwrap();
+    server
+        .server_enable_ech(ECH_CONFIG_ID, ECH_PUBLIC_NAME, &sk, &pk)
+        .unwrap();
+    let server_log = ech_qlog(&mut server);
+
+    let mut client = default_client();
+    client.client_enable_ech([0xfe; 17]).unwrap_err();
+    let client_log = ech_qlog(&mut client);
+
+    connect(&mut client, &mut server);
+
+    assert_eq!(client.ech_state(), EchState::NotAttempted);
+    assert!(ech_events(&client_log).is_empty());
+    assert_eq!(server.ech_state(), EchState::NotAttempted);
+    assert!(ech_events(&server_log).is_empty());
 }
 
 fn damaged_ech_config(config: &[u8]) -> Vec<u8> {
@@ -910,11 +1127,13 @@ fn ech_retry() {
     server
         .server_enable_ech(ECH_CONFIG_ID, ECH_PUBLIC_NAME, &sk, &pk)
         .unwrap();
+    let server_log = ech_qlog(&mut server);
 
     let mut client = default_client();
     client
         .client_enable_ech(&damaged_ech_config(server.ech_config()))
         .unwrap();
+    let client_log = ech_qlog(&mut client);
 
     let dgram = client.process_output(now()).dgram();
     let dgram = server.process(dgram.as_ref(), now()).dgram();
@@ -942,6 +1161,25 @@ fn ech_retry() {
         );
     };
 
+    let damaged_id = ECH_CONFIG_ID ^ 0x94;
+    assert_eq!(client.ech_state(), EchState::RejectedWithRetry);
+    assert_eq!(
+        ech_events(&client_log),
+        [
+            ech_event(EchState::FallbackPublicName, damaged_id, None),
+            ech_event(EchState::RejectedWithRetry, damaged_id, Some("received")),
+        ]
+    );
+    assert_eq!(server.ech_state(), EchState::RejectedWithRetry);
+    assert_eq!(
+        ech_events(&server_log),
+        [ech_event(
+            EchState::RejectedWithRetry,
+            ECH_CONFIG_ID,
+            Some("sent")
+        )]
+    );
+
     let mut server = default_server();
     server
         .server_enable_ech(ECH_CONFIG_ID, ECH_PUBLIC_NAME, &sk, &pk)
@@ -984,6 +1222,7 @@ fn ech_retry_fallback_rejected() {
     if let Some(CloseReason::Transport(Error::EchRetry(_))) = client.state().error() {
         panic!("Client should not get EchRetry error");
     }
+    assert_eq!(client.ech_state(), EchState::FallbackPublicName);
 
     // Pass the error on.
     let dgram = client.process_output(now()).dgram();
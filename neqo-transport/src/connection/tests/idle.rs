@@ -20,7 +20,6 @@ use super::{
 };
 use crate::{
     packet::PacketBuilder,
-    stats::FrameStats,
     stream_id::{StreamId, StreamType},
     tparams::{self, TransportParameter},
     tracking::PacketNumberSpace,
@@ -310,20 +309,16 @@ fn idle_caching() {
     server.process_input(&dgram.unwrap(), middle);
     assert_eq!(server.stats().frame_rx.ping, ping_before_s + 1);
     let mut tokens = Vec::new();
-    server.crypto.streams.write_frame(
-        PacketNumberSpace::Initial,
-        &mut builder,
-        &mut tokens,
-        &mut FrameStats::default(),
-    );
+    server
+        .crypto
+        .streams
+        .write_frame(PacketNumberSpace::Initial, &mut builder, &mut tokens);
     assert_eq!(tokens.len(), 1);
     tokens.clear();
-    server.crypto.streams.write_frame(
-        PacketNumberSpace::Initial,
-        &mut builder,
-        &mut tokens,
-        &mut FrameStats::default(),
-    );
+    server
+        .crypto
+        .streams
+        .write_frame(PacketNumberSpace::Initial, &mut builder, &mut tokens);
     assert!(tokens.is_empty());
     let dgram = server.process_output(middle).dgram();
 
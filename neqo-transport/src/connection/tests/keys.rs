@@ -216,6 +216,69 @@ fn key_update_consecutive() {
     check_discarded(&mut client, &dgram, false, 1, 0);
 }
 
+#[test]
+fn key_update_reordered() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let mut now = now();
+
+    // Hold back two packets that use the old keys.
+    let early1 = send_something(&mut client, now);
+    let early2 = send_something(&mut client, now);
+    client.initiate_key_update().unwrap();
+    let late = send_something(&mut client, now);
+
+    server.process_input(&late, now);
+    assert_eq!(server.get_epochs(), (Some(4), Some(3)));
+
+    // While the old keys are retained, a reordered packet is still accepted.
+    server.process_input(&early1, now);
+    assert_eq!(server.stats().dropped_rx, 0);
+
+    // Once they are gone, a reordered packet can't be decrypted,
+    // but the failure is attributed to the key update.
+    now += AT_LEAST_PTO;
+    mem::drop(server.process_output(now));
+    assert_eq!(server.get_epochs(), (Some(4), Some(4)));
+    server.process_input(&early2, now);
+    let stats = server.stats();
+    assert_eq!(stats.dropped_rx, 1);
+    assert_eq!(stats.key_phase_failures_rx, 1);
+    assert_eq!(stats.decrypt_failures_rx, 0);
+    assert_eq!(*server.state(), State::Confirmed);
+}
+
+#[test]
+fn key_update_policy_bytes() {
+    const THRESHOLD: u64 = 2_000;
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    server.set_key_update_policy(None, Some(THRESHOLD));
+
+    let stream_id = server.stream_create(StreamType::UniDi).unwrap();
+    server.stream_send(stream_id, &[0; 6_000]).unwrap();
+    let mut sent = 0;
+    let mut dgrams = Vec::new();
+    while let Some(d) = server.process_output(now()).dgram() {
+        sent += u64::try_from(d.len()).unwrap();
+        dgrams.push(d);
+        // The key phase flips once the threshold is crossed.
+        let expected = if sent < THRESHOLD { 3 } else { 4 };
+        assert_eq!(server.get_epochs(), (Some(expected), Some(3)));
+    }
+    assert!(sent > THRESHOLD);
+    assert_eq!(server.get_epochs(), (Some(4), Some(3)));
+
+    // The client follows the update when it sees the new key phase.
+    for d in dgrams {
+        client.process_input(&d, now());
+    }
+    assert_eq!(client.get_epochs(), (Some(4), Some(3)));
+    assert_eq!(client.stats().dropped_rx, 0);
+}
+
 // Key updates can't be initiated too early.
 #[test]
 fn key_update_before_confirmed() {
@@ -12,7 +12,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use neqo_common::{Datagram, Decoder};
+use neqo_common::{event::Provider, Datagram, Decoder};
 use test_fixture::{
     assertions::{assert_v4_path, assert_v6_path},
     fixture_init, new_neqo_qlog, now, DEFAULT_ADDR, DEFAULT_ADDR_V4,
@@ -20,18 +20,19 @@ use test_fixture::{
 
 use super::{
     super::{Connection, Output, State, StreamType},
-    connect_fail, connect_force_idle, connect_rtt_idle, default_client, default_server,
-    maybe_authenticate, new_client, new_server, send_something, CountingConnectionIdGenerator,
+    connect_fail, connect_force_idle, connect_force_idle_with_modifier, connect_rtt_idle, cwnd,
+    default_client, default_server, increase_cwnd, maybe_authenticate, new_client, new_server,
+    send_something, CountingConnectionIdGenerator, DEFAULT_RTT,
 };
 use crate::{
     cid::LOCAL_ACTIVE_CID_LIMIT,
     connection::tests::send_something_paced,
     frame::FRAME_TYPE_NEW_CONNECTION_ID,
     packet::PacketBuilder,
-    path::{PATH_MTU_V4, PATH_MTU_V6},
+    path::{PathState, PATH_MTU_V4, PATH_MTU_V6},
     tparams::{self, PreferredAddress, TransportParameter},
-    CloseReason, ConnectionId, ConnectionIdDecoder, ConnectionIdGenerator, ConnectionIdRef,
-    ConnectionParameters, EmptyConnectionIdGenerator, Error,
+    CloseReason, ConnectionEvent, ConnectionId, ConnectionIdDecoder, ConnectionIdGenerator,
+    ConnectionIdRef, ConnectionParameters, EmptyConnectionIdGenerator, Error,
 };
 
 /// This should be a valid-seeming transport parameter.
@@ -98,6 +99,172 @@ fn rebinding_port() {
     assert_eq!(dgram.destination(), new_port(DEFAULT_ADDR));
 }
 
+/// The IPv4-mapped IPv6 form of an IPv4 address.
+fn mapped(a: SocketAddr) -> SocketAddr {
+    let IpAddr::V4(v4) = a.ip() else {
+        panic!("not an IPv4 address");
+    };
+    SocketAddr::new(IpAddr::V6(v4.to_ipv6_mapped()), a.port())
+}
+
+/// A dual-stack socket might report IPv4 addresses in mapped form.
+/// That isn't a migration, so it shouldn't create a new path at either end.
+#[test]
+fn ipv4_mapped_same_path() {
+    fixture_init();
+    let mut client = Connection::new_client(
+        test_fixture::DEFAULT_SERVER_NAME,
+        test_fixture::DEFAULT_ALPN,
+        Rc::new(RefCell::new(CountingConnectionIdGenerator::default())),
+        DEFAULT_ADDR_V4,
+        DEFAULT_ADDR_V4,
+        ConnectionParameters::default(),
+        now(),
+    )
+    .unwrap();
+    let mut server = default_server();
+    connect_force_idle_with_modifier(&mut client, &mut server, |d| {
+        Some(change_path(&d, mapped(DEFAULT_ADDR_V4)))
+    });
+
+    // The server sees the plain IPv4 form and the client sees the mapped form.
+    let dgram = send_something(&mut client, now());
+    assert_eq!(dgram.source(), DEFAULT_ADDR_V4);
+    server.process_input(&dgram, now());
+    let dgram = send_something(&mut server, now());
+    assert_eq!(dgram.destination(), mapped(DEFAULT_ADDR_V4));
+    client.process_input(&dgram, now());
+
+    assert_eq!(client.paths().len(), 1);
+    assert_eq!(server.paths().len(), 1);
+    assert_eq!(client.stats().frame_rx.path_challenge, 0);
+    assert_eq!(server.stats().frame_rx.path_challenge, 0);
+}
+
+/// A client can't migrate if the server asked it not to.
+#[test]
+fn disable_migration_refused() {
+    let mut client = default_client();
+    let mut server = new_server(ConnectionParameters::default().disable_migration(true));
+    connect_force_idle(&mut client, &mut server);
+
+    assert_eq!(
+        client
+            .migrate(Some(DEFAULT_ADDR_V4), Some(DEFAULT_ADDR_V4), false, now())
+            .unwrap_err(),
+        Error::InvalidMigration
+    );
+}
+
+/// A server that asked the client not to migrate doesn't follow a change
+/// in the client port until the new path is validated.  This allows for
+/// NAT rebinding, but not for an attacker that rewrites the port.
+#[test]
+fn disable_migration_rebinding() {
+    let mut client = default_client();
+    let mut server = new_server(ConnectionParameters::default().disable_migration(true));
+    connect_force_idle(&mut client, &mut server);
+    let now = now();
+
+    let dgram = change_source_port(&send_something(&mut client, now));
+    server.process_input(&dgram, now);
+
+    // The server probes the new port, but continues to use the old one.
+    let probe = server.process_output(now).dgram().unwrap();
+    assert_eq!(probe.destination(), new_port(DEFAULT_ADDR));
+    assert_eq!(server.stats().frame_tx.path_challenge, 1);
+    let data = send_something(&mut server, now);
+    assert_eq!(data.destination(), DEFAULT_ADDR);
+
+    // The NAT delivers the probe to the client, which responds.
+    let probe = Datagram::new(
+        probe.source(),
+        DEFAULT_ADDR,
+        probe.tos(),
+        probe.ttl(),
+        &probe[..],
+    );
+    client.process_input(&probe, now);
+    let resp = client.process_output(now).dgram().unwrap();
+    assert_eq!(client.stats().frame_tx.path_response, 1);
+
+    // Once the response arrives through the NAT, the server moves to the new port.
+    server.process_input(&change_source_port(&resp), now);
+    let data = send_something(&mut server, now);
+    assert_eq!(data.destination(), new_port(DEFAULT_ADDR));
+}
+
+/// Grow the congestion window at the server, then deliver a packet from the
+/// client after passing it through `change`.  This returns the server, the
+/// modified datagram, the time, and the congestion window of the server's
+/// primary path before and after the change.
+fn server_cwnd_after_migration(
+    params: ConnectionParameters,
+    change: impl FnOnce(&Datagram) -> Datagram,
+) -> (Connection, Datagram, Instant, usize, usize) {
+    let mut client = default_client();
+    let mut server = new_server(params);
+    connect_force_idle(&mut client, &mut server);
+
+    let stream_id = server.stream_create(StreamType::UniDi).unwrap();
+    let now = increase_cwnd(&mut server, &mut client, stream_id, now());
+    let before = cwnd(&server);
+
+    let dgram = change(&send_something(&mut client, now));
+    server.process_input(&dgram, now);
+    let after = cwnd(&server);
+    (server, dgram, now, before, after)
+}
+
+#[test]
+fn rebinding_port_keeps_cwnd() {
+    let (_, _, _, before, after) =
+        server_cwnd_after_migration(ConnectionParameters::default(), change_source_port);
+    assert_eq!(before, after);
+}
+
+#[test]
+fn rebinding_port_resets_cwnd_when_disabled() {
+    let (_, _, _, before, after) = server_cwnd_after_migration(
+        ConnectionParameters::default().fast_rebinding(false),
+        change_source_port,
+    );
+    assert!(after < before);
+}
+
+#[test]
+fn rebinding_address_resets_cwnd() {
+    let (_, _, _, before, after) =
+        server_cwnd_after_migration(ConnectionParameters::default(), |d| {
+            change_path(d, DEFAULT_ADDR_V4)
+        });
+    assert!(after < before);
+}
+
+/// An attacker that rewrites the source port of a packet can get the server
+/// to follow, but the server sends no more than the amplification limit to
+/// the new port until that port is validated.
+#[test]
+fn rebinding_port_amplification_limit() {
+    let (mut server, dgram, mut now, _, _) =
+        server_cwnd_after_migration(ConnectionParameters::default(), change_source_port);
+
+    let mut sent = 0;
+    loop {
+        match server.process_output(now) {
+            Output::Datagram(d) => {
+                if d.destination() == dgram.source() {
+                    sent += d.len();
+                }
+            }
+            Output::Callback(t) if t < DEFAULT_RTT => now += t,
+            _ => break,
+        }
+    }
+    assert!(sent > 0);
+    assert!(sent <= dgram.len() * 3);
+}
+
 /// This simulates an attack where a valid packet is forwarded on
 /// a different path.  This shows how both paths are probed and the
 /// server eventually returns to the original path.
@@ -220,6 +387,38 @@ fn migrate_immediate() {
     assert_v4_path(&client3, false);
 }
 
+/// After migrating to a new local address, every datagram the client sends uses
+/// that address, including path probes, retransmissions, and PTO probes.
+#[test]
+fn migrate_local_address_all_output() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let mut now = now();
+    let local = new_port(DEFAULT_ADDR);
+    let check = |d: &Datagram| {
+        assert_eq!(d.source(), local);
+        assert_eq!(d.destination(), DEFAULT_ADDR);
+    };
+
+    client.migrate(Some(local), None, true, now).unwrap();
+    let probe = send_something(&mut client, now);
+    check(&probe);
+    let resp = server.process(Some(&probe), now).dgram().unwrap();
+    assert_eq!(resp.destination(), local);
+    client.process_input(&resp, now);
+
+    // Lose everything from here on, so that the client has to retransmit.
+    check(&send_something(&mut client, now));
+    while client.stats().pto_counts[2] == 0 {
+        match client.process_output(now) {
+            Output::Datagram(d) => check(&d),
+            Output::Callback(t) => now += t,
+            Output::None => panic!("connection should not be idle"),
+        }
+    }
+}
+
 /// RTT estimates for paths should be preserved across migrations.
 #[test]
 fn migrate_rtt() {
@@ -269,7 +468,9 @@ fn migrate_immediate_fail() {
             assert_v4_path(&probe, false); // Contains PATH_CHALLENGE.
             let after = client.stats().frame_tx;
             assert_eq!(after.ping, before.ping + 1);
-            assert_eq!(after.all, before.all + 3);
+            // The PING is padded so that there is enough to sample for header protection.
+            assert_eq!(after.padding, before.padding + 2);
+            assert_eq!(after.all, before.all + 4);
         }
     }
 
@@ -344,7 +545,9 @@ fn migrate_same_fail() {
             assert_v6_path(&probe, false); // Contains PATH_CHALLENGE.
             let after = client.stats().frame_tx;
             assert_eq!(after.ping, before.ping + 1);
-            assert_eq!(after.all, before.all + 3);
+            // The PING is padded so that there is enough to sample for header protection.
+            assert_eq!(after.padding, before.padding + 2);
+            assert_eq!(after.all, before.all + 4);
         }
     }
 
@@ -441,6 +644,129 @@ fn migration_graceful() {
     migration(default_client());
 }
 
+/// After a migration, both paths are reported, until the old path is retired.
+#[test]
+fn migration_path_info() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let now = now();
+    assert_eq!(client.paths().len(), 1);
+
+    client
+        .migrate(Some(DEFAULT_ADDR_V4), Some(DEFAULT_ADDR_V4), false, now)
+        .unwrap();
+    let probe = client.process_output(now).dgram().unwrap();
+    assert_v4_path(&probe, true); // Contains PATH_CHALLENGE.
+
+    let paths = client.paths();
+    assert_eq!(paths.len(), 2);
+    let probed = paths.iter().find(|p| p.remote == DEFAULT_ADDR_V4).unwrap();
+    assert_eq!(probed.state, PathState::Probing);
+    assert_eq!(probed.local, DEFAULT_ADDR_V4);
+    assert!(probed.validated_at.is_none());
+    assert!(!probed.active);
+
+    let resp = server.process(Some(&probe), now).dgram().unwrap();
+    client.process_input(&resp, now);
+
+    let paths = client.paths();
+    assert_eq!(paths.len(), 2);
+    let new = paths.iter().find(|p| p.remote == DEFAULT_ADDR_V4).unwrap();
+    assert_eq!(new.state, PathState::Valid);
+    assert_eq!(new.validated_at, Some(now));
+    assert!(new.active);
+    let old = paths.iter().find(|p| p.remote == DEFAULT_ADDR).unwrap();
+    assert_eq!(old.state, PathState::Valid);
+    assert!(old.rtt.is_some());
+    assert!(!old.active);
+
+    // The old path is retired once it hasn't been used for a while.
+    let later = now + client.pto() * 5;
+    mem::drop(client.process_output(later));
+    let paths = client.paths();
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].remote, DEFAULT_ADDR_V4);
+    assert!(paths[0].active);
+}
+
+fn abandoned_paths(c: &mut Connection) -> Vec<(SocketAddr, SocketAddr)> {
+    c.events()
+        .filter_map(|e| match e {
+            ConnectionEvent::PathAbandoned { local, remote } => Some((local, remote)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A path that fails validation is abandoned and its connection ID is retired.
+#[test]
+fn migration_fail_abandons_path() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let mut now = now();
+
+    client
+        .migrate(Some(DEFAULT_ADDR_V4), Some(DEFAULT_ADDR_V4), false, now)
+        .unwrap();
+    let probe = client.process_output(now).dgram().unwrap();
+    assert_v4_path(&probe, true); // Contains PATH_CHALLENGE.
+    assert_eq!(client.paths().len(), 2);
+
+    // Drop all probes until the client gives up.
+    for _ in 0..10 {
+        while client.process_output(now).dgram().is_some() {}
+        if client.paths().len() == 1 {
+            break;
+        }
+        now += client.process_output(now).callback();
+    }
+    assert_eq!(*client.state(), State::Confirmed);
+    assert_eq!(
+        abandoned_paths(&mut client),
+        [(DEFAULT_ADDR_V4, DEFAULT_ADDR_V4)]
+    );
+    assert!(client.paths()[0].active);
+
+    let dgram = send_something(&mut client, now);
+    assert_v6_path(&dgram, false);
+    assert_eq!(client.stats().frame_tx.retire_connection_id, 1);
+}
+
+/// With `path_idle_timeout`, an old path is kept until that much time passes
+/// without it being used.  The active path is not affected.
+#[test]
+fn migration_path_idle_timeout() {
+    const IDLE: Duration = Duration::from_secs(10);
+    let mut client = new_client(ConnectionParameters::default().path_idle_timeout(IDLE));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let now = now();
+
+    client
+        .migrate(Some(DEFAULT_ADDR_V4), Some(DEFAULT_ADDR_V4), false, now)
+        .unwrap();
+    let probe = client.process_output(now).dgram().unwrap();
+    let resp = server.process(Some(&probe), now).dgram().unwrap();
+    client.process_input(&resp, now);
+    assert_eq!(client.paths().len(), 2);
+
+    // Without the setting, the old path would be gone by now.
+    let later = now + client.pto() * 5;
+    assert!(later < now + IDLE);
+    mem::drop(client.process_output(later));
+    assert_eq!(client.paths().len(), 2);
+    assert!(abandoned_paths(&mut client).is_empty());
+
+    mem::drop(client.process_output(now + IDLE));
+    let paths = client.paths();
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].remote, DEFAULT_ADDR_V4);
+    assert!(paths[0].active);
+    assert_eq!(abandoned_paths(&mut client), [(DEFAULT_ADDR, DEFAULT_ADDR)]);
+}
+
 /// A client should be able to migrate when it has a zero-length connection ID.
 #[test]
 fn migration_client_empty_cid() {
@@ -470,6 +796,20 @@ fn fast_handshake(client: &mut Connection, server: &mut Connection) -> Option<Da
 }
 
 fn preferred_address(hs_client: SocketAddr, hs_server: SocketAddr, preferred: SocketAddr) {
+    preferred_address_with(
+        hs_client,
+        hs_server,
+        preferred,
+        ConnectionParameters::default(),
+    );
+}
+
+fn preferred_address_with(
+    hs_client: SocketAddr,
+    hs_server: SocketAddr,
+    preferred: SocketAddr,
+    server_params: ConnectionParameters,
+) {
     let mtu = match hs_client.ip() {
         IpAddr::V4(_) => PATH_MTU_V4,
         IpAddr::V6(_) => PATH_MTU_V6,
@@ -521,7 +861,7 @@ fn preferred_address(hs_client: SocketAddr, hs_server: SocketAddr, preferred: So
         SocketAddr::V6(v6) => PreferredAddress::new(None, Some(v6)),
         SocketAddr::V4(v4) => PreferredAddress::new(Some(v4), None),
     };
-    let mut server = new_server(ConnectionParameters::default().preferred_address(spa));
+    let mut server = new_server(server_params.preferred_address(spa));
 
     let dgram = fast_handshake(&mut client, &mut server);
 
@@ -565,6 +905,18 @@ fn preferred_address(hs_client: SocketAddr, hs_server: SocketAddr, preferred: So
     assert_from_spa(&data, false);
 }
 
+/// A server that asks clients not to migrate still lets them use its preferred address.
+#[test]
+fn preferred_address_disable_migration() {
+    let a = DEFAULT_ADDR;
+    preferred_address_with(
+        a,
+        a,
+        new_port(a),
+        ConnectionParameters::default().disable_migration(true),
+    );
+}
+
 /// Migration works for a new port number.
 #[test]
 fn preferred_address_new_port() {
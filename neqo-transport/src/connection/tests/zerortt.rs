@@ -6,17 +6,19 @@
 
 use std::{cell::RefCell, rc::Rc};
 
-use neqo_common::event::Provider;
+use neqo_common::{event::Provider, Datagram};
 use neqo_crypto::{AllowZeroRtt, AntiReplay};
 use test_fixture::{assertions, now};
 
 use super::{
-    super::Connection, connect, default_client, default_server, exchange_ticket, new_server,
-    resumed_server, CountingConnectionIdGenerator,
+    super::{Connection, State},
+    connect, default_client, default_server, exchange_ticket, new_server, resumed_server,
+    CountingConnectionIdGenerator,
 };
 use crate::{
-    events::ConnectionEvent, ConnectionParameters, Error, StreamType, Version,
-    MIN_INITIAL_PACKET_SIZE,
+    events::ConnectionEvent,
+    packet::{PacketType, PublicPacket},
+    ConnectionParameters, Error, StreamType, Version, MIN_INITIAL_PACKET_SIZE,
 };
 
 #[test]
@@ -83,6 +85,52 @@ fn zero_rtt_send_recv() {
     assert_eq!(client_stream_id, server_stream_id.as_u64());
 }
 
+/// A datagram that contains an Initial packet needs to be padded.
+/// As the Initial is always first, only the first packet needs to be checked.
+fn assert_initial_padded(dgram: &Datagram) {
+    let (packet, _) =
+        PublicPacket::decode(&dgram[..], &CountingConnectionIdGenerator::default()).unwrap();
+    if packet.packet_type() == PacketType::Initial {
+        assert!(dgram.len() >= MIN_INITIAL_PACKET_SIZE);
+    }
+}
+
+/// Check every datagram that a client sends in a 0-RTT handshake, including
+/// when a small amount of 0-RTT is coalesced with the Initial packet, and
+/// when the Initial packet is retransmitted after the first flight is lost.
+#[test]
+fn zero_rtt_initial_padding() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect(&mut client, &mut server);
+
+    let token = exchange_ticket(&mut client, &mut server, now());
+    let mut client = default_client();
+    client
+        .enable_resumption(now(), token)
+        .expect("should set token");
+    let mut server = resumed_server(&client);
+
+    let client_stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(client_stream_id, &[1]).unwrap();
+    let lost = client.process_output(now()).dgram().unwrap();
+    assertions::assert_coalesced_0rtt(&lost[..]);
+    assert_initial_padded(&lost);
+
+    // Wait for the PTO, then run the handshake to completion.
+    let now = now() + client.process_output(now()).callback();
+    let mut input = None;
+    while *client.state() != State::Confirmed {
+        let output = client.process(input.as_ref(), now).dgram();
+        if let Some(d) = &output {
+            assert_initial_padded(d);
+        }
+        input = server.process(output.as_ref(), now).dgram();
+        assert!(output.is_some() || input.is_some());
+    }
+    assert!(client.tls_info().unwrap().early_data_accepted());
+}
+
 #[test]
 fn zero_rtt_send_coalesce() {
     let mut client = default_client();
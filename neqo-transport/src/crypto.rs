@@ -30,7 +30,6 @@ use crate::{
     recovery::RecoveryToken,
     recv_stream::RxStreamOrderer,
     send_stream::TxBuffer,
-    stats::FrameStats,
     tparams::{TpZeroRttChecker, TransportParameters, TransportParametersHandler},
     tracking::PacketNumberSpace,
     version::Version,
@@ -38,6 +37,24 @@ use crate::{
 };
 
 const MAX_AUTH_TAG: usize = 32;
+
+/// When to update write keys, before the keys are close to being exhausted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyUpdatePolicy {
+    /// Update after this many packets have been sent with the same keys.
+    pub after_packets: Option<u64>,
+    /// Update after this many bytes have been sent with the same keys.
+    pub after_bytes: Option<u64>,
+}
+
+impl KeyUpdatePolicy {
+    fn due(&self, dx: &CryptoDxState) -> bool {
+        self.after_packets
+            .map_or(false, |p| dx.packets_written >= p)
+            || self.after_bytes.map_or(false, |b| dx.bytes_written >= b)
+    }
+}
+
 /// The number of invocations remaining on a write cipher before we try
 /// to update keys.  This has to be much smaller than the number returned
 /// by `CryptoDxState::limit` or updates will happen too often.  As we don't
@@ -161,6 +178,46 @@ impl Crypto {
         }
     }
 
+    pub fn add_external_psk(&mut self, identity: &[u8], key: &[u8], cipher: Cipher) -> Res<()> {
+        self.tls.add_external_psk(identity, key, cipher)?;
+        Ok(())
+    }
+
+    pub fn server_disable_resumption(&mut self) -> Res<()> {
+        if let Agent::Server(s) = &mut self.tls {
+            s.disable_resumption()?;
+            Ok(())
+        } else {
+            panic!("not a server");
+        }
+    }
+
+    pub fn server_require_client_auth(&mut self, cas: &[impl AsRef<str>]) -> Res<()> {
+        if let Agent::Server(s) = &mut self.tls {
+            s.require_client_auth(cas)?;
+            Ok(())
+        } else {
+            panic!("not a server");
+        }
+    }
+
+    pub fn client_set_certificate(&mut self, nickname: &str) -> Res<()> {
+        if let Agent::Client(c) = &mut self.tls {
+            c.set_client_certificate(nickname)?;
+            Ok(())
+        } else {
+            panic!("not a client");
+        }
+    }
+
+    pub fn server_name(&self) -> Option<&str> {
+        if let Agent::Server(s) = &self.tls {
+            s.server_name()
+        } else {
+            None
+        }
+    }
+
     /// Get the active ECH configuration, which is empty if ECH is disabled.
     pub fn ech_config(&self) -> &[u8] {
         self.tls.ech_config()
@@ -309,9 +366,8 @@ impl Crypto {
         space: PacketNumberSpace,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
-        self.streams.write_frame(space, builder, tokens, stats);
+        self.streams.write_frame(space, builder, tokens);
     }
 
     pub fn acked(&mut self, token: &CryptoRecoveryToken) {
@@ -418,6 +474,10 @@ pub struct CryptoDxState {
     /// The total number of operations that are remaining before the keys
     /// become exhausted and can't be used any more.
     invocations: PacketNumber,
+    /// The number of packets and bytes that have been protected with these keys.
+    /// Only writing is counted.
+    packets_written: u64,
+    bytes_written: u64,
 }
 
 impl CryptoDxState {
@@ -446,6 +506,8 @@ impl CryptoDxState {
             used_pn: 0..0,
             min_pn: 0,
             invocations: Self::limit(direction, cipher),
+            packets_written: 0,
+            bytes_written: 0,
         }
     }
 
@@ -539,6 +601,8 @@ impl CryptoDxState {
             used_pn: pn..pn,
             min_pn: pn,
             invocations,
+            packets_written: 0,
+            bytes_written: 0,
         }
     }
 
@@ -650,6 +714,8 @@ impl CryptoDxState {
         qtrace!([self], "encrypt ct={}", hex(res));
         debug_assert_eq!(pn, self.next_pn());
         self.used(pn)?;
+        self.packets_written += 1;
+        self.bytes_written += u64::try_from(res.len())?;
         Ok(res.to_vec())
     }
 
@@ -800,6 +866,7 @@ pub struct CryptoStates {
     // If this is set, then we have noticed a genuine update.
     // Once this time passes, we should switch in new keys.
     read_update_time: Option<Instant>,
+    key_update_policy: KeyUpdatePolicy,
 }
 
 impl CryptoStates {
@@ -1148,6 +1215,18 @@ impl CryptoStates {
         Ok(())
     }
 
+    pub fn set_key_update_policy(&mut self, policy: KeyUpdatePolicy) {
+        self.key_update_policy = policy;
+    }
+
+    /// Whether the key update policy calls for the write keys to be updated.
+    #[must_use]
+    pub fn key_update_due(&self) -> bool {
+        self.app_write
+            .as_ref()
+            .map_or(false, |app| self.key_update_policy.due(&app.dx))
+    }
+
     fn has_0rtt_read(&self) -> bool {
         self.zero_rtt
             .as_ref()
@@ -1176,6 +1255,19 @@ impl CryptoStates {
         Ok(())
     }
 
+    /// Whether a 1-RTT packet with the given key phase might fail to decrypt
+    /// because of a key update rather than because it is corrupted.
+    /// That is the case when the key phase selects the next generation of keys,
+    /// or when the previous generation is still being retained.
+    #[must_use]
+    pub fn key_phase_ambiguous(&self, key_phase: bool) -> bool {
+        (self.read_update_time.is_some() && !self.has_0rtt_read())
+            || self
+                .app_read
+                .as_ref()
+                .map_or(false, |ar| ar.dx.key_phase() != key_phase)
+    }
+
     #[must_use]
     pub fn update_time(&self) -> Option<Instant> {
         self.read_update_time
@@ -1256,6 +1348,7 @@ impl CryptoStates {
             app_read: Some(app_read(3)),
             app_read_next: Some(app_read(4)),
             read_update_time: None,
+            key_update_policy: KeyUpdatePolicy::default(),
         }
     }
 
@@ -1289,6 +1382,8 @@ impl CryptoStates {
                 used_pn: 0..645_971_972,
                 min_pn: 0,
                 invocations: 10,
+                packets_written: 0,
+                bytes_written: 0,
             },
             cipher: TLS_CHACHA20_POLY1305_SHA256,
             next_secret: secret.clone(),
@@ -1302,6 +1397,7 @@ impl CryptoStates {
             app_read: Some(app_read(3)),
             app_read_next: Some(app_read(4)),
             read_update_time: None,
+            key_update_policy: KeyUpdatePolicy::default(),
         }
     }
 }
@@ -1460,7 +1556,6 @@ impl CryptoStreams {
         space: PacketNumberSpace,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
         let cs = self.get_mut(space).unwrap();
         if let Some((offset, data)) = cs.tx.next_bytes() {
@@ -1490,7 +1585,6 @@ impl CryptoStreams {
                 offset,
                 length,
             }));
-            stats.crypto += 1;
         }
     }
 }
@@ -4,16 +4,26 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::ops::{AddAssign, Deref, DerefMut, Sub};
+use std::{
+    ops::{AddAssign, Deref, DerefMut, Sub},
+    time::{Duration, Instant},
+};
 
 use enum_map::EnumMap;
 use neqo_common::{qdebug, qinfo, qwarn, IpTosEcn};
+#[cfg(feature = "serde")]
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+#[cfg(feature = "serde")]
+use serde_derive::Serialize;
 
 use crate::{packet::PacketNumber, recovery::SentPacket};
 
 /// The number of packets to use for testing a path for ECN capability.
 pub const ECN_TEST_COUNT: usize = 10;
 
+/// How long to wait after ECN validation fails before testing the path again.
+pub const ECN_RETEST_INTERVAL: Duration = Duration::from_secs(60);
+
 /// The state information related to testing a path for ECN capability.
 /// See RFC9000, Appendix A.4.
 #[derive(Debug, PartialEq, Clone)]
@@ -35,6 +45,33 @@ impl Default for EcnValidationState {
     }
 }
 
+/// The outcome of ECN validation on a path, as reported in [`crate::Stats`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EcnValidationOutcome {
+    /// The path is being tested.
+    #[default]
+    Testing,
+    /// Testing has concluded, but no acknowledgment has confirmed the outcome yet.
+    Unknown,
+    /// The path is not ECN capable, so packets are sent without a mark.
+    Failed,
+    /// The path is ECN capable.
+    Capable,
+}
+
+impl From<&EcnValidationState> for EcnValidationOutcome {
+    fn from(state: &EcnValidationState) -> Self {
+        match state {
+            EcnValidationState::Testing(_) => Self::Testing,
+            EcnValidationState::Unknown => Self::Unknown,
+            EcnValidationState::Failed => Self::Failed,
+            EcnValidationState::Capable => Self::Capable,
+        }
+    }
+}
+
 /// The counts for different ECN marks.
 #[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
 pub struct EcnCount(EnumMap<IpTosEcn, u64>);
@@ -54,17 +91,32 @@ impl DerefMut for EcnCount {
 }
 
 impl EcnCount {
+    #[must_use]
     pub fn new(not_ect: u64, ect0: u64, ect1: u64, ce: u64) -> Self {
         // Yes, the enum array order is different from the argument order.
         Self(EnumMap::from_array([not_ect, ect1, ect0, ce]))
     }
 
     /// Whether any of the ECN counts are non-zero.
+    #[must_use]
     pub fn is_some(&self) -> bool {
         self[IpTosEcn::Ect0] > 0 || self[IpTosEcn::Ect1] > 0 || self[IpTosEcn::Ce] > 0
     }
 }
 
+/// This serializes as an object with one field for each codepoint.
+#[cfg(feature = "serde")]
+impl Serialize for EcnCount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("EcnCount", 4)?;
+        s.serialize_field("not_ect", &self[IpTosEcn::NotEct])?;
+        s.serialize_field("ect0", &self[IpTosEcn::Ect0])?;
+        s.serialize_field("ect1", &self[IpTosEcn::Ect1])?;
+        s.serialize_field("ce", &self[IpTosEcn::Ce])?;
+        s.end()
+    }
+}
+
 impl Sub<EcnCount> for EcnCount {
     type Output = EcnCount;
 
@@ -94,6 +146,10 @@ pub struct EcnInfo {
 
     /// The ECN counts from the last ACK frame that increased `largest_acked`.
     baseline: EcnCount,
+
+    /// When to test the path again after validation failed.
+    /// This is set when the first packet is sent after the failure.
+    retest_at: Option<Instant>,
 }
 
 impl EcnInfo {
@@ -111,14 +167,29 @@ impl EcnInfo {
     /// Exit ECN validation if the number of packets sent exceeds `ECN_TEST_COUNT`.
     /// We do not implement the part of the RFC that says to exit ECN validation if the time since
     /// the start of ECN validation exceeds 3 * PTO, since this seems to happen much too quickly.
-    pub fn on_packet_sent(&mut self) {
-        if let EcnValidationState::Testing(ref mut probes_sent) = &mut self.state {
-            *probes_sent += 1;
-            qdebug!("ECN probing: sent {} probes", probes_sent);
-            if *probes_sent == ECN_TEST_COUNT {
-                qdebug!("ECN probing concluded with {} probes sent", probes_sent);
-                self.state = EcnValidationState::Unknown;
+    ///
+    /// A path that failed validation is tested again once `ECN_RETEST_INTERVAL` has passed,
+    /// in case whatever interfered with the marks has gone away.
+    pub fn on_packet_sent(&mut self, now: Instant) {
+        match &mut self.state {
+            EcnValidationState::Testing(ref mut probes_sent) => {
+                *probes_sent += 1;
+                qdebug!("ECN probing: sent {} probes", probes_sent);
+                if *probes_sent == ECN_TEST_COUNT {
+                    qdebug!("ECN probing concluded with {} probes sent", probes_sent);
+                    self.state = EcnValidationState::Unknown;
+                }
             }
+            EcnValidationState::Failed => match self.retest_at {
+                Some(t) if now >= t => {
+                    qinfo!("ECN validation failed earlier, testing path again");
+                    self.retest_at = None;
+                    self.state = EcnValidationState::Testing(0);
+                }
+                Some(_) => {}
+                None => self.retest_at = Some(now + ECN_RETEST_INTERVAL),
+            },
+            EcnValidationState::Unknown | EcnValidationState::Capable => {}
         }
     }
 
@@ -215,6 +286,11 @@ impl EcnInfo {
         self.largest_acked = largest_acked;
     }
 
+    /// The outcome of ECN validation on this path so far.
+    pub fn outcome(&self) -> EcnValidationOutcome {
+        EcnValidationOutcome::from(&self.state)
+    }
+
     /// The ECN mark to use for packets sent on this path.
     pub fn ecn_mark(&self) -> IpTosEcn {
         match self.state {
@@ -6,7 +6,13 @@
 
 // Collecting a list of events relevant to whoever is using the Connection.
 
-use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    net::SocketAddr,
+    rc::Rc,
+    time::Instant,
+};
 
 use neqo_common::event::Provider as EventProvider;
 use neqo_crypto::ResumptionToken;
@@ -78,15 +84,37 @@ pub enum ConnectionEvent {
         outcome: OutgoingDatagramOutcome,
     },
     IncomingDatagramDropped,
+    /// A path was abandoned, either because validation failed or because
+    /// it was not used for too long.  The connection IDs it used are retired.
+    PathAbandoned {
+        local: SocketAddr,
+        remote: SocketAddr,
+    },
 }
 
 #[derive(Debug, Default, Clone)]
 #[allow(clippy::module_name_repetitions)]
 pub struct ConnectionEvents {
-    events: Rc<RefCell<VecDeque<ConnectionEvent>>>,
+    events: Rc<RefCell<VecDeque<(Instant, ConnectionEvent)>>>,
+    /// The time passed to the `process` call that is currently running.
+    now: Rc<Cell<Option<Instant>>>,
 }
 
 impl ConnectionEvents {
+    /// Set the time that is attached to events queued from here on.
+    pub fn set_now(&self, now: Instant) {
+        self.now.set(Some(now));
+    }
+
+    fn now(&self) -> Instant {
+        self.now.get().unwrap_or_else(Instant::now)
+    }
+
+    /// Get the next event, along with the time at which it was generated.
+    pub fn next_event_timed(&mut self) -> Option<(Instant, ConnectionEvent)> {
+        self.events.borrow_mut().pop_front()
+    }
+
     pub fn authentication_needed(&self) {
         self.insert(ConnectionEvent::AuthenticationNeeded);
     }
@@ -159,18 +187,35 @@ impl ConnectionEvents {
         self.insert(ConnectionEvent::ZeroRttRejected);
     }
 
+    pub fn path_abandoned(&self, local: SocketAddr, remote: SocketAddr) {
+        self.insert(ConnectionEvent::PathAbandoned { local, remote });
+    }
+
     pub fn recv_stream_complete(&self, stream_id: StreamId) {
         // If stopped, no longer readable.
         self.remove(|evt| matches!(evt, ConnectionEvent::RecvStreamReadable { stream_id: x } if *x == stream_id.as_u64()));
     }
 
+    /// Remove all events that relate to the identified stream.
+    pub fn remove_stream_events(&self, stream_id: StreamId) {
+        self.remove(|evt| match evt {
+            ConnectionEvent::NewStream { stream_id: x }
+            | ConnectionEvent::SendStreamWritable { stream_id: x }
+            | ConnectionEvent::RecvStreamReadable { stream_id: x }
+            | ConnectionEvent::RecvStreamReset { stream_id: x, .. }
+            | ConnectionEvent::SendStreamStopSending { stream_id: x, .. }
+            | ConnectionEvent::SendStreamComplete { stream_id: x } => *x == stream_id,
+            _ => false,
+        });
+    }
+
     // The number of datagrams in the events queue is limited to max_queued_datagrams.
     // This function ensure this and deletes the oldest datagrams if needed.
     fn check_datagram_queued(&self, max_queued_datagrams: usize, stats: &mut Stats) {
         let mut q = self.events.borrow_mut();
         let mut remove = None;
         if q.iter()
-            .filter(|evt| matches!(evt, ConnectionEvent::Datagram(_)))
+            .filter(|(_, evt)| matches!(evt, ConnectionEvent::Datagram(_)))
             .count()
             == max_queued_datagrams
         {
@@ -178,7 +223,7 @@ impl ConnectionEvents {
                 .iter()
                 .rev()
                 .enumerate()
-                .filter(|(_, evt)| matches!(evt, ConnectionEvent::Datagram(_)))
+                .filter(|(_, (_, evt))| matches!(evt, ConnectionEvent::Datagram(_)))
                 .take(1)
                 .next()
             {
@@ -187,7 +232,7 @@ impl ConnectionEvents {
         }
         if let Some(r) = remove {
             q.remove(r);
-            q.push_back(ConnectionEvent::IncomingDatagramDropped);
+            q.push_back((self.now(), ConnectionEvent::IncomingDatagramDropped));
             stats.incoming_datagram_dropped += 1;
         }
     }
@@ -196,7 +241,7 @@ impl ConnectionEvents {
         self.check_datagram_queued(max_queued_datagrams, stats);
         self.events
             .borrow_mut()
-            .push_back(ConnectionEvent::Datagram(data.to_vec()));
+            .push_back((self.now(), ConnectionEvent::Datagram(data.to_vec())));
     }
 
     pub fn datagram_outcome(
@@ -205,9 +250,10 @@ impl ConnectionEvents {
         outcome: OutgoingDatagramOutcome,
     ) {
         if let DatagramTracking::Id(id) = dgram_tracker {
-            self.events
-                .borrow_mut()
-                .push_back(ConnectionEvent::OutgoingDatagramOutcome { id: *id, outcome });
+            self.events.borrow_mut().push_back((
+                self.now(),
+                ConnectionEvent::OutgoingDatagramOutcome { id: *id, outcome },
+            ));
         }
     }
 
@@ -217,18 +263,18 @@ impl ConnectionEvents {
         // Special-case two enums that are not strictly PartialEq equal but that
         // we wish to avoid inserting duplicates.
         let already_present = match &event {
-            ConnectionEvent::SendStreamStopSending { stream_id, .. } => q.iter().any(|evt| {
+            ConnectionEvent::SendStreamStopSending { stream_id, .. } => q.iter().any(|(_, evt)| {
                 matches!(evt, ConnectionEvent::SendStreamStopSending { stream_id: x, .. }
 		                    if *x == *stream_id)
             }),
-            ConnectionEvent::RecvStreamReset { stream_id, .. } => q.iter().any(|evt| {
+            ConnectionEvent::RecvStreamReset { stream_id, .. } => q.iter().any(|(_, evt)| {
                 matches!(evt, ConnectionEvent::RecvStreamReset { stream_id: x, .. }
 		                    if *x == *stream_id)
             }),
-            _ => q.contains(&event),
+            _ => q.iter().any(|(_, evt)| *evt == event),
         };
         if !already_present {
-            q.push_back(event);
+            q.push_back((self.now(), event));
         }
     }
 
@@ -236,7 +282,7 @@ impl ConnectionEvents {
     where
         F: Fn(&ConnectionEvent) -> bool,
     {
-        self.events.borrow_mut().retain(|evt| !f(evt));
+        self.events.borrow_mut().retain(|(_, evt)| !f(evt));
     }
 }
 
@@ -248,7 +294,7 @@ impl EventProvider for ConnectionEvents {
     }
 
     fn next_event(&mut self) -> Option<Self::Event> {
-        self.events.borrow_mut().pop_front()
+        self.next_event_timed().map(|(_, evt)| evt)
     }
 }
 
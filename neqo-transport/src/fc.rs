@@ -22,11 +22,29 @@ use crate::{
     },
     packet::PacketBuilder,
     recovery::{RecoveryToken, StreamRecoveryToken},
-    stats::FrameStats,
     stream_id::{StreamId, StreamType},
     Error, Res,
 };
 
+/// Whether sending is currently limited by the flow control limits set by the peer.
+/// See `Connection::flow_control_blocked`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowControlState {
+    /// Sending is blocked on the connection-level limit (MAX_DATA).
+    pub connection: bool,
+    /// The streams that are blocked on their own limit (MAX_STREAM_DATA),
+    /// in order of stream ID.
+    pub streams: Vec<StreamId>,
+}
+
+impl FlowControlState {
+    /// Whether anything is blocked by flow control.
+    #[must_use]
+    pub fn is_blocked(&self) -> bool {
+        self.connection || !self.streams.is_empty()
+    }
+}
+
 #[derive(Debug)]
 pub struct SenderFlowControl<T>
 where
@@ -103,6 +121,12 @@ where
         }
     }
 
+    /// Whether the sender is blocked at the current limit.
+    /// This is cleared when the limit is increased.
+    pub fn is_blocked(&self) -> bool {
+        self.limit < self.blocked_at
+    }
+
     /// Return whether a blocking frame needs to be sent.
     /// This is `Some` with the active limit if `blocked` has been called,
     /// if a blocking frame has not been sent (or it has been lost), and
@@ -131,15 +155,9 @@ where
 }
 
 impl SenderFlowControl<()> {
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         if let Some(limit) = self.blocked_needed() {
             if builder.write_varint_frame(&[FRAME_TYPE_DATA_BLOCKED, limit]) {
-                stats.data_blocked += 1;
                 tokens.push(RecoveryToken::Stream(StreamRecoveryToken::DataBlocked(
                     limit,
                 )));
@@ -150,19 +168,13 @@ impl SenderFlowControl<()> {
 }
 
 impl SenderFlowControl<StreamId> {
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         if let Some(limit) = self.blocked_needed() {
             if builder.write_varint_frame(&[
                 FRAME_TYPE_STREAM_DATA_BLOCKED,
                 self.subject.as_u64(),
                 limit,
             ]) {
-                stats.stream_data_blocked += 1;
                 tokens.push(RecoveryToken::Stream(
                     StreamRecoveryToken::StreamDataBlocked {
                         stream_id: self.subject,
@@ -176,19 +188,13 @@ impl SenderFlowControl<StreamId> {
 }
 
 impl SenderFlowControl<StreamType> {
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         if let Some(limit) = self.blocked_needed() {
             let frame = match self.subject {
                 StreamType::BiDi => FRAME_TYPE_STREAMS_BLOCKED_BIDI,
                 StreamType::UniDi => FRAME_TYPE_STREAMS_BLOCKED_UNIDI,
             };
             if builder.write_varint_frame(&[frame, limit]) {
-                stats.streams_blocked += 1;
                 tokens.push(RecoveryToken::Stream(StreamRecoveryToken::StreamsBlocked {
                     stream_type: self.subject,
                     limit,
@@ -296,18 +302,12 @@ where
 }
 
 impl ReceiverFlowControl<()> {
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         if !self.frame_needed() {
             return;
         }
         let max_allowed = self.next_limit();
         if builder.write_varint_frame(&[FRAME_TYPE_MAX_DATA, max_allowed]) {
-            stats.max_data += 1;
             tokens.push(RecoveryToken::Stream(StreamRecoveryToken::MaxData(
                 max_allowed,
             )));
@@ -345,12 +345,7 @@ impl Default for ReceiverFlowControl<()> {
 }
 
 impl ReceiverFlowControl<StreamId> {
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         if !self.frame_needed() {
             return;
         }
@@ -360,7 +355,6 @@ impl ReceiverFlowControl<StreamId> {
             self.subject.as_u64(),
             max_allowed,
         ]) {
-            stats.max_stream_data += 1;
             tokens.push(RecoveryToken::Stream(StreamRecoveryToken::MaxStreamData {
                 stream_id: self.subject,
                 max_data: max_allowed,
@@ -399,12 +393,7 @@ impl Default for ReceiverFlowControl<StreamId> {
 }
 
 impl ReceiverFlowControl<StreamType> {
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         if !self.frame_needed() {
             return;
         }
@@ -414,7 +403,6 @@ impl ReceiverFlowControl<StreamType> {
             StreamType::UniDi => FRAME_TYPE_MAX_STREAMS_UNIDI,
         };
         if builder.write_varint_frame(&[frame, max_streams]) {
-            stats.max_streams += 1;
             tokens.push(RecoveryToken::Stream(StreamRecoveryToken::MaxStreams {
                 stream_type: self.subject,
                 max_streams,
@@ -581,7 +569,6 @@ mod test {
     use super::{LocalStreamLimits, ReceiverFlowControl, RemoteStreamLimits, SenderFlowControl};
     use crate::{
         packet::PacketBuilder,
-        stats::FrameStats,
         stream_id::{StreamId, StreamType},
         Error,
     };
@@ -812,7 +799,7 @@ mod test {
         // consume the frame
         let mut builder = PacketBuilder::short(Encoder::new(), false, []);
         let mut tokens = Vec::new();
-        fc[StreamType::BiDi].write_frames(&mut builder, &mut tokens, &mut FrameStats::default());
+        fc[StreamType::BiDi].write_frames(&mut builder, &mut tokens);
         assert_eq!(tokens.len(), 1);
 
         // Now 9 can be a new StreamId.
@@ -832,7 +819,7 @@ mod test {
         fc[StreamType::UniDi].add_retired(1);
         fc[StreamType::UniDi].send_flowc_update();
         // consume the frame
-        fc[StreamType::UniDi].write_frames(&mut builder, &mut tokens, &mut FrameStats::default());
+        fc[StreamType::UniDi].write_frames(&mut builder, &mut tokens);
         assert_eq!(tokens.len(), 2);
 
         // Now 7 can be a new StreamId.
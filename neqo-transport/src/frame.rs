@@ -405,6 +405,14 @@ impl<'a> Frame<'a> {
         }
     }
 
+    /// Read the type of the frame at the start of `data`, without decoding
+    /// the remainder of the frame.  This identifies the frame that was at fault
+    /// when `decode` fails.  Returns zero if the type itself can't be read.
+    #[must_use]
+    pub fn peek_type(data: &[u8]) -> FrameType {
+        Decoder::from(data).decode_varint().unwrap_or(0)
+    }
+
     /// # Errors
     ///
     /// Returns an error if the frame cannot be decoded.
@@ -421,8 +429,13 @@ impl<'a> Frame<'a> {
         /// be the limiting factor. Though for simplicity the higher limit is chosen.
         const MAX_ACK_RANGE_COUNT: u64 = 32 * 1024;
 
+        /// The largest value that can be used for a stream count,
+        /// see Section 4.6 of RFC 9000.
+        const MAX_STREAM_COUNT: u64 = 1 << 60;
+
+        // A frame that is truncated is malformed, see Section 12.4 of RFC 9000.
         fn d<T>(v: Option<T>) -> Res<T> {
-            v.ok_or(Error::NoMoreData)
+            v.ok_or(Error::FrameEncodingError)
         }
         fn dv(dec: &mut Decoder) -> Res<u64> {
             d(dec.decode_varint())
@@ -435,7 +448,7 @@ impl<'a> Frame<'a> {
                 if nr < MAX_ACK_RANGE_COUNT {
                     Ok(nr)
                 } else {
-                    Err(Error::TooMuchData)
+                    Err(Error::FrameEncodingError)
                 }
             })?;
             let fa = dv(dec)?;
@@ -492,10 +505,7 @@ impl<'a> Frame<'a> {
             FRAME_TYPE_RESET_STREAM => Ok(Self::ResetStream {
                 stream_id: StreamId::from(dv(dec)?),
                 application_error_code: dv(dec)?,
-                final_size: match dec.decode_varint() {
-                    Some(v) => v,
-                    _ => return Err(Error::NoMoreData),
-                },
+                final_size: dv(dec)?,
             }),
             FRAME_TYPE_ACK => decode_ack(dec, false),
             FRAME_TYPE_ACK_ECN => decode_ack(dec, true),
@@ -553,8 +563,8 @@ impl<'a> Frame<'a> {
             }),
             FRAME_TYPE_MAX_STREAMS_BIDI | FRAME_TYPE_MAX_STREAMS_UNIDI => {
                 let m = dv(dec)?;
-                if m > (1 << 60) {
-                    return Err(Error::StreamLimitError);
+                if m > MAX_STREAM_COUNT {
+                    return Err(Error::FrameEncodingError);
                 }
                 Ok(Self::MaxStreams {
                     stream_type: Self::stream_type_from_bit(t),
@@ -569,17 +579,24 @@ impl<'a> Frame<'a> {
                 stream_data_limit: dv(dec)?,
             }),
             FRAME_TYPE_STREAMS_BLOCKED_BIDI | FRAME_TYPE_STREAMS_BLOCKED_UNIDI => {
+                let l = dv(dec)?;
+                if l > MAX_STREAM_COUNT {
+                    return Err(Error::FrameEncodingError);
+                }
                 Ok(Self::StreamsBlocked {
                     stream_type: Self::stream_type_from_bit(t),
-                    stream_limit: dv(dec)?,
+                    stream_limit: l,
                 })
             }
             FRAME_TYPE_NEW_CONNECTION_ID => {
                 let sequence_number = dv(dec)?;
                 let retire_prior = dv(dec)?;
+                if retire_prior > sequence_number {
+                    return Err(Error::FrameEncodingError);
+                }
                 let connection_id = d(dec.decode_vec(1))?;
-                if connection_id.len() > MAX_CONNECTION_ID_LEN {
-                    return Err(Error::DecodingFrame);
+                if connection_id.is_empty() || connection_id.len() > MAX_CONNECTION_ID_LEN {
+                    return Err(Error::FrameEncodingError);
                 }
                 let srt = d(dec.decode(16))?;
                 let stateless_reset_token = <&[_; 16]>::try_from(srt)?;
@@ -664,7 +681,16 @@ mod tests {
     use crate::{
         cid::MAX_CONNECTION_ID_LEN,
         ecn::EcnCount,
-        frame::{AckRange, Frame, FRAME_TYPE_ACK},
+        frame::{
+            AckRange, Frame, FrameType, FRAME_TYPE_ACK, FRAME_TYPE_ACK_ECN,
+            FRAME_TYPE_ACK_FREQUENCY, FRAME_TYPE_CONNECTION_CLOSE_APPLICATION,
+            FRAME_TYPE_CONNECTION_CLOSE_TRANSPORT, FRAME_TYPE_CRYPTO, FRAME_TYPE_DATAGRAM_WITH_LEN,
+            FRAME_TYPE_MAX_DATA, FRAME_TYPE_MAX_STREAMS_BIDI, FRAME_TYPE_MAX_STREAMS_UNIDI,
+            FRAME_TYPE_MAX_STREAM_DATA, FRAME_TYPE_NEW_CONNECTION_ID, FRAME_TYPE_NEW_TOKEN,
+            FRAME_TYPE_PATH_CHALLENGE, FRAME_TYPE_PATH_RESPONSE, FRAME_TYPE_RESET_STREAM,
+            FRAME_TYPE_STOP_SENDING, FRAME_TYPE_STREAMS_BLOCKED_BIDI,
+            FRAME_TYPE_STREAMS_BLOCKED_UNIDI,
+        },
         CloseError, Error, StreamId, StreamType,
     };
 
@@ -705,7 +731,10 @@ mod tests {
         // Try to parse ACK_ECN without ECN values
         let enc = Encoder::from_hex("035234523502523601020304");
         let mut dec = enc.as_decoder();
-        assert_eq!(Frame::decode(&mut dec).unwrap_err(), Error::NoMoreData);
+        assert_eq!(
+            Frame::decode(&mut dec).unwrap_err(),
+            Error::FrameEncodingError
+        );
 
         // Try to parse ACK_ECN with ECN values
         let ecn_count = Some(EcnCount::new(0, 1, 2, 3));
@@ -893,7 +922,7 @@ mod tests {
         enc.encode(&[0x11; 16][..]);
         assert_eq!(
             Frame::decode(&mut enc.as_decoder()).unwrap_err(),
-            Error::DecodingFrame
+            Error::FrameEncodingError
         );
     }
 
@@ -1034,7 +1063,10 @@ mod tests {
         e.encode_varint(0u64); // ACK delay
         e.encode_varint(u32::MAX); // ACK range count = huge, but maybe available for allocation
 
-        assert_eq!(Err(Error::TooMuchData), Frame::decode(&mut e.as_decoder()));
+        assert_eq!(
+            Err(Error::FrameEncodingError),
+            Frame::decode(&mut e.as_decoder())
+        );
     }
 
     #[test]
@@ -1047,4 +1079,167 @@ mod tests {
 
         just_dec(&f, "4030010203");
     }
+
+    /// A corpus of malformed frame encodings, with the frame type that is reported
+    /// and the error that results from decoding each.  These are also written to the
+    /// seed corpus for the `frame` fuzzing target when building that corpus.
+    const MALFORMED_FRAMES: &[(&str, FrameType, Error)] = &[
+        // ACK: truncated largest acknowledged varint.
+        ("0240", FRAME_TYPE_ACK, Error::FrameEncodingError),
+        // ACK: missing first ACK range.
+        ("02000000", FRAME_TYPE_ACK, Error::FrameEncodingError),
+        // ACK: range count that exceeds what a datagram can carry.
+        (
+            "020000c000000000008000",
+            FRAME_TYPE_ACK,
+            Error::FrameEncodingError,
+        ),
+        // ACK: fewer ranges than the range count promises.
+        ("0205000200", FRAME_TYPE_ACK, Error::FrameEncodingError),
+        // ACK_ECN: missing ECN counts.
+        ("0305000000", FRAME_TYPE_ACK_ECN, Error::FrameEncodingError),
+        // RESET_STREAM: missing final size.
+        ("040401", FRAME_TYPE_RESET_STREAM, Error::FrameEncodingError),
+        // STOP_SENDING: missing error code.
+        ("0504", FRAME_TYPE_STOP_SENDING, Error::FrameEncodingError),
+        // CRYPTO: length exceeds the remaining data.
+        ("06000501", FRAME_TYPE_CRYPTO, Error::FrameEncodingError),
+        // CRYPTO: offset and length exceed 2^62-1.
+        (
+            "06ffffffffffffffff0101",
+            FRAME_TYPE_CRYPTO,
+            Error::FrameEncodingError,
+        ),
+        // NEW_TOKEN: empty token.
+        ("0700", FRAME_TYPE_NEW_TOKEN, Error::FrameEncodingError),
+        // NEW_TOKEN: token length exceeds the remaining data.
+        ("070401", FRAME_TYPE_NEW_TOKEN, Error::FrameEncodingError),
+        // STREAM: missing stream ID.
+        ("08", 0x08, Error::FrameEncodingError),
+        // STREAM: length exceeds the remaining data.
+        ("0a0440ff01", 0x0a, Error::FrameEncodingError),
+        // STREAM: missing offset.
+        ("0c04", 0x0c, Error::FrameEncodingError),
+        // STREAM: offset and length exceed 2^62-1.
+        ("0e04ffffffffffffffff0101", 0x0e, Error::FrameEncodingError),
+        // MAX_DATA: missing maximum.
+        ("10", FRAME_TYPE_MAX_DATA, Error::FrameEncodingError),
+        // MAX_STREAM_DATA: missing maximum.
+        (
+            "1104",
+            FRAME_TYPE_MAX_STREAM_DATA,
+            Error::FrameEncodingError,
+        ),
+        // MAX_STREAMS: more than 2^60 streams.
+        (
+            "12d000000000000001",
+            FRAME_TYPE_MAX_STREAMS_BIDI,
+            Error::FrameEncodingError,
+        ),
+        (
+            "13d000000000000001",
+            FRAME_TYPE_MAX_STREAMS_UNIDI,
+            Error::FrameEncodingError,
+        ),
+        // STREAMS_BLOCKED: more than 2^60 streams.
+        (
+            "16d000000000000001",
+            FRAME_TYPE_STREAMS_BLOCKED_BIDI,
+            Error::FrameEncodingError,
+        ),
+        (
+            "17d000000000000001",
+            FRAME_TYPE_STREAMS_BLOCKED_UNIDI,
+            Error::FrameEncodingError,
+        ),
+        // NEW_CONNECTION_ID: Retire Prior To greater than the sequence number.
+        (
+            "1801020401020304000102030405060708090a0b0c0d0e0f",
+            FRAME_TYPE_NEW_CONNECTION_ID,
+            Error::FrameEncodingError,
+        ),
+        // NEW_CONNECTION_ID: zero-length connection ID.
+        (
+            "180100000102030405060708090a0b0c0d0e0f",
+            FRAME_TYPE_NEW_CONNECTION_ID,
+            Error::FrameEncodingError,
+        ),
+        // NEW_CONNECTION_ID: truncated stateless reset token.
+        (
+            "1801000401020304000102",
+            FRAME_TYPE_NEW_CONNECTION_ID,
+            Error::FrameEncodingError,
+        ),
+        // PATH_CHALLENGE and PATH_RESPONSE: truncated data.
+        (
+            "1a01020304",
+            FRAME_TYPE_PATH_CHALLENGE,
+            Error::FrameEncodingError,
+        ),
+        (
+            "1b01020304",
+            FRAME_TYPE_PATH_RESPONSE,
+            Error::FrameEncodingError,
+        ),
+        // CONNECTION_CLOSE: missing frame type.
+        (
+            "1c01",
+            FRAME_TYPE_CONNECTION_CLOSE_TRANSPORT,
+            Error::FrameEncodingError,
+        ),
+        // CONNECTION_CLOSE: reason phrase exceeds the remaining data.
+        (
+            "1d010561",
+            FRAME_TYPE_CONNECTION_CLOSE_APPLICATION,
+            Error::FrameEncodingError,
+        ),
+        // ACK_FREQUENCY: zero tolerance.
+        (
+            "40af00000101",
+            FRAME_TYPE_ACK_FREQUENCY,
+            Error::FrameEncodingError,
+        ),
+        // ACK_FREQUENCY: missing Ignore Order.
+        (
+            "40af000101",
+            FRAME_TYPE_ACK_FREQUENCY,
+            Error::FrameEncodingError,
+        ),
+        // ACK_FREQUENCY: Ignore Order that isn't 0 or 1.
+        (
+            "40af00010102",
+            FRAME_TYPE_ACK_FREQUENCY,
+            Error::FrameEncodingError,
+        ),
+        // DATAGRAM: length exceeds the remaining data.
+        (
+            "310501",
+            FRAME_TYPE_DATAGRAM_WITH_LEN,
+            Error::FrameEncodingError,
+        ),
+        // An unknown frame type.
+        ("21", 0x21, Error::UnknownFrameType),
+        // A frame type that doesn't use the shortest encoding.
+        ("4001", 0x01, Error::ProtocolViolation),
+    ];
+
+    #[test]
+    fn malformed() {
+        for (hex, frame_type, error) in MALFORMED_FRAMES {
+            let enc = Encoder::from_hex(hex);
+            #[cfg(feature = "build-fuzzing-corpus")]
+            neqo_common::write_item_to_fuzzing_corpus("frame", enc.as_ref());
+
+            assert_eq!(Frame::peek_type(enc.as_ref()), *frame_type, "{hex}");
+            let err = Frame::decode(&mut enc.as_decoder()).unwrap_err();
+            assert_eq!(err, *error, "{hex}");
+            // Everything other than the frame type encoding is a FRAME_ENCODING_ERROR.
+            let code = if *error == Error::ProtocolViolation {
+                Error::ProtocolViolation.code()
+            } else {
+                Error::FrameEncodingError.code()
+            };
+            assert_eq!(err.code(), code, "{hex}");
+        }
+    }
 }
@@ -28,6 +28,7 @@ pub mod packet;
 #[cfg(not(fuzzing))]
 mod packet;
 mod path;
+mod pmtud;
 mod qlog;
 mod quic_datagrams;
 mod recovery;
@@ -50,22 +51,28 @@ mod tracking;
 pub mod version;
 
 pub use self::{
-    cc::CongestionControlAlgorithm,
+    cc::{CcSnapshot, CongestionControlAlgorithm, CongestionPhase},
     cid::{
         ConnectionId, ConnectionIdDecoder, ConnectionIdGenerator, ConnectionIdRef,
         EmptyConnectionIdGenerator, RandomConnectionIdGenerator,
     },
     connection::{
         params::{ConnectionParameters, ACK_RATIO_SCALE},
-        Connection, Output, State, ZeroRttState,
+        Connection, EchState, HandshakePhase, Output, State, ZeroRttState,
     },
+    ecn::{EcnCount, EcnValidationOutcome},
     events::{ConnectionEvent, ConnectionEvents},
+    fc::FlowControlState,
     frame::CloseError,
     packet::MIN_INITIAL_PACKET_SIZE,
-    quic_datagrams::DatagramTracking,
+    path::{PathInfo, PathState, MAX_PATHS},
+    quic_datagrams::{DatagramOptions, DatagramTracking},
     recv_stream::{RecvStreamStats, RECV_BUFFER_SIZE},
     send_stream::{SendStreamStats, SEND_BUFFER_SIZE},
-    stats::Stats,
+    stats::{
+        DatagramStats, EcnStats, FrameStats, PacketDropReason, ProcessDiag, RecoveryStats,
+        SpaceStats, Stats, Timings,
+    },
     stream_id::{StreamId, StreamType},
     version::Version,
 };
@@ -108,12 +115,18 @@ pub enum Error {
     HandshakeFailed,
     IdleTimeout,
     IntegerOverflow,
+    /// A connection ID generator produced a connection ID of an invalid length.
+    InvalidConnectionIdLength,
     InvalidInput,
     InvalidMigration,
     InvalidPacket,
     InvalidResumptionToken,
     InvalidRetry,
     InvalidStreamId,
+    /// A short header packet could not be decrypted with the keys that its
+    /// key phase selected, while those keys were from a different generation
+    /// than the current read keys or a key update was still in progress.
+    KeyPhaseAmbiguous,
     KeysDiscarded(crypto::CryptoSpace),
     /// Packet protection keys are exhausted.
     /// Also used when too many key updates have happened.
@@ -129,6 +142,9 @@ pub enum Error {
     PacketNumberOverlap,
     PeerApplicationError(AppError),
     PeerError(TransportError),
+    /// A transport error code that the application closed the connection with;
+    /// see `Connection::close_with_transport_error`.
+    LocalError(TransportError),
     StatelessReset,
     TooMuchData,
     UnexpectedMessage,
@@ -152,7 +168,8 @@ impl Error {
             Self::StreamLimitError => 4,
             Self::StreamStateError => 5,
             Self::FinalSizeError => 6,
-            Self::FrameEncodingError => 7,
+            // RFC 9000, Section 12.4: unknown frame types are a FRAME_ENCODING_ERROR.
+            Self::FrameEncodingError | Self::UnknownFrameType => 7,
             Self::TransportParameterError => 8,
             Self::ProtocolViolation => 10,
             Self::InvalidToken => 11,
@@ -165,6 +182,7 @@ impl Error {
             // Send the server "ech_required" directly.
             Self::EchRetry(_) => 0x100 + 121,
             Self::VersionNegotiation => 0x53f8,
+            Self::LocalError(e) => *e,
             // All the rest are internal errors.
             _ => 1,
         }
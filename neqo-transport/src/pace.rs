@@ -81,6 +81,20 @@ impl Pacer {
         }
     }
 
+    /// The rate at which packets are released, in bytes per second, based on the
+    /// provided RTT and congestion window.  This is `None` if pacing is disabled
+    /// or there is no RTT estimate.
+    pub fn rate(&self, rtt: Duration, cwnd: usize) -> Option<u64> {
+        if !self.enabled {
+            return None;
+        }
+        u128::try_from(cwnd * PACER_SPEEDUP)
+            .unwrap()
+            .saturating_mul(1_000_000_000)
+            .checked_div(rtt.as_nanos())
+            .map(|r| u64::try_from(r).unwrap_or(u64::MAX))
+    }
+
     /// Spend credit.  This cannot fail; users of this API are expected to call
     /// `next()` to determine when to spend.  This takes the current time (`now`),
     /// an estimate of the round trip time (`rtt`), the estimated congestion
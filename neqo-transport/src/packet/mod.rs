@@ -12,7 +12,7 @@ use std::{
     time::Instant,
 };
 
-use neqo_common::{hex, hex_with_len, qtrace, qwarn, Decoder, Encoder};
+use neqo_common::{hex, hex_with_len, qdebug, qtrace, qwarn, Decoder, Encoder};
 use neqo_crypto::random;
 
 use crate::{
@@ -283,6 +283,18 @@ impl PacketBuilder {
         self.encoder.as_mut()[first] ^= random::<1>()[0] & mask;
     }
 
+    /// For a short header packet, set the latency spin bit.
+    /// This has to be called after `scramble`, which randomizes that bit.
+    pub fn set_spin(&mut self, spin: bool) {
+        debug_assert!(!self.is_long());
+        let first = self.header.start;
+        if spin {
+            self.encoder.as_mut()[first] |= PACKET_BIT_SPIN;
+        } else {
+            self.encoder.as_mut()[first] &= !PACKET_BIT_SPIN;
+        }
+    }
+
     /// For an Initial packet, encode the token.
     /// If you fail to do this, then you will not get a valid packet.
     pub fn initial_token(&mut self, token: &[u8]) {
@@ -335,8 +347,10 @@ impl PacketBuilder {
         self.encoder.as_mut()[self.offsets.len + 1] = (len & 0xff) as u8;
     }
 
-    fn pad_for_crypto(&mut self, crypto: &mut CryptoDxState) {
-        // Make sure that there is enough data in the packet.
+    /// Make sure that there is enough data in the packet for header protection.
+    /// This is done when the packet is built, but it can be done earlier to
+    /// see the final packet payload.
+    pub fn pad_for_crypto(&mut self, crypto: &mut CryptoDxState) {
         // The length of the packet number plus the payload length needs to
         // be at least 4 (MAX_PACKET_NUMBER_LEN) plus any amount by which
         // the header protection sample exceeds the AEAD expansion.
@@ -713,6 +727,13 @@ impl<'a> PublicPacket<'a> {
         self.packet_type
     }
 
+    /// The latency spin bit of a short header packet.  This bit is not
+    /// covered by header protection.  Always false for long header packets.
+    #[must_use]
+    pub fn spin(&self) -> bool {
+        self.packet_type == PacketType::Short && (self.data[0] & PACKET_BIT_SPIN) != 0
+    }
+
     #[must_use]
     pub fn dcid(&self) -> ConnectionIdRef<'a> {
         self.dcid
@@ -836,11 +857,20 @@ impl<'a> PublicPacket<'a> {
             // too small (which is public information).
             let (key_phase, pn, header, body) = self.decrypt_header(rx)?;
             qtrace!([rx], "decoded header: {:?}", header);
+            let ambiguous =
+                cspace == CryptoSpace::ApplicationData && crypto.key_phase_ambiguous(key_phase);
             let Some(rx) = crypto.rx(version, cspace, key_phase) else {
                 return Err(Error::DecryptError);
             };
             let version = rx.version(); // Version fixup; see above.
-            let d = rx.decrypt(pn, &header, body)?;
+            let d = rx.decrypt(pn, &header, body).map_err(|e| {
+                if ambiguous {
+                    qdebug!([rx], "decryption failed during key update: {:?}", e);
+                    Error::KeyPhaseAmbiguous
+                } else {
+                    e
+                }
+            })?;
             // If this is the first packet ever successfully decrypted
             // using `rx`, make sure to initiate a key update.
             if rx.needs_update() {
@@ -15,20 +15,25 @@ use std::{
     time::{Duration, Instant},
 };
 
-use neqo_common::{hex, qdebug, qinfo, qlog::NeqoQlog, qtrace, Datagram, Encoder, IpTos};
+use neqo_common::{
+    hex, qdebug, qinfo, qlog::NeqoQlog, qtrace, Datagram, Encoder, IpTos, IpTosDscp,
+};
 use neqo_crypto::random;
 
 use crate::{
     ackrate::{AckRate, PeerAckDelay},
     cc::CongestionControlAlgorithm,
     cid::{ConnectionId, ConnectionIdRef, ConnectionIdStore, RemoteConnectionIdEntry},
-    ecn::{EcnCount, EcnInfo},
+    connection::{params::DEFAULT_AMPLIFICATION_FACTOR, ConnectionParameters},
+    ecn::{EcnCount, EcnInfo, EcnValidationOutcome},
+    events::ConnectionEvents,
     frame::{FRAME_TYPE_PATH_CHALLENGE, FRAME_TYPE_PATH_RESPONSE, FRAME_TYPE_RETIRE_CONNECTION_ID},
     packet::PacketBuilder,
+    pmtud::Pmtud,
+    qlog::{self, PathTrigger},
     recovery::{RecoveryToken, SentPacket},
     rtt::RttEstimate,
     sender::PacketSender,
-    stats::FrameStats,
     tracking::PacketNumberSpace,
     Stats,
 };
@@ -44,10 +49,25 @@ pub const PATH_MTU_V4: usize = PATH_MTU_V6 + 20;
 /// The number of times that a path will be probed before it is considered failed.
 const MAX_PATH_PROBES: usize = 3;
 /// The maximum number of paths that `Paths` will track.
-const MAX_PATHS: usize = 15;
+/// Once this limit is reached, older paths are forgotten to make room for new ones.
+pub const MAX_PATHS: usize = 15;
 
 pub type PathRef = Rc<RefCell<Path>>;
 
+/// Convert an IPv4-mapped IPv6 address into the equivalent IPv4 address.
+/// A dual-stack socket reports IPv4 peers in the mapped form, so the same
+/// address can reach us in either form.  This is only for comparing addresses;
+/// datagrams are still sent to the address as it was given.
+pub(crate) fn canonical_address(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => v6
+            .ip()
+            .to_ipv4_mapped()
+            .map_or(addr, |v4| SocketAddr::new(IpAddr::V4(v4), v6.port())),
+        SocketAddr::V4(_) => addr,
+    }
+}
+
 /// A collection for network paths.
 /// This holds a collection of paths that have been used for sending or
 /// receiving, plus an additional "temporary" path that is held only while
@@ -67,15 +87,39 @@ pub struct Paths {
 
     /// The path that we would prefer to migrate to.
     migration_target: Option<PathRef>,
+    /// Why we are migrating to `migration_target`.
+    migration_trigger: PathTrigger,
 
     /// Connection IDs that need to be retired.
     to_retire: Vec<u64>,
 
+    /// The DSCP value for datagrams sent on any path.
+    dscp: IpTosDscp,
+
+    /// How long an unused, non-primary path is kept, if set.
+    idle_timeout: Option<Duration>,
+    /// Whether to redact addresses in qlog path events.
+    redact_qlog_addresses: bool,
+    /// The anti-amplification factor for new paths.
+    amplification_factor: u8,
+    /// For reporting paths that are abandoned.
+    events: ConnectionEvents,
+
     /// `QLog` handler.
     qlog: NeqoQlog,
 }
 
 impl Paths {
+    pub fn new(conn_params: &ConnectionParameters, events: ConnectionEvents) -> Self {
+        Self {
+            idle_timeout: conn_params.get_path_idle_timeout(),
+            redact_qlog_addresses: conn_params.qlog_addresses_redacted(),
+            amplification_factor: conn_params.get_amplification_factor(),
+            events,
+            ..Self::default()
+        }
+    }
+
     /// Find the path for the given addresses.
     /// This might be a temporary path.
     pub fn find_path(
@@ -100,52 +144,12 @@ impl Paths {
                 if let Some(primary) = self.primary.as_ref() {
                     p.prime_rtt(primary.borrow().rtt());
                 }
+                p.set_dscp(self.dscp);
+                p.amplification_factor = self.amplification_factor;
                 Rc::new(RefCell::new(p))
             })
     }
 
-    /// Find the path, but allow for rebinding.  That matches the pair of addresses
-    /// to paths that match the remote address only based on IP addres, not port.
-    /// We use this when the other side migrates to skip address validation and
-    /// creating a new path.
-    pub fn find_path_with_rebinding(
-        &self,
-        local: SocketAddr,
-        remote: SocketAddr,
-        cc: CongestionControlAlgorithm,
-        pacing: bool,
-        now: Instant,
-    ) -> PathRef {
-        self.paths
-            .iter()
-            .find_map(|p| {
-                if p.borrow().received_on(local, remote, false) {
-                    Some(Rc::clone(p))
-                } else {
-                    None
-                }
-            })
-            .or_else(|| {
-                self.paths.iter().find_map(|p| {
-                    if p.borrow().received_on(local, remote, true) {
-                        Some(Rc::clone(p))
-                    } else {
-                        None
-                    }
-                })
-            })
-            .unwrap_or_else(|| {
-                Rc::new(RefCell::new(Path::temporary(
-                    local,
-                    remote,
-                    cc,
-                    pacing,
-                    self.qlog.clone(),
-                    now,
-                )))
-            })
-    }
-
     /// Get a reference to the primary path, if one exists.
     pub fn primary(&self) -> Option<PathRef> {
         self.primary.clone()
@@ -167,13 +171,23 @@ impl Paths {
         to_retire.push(seqno);
     }
 
+    /// Retire the connection ID used by a path that is being removed
+    /// and let the application know.
+    fn abandon(to_retire: &mut Vec<u64>, events: &ConnectionEvents, abandoned: &PathRef) {
+        Self::retire(to_retire, abandoned);
+        let p = abandoned.borrow();
+        events.path_abandoned(p.local, p.remote);
+    }
+
     /// Adopt a temporary path as permanent.
     /// The first path that is made permanent is made primary.
+    /// `trigger` records why the path was created.
     pub fn make_permanent(
         &mut self,
         path: &PathRef,
         local_cid: Option<ConnectionId>,
         remote_cid: RemoteConnectionIdEntry,
+        trigger: PathTrigger,
     ) {
         debug_assert!(self.is_temporary(path));
 
@@ -181,8 +195,17 @@ impl Paths {
         // This protects index 0, which contains the primary path.
         if self.paths.len() >= MAX_PATHS {
             debug_assert_eq!(self.paths.len(), MAX_PATHS);
-            let removed = self.paths.remove(1);
-            Self::retire(&mut self.to_retire, &removed);
+            // Prefer evicting the oldest path that was never validated or has
+            // failed validation, as that is what spoofed addresses produce.
+            // Otherwise, evict the oldest path.
+            let idx = self
+                .paths
+                .iter()
+                .skip(1)
+                .position(|p| !p.borrow().is_valid())
+                .map_or(1, |i| i + 1);
+            let removed = self.paths.remove(idx);
+            Self::abandon(&mut self.to_retire, &self.events, &removed);
             if self
                 .migration_target
                 .as_ref()
@@ -199,9 +222,11 @@ impl Paths {
 
         qdebug!([path.borrow()], "Make permanent");
         path.borrow_mut().make_permanent(local_cid, remote_cid);
+        path.borrow_mut().qlog_redact = self.redact_qlog_addresses;
+        qlog::path_assigned(&mut self.qlog, &path.borrow(), trigger);
         self.paths.push(Rc::clone(path));
         if self.primary.is_none() {
-            assert!(self.select_primary(path).is_none());
+            assert!(self.select_primary(path, trigger).is_none());
         }
     }
 
@@ -209,12 +234,18 @@ impl Paths {
     /// Using the old path is only necessary if this change in path is a reaction
     /// to a migration from a peer, in which case the old path needs to be probed.
     #[must_use]
-    fn select_primary(&mut self, path: &PathRef) -> Option<PathRef> {
+    fn select_primary(&mut self, path: &PathRef, trigger: PathTrigger) -> Option<PathRef> {
         qdebug!([path.borrow()], "set as primary path");
         let old_path = self.primary.replace(Rc::clone(path)).map(|old| {
             old.borrow_mut().set_primary(false);
             old
         });
+        qlog::path_updated(
+            &mut self.qlog,
+            old_path.as_ref().map(|old| old.borrow()).as_deref(),
+            &path.borrow(),
+            trigger,
+        );
 
         // Swap the primary path into slot 0, so that it is protected from eviction.
         let idx = self
@@ -234,7 +265,13 @@ impl Paths {
     /// Otherwise, migration will occur after probing succeeds.
     /// The path is always probed and will be abandoned if probing fails.
     /// Returns `true` if the path was migrated.
-    pub fn migrate(&mut self, path: &PathRef, force: bool, now: Instant) -> bool {
+    pub fn migrate(
+        &mut self,
+        path: &PathRef,
+        force: bool,
+        trigger: PathTrigger,
+        now: Instant,
+    ) -> bool {
         debug_assert!(!self.is_temporary(path));
         let baseline = self.primary().map_or_else(
             || EcnInfo::default().baseline(),
@@ -243,10 +280,11 @@ impl Paths {
         path.borrow_mut().set_ecn_baseline(baseline);
         if force || path.borrow().is_valid() {
             path.borrow_mut().set_valid(now);
-            mem::drop(self.select_primary(path));
+            mem::drop(self.select_primary(path, trigger));
             self.migration_target = None;
         } else {
             self.migration_target = Some(Rc::clone(path));
+            self.migration_trigger = trigger;
         }
         path.borrow_mut().probe();
         self.migration_target.is_none()
@@ -259,16 +297,25 @@ impl Paths {
     /// for themselves.
     pub fn process_timeout(&mut self, now: Instant, pto: Duration) -> bool {
         let to_retire = &mut self.to_retire;
+        let events = &self.events;
+        let migration_target = &mut self.migration_target;
+        let idle_timeout = self.idle_timeout;
         let mut primary_failed = false;
         self.paths.retain(|p| {
-            if p.borrow_mut().process_timeout(now, pto) {
+            if p.borrow_mut().process_timeout(now, pto, idle_timeout) {
                 true
             } else {
                 qdebug!([p.borrow()], "Retiring path");
                 if p.borrow().is_primary() {
                     primary_failed = true;
                 }
-                Self::retire(to_retire, p);
+                if migration_target
+                    .as_ref()
+                    .map_or(false, |target| Rc::ptr_eq(target, p))
+                {
+                    *migration_target = None;
+                }
+                Self::abandon(to_retire, events, p);
                 false
             }
         });
@@ -285,7 +332,7 @@ impl Paths {
                 // Need a clone as `fallback` is borrowed from `self`.
                 let path = Rc::clone(fallback);
                 qinfo!([path.borrow()], "Failing over after primary path failed");
-                mem::drop(self.select_primary(&path));
+                mem::drop(self.select_primary(&path, PathTrigger::Failover));
                 true
             } else {
                 false
@@ -299,13 +346,21 @@ impl Paths {
     pub fn next_timeout(&self, pto: Duration) -> Option<Instant> {
         self.paths
             .iter()
-            .filter_map(|p| p.borrow().next_timeout(pto))
+            .filter_map(|p| p.borrow().next_timeout(pto, self.idle_timeout))
             .min()
     }
 
     /// Set the identified path to be primary.
     /// This panics if `make_permanent` hasn't been called.
-    pub fn handle_migration(&mut self, path: &PathRef, remote: SocketAddr, now: Instant) {
+    /// If `fast_rebinding` is set and the peer only changed its port, the new path
+    /// takes over the congestion state of the old path, though it is still validated.
+    pub fn handle_migration(
+        &mut self,
+        path: &PathRef,
+        remote: SocketAddr,
+        fast_rebinding: bool,
+        now: Instant,
+    ) {
         // The update here needs to match the checks in `Path::received_on`.
         // Here, we update the remote port number to match the source port on the
         // datagram that was received.  This ensures that we send subsequent
@@ -318,13 +373,79 @@ impl Paths {
             return;
         }
 
-        if let Some(old_path) = self.select_primary(path) {
+        if fast_rebinding {
+            if let Some(primary) = self.primary.as_ref() {
+                if primary.borrow().is_rebinding_of(&path.borrow()) {
+                    path.borrow_mut()
+                        .take_congestion_state(&mut primary.borrow_mut());
+                }
+            }
+        }
+
+        if let Some(old_path) = self.select_primary(path, PathTrigger::PeerAddressChange) {
             // Need to probe the old path if the peer migrates.
             old_path.borrow_mut().probe();
             // TODO(mt) - suppress probing if the path was valid within 3PTO.
         }
     }
 
+    /// With multipath, use a path that the peer sent on alongside the primary path,
+    /// rather than migrating to it.  A new path is probed before it is used for
+    /// anything other than responding to the peer.  Returns `false` if the path
+    /// would exceed `max_paths` active paths, or if it is the primary path.
+    pub fn add_active(
+        &mut self,
+        path: &PathRef,
+        remote: SocketAddr,
+        max_paths: usize,
+        now: Instant,
+    ) -> bool {
+        if path.borrow().is_primary() {
+            return false;
+        }
+        if !path.borrow().active {
+            let active = self.paths.iter().filter(|p| p.borrow().is_active()).count();
+            if active >= max_paths {
+                qinfo!([path.borrow()], "Too many active paths, not adding path");
+                return false;
+            }
+            qinfo!([path.borrow()], "Adding active path");
+            path.borrow_mut().active = true;
+            path.borrow_mut().probe();
+        }
+        path.borrow_mut().update_port(remote.port());
+        path.borrow_mut().update(now);
+        true
+    }
+
+    /// Handle a peer moving to a new path after we asked it not to migrate.
+    /// The peer might not have had a choice, as with NAT rebinding, so the path
+    /// is probed rather than ignored, but the connection only moves there once
+    /// the path is validated.  Returns `true` if the path was migrated.
+    pub fn handle_unpermitted_migration(
+        &mut self,
+        path: &PathRef,
+        remote: SocketAddr,
+        now: Instant,
+    ) -> bool {
+        path.borrow_mut().update_port(remote.port());
+
+        if path.borrow().is_primary() {
+            path.borrow_mut().update(now);
+            return false;
+        }
+        if self
+            .migration_target
+            .as_ref()
+            .map_or(false, |target| Rc::ptr_eq(target, path))
+        {
+            // Already probing; this avoids using up probes with each packet.
+            return false;
+        }
+        qinfo!([path.borrow()], "Peer migrated when it was asked not to");
+        self.migrate(path, false, PathTrigger::PeerAddressChange, now)
+    }
+
     /// Select a path to send on.  This will select the first path that has
     /// probes to send, then fall back to the primary path.
     pub fn select_path(&self) -> Option<PathRef> {
@@ -356,7 +477,8 @@ impl Paths {
                     .map_or(false, |target| Rc::ptr_eq(target, p))
                 {
                     let primary = self.migration_target.take();
-                    mem::drop(self.select_primary(&primary.unwrap()));
+                    let trigger = self.migration_trigger;
+                    mem::drop(self.select_primary(&primary.unwrap(), trigger));
                     return true;
                 }
                 break;
@@ -372,6 +494,7 @@ impl Paths {
     pub fn retire_cids(&mut self, retire_prior: u64, store: &mut ConnectionIdStore<[u8; 16]>) {
         let to_retire = &mut self.to_retire;
         let migration_target = &mut self.migration_target;
+        let events = &self.events;
 
         // First, tell the store to release any connection IDs that are too old.
         let mut retired = store.retire_prior_to(retire_prior);
@@ -398,6 +521,10 @@ impl Paths {
                     );
                     *migration_target = None;
                 }
+                if !has_replacement {
+                    let p = p.borrow();
+                    events.path_abandoned(p.local, p.remote);
+                }
                 has_replacement
             } else {
                 true
@@ -406,12 +533,7 @@ impl Paths {
     }
 
     /// Write out any `RETIRE_CONNECTION_ID` frames that are outstanding.
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         while let Some(seqno) = self.to_retire.pop() {
             if builder.remaining() < 1 + Encoder::varint_len(seqno) {
                 self.to_retire.push(seqno);
@@ -420,12 +542,11 @@ impl Paths {
             builder.encode_varint(FRAME_TYPE_RETIRE_CONNECTION_ID);
             builder.encode_varint(seqno);
             tokens.push(RecoveryToken::RetireConnectionId(seqno));
-            stats.retire_connection_id += 1;
         }
 
         if let Some(path) = self.primary() {
             // Write out any ACK_FREQUENCY frames.
-            path.borrow_mut().write_cc_frames(builder, tokens, stats);
+            path.borrow_mut().write_cc_frames(builder, tokens);
         }
     }
 
@@ -462,6 +583,19 @@ impl Paths {
             })
     }
 
+    /// Set the DSCP value for datagrams sent on all paths, including new ones.
+    pub fn set_dscp(&mut self, dscp: IpTosDscp) {
+        for p in &self.paths {
+            p.borrow_mut().set_dscp(dscp);
+        }
+        self.dscp = dscp;
+    }
+
+    /// Get a snapshot of all the paths that are being tracked.
+    pub fn info(&self) -> Vec<PathInfo> {
+        self.paths.iter().map(|p| p.borrow().info()).collect()
+    }
+
     pub fn set_qlog(&mut self, qlog: NeqoQlog) {
         for p in &mut self.paths {
             p.borrow_mut().set_qlog(qlog.clone());
@@ -499,6 +633,46 @@ impl ProbeState {
     }
 }
 
+/// The validation state of a path, as reported in `PathInfo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathState {
+    /// The path has been validated.
+    Valid,
+    /// The path is being probed, either because it is new or
+    /// because it needs to be revalidated.
+    Probing,
+    /// Validation of the path failed.
+    Failed,
+}
+
+impl From<&ProbeState> for PathState {
+    fn from(state: &ProbeState) -> Self {
+        match state {
+            ProbeState::Valid => Self::Valid,
+            ProbeState::ProbeNeeded { .. } | ProbeState::Probing { .. } => Self::Probing,
+            ProbeState::Failed => Self::Failed,
+        }
+    }
+}
+
+/// A snapshot of the state of a network path, see `Connection::paths`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathInfo {
+    /// The local socket address.
+    pub local: SocketAddr,
+    /// The remote socket address.
+    pub remote: SocketAddr,
+    /// The validation state of the path.
+    pub state: PathState,
+    /// The smoothed RTT for the path, if an RTT sample has been taken on it.
+    pub rtt: Option<Duration>,
+    /// The time that the path was last validated.
+    pub validated_at: Option<Instant>,
+    /// Whether the connection is using this path.  With multipath, more than
+    /// one path can be active.
+    pub active: bool,
+}
+
 /// A network path.
 ///
 /// Paths are used a little bit strangely by connections:
@@ -522,6 +696,8 @@ pub struct Path {
 
     /// Whether this is the primary path.
     primary: bool,
+    /// Whether this path is in use alongside the primary path, with multipath.
+    active: bool,
     /// Whether the current path is considered valid.
     state: ProbeState,
     /// For a path that is not validated, this is `None`.  For a validated
@@ -536,6 +712,9 @@ pub struct Path {
     sender: PacketSender,
     /// The IP TTL to use for outgoing packets on this path.
     ttl: u8,
+    /// The DSCP value for outgoing packets on this path.
+    /// The ECN codepoint is chosen separately.
+    dscp: IpTosDscp,
 
     /// The number of bytes received on this path.
     /// Note that this value might saturate on a long-lived connection,
@@ -543,10 +722,18 @@ pub struct Path {
     received_bytes: usize,
     /// The number of bytes sent on this path.
     sent_bytes: usize,
+    /// When a packet was last sent or received on this path.
+    last_used: Instant,
     /// The ECN-related state for this path (see RFC9000, Section 13.4 and Appendix A.4)
     ecn_info: EcnInfo,
+    /// Path MTU discovery for this path, which determines the path MTU.
+    pmtud: Pmtud,
     /// For logging of events.
     qlog: NeqoQlog,
+    /// Whether addresses in qlog path events are redacted.
+    qlog_redact: bool,
+    /// Before validation, this many times `received_bytes` can be sent.
+    amplification_factor: u8,
 }
 
 impl Path {
@@ -568,16 +755,22 @@ impl Path {
             local_cid: None,
             remote_cid: None,
             primary: false,
+            active: false,
             state: ProbeState::ProbeNeeded { probe_count: 0 },
             validated: None,
             challenge: None,
             rtt: RttEstimate::default(),
             sender,
             ttl: 64, // This is the default TTL on many OSes.
+            dscp: IpTosDscp::default(),
             received_bytes: 0,
             sent_bytes: 0,
+            last_used: now,
             ecn_info: EcnInfo::default(),
+            pmtud: Pmtud::new(remote.ip(), Self::mtu_by_addr(remote.ip())),
             qlog,
+            qlog_redact: false,
+            amplification_factor: DEFAULT_AMPLIFICATION_FACTOR,
         }
     }
 
@@ -587,7 +780,16 @@ impl Path {
 
     /// Return the DSCP/ECN marking to use for outgoing packets on this path.
     pub fn tos(&self) -> IpTos {
-        self.ecn_info.ecn_mark().into()
+        (self.dscp, self.ecn_info.ecn_mark()).into()
+    }
+
+    pub fn set_dscp(&mut self, dscp: IpTosDscp) {
+        self.dscp = dscp;
+    }
+
+    /// The outcome of ECN validation on this path.
+    pub fn ecn_outcome(&self) -> EcnValidationOutcome {
+        self.ecn_info.outcome()
     }
 
     /// Whether this path is the primary or current path for the connection.
@@ -595,6 +797,12 @@ impl Path {
         self.primary
     }
 
+    /// Whether this path is in use, either as the primary path or as an
+    /// additional path with multipath.
+    pub fn is_active(&self) -> bool {
+        self.primary || self.active
+    }
+
     /// Whether this path is a temporary one.
     pub fn is_temporary(&self) -> bool {
         self.remote_cid.is_none()
@@ -617,9 +825,10 @@ impl Path {
     /// Determine if this path was the one that the provided datagram was received on.
     /// This uses the full local socket address, but ignores the port number on the peer
     /// if `flexible` is true, allowing for NAT rebinding that retains the same IP.
+    /// Addresses are compared in their canonical form.
     fn received_on(&self, local: SocketAddr, remote: SocketAddr, flexible: bool) -> bool {
-        self.local == local
-            && self.remote.ip() == remote.ip()
+        canonical_address(self.local) == canonical_address(local)
+            && canonical_address(self.remote).ip() == canonical_address(remote).ip()
             && (flexible || self.remote.port() == remote.port())
     }
 
@@ -629,6 +838,26 @@ impl Path {
         self.remote.set_port(port);
     }
 
+    /// Whether `other` differs from this path only in the remote port.
+    fn is_rebinding_of(&self, other: &Self) -> bool {
+        self.received_on(other.local, other.remote, true)
+            && self.remote.port() != other.remote.port()
+    }
+
+    /// Take the congestion controller, RTT estimate, and path MTU from `other`.
+    /// This is used when the peer rebinds to a new port, where the network path
+    /// is probably unchanged.  `other` is left with the fresh state of this path.
+    fn take_congestion_state(&mut self, other: &mut Self) {
+        qinfo!(
+            [self],
+            "Peer rebinding, keeping congestion state of {}",
+            other
+        );
+        mem::swap(&mut self.sender, &mut other.sender);
+        mem::swap(&mut self.rtt, &mut other.rtt);
+        mem::swap(&mut self.pmtud, &mut other.pmtud);
+    }
+
     /// Set whether this path is primary.
     pub(crate) fn set_primary(&mut self, primary: bool) {
         qtrace!([self], "Make primary {}", primary);
@@ -662,9 +891,18 @@ impl Path {
         }
     }
 
-    /// Get the path MTU.  This is currently fixed based on IP version.
+    /// Get the path MTU.  This starts at a fixed value based on IP version,
+    /// which path MTU discovery can then change.
     pub fn mtu(&self) -> usize {
-        Self::mtu_by_addr(self.remote.ip())
+        self.pmtud.plpmtu()
+    }
+
+    pub fn pmtud(&self) -> &Pmtud {
+        &self.pmtud
+    }
+
+    pub fn pmtud_mut(&mut self) -> &mut Pmtud {
+        &mut self.pmtud
     }
 
     /// Get the first local connection ID.
@@ -705,8 +943,8 @@ impl Path {
     }
 
     /// Make a datagram.
-    pub fn datagram<V: Into<Vec<u8>>>(&mut self, payload: V) -> Datagram {
-        self.ecn_info.on_packet_sent();
+    pub fn datagram<V: Into<Vec<u8>>>(&mut self, payload: V, now: Instant) -> Datagram {
+        self.ecn_info.on_packet_sent(now);
         Datagram::new(self.local, self.remote, self.tos(), Some(self.ttl), payload)
     }
 
@@ -725,6 +963,11 @@ impl Path {
         self.validated.is_some()
     }
 
+    /// Whether addresses for this path are redacted in qlog.
+    pub fn qlog_redacted(&self) -> bool {
+        self.qlog_redact
+    }
+
     /// Handle a `PATH_RESPONSE` frame. Returns true if the response was accepted.
     pub fn path_response(&mut self, response: [u8; 8], now: Instant) -> bool {
         if let ProbeState::Probing { data, mtu, .. } = &mut self.state {
@@ -734,6 +977,8 @@ impl Path {
                 if need_full_probe {
                     qdebug!([self], "Sub-MTU probe successful, reset probe count");
                     self.probe();
+                } else {
+                    qlog::path_validation_succeeded(&mut self.qlog.clone(), self);
                 }
                 true
             } else {
@@ -760,6 +1005,7 @@ impl Path {
         };
         self.state = if probe_count >= MAX_PATH_PROBES {
             qinfo!([self], "Probing failed");
+            qlog::path_validation_failed(&mut self.qlog.clone(), self);
             ProbeState::Failed
         } else {
             qdebug!([self], "Initiating probe");
@@ -775,7 +1021,6 @@ impl Path {
     pub fn write_frames(
         &mut self,
         builder: &mut PacketBuilder,
-        stats: &mut FrameStats,
         mtu: bool, // Whether the packet we're writing into will be a full MTU.
         now: Instant,
     ) -> bool {
@@ -790,10 +1035,6 @@ impl Path {
             builder.encode(&challenge[..]);
 
             // These frames are not retransmitted in the usual fashion.
-            // There is no token, therefore we need to count `all` specially.
-            stats.path_response += 1;
-            stats.all += 1;
-
             if builder.remaining() < 9 {
                 return true;
             }
@@ -805,14 +1046,14 @@ impl Path {
         // Send PATH_CHALLENGE.
         if let ProbeState::ProbeNeeded { probe_count } = self.state {
             qtrace!([self], "Initiating path challenge {}", probe_count);
+            if probe_count == 0 {
+                qlog::path_validation_started(&mut self.qlog.clone(), self);
+            }
             let data = random::<8>();
             builder.encode_varint(FRAME_TYPE_PATH_CHALLENGE);
             builder.encode(&data);
 
             // As above, no recovery token.
-            stats.path_challenge += 1;
-            stats.all += 1;
-
             self.state = ProbeState::Probing {
                 probe_count,
                 data,
@@ -830,9 +1071,8 @@ impl Path {
         &mut self,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
-        self.rtt.write_frames(builder, tokens, stats);
+        self.rtt.write_frames(builder, tokens);
     }
 
     pub fn lost_ack_frequency(&mut self, lost: &AckRate) {
@@ -845,7 +1085,14 @@ impl Path {
 
     /// Process a timer for this path.
     /// This returns true if the path is viable and can be kept alive.
-    pub fn process_timeout(&mut self, now: Instant, pto: Duration) -> bool {
+    /// If `idle_timeout` is set, a validated path that is not primary is only kept
+    /// if it was used more recently than that.
+    pub fn process_timeout(
+        &mut self,
+        now: Instant,
+        pto: Duration,
+        idle_timeout: Option<Duration>,
+    ) -> bool {
         if let ProbeState::Probing { sent, .. } = &self.state {
             if now >= *sent + pto {
                 self.probe();
@@ -858,6 +1105,9 @@ impl Path {
             // Keep valid primary paths otherwise.
             true
         } else if let ProbeState::Valid = self.state {
+            if let Some(idle_timeout) = idle_timeout {
+                return self.last_used + idle_timeout > now;
+            }
             // Retire validated, non-primary paths.
             // Allow more than `MAX_PATH_PROBES` times the PTO so that an old
             // path remains around until after a previous path fails.
@@ -870,14 +1120,28 @@ impl Path {
     }
 
     /// Return the next time that this path needs servicing.
-    /// This only considers retransmissions of probes, not cleanup of the path.
-    /// If there is no other activity, then there is no real need to schedule a
-    /// timer to cleanup old paths.
-    pub fn next_timeout(&self, pto: Duration) -> Option<Instant> {
-        if let ProbeState::Probing { sent, .. } = &self.state {
-            Some(*sent + pto)
-        } else {
-            None
+    /// This considers retransmissions of probes and, if `idle_timeout` is set,
+    /// when an unused path is abandoned.  Without `idle_timeout`, old paths are
+    /// only cleaned up when some other timer fires.
+    pub fn next_timeout(&self, pto: Duration, idle_timeout: Option<Duration>) -> Option<Instant> {
+        match (&self.state, idle_timeout) {
+            (ProbeState::Probing { sent, .. }, _) => Some(*sent + pto),
+            (ProbeState::Valid, Some(idle_timeout)) if !self.primary => {
+                Some(self.last_used + idle_timeout)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get a snapshot of the state of this path.
+    pub fn info(&self) -> PathInfo {
+        PathInfo {
+            local: self.local,
+            remote: self.remote,
+            state: PathState::from(&self.state),
+            rtt: self.rtt.first_sample_time().map(|_| self.rtt.estimate()),
+            validated_at: self.validated,
+            active: self.is_active(),
         }
     }
 
@@ -935,6 +1199,11 @@ impl Path {
         self.sent_bytes = self.sent_bytes.saturating_add(count);
     }
 
+    /// Note that a packet was sent or received on this path.
+    pub fn used(&mut self, now: Instant) {
+        self.last_used = now;
+    }
+
     /// Record a packet as having been sent on this path.
     pub fn packet_sent(&mut self, sent: &mut SentPacket) {
         if !self.is_primary() {
@@ -989,6 +1258,7 @@ impl Path {
         }
 
         self.sender.on_packets_acked(acked_pkts, &self.rtt, now);
+        self.pmtud.on_packets_acked(acked_pkts);
     }
 
     /// Record packets as lost with the sender.
@@ -997,17 +1267,37 @@ impl Path {
         prev_largest_acked_sent: Option<Instant>,
         space: PacketNumberSpace,
         lost_packets: &[SentPacket],
+        now: Instant,
     ) {
         debug_assert!(self.is_primary());
-        let cwnd_reduced = self.sender.on_packets_lost(
-            self.rtt.first_sample_time(),
-            prev_largest_acked_sent,
-            self.rtt.pto(space), // Important: the base PTO, not adjusted.
-            lost_packets,
-        );
+        let first_rtt_sample_time = self.rtt.first_sample_time();
+        let pto = self.rtt.pto(space); // Important: the base PTO, not adjusted.
+        let cwnd_reduced = if lost_packets.iter().any(|p| self.pmtud.is_probe(p)) {
+            // Losing a packet that is larger than the path MTU is not a sign of congestion.
+            let (probes, lost): (Vec<_>, Vec<_>) = lost_packets
+                .iter()
+                .cloned()
+                .partition(|p| self.pmtud.is_probe(p));
+            self.sender.discard_lost(&probes);
+            self.sender
+                .on_packets_lost(first_rtt_sample_time, prev_largest_acked_sent, pto, &lost)
+        } else {
+            self.sender.on_packets_lost(
+                first_rtt_sample_time,
+                prev_largest_acked_sent,
+                pto,
+                lost_packets,
+            )
+        };
         if cwnd_reduced {
             self.rtt.update_ack_delay(self.sender.cwnd(), self.mtu());
         }
+        self.pmtud.on_packets_lost(lost_packets, now);
+    }
+
+    /// Record a PTO.  `pto_packets` are the packets that will be retransmitted.
+    pub fn on_pto(&mut self, pto_packets: &[SentPacket]) {
+        self.pmtud.on_pto(pto_packets);
     }
 
     /// Get the number of bytes that can be written to this path.
@@ -1018,7 +1308,7 @@ impl Path {
             usize::MAX
         } else {
             self.received_bytes
-                .checked_mul(3)
+                .checked_mul(usize::from(self.amplification_factor))
                 .map_or(usize::MAX, |limit| {
                     let budget = if limit == 0 {
                         // If we have received absolutely nothing thus far, then this endpoint
@@ -0,0 +1,369 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Datagram Packetization Layer Path MTU Discovery (DPLPMTUD), RFC 8899.
+
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use neqo_common::{qdebug, qinfo};
+
+use crate::recovery::SentPacket;
+
+/// The IP MTUs that are probed, in increasing order.  The first entry is the
+/// minimum IPv6 MTU, which is the base PLPMTU that we fall back to when a black
+/// hole is detected.  We don't query the local interface, so the last entry,
+/// the Ethernet MTU, stands in for the interface MTU.
+const SEARCH_TABLE: [usize; 5] = [1280, 1380, 1420, 1472, 1500];
+/// The number of times a probe of a given size is sent before giving up on
+/// that size (`MAX_PROBES` in RFC 8899).
+const MAX_PROBES: usize = 3;
+/// The number of packets larger than the base PLPMTU that need to be lost,
+/// without any being acknowledged, before a black hole is declared.
+const BLACK_HOLE_THRESHOLD: usize = MAX_PROBES;
+/// How long to wait before searching again after a probe failed
+/// (`PMTU_RAISE_TIMER` in RFC 8899).
+const PMTU_RAISE_TIMER: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Probe {
+    /// The search is complete or hasn't started.
+    NotNeeded,
+    /// A probe needs to be sent.
+    Needed,
+    /// A probe was sent and hasn't been acknowledged or lost.
+    Sent,
+}
+
+/// The PMTUD state for a path.
+#[derive(Debug)]
+pub struct Pmtud {
+    /// Whether PMTUD has been started for the path.
+    active: bool,
+    /// The size of the IP and UDP headers for the path.
+    header_size: usize,
+    /// The current PLPMTU, as a UDP payload size.
+    plpmtu: usize,
+    /// The largest UDP payload that the peer is willing to receive.
+    max_payload: usize,
+    /// The index into `SEARCH_TABLE` of the next size to probe.
+    probe_index: usize,
+    /// The number of probes sent at the current probe size.
+    probe_count: usize,
+    probe_state: Probe,
+    /// The number of packets larger than the base PLPMTU that were lost since
+    /// the last time that one of them was acknowledged.
+    loss_count: usize,
+    /// When to search again after a probe size was found to be unusable.
+    raise_at: Option<Instant>,
+}
+
+impl Pmtud {
+    /// Create PMTUD state for a path to `remote`, starting from a PLPMTU of `plpmtu`.
+    /// Probing does not start until `start` is called.
+    pub fn new(remote: IpAddr, plpmtu: usize) -> Self {
+        let header_size = match remote {
+            IpAddr::V4(_) => 20 + 8,
+            IpAddr::V6(_) => 40 + 8,
+        };
+        Self {
+            active: false,
+            header_size,
+            plpmtu,
+            max_payload: plpmtu,
+            probe_index: SEARCH_TABLE.len(),
+            probe_count: 0,
+            probe_state: Probe::NotNeeded,
+            loss_count: 0,
+            raise_at: None,
+        }
+    }
+
+    /// The current PLPMTU, as a UDP payload size.
+    pub fn plpmtu(&self) -> usize {
+        self.plpmtu
+    }
+
+    /// The base PLPMTU, which every path is expected to support.
+    fn base(&self) -> usize {
+        SEARCH_TABLE[0] - self.header_size
+    }
+
+    /// The size of the next probe, as a UDP payload size, if there is one.
+    fn next_size(&self) -> Option<usize> {
+        SEARCH_TABLE
+            .get(self.probe_index)
+            .map(|mtu| mtu - self.header_size)
+            .filter(|&size| size <= self.max_payload)
+    }
+
+    /// The size of the probe to send, as a UDP payload size.
+    ///
+    /// # Panics
+    ///
+    /// When there is no probe to send.
+    pub fn probe_size(&self) -> usize {
+        self.next_size().expect("a probe size")
+    }
+
+    /// Start searching for a larger PLPMTU, up to `max_payload` bytes of UDP payload.
+    pub fn start(&mut self, max_payload: usize) {
+        self.active = true;
+        self.max_payload = max_payload;
+        self.loss_count = 0;
+        self.search_from(self.plpmtu);
+    }
+
+    /// Search for sizes larger than `plpmtu`.
+    fn search_from(&mut self, plpmtu: usize) {
+        self.plpmtu = plpmtu;
+        self.probe_index = SEARCH_TABLE
+            .iter()
+            .position(|mtu| mtu - self.header_size > plpmtu)
+            .unwrap_or(SEARCH_TABLE.len());
+        self.probe_count = 0;
+        self.raise_at = None;
+        self.next_probe();
+    }
+
+    fn next_probe(&mut self) {
+        if let Some(size) = self.next_size() {
+            qdebug!("PMTUD: probing for {} after {}", size, self.plpmtu);
+            self.probe_state = Probe::Needed;
+        } else {
+            qinfo!("PMTUD: search complete at {}", self.plpmtu);
+            self.probe_state = Probe::NotNeeded;
+        }
+    }
+
+    /// Whether a probe needs to be sent.  This also restarts the search
+    /// if enough time has passed since the last probe failed.
+    pub fn needs_probe(&mut self, now: Instant) -> bool {
+        if self.raise_at.map_or(false, |t| t <= now) {
+            self.search_from(self.plpmtu);
+        }
+        self.probe_state == Probe::Needed
+    }
+
+    /// Record that a probe was sent.
+    pub fn probe_sent(&mut self) {
+        qdebug!("PMTUD: sent probe of {}", self.probe_size());
+        self.probe_state = Probe::Sent;
+        self.probe_count += 1;
+    }
+
+    /// Whether a packet is a probe, or at least larger than the current PLPMTU.
+    /// The loss of these packets is not a congestion signal.
+    pub fn is_probe(&self, p: &SentPacket) -> bool {
+        p.len() > self.plpmtu
+    }
+
+    fn is_current_probe(&self, p: &SentPacket) -> bool {
+        self.probe_state == Probe::Sent && Some(p.len()) == self.next_size()
+    }
+
+    /// Whether a packet counts toward black hole detection.
+    fn is_large(&self, p: &SentPacket) -> bool {
+        p.len() > self.base() && !self.is_probe(p)
+    }
+
+    pub fn on_packets_acked(&mut self, acked: &[SentPacket]) {
+        if acked.iter().any(|p| self.is_current_probe(p)) {
+            self.plpmtu = self.probe_size();
+            qinfo!("PMTUD: PLPMTU raised to {}", self.plpmtu);
+            self.probe_index += 1;
+            self.probe_count = 0;
+            self.next_probe();
+        }
+        if acked.iter().any(|p| p.len() > self.base()) {
+            self.loss_count = 0;
+        } else if self.loss_count >= BLACK_HOLE_THRESHOLD {
+            // Smaller packets are getting through, but larger ones are not.
+            self.black_hole();
+        }
+    }
+
+    pub fn on_packets_lost(&mut self, lost: &[SentPacket], now: Instant) {
+        if lost.iter().any(|p| self.is_current_probe(p)) {
+            if self.probe_count >= MAX_PROBES {
+                qinfo!(
+                    "PMTUD: probe of {} failed, staying at {}",
+                    self.probe_size(),
+                    self.plpmtu
+                );
+                self.probe_state = Probe::NotNeeded;
+                self.raise_at = Some(now + PMTU_RAISE_TIMER);
+            } else {
+                self.probe_state = Probe::Needed;
+            }
+        }
+        self.loss_count += lost.iter().filter(|p| self.is_large(p)).count();
+    }
+
+    /// Handle a PTO.  `pto_packets` are the packets that might have been lost.
+    /// If nothing has been acknowledged for long enough to reach the black
+    /// hole threshold, the path might not support the current PLPMTU.
+    pub fn on_pto(&mut self, pto_packets: &[SentPacket]) {
+        self.loss_count += pto_packets.iter().filter(|p| self.is_large(p)).count();
+        if self.loss_count >= BLACK_HOLE_THRESHOLD {
+            self.black_hole();
+        }
+    }
+
+    fn black_hole(&mut self) {
+        if !self.active || self.plpmtu <= self.base() {
+            return;
+        }
+        qinfo!("PMTUD: black hole detected at {}", self.plpmtu);
+        self.loss_count = 0;
+        self.search_from(self.base());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        time::Duration,
+    };
+
+    use neqo_common::IpTosEcn;
+    use test_fixture::now;
+
+    use super::{Pmtud, MAX_PROBES, PMTU_RAISE_TIMER};
+    use crate::{
+        packet::{PacketNumber, PacketType},
+        path::{PATH_MTU_V4, PATH_MTU_V6},
+        recovery::SentPacket,
+    };
+
+    const V4: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const V6: IpAddr = IpAddr::V6(Ipv6Addr::LOCALHOST);
+
+    fn packet(pn: PacketNumber, len: usize) -> SentPacket {
+        SentPacket::new(
+            PacketType::Short,
+            pn,
+            IpTosEcn::default(),
+            now(),
+            true,
+            Vec::new(),
+            len,
+        )
+    }
+
+    /// Probe until the search completes, with all probes up to `mtu` succeeding.
+    fn search(pmtud: &mut Pmtud, mtu: usize, header: usize) {
+        let mut pn = 0;
+        while pmtud.needs_probe(now()) {
+            let size = pmtud.probe_size();
+            pmtud.probe_sent();
+            let p = packet(pn, size);
+            pn += 1;
+            if size + header <= mtu {
+                pmtud.on_packets_acked(&[p]);
+            } else {
+                pmtud.on_packets_lost(&[p], now());
+            }
+        }
+    }
+
+    #[test]
+    fn not_started() {
+        let mut pmtud = Pmtud::new(V6, PATH_MTU_V6);
+        assert!(!pmtud.needs_probe(now()));
+        // Without PMTUD, there is no black hole detection either.
+        let lost = (0..5).map(|pn| packet(pn, PATH_MTU_V6)).collect::<Vec<_>>();
+        pmtud.on_pto(&lost);
+        assert_eq!(pmtud.plpmtu(), PATH_MTU_V6);
+    }
+
+    #[test]
+    fn search_ethernet() {
+        let mut pmtud = Pmtud::new(V4, PATH_MTU_V4);
+        pmtud.start(65527);
+        search(&mut pmtud, 1500, 28);
+        assert_eq!(pmtud.plpmtu(), 1472);
+
+        let mut pmtud = Pmtud::new(V6, PATH_MTU_V6);
+        pmtud.start(65527);
+        search(&mut pmtud, 1500, 48);
+        assert_eq!(pmtud.plpmtu(), 1452);
+    }
+
+    #[test]
+    fn search_limited_by_peer() {
+        let mut pmtud = Pmtud::new(V4, PATH_MTU_V4);
+        pmtud.start(1400);
+        search(&mut pmtud, 1500, 28);
+        assert_eq!(pmtud.plpmtu(), 1392);
+    }
+
+    #[test]
+    fn probe_loss_caps_size() {
+        let mut pmtud = Pmtud::new(V4, PATH_MTU_V4);
+        pmtud.start(65527);
+        let mut probes = 0;
+        while pmtud.needs_probe(now()) {
+            let size = pmtud.probe_size();
+            pmtud.probe_sent();
+            if size + 28 <= 1450 {
+                pmtud.on_packets_acked(&[packet(probes, size)]);
+            } else {
+                probes += 1;
+                pmtud.on_packets_lost(&[packet(probes, size)], now());
+            }
+        }
+        assert_eq!(usize::try_from(probes).unwrap(), MAX_PROBES);
+        assert_eq!(pmtud.plpmtu(), 1420 - 28);
+
+        // After a while, the search resumes.
+        assert!(!pmtud.needs_probe(now() + PMTU_RAISE_TIMER - Duration::from_secs(1)));
+        assert!(pmtud.needs_probe(now() + PMTU_RAISE_TIMER));
+        assert_eq!(pmtud.probe_size(), 1472 - 28);
+    }
+
+    #[test]
+    fn black_hole() {
+        let mut pmtud = Pmtud::new(V6, PATH_MTU_V6);
+        pmtud.start(65527);
+        search(&mut pmtud, 1500, 48);
+        assert_eq!(pmtud.plpmtu(), 1452);
+
+        // Full-size packets are lost, but small packets get through.
+        let lost = (0..3).map(|pn| packet(pn, 1452)).collect::<Vec<_>>();
+        pmtud.on_packets_lost(&lost, now());
+        pmtud.on_packets_acked(&[packet(3, 100)]);
+        assert_eq!(pmtud.plpmtu(), 1232);
+
+        // The search starts again from the base.
+        search(&mut pmtud, 1300, 48);
+        assert_eq!(pmtud.plpmtu(), 1232);
+    }
+
+    #[test]
+    fn black_hole_pto() {
+        let mut pmtud = Pmtud::new(V6, PATH_MTU_V6);
+        pmtud.start(65527);
+        pmtud.on_pto(&[packet(0, PATH_MTU_V6), packet(1, PATH_MTU_V6)]);
+        assert_eq!(pmtud.plpmtu(), PATH_MTU_V6);
+        pmtud.on_pto(&[packet(2, PATH_MTU_V6), packet(3, PATH_MTU_V6)]);
+        assert_eq!(pmtud.plpmtu(), 1232);
+    }
+
+    #[test]
+    fn congestion_loss_not_black_hole() {
+        let mut pmtud = Pmtud::new(V6, PATH_MTU_V6);
+        pmtud.start(65527);
+        let lost = (0..5).map(|pn| packet(pn, PATH_MTU_V6)).collect::<Vec<_>>();
+        pmtud.on_packets_lost(&lost, now());
+        // Other full-size packets are acknowledged at the same time.
+        pmtud.on_packets_acked(&[packet(5, PATH_MTU_V6)]);
+        assert_eq!(pmtud.plpmtu(), PATH_MTU_V6);
+    }
+}
@@ -7,27 +7,32 @@
 // Functions that handle capturing QLOG traces.
 
 use std::{
+    net::{IpAddr, SocketAddr},
     ops::{Deref, RangeInclusive},
     time::Duration,
 };
 
-use neqo_common::{hex, qinfo, qlog::NeqoQlog, Decoder, IpTosEcn};
+use neqo_common::{hex, qinfo, qlog::NeqoQlog, Decoder, IpTosEcn, Role};
 use qlog::events::{
     connectivity::{ConnectionStarted, ConnectionState, ConnectionStateUpdated},
     quic::{
-        AckedRanges, ErrorSpace, MetricsUpdated, PacketDropped, PacketHeader, PacketLost,
-        PacketReceived, PacketSent, QuicFrame, StreamType, VersionInformation,
+        AckedRanges, ErrorSpace, MetricsUpdated, PacketDropped, PacketDroppedTrigger, PacketHeader,
+        PacketLost, PacketReceived, PacketSent, QuicFrame, RecoveryEventType, StreamType,
+        TransportEventType, VersionInformation,
     },
-    EventData, RawInfo,
+    EventData, EventImportance, EventType, JsonEvent, RawInfo,
 };
+use serde_json::json;
 use smallvec::SmallVec;
 
 use crate::{
-    connection::State,
+    cid::ConnectionIdDecoder,
+    connection::{EchState, State},
     frame::{CloseError, Frame},
     packet::{DecryptedPacket, PacketNumber, PacketType, PublicPacket},
-    path::PathRef,
+    path::{Path, PathRef},
     recovery::SentPacket,
+    server::DropReason,
     stream_id::StreamType as NeqoStreamType,
     tparams::{self, TransportParametersHandler},
     version::{Version, VersionConfig, WireVersion},
@@ -112,6 +117,147 @@ fn connection_started(qlog: &mut NeqoQlog, path: &PathRef) {
     });
 }
 
+/// What caused a path to be created or to become the active path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathTrigger {
+    /// The path that the handshake used.
+    #[default]
+    Handshake,
+    /// A packet arrived from the peer on a new path.
+    PeerAddressChange,
+    /// The application asked to migrate.
+    Application,
+    /// The client moved to the preferred address of the server.
+    PreferredAddress,
+    /// The active path failed and another path took over.
+    Failover,
+}
+
+impl PathTrigger {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Handshake => "handshake",
+            Self::PeerAddressChange => "peer_address_change",
+            Self::Application => "application",
+            Self::PreferredAddress => "preferred_address",
+            Self::Failover => "failover",
+        }
+    }
+}
+
+/// Format an address for a path event, zeroing the last octet if `redact` is set.
+fn path_address(addr: SocketAddr, redact: bool) -> String {
+    let ip = match addr.ip() {
+        IpAddr::V4(v4) if redact => {
+            let mut octets = v4.octets();
+            octets[3] = 0;
+            IpAddr::from(octets)
+        }
+        IpAddr::V6(v6) if redact => {
+            let mut octets = v6.octets();
+            octets[15] = 0;
+            IpAddr::from(octets)
+        }
+        ip => ip,
+    };
+    SocketAddr::new(ip, addr.port()).to_string()
+}
+
+fn path_tuple(path: &Path) -> serde_json::Value {
+    let redact = path.qlog_redacted();
+    json!({
+        "local": path_address(path.local_address(), redact),
+        "remote": path_address(path.remote_address(), redact),
+    })
+}
+
+/// qlog 0.13 has no definitions for path events, so these are written as JSON.
+fn path_event<F>(qlog: &mut NeqoQlog, name: &str, f: F)
+where
+    F: FnOnce() -> serde_json::Value,
+{
+    qlog.add_event_with_stream(|s| {
+        s.add_event_now(JsonEvent {
+            time: 0.0,
+            importance: EventImportance::Base,
+            name: format!("connectivity:{name}"),
+            data: f(),
+        })
+    });
+}
+
+/// The ECH configuration identifier, read from the first entry of an `ECHConfigList`.
+fn ech_config_id(config: &[u8]) -> Option<u64> {
+    let mut dec = Decoder::from(config);
+    // Skip the length of the list, then the version and length of the entry.
+    dec.decode(6)?;
+    dec.decode_uint(1)
+}
+
+/// qlog 0.13 only defines events for keys, so ECH outcomes are written as JSON.
+pub fn ech_updated(qlog: &mut NeqoQlog, role: Role, state: EchState, config: &[u8]) {
+    qlog.add_event_with_stream(|s| {
+        let retry_configs = match (state, role) {
+            (EchState::RejectedWithRetry, Role::Client) => Some("received"),
+            (EchState::RejectedWithRetry, Role::Server) => Some("sent"),
+            _ => None,
+        };
+        s.add_event_now(JsonEvent {
+            time: 0.0,
+            importance: EventImportance::Base,
+            name: "security:ech_updated".to_string(),
+            data: json!({
+                "state": state.label(),
+                "config_id": ech_config_id(config),
+                "retry_configs": retry_configs,
+            }),
+        })
+    });
+}
+
+pub fn path_assigned(qlog: &mut NeqoQlog, path: &Path, trigger: PathTrigger) {
+    path_event(qlog, "path_assigned", || {
+        json!({
+            "path": path_tuple(path),
+            "trigger": trigger.label(),
+        })
+    });
+}
+
+pub fn path_updated(qlog: &mut NeqoQlog, old: Option<&Path>, new: &Path, trigger: PathTrigger) {
+    path_event(qlog, "path_updated", || {
+        json!({
+            "old": old.map(path_tuple),
+            "new": path_tuple(new),
+            "trigger": trigger.label(),
+        })
+    });
+}
+
+pub fn path_validation_started(qlog: &mut NeqoQlog, path: &Path) {
+    path_event(
+        qlog,
+        "path_validation_started",
+        || json!({ "path": path_tuple(path) }),
+    );
+}
+
+pub fn path_validation_succeeded(qlog: &mut NeqoQlog, path: &Path) {
+    path_event(
+        qlog,
+        "path_validation_succeeded",
+        || json!({ "path": path_tuple(path) }),
+    );
+}
+
+pub fn path_validation_failed(qlog: &mut NeqoQlog, path: &Path) {
+    path_event(
+        qlog,
+        "path_validation_failed",
+        || json!({ "path": path_tuple(path) }),
+    );
+}
+
 pub fn connection_state_updated(qlog: &mut NeqoQlog, new: &State) {
     qlog.add_event_data(|| {
         let ev_data = EventData::ConnectionStateUpdated(ConnectionStateUpdated {
@@ -193,6 +339,11 @@ pub fn packet_sent(
     plen: usize,
     body: &[u8],
 ) {
+    if !qlog.wants(&EventType::TransportEventType(
+        TransportEventType::PacketSent,
+    )) {
+        return;
+    }
     qlog.add_event_with_stream(|stream| {
         let mut d = Decoder::from(body);
         let header = PacketHeader::with_type(pt.into(), Some(pn), None, None, None);
@@ -251,7 +402,47 @@ pub fn packet_dropped(qlog: &mut NeqoQlog, public_packet: &PublicPacket) {
     });
 }
 
+/// A datagram that the server dropped without giving it to a connection.
+/// The header of the first packet is included if it can be decoded.
+pub fn server_datagram_dropped(
+    qlog: &mut NeqoQlog,
+    dgram: &[u8],
+    decoder: &dyn ConnectionIdDecoder,
+    reason: DropReason,
+) {
+    qlog.add_event_data(|| {
+        let header = PublicPacket::decode(dgram, decoder).ok().map(|(p, _)| {
+            let long = p.packet_type() != PacketType::Short;
+            PacketHeader::with_type(
+                p.packet_type().into(),
+                None,
+                long.then(|| p.wire_version()),
+                long.then(|| &p.scid()[..]),
+                Some(&p.dcid()[..]),
+            )
+        });
+        let raw = RawInfo {
+            length: Some(dgram.len() as u64),
+            payload_length: None,
+            data: None,
+        };
+
+        let ev_data = EventData::PacketDropped(PacketDropped {
+            header,
+            raw: Some(raw),
+            datagram_id: None,
+            details: Some(format!("{reason:?}")),
+            trigger: Some(reason.into()),
+        });
+
+        Some(ev_data)
+    });
+}
+
 pub fn packets_lost(qlog: &mut NeqoQlog, pkts: &[SentPacket]) {
+    if !qlog.wants(&EventType::RecoveryEventType(RecoveryEventType::PacketLost)) {
+        return;
+    }
     qlog.add_event_with_stream(|stream| {
         for pkt in pkts {
             let header =
@@ -274,6 +465,11 @@ pub fn packet_received(
     public_packet: &PublicPacket,
     payload: &DecryptedPacket,
 ) {
+    if !qlog.wants(&EventType::TransportEventType(
+        TransportEventType::PacketReceived,
+    )) {
+        return;
+    }
     qlog.add_event_with_stream(|stream| {
         let mut d = Decoder::from(&payload[..]);
 
@@ -563,6 +759,20 @@ impl From<Frame<'_>> for QuicFrame {
     }
 }
 
+impl From<DropReason> for PacketDroppedTrigger {
+    fn from(value: DropReason) -> Self {
+        match value {
+            DropReason::Undecodable
+            | DropReason::InitialTooShort
+            | DropReason::TokenTooLong
+            | DropReason::InvalidToken => Self::Invalid,
+            DropReason::UnsupportedVersion => Self::Unsupported,
+            DropReason::UnknownConnection => Self::ConnectionUnknown,
+            DropReason::ShuttingDown | DropReason::RetryTooShort => Self::Rejected,
+        }
+    }
+}
+
 impl From<PacketType> for qlog::events::quic::PacketType {
     fn from(value: PacketType) -> Self {
         match value {
@@ -8,7 +8,7 @@
 
 use std::{cmp::min, collections::VecDeque};
 
-use neqo_common::Encoder;
+use neqo_common::{Encoder, IpTosDscp};
 
 use crate::{
     events::OutgoingDatagramOutcome,
@@ -44,9 +44,27 @@ impl From<DatagramTracking> for Option<u64> {
     }
 }
 
+/// Options for sending a single datagram, see `Connection::send_datagram_with`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DatagramOptions {
+    dscp: Option<IpTosDscp>,
+}
+
+impl DatagramOptions {
+    /// Mark the UDP datagram that carries this datagram with the given DSCP value,
+    /// rather than the one set for the connection.
+    /// Other frames can be sent in the same UDP datagram, and they get the same marking.
+    #[must_use]
+    pub fn dscp(mut self, dscp: IpTosDscp) -> Self {
+        self.dscp = Some(dscp);
+        self
+    }
+}
+
 struct QuicDatagram {
     data: Vec<u8>,
     tracking: DatagramTracking,
+    options: DatagramOptions,
 }
 
 impl QuicDatagram {
@@ -73,6 +91,8 @@ pub struct QuicDatagrams {
     max_queued_incoming_datagrams: usize,
     /// Datagram queued for sending.
     datagrams: VecDeque<QuicDatagram>,
+    /// The DSCP override for the datagrams written to the current packet.
+    dscp: Option<IpTosDscp>,
     conn_events: ConnectionEvents,
 }
 
@@ -89,6 +109,7 @@ impl QuicDatagrams {
             max_queued_outgoing_datagrams,
             max_queued_incoming_datagrams,
             datagrams: VecDeque::with_capacity(max_queued_outgoing_datagrams),
+            dscp: None,
             conn_events,
         }
     }
@@ -101,9 +122,16 @@ impl QuicDatagrams {
         self.remote_datagram_size = min(v, MAX_QUIC_DATAGRAM);
     }
 
+    /// Take the DSCP override for the datagrams that were written since the
+    /// last call to this function.
+    pub fn take_dscp(&mut self) -> Option<IpTosDscp> {
+        self.dscp.take()
+    }
+
     /// This function tries to write a datagram frame into a packet.
     /// If the frame does not fit into the packet, the datagram will
     /// be dropped and a `DatagramLost` event will be posted.
+    /// Datagrams with different DSCP overrides are not sent together.
     pub fn write_frames(
         &mut self,
         builder: &mut PacketBuilder,
@@ -111,6 +139,12 @@ impl QuicDatagrams {
         stats: &mut Stats,
     ) {
         while let Some(dgram) = self.datagrams.pop_front() {
+            if let Some(dscp) = dgram.options.dscp {
+                if self.dscp.map_or(false, |d| d != dscp) {
+                    self.datagrams.push_front(dgram);
+                    return;
+                }
+            }
             let len = dgram.as_ref().len();
             if builder.remaining() > len {
                 // We need 1 more than `len` for the Frame type.
@@ -125,8 +159,11 @@ impl QuicDatagrams {
                     builder.mark_full();
                 }
                 debug_assert!(builder.len() <= builder.limit());
-                stats.frame_tx.datagram += 1;
+                stats.datagram_tx.sent += 1;
                 tokens.push(RecoveryToken::Datagram(*dgram.tracking()));
+                if dgram.options.dscp.is_some() {
+                    self.dscp = dgram.options.dscp;
+                }
             } else if tokens.is_empty() {
                 // If the packet is empty, except packet headers, and the
                 // datagram cannot fit, drop it.
@@ -155,6 +192,7 @@ impl QuicDatagrams {
         &mut self,
         buf: &[u8],
         tracking: DatagramTracking,
+        options: DatagramOptions,
         stats: &mut Stats,
     ) -> Res<()> {
         if u64::try_from(buf.len()).unwrap() > self.remote_datagram_size {
@@ -170,6 +208,7 @@ impl QuicDatagrams {
         self.datagrams.push_back(QuicDatagram {
             data: buf.to_vec(),
             tracking,
+            options,
         });
         Ok(())
     }
@@ -178,6 +217,7 @@ impl QuicDatagrams {
         if self.local_datagram_size < u64::try_from(data.len()).unwrap() {
             return Err(Error::ProtocolViolation);
         }
+        stats.datagram_tx.received += 1;
         self.conn_events
             .add_datagram(self.max_queued_incoming_datagrams, data, stats);
         Ok(())
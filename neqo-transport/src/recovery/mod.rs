@@ -262,6 +262,7 @@ impl LossRecoverySpace {
         R::IntoIter: ExactSizeIterator,
     {
         let acked = self.sent_packets.take_ranges(acked_ranges);
+        stats.acked += acked.len();
         let mut eliciting = false;
         for p in &acked {
             self.remove_packet(p);
@@ -518,6 +519,12 @@ impl LossRecovery {
         self.spaces.get(pn_space).and_then(|sp| sp.largest_acked)
     }
 
+    /// The number of consecutive times that the PTO timer has fired.
+    /// This is reset when an acknowledgment is received.
+    pub fn pto_count(&self) -> usize {
+        self.pto_state.as_ref().map_or(0, PtoState::count)
+    }
+
     pub fn set_qlog(&mut self, qlog: NeqoQlog) {
         self.qlog = qlog;
     }
@@ -655,7 +662,7 @@ impl LossRecovery {
         // backoff, so that we can determine persistent congestion.
         primary_path
             .borrow_mut()
-            .on_packets_lost(prev_largest_acked, pn_space, &lost);
+            .on_packets_lost(prev_largest_acked, pn_space, &lost, now);
 
         // This must happen after on_packets_lost. If in recovery, this could
         // take us out, and then lost packets will start a new recovery period
@@ -880,11 +887,16 @@ impl LossRecovery {
                 space.largest_acked_sent_time,
                 space.space(),
                 &lost_packets[first..],
+                now,
             );
         }
         self.stats.borrow_mut().lost += lost_packets.len();
 
+        let first_pto = lost_packets.len();
         self.maybe_fire_pto(primary_path.borrow().rtt(), now, &mut lost_packets);
+        if lost_packets.len() > first_pto {
+            primary_path.borrow_mut().on_pto(&lost_packets[first_pto..]);
+        }
         lost_packets
     }
 
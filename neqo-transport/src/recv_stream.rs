@@ -25,7 +25,6 @@ use crate::{
     packet::PacketBuilder,
     recovery::{RecoveryToken, StreamRecoveryToken},
     send_stream::SendStreams,
-    stats::FrameStats,
     stream_id::StreamId,
     AppError, Error, Res,
 };
@@ -43,14 +42,9 @@ pub(crate) struct RecvStreams {
 }
 
 impl RecvStreams {
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         for stream in self.streams.values_mut() {
-            stream.write_frame(builder, tokens, stats);
+            stream.write_frame(builder, tokens);
             if builder.is_full() {
                 return;
             }
@@ -88,6 +82,12 @@ impl RecvStreams {
         self.streams.clear();
     }
 
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(StreamId, &mut RecvStream)) {
+        for (id, stream) in &mut self.streams {
+            f(*id, stream);
+        }
+    }
+
     pub fn clear_terminal(&mut self, send_streams: &SendStreams, role: Role) -> (u64, u64) {
         let recv_to_remove = self
             .streams
@@ -118,6 +118,15 @@ impl RecvStreams {
 
         (removed_bidi, removed_uni)
     }
+
+    /// Streams that hold data that can't be read yet because earlier data is
+    /// missing, with the offset of the first missing byte.
+    pub fn reassembly_gaps(&self) -> Vec<(StreamId, u64)> {
+        self.streams
+            .iter()
+            .filter_map(|(id, stream)| stream.reassembly_gap().map(|gap| (*id, gap)))
+            .collect()
+    }
 }
 
 /// Holds data not yet read by application. Orders and dedupes data ranges
@@ -312,6 +321,19 @@ impl RxStreamOrderer {
             })
     }
 
+    /// The offset of the first missing byte, if data after it has been received.
+    #[must_use]
+    pub fn first_gap(&self) -> Option<u64> {
+        let mut prev_end = self.retired;
+        for (&start, data) in &self.data_ranges {
+            if start > prev_end {
+                return Some(prev_end);
+            }
+            prev_end = max(prev_end, start + data.len() as u64);
+        }
+        None
+    }
+
     /// Bytes read by the application.
     #[must_use]
     pub fn retired(&self) -> u64 {
@@ -632,6 +654,12 @@ impl RecvStream {
         }
     }
 
+    /// The offset of the first byte that is missing before other received data.
+    #[must_use]
+    pub fn reassembly_gap(&self) -> Option<u64> {
+        self.state.recv_buf().and_then(RxStreamOrderer::first_gap)
+    }
+
     /// # Errors
     /// When the incoming data violates flow control limits.
     /// # Panics
@@ -909,15 +937,10 @@ impl RecvStream {
     }
 
     /// Maybe write a `MAX_STREAM_DATA` frame.
-    pub fn write_frame(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
+    pub fn write_frame(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
         match &mut self.state {
             // Maybe send MAX_STREAM_DATA
-            RecvStreamState::Recv { fc, .. } => fc.write_frames(builder, tokens, stats),
+            RecvStreamState::Recv { fc, .. } => fc.write_frames(builder, tokens),
             // Maybe send STOP_SENDING
             RecvStreamState::AbortReading {
                 frame_needed, err, ..
@@ -932,7 +955,6 @@ impl RecvStream {
                     tokens.push(RecoveryToken::Stream(StreamRecoveryToken::StopSending {
                         stream_id: self.stream_id,
                     }));
-                    stats.stop_sending += 1;
                     *frame_needed = false;
                 }
             }
@@ -1018,8 +1040,8 @@ mod tests {
     use crate::{
         fc::ReceiverFlowControl,
         packet::PacketBuilder,
+        recovery::{RecoveryToken, StreamRecoveryToken},
         recv_stream::{RxStreamOrderer, RX_STREAM_DATA_WINDOW},
-        stats::FrameStats,
         ConnectionEvents, Error, StreamId, RECV_BUFFER_SIZE,
     };
 
@@ -1441,6 +1463,27 @@ mod tests {
         assert_eq!(buf[..10], [9; 10]);
     }
 
+    #[test]
+    fn first_gap() {
+        let mut s = RxStreamOrderer::new();
+        assert_eq!(s.first_gap(), None);
+
+        s.inbound_frame(4, &[1; 4]);
+        assert_eq!(s.first_gap(), Some(0));
+        s.inbound_frame(0, &[2; 2]);
+        assert_eq!(s.first_gap(), Some(2));
+
+        // Filling the gap leaves nothing missing.
+        s.inbound_frame(2, &[3; 2]);
+        assert_eq!(s.first_gap(), None);
+
+        // Gaps are measured from what the application has read.
+        let mut buf = [0; 8];
+        assert_eq!(s.read(&mut buf), 8);
+        s.inbound_frame(10, &[4; 1]);
+        assert_eq!(s.first_gap(), Some(8));
+    }
+
     #[test]
     fn trim_retired() {
         let mut s = RxStreamOrderer::new();
@@ -1483,7 +1526,7 @@ mod tests {
         // consume it
         let mut builder = PacketBuilder::short(Encoder::new(), false, []);
         let mut token = Vec::new();
-        s.write_frame(&mut builder, &mut token, &mut FrameStats::default());
+        s.write_frame(&mut builder, &mut token);
 
         // it should be gone
         assert!(!s.has_frames_to_write());
@@ -1599,7 +1642,7 @@ mod tests {
         let mut token = Vec::new();
         session_fc
             .borrow_mut()
-            .write_frames(&mut builder, &mut token, &mut FrameStats::default());
+            .write_frames(&mut builder, &mut token);
 
         // Switch to SizeKnown state
         s.inbound_stream_frame(true, 2 * u64::try_from(SESSION_WINDOW).unwrap() - 1, &[0])
@@ -1620,7 +1663,7 @@ mod tests {
         let mut token = Vec::new();
         session_fc
             .borrow_mut()
-            .write_frames(&mut builder, &mut token, &mut FrameStats::default());
+            .write_frames(&mut builder, &mut token);
 
         // Test DataRecvd state
         let session_fc = Rc::new(RefCell::new(ReceiverFlowControl::new(
@@ -1866,12 +1909,15 @@ mod tests {
         // Write the fc update frame
         let mut builder = PacketBuilder::short(Encoder::new(), false, []);
         let mut token = Vec::new();
-        let mut stats = FrameStats::default();
-        fc.borrow_mut()
-            .write_frames(&mut builder, &mut token, &mut stats);
-        assert_eq!(stats.max_data, 0);
-        s.write_frame(&mut builder, &mut token, &mut stats);
-        assert_eq!(stats.max_stream_data, 1);
+        fc.borrow_mut().write_frames(&mut builder, &mut token);
+        assert!(token.is_empty());
+        s.write_frame(&mut builder, &mut token);
+        assert!(matches!(
+            token[..],
+            [RecoveryToken::Stream(
+                StreamRecoveryToken::MaxStreamData { .. }
+            )]
+        ));
 
         // Receive 1 byte that will case a session fc update after it is read.
         s.inbound_stream_frame(false, SW / 2, &[0]).unwrap();
@@ -1880,11 +1926,13 @@ mod tests {
         check_fc(s.fc().unwrap(), SW / 2 + 1, SW / 2 + 1);
         assert!(fc.borrow().frame_needed());
         assert!(!s.fc().unwrap().frame_needed());
-        fc.borrow_mut()
-            .write_frames(&mut builder, &mut token, &mut stats);
-        assert_eq!(stats.max_data, 1);
-        s.write_frame(&mut builder, &mut token, &mut stats);
-        assert_eq!(stats.max_stream_data, 1);
+        fc.borrow_mut().write_frames(&mut builder, &mut token);
+        assert!(matches!(
+            token[..],
+            [_, RecoveryToken::Stream(StreamRecoveryToken::MaxData(_))]
+        ));
+        s.write_frame(&mut builder, &mut token);
+        assert_eq!(token.len(), 2);
     }
 
     /// Test flow control in `RecvStreamState::SizeKnown`
@@ -18,7 +18,6 @@ use crate::{
     packet::PacketBuilder,
     qlog::{self, QlogMetric},
     recovery::RecoveryToken,
-    stats::FrameStats,
     tracking::PacketNumberSpace,
 };
 
@@ -177,13 +176,8 @@ impl RttEstimate {
         self.min_rtt
     }
 
-    pub fn write_frames(
-        &mut self,
-        builder: &mut PacketBuilder,
-        tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
-    ) {
-        self.ack_delay.write_frames(builder, tokens, stats);
+    pub fn write_frames(&mut self, builder: &mut PacketBuilder, tokens: &mut Vec<RecoveryToken>) {
+        self.ack_delay.write_frames(builder, tokens);
     }
 
     pub fn frame_lost(&mut self, lost: &AckRate) {
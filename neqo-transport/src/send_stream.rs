@@ -27,7 +27,6 @@ use crate::{
     frame::{Frame, FRAME_TYPE_RESET_STREAM},
     packet::PacketBuilder,
     recovery::{RecoveryToken, StreamRecoveryToken},
-    stats::FrameStats,
     stream_id::StreamId,
     streams::SendOrder,
     tparams::{self, TransportParameters},
@@ -761,12 +760,11 @@ impl SendStream {
         priority: TransmissionPriority,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
         qtrace!("write STREAM frames at priority {:?}", priority);
-        if !self.write_reset_frame(priority, builder, tokens, stats) {
-            self.write_blocked_frame(priority, builder, tokens, stats);
-            self.write_stream_frame(priority, builder, tokens, stats);
+        if !self.write_reset_frame(priority, builder, tokens) {
+            self.write_blocked_frame(priority, builder, tokens);
+            self.write_stream_frame(priority, builder, tokens);
         }
     }
 
@@ -776,14 +774,13 @@ impl SendStream {
         priority: TransmissionPriority,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) -> bool {
-        if !self.write_reset_frame(priority, builder, tokens, stats) {
-            self.write_blocked_frame(priority, builder, tokens, stats);
+        if !self.write_reset_frame(priority, builder, tokens) {
+            self.write_blocked_frame(priority, builder, tokens);
             if builder.is_full() {
                 return false;
             }
-            self.write_stream_frame(priority, builder, tokens, stats);
+            self.write_stream_frame(priority, builder, tokens);
             if builder.is_full() {
                 return false;
             }
@@ -857,6 +854,17 @@ impl SendStream {
         }
     }
 
+    /// Bytes that were written to the stream but not yet sent.
+    #[must_use]
+    pub fn bytes_unsent(&self) -> u64 {
+        match &self.state {
+            SendStreamState::Send { .. } | SendStreamState::DataSent { .. } => {
+                self.bytes_written().saturating_sub(self.bytes_sent)
+            }
+            _ => 0,
+        }
+    }
+
     #[must_use]
     pub fn bytes_acked(&self) -> u64 {
         match &self.state {
@@ -953,7 +961,6 @@ impl SendStream {
         priority: TransmissionPriority,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
         let retransmission = if priority == self.priority {
             false
@@ -1008,7 +1015,6 @@ impl SendStream {
                     fin,
                 },
             )));
-            stats.stream += 1;
         }
     }
 
@@ -1050,7 +1056,6 @@ impl SendStream {
         p: TransmissionPriority,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) -> bool {
         if let SendStreamState::ResetSent {
             final_size,
@@ -1071,7 +1076,6 @@ impl SendStream {
                 tokens.push(RecoveryToken::Stream(StreamRecoveryToken::ResetStream {
                     stream_id: self.stream_id,
                 }));
-                stats.reset_stream += 1;
                 *priority = None;
                 true
             } else {
@@ -1098,14 +1102,13 @@ impl SendStream {
         priority: TransmissionPriority,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
         // Send STREAM_DATA_BLOCKED at normal priority always.
         if priority == self.priority {
             if let SendStreamState::Ready { fc, .. } | SendStreamState::Send { fc, .. } =
                 &mut self.state
             {
-                fc.write_frames(builder, tokens, stats);
+                fc.write_frames(builder, tokens);
             }
         }
     }
@@ -1191,6 +1194,15 @@ impl SendStream {
         }
     }
 
+    /// Whether the stream is blocked by its stream-level flow control limit.
+    pub(crate) fn fc_blocked(&self) -> bool {
+        if let SendStreamState::Ready { fc, .. } | SendStreamState::Send { fc, .. } = &self.state {
+            fc.is_blocked()
+        } else {
+            false
+        }
+    }
+
     /// Bytes sendable on stream. Constrained by stream credit available,
     /// connection credit available, and space in the tx buffer.
     #[must_use]
@@ -1561,6 +1573,22 @@ impl SendStreams {
         self.map.insert(id, stream);
     }
 
+    /// The streams that are blocked by stream-level flow control, in order.
+    /// Bytes that were written to any stream but not yet sent.
+    pub fn bytes_unsent(&self) -> u64 {
+        self.map.values().map(SendStream::bytes_unsent).sum()
+    }
+
+    pub fn fc_blocked(&self) -> Vec<StreamId> {
+        let mut blocked = self
+            .map
+            .iter()
+            .filter_map(|(id, ss)| ss.fc_blocked().then_some(*id))
+            .collect::<Vec<_>>();
+        blocked.sort_unstable();
+        blocked
+    }
+
     fn group_mut(&mut self, sendorder: Option<SendOrder>) -> &mut OrderGroup {
         if let Some(order) = sendorder {
             self.sendordered.entry(order).or_default()
@@ -1691,7 +1719,6 @@ impl SendStreams {
         priority: TransmissionPriority,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
         qtrace!("write STREAM frames at priority {:?}", priority);
         // WebTransport data (which is Normal) may have a SendOrder
@@ -1731,7 +1758,7 @@ impl SendStreams {
         for stream in self.map.values_mut() {
             if !stream.is_fair() {
                 qtrace!("   {}", stream);
-                if !stream.write_frames_with_early_return(priority, builder, tokens, stats) {
+                if !stream.write_frames_with_early_return(priority, builder, tokens) {
                     break;
                 }
             }
@@ -1750,7 +1777,7 @@ impl SendStreams {
             } else {
                 qtrace!("   None");
             }
-            if !stream.write_frames_with_early_return(priority, builder, tokens, stats) {
+            if !stream.write_frames_with_early_return(priority, builder, tokens) {
                 break;
             }
         }
@@ -1802,7 +1829,6 @@ mod tests {
         send_stream::{
             RangeState, RangeTracker, SendStream, SendStreamState, SendStreams, TxBuffer,
         },
-        stats::FrameStats,
         ConnectionEvents, StreamId, SEND_BUFFER_SIZE,
     };
 
@@ -2599,12 +2625,7 @@ mod tests {
         // Write a small frame: no fin.
         let written = builder.len();
         builder.set_limit(written + 6);
-        ss.write_frames(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut FrameStats::default(),
-        );
+        ss.write_frames(TransmissionPriority::default(), &mut builder, &mut tokens);
         assert_eq!(builder.len(), written + 6);
         assert_eq!(tokens.len(), 1);
         let f1_token = tokens.remove(0);
@@ -2613,12 +2634,7 @@ mod tests {
         // Write the rest: fin.
         let written = builder.len();
         builder.set_limit(written + 200);
-        ss.write_frames(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut FrameStats::default(),
-        );
+        ss.write_frames(TransmissionPriority::default(), &mut builder, &mut tokens);
         assert_eq!(builder.len(), written + 10);
         assert_eq!(tokens.len(), 1);
         let f2_token = tokens.remove(0);
@@ -2626,12 +2642,7 @@ mod tests {
 
         // Should be no more data to frame.
         let written = builder.len();
-        ss.write_frames(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut FrameStats::default(),
-        );
+        ss.write_frames(TransmissionPriority::default(), &mut builder, &mut tokens);
         assert_eq!(builder.len(), written);
         assert!(tokens.is_empty());
 
@@ -2645,7 +2656,6 @@ mod tests {
             TransmissionPriority::default() + RetransmissionPriority::default(),
             &mut builder,
             &mut tokens,
-            &mut FrameStats::default(),
         );
         assert_eq!(builder.len(), written + 7); // Needs a length this time.
         assert_eq!(tokens.len(), 1);
@@ -2661,7 +2671,6 @@ mod tests {
             TransmissionPriority::default() + RetransmissionPriority::default(),
             &mut builder,
             &mut tokens,
-            &mut FrameStats::default(),
         );
         assert_eq!(builder.len(), written + 10);
         assert_eq!(tokens.len(), 1);
@@ -2684,34 +2693,19 @@ mod tests {
 
         let mut tokens = Vec::new();
         let mut builder = PacketBuilder::short(Encoder::new(), false, []);
-        ss.write_frames(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut FrameStats::default(),
-        );
+        ss.write_frames(TransmissionPriority::default(), &mut builder, &mut tokens);
         let f1_token = tokens.remove(0);
         assert_eq!(as_stream_token(&f1_token).offset, 0);
         assert_eq!(as_stream_token(&f1_token).length, 10);
         assert!(!as_stream_token(&f1_token).fin);
 
         // Should be no more data to frame
-        ss.write_frames(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut FrameStats::default(),
-        );
+        ss.write_frames(TransmissionPriority::default(), &mut builder, &mut tokens);
         assert!(tokens.is_empty());
 
         ss.get_mut(StreamId::from(0)).unwrap().close();
 
-        ss.write_frames(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut FrameStats::default(),
-        );
+        ss.write_frames(TransmissionPriority::default(), &mut builder, &mut tokens);
         let f2_token = tokens.remove(0);
         assert_eq!(as_stream_token(&f2_token).offset, 10);
         assert_eq!(as_stream_token(&f2_token).length, 0);
@@ -2721,12 +2715,7 @@ mod tests {
         ss.lost(as_stream_token(&f2_token));
 
         // Next frame should set fin
-        ss.write_frames(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut FrameStats::default(),
-        );
+        ss.write_frames(TransmissionPriority::default(), &mut builder, &mut tokens);
         let f3_token = tokens.remove(0);
         assert_eq!(as_stream_token(&f3_token).offset, 10);
         assert_eq!(as_stream_token(&f3_token).length, 0);
@@ -2736,12 +2725,7 @@ mod tests {
         ss.lost(as_stream_token(&f1_token));
 
         // Next frame should set fin and include all data
-        ss.write_frames(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut FrameStats::default(),
-        );
+        ss.write_frames(TransmissionPriority::default(), &mut builder, &mut tokens);
         let f4_token = tokens.remove(0);
         assert_eq!(as_stream_token(&f4_token).offset, 0);
         assert_eq!(as_stream_token(&f4_token).length, 10);
@@ -2763,24 +2747,18 @@ mod tests {
         // This doesn't report blocking yet.
         let mut builder = PacketBuilder::short(Encoder::new(), false, []);
         let mut tokens = Vec::new();
-        let mut stats = FrameStats::default();
-        s.write_blocked_frame(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut stats,
-        );
-        assert_eq!(stats.stream_data_blocked, 0);
+        s.write_blocked_frame(TransmissionPriority::default(), &mut builder, &mut tokens);
+        assert!(tokens.is_empty());
 
         // Blocking is reported after sending the last available credit.
         s.mark_as_sent(0, 2, false);
-        s.write_blocked_frame(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut stats,
-        );
-        assert_eq!(stats.stream_data_blocked, 1);
+        s.write_blocked_frame(TransmissionPriority::default(), &mut builder, &mut tokens);
+        assert!(matches!(
+            tokens[..],
+            [RecoveryToken::Stream(
+                StreamRecoveryToken::StreamDataBlocked { .. }
+            )]
+        ));
 
         // Now increase the stream limit and test the connection limit.
         s.set_max_stream_data(10);
@@ -2788,17 +2766,19 @@ mod tests {
         assert_eq!(s.send(b"abcd").unwrap(), 3);
         assert_eq!(s.next_bytes(false), Some((2, &b"abc"[..])));
         // DATA_BLOCKED is not sent yet.
-        conn_fc
-            .borrow_mut()
-            .write_frames(&mut builder, &mut tokens, &mut stats);
-        assert_eq!(stats.data_blocked, 0);
+        conn_fc.borrow_mut().write_frames(&mut builder, &mut tokens);
+        assert_eq!(tokens.len(), 1);
 
         // DATA_BLOCKED is queued once bytes using all credit are sent.
         s.mark_as_sent(2, 3, false);
-        conn_fc
-            .borrow_mut()
-            .write_frames(&mut builder, &mut tokens, &mut stats);
-        assert_eq!(stats.data_blocked, 1);
+        conn_fc.borrow_mut().write_frames(&mut builder, &mut tokens);
+        assert!(matches!(
+            tokens[..],
+            [
+                _,
+                RecoveryToken::Stream(StreamRecoveryToken::DataBlocked(_))
+            ]
+        ));
     }
 
     #[test]
@@ -2816,14 +2796,13 @@ mod tests {
         // Assert that STREAM_DATA_BLOCKED is sent.
         let mut builder = PacketBuilder::short(Encoder::new(), false, []);
         let mut tokens = Vec::new();
-        let mut stats = FrameStats::default();
-        s.write_blocked_frame(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut stats,
-        );
-        assert_eq!(stats.stream_data_blocked, 1);
+        s.write_blocked_frame(TransmissionPriority::default(), &mut builder, &mut tokens);
+        assert!(matches!(
+            tokens[..],
+            [RecoveryToken::Stream(
+                StreamRecoveryToken::StreamDataBlocked { .. }
+            )]
+        ));
 
         // Assert that a non-atomic write works.
         assert_eq!(s.send(b"abc").unwrap(), 2);
@@ -2837,10 +2816,14 @@ mod tests {
         assert_eq!(s.send_atomic(b"abcd").unwrap(), 0);
 
         // Assert that DATA_BLOCKED is sent.
-        conn_fc
-            .borrow_mut()
-            .write_frames(&mut builder, &mut tokens, &mut stats);
-        assert_eq!(stats.data_blocked, 1);
+        conn_fc.borrow_mut().write_frames(&mut builder, &mut tokens);
+        assert!(matches!(
+            tokens[..],
+            [
+                _,
+                RecoveryToken::Stream(StreamRecoveryToken::DataBlocked(_))
+            ]
+        ));
 
         // Check that a non-atomic write works.
         assert_eq!(s.send(b"abcd").unwrap(), 3);
@@ -2903,14 +2886,8 @@ mod tests {
         // No frame should be sent here.
         let mut builder = PacketBuilder::short(Encoder::new(), false, []);
         let mut tokens = Vec::new();
-        let mut stats = FrameStats::default();
-        s.write_stream_frame(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut stats,
-        );
-        assert_eq!(stats.stream, 0);
+        s.write_stream_frame(TransmissionPriority::default(), &mut builder, &mut tokens);
+        assert!(tokens.is_empty());
     }
 
     /// Create a `SendStream` and force it into a state where it believes that
@@ -2966,18 +2943,12 @@ mod tests {
         builder.set_limit(header_len + space);
 
         let mut tokens = Vec::new();
-        let mut stats = FrameStats::default();
-        s.write_stream_frame(
-            TransmissionPriority::default(),
-            &mut builder,
-            &mut tokens,
-            &mut stats,
-        );
+        s.write_stream_frame(TransmissionPriority::default(), &mut builder, &mut tokens);
         qtrace!(
             "STREAM frame: {}",
             hex_with_len(&builder.as_ref()[header_len..])
         );
-        stats.stream > 0
+        !tokens.is_empty()
     }
 
     fn frame_sent(offset: usize, len: usize, fin: bool, space: usize) -> bool {
@@ -3067,14 +3038,8 @@ mod tests {
             // Add 2 for the frame type and stream ID, then add the extra.
             builder.set_limit(header_len + data.len() + 2 + extra);
             let mut tokens = Vec::new();
-            let mut stats = FrameStats::default();
-            s.write_stream_frame(
-                TransmissionPriority::default(),
-                &mut builder,
-                &mut tokens,
-                &mut stats,
-            );
-            assert_eq!(stats.stream, 1);
+            s.write_stream_frame(TransmissionPriority::default(), &mut builder, &mut tokens);
+            assert_eq!(tokens.len(), 1);
             assert_eq!(builder.is_full(), expect_full);
             Vec::from(Encoder::from(builder)).split_off(header_len)
         }
@@ -16,7 +16,10 @@ use std::{
 use neqo_common::qlog::NeqoQlog;
 
 use crate::{
-    cc::{ClassicCongestionControl, CongestionControl, CongestionControlAlgorithm, Cubic, NewReno},
+    cc::{
+        CcSnapshot, ClassicCongestionControl, CongestionControl, CongestionControlAlgorithm, Cubic,
+        NewReno,
+    },
     pace::Pacer,
     recovery::SentPacket,
     rtt::RttEstimate,
@@ -27,6 +30,7 @@ pub const PACING_BURST_SIZE: usize = 2;
 
 #[derive(Debug)]
 pub struct PacketSender {
+    alg: CongestionControlAlgorithm,
     cc: Box<dyn CongestionControl>,
     pacer: Pacer,
 }
@@ -46,6 +50,7 @@ impl PacketSender {
         now: Instant,
     ) -> Self {
         Self {
+            alg,
             cc: match alg {
                 CongestionControlAlgorithm::NewReno => {
                     Box::new(ClassicCongestionControl::new(NewReno::default()))
@@ -67,11 +72,31 @@ impl PacketSender {
         self.cc.cwnd()
     }
 
+    #[must_use]
+    pub fn bytes_in_flight(&self) -> usize {
+        self.cc.bytes_in_flight()
+    }
+
     #[must_use]
     pub fn cwnd_avail(&self) -> usize {
         self.cc.cwnd_avail()
     }
 
+    /// Get the current values from congestion control.
+    #[must_use]
+    pub fn snapshot(&self, rtt: Duration) -> CcSnapshot {
+        let cwnd = self.cc.cwnd();
+        CcSnapshot {
+            algorithm: self.alg,
+            phase: self.cc.phase(),
+            cwnd,
+            ssthresh: self.cc.ssthresh(),
+            bytes_in_flight: self.cc.bytes_in_flight(),
+            pacing_rate: self.pacer.rate(rtt, cwnd),
+            app_limited: self.cc.app_limited(),
+        }
+    }
+
     pub fn on_packets_acked(
         &mut self,
         acked_pkts: &[SentPacket],
@@ -106,6 +131,11 @@ impl PacketSender {
         self.cc.discard(pkt);
     }
 
+    /// Called when packets are lost for reasons other than congestion.
+    pub fn discard_lost(&mut self, lost_packets: &[SentPacket]) {
+        self.cc.discard_lost(lost_packets);
+    }
+
     /// When we migrate, the congestion controller for the previously active path drops
     /// all bytes in flight.
     pub fn discard_in_flight(&mut self) {
@@ -7,34 +7,47 @@
 // This file implements a server that can handle multiple connections.
 
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
+    cmp::min,
     collections::{HashMap, HashSet, VecDeque},
-    fs::OpenOptions,
+    io::Write,
     mem,
     net::SocketAddr,
     ops::{Deref, DerefMut},
-    path::PathBuf,
+    path::{Path, PathBuf},
     rc::{Rc, Weak},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
+use enum_map::{Enum, EnumMap};
 use neqo_common::{
-    self as common, event::Provider, hex, qdebug, qerror, qinfo, qlog::NeqoQlog, qtrace, qwarn,
-    Datagram, Decoder, Role,
+    self as common,
+    event::Provider,
+    hex, qdebug, qerror, qinfo,
+    qlog::{NeqoQlog, QlogFormat, QlogLimits, QlogVersion},
+    qspan, qtrace, qwarn, Datagram, Decoder, IpTos, IpTosDscp, Role,
 };
 use neqo_crypto::{
-    encode_ech_config, AntiReplay, Cipher, PrivateKey, PublicKey, ZeroRttCheckResult,
-    ZeroRttChecker,
+    agent::CertificateInfo, encode_ech_config, random, AntiReplay, AntiReplayWindow, Cipher,
+    PrivateKey, PublicKey, SecretAgentInfo, ZeroRttCheckResult, ZeroRttChecker,
 };
 use qlog::streamer::QlogStreamer;
 
 pub use crate::addr_valid::ValidateAddress;
 use crate::{
     addr_valid::{AddressValidation, AddressValidationResult},
-    cid::{ConnectionId, ConnectionIdDecoder, ConnectionIdGenerator, ConnectionIdRef},
-    connection::{Connection, Output, State},
+    cc::CongestionPhase,
+    cid::{
+        generate_checked_cid, ConnectionId, ConnectionIdDecoder, ConnectionIdGenerator,
+        ConnectionIdRef, LOCAL_ACTIVE_CID_LIMIT, MAX_CONNECTION_ID_LEN,
+    },
+    connection::{Connection, HandshakePhase, Output, State},
+    crypto::KeyUpdatePolicy,
+    fc::FlowControlState,
     packet::{PacketBuilder, PacketType, PublicPacket, MIN_INITIAL_PACKET_SIZE},
-    ConnectionParameters, Res, Version,
+    path::canonical_address,
+    stats::{DatagramStats, ProcessDiag, RecoveryStats},
+    AppError, ConnectionParameters, Error, Res, StreamId, TransportError, Version,
 };
 
 pub enum InitialResult {
@@ -44,13 +57,51 @@ pub enum InitialResult {
 }
 
 type StateRef = Rc<RefCell<ServerConnectionState>>;
+type QlogWriterFactory = Box<dyn FnMut(ConnectionIdRef<'_>) -> Box<dyn Write + Send + Sync>>;
+
+/// Where the server writes qlog traces for its connections.
+enum QlogOutput {
+    /// A file per connection in this directory.
+    Dir(PathBuf),
+    /// A writer per connection, provided by the application.
+    Writer(QlogWriterFactory),
+}
 type ConnectionTableRef = Rc<RefCell<HashMap<ConnectionId, StateRef>>>;
+/// A function that decides whether to accept a stream that a peer opened.
+type StreamFilter = Box<dyn FnMut(&ActiveConnectionRef, StreamId) -> bool>;
+/// A function that is told about each Version Negotiation packet that is sent.
+type VersionNegotiationObserver = Box<dyn FnMut(u32, SocketAddr)>;
+/// A function that is told when the address of a client has been validated.
+type AddressValidatedObserver = Box<dyn FnMut(&ActiveConnectionRef, SocketAddr)>;
 
 #[derive(Debug)]
 pub struct ServerConnectionState {
     c: Connection,
     active_attempt: Option<AttemptKey>,
+    /// The destination connection ID from the first Initial the client sent,
+    /// from before any Retry.
+    original_dcid: ConnectionId,
+    /// The source connection ID from the client's Initial.
+    client_initial_scid: ConnectionId,
     wake_at: Option<Instant>,
+    /// The last time that the connection produced events for the application.
+    last_activity: Instant,
+    /// Whether the application has paused sending on this connection.
+    paused: bool,
+    /// Whether sending resumed since the server last looked at the connection.
+    resumed: bool,
+    /// Datagrams that the connection produced while sending was paused.
+    held: VecDeque<Datagram>,
+    /// The priority of this connection, for use by an `OutputScheduler`.
+    priority: u32,
+    /// The number of datagrams received before the handshake completed.
+    handshake_packets: u32,
+    /// The last datagram that carried `CONNECTION_CLOSE`.
+    close: Option<Datagram>,
+    /// Whether the address of the client has been validated.
+    address_validated: bool,
+    /// How qlog traces that are started later are written.
+    qlog_settings: QlogSettings,
 }
 
 impl ServerConnectionState {
@@ -59,11 +110,16 @@ impl ServerConnectionState {
     }
 
     fn needs_waking(&self, now: Instant) -> bool {
-        self.wake_at.map_or(false, |t| t <= now)
+        self.resumed || self.wake_at.map_or(false, |t| t <= now)
     }
 
     fn woken(&mut self) {
         self.wake_at = None;
+        self.resumed = false;
+    }
+
+    fn idle_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_activity)
     }
 }
 
@@ -80,16 +136,130 @@ impl DerefMut for ServerConnectionState {
     }
 }
 
+/// What a server does with packets for connections that it has closed and forgotten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PostClosePolicy {
+    /// Drop the packets.
+    #[default]
+    Drop,
+    /// Respond with a stateless reset.
+    StatelessReset,
+    /// Send the `CONNECTION_CLOSE` again, at most `count` times.
+    ReplayClose { count: usize },
+}
+
+/// Why the server dropped a datagram without passing it to a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum DropReason {
+    /// The header of the first packet could not be decoded.
+    Undecodable,
+    /// The packet used an unsupported version, but the datagram was too small
+    /// to be sent a Version Negotiation packet.
+    UnsupportedVersion,
+    /// The packet was not an Initial and it was for an unknown connection.
+    UnknownConnection,
+    /// An Initial packet was in a datagram that was too small.
+    InitialTooShort,
+    /// An Initial packet arrived while the server was shutting down.
+    ShuttingDown,
+    /// An Initial packet carried a token that was longer than the limit.
+    TokenTooLong,
+    /// An Initial packet carried a token that was not valid.
+    InvalidToken,
+    /// An Initial packet would have been sent a Retry, but the datagram was smaller
+    /// than the limit set with `Server::set_retry_only_for_larger_initials`.
+    RetryTooShort,
+}
+
+/// The default limit on the size of the token in an Initial packet.
+/// This comfortably exceeds the size of the tokens that this server generates.
+const DEFAULT_MAX_TOKEN_LENGTH: usize = 512;
+
+/// Decides the order in which connections that have something to send are
+/// asked for a datagram.
+pub trait OutputScheduler {
+    /// Choose the connection to ask for a datagram next, returning its index in `waiting`.
+    /// `waiting` is never empty.  It lists connections in the order that they became
+    /// ready to send, and a connection that sends a datagram moves to the end.
+    fn select(&mut self, waiting: &[ActiveConnectionRef]) -> usize;
+}
+
+/// Asks each connection for one datagram in turn.  This is what a server does
+/// if no other scheduler is set.
+#[derive(Debug, Default)]
+pub struct RoundRobin;
+
+impl OutputScheduler for RoundRobin {
+    fn select(&mut self, _waiting: &[ActiveConnectionRef]) -> usize {
+        0
+    }
+}
+
+/// Shares datagrams between connections in proportion to the priority of each,
+/// as set with `ActiveConnectionRef::set_priority`.  Sending is interleaved,
+/// so no connection is starved, even with a low priority.
+#[derive(Debug, Default)]
+pub struct WeightedRoundRobin {
+    credit: HashMap<ActiveConnectionRef, i64>,
+}
+
+impl OutputScheduler for WeightedRoundRobin {
+    fn select(&mut self, waiting: &[ActiveConnectionRef]) -> usize {
+        // Forget about any connection that has nothing to send.
+        self.credit.retain(|c, _| waiting.contains(c));
+        let mut total = 0;
+        let mut best = (0, i64::MIN);
+        for (i, c) in waiting.iter().enumerate() {
+            let weight = i64::from(c.priority().max(1));
+            total += weight;
+            let credit = self.credit.entry(c.clone()).or_default();
+            *credit += weight;
+            if *credit > best.1 {
+                best = (i, *credit);
+            }
+        }
+        *self.credit.get_mut(&waiting[best.0]).unwrap() -= total;
+        best.0
+    }
+}
+
+/// How long to retain state for closed connections.
+const CLOSED_CONNECTION_RETENTION: Duration = Duration::from_secs(10);
+
+/// The `CONNECTION_CLOSE` of a closed connection, which is shared by all
+/// of its connection IDs.
+#[derive(Debug)]
+struct ReplayClose {
+    close: Datagram,
+    remaining: usize,
+}
+
+/// What is retained for a connection ID of a closed connection.
+#[derive(Debug)]
+struct ClosedConnection {
+    replay: Option<Rc<RefCell<ReplayClose>>>,
+    reset_token: Option<[u8; 16]>,
+    expires: Instant,
+}
+
 /// A `AttemptKey` is used to disambiguate connection attempts.
 /// Multiple connection attempts with the same key won't produce multiple connections.
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 struct AttemptKey {
     // Using the remote address is sufficient for disambiguation,
     // until we support multiple local socket addresses.
+    // This is in canonical form, see `canonical_address`.
     remote_address: SocketAddr,
     odcid: ConnectionId,
 }
 
+/// A 0-RTT datagram that arrived before the Initial for its connection.
+struct BufferedZeroRtt {
+    attempt_key: AttemptKey,
+    dgram: Datagram,
+    received: Instant,
+}
+
 /// A `ServerZeroRttChecker` is a simple wrapper around a single checker.
 /// It uses `RefCell` so that the wrapped checker can be shared between
 /// multiple connections created by the server.
@@ -131,6 +301,58 @@ impl InitialDetails {
     }
 }
 
+/// How the qlog traces of connections are written.
+#[derive(Debug, Clone, Copy, Default)]
+struct QlogSettings {
+    /// How qlog traces are serialized.
+    format: QlogFormat,
+    /// The version of qlog that traces follow.
+    version: QlogVersion,
+    /// Limits on the size of qlog traces.
+    limits: QlogLimits,
+}
+
+impl QlogSettings {
+    /// The limits that apply to traces.  Size limits only apply to JSON-SEQ.
+    fn limits(self) -> QlogLimits {
+        if self.format == QlogFormat::JsonSeq {
+            self.limits
+        } else {
+            QlogLimits::default()
+        }
+    }
+
+    /// The name of the trace for the connection with the given original DCID.
+    fn path(self, odcid: ConnectionIdRef<'_>) -> PathBuf {
+        PathBuf::from(format!("{odcid}.{}", self.format.extension()))
+    }
+
+    /// Start a trace that is written to `writer`, after any size limit is applied.
+    fn trace(
+        self,
+        writer: Box<dyn Write + Send + Sync>,
+        qlog_path: PathBuf,
+    ) -> Result<NeqoQlog, qlog::Error> {
+        let streamer = QlogStreamer::new(
+            qlog::QLOG_VERSION.to_string(),
+            Some("Neqo server qlog".to_string()),
+            Some("Neqo server qlog".to_string()),
+            None,
+            std::time::Instant::now(),
+            common::qlog::new_trace(Role::Server),
+            qlog::events::EventImportance::Base,
+            self.version.writer(self.format.writer(writer)),
+        );
+        NeqoQlog::enabled(streamer, qlog_path)
+    }
+}
+
+struct ExternalPsk {
+    identity: Vec<u8>,
+    key: Vec<u8>,
+    cipher: Cipher,
+}
+
 struct EchConfig {
     config: u8,
     public_name: String,
@@ -157,8 +379,18 @@ pub struct Server {
     certs: Vec<String>,
     /// The ALPN values that the server supports.
     protocols: Vec<String>,
+    /// ALPN values that replace `protocols` for connections that start with
+    /// a particular version.
+    version_protocols: HashMap<Version, Vec<String>>,
     /// The cipher suites that the server supports.
     ciphers: Vec<Cipher>,
+    /// The names of the certificate authorities for client certificates.
+    /// If this is empty, clients are not asked to authenticate.
+    client_auth_cas: Vec<String>,
+    /// An external pre-shared key that clients can use instead of the certificate.
+    external_psk: Option<ExternalPsk>,
+    /// Whether connections do full handshakes, without resumption or 0-RTT.
+    resumption_disabled: bool,
     /// Anti-replay configuration for 0-RTT.
     anti_replay: AntiReplay,
     /// A function for determining if 0-RTT can be accepted.
@@ -179,12 +411,67 @@ pub struct Server {
     waiting: VecDeque<StateRef>,
     /// The latest [`Output::Callback`] returned from [`Server::process`].
     wake_at: Option<Instant>,
+    /// The connection that the datagram passed to [`Server::process_into`]
+    /// was routed to, if any.
+    routed: Option<StateRef>,
     /// Address validation logic, which determines whether we send a Retry.
     address_validation: Rc<RefCell<AddressValidation>>,
-    /// Directory to create qlog traces in
-    qlog_dir: Option<PathBuf>,
+    /// Decides the order in which waiting connections send.
+    /// Connections send in turn if this isn't set.
+    output_scheduler: Option<Box<dyn OutputScheduler>>,
+    /// Where to write qlog traces, if anywhere.
+    qlog_output: Option<QlogOutput>,
+    /// How qlog traces are written.
+    qlog_settings: QlogSettings,
     /// Encrypted client hello (ECH) configuration.
     ech_config: Option<EchConfig>,
+    /// When connections should update their 1-RTT keys.
+    key_update_policy: KeyUpdatePolicy,
+    /// How long new connections can be idle before they send a PING.
+    keep_alive: Option<Duration>,
+    /// The number of datagrams a connection attempt can receive before it is abandoned.
+    max_handshake_packets: u32,
+    /// How to respond to packets for closed connections.
+    post_close_policy: PostClosePolicy,
+    /// Connections that were closed, keyed by their connection IDs.
+    closed: HashMap<ConnectionId, ClosedConnection>,
+    /// Set when a connection closes, so that the next sweep removes it from
+    /// `connections`.  Scanning the table is skipped when this isn't set.
+    sweep_needed: bool,
+    /// The DSCP value for datagrams that the server sends without a connection.
+    dscp: IpTosDscp,
+    /// Decides whether streams opened by peers are accepted.
+    stream_filter: Option<StreamFilter>,
+    /// 0-RTT datagrams that arrived before the Initial that creates their connection.
+    zero_rtt_buffer: VecDeque<BufferedZeroRtt>,
+    /// The maximum number of datagrams in `zero_rtt_buffer`.
+    zero_rtt_buffer_limit: usize,
+    /// How long datagrams are kept in `zero_rtt_buffer`.
+    zero_rtt_buffer_age: Duration,
+    /// Set once the server is shutting down, after which new connections are refused.
+    shutting_down: bool,
+    /// Initial packets with tokens longer than this are dropped.
+    max_token_length: usize,
+    /// The smallest datagram containing an Initial that will be sent a Retry.
+    retry_min_datagram_size: usize,
+    /// Called with the version and address of clients that are sent Version Negotiation.
+    on_version_negotiation: Option<VersionNegotiationObserver>,
+    /// Called when the connection ID generator runs out of connection IDs.
+    on_cid_exhaustion: Option<Box<dyn FnMut()>>,
+    /// Called once for each connection when the address of the client is validated.
+    on_address_validated: Option<AddressValidatedObserver>,
+    /// The number of connection IDs each connection can be issued per second,
+    /// or zero if there is no limit.
+    cid_issuance_rate: u32,
+    /// The time passed to the current call to `process`, which connection ID
+    /// generators use to limit issuance.
+    clock: Rc<Cell<Instant>>,
+    /// A trace for events that don't belong to any connection.
+    qlog: NeqoQlog,
+    /// Counts of the datagrams that were dropped without reaching a connection.
+    dropped: EnumMap<DropReason, usize>,
+    /// Why the most recent datagram was dropped, for `process_with_diag`.
+    last_drop: Option<DropReason>,
 }
 
 impl Server {
@@ -213,7 +500,11 @@ impl Server {
         Ok(Self {
             certs: certs.iter().map(|x| String::from(x.as_ref())).collect(),
             protocols: protocols.iter().map(|x| String::from(x.as_ref())).collect(),
+            version_protocols: HashMap::default(),
             ciphers: Vec::new(),
+            client_auth_cas: Vec::new(),
+            external_psk: None,
+            resumption_disabled: false,
             anti_replay,
             zero_rtt_checker: ServerZeroRttChecker::new(zero_rtt_checker),
             cid_generator,
@@ -223,15 +514,76 @@ impl Server {
             active: HashSet::default(),
             waiting: VecDeque::default(),
             address_validation: Rc::new(RefCell::new(validation)),
-            qlog_dir: None,
+            output_scheduler: None,
+            qlog_output: None,
+            qlog_settings: QlogSettings::default(),
             ech_config: None,
+            key_update_policy: KeyUpdatePolicy::default(),
+            keep_alive: None,
+            max_handshake_packets: u32::MAX,
+            post_close_policy: PostClosePolicy::default(),
+            closed: HashMap::default(),
+            sweep_needed: false,
+            dscp: IpTosDscp::default(),
+            stream_filter: None,
+            zero_rtt_buffer: VecDeque::new(),
+            zero_rtt_buffer_limit: 0,
+            zero_rtt_buffer_age: Duration::ZERO,
+            shutting_down: false,
+            max_token_length: DEFAULT_MAX_TOKEN_LENGTH,
+            retry_min_datagram_size: MIN_INITIAL_PACKET_SIZE,
+            on_version_negotiation: None,
+            on_cid_exhaustion: None,
+            on_address_validated: None,
+            cid_issuance_rate: 0,
+            clock: Rc::new(Cell::new(now)),
+            qlog: NeqoQlog::disabled(),
+            dropped: EnumMap::default(),
+            last_drop: None,
             wake_at: None,
+            routed: None,
         })
     }
 
     /// Set or clear directory to create logs of connection events in QLOG format.
     pub fn set_qlog_dir(&mut self, dir: Option<PathBuf>) {
-        self.qlog_dir = dir;
+        self.qlog_output = dir.map(QlogOutput::Dir);
+    }
+
+    /// Write logs of connection events in QLOG format to writers that are
+    /// produced by `f`, which is called with the original destination
+    /// connection ID of each new connection.  This replaces any directory
+    /// set with `set_qlog_dir`.
+    pub fn set_qlog_writer_factory(
+        &mut self,
+        f: Box<dyn FnMut(ConnectionIdRef<'_>) -> Box<dyn Write + Send + Sync>>,
+    ) {
+        self.qlog_output = Some(QlogOutput::Writer(f));
+    }
+
+    /// Set the scheduler that decides which connection sends next when several
+    /// have something to send.  By default, connections take turns.
+    pub fn set_output_scheduler(&mut self, s: Box<dyn OutputScheduler>) {
+        self.output_scheduler = Some(s);
+    }
+
+    /// Set how qlog traces are serialized.  This also determines the extension
+    /// of files created in the directory set with `set_qlog_dir`.
+    /// The default is JSON-SEQ.
+    pub fn set_qlog_format(&mut self, format: QlogFormat) {
+        self.qlog_settings.format = format;
+    }
+
+    /// Set the version of qlog that traces follow.  The default is qlog 0.3.
+    pub fn set_qlog_version(&mut self, version: QlogVersion) {
+        self.qlog_settings.version = version;
+    }
+
+    /// Limit the size of each qlog trace.  Traces written to `set_qlog_dir` can
+    /// be rotated; those written to a writer from `set_qlog_writer_factory` stop
+    /// at the limit.  This has no effect on traces in the JSON format.
+    pub fn set_qlog_limits(&mut self, limits: QlogLimits) {
+        self.qlog_settings.limits = limits;
     }
 
     /// Set the policy for address validation.
@@ -239,12 +591,261 @@ impl Server {
         self.address_validation.borrow_mut().set_validation(v);
     }
 
-    /// Set the cipher suites that should be used.  Set an empty value to use
-    /// default values.
+    /// Set how long a Retry token remains valid.  Shorter lifetimes limit how
+    /// long a token can be replayed, while longer lifetimes accommodate clients
+    /// on slow paths.  The default is 5 seconds.
+    pub fn set_retry_token_lifetime(&mut self, d: Duration) {
+        self.address_validation
+            .borrow_mut()
+            .set_retry_token_lifetime(d);
+    }
+
+    /// Set the ALPN values, in order of preference, for connections where the
+    /// client's first Initial packet uses `version`.  Other connections use the
+    /// values that the server was created with.  This only affects connections
+    /// that are accepted afterwards.
+    pub fn set_protocols_for_version(&mut self, version: Version, protocols: &[impl AsRef<str>]) {
+        self.version_protocols.insert(
+            version,
+            protocols.iter().map(|x| String::from(x.as_ref())).collect(),
+        );
+    }
+
+    /// Set the cipher suites that should be used, in order of preference.  Set an
+    /// empty value to use default values.  This can be called at any time, but only
+    /// connections that are accepted afterwards use the new value.
     pub fn set_ciphers(&mut self, ciphers: impl AsRef<[Cipher]>) {
         self.ciphers = Vec::from(ciphers.as_ref());
     }
 
+    /// The cipher suites that new connections use.  If this is empty, the defaults are used.
+    #[must_use]
+    pub fn ciphers(&self) -> &[Cipher] {
+        &self.ciphers
+    }
+
+    /// Require clients to authenticate with a certificate that chains to one of
+    /// the certificate authorities with the given nicknames.  Handshakes fail if
+    /// the client has no certificate or if it can't be validated.  An empty value
+    /// stops asking clients for certificates.  Only connections that are accepted
+    /// afterwards are affected.
+    pub fn require_client_auth(&mut self, cas: &[String]) {
+        self.client_auth_cas = Vec::from(cas);
+    }
+
+    /// Let clients authenticate the handshake with an external pre-shared key.
+    /// A client that offers `identity` and has the same key completes the
+    /// handshake without the server presenting a certificate; other clients
+    /// are unaffected.  A server has at most one key, so this replaces any key
+    /// that was set before.  Only connections that are accepted afterwards are affected.
+    pub fn set_external_psk(&mut self, identity: &[u8], key: &[u8], cipher: Cipher) {
+        let old = self.external_psk.replace(ExternalPsk {
+            identity: identity.to_vec(),
+            key: key.to_vec(),
+            cipher,
+        });
+        if let Some(old) = old {
+            qdebug!([self], "Replacing external PSK {}", hex(&old.identity));
+        }
+    }
+
+    /// Make every connection do a full handshake.  Clients are not sent session
+    /// tickets and any ticket that a client presents is ignored, so neither
+    /// resumption nor 0-RTT is possible.  Only connections that are accepted
+    /// afterwards are affected.
+    pub fn disable_resumption(&mut self) {
+        self.resumption_disabled = true;
+    }
+
+    /// Have new connections update their 1-RTT keys once they have sent
+    /// `after_packets` packets or `after_bytes` bytes with the same keys.
+    /// `None` for both values disables this and keys are only updated when
+    /// they approach their usage limits.
+    pub fn set_key_update_policy(&mut self, after_packets: Option<u64>, after_bytes: Option<u64>) {
+        self.key_update_policy = KeyUpdatePolicy {
+            after_packets,
+            after_bytes,
+        };
+    }
+
+    /// Have new connections send a PING when they are otherwise idle for `interval`,
+    /// which keeps NAT bindings for quiet connections alive and resets their idle
+    /// timeout.  See `Connection::set_keep_alive`.
+    pub fn set_keep_alive(&mut self, interval: Option<Duration>) {
+        self.keep_alive = interval;
+    }
+
+    /// Abandon any connection attempt that receives more than `n` datagrams
+    /// without completing the handshake.  Abandoned attempts are removed without
+    /// sending anything to the peer.  This bounds the amount of work that
+    /// a client can cause with a single connection attempt.
+    pub fn set_max_handshake_packets(&mut self, n: u32) {
+        self.max_handshake_packets = n;
+    }
+
+    /// Set how to respond to packets for connections that have closed.
+    /// Connections only have a stateless reset token for their handshake
+    /// connection ID if this is set to `StatelessReset` before they are created.
+    pub fn set_post_close_policy(&mut self, policy: PostClosePolicy) {
+        self.post_close_policy = policy;
+        self.conn_params = self
+            .conn_params
+            .clone()
+            .reset_token(policy == PostClosePolicy::StatelessReset);
+        if policy == PostClosePolicy::Drop {
+            self.closed.clear();
+        }
+    }
+
+    /// Set the anti-amplification factor for new connections, which limits what
+    /// they send to an address before it is validated to `factor` times what
+    /// was received from that address.  The default is 3.
+    /// See `ConnectionParameters::amplification_factor`.
+    pub fn set_amplification_factor(&mut self, factor: u8) {
+        self.conn_params = self.conn_params.clone().amplification_factor(factor);
+    }
+
+    /// Use the latency spin bit on new connections.  When this is disabled, which
+    /// is the default, each connection sends a random value that never changes.
+    pub fn set_spin_bit_enabled(&mut self, enabled: bool) {
+        self.conn_params = self.conn_params.clone().spin_bit(enabled);
+    }
+
+    /// Offer experimental multipath support on new connections.  When a client also
+    /// offers it, datagrams that arrive from a new address add an active path to the
+    /// connection, up to `max_paths` paths, rather than being treated as a migration.
+    /// See `ConnectionParameters::multipath`.
+    pub fn set_multipath(&mut self, max_paths: u8) {
+        self.conn_params = self.conn_params.clone().multipath(max_paths);
+    }
+
+    /// Limit how much stream data each new connection buffers before it is sent to
+    /// `packets` full-sized packets.  Writes beyond that accept nothing until the
+    /// connection has sent some of that data.
+    /// See `ConnectionParameters::max_output_queue`.
+    pub fn set_max_output_queue(&mut self, packets: usize) {
+        self.conn_params = self.conn_params.clone().max_output_queue(packets);
+    }
+
+    /// Set the DSCP value for datagrams that the server sends without a connection:
+    /// Retry, Version Negotiation, and stateless resets.
+    /// Use `Connection::set_dscp` to mark datagrams for established connections.
+    pub fn set_dscp(&mut self, dscp: IpTosDscp) {
+        self.dscp = dscp;
+    }
+
+    /// Set a function that decides whether to accept each stream that a peer opens.
+    /// The application never sees streams that are refused; the server resets them
+    /// and asks the peer to stop sending.  This only applies to connections that
+    /// are created after this is set.
+    pub fn set_stream_filter(
+        &mut self,
+        filter: Box<dyn FnMut(&ActiveConnectionRef, StreamId) -> bool>,
+    ) {
+        self.stream_filter = Some(filter);
+    }
+
+    /// Buffer up to `max_datagrams` 0-RTT datagrams that arrive before the Initial
+    /// that creates their connection, as can happen when packets are reordered.
+    /// Buffered datagrams are processed when the Initial arrives, provided that
+    /// happens within `max_age`.  Once the buffer is full, 0-RTT datagrams for
+    /// unknown connections are dropped.  By default, nothing is buffered.
+    pub fn set_zero_rtt_buffer(&mut self, max_datagrams: usize, max_age: Duration) {
+        self.zero_rtt_buffer_limit = max_datagrams;
+        self.zero_rtt_buffer_age = max_age;
+        self.zero_rtt_buffer.truncate(max_datagrams);
+    }
+
+    /// Drop any Initial packet that carries a token longer than `len` bytes.
+    /// These packets are dropped before any attempt is made to validate the token.
+    /// The default is 512 bytes.
+    pub fn set_max_token_length(&mut self, len: usize) {
+        self.max_token_length = len;
+    }
+
+    /// Only send a Retry in response to Initial packets in datagrams of at least `min`
+    /// bytes.  Smaller datagrams that would otherwise get a Retry are dropped, which
+    /// avoids the cost of a Retry for scanners that send minimum-sized datagrams.
+    /// Values below the minimum size for a client Initial have no effect.
+    pub fn set_retry_only_for_larger_initials(&mut self, min: usize) {
+        self.retry_min_datagram_size = min;
+    }
+
+    /// Set a function that is called each time a Version Negotiation packet is sent.
+    /// It is passed the unsupported version that the client used and the client's address.
+    pub fn set_on_version_negotiation(&mut self, f: Box<dyn FnMut(u32, SocketAddr)>) {
+        self.on_version_negotiation = Some(f);
+    }
+
+    /// Set a function that is called when the connection ID generator fails to
+    /// produce a connection ID for a new connection or a Retry.  The Initial
+    /// packet that needed the connection ID is dropped, as it is when the server
+    /// is shutting down, so the client will eventually give up.
+    pub fn set_on_cid_exhaustion(&mut self, f: Box<dyn FnMut()>) {
+        self.on_cid_exhaustion = Some(f);
+    }
+
+    /// Set a function that is called when a client's address is first validated,
+    /// either by a Retry token or by the handshake.  After that, the address is
+    /// known not to be spoofed.  It is called once for each connection, with the
+    /// connection and the validated address.
+    pub fn set_on_address_validated(
+        &mut self,
+        f: Box<dyn FnMut(&ActiveConnectionRef, SocketAddr)>,
+    ) {
+        self.on_address_validated = Some(f);
+    }
+
+    /// Limit how quickly new connection IDs are issued to each connection, so that
+    /// peers that retire connection IDs rapidly can't churn the routing table.
+    /// Connections can hold a full set of connection IDs, but after that they only
+    /// get `per_sec` replacements each second; further replacements are deferred.
+    /// Zero, the default, removes the limit.  Only connections that are accepted
+    /// afterwards are affected.
+    pub fn set_cid_issuance_rate(&mut self, per_sec: u32) {
+        self.cid_issuance_rate = per_sec;
+    }
+
+    /// Set a trace for events that happen outside of any connection, such as
+    /// datagrams that are dropped before a connection is found for them.
+    /// This is separate from the traces that are made for each connection.
+    pub fn set_server_qlog(&mut self, qlog: NeqoQlog) {
+        self.qlog = qlog;
+    }
+
+    /// The anti-replay window that 0-RTT is checked against, as of the last call
+    /// to `process`.  This can be compared with ticket lifetimes.
+    #[must_use]
+    pub fn anti_replay_window(&self) -> AntiReplayWindow {
+        self.anti_replay.bounds(self.clock.get())
+    }
+
+    /// The number of datagrams that were dropped for the given reason.
+    #[must_use]
+    pub fn dropped(&self, reason: DropReason) -> usize {
+        self.dropped[reason]
+    }
+
+    fn drop_datagram(&mut self, dgram: &Datagram, reason: DropReason) -> Option<Datagram> {
+        qdebug!([self], "Drop datagram: {:?}", reason);
+        self.dropped[reason] += 1;
+        self.last_drop = Some(reason);
+        crate::qlog::server_datagram_dropped(
+            &mut self.qlog,
+            &dgram[..],
+            self.cid_generator.borrow().as_decoder(),
+            reason,
+        );
+        None
+    }
+
+    fn cid_exhausted(&mut self) {
+        qerror!([self], "Connection IDs exhausted");
+        if let Some(f) = &mut self.on_cid_exhaustion {
+            f();
+        }
+    }
+
     /// # Errors
     /// When the configuration is invalid.
     pub fn enable_ech(
@@ -270,9 +871,41 @@ impl Server {
         now: Instant,
     ) -> Option<Datagram> {
         qtrace!([self], "Process connection {:?}", c);
-        let out = c.borrow_mut().process(dgram, now);
+        if dgram.is_some() && *c.borrow().state() <= State::Handshaking {
+            let mut conn = c.borrow_mut();
+            conn.handshake_packets = conn.handshake_packets.saturating_add(1);
+            if conn.handshake_packets > self.max_handshake_packets {
+                drop(conn);
+                self.abandon(c);
+                return None;
+            }
+        }
+        if dgram.is_some() {
+            self.routed = Some(Rc::clone(c));
+        }
+        let paused = c.borrow().paused;
+        // Datagrams that were held while paused are sent before any new ones.
+        let held = if paused {
+            None
+        } else {
+            c.borrow_mut().held.pop_front()
+        };
+        let out = if self.stream_filter.is_some() || held.is_some() {
+            // Check new streams before generating output,
+            // so that any refusal is sent straight away.
+            if let Some(d) = dgram {
+                c.borrow_mut().process_input(d, now);
+            }
+            self.filter_streams(c);
+            held.map_or_else(|| c.borrow_mut().process_output(now), Output::Datagram)
+        } else {
+            c.borrow_mut().process(dgram, now)
+        };
         match out {
-            Output::Datagram(_) => {
+            Output::Datagram(ref d) => {
+                if matches!(c.borrow().state(), State::Closing { .. }) {
+                    c.borrow_mut().close = Some(d.clone());
+                }
                 qtrace!([self], "Sending packet, added to waiting connections");
                 self.waiting.push_back(Rc::clone(c));
             }
@@ -285,10 +918,8 @@ impl Server {
             }
             Output::None => {}
         }
-        if c.borrow().has_events() {
-            qtrace!([self], "Connection active: {:?}", c);
-            self.active.insert(ActiveConnectionRef { c: Rc::clone(c) });
-        }
+        self.check_address_validated(c);
+        self.note_activity(c, now);
 
         if *c.borrow().state() > State::Handshaking {
             // Remove any active connection attempt now that this is no longer handshaking.
@@ -299,13 +930,164 @@ impl Server {
 
         if matches!(c.borrow().state(), State::Closed(_)) {
             c.borrow_mut().set_qlog(NeqoQlog::disabled());
-            self.connections
-                .borrow_mut()
-                .retain(|_, v| !Rc::ptr_eq(v, c));
+            self.sweep_needed = true;
+        }
+        if paused {
+            // The connection runs its timers as usual, but what it sends
+            // is held until sending resumes.
+            if let Some(d) = out.dgram() {
+                qtrace!([self], "Connection paused, holding datagram: {:?}", c);
+                c.borrow_mut().held.push_back(d);
+            }
+            return None;
         }
         out.dgram()
     }
 
+    /// Remove closed connections from the connection table, keeping what is needed
+    /// to respond to packets for them.  This only scans the table if a connection
+    /// closed since the last sweep.
+    fn sweep(&mut self, now: Instant) -> usize {
+        if !self.sweep_needed {
+            return 0;
+        }
+        self.sweep_needed = false;
+        self.closed.retain(|_, closed| closed.expires > now);
+
+        let mut removed: Vec<(StateRef, Vec<ConnectionId>)> = Vec::new();
+        self.connections.borrow_mut().retain(|cid, c| {
+            if !matches!(c.borrow().state(), State::Closed(_)) {
+                return true;
+            }
+            if let Some((_, cids)) = removed.iter_mut().find(|(r, _)| Rc::ptr_eq(r, c)) {
+                cids.push(cid.clone());
+            } else {
+                removed.push((Rc::clone(c), vec![cid.clone()]));
+            }
+            false
+        });
+        for (c, cids) in &removed {
+            self.retain_closed(c, cids, now);
+        }
+        removed.len()
+    }
+
+    /// Remove connections that have closed from the connection table now, rather
+    /// than at the end of the next call to `process`.  Returns the number of
+    /// connections that were removed.
+    pub fn sweep_closed(&mut self) -> usize {
+        self.sweep_needed = true;
+        self.sweep(self.clock.get())
+    }
+
+    /// A client that did not present a Retry token has its address validated
+    /// when the handshake completes.
+    fn check_address_validated(&mut self, c: &StateRef) {
+        let addr = {
+            let conn = c.borrow();
+            if conn.address_validated || !conn.state().connected() {
+                return;
+            }
+            conn.peer_address()
+        };
+        if let Some(addr) = addr {
+            self.address_validated(c, addr);
+        }
+    }
+
+    /// Tell the application, once for each connection, that the address of the client
+    /// has been validated.
+    fn address_validated(&mut self, c: &StateRef, addr: SocketAddr) {
+        c.borrow_mut().address_validated = true;
+        qdebug!([self], "Address {} validated for {:?}", addr, c);
+        if let Some(f) = &mut self.on_address_validated {
+            f(&ActiveConnectionRef { c: Rc::clone(c) }, addr);
+        }
+    }
+
+    /// Mark the connection as active if it has events for the application.
+    fn note_activity(&mut self, c: &StateRef, now: Instant) {
+        if c.borrow().has_events() {
+            qtrace!([self], "Connection active: {:?}", c);
+            c.borrow_mut().last_activity = now;
+            self.active.insert(ActiveConnectionRef { c: Rc::clone(c) });
+        }
+    }
+
+    /// Keep what is needed to respond to packets for a closed connection,
+    /// which used the connection IDs in `cids`.
+    fn retain_closed(&mut self, c: &StateRef, cids: &[ConnectionId], now: Instant) {
+        let replay = match self.post_close_policy {
+            PostClosePolicy::Drop => return,
+            PostClosePolicy::StatelessReset => None,
+            PostClosePolicy::ReplayClose { count } => c.borrow_mut().close.take().map(|close| {
+                Rc::new(RefCell::new(ReplayClose {
+                    close,
+                    remaining: count,
+                }))
+            }),
+        };
+        let conn = c.borrow();
+        let expires = now + CLOSED_CONNECTION_RETENTION;
+        for cid in cids {
+            let reset_token = conn
+                .reset_tokens()
+                .find_map(|(id, srt)| (id == cid).then_some(*srt));
+            self.closed.insert(
+                cid.clone(),
+                ClosedConnection {
+                    replay: replay.clone(),
+                    reset_token,
+                    expires,
+                },
+            );
+        }
+    }
+
+    /// Respond to a packet for a connection that has closed, if the policy allows.
+    fn handle_closed(
+        &self,
+        dcid: ConnectionIdRef,
+        dgram: &Datagram,
+        now: Instant,
+    ) -> Option<Datagram> {
+        let closed = self.closed.get(&dcid[..])?;
+        if closed.expires <= now {
+            return None;
+        }
+        match self.post_close_policy {
+            PostClosePolicy::Drop => None,
+            PostClosePolicy::StatelessReset => {
+                qdebug!([self], "Send stateless reset for closed connection");
+                stateless_reset(closed.reset_token.as_ref()?, dgram, self.dscp.into())
+            }
+            PostClosePolicy::ReplayClose { .. } => {
+                let mut replay = closed.replay.as_ref()?.borrow_mut();
+                // Only send to the address that the close was originally sent to.
+                if replay.remaining == 0 || replay.close.destination() != dgram.source() {
+                    return None;
+                }
+                qdebug!([self], "Replay CONNECTION_CLOSE for closed connection");
+                replay.remaining -= 1;
+                Some(replay.close.clone())
+            }
+        }
+    }
+
+    /// Remove all state for a connection attempt without notifying the peer.
+    fn abandon(&mut self, c: &StateRef) {
+        qinfo!([self], "Abandoning connection attempt {:?}", c);
+        if let Some(k) = c.borrow_mut().active_attempt.take() {
+            self.active_attempts.remove(&k);
+        }
+        c.borrow_mut().set_qlog(NeqoQlog::disabled());
+        self.connections
+            .borrow_mut()
+            .retain(|_, v| !Rc::ptr_eq(v, c));
+        self.waiting.retain(|w| !Rc::ptr_eq(w, c));
+        self.active.retain(|a| !Rc::ptr_eq(&a.c, c));
+    }
+
     fn connection(&self, cid: ConnectionIdRef) -> Option<StateRef> {
         self.connections.borrow().get(&cid[..]).cloned()
     }
@@ -322,12 +1104,15 @@ impl Server {
             .borrow()
             .validate(&initial.token, dgram.source(), now);
         match res {
-            AddressValidationResult::Invalid => None,
+            AddressValidationResult::Invalid => self.drop_datagram(dgram, DropReason::InvalidToken),
             AddressValidationResult::Pass => self.connection_attempt(initial, dgram, None, now),
             AddressValidationResult::ValidRetry(orig_dcid) => {
                 self.connection_attempt(initial, dgram, Some(orig_dcid), now)
             }
             AddressValidationResult::Validate => {
+                if dgram.len() < self.retry_min_datagram_size {
+                    return self.drop_datagram(dgram, DropReason::RetryTooShort);
+                }
                 qinfo!([self], "Send retry for {:?}", initial.dst_cid);
 
                 let res = self.address_validation.borrow().generate_retry_token(
@@ -339,7 +1124,7 @@ impl Server {
                     qerror!([self], "unable to generate token, dropping packet");
                     return None;
                 };
-                if let Some(new_dcid) = self.cid_generator.borrow_mut().generate_cid() {
+                if let Ok(new_dcid) = generate_checked_cid(&mut *self.cid_generator.borrow_mut()) {
                     let packet = PacketBuilder::retry(
                         initial.version,
                         &initial.src_cid,
@@ -351,7 +1136,7 @@ impl Server {
                         let retry = Datagram::new(
                             dgram.destination(),
                             dgram.source(),
-                            dgram.tos(),
+                            self.dscp.into(),
                             dgram.ttl(),
                             p,
                         );
@@ -362,6 +1147,7 @@ impl Server {
                     }
                 } else {
                     qerror!([self], "no connection ID for retry, dropping packet");
+                    self.cid_exhausted();
                     None
                 }
             }
@@ -376,7 +1162,7 @@ impl Server {
         now: Instant,
     ) -> Option<Datagram> {
         let attempt_key = AttemptKey {
-            remote_address: dgram.source(),
+            remote_address: canonical_address(dgram.source()),
             odcid: orig_dcid.as_ref().unwrap_or(&initial.dst_cid).clone(),
         };
         if let Some(c) = self.active_attempts.get(&attempt_key) {
@@ -392,53 +1178,51 @@ impl Server {
         }
     }
 
-    fn create_qlog_trace(&self, odcid: ConnectionIdRef<'_>) -> NeqoQlog {
-        if let Some(qlog_dir) = &self.qlog_dir {
-            let mut qlog_path = qlog_dir.clone();
+    fn open_qlog_file(
+        qlog_dir: &Path,
+        odcid: ConnectionIdRef<'_>,
+        settings: QlogSettings,
+    ) -> Option<(Box<dyn Write + Send + Sync>, PathBuf)> {
+        let qlog_path = qlog_dir.join(settings.path(odcid));
+
+        // The original DCID is chosen by the client.  This doesn't overwrite
+        // existing files, which prevents attackers from overwriting existing logs.
+        match settings.limits().create_file(&qlog_path) {
+            Ok(f) => {
+                qinfo!("Qlog output to {}", qlog_path.display());
+                Some((f, qlog_path))
+            }
+            Err(e) => {
+                qerror!(
+                    "Could not open file {} for qlog output: {}",
+                    qlog_path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
 
-            qlog_path.push(format!("{odcid}.qlog"));
+    fn create_qlog_trace(&mut self, odcid: ConnectionIdRef<'_>) -> NeqoQlog {
+        let settings = self.qlog_settings;
+        let (writer, qlog_path) = match &mut self.qlog_output {
+            None => return NeqoQlog::disabled(),
+            Some(QlogOutput::Dir(dir)) => match Self::open_qlog_file(dir, odcid, settings) {
+                Some(output) => output,
+                None => return NeqoQlog::disabled(),
+            },
+            Some(QlogOutput::Writer(f)) => {
+                (settings.limits().writer(f(odcid)), settings.path(odcid))
+            }
+        };
 
-            // The original DCID is chosen by the client. Using create_new()
-            // prevents attackers from overwriting existing logs.
-            match OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(&qlog_path)
-            {
-                Ok(f) => {
-                    qinfo!("Qlog output to {}", qlog_path.display());
-
-                    let streamer = QlogStreamer::new(
-                        qlog::QLOG_VERSION.to_string(),
-                        Some("Neqo server qlog".to_string()),
-                        Some("Neqo server qlog".to_string()),
-                        None,
-                        std::time::Instant::now(),
-                        common::qlog::new_trace(Role::Server),
-                        qlog::events::EventImportance::Base,
-                        Box::new(f),
-                    );
-                    let n_qlog = NeqoQlog::enabled(streamer, qlog_path);
-                    match n_qlog {
-                        Ok(nql) => nql,
-                        Err(e) => {
-                            // Keep going but w/o qlogging
-                            qerror!("NeqoQlog error: {}", e);
-                            NeqoQlog::disabled()
-                        }
-                    }
-                }
-                Err(e) => {
-                    qerror!(
-                        "Could not open file {} for qlog output: {}",
-                        qlog_path.display(),
-                        e
-                    );
-                    NeqoQlog::disabled()
-                }
+        match settings.trace(writer, qlog_path) {
+            Ok(nql) => nql,
+            Err(e) => {
+                // Keep going but w/o qlogging
+                qerror!("NeqoQlog error: {}", e);
+                NeqoQlog::disabled()
             }
-        } else {
-            NeqoQlog::disabled()
         }
     }
 
@@ -449,15 +1233,41 @@ impl Server {
         initial: InitialDetails,
         orig_dcid: Option<ConnectionId>,
     ) {
-        let zcheck = self.zero_rtt_checker.clone();
-        if c.server_enable_0rtt(&self.anti_replay, zcheck).is_err() {
-            qwarn!([self], "Unable to enable 0-RTT");
+        if self.resumption_disabled {
+            if c.server_disable_resumption().is_err() {
+                qwarn!([self], "Unable to disable resumption");
+            }
+        } else {
+            let zcheck = self.zero_rtt_checker.clone();
+            if c.server_enable_0rtt(&self.anti_replay, zcheck).is_err() {
+                qwarn!([self], "Unable to enable 0-RTT");
+            }
         }
         if let Some(odcid) = orig_dcid {
             // There was a retry, so set the connection IDs for.
             c.set_retry_cids(&odcid, initial.src_cid, &initial.dst_cid);
         }
         c.set_validation(&self.address_validation);
+        if !self.ciphers.is_empty() && c.set_ciphers(&self.ciphers).is_err() {
+            qwarn!([self], "Unable to set ciphers");
+        }
+        if !self.client_auth_cas.is_empty()
+            && c.server_require_client_auth(&self.client_auth_cas).is_err()
+        {
+            qwarn!([self], "Unable to configure client authentication");
+        }
+        if let Some(psk) = &self.external_psk {
+            if c.add_external_psk(&psk.identity, &psk.key, psk.cipher)
+                .is_err()
+            {
+                qwarn!([self], "Unable to add external PSK");
+            }
+        }
+        c.set_key_update_policy(
+            self.key_update_policy.after_packets,
+            self.key_update_policy.after_bytes,
+        );
+        c.set_keep_alive(self.keep_alive);
         c.set_qlog(self.create_qlog_trace(attempt_key.odcid.as_cid_ref()));
         if let Some(cfg) = &self.ech_config {
             if c.server_enable_ech(cfg.config, &cfg.public_name, &cfg.sk, &cfg.pk)
@@ -468,6 +1278,22 @@ impl Server {
         }
     }
 
+    /// Ask the stream filter about any streams that the peer opened.
+    fn filter_streams(&mut self, c: &StateRef) {
+        let Some(filter) = self.stream_filter.as_mut() else {
+            return;
+        };
+        let new_streams = c.borrow_mut().take_new_streams();
+        let conn = ActiveConnectionRef { c: Rc::clone(c) };
+        for stream_id in new_streams {
+            if !filter(&conn, stream_id) {
+                qdebug!([self], "Refusing stream {} on {:?}", stream_id, c);
+                c.borrow_mut()
+                    .refuse_stream(stream_id, Error::StreamStateError.code());
+            }
+        }
+    }
+
     fn accept_connection(
         &mut self,
         attempt_key: AttemptKey,
@@ -485,38 +1311,80 @@ impl Server {
             cid_generator: Rc::clone(&self.cid_generator),
             connections: Rc::clone(&self.connections),
             saved_cids: Vec::new(),
+            rate_limit: (self.cid_issuance_rate > 0)
+                .then(|| CidRateLimit::new(self.cid_issuance_rate, Rc::clone(&self.clock))),
         }));
 
         let mut params = self.conn_params.clone();
         params.get_versions_mut().set_initial(initial.version);
-        let sconn = Connection::new_server(
-            &self.certs,
-            &self.protocols,
-            Rc::clone(&cid_mgr) as _,
-            params,
-        );
+        let protocols = self
+            .version_protocols
+            .get(&initial.version)
+            .unwrap_or(&self.protocols);
+        let sconn =
+            Connection::new_server(&self.certs, protocols, Rc::clone(&cid_mgr) as _, params);
 
         match sconn {
             Ok(mut c) => {
+                let client_initial_scid = initial.src_cid.clone();
+                let retried = orig_dcid.is_some();
                 self.setup_connection(&mut c, &attempt_key, initial, orig_dcid);
+                if self.stream_filter.is_some() {
+                    c.track_new_streams();
+                }
                 let c = Rc::new(RefCell::new(ServerConnectionState {
                     c,
                     wake_at: None,
                     active_attempt: Some(attempt_key.clone()),
+                    original_dcid: attempt_key.odcid.clone(),
+                    client_initial_scid,
+                    last_activity: now,
+                    paused: false,
+                    resumed: false,
+                    held: VecDeque::new(),
+                    priority: 1,
+                    handshake_packets: 0,
+                    close: None,
+                    address_validated: false,
+                    qlog_settings: self.qlog_settings,
                 }));
                 cid_mgr.borrow_mut().set_connection(&c);
+                if retried {
+                    // The Retry token proves that the client can receive at this address.
+                    self.address_validated(&c, dgram.source());
+                }
+                let buffered = self.take_buffered_0rtt(&attempt_key, now);
                 let previous_attempt = self.active_attempts.insert(attempt_key, Rc::clone(&c));
                 debug_assert!(previous_attempt.is_none());
-                self.process_connection(&c, Some(dgram), now)
+                let out = self.process_connection(&c, Some(dgram), now);
+                if !buffered.is_empty() {
+                    qdebug!([self], "Replay {} buffered 0-RTT datagrams", buffered.len());
+                    c.borrow_mut().process_multiple_input(buffered.iter(), now);
+                    self.note_activity(&c, now);
+                    // Have the connection respond to the 0-RTT.
+                    if !self.waiting.iter().any(|w| Rc::ptr_eq(w, &c)) {
+                        self.waiting.push_back(Rc::clone(&c));
+                    }
+                }
+                out
             }
             Err(e) => {
                 qwarn!([self], "Unable to create connection");
-                if e == crate::Error::VersionNegotiation {
-                    crate::qlog::server_version_information_failed(
-                        &mut self.create_qlog_trace(attempt_key.odcid.as_cid_ref()),
-                        self.conn_params.get_versions().all(),
-                        initial.version.wire_version(),
-                    );
+                match e {
+                    crate::Error::VersionNegotiation => {
+                        crate::qlog::server_version_information_failed(
+                            &mut self.create_qlog_trace(attempt_key.odcid.as_cid_ref()),
+                            self.conn_params.get_versions().all(),
+                            initial.version.wire_version(),
+                        );
+                    }
+                    crate::Error::ConnectionIdsExhausted => {
+                        // Without a connection ID, nothing can be routed to the connection.
+                        // Drop the attempt entirely, including any 0-RTT that was held for it.
+                        mem::drop(self.take_buffered_0rtt(&attempt_key, now));
+                        self.cid_exhausted();
+                    }
+                    _ => {}
                 }
                 None
             }
@@ -533,7 +1401,7 @@ impl Server {
         now: Instant,
     ) -> Option<Datagram> {
         let attempt_key = AttemptKey {
-            remote_address: dgram.source(),
+            remote_address: canonical_address(dgram.source()),
             odcid: dcid,
         };
         if let Some(c) = self.active_attempts.get(&attempt_key) {
@@ -545,11 +1413,41 @@ impl Server {
             let c = Rc::clone(c);
             self.process_connection(&c, Some(dgram), now)
         } else {
-            qdebug!([self], "Dropping 0-RTT for unknown connection");
-            None
+            self.expire_buffered_0rtt(now);
+            if self.zero_rtt_buffer.len() < self.zero_rtt_buffer_limit {
+                qdebug!([self], "Buffering 0-RTT for unknown connection");
+                self.zero_rtt_buffer.push_back(BufferedZeroRtt {
+                    attempt_key,
+                    dgram: dgram.clone(),
+                    received: now,
+                });
+                None
+            } else {
+                self.drop_datagram(dgram, DropReason::UnknownConnection)
+            }
         }
     }
 
+    fn expire_buffered_0rtt(&mut self, now: Instant) {
+        let max_age = self.zero_rtt_buffer_age;
+        self.zero_rtt_buffer.retain(|b| b.received + max_age > now);
+    }
+
+    /// Remove and return any buffered 0-RTT datagrams for a new connection.
+    fn take_buffered_0rtt(&mut self, attempt_key: &AttemptKey, now: Instant) -> Vec<Datagram> {
+        self.expire_buffered_0rtt(now);
+        let mut taken = Vec::new();
+        self.zero_rtt_buffer.retain(|b| {
+            if b.attempt_key == *attempt_key {
+                taken.push(b.dgram.clone());
+                false
+            } else {
+                true
+            }
+        });
+        taken
+    }
+
     fn process_input(&mut self, dgram: &Datagram, now: Instant) -> Option<Datagram> {
         qtrace!("Process datagram: {}", hex(&dgram[..]));
 
@@ -557,8 +1455,7 @@ impl Server {
         // All packets in the datagram are routed to the same connection.
         let res = PublicPacket::decode(&dgram[..], self.cid_generator.borrow().as_decoder());
         let Ok((packet, _remainder)) = res else {
-            qtrace!([self], "Discarding {:?}", dgram);
-            return None;
+            return self.drop_datagram(dgram, DropReason::Undecodable);
         };
 
         // Finding an existing connection. Should be the most common case.
@@ -567,9 +1464,10 @@ impl Server {
         }
 
         if packet.packet_type() == PacketType::Short {
-            // TODO send a stateless reset here.
             qtrace!([self], "Short header packet for an unknown connection");
-            return None;
+            return self
+                .handle_closed(packet.dcid(), dgram, now)
+                .or_else(|| self.drop_datagram(dgram, DropReason::UnknownConnection));
         }
 
         if packet.packet_type() == PacketType::OtherVersion
@@ -581,8 +1479,7 @@ impl Server {
                     .contains(&packet.version().unwrap()))
         {
             if dgram.len() < MIN_INITIAL_PACKET_SIZE {
-                qdebug!([self], "Unsupported version: too short");
-                return None;
+                return self.drop_datagram(dgram, DropReason::UnsupportedVersion);
             }
 
             qdebug!([self], "Unsupported version: {:x}", packet.wire_version());
@@ -598,11 +1495,14 @@ impl Server {
                 self.conn_params.get_versions().all(),
                 packet.wire_version(),
             );
+            if let Some(f) = &mut self.on_version_negotiation {
+                f(packet.wire_version(), dgram.source());
+            }
 
             return Some(Datagram::new(
                 dgram.destination(),
                 dgram.source(),
-                dgram.tos(),
+                self.dscp.into(),
                 dgram.ttl(),
                 vn,
             ));
@@ -611,8 +1511,13 @@ impl Server {
         match packet.packet_type() {
             PacketType::Initial => {
                 if dgram.len() < MIN_INITIAL_PACKET_SIZE {
-                    qdebug!([self], "Drop initial: too short");
-                    return None;
+                    return self.drop_datagram(dgram, DropReason::InitialTooShort);
+                }
+                if self.shutting_down {
+                    return self.drop_datagram(dgram, DropReason::ShuttingDown);
+                }
+                if packet.token().len() > self.max_token_length {
+                    return self.drop_datagram(dgram, DropReason::TokenTooLong);
                 }
                 // Copy values from `packet` because they are currently still borrowing from
                 // `dgram`.
@@ -626,16 +1531,33 @@ impl Server {
             PacketType::OtherVersion => unreachable!(),
             _ => {
                 qtrace!([self], "Not an initial packet");
-                None
+                self.drop_datagram(dgram, DropReason::UnknownConnection)
             }
         }
     }
 
+    /// Take the next connection from `waiting`, as chosen by the output scheduler.
+    fn next_waiting(&mut self) -> Option<StateRef> {
+        let Some(scheduler) = self.output_scheduler.as_mut() else {
+            return self.waiting.pop_front();
+        };
+        if self.waiting.is_empty() {
+            return None;
+        }
+        let waiting = self
+            .waiting
+            .iter()
+            .map(|c| ActiveConnectionRef { c: Rc::clone(c) })
+            .collect::<Vec<_>>();
+        let i = scheduler.select(&waiting);
+        self.waiting.remove(i)
+    }
+
     /// Iterate through the pending connections looking for any that might want
     /// to send a datagram.  Stop at the first one that does.
     fn process_next_output(&mut self, now: Instant) -> Option<Datagram> {
         qtrace!([self], "No packet to send, look at waiting connections");
-        while let Some(c) = self.waiting.pop_front() {
+        while let Some(c) = self.next_waiting() {
             if let Some(d) = self.process_connection(&c, None, now) {
                 return Some(d);
             }
@@ -657,12 +1579,32 @@ impl Server {
         }
     }
 
+    /// Process an incoming datagram, if any, and produce the next output.
+    /// A server with several local addresses needs to send each datagram from its
+    /// [`Datagram::source`] address.  Retry, Version Negotiation, and stateless resets
+    /// come from the address that the triggering datagram was sent to, but connections
+    /// can use other addresses after migration or with a preferred address.
     pub fn process(&mut self, dgram: Option<&Datagram>, now: Instant) -> Output {
+        self.process_into(dgram, now).0
+    }
+
+    /// As with `process`, but this also returns the connection that `dgram`
+    /// was routed to, which might be a connection that was created as a result.
+    /// No connection is returned if `dgram` is `None`, if it was discarded,
+    /// or if the server responded without creating a connection, such as
+    /// with a Version Negotiation or Retry packet.
+    pub fn process_into(
+        &mut self,
+        dgram: Option<&Datagram>,
+        now: Instant,
+    ) -> (Output, Option<ActiveConnectionRef>) {
+        let _span = qspan!("Server::process", self);
+        self.clock.set(now);
         if self.wake_at.map_or(false, |c| c <= now) {
             self.wake_at = None;
         }
 
-        dgram
+        let out = dgram
             .and_then(|d| self.process_input(d, now))
             .or_else(|| self.process_next_output(now))
             .map(|d| {
@@ -673,7 +1615,37 @@ impl Server {
             .unwrap_or_else(|| {
                 qtrace!([self], "Go dormant");
                 Output::None
-            })
+            });
+        self.sweep(now);
+        let routed = self.routed.take().map(|c| ActiveConnectionRef { c });
+        (out, routed)
+    }
+
+    /// As with `process`, but this also reports what happened to the packets in
+    /// `dgram`.  If the datagram was dropped before it reached a connection, the
+    /// `DropReason` is listed in `server_dropped`.  The report is empty if the
+    /// server answered without creating a connection, or if it held the
+    /// datagram back, as it does with 0-RTT that arrives ahead of its Initial.
+    pub fn process_with_diag(&mut self, dgram: &Datagram, now: Instant) -> (Output, ProcessDiag) {
+        self.last_drop = None;
+        let (out, routed) = self.process_into(Some(dgram), now);
+        let diag = if let Some(c) = routed {
+            c.borrow().last_input_diag().clone()
+        } else if let Some(reason) = self.last_drop.take() {
+            ProcessDiag {
+                server_dropped: vec![reason],
+                ..ProcessDiag::default()
+            }
+        } else {
+            ProcessDiag::default()
+        };
+        (out, diag)
+    }
+
+    /// The QUIC versions that this server accepts, in order of preference.
+    #[must_use]
+    pub fn supported_versions(&self) -> Vec<Version> {
+        self.conn_params.get_versions().all().to_vec()
     }
 
     /// This lists the connections that have received new events
@@ -682,6 +1654,23 @@ impl Server {
         mem::take(&mut self.active).into_iter().collect()
     }
 
+    /// As `active_connections`, but ordered by the remote address of each connection
+    /// and then by the destination connection ID of the client's first Initial.
+    /// Unlike `active_connections`, the order doesn't vary between runs.
+    pub fn active_connections_sorted(&mut self) -> Vec<ActiveConnectionRef> {
+        let mut active = self.active_connections();
+        active.sort_by_cached_key(|c| {
+            let remote = c
+                .borrow()
+                .paths()
+                .into_iter()
+                .find(|p| p.active)
+                .map(|p| p.remote);
+            (remote, c.original_dcid().to_vec())
+        });
+        active
+    }
+
     /// Whether any connections have received new events as a result of calling
     /// `process()`.
     #[must_use]
@@ -692,6 +1681,126 @@ impl Server {
     pub fn add_to_waiting(&mut self, c: &ActiveConnectionRef) {
         self.waiting.push_back(c.connection());
     }
+
+    /// Close all connections that haven't produced any events for the
+    /// application for more than `threshold`.  This is independent of the
+    /// transport idle timeout, which is reset by any packet, including those
+    /// that only carry acknowledgments or keep-alive pings.
+    /// Returns the datagrams containing `CONNECTION_CLOSE` for the connections
+    /// that were closed.
+    pub fn close_idle(&mut self, threshold: Duration, now: Instant) -> Vec<Datagram> {
+        let mut idle: Vec<StateRef> = Vec::new();
+        for c in self.connections.borrow().values() {
+            let conn = c.borrow();
+            if !conn.state().closed()
+                && conn.idle_for(now) > threshold
+                && !idle.iter().any(|i| Rc::ptr_eq(i, c))
+            {
+                idle.push(Rc::clone(c));
+            }
+        }
+
+        idle.iter()
+            .filter_map(|c| {
+                qinfo!([self], "Closing idle connection {:?}", c);
+                c.borrow_mut().close(now, 0, "idle");
+                self.process_connection(c, None, now)
+            })
+            .collect()
+    }
+
+    /// Advertise lower flow control limits to new connections, which clients
+    /// remember and apply to 0-RTT when they resume, and raise the limits once
+    /// the handshake is done.  See `ConnectionParameters::zero_rtt_flow_control`.
+    pub fn set_zero_rtt_flow_control(&mut self, max_data: u64, max_stream_data: u64) {
+        self.conn_params = self
+            .conn_params
+            .clone()
+            .zero_rtt_flow_control(max_data, max_stream_data);
+    }
+
+    /// Close the connection that uses `cid` with a transport error code, for a
+    /// peer that misbehaves at the protocol layer.  `frame_type` is the type of
+    /// the frame that caused the error, if any.  Returns the datagram containing
+    /// `CONNECTION_CLOSE`, or `None` if there is no open connection for `cid`.
+    pub fn close_connection_transport(
+        &mut self,
+        cid: ConnectionIdRef,
+        error: TransportError,
+        frame_type: Option<u64>,
+        reason: &str,
+        now: Instant,
+    ) -> Option<Datagram> {
+        let c = self.connection(cid)?;
+        if c.borrow().state().closed() {
+            return None;
+        }
+        qinfo!(
+            [self],
+            "Closing connection {:?} with transport error {}",
+            c,
+            error
+        );
+        c.borrow_mut()
+            .close_with_transport_error(now, error, frame_type, reason);
+        self.process_connection(&c, None, now)
+    }
+
+    /// Start shutting down the server.  Every connection that is still open
+    /// is closed with the given application error code and reason phrase,
+    /// and Initial packets for new connections are dropped from now on.
+    /// Returns the datagrams containing `CONNECTION_CLOSE` for the connections
+    /// that were closed.
+    pub fn initiate_shutdown_with_reason(
+        &mut self,
+        error: AppError,
+        reason: &str,
+        now: Instant,
+    ) -> Vec<Datagram> {
+        self.shutting_down = true;
+        let mut open: Vec<StateRef> = Vec::new();
+        for c in self.connections.borrow().values() {
+            if !c.borrow().state().closed() && !open.iter().any(|o| Rc::ptr_eq(o, c)) {
+                open.push(Rc::clone(c));
+            }
+        }
+
+        open.iter()
+            .filter_map(|c| {
+                qinfo!([self], "Closing connection {:?} for shutdown", c);
+                c.borrow_mut().close(now, error, reason);
+                self.process_connection(c, None, now)
+            })
+            .collect()
+    }
+
+    /// Whether `initiate_shutdown_with_reason` has been called.
+    #[must_use]
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+    }
+}
+
+/// Make a stateless reset in response to `dgram`; see RFC 9000, Section 10.3.
+/// The reset is smaller than `dgram`, so that two endpoints can't get stuck in a loop.
+fn stateless_reset(token: &[u8; 16], dgram: &Datagram, tos: IpTos) -> Option<Datagram> {
+    const MIN_RESET_LEN: usize = 21;
+    const MAX_RESET_LEN: usize = 42;
+    let len = min(dgram.len().checked_sub(1)?, MAX_RESET_LEN);
+    if len < MIN_RESET_LEN {
+        return None;
+    }
+    let mut reset = random::<MAX_RESET_LEN>()[..len - token.len()].to_vec();
+    // This needs to look like a short header packet: long header bit clear, fixed bit set.
+    reset[0] = 0x40 | (reset[0] & 0x3f);
+    reset.extend_from_slice(token);
+    Some(Datagram::new(
+        dgram.destination(),
+        dgram.source(),
+        tos,
+        dgram.ttl(),
+        reset,
+    ))
 }
 
 #[derive(Clone, Debug)]
@@ -713,6 +1822,184 @@ impl ActiveConnectionRef {
     pub fn connection(&self) -> StateRef {
         Rc::clone(&self.c)
     }
+
+    /// Have this connection drop and delay the datagrams it sends.
+    /// See `Connection::set_test_network`.
+    #[cfg(feature = "test-network")]
+    pub fn set_test_network(&mut self, loss: f64, added_latency: Duration) {
+        self.borrow_mut().set_test_network(loss, added_latency);
+    }
+
+    /// Get the furthest phase that the handshake on this connection has reached.
+    #[must_use]
+    pub fn handshake_phase(&self) -> HandshakePhase {
+        self.borrow().handshake_phase()
+    }
+
+    /// Whether the handshake on this connection used session resumption.
+    /// This is true for any resumed handshake, whether or not 0-RTT was accepted.
+    #[must_use]
+    pub fn was_resumed(&self) -> bool {
+        self.borrow()
+            .tls_info()
+            .map_or(false, SecretAgentInfo::resumed)
+    }
+
+    /// The destination connection ID that the client chose for its first Initial
+    /// packet, before any Retry.  This is also used to name qlog files, so it
+    /// can be used to match server and client logs.
+    #[must_use]
+    pub fn original_dcid(&self) -> ConnectionId {
+        self.c.borrow().original_dcid.clone()
+    }
+
+    /// The source connection ID that the client used in its Initial packets.
+    /// This is the connection ID that the server sends to during the handshake,
+    /// so it can help match this connection with client logs.
+    #[must_use]
+    pub fn client_initial_scid(&self) -> ConnectionId {
+        self.c.borrow().client_initial_scid.clone()
+    }
+
+    /// Counters for the DATAGRAM frames on this connection.
+    /// See `Connection::datagram_stats`.
+    #[must_use]
+    pub fn datagram_stats(&self) -> DatagramStats {
+        self.borrow().datagram_stats()
+    }
+
+    /// Start counting DATAGRAM frames on this connection from zero.
+    pub fn reset_datagram_stats(&mut self) {
+        self.borrow_mut().reset_datagram_stats();
+    }
+
+    /// Report whether sending on this connection is blocked by flow control.
+    /// See `Connection::flow_control_blocked`.
+    #[must_use]
+    pub fn flow_control_blocked(&self) -> FlowControlState {
+        self.borrow().flow_control_blocked()
+    }
+
+    /// Pause or resume sending on this connection.  While paused, the server
+    /// produces no datagrams for the connection, but it continues to process
+    /// packets that it receives and its timers keep running, so it can still
+    /// time out.  Anything that the connection sends while paused is held and
+    /// sent, in order, once sending resumes.  A resumed connection sends the
+    /// next time that the server is asked for output.
+    pub fn pause_sending(&mut self, paused: bool) {
+        let mut c = self.c.borrow_mut();
+        c.resumed |= c.paused && !paused;
+        c.paused = paused;
+    }
+
+    /// Set the priority of this connection, which an `OutputScheduler` can use
+    /// to decide which connection sends next.  The default is 1.
+    pub fn set_priority(&mut self, priority: u32) {
+        self.c.borrow_mut().priority = priority;
+    }
+
+    /// The priority of this connection.
+    #[must_use]
+    pub fn priority(&self) -> u32 {
+        self.c.borrow().priority
+    }
+
+    /// Start writing a qlog trace for this connection to `writer`.  This can be
+    /// used on a connection that was created without a trace, to look at what
+    /// it does from now on.  See `Connection::start_qlog`.  The trace is written
+    /// with the qlog format, version, and limits that the server had when it
+    /// accepted the connection.
+    ///
+    /// # Errors
+    ///
+    /// When the trace header cannot be written.
+    pub fn start_qlog(&mut self, writer: Box<dyn Write + Send + Sync>) -> Res<()> {
+        let settings = self.c.borrow().qlog_settings;
+        let path = settings.path(self.original_dcid().as_cid_ref());
+        let qlog = settings.trace(settings.limits().writer(writer), path)?;
+        self.borrow_mut().start_qlog(qlog);
+        Ok(())
+    }
+
+    /// The latency spin bit that this connection sends.
+    /// See `Server::set_spin_bit_enabled`.
+    #[must_use]
+    pub fn spin_bit(&self) -> bool {
+        self.borrow().spin_bit()
+    }
+
+    /// The certificate chain that the client presented, if any.
+    /// This is only set once client authentication has succeeded.
+    /// See `Connection::peer_certificate`.
+    #[must_use]
+    pub fn peer_certificate(&self) -> Option<CertificateInfo> {
+        self.borrow().peer_certificate()
+    }
+
+    /// The server name that the client sent, or `None` if there was none.
+    /// See `Connection::server_name`.
+    #[must_use]
+    pub fn server_name(&self) -> Option<String> {
+        self.borrow().server_name().map(String::from)
+    }
+
+    /// The loss recovery statistics for this connection.
+    /// See `Connection::recovery_stats`.
+    #[must_use]
+    pub fn recovery_stats(&self) -> RecoveryStats {
+        self.borrow().recovery_stats()
+    }
+
+    /// The number of consecutive PTOs on this connection.
+    /// See `Connection::pto_count`.
+    #[must_use]
+    pub fn pto_count(&self) -> u32 {
+        self.borrow().pto_count()
+    }
+
+    /// How long this connection waits for the pacer before it sends again.
+    /// See `Connection::pacing_delay`.
+    #[must_use]
+    pub fn pacing_delay(&self, now: Instant) -> Duration {
+        self.borrow().pacing_delay(now)
+    }
+
+    /// Whether the client used ECH, or `None` if that is not known.
+    /// A client that sends a GREASE ECH extension results in `Some(false)`.
+    /// See `Connection::tls_preinfo`.
+    #[must_use]
+    pub fn ech_accepted(&self) -> Option<bool> {
+        self.borrow()
+            .tls_preinfo()
+            .ok()
+            .and_then(|info| info.ech_accepted())
+    }
+
+    /// The number of bytes of each packet that the packet header and the AEAD tag
+    /// currently take, so a datagram of `n` bytes has room for `n - packet_overhead()`
+    /// bytes of frames.  This is 0 if the connection cannot send application data yet.
+    /// See `Connection::packet_overhead`.
+    #[must_use]
+    pub fn packet_overhead(&self) -> usize {
+        self.borrow().packet_overhead().unwrap_or(0)
+    }
+
+    /// The phase of congestion control on the primary path.  A connection that
+    /// has no path yet has not sent anything, so it is in slow start.
+    /// See `Connection::cc_snapshot`.
+    #[must_use]
+    pub fn cc_phase(&self) -> CongestionPhase {
+        self.borrow()
+            .cc_snapshot()
+            .map_or(CongestionPhase::SlowStart, |s| s.phase)
+    }
+
+    /// Streams that are holding received data behind a gap, with the offset
+    /// of the first missing byte.  See `Connection::reassembly_gaps`.
+    #[must_use]
+    pub fn reassembly_gaps(&self) -> Vec<(StreamId, u64)> {
+        self.borrow().reassembly_gaps()
+    }
 }
 
 impl std::hash::Hash for ActiveConnectionRef {
@@ -730,11 +2017,54 @@ impl PartialEq for ActiveConnectionRef {
 
 impl Eq for ActiveConnectionRef {}
 
+/// A token bucket that limits how quickly a connection is issued connection IDs.
+/// The bucket holds enough for a full set of connection IDs.
+struct CidRateLimit {
+    /// The time between each new token.
+    interval: Duration,
+    clock: Rc<Cell<Instant>>,
+    tokens: usize,
+    /// When the last token was added.
+    refilled: Instant,
+}
+
+impl CidRateLimit {
+    fn new(per_sec: u32, clock: Rc<Cell<Instant>>) -> Self {
+        let refilled = clock.get();
+        Self {
+            interval: Duration::from_secs(1) / per_sec,
+            clock,
+            tokens: LOCAL_ACTIVE_CID_LIMIT,
+            refilled,
+        }
+    }
+
+    /// Take a token, if one is available.
+    fn take(&mut self) -> bool {
+        let now = self.clock.get();
+        while self.tokens < LOCAL_ACTIVE_CID_LIMIT && now >= self.refilled + self.interval {
+            self.tokens += 1;
+            self.refilled += self.interval;
+        }
+        if self.tokens == LOCAL_ACTIVE_CID_LIMIT {
+            // A full bucket doesn't accumulate time toward the next token.
+            self.refilled = now;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
 struct ServerConnectionIdGenerator {
     c: Weak<RefCell<ServerConnectionState>>,
     connections: ConnectionTableRef,
     cid_generator: Rc<RefCell<dyn ConnectionIdGenerator>>,
     saved_cids: Vec<ConnectionId>,
+    /// Limits how quickly connection IDs are issued once the connection is set.
+    rate_limit: Option<CidRateLimit>,
 }
 
 impl ServerConnectionIdGenerator {
@@ -761,8 +2091,18 @@ impl ConnectionIdDecoder for ServerConnectionIdGenerator {
 
 impl ConnectionIdGenerator for ServerConnectionIdGenerator {
     fn generate_cid(&mut self) -> Option<ConnectionId> {
+        // Connection IDs that are made before the connection is set up are needed
+        // to create it, so those are never limited.
+        if self.c.strong_count() > 0 && self.rate_limit.as_mut().is_some_and(|l| !l.take()) {
+            qdebug!("ServerConnectionIdGenerator deferring a new connection ID");
+            return None;
+        }
         let maybe_cid = self.cid_generator.borrow_mut().generate_cid();
         if let Some(cid) = maybe_cid {
+            if cid.len() > MAX_CONNECTION_ID_LEN {
+                // Don't route with this; the connection will reject it.
+                return Some(cid);
+            }
             if let Some(rc) = self.c.upgrade() {
                 self.insert_cid(cid.clone(), rc);
             } else {
@@ -8,53 +8,201 @@
 
 use std::{
     cell::RefCell,
+    cmp::max,
     fmt::{self, Debug},
     ops::Deref,
     rc::Rc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use neqo_common::qwarn;
+use neqo_common::{qwarn, Decoder};
+#[cfg(feature = "serde")]
+use serde_derive::Serialize;
 
-use crate::packet::PacketNumber;
+use crate::{
+    ecn::{EcnCount, EcnValidationOutcome},
+    frame::Frame,
+    packet::PacketNumber,
+    server::DropReason,
+    tracking::PacketNumberSpace,
+    Error,
+};
 
 pub(crate) const MAX_PTO_COUNTS: usize = 16;
 
+/// Counts of frames, by type.
 #[derive(Default, Clone)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[allow(clippy::module_name_repetitions)]
 pub struct FrameStats {
+    /// All frames.
     pub all: usize,
+    /// ACK and `ACK_ECN` frames.
     pub ack: usize,
+    /// The largest packet number acknowledged in an ACK frame.  This is a
+    /// snapshot, not a counter.
     pub largest_acknowledged: PacketNumber,
 
+    /// CRYPTO frames.
     pub crypto: usize,
+    /// STREAM frames.
     pub stream: usize,
+    /// `RESET_STREAM` frames.
     pub reset_stream: usize,
+    /// `STOP_SENDING` frames.
     pub stop_sending: usize,
 
+    /// PING frames.
     pub ping: usize,
+    /// PADDING frames.  A run of padding bytes counts as one frame, whether
+    /// it is sent or received; this is not a count of padding bytes.
     pub padding: usize,
 
+    /// `MAX_STREAMS` frames.
     pub max_streams: usize,
+    /// `STREAMS_BLOCKED` frames.
     pub streams_blocked: usize,
+    /// `MAX_DATA` frames.
     pub max_data: usize,
+    /// `DATA_BLOCKED` frames.
     pub data_blocked: usize,
+    /// `MAX_STREAM_DATA` frames.
     pub max_stream_data: usize,
+    /// `STREAM_DATA_BLOCKED` frames.
     pub stream_data_blocked: usize,
 
+    /// `NEW_CONNECTION_ID` frames.
     pub new_connection_id: usize,
+    /// `RETIRE_CONNECTION_ID` frames.
     pub retire_connection_id: usize,
 
+    /// `PATH_CHALLENGE` frames.
     pub path_challenge: usize,
+    /// `PATH_RESPONSE` frames.
     pub path_response: usize,
 
+    /// `CONNECTION_CLOSE` frames, of either type.
     pub connection_close: usize,
+    /// `HANDSHAKE_DONE` frames.
     pub handshake_done: usize,
+    /// `NEW_TOKEN` frames.
     pub new_token: usize,
 
+    /// `ACK_FREQUENCY` frames.
     pub ack_frequency: usize,
+    /// DATAGRAM frames.
     pub datagram: usize,
+
+    /// Frames of a type that is not understood, including greased frame types.
+    pub unknown: usize,
+}
+
+impl FrameStats {
+    /// Count a frame.  All frames that are sent or received are counted here.
+    pub(crate) fn add(&mut self, frame: &Frame) {
+        self.all += 1;
+        let counter = match frame {
+            Frame::Padding(_) => &mut self.padding,
+            Frame::Ping => &mut self.ping,
+            Frame::Ack {
+                largest_acknowledged,
+                ..
+            } => {
+                self.largest_acknowledged = max(self.largest_acknowledged, *largest_acknowledged);
+                &mut self.ack
+            }
+            Frame::ResetStream { .. } => &mut self.reset_stream,
+            Frame::StopSending { .. } => &mut self.stop_sending,
+            Frame::Crypto { .. } => &mut self.crypto,
+            Frame::NewToken { .. } => &mut self.new_token,
+            Frame::Stream { .. } => &mut self.stream,
+            Frame::MaxData { .. } => &mut self.max_data,
+            Frame::MaxStreamData { .. } => &mut self.max_stream_data,
+            Frame::MaxStreams { .. } => &mut self.max_streams,
+            Frame::DataBlocked { .. } => &mut self.data_blocked,
+            Frame::StreamDataBlocked { .. } => &mut self.stream_data_blocked,
+            Frame::StreamsBlocked { .. } => &mut self.streams_blocked,
+            Frame::NewConnectionId { .. } => &mut self.new_connection_id,
+            Frame::RetireConnectionId { .. } => &mut self.retire_connection_id,
+            Frame::PathChallenge { .. } => &mut self.path_challenge,
+            Frame::PathResponse { .. } => &mut self.path_response,
+            Frame::ConnectionClose { .. } => &mut self.connection_close,
+            Frame::HandshakeDone => &mut self.handshake_done,
+            Frame::AckFrequency { .. } => &mut self.ack_frequency,
+            Frame::Datagram { .. } => &mut self.datagram,
+        };
+        *counter += 1;
+    }
+
+    /// Count a frame with a type that could not be understood.
+    pub(crate) fn add_unknown(&mut self) {
+        self.all += 1;
+        self.unknown += 1;
+    }
+
+    /// Count the frames in the payload of a packet that is being sent.
+    /// Frame writers don't count what they write; this is called once for
+    /// each packet, before it is protected.
+    pub(crate) fn add_payload(&mut self, payload: &[u8]) {
+        let mut d = Decoder::from(payload);
+        while d.remaining() > 0 {
+            match Frame::decode(&mut d) {
+                Ok(frame) => self.add(&frame),
+                Err(e) => {
+                    // Nothing after a frame that can't be decoded can be counted.
+                    if e == Error::UnknownFrameType {
+                        self.add_unknown();
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The frames counted since `previous` was taken.
+    #[must_use]
+    pub fn delta(&self, previous: &Self) -> Self {
+        Self {
+            all: self.all.saturating_sub(previous.all),
+            ack: self.ack.saturating_sub(previous.ack),
+            largest_acknowledged: self.largest_acknowledged,
+            crypto: self.crypto.saturating_sub(previous.crypto),
+            stream: self.stream.saturating_sub(previous.stream),
+            reset_stream: self.reset_stream.saturating_sub(previous.reset_stream),
+            stop_sending: self.stop_sending.saturating_sub(previous.stop_sending),
+            ping: self.ping.saturating_sub(previous.ping),
+            padding: self.padding.saturating_sub(previous.padding),
+            max_streams: self.max_streams.saturating_sub(previous.max_streams),
+            streams_blocked: self
+                .streams_blocked
+                .saturating_sub(previous.streams_blocked),
+            max_data: self.max_data.saturating_sub(previous.max_data),
+            data_blocked: self.data_blocked.saturating_sub(previous.data_blocked),
+            max_stream_data: self
+                .max_stream_data
+                .saturating_sub(previous.max_stream_data),
+            stream_data_blocked: self
+                .stream_data_blocked
+                .saturating_sub(previous.stream_data_blocked),
+            new_connection_id: self
+                .new_connection_id
+                .saturating_sub(previous.new_connection_id),
+            retire_connection_id: self
+                .retire_connection_id
+                .saturating_sub(previous.retire_connection_id),
+            path_challenge: self.path_challenge.saturating_sub(previous.path_challenge),
+            path_response: self.path_response.saturating_sub(previous.path_response),
+            connection_close: self
+                .connection_close
+                .saturating_sub(previous.connection_close),
+            handshake_done: self.handshake_done.saturating_sub(previous.handshake_done),
+            new_token: self.new_token.saturating_sub(previous.new_token),
+            ack_frequency: self.ack_frequency.saturating_sub(previous.ack_frequency),
+            datagram: self.datagram.saturating_sub(previous.datagram),
+            unknown: self.unknown.saturating_sub(previous.unknown),
+        }
+    }
 }
 
 impl Debug for FrameStats {
@@ -84,7 +232,7 @@ impl Debug for FrameStats {
             "    blocked: stream {} data {} stream_data {}",
             self.streams_blocked, self.data_blocked, self.stream_data_blocked,
         )?;
-        writeln!(f, "    datagram {}", self.datagram)?;
+        writeln!(f, "    datagram {} unknown {}", self.datagram, self.unknown)?;
         writeln!(
             f,
             "    ncid {} rcid {} pchallenge {} presponse {}",
@@ -98,9 +246,14 @@ impl Debug for FrameStats {
 }
 
 /// Datagram stats
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[allow(clippy::module_name_repetitions)]
 pub struct DatagramStats {
+    /// The number of DATAGRAM frames sent.
+    pub sent: usize,
+    /// The number of DATAGRAM frames received.
+    pub received: usize,
     /// The number of datagrams declared lost.
     pub lost: usize,
     /// The number of datagrams dropped due to being too large.
@@ -110,26 +263,284 @@ pub struct DatagramStats {
     pub dropped_queue_full: usize,
 }
 
-/// Connection statistics
+impl DatagramStats {
+    #[must_use]
+    pub fn delta(&self, previous: &Self) -> Self {
+        Self {
+            sent: self.sent.saturating_sub(previous.sent),
+            received: self.received.saturating_sub(previous.received),
+            lost: self.lost.saturating_sub(previous.lost),
+            dropped_too_big: self
+                .dropped_too_big
+                .saturating_sub(previous.dropped_too_big),
+            dropped_queue_full: self
+                .dropped_queue_full
+                .saturating_sub(previous.dropped_queue_full),
+        }
+    }
+}
+
+/// ECN statistics
+#[derive(Default, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[allow(clippy::module_name_repetitions)]
+pub struct EcnStats {
+    /// The number of datagrams sent with an ECN mark.
+    pub tx_marked: usize,
+    /// The number of packets received with each ECN codepoint.
+    pub rx: EcnCount,
+    /// The outcome of ECN validation on the primary path.  This is a snapshot.
+    pub validation: EcnValidationOutcome,
+}
+
+impl EcnStats {
+    #[must_use]
+    pub fn delta(&self, previous: &Self) -> Self {
+        Self {
+            tx_marked: self.tx_marked.saturating_sub(previous.tx_marked),
+            rx: self.rx - previous.rx,
+            validation: self.validation,
+        }
+    }
+}
+
+/// When the milestones of a connection were reached, measured from when the
+/// connection was created.  Each value is set once and never changes after that.
+/// A milestone that has not been reached is `None`.
+#[derive(Default, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Timings {
+    #[cfg_attr(feature = "serde", serde(skip))]
+    created: Option<Instant>,
+    /// When the first Initial packet was sent.
+    pub first_initial_sent: Option<Duration>,
+    /// When the first Initial packet was received.
+    pub first_initial_received: Option<Duration>,
+    /// When the handshake completed.
+    pub handshake_completed: Option<Duration>,
+    /// When the handshake was confirmed.
+    pub handshake_confirmed: Option<Duration>,
+    /// When the first packet containing stream data was sent.
+    pub first_stream_byte_sent: Option<Duration>,
+    /// When the first packet containing stream data was acknowledged.
+    pub first_stream_byte_acked: Option<Duration>,
+    /// The number of times that the PTO timer fired before the handshake was confirmed.
+    pub handshake_pto_count: usize,
+}
+
+impl Timings {
+    /// Set the time that the other values are measured from, if that hasn't happened yet.
+    pub(crate) fn start(&mut self, now: Instant) {
+        self.created.get_or_insert(now);
+    }
+
+    fn mark(created: Option<Instant>, slot: &mut Option<Duration>, now: Instant) {
+        if let Some(created) = created {
+            slot.get_or_insert_with(|| now.saturating_duration_since(created));
+        }
+    }
+
+    pub(crate) fn initial_sent(&mut self, now: Instant) {
+        Self::mark(self.created, &mut self.first_initial_sent, now);
+    }
+
+    pub(crate) fn initial_received(&mut self, now: Instant) {
+        Self::mark(self.created, &mut self.first_initial_received, now);
+    }
+
+    pub(crate) fn handshake_completed(&mut self, now: Instant) {
+        Self::mark(self.created, &mut self.handshake_completed, now);
+    }
+
+    pub(crate) fn handshake_confirmed(&mut self, now: Instant, pto_count: usize) {
+        if self.handshake_confirmed.is_none() {
+            self.handshake_pto_count = pto_count;
+        }
+        Self::mark(self.created, &mut self.handshake_confirmed, now);
+    }
+
+    pub(crate) fn stream_sent(&mut self, now: Instant) {
+        Self::mark(self.created, &mut self.first_stream_byte_sent, now);
+    }
+
+    pub(crate) fn stream_acked(&mut self, now: Instant) {
+        Self::mark(self.created, &mut self.first_stream_byte_acked, now);
+    }
+}
+
+/// Bytes counted separately for each packet number space.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[allow(clippy::module_name_repetitions)]
+pub struct SpaceStats {
+    /// Bytes in Initial packets.
+    pub initial: usize,
+    /// Bytes in Handshake packets.
+    pub handshake: usize,
+    /// Bytes in 0-RTT and 1-RTT packets.
+    pub application_data: usize,
+}
+
+impl SpaceStats {
+    pub(crate) fn add(&mut self, space: PacketNumberSpace, bytes: usize) {
+        match space {
+            PacketNumberSpace::Initial => self.initial += bytes,
+            PacketNumberSpace::Handshake => self.handshake += bytes,
+            PacketNumberSpace::ApplicationData => self.application_data += bytes,
+        }
+    }
+
+    /// The total across all packet number spaces.
+    #[must_use]
+    pub const fn total(&self) -> usize {
+        self.initial + self.handshake + self.application_data
+    }
+
+    #[must_use]
+    pub const fn delta(&self, previous: &Self) -> Self {
+        Self {
+            initial: self.initial.saturating_sub(previous.initial),
+            handshake: self.handshake.saturating_sub(previous.handshake),
+            application_data: self
+                .application_data
+                .saturating_sub(previous.application_data),
+        }
+    }
+}
+
+/// The state of loss recovery and congestion control for a connection,
+/// gathered from `Stats` and the primary path.  See `Connection::recovery_stats`.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[allow(clippy::module_name_repetitions)]
+pub struct RecoveryStats {
+    /// Total packets sent.
+    pub packets_sent: usize,
+    /// Total packets that were acknowledged.
+    pub packets_acked: usize,
+    /// Total packets that were declared lost.
+    pub packets_lost: usize,
+    /// Packets that were declared lost, but were then acknowledged.
+    pub spurious_losses: usize,
+    /// The number of times that the PTO timer fired.
+    pub pto_count: usize,
+    /// The congestion window.
+    pub cwnd: usize,
+    /// The slow start threshold.
+    pub ssthresh: usize,
+    pub bytes_in_flight: usize,
+    /// The most recent RTT sample.
+    pub latest_rtt: Duration,
+    /// The smallest RTT sample.
+    pub min_rtt: Duration,
+    pub smoothed_rtt: Duration,
+    pub rttvar: Duration,
+}
+
+/// Why a connection dropped a packet that it received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDropReason {
+    /// The packet could not be decoded.
+    Undecodable,
+    /// The packet could not be decrypted, possibly because keys were not available.
+    DecryptionFailure,
+    /// The packet was received before.
+    Duplicate,
+    /// A packet that followed another in the same datagram had a different
+    /// destination connection ID.
+    CoalescedDcidMismatch,
+    /// The packet used a connection ID that the connection doesn't recognize.
+    UnknownConnectionId,
+    /// An Initial packet that started a server connection was not valid, or it
+    /// used a version that the server doesn't support.
+    InvalidInitial,
+    /// A Version Negotiation packet was not valid, or it arrived after a Retry.
+    InvalidVersionNegotiation,
+    /// A Version Negotiation packet listed no versions.
+    EmptyVersionNegotiation,
+    /// A Retry packet arrived after a Retry was already received.
+    ExtraRetry,
+    /// A Retry packet had no token.
+    RetryWithoutToken,
+    /// The integrity tag of a Retry packet was not valid.
+    RetryIntegrity,
+    /// A Retry packet arrived when there was no path to update.
+    RetryWithoutPath,
+    /// The packet was of a type that is not expected in the current state.
+    UnexpectedType,
+    /// The packet arrived before the connection was started.
+    NotStarted,
+    /// The packet arrived after the connection was closed.
+    Closed,
+}
+
+/// What happened to the packets in the datagrams given to a connection in
+/// one call to `Connection::process_input` or `Server::process_with_diag`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProcessDiag {
+    /// Packets that were decrypted and processed.
+    pub processed: usize,
+    /// Packets that were saved because keys were not yet available.
+    /// Each saved datagram counts once, regardless of how many packets remain in it.
+    pub saved: usize,
+    /// The reason that each dropped packet was dropped, in order.
+    pub dropped: Vec<PacketDropReason>,
+    /// Why the server dropped the datagram before it reached a connection.
+    /// Only `Server::process_with_diag` reports this.
+    pub server_dropped: Vec<DropReason>,
+}
+
+/// Connection statistics.
+///
+/// Most fields are counters that only increase over the life of a connection;
+/// `Stats::delta` turns two snapshots into the counts for the interval between
+/// them.  The fields that are documented as snapshots describe the connection
+/// at the time `Connection::stats` was called.  With the `serde` feature,
+/// this can be serialized; the field names are stable.
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[allow(clippy::module_name_repetitions)]
 pub struct Stats {
+    #[cfg_attr(feature = "serde", serde(skip))]
     info: String,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    diag: ProcessDiag,
 
+    /// Total datagrams received.
+    pub datagrams_rx: usize,
     /// Total packets received, including all the bad ones.
     pub packets_rx: usize,
+    /// Bytes in packets that were received and decrypted, by packet number space.
+    pub bytes_rx: SpaceStats,
     /// Duplicate packets received.
     pub dups_rx: usize,
     /// Dropped packets or dropped garbage.
     pub dropped_rx: usize,
+    /// Packets that failed decryption when a key update might explain the failure.
+    pub key_phase_failures_rx: usize,
+    /// Packets that failed decryption for reasons unrelated to key updates.
+    pub decrypt_failures_rx: usize,
     /// The number of packet that were saved for later processing.
     pub saved_datagrams: usize,
 
+    /// Total datagrams sent.
+    pub datagrams_tx: usize,
     /// Total packets sent.
     pub packets_tx: usize,
+    /// Bytes in packets that were sent, by packet number space.  This does not
+    /// include the padding that is added after an Initial packet.
+    pub bytes_tx: SpaceStats,
+    /// The path MTU of the primary path, as a UDP payload size.  A snapshot.
+    pub pmtu: usize,
+    /// Packets sent that contained acknowledgments and nothing ack-eliciting.
+    pub ack_only_tx: usize,
     /// Total number of packets that are declared lost.
     pub lost: usize,
+    /// Total number of packets that were acknowledged, including those
+    /// that were declared lost first.
+    pub acked: usize,
     /// Late acknowledgments, for packets that were declared lost already.
+    /// This is the number of spurious losses.
     pub late_ack: usize,
     /// Acknowledgments for packets that contained data that was marked
     /// for retransmission when the PTO timer popped.
@@ -138,13 +549,21 @@ pub struct Stats {
     /// Whether the connection was resumed successfully.
     pub resumed: bool,
 
-    /// The current, estimated round-trip time on the primary path.
+    /// The current, estimated round-trip time on the primary path.  A snapshot.
     pub rtt: Duration,
     /// The current, estimated round-trip time variation on the primary path.
+    /// A snapshot.
     pub rttvar: Duration,
     /// Whether the first RTT sample was guessed from a discarded packet.
     pub rtt_init_guess: bool,
 
+    /// The congestion window of the primary path.  A snapshot.
+    pub cwnd: usize,
+    /// The bytes in flight on the primary path.  A snapshot.
+    pub bytes_in_flight: usize,
+
+    /// The number of times that the PTO timer fired.
+    pub pto_count: usize,
     /// Count PTOs. Single PTOs, 2 PTOs in a row, 3 PTOs in row, etc. are counted
     /// separately.
     pub pto_counts: [usize; MAX_PTO_COUNTS],
@@ -158,7 +577,14 @@ pub struct Stats {
     /// of the incoming queue.
     pub incoming_datagram_dropped: usize,
 
+    /// DATAGRAM frames that were sent, received, lost, or dropped.
     pub datagram_tx: DatagramStats,
+
+    /// ECN marking and validation.
+    pub ecn: EcnStats,
+
+    /// When the handshake and the first stream data progressed.
+    pub timings: Timings,
 }
 
 impl Stats {
@@ -166,21 +592,42 @@ impl Stats {
         self.info = info;
     }
 
-    pub fn pkt_dropped(&mut self, reason: impl AsRef<str>) {
+    pub fn pkt_dropped(&mut self, reason: PacketDropReason) {
         self.dropped_rx += 1;
+        self.diag.dropped.push(reason);
         qwarn!(
             [self.info],
-            "Dropped received packet: {}; Total: {}",
-            reason.as_ref(),
+            "Dropped received packet: {:?}; Total: {}",
+            reason,
             self.dropped_rx
         );
     }
 
+    /// Start collecting a new `ProcessDiag`, returning the previous one.
+    pub(crate) fn take_diag(&mut self) -> ProcessDiag {
+        std::mem::take(&mut self.diag)
+    }
+
+    pub(crate) fn pkt_processed(&mut self) {
+        self.diag.processed += 1;
+    }
+
+    pub(crate) fn pkt_duplicate(&mut self) {
+        self.dups_rx += 1;
+        self.diag.dropped.push(PacketDropReason::Duplicate);
+    }
+
+    pub(crate) fn datagram_saved(&mut self) {
+        self.saved_datagrams += 1;
+        self.diag.saved += 1;
+    }
+
     /// # Panics
     ///
     /// When preconditions are violated.
     pub fn add_pto_count(&mut self, count: usize) {
         debug_assert!(count > 0);
+        self.pto_count += 1;
         if count >= MAX_PTO_COUNTS {
             // We can't move this count any further, so stop.
             return;
@@ -191,6 +638,64 @@ impl Stats {
             self.pto_counts[count - 2] -= 1;
         }
     }
+
+    /// The counts for the interval since `previous` was taken from the same
+    /// connection.  Counters are subtracted, saturating at zero, and snapshot
+    /// values are taken from `self`.  For `pto_counts`, which is not a simple
+    /// counter, the difference is only approximate.
+    #[must_use]
+    pub fn delta(&self, previous: &Self) -> Self {
+        let mut pto_counts = [0; MAX_PTO_COUNTS];
+        for (d, (now, prev)) in pto_counts
+            .iter_mut()
+            .zip(self.pto_counts.iter().zip(&previous.pto_counts))
+        {
+            *d = now.saturating_sub(*prev);
+        }
+        Self {
+            info: self.info.clone(),
+            diag: ProcessDiag::default(),
+            datagrams_rx: self.datagrams_rx.saturating_sub(previous.datagrams_rx),
+            packets_rx: self.packets_rx.saturating_sub(previous.packets_rx),
+            bytes_rx: self.bytes_rx.delta(&previous.bytes_rx),
+            dups_rx: self.dups_rx.saturating_sub(previous.dups_rx),
+            dropped_rx: self.dropped_rx.saturating_sub(previous.dropped_rx),
+            key_phase_failures_rx: self
+                .key_phase_failures_rx
+                .saturating_sub(previous.key_phase_failures_rx),
+            decrypt_failures_rx: self
+                .decrypt_failures_rx
+                .saturating_sub(previous.decrypt_failures_rx),
+            saved_datagrams: self
+                .saved_datagrams
+                .saturating_sub(previous.saved_datagrams),
+            datagrams_tx: self.datagrams_tx.saturating_sub(previous.datagrams_tx),
+            packets_tx: self.packets_tx.saturating_sub(previous.packets_tx),
+            bytes_tx: self.bytes_tx.delta(&previous.bytes_tx),
+            pmtu: self.pmtu,
+            ack_only_tx: self.ack_only_tx.saturating_sub(previous.ack_only_tx),
+            lost: self.lost.saturating_sub(previous.lost),
+            acked: self.acked.saturating_sub(previous.acked),
+            late_ack: self.late_ack.saturating_sub(previous.late_ack),
+            pto_ack: self.pto_ack.saturating_sub(previous.pto_ack),
+            resumed: self.resumed,
+            rtt: self.rtt,
+            rttvar: self.rttvar,
+            rtt_init_guess: self.rtt_init_guess,
+            cwnd: self.cwnd,
+            bytes_in_flight: self.bytes_in_flight,
+            pto_count: self.pto_count.saturating_sub(previous.pto_count),
+            pto_counts,
+            frame_rx: self.frame_rx.delta(&previous.frame_rx),
+            frame_tx: self.frame_tx.delta(&previous.frame_tx),
+            incoming_datagram_dropped: self
+                .incoming_datagram_dropped
+                .saturating_sub(previous.incoming_datagram_dropped),
+            datagram_tx: self.datagram_tx.delta(&previous.datagram_tx),
+            ecn: self.ecn.delta(&previous.ecn),
+            timings: self.timings.clone(),
+        }
+    }
 }
 
 impl Debug for Stats {
@@ -198,15 +703,47 @@ impl Debug for Stats {
         writeln!(f, "stats for {}", self.info)?;
         writeln!(
             f,
-            "  rx: {} drop {} dup {} saved {}",
-            self.packets_rx, self.dropped_rx, self.dups_rx, self.saved_datagrams
+            "  rx: {} drop {} dup {} saved {} datagrams {} bytes {:?}",
+            self.packets_rx,
+            self.dropped_rx,
+            self.dups_rx,
+            self.saved_datagrams,
+            self.datagrams_rx,
+            self.bytes_rx
         )?;
         writeln!(
             f,
-            "  tx: {} lost {} lateack {} ptoack {}",
-            self.packets_tx, self.lost, self.late_ack, self.pto_ack
+            "  decrypt failures: key phase {} other {}",
+            self.key_phase_failures_rx, self.decrypt_failures_rx
+        )?;
+        writeln!(
+            f,
+            "  tx: {} ackonly {} acked {} lost {} lateack {} ptoack {} pmtu {}",
+            self.packets_tx,
+            self.ack_only_tx,
+            self.acked,
+            self.lost,
+            self.late_ack,
+            self.pto_ack,
+            self.pmtu
+        )?;
+        writeln!(
+            f,
+            "  tx: datagrams {} bytes {:?}",
+            self.datagrams_tx, self.bytes_tx
+        )?;
+        writeln!(
+            f,
+            "  cc: cwnd {} in flight {} pto {}",
+            self.cwnd, self.bytes_in_flight, self.pto_count
         )?;
         writeln!(f, "  resumed: {}", self.resumed)?;
+        writeln!(f, "  timings: {:?}", self.timings)?;
+        writeln!(
+            f,
+            "  ecn: tx marked {} rx {:?} validation {:?}",
+            self.ecn.tx_marked, self.ecn.rx, self.ecn.validation
+        )?;
         writeln!(f, "  frames rx:")?;
         self.frame_rx.fmt(f)?;
         writeln!(f, "  frames tx:")?;
@@ -232,3 +769,135 @@ impl Debug for StatsCell {
         self.stats.borrow().fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use neqo_common::IpTosEcn;
+
+    use super::{FrameStats, SpaceStats, Stats};
+    use crate::{ecn::EcnValidationOutcome, tracking::PacketNumberSpace};
+
+    fn sample() -> Stats {
+        let mut s = Stats::default();
+        s.init("sample".to_string());
+        s.datagrams_rx = 10;
+        s.packets_rx = 12;
+        s.bytes_rx.add(PacketNumberSpace::Initial, 1200);
+        s.bytes_rx.add(PacketNumberSpace::ApplicationData, 500);
+        s.datagrams_tx = 8;
+        s.packets_tx = 9;
+        s.bytes_tx.add(PacketNumberSpace::Handshake, 300);
+        s.lost = 2;
+        s.late_ack = 1;
+        s.add_pto_count(1);
+        s.add_pto_count(2);
+        s.rtt = Duration::from_millis(40);
+        s.cwnd = 12_000;
+        s.bytes_in_flight = 3_000;
+        s.frame_rx.stream = 4;
+        s.frame_tx.ack = 5;
+        s.ecn.rx[IpTosEcn::Ect0] = 6;
+        s.ecn.validation = EcnValidationOutcome::Capable;
+        s
+    }
+
+    #[test]
+    fn delta() {
+        let previous = sample();
+        let mut current = sample();
+        current.datagrams_rx += 3;
+        current
+            .bytes_rx
+            .add(PacketNumberSpace::ApplicationData, 100);
+        current.add_pto_count(1);
+        current.frame_rx.stream += 2;
+        current.frame_rx.largest_acknowledged = 17;
+        current.ecn.rx[IpTosEcn::Ect0] += 1;
+        current.rtt = Duration::from_millis(30);
+        current.cwnd = 20_000;
+
+        let d = current.delta(&previous);
+        assert_eq!(d.datagrams_rx, 3);
+        assert_eq!(d.packets_rx, 0);
+        assert_eq!(
+            d.bytes_rx,
+            SpaceStats {
+                application_data: 100,
+                ..SpaceStats::default()
+            }
+        );
+        assert_eq!(d.pto_count, 1);
+        assert_eq!(d.frame_rx.stream, 2);
+        assert_eq!(d.ecn.rx[IpTosEcn::Ect0], 1);
+        // Snapshots come from the newer value.
+        assert_eq!(d.frame_rx.largest_acknowledged, 17);
+        assert_eq!(d.rtt, Duration::from_millis(30));
+        assert_eq!(d.cwnd, 20_000);
+        assert_eq!(d.bytes_in_flight, 3_000);
+        assert_eq!(d.ecn.validation, EcnValidationOutcome::Capable);
+    }
+
+    #[test]
+    fn delta_saturates() {
+        // Taking the delta against a newer snapshot doesn't underflow.
+        let d = Stats::default().delta(&sample());
+        assert_eq!(d.datagrams_rx, 0);
+        assert_eq!(d.bytes_rx.total(), 0);
+        assert_eq!(d.bytes_tx.total(), 0);
+        assert_eq!(d.pto_count, 0);
+        assert_eq!(d.pto_counts, [0; super::MAX_PTO_COUNTS]);
+        assert_eq!(d.frame_rx, super::FrameStats::default());
+        assert_eq!(d.ecn.rx[IpTosEcn::Ect0], 0);
+    }
+
+    #[test]
+    fn add_payload() {
+        // Two bytes of PADDING, a PING, then a frame of an unknown type.
+        // Nothing after the unknown frame can be decoded, so that isn't counted.
+        let mut f = FrameStats::default();
+        f.add_payload(&[0x00, 0x00, 0x01, 0x21, 0x01]);
+        assert_eq!(f.padding, 1);
+        assert_eq!(f.ping, 1);
+        assert_eq!(f.unknown, 1);
+        assert_eq!(f.all, 3);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        let v = serde_json::to_value(sample()).unwrap();
+        let obj = v.as_object().unwrap();
+        assert!(!obj.contains_key("info"));
+        for key in [
+            "datagrams_rx",
+            "packets_rx",
+            "datagrams_tx",
+            "packets_tx",
+            "pmtu",
+            "lost",
+            "late_ack",
+            "rtt",
+            "cwnd",
+            "bytes_in_flight",
+            "pto_count",
+            "pto_counts",
+        ] {
+            assert!(obj.contains_key(key), "missing {key}");
+        }
+        assert_eq!(v["datagrams_rx"], 10);
+        assert_eq!(v["bytes_rx"]["initial"], 1200);
+        assert_eq!(v["bytes_rx"]["application_data"], 500);
+        assert_eq!(v["bytes_tx"]["handshake"], 300);
+        assert_eq!(v["pto_count"], 2);
+        assert_eq!(v["pto_counts"][1], 1);
+        assert_eq!(v["cwnd"], 12_000);
+        assert_eq!(v["frame_rx"]["stream"], 4);
+        assert_eq!(v["frame_tx"]["ack"], 5);
+        assert_eq!(v["ecn"]["rx"]["ect0"], 6);
+        assert_eq!(v["ecn"]["validation"], "capable");
+        assert_eq!(v["rtt"]["secs"], 0);
+        assert_eq!(v["rtt"]["nanos"], 40_000_000);
+    }
+}
@@ -5,20 +5,22 @@
 // except according to those terms.
 
 // Stream management for a connection.
-use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+use std::{cell::RefCell, cmp::Ordering, mem, rc::Rc};
 
 use neqo_common::{qtrace, qwarn, Role};
 
 use crate::{
-    fc::{LocalStreamLimits, ReceiverFlowControl, RemoteStreamLimits, SenderFlowControl},
+    fc::{
+        FlowControlState, LocalStreamLimits, ReceiverFlowControl, RemoteStreamLimits,
+        SenderFlowControl,
+    },
     frame::Frame,
     packet::PacketBuilder,
     recovery::{RecoveryToken, StreamRecoveryToken},
     recv_stream::{RecvStream, RecvStreams},
     send_stream::{SendStream, SendStreams, TransmissionPriority},
-    stats::FrameStats,
     stream_id::{StreamId, StreamType},
-    tparams::{self, TransportParametersHandler},
+    tparams::{self, TransportParameterId, TransportParametersHandler},
     ConnectionEvents, Error, Res,
 };
 
@@ -65,6 +67,27 @@ pub struct Streams {
     local_stream_limits: LocalStreamLimits,
     pub(crate) send: SendStreams,
     pub(crate) recv: RecvStreams,
+    /// Streams opened by the peer that haven't been collected with `take_new_streams`.
+    /// This is `None` unless `track_new_streams` was called.
+    new_streams: Option<Vec<StreamId>>,
+    /// Limits on receiving stream data that replace those in the local transport
+    /// parameters, for each of those parameters; see `raise_recv_limits`.
+    raised_recv_limits: Vec<(TransportParameterId, u64)>,
+}
+
+/// The local transport parameter that sets how much data the peer can initially
+/// send on a stream.
+fn recv_limit_param(stream_id: StreamId, role: Role) -> TransportParameterId {
+    match stream_id.stream_type() {
+        // A BiDi stream opened by the peer is local-originated from the peer's
+        // perspective, so INITIAL_MAX_STREAM_DATA_BIDI_REMOTE applies, and the
+        // reverse for a BiDi stream that this endpoint opened.
+        StreamType::BiDi if stream_id.is_remote_initiated(role) => {
+            tparams::INITIAL_MAX_STREAM_DATA_BIDI_REMOTE
+        }
+        StreamType::BiDi => tparams::INITIAL_MAX_STREAM_DATA_BIDI_LOCAL,
+        StreamType::UniDi => tparams::INITIAL_MAX_STREAM_DATA_UNI,
+    }
 }
 
 impl Streams {
@@ -92,7 +115,53 @@ impl Streams {
             local_stream_limits: LocalStreamLimits::new(role),
             send: SendStreams::default(),
             recv: RecvStreams::default(),
+            new_streams: None,
+            raised_recv_limits: Vec::new(),
+        }
+    }
+
+    fn new_recv_stream(&self, stream_id: StreamId) -> RecvStream {
+        let tp = recv_limit_param(stream_id, self.role);
+        let initial = self.tps.borrow().local.get_integer(tp);
+        let mut stream = RecvStream::new(
+            stream_id,
+            initial,
+            Rc::clone(&self.receiver_fc),
+            self.events.clone(),
+        );
+        if let Some(&(_, limit)) = self.raised_recv_limits.iter().find(|(t, _)| *t == tp) {
+            if limit > initial {
+                stream.set_stream_max_data(limit);
+            }
         }
+        stream
+    }
+
+    /// Raise the limits on receiving data above what the local transport parameters
+    /// allowed.  `stream_limits` has the new value for each of the stream data
+    /// parameters.  Streams that exist are updated now, and new streams as they are
+    /// created, each of which sends `MAX_STREAM_DATA` to tell the peer.
+    pub fn raise_recv_limits(
+        &mut self,
+        max_data: u64,
+        stream_limits: &[(TransportParameterId, u64)],
+    ) {
+        {
+            let mut fc = self.receiver_fc.borrow_mut();
+            if fc.max_active() < max_data {
+                fc.set_max_active(max_data);
+            }
+        }
+        self.raised_recv_limits = stream_limits.to_vec();
+        let role = self.role;
+        self.recv.for_each_mut(|stream_id, stream| {
+            let tp = recv_limit_param(stream_id, role);
+            if let Some(&(_, limit)) = stream_limits.iter().find(|(t, _)| *t == tp) {
+                if stream.fc().is_some_and(|fc| fc.max_active() < limit) {
+                    stream.set_stream_max_data(limit);
+                }
+            }
+        });
     }
 
     #[must_use]
@@ -121,14 +190,13 @@ impl Streams {
 
     /// # Errors
     /// When the frame is invalid.
-    pub fn input_frame(&mut self, frame: &Frame, stats: &mut FrameStats) -> Res<()> {
+    pub fn input_frame(&mut self, frame: &Frame) -> Res<()> {
         match frame {
             Frame::ResetStream {
                 stream_id,
                 application_error_code,
                 final_size,
             } => {
-                stats.reset_stream += 1;
                 if let (_, Some(rs)) = self.obtain_stream(*stream_id)? {
                     rs.reset(*application_error_code, *final_size)?;
                 }
@@ -137,7 +205,6 @@ impl Streams {
                 stream_id,
                 application_error_code,
             } => {
-                stats.stop_sending += 1;
                 self.events
                     .send_stream_stop_sending(*stream_id, *application_error_code);
                 if let (Some(ss), _) = self.obtain_stream(*stream_id)? {
@@ -151,13 +218,11 @@ impl Streams {
                 data,
                 ..
             } => {
-                stats.stream += 1;
                 if let (_, Some(rs)) = self.obtain_stream(*stream_id)? {
                     rs.inbound_stream_frame(*fin, *offset, data)?;
                 }
             }
             Frame::MaxData { maximum_data } => {
-                stats.max_data += 1;
                 self.handle_max_data(*maximum_data);
             }
             Frame::MaxStreamData {
@@ -169,7 +234,6 @@ impl Streams {
                     *stream_id,
                     *maximum_stream_data
                 );
-                stats.max_stream_data += 1;
                 if let (Some(ss), _) = self.obtain_stream(*stream_id)? {
                     ss.set_max_stream_data(*maximum_stream_data);
                 }
@@ -178,18 +242,15 @@ impl Streams {
                 stream_type,
                 maximum_streams,
             } => {
-                stats.max_streams += 1;
                 self.handle_max_streams(*stream_type, *maximum_streams);
             }
             Frame::DataBlocked { data_limit } => {
                 // Should never happen since we set data limit to max
                 qwarn!("Received DataBlocked with data limit {}", data_limit);
-                stats.data_blocked += 1;
                 self.handle_data_blocked();
             }
             Frame::StreamDataBlocked { stream_id, .. } => {
                 qtrace!("Received StreamDataBlocked");
-                stats.stream_data_blocked += 1;
                 // Terminate connection with STREAM_STATE_ERROR if send-only
                 // stream (-transport 19.13)
                 if stream_id.is_send_only(self.role) {
@@ -201,7 +262,6 @@ impl Streams {
                 }
             }
             Frame::StreamsBlocked { .. } => {
-                stats.streams_blocked += 1;
                 // We send an update evry time we retire a stream. There is no need to
                 // trigger flow updates here.
             }
@@ -214,41 +274,36 @@ impl Streams {
         &mut self,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
         // Send `DATA_BLOCKED` as necessary.
-        self.sender_fc
-            .borrow_mut()
-            .write_frames(builder, tokens, stats);
+        self.sender_fc.borrow_mut().write_frames(builder, tokens);
         if builder.is_full() {
             return;
         }
 
         // Send `MAX_DATA` as necessary.
-        self.receiver_fc
-            .borrow_mut()
-            .write_frames(builder, tokens, stats);
+        self.receiver_fc.borrow_mut().write_frames(builder, tokens);
         if builder.is_full() {
             return;
         }
 
-        self.recv.write_frames(builder, tokens, stats);
+        self.recv.write_frames(builder, tokens);
 
-        self.remote_stream_limits[StreamType::BiDi].write_frames(builder, tokens, stats);
+        self.remote_stream_limits[StreamType::BiDi].write_frames(builder, tokens);
         if builder.is_full() {
             return;
         }
-        self.remote_stream_limits[StreamType::UniDi].write_frames(builder, tokens, stats);
+        self.remote_stream_limits[StreamType::UniDi].write_frames(builder, tokens);
         if builder.is_full() {
             return;
         }
 
-        self.local_stream_limits[StreamType::BiDi].write_frames(builder, tokens, stats);
+        self.local_stream_limits[StreamType::BiDi].write_frames(builder, tokens);
         if builder.is_full() {
             return;
         }
 
-        self.local_stream_limits[StreamType::UniDi].write_frames(builder, tokens, stats);
+        self.local_stream_limits[StreamType::UniDi].write_frames(builder, tokens);
     }
 
     pub fn write_frames(
@@ -256,16 +311,15 @@ impl Streams {
         priority: TransmissionPriority,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
         if priority == TransmissionPriority::Important {
-            self.write_maintenance_frames(builder, tokens, stats);
+            self.write_maintenance_frames(builder, tokens);
             if builder.is_full() {
                 return;
             }
         }
 
-        self.send.write_frames(priority, builder, tokens, stats);
+        self.send.write_frames(priority, builder, tokens);
     }
 
     pub fn lost(&mut self, token: &StreamRecoveryToken) {
@@ -351,31 +405,16 @@ impl Streams {
             return Ok(());
         }
 
-        let tp = match stream_id.stream_type() {
-            // From the local perspective, this is a remote- originated BiDi stream. From
-            // the remote perspective, this is a local-originated BiDi stream. Therefore,
-            // look at the local transport parameters for the
-            // INITIAL_MAX_STREAM_DATA_BIDI_REMOTE value to decide how much this endpoint
-            // will allow its peer to send.
-            StreamType::BiDi => tparams::INITIAL_MAX_STREAM_DATA_BIDI_REMOTE,
-            StreamType::UniDi => tparams::INITIAL_MAX_STREAM_DATA_UNI,
-        };
-        let recv_initial_max_stream_data = self.tps.borrow().local.get_integer(tp);
-
         while self.remote_stream_limits[stream_id.stream_type()].is_new_stream(stream_id)? {
             let next_stream_id =
                 self.remote_stream_limits[stream_id.stream_type()].take_stream_id();
             self.events.new_stream(next_stream_id);
+            if let Some(new_streams) = &mut self.new_streams {
+                new_streams.push(next_stream_id);
+            }
 
-            self.recv.insert(
-                next_stream_id,
-                RecvStream::new(
-                    next_stream_id,
-                    recv_initial_max_stream_data,
-                    Rc::clone(&self.receiver_fc),
-                    self.events.clone(),
-                ),
-            );
+            let recv_stream = self.new_recv_stream(next_stream_id);
+            self.recv.insert(next_stream_id, recv_stream);
 
             if next_stream_id.is_bidi() {
                 // From the local perspective, this is a remote- originated BiDi stream.
@@ -449,26 +488,8 @@ impl Streams {
                 self.send.insert(new_id, stream);
 
                 if st == StreamType::BiDi {
-                    // From the local perspective, this is a local- originated BiDi stream. From the
-                    // remote perspective, this is a remote-originated BiDi stream. Therefore, look
-                    // at the local transport parameters for the
-                    // INITIAL_MAX_STREAM_DATA_BIDI_LOCAL value to decide how
-                    // much this endpoint will allow its peer to send.
-                    let recv_initial_max_stream_data = self
-                        .tps
-                        .borrow()
-                        .local
-                        .get_integer(tparams::INITIAL_MAX_STREAM_DATA_BIDI_LOCAL);
-
-                    self.recv.insert(
-                        new_id,
-                        RecvStream::new(
-                            new_id,
-                            recv_initial_max_stream_data,
-                            Rc::clone(&self.receiver_fc),
-                            self.events.clone(),
-                        ),
-                    );
+                    let recv_stream = self.new_recv_stream(new_id);
+                    self.recv.insert(new_id, recv_stream);
                 }
                 Ok(new_id)
             }
@@ -486,6 +507,33 @@ impl Streams {
         }
     }
 
+    /// Start keeping a list of the streams that the peer opens.
+    pub fn track_new_streams(&mut self) {
+        self.new_streams.get_or_insert_with(Vec::new);
+    }
+
+    /// Take the streams that the peer opened since the last call.
+    pub fn take_new_streams(&mut self) -> Vec<StreamId> {
+        self.new_streams.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    /// Bytes that were written to streams but not yet sent.
+    pub fn send_bytes_unsent(&self) -> u64 {
+        self.send.bytes_unsent()
+    }
+
+    pub fn reassembly_gaps(&self) -> Vec<(StreamId, u64)> {
+        self.recv.reassembly_gaps()
+    }
+
+    /// Report which flow control limits are preventing data from being sent.
+    pub fn flow_control_blocked(&self) -> FlowControlState {
+        FlowControlState {
+            connection: self.sender_fc.borrow().is_blocked(),
+            streams: self.send.fc_blocked(),
+        }
+    }
+
     pub fn handle_data_blocked(&mut self) {
         self.receiver_fc.borrow_mut().send_flowc_update();
     }
@@ -59,6 +59,8 @@ tpids! {
     GREASE_QUIC_BIT = 0x2ab2,
     MIN_ACK_DELAY = 0xff02_de1a,
     MAX_DATAGRAM_FRAME_SIZE = 0x0020,
+    // draft-ietf-quic-multipath-05
+    ENABLE_MULTIPATH = 0x0f73_9bbc_1b66_6d05,
 }
 
 #[derive(Clone, Debug)]
@@ -255,7 +257,12 @@ impl TransportParameter {
         let value = match tp {
             ORIGINAL_DESTINATION_CONNECTION_ID
             | INITIAL_SOURCE_CONNECTION_ID
-            | RETRY_SOURCE_CONNECTION_ID => Self::Bytes(d.decode_remainder().to_vec()),
+            | RETRY_SOURCE_CONNECTION_ID => {
+                if d.remaining() > MAX_CONNECTION_ID_LEN {
+                    return Err(Error::TransportParameterError);
+                }
+                Self::Bytes(d.decode_remainder().to_vec())
+            }
             STATELESS_RESET_TOKEN => {
                 if d.remaining() != 16 {
                     return Err(Error::TransportParameterError);
@@ -292,7 +299,7 @@ impl TransportParameter {
                 _ => return Err(Error::TransportParameterError),
             },
 
-            DISABLE_MIGRATION | GREASE_QUIC_BIT => Self::Empty,
+            DISABLE_MIGRATION | GREASE_QUIC_BIT | ENABLE_MULTIPATH => Self::Empty,
 
             PREFERRED_ADDRESS => Self::decode_preferred_address(&mut d)?,
 
@@ -443,7 +450,7 @@ impl TransportParameters {
     /// When the transport parameter isn't recognized as being empty.
     pub fn set_empty(&mut self, tp: TransportParameterId) {
         match tp {
-            DISABLE_MIGRATION | GREASE_QUIC_BIT => {
+            DISABLE_MIGRATION | GREASE_QUIC_BIT | ENABLE_MULTIPATH => {
                 self.set(tp, TransportParameter::Empty);
             }
             _ => panic!("Transport parameter not known or not type empty"),
@@ -23,7 +23,6 @@ use crate::{
     frame::{FRAME_TYPE_ACK, FRAME_TYPE_ACK_ECN},
     packet::{PacketBuilder, PacketNumber, PacketType},
     recovery::RecoveryToken,
-    stats::FrameStats,
 };
 
 // TODO(mt) look at enabling EnumMap for this: https://stackoverflow.com/a/44905797/1375574
@@ -256,6 +255,8 @@ pub struct RecvdPackets {
     largest_pn_time: Option<Instant>,
     /// The time that we should be sending an ACK.
     ack_time: Option<Instant>,
+    /// The time that the oldest unacknowledged ack-eliciting packet arrived.
+    ack_pending_since: Option<Instant>,
     /// The time we last sent an ACK.
     last_ack_time: Option<Instant>,
     /// The current ACK frequency sequence number.
@@ -285,6 +286,7 @@ impl RecvdPackets {
             min_tracked: 0,
             largest_pn_time: None,
             ack_time: None,
+            ack_pending_since: None,
             last_ack_time: None,
             ack_frequency_seqno: 0,
             ack_delay: DEFAULT_ACK_DELAY,
@@ -324,14 +326,23 @@ impl RecvdPackets {
         }
     }
 
+    /// Get the time at which an ACK needs to be sent, even if there is nothing
+    /// else to send.  This is the delayed ACK timer, except when more than an
+    /// RTT had passed since the last ACK was sent when the first unacknowledged
+    /// packet arrived; that packet is acknowledged immediately.
+    fn ack_deadline(&self, rtt: Duration) -> Option<Instant> {
+        let ack_time = self.ack_time?;
+        let overdue = self
+            .ack_pending_since
+            .zip(self.last_ack_time)
+            .filter(|&(since, last)| last + rtt <= since)
+            .map(|(since, _)| since);
+        Some(overdue.map_or(ack_time, |since| min(since, ack_time)))
+    }
+
     /// Returns true if an ACK frame should be sent now.
     fn ack_now(&self, now: Instant, rtt: Duration) -> bool {
-        // If ack_time is Some, then we have something to acknowledge.
-        // In that case, either ack because `now >= ack_time`, or
-        // because it is more than an RTT since the last time we sent an ack.
-        self.ack_time.map_or(false, |next| {
-            next <= now || self.last_ack_time.map_or(false, |last| last + rtt <= now)
-        })
+        self.ack_deadline(rtt).map_or(false, |t| t <= now)
     }
 
     // A simple addition of a packet number to the tracked set.
@@ -412,6 +423,7 @@ impl RecvdPackets {
             };
             qdebug!([self], "Set ACK timer to {:?}", ack_time);
             self.ack_time = Some(ack_time);
+            self.ack_pending_since.get_or_insert(now);
         }
         largest
     }
@@ -422,6 +434,15 @@ impl RecvdPackets {
         qdebug!([self], "immediate_ack at {:?}", now);
     }
 
+    /// A packet marked ECN-CE was received.  If there is anything to
+    /// acknowledge, do so immediately so that the peer can react to the
+    /// congestion signal without waiting for the delayed ACK timer.
+    pub fn ce_received(&mut self, now: Instant) {
+        if self.ack_time.is_some() {
+            self.immediate_ack(now);
+        }
+    }
+
     /// Check if the packet is a duplicate.
     pub fn is_duplicate(&self, pn: PacketNumber) -> bool {
         if pn < self.min_tracked {
@@ -468,13 +489,32 @@ impl RecvdPackets {
         rtt: Duration,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
         // Check that we aren't delaying ACKs.
-        if !self.ack_now(now, rtt) {
-            return;
+        if self.ack_now(now, rtt) {
+            self.encode_frame(now, builder, tokens);
         }
+    }
 
+    /// Add an ACK frame to a packet that is being sent anyway, if there is
+    /// anything that needs acknowledgment, even if the ACK isn't due yet.
+    fn write_piggyback_frame(
+        &mut self,
+        now: Instant,
+        builder: &mut PacketBuilder,
+        tokens: &mut Vec<RecoveryToken>,
+    ) {
+        if self.ack_time.is_some() {
+            self.encode_frame(now, builder, tokens);
+        }
+    }
+
+    fn encode_frame(
+        &mut self,
+        now: Instant,
+        builder: &mut PacketBuilder,
+        tokens: &mut Vec<RecoveryToken>,
+    ) {
         // Drop extra ACK ranges to fit the available space.  Do this based on
         // a worst-case estimate of frame size for simplicity.
         //
@@ -507,8 +547,6 @@ impl RecvdPackets {
         let mut iter = ranges.iter();
         let Some(first) = iter.next() else { return };
         builder.encode_varint(first.largest);
-        stats.largest_acknowledged = first.largest;
-        stats.ack += 1;
 
         let elapsed = now.duration_since(self.largest_pn_time.unwrap());
         // We use the default exponent, so delay is in multiples of 8 microseconds.
@@ -535,6 +573,7 @@ impl RecvdPackets {
 
         // We've sent an ACK, reset the timer.
         self.ack_time = None;
+        self.ack_pending_since = None;
         self.last_ack_time = Some(now);
         self.unacknowledged_count = 0;
 
@@ -602,13 +641,17 @@ impl AckTracker {
     }
 
     /// Determine the earliest time that an ACK might be needed.
-    pub fn ack_time(&self, now: Instant) -> Option<Instant> {
+    pub fn ack_time(&self, now: Instant, rtt: Duration) -> Option<Instant> {
         for recvd in &self.spaces {
-            qtrace!("ack_time for {} = {:?}", recvd.space, recvd.ack_time());
+            qtrace!(
+                "ack_time for {} = {:?}",
+                recvd.space,
+                recvd.ack_deadline(rtt)
+            );
         }
 
         if self.spaces.len() == 1 {
-            self.spaces[0].ack_time()
+            self.spaces[0].ack_deadline(rtt)
         } else {
             // Ignore any time that is in the past relative to `now`.
             // That is something of a hack, but there are cases where we can't send ACK
@@ -617,7 +660,7 @@ impl AckTracker {
             // be able to send ACK frames.
             self.spaces
                 .iter()
-                .filter_map(|recvd| recvd.ack_time().filter(|t| *t > now))
+                .filter_map(|recvd| recvd.ack_deadline(rtt).filter(|t| *t > now))
                 .min()
         }
     }
@@ -635,10 +678,22 @@ impl AckTracker {
         rtt: Duration,
         builder: &mut PacketBuilder,
         tokens: &mut Vec<RecoveryToken>,
-        stats: &mut FrameStats,
     ) {
         if let Some(space) = self.get_mut(pn_space) {
-            space.write_frame(now, rtt, builder, tokens, stats);
+            space.write_frame(now, rtt, builder, tokens);
+        }
+    }
+
+    /// Add any pending ACK for `pn_space` to a packet that carries other frames.
+    pub(crate) fn write_piggyback_frame(
+        &mut self,
+        pn_space: PacketNumberSpace,
+        now: Instant,
+        builder: &mut PacketBuilder,
+        tokens: &mut Vec<RecoveryToken>,
+    ) {
+        if let Some(space) = self.get_mut(pn_space) {
+            space.write_piggyback_frame(now, builder, tokens);
         }
     }
 }
@@ -664,12 +719,11 @@ mod tests {
 
     use super::{
         AckTracker, Duration, Instant, PacketNumberSpace, PacketNumberSpaceSet, RecoveryToken,
-        RecvdPackets, MAX_TRACKED_RANGES,
+        RecvdPackets, DEFAULT_ACK_DELAY, MAX_TRACKED_RANGES,
     };
     use crate::{
         frame::Frame,
         packet::{PacketBuilder, PacketNumber},
-        stats::FrameStats,
     };
 
     const RTT: Duration = Duration::from_millis(100);
@@ -799,11 +853,9 @@ mod tests {
 
     fn write_frame_at(rp: &mut RecvdPackets, now: Instant) {
         let mut builder = PacketBuilder::short(Encoder::new(), false, []);
-        let mut stats = FrameStats::default();
         let mut tokens = Vec::new();
-        rp.write_frame(now, RTT, &mut builder, &mut tokens, &mut stats);
-        assert!(!tokens.is_empty());
-        assert_eq!(stats.ack, 1);
+        rp.write_frame(now, RTT, &mut builder, &mut tokens);
+        assert_eq!(tokens.len(), 1);
     }
 
     fn write_frame(rp: &mut RecvdPackets) {
@@ -840,6 +892,57 @@ mod tests {
         write_frame_at(&mut rp, now() + RTT);
     }
 
+    #[test]
+    fn ack_deadline_after_rtt() {
+        let mut rp = RecvdPackets::new(PacketNumberSpace::ApplicationData);
+        rp.set_received(now(), 1, true);
+        write_frame(&mut rp);
+
+        // The delayed ACK timer is set, but the deadline is immediate
+        // because it has been an RTT since the last ACK.
+        rp.set_received(now() + RTT, 2, true);
+        assert_eq!(Some(now() + RTT + DEFAULT_ACK_DELAY), rp.ack_time());
+        assert_eq!(Some(now() + RTT), rp.ack_deadline(RTT));
+
+        // Within an RTT, the delayed ACK timer applies.
+        write_frame_at(&mut rp, now() + RTT);
+        rp.set_received(now() + RTT, 3, true);
+        assert_eq!(rp.ack_time(), rp.ack_deadline(RTT));
+        assert!(!rp.ack_now(now() + RTT, RTT));
+    }
+
+    #[test]
+    fn ce_immediate_ack() {
+        let mut rp = RecvdPackets::new(PacketNumberSpace::ApplicationData);
+        // Nothing to acknowledge.
+        rp.ce_received(now());
+        assert!(rp.ack_time().is_none());
+
+        rp.set_received(now(), 0, true);
+        assert_eq!(Some(now() + DEFAULT_ACK_DELAY), rp.ack_time());
+        rp.ce_received(now());
+        assert_eq!(Some(now()), rp.ack_time());
+    }
+
+    #[test]
+    fn piggyback_ack() {
+        let mut rp = RecvdPackets::new(PacketNumberSpace::ApplicationData);
+        let mut builder = PacketBuilder::short(Encoder::new(), false, []);
+        let mut tokens = Vec::new();
+
+        // Nothing is written if there is nothing to acknowledge.
+        rp.write_piggyback_frame(now(), &mut builder, &mut tokens);
+        assert!(tokens.is_empty());
+
+        // A delayed ACK is written when piggybacking.
+        rp.set_received(now(), 0, true);
+        rp.write_frame(now(), RTT, &mut builder, &mut tokens);
+        assert!(tokens.is_empty());
+        rp.write_piggyback_frame(now(), &mut builder, &mut tokens);
+        assert_eq!(tokens.len(), 1);
+        assert!(rp.ack_time().is_none());
+    }
+
     #[test]
     fn ooo_no_ack_delay_threshold_new() {
         let mut rp = RecvdPackets::new(PacketNumberSpace::ApplicationData);
@@ -918,14 +1021,14 @@ mod tests {
             .get_mut(PacketNumberSpace::Handshake)
             .unwrap()
             .set_received(now(), 0, false);
-        assert_eq!(None, tracker.ack_time(now()));
+        assert_eq!(None, tracker.ack_time(now(), RTT));
 
         // This should be delayed.
         tracker
             .get_mut(PacketNumberSpace::ApplicationData)
             .unwrap()
             .set_received(now(), 0, true);
-        assert_eq!(Some(now() + DELAY), tracker.ack_time(now()));
+        assert_eq!(Some(now() + DELAY), tracker.ack_time(now(), RTT));
 
         // This should move the time forward.
         let later = now() + (DELAY / 2);
@@ -933,7 +1036,7 @@ mod tests {
             .get_mut(PacketNumberSpace::Initial)
             .unwrap()
             .set_received(later, 0, true);
-        assert_eq!(Some(later), tracker.ack_time(now()));
+        assert_eq!(Some(later), tracker.ack_time(now(), RTT));
     }
 
     #[test]
@@ -960,20 +1063,18 @@ mod tests {
             .set_received(now(), 0, true);
         // The reference time for `ack_time` has to be in the past or we filter out the timer.
         assert!(tracker
-            .ack_time(now().checked_sub(Duration::from_millis(1)).unwrap())
+            .ack_time(now().checked_sub(Duration::from_millis(1)).unwrap(), RTT)
             .is_some());
 
         let mut tokens = Vec::new();
-        let mut stats = FrameStats::default();
         tracker.write_frame(
             PacketNumberSpace::Initial,
             now(),
             RTT,
             &mut builder,
             &mut tokens,
-            &mut stats,
         );
-        assert_eq!(stats.ack, 1);
+        assert_eq!(tokens.len(), 1);
 
         // Mark another packet as received so we have cause to send another ACK in that space.
         tracker
@@ -981,7 +1082,7 @@ mod tests {
             .unwrap()
             .set_received(now(), 1, true);
         assert!(tracker
-            .ack_time(now().checked_sub(Duration::from_millis(1)).unwrap())
+            .ack_time(now().checked_sub(Duration::from_millis(1)).unwrap(), RTT)
             .is_some());
 
         // Now drop that space.
@@ -989,7 +1090,7 @@ mod tests {
 
         assert!(tracker.get_mut(PacketNumberSpace::Initial).is_none());
         assert!(tracker
-            .ack_time(now().checked_sub(Duration::from_millis(1)).unwrap())
+            .ack_time(now().checked_sub(Duration::from_millis(1)).unwrap(), RTT)
             .is_none());
         tracker.write_frame(
             PacketNumberSpace::Initial,
@@ -997,9 +1098,8 @@ mod tests {
             RTT,
             &mut builder,
             &mut tokens,
-            &mut stats,
         );
-        assert_eq!(stats.ack, 1);
+        assert_eq!(tokens.len(), 1);
         if let RecoveryToken::Ack(tok) = &tokens[0] {
             tracker.acked(tok); // Should be a noop.
         } else {
@@ -1015,22 +1115,21 @@ mod tests {
             .unwrap()
             .set_received(now(), 0, true);
         assert!(tracker
-            .ack_time(now().checked_sub(Duration::from_millis(1)).unwrap())
+            .ack_time(now().checked_sub(Duration::from_millis(1)).unwrap(), RTT)
             .is_some());
 
         let mut builder = PacketBuilder::short(Encoder::new(), false, []);
         builder.set_limit(10);
 
-        let mut stats = FrameStats::default();
+        let mut tokens = Vec::new();
         tracker.write_frame(
             PacketNumberSpace::Initial,
             now(),
             RTT,
             &mut builder,
-            &mut Vec::new(),
-            &mut stats,
+            &mut tokens,
         );
-        assert_eq!(stats.ack, 0);
+        assert!(tokens.is_empty());
         assert_eq!(builder.len(), 1); // Only the short packet header has been added.
     }
 
@@ -1046,7 +1145,7 @@ mod tests {
             .unwrap()
             .set_received(now(), 2, true);
         assert!(tracker
-            .ack_time(now().checked_sub(Duration::from_millis(1)).unwrap())
+            .ack_time(now().checked_sub(Duration::from_millis(1)).unwrap(), RTT)
             .is_some());
 
         let mut builder = PacketBuilder::short(Encoder::new(), false, []);
@@ -1054,16 +1153,15 @@ mod tests {
         // So this won't be enough for a second range.
         builder.set_limit(RecvdPackets::USEFUL_ACK_LEN + 8);
 
-        let mut stats = FrameStats::default();
+        let mut tokens = Vec::new();
         tracker.write_frame(
             PacketNumberSpace::Initial,
             now(),
             RTT,
             &mut builder,
-            &mut Vec::new(),
-            &mut stats,
+            &mut tokens,
         );
-        assert_eq!(stats.ack, 1);
+        assert_eq!(tokens.len(), 1);
 
         let mut dec = builder.as_decoder();
         _ = dec.decode_byte().unwrap(); // Skip the short header.
@@ -1085,13 +1183,15 @@ mod tests {
             .get_mut(PacketNumberSpace::ApplicationData)
             .unwrap()
             .set_received(now(), 3, true);
-        assert!(tracker.ack_time(now() + Duration::from_millis(1)).is_none());
+        assert!(tracker
+            .ack_time(now() + Duration::from_millis(1), RTT)
+            .is_none());
 
         // When we are reduced to one space, that filter is off.
         tracker.drop_space(PacketNumberSpace::Initial);
         tracker.drop_space(PacketNumberSpace::Handshake);
         assert_eq!(
-            tracker.ack_time(now() + Duration::from_millis(1)),
+            tracker.ack_time(now() + Duration::from_millis(1), RTT),
             Some(now())
         );
     }
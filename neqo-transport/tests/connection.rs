@@ -6,17 +6,21 @@
 
 mod common;
 
-use neqo_common::{Datagram, Decoder, Encoder, Role};
+use neqo_common::{
+    qlog::{Importance, NeqoQlog, QlogFilter, QlogFormat, QlogVersion},
+    Datagram, Decoder, Encoder, Role,
+};
 use neqo_transport::{
-    CloseReason, ConnectionParameters, Error, State, Version, MIN_INITIAL_PACKET_SIZE,
+    CloseReason, ConnectionParameters, Error, State, StreamType, Version, MIN_INITIAL_PACKET_SIZE,
 };
+use serde_json::Value;
 use test_fixture::{
     default_client, default_server,
     header_protection::{
         apply_header_protection, decode_initial_header, initial_aead_and_hp,
         remove_header_protection,
     },
-    new_client, now, split_datagram,
+    new_client, now, split_datagram, SharedVec,
 };
 
 #[test]
@@ -24,6 +28,178 @@ fn connect() {
     let (_client, _server) = test_fixture::connect();
 }
 
+/// Run a handshake with the client writing qlog in the given format and version.
+fn handshake_qlog(format: QlogFormat, version: QlogVersion) -> String {
+    let contents = SharedVec::default();
+    let log = NeqoQlog::enabled_with_version(
+        Role::Client,
+        format,
+        version,
+        Box::new(contents.clone()),
+        "client trace",
+    )
+    .unwrap();
+    let mut client = default_client();
+    let mut server = default_server();
+    client.set_qlog(log);
+    test_fixture::handshake(&mut client, &mut server);
+    drop(client);
+    contents.to_string()
+}
+
+/// Run a handshake and send some data, with the client filtering qlog events.
+/// Returns the trace from the client.
+fn transfer_qlog(filter: QlogFilter) -> String {
+    let contents = SharedVec::default();
+    let log = NeqoQlog::enabled_with_writer(
+        Role::Client,
+        QlogFormat::JsonSeq,
+        Box::new(contents.clone()),
+        "client trace",
+    )
+    .unwrap();
+    let mut client = default_client();
+    let mut server = default_server();
+    client.set_qlog(log);
+    client.set_qlog_filter(filter);
+    test_fixture::handshake(&mut client, &mut server);
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, &[0; 5000]).unwrap();
+    client.stream_close_send(stream_id).unwrap();
+    while let Some(d) = client.process_output(now()).dgram() {
+        server.process_input(&d, now());
+    }
+    while let Some(d) = server.process_output(now()).dgram() {
+        client.process_input(&d, now());
+    }
+    drop(client);
+    contents.to_string()
+}
+
+fn qlog_event_names(contents: &str) -> Vec<String> {
+    contents
+        .split_terminator('\n')
+        .skip(1) // The header.
+        .map(|r| {
+            let ev = serde_json::from_str::<Value>(r.strip_prefix('\u{1e}').unwrap()).unwrap();
+            ev["name"].as_str().unwrap().to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn qlog_filter() {
+    let full = transfer_qlog(QlogFilter::default());
+    let filtered = transfer_qlog(QlogFilter {
+        transport: Importance::Off,
+        ..QlogFilter::default()
+    });
+
+    let is_packet = |name: &String| name.starts_with("transport:packet_");
+    let full_names = qlog_event_names(&full);
+    assert!(full_names.iter().any(is_packet));
+    let filtered_names = qlog_event_names(&filtered);
+    assert!(!filtered_names.iter().any(is_packet));
+    assert!(filtered_names
+        .iter()
+        .any(|name| name == "recovery:metrics_updated"));
+    assert!(filtered_names
+        .iter()
+        .any(|name| name == "connectivity:connection_state_updated"));
+    assert!(filtered.len() * 4 < full.len());
+}
+
+/// Check the structure of some events against what qvis expects.
+fn check_qlog_events(events: &[Value]) {
+    for ev in events {
+        assert!(ev["time"].is_number());
+        assert!(ev["name"].is_string());
+        assert!(ev["data"].is_object());
+    }
+    let find = |name: &str| {
+        events
+            .iter()
+            .find(|ev| ev["name"] == name)
+            .map(|ev| &ev["data"])
+            .unwrap()
+    };
+    let started = find("connectivity:connection_started");
+    assert_eq!(started["protocol"], "QUIC");
+    assert!(started["src_port"].is_number());
+    let sent = find("transport:packet_sent");
+    assert_eq!(sent["header"]["packet_type"], "initial");
+    assert!(sent["raw"]["length"].is_number());
+    assert!(sent["frames"][0]["frame_type"].is_string());
+    let received = find("transport:packet_received");
+    assert!(received["header"]["packet_number"].is_number());
+    assert!(events
+        .iter()
+        .any(|ev| ev["name"] == "connectivity:connection_state_updated"
+            && ev["data"]["new"] == "handshake_confirmed"));
+}
+
+/// A trace can be written to any writer, not just a file.
+#[test]
+fn qlog_json_seq() {
+    let contents = handshake_qlog(QlogFormat::JsonSeq, QlogVersion::V0_3);
+    // Every record is complete on its own.
+    let mut records = contents
+        .split_terminator('\n')
+        .map(|r| serde_json::from_str::<Value>(r.strip_prefix('\u{1e}').unwrap()).unwrap())
+        .collect::<Vec<_>>();
+    let header = records.remove(0);
+    assert_eq!(header["qlog_format"], "JSON-SEQ");
+    assert_eq!(header["title"], "client trace");
+    assert_eq!(header["trace"]["vantage_point"]["type"], "client");
+    check_qlog_events(&records);
+}
+
+#[test]
+fn qlog_json() {
+    let contents = handshake_qlog(QlogFormat::Json, QlogVersion::V0_3);
+    let doc = serde_json::from_str::<Value>(&contents).unwrap();
+    assert_eq!(doc["qlog_format"], "JSON");
+    assert_eq!(doc["title"], "client trace");
+    let trace = &doc["traces"][0];
+    assert_eq!(trace["vantage_point"]["type"], "client");
+    check_qlog_events(trace["events"].as_array().unwrap());
+}
+
+/// The newer qlog vocabulary renames events, but doesn't add or remove any.
+#[test]
+fn qlog_version() {
+    let records = |version| {
+        handshake_qlog(QlogFormat::JsonSeq, version)
+            .split_terminator('\n')
+            .map(|r| serde_json::from_str::<Value>(r.strip_prefix('\u{1e}').unwrap()).unwrap())
+            .collect::<Vec<_>>()
+    };
+    let old = records(QlogVersion::V0_3);
+    let new = records(QlogVersion::Draft10);
+    assert_eq!(old[0]["qlog_version"], "0.3");
+    assert_eq!(new[0]["qlog_version"], "draft-10");
+    assert_eq!(old.len(), new.len());
+
+    let count =
+        |records: &[Value], name: &str| records.iter().filter(|ev| ev["name"] == name).count();
+    for (old_name, new_name) in [
+        ("transport:packet_sent", "quic:packet_sent"),
+        ("recovery:metrics_updated", "quic:recovery_metrics_updated"),
+        (
+            "connectivity:connection_state_updated",
+            "quic:connection_state_updated",
+        ),
+    ] {
+        assert_ne!(count(&old, old_name), 0);
+        assert_eq!(count(&old, old_name), count(&new, new_name));
+        assert_eq!(count(&new, old_name), 0);
+    }
+    assert!(new[1..]
+        .iter()
+        .all(|ev| ev["name"].as_str().unwrap().starts_with("quic:")));
+}
+
 #[test]
 fn truncate_long_packet() {
     let mut client = default_client();
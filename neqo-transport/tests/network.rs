@@ -4,17 +4,30 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{ops::Range, time::Duration};
+use std::{
+    cell::RefCell,
+    net::SocketAddr,
+    ops::Range,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
-use neqo_transport::{CloseReason, ConnectionParameters, Error, State};
+use neqo_common::Role;
+use neqo_transport::{
+    CloseReason, Connection, ConnectionEvent, ConnectionParameters, Error, FrameStats, State,
+    StreamId, StreamType, MAX_PATHS,
+};
 use test_fixture::{
-    boxed,
+    boxed, new_neqo_qlog,
     sim::{
-        connection::{ConnectionNode, ReachState, ReceiveData, SendData},
-        network::{Delay, Drop, TailDrop},
+        connection::{
+            ConnectionGoal, ConnectionNode, GoalStatus, Migrate, PathLimit, ReachMtu, ReachState,
+            ReceiveData, SendData, UpdateKeys,
+        },
+        network::{Delay, Drop, Mtu, PortRestore, PortSpray, TailDrop},
         Simulator,
     },
-    simulate,
+    simulate, DEFAULT_ADDR,
 };
 
 /// The amount of transfer.  Much more than this takes a surprising amount of time.
@@ -23,6 +36,8 @@ const ZERO: Duration = Duration::from_millis(0);
 const DELAY: Duration = Duration::from_millis(50);
 const DELAY_RANGE: Range<Duration> = DELAY..Duration::from_millis(55);
 const JITTER: Duration = Duration::from_millis(10);
+/// The IP MTU of an Ethernet link.
+const ETHERNET_MTU: usize = 1500;
 
 const fn weeks(m: u32) -> Duration {
     Duration::from_secs((m as u64) * 60 * 60 * 24 * 7)
@@ -196,3 +211,553 @@ fn transfer_fixed_seed() {
     sim.seed_str("117f65d90ee5c1a7fb685f3af502c7730ba5d31866b758d98f5e3c2117cf9b86");
     sim.run();
 }
+
+/// Both endpoints update keys frequently while the network reorders packets
+/// heavily, so plenty of packets arrive on the other side of a key update.
+#[test]
+fn transfer_key_update_reordered() {
+    let mut sim = Simulator::new(
+        "transfer_key_update_reordered",
+        boxed![
+            ConnectionNode::default_client(boxed![
+                UpdateKeys::new(50),
+                SendData::new(TRANSFER_AMOUNT)
+            ]),
+            Delay::new(ZERO..DELAY),
+            ConnectionNode::default_server(boxed![
+                UpdateKeys::new(10),
+                ReceiveData::new(TRANSFER_AMOUNT)
+            ]),
+            Delay::new(ZERO..DELAY),
+        ],
+    );
+    sim.seed_str("4d0f4cf2d4a0c7b6d3b1e2f17d5ac06d4e9f1b0c2a6e8d7c3b5a49f0e1d2c3b4");
+    sim.run();
+}
+
+/// The client migrates to a new local address part way through a transfer.
+/// Its qlog should record the new path being assigned, validated, and then adopted.
+#[test]
+fn migrate_qlog_path_events() {
+    let local = SocketAddr::new(DEFAULT_ADDR.ip(), DEFAULT_ADDR.port() + 1);
+    let (log, contents) = new_neqo_qlog();
+    let mut client =
+        ConnectionNode::default_client(boxed![Migrate::new(local), SendData::new(TRANSFER_AMOUNT)]);
+    client.set_qlog(log);
+    Simulator::new(
+        "migrate_qlog_path_events",
+        boxed![
+            client,
+            Delay::new(DELAY_RANGE),
+            ConnectionNode::default_server(boxed![ReceiveData::new(TRANSFER_AMOUNT)]),
+            Delay::new(DELAY_RANGE),
+        ],
+    )
+    .run();
+
+    let events = contents
+        .to_string()
+        .split('\x1e')
+        .filter_map(|record| serde_json::from_str::<serde_json::Value>(record).ok())
+        .filter(|ev| {
+            ev["name"]
+                .as_str()
+                .is_some_and(|name| name.starts_with("connectivity:path"))
+        })
+        .collect::<Vec<_>>();
+    let trigger = |ev: &serde_json::Value| ev["data"]["trigger"].as_str().map(String::from);
+
+    // The handshake path is assigned first.
+    assert_eq!(events[0]["name"], "connectivity:path_assigned");
+    assert_eq!(trigger(&events[0]).as_deref(), Some("handshake"));
+
+    // Then the migration proceeds in order.
+    let migration = events
+        .iter()
+        .skip_while(|ev| trigger(ev).as_deref() != Some("application"))
+        .map(|ev| ev["name"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        migration,
+        [
+            "connectivity:path_assigned",
+            "connectivity:path_validation_started",
+            "connectivity:path_validation_succeeded",
+            "connectivity:path_updated",
+        ]
+    );
+    let updated = events.last().unwrap();
+    assert_eq!(trigger(updated).as_deref(), Some("application"));
+    assert_eq!(
+        updated["data"]["new"]["local"].as_str(),
+        Some(local.to_string().as_str())
+    );
+}
+
+simulate!(
+    pmtud_ethernet,
+    [
+        ConnectionNode::new_client(
+            ConnectionParameters::default().pmtud(true),
+            boxed![ReachState::new(State::Confirmed)],
+            // The simulator uses IPv6, so this is 1500 minus 48 bytes of headers.
+            boxed![SendData::new(TRANSFER_AMOUNT), ReachMtu::new(1452)]
+        ),
+        Delay::new(DELAY_RANGE),
+        Mtu::new(ETHERNET_MTU),
+        ConnectionNode::new_server(
+            ConnectionParameters::default().pmtud(true),
+            boxed![ReachState::new(State::Confirmed)],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        Delay::new(DELAY_RANGE),
+        Mtu::new(ETHERNET_MTU),
+    ],
+);
+
+// The path MTU drops partway through a transfer.  The sender has to detect
+// the black hole and fall back to the minimum size to finish the transfer.
+simulate!(
+    pmtud_black_hole,
+    [
+        ConnectionNode::new_client(
+            ConnectionParameters::default().pmtud(true),
+            boxed![ReachState::new(State::Confirmed)],
+            boxed![SendData::new(TRANSFER_AMOUNT), ReachMtu::new(1232)]
+        ),
+        Delay::new(DELAY_RANGE),
+        Mtu::new(ETHERNET_MTU).change_after(Duration::from_millis(500), 1300),
+        ConnectionNode::new_server(
+            ConnectionParameters::default().pmtud(true),
+            boxed![ReachState::new(State::Confirmed)],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        Delay::new(DELAY_RANGE),
+        Mtu::new(ETHERNET_MTU).change_after(Duration::from_millis(500), 1300),
+    ],
+);
+
+// An attacker that rewrites source ports can't make the server track too many paths.
+simulate!(
+    path_spray,
+    [
+        ConnectionNode::default_client(boxed![SendData::new(TRANSFER_AMOUNT)]),
+        Delay::new(DELAY_RANGE),
+        PortSpray::new(8),
+        ConnectionNode::default_server(boxed![
+            ReceiveData::new(TRANSFER_AMOUNT),
+            PathLimit::new(MAX_PATHS)
+        ]),
+        Delay::new(DELAY_RANGE),
+        PortRestore::new(test_fixture::DEFAULT_ADDR),
+    ],
+);
+
+/// Checks that the handshake completes one round trip after the first Initial.
+#[derive(Debug)]
+struct HandshakeTimings {
+    rtt: Duration,
+}
+
+impl ConnectionGoal for HandshakeTimings {
+    fn handle_event(
+        &mut self,
+        c: &mut Connection,
+        e: &ConnectionEvent,
+        _now: Instant,
+    ) -> GoalStatus {
+        if !matches!(e, ConnectionEvent::StateChange(State::Confirmed)) {
+            return GoalStatus::Waiting;
+        }
+        let timings = c.stats().timings;
+        let first_initial = if c.role() == Role::Client {
+            timings.first_initial_sent
+        } else {
+            timings.first_initial_received
+        };
+        let handshake = timings.handshake_completed.unwrap() - first_initial.unwrap();
+        assert!(handshake >= self.rtt, "{handshake:?} < {:?}", self.rtt);
+        assert!(
+            handshake <= self.rtt + JITTER,
+            "{handshake:?} > {:?}",
+            self.rtt + JITTER
+        );
+        assert!(timings.handshake_confirmed.is_some());
+        assert_eq!(timings.handshake_pto_count, 0);
+        GoalStatus::Done
+    }
+}
+
+simulate!(
+    handshake_timings,
+    [
+        ConnectionNode::new_client(
+            ConnectionParameters::default(),
+            [],
+            boxed![HandshakeTimings { rtt: DELAY }]
+        ),
+        Delay::new(DELAY / 2..DELAY / 2),
+        ConnectionNode::new_server(
+            ConnectionParameters::default(),
+            [],
+            boxed![HandshakeTimings { rtt: DELAY }]
+        ),
+        Delay::new(DELAY / 2..DELAY / 2),
+    ],
+);
+
+/// The frames that one end of a connection counted, both when its goal started
+/// and when the connection closed.
+#[derive(Debug, Default)]
+struct FrameCounts {
+    start: (FrameStats, FrameStats),
+    end: Option<(FrameStats, FrameStats)>,
+}
+
+impl FrameCounts {
+    fn record_start(&mut self, c: &Connection) {
+        let stats = c.stats();
+        self.start = (stats.frame_tx, stats.frame_rx);
+    }
+
+    fn record_end(&mut self, c: &Connection) {
+        let stats = c.stats();
+        self.end = Some((stats.frame_tx, stats.frame_rx));
+    }
+
+    /// The frames sent and received since the goal started.
+    fn since_start(&self, c: &Connection) -> (FrameStats, FrameStats) {
+        let stats = c.stats();
+        (
+            stats.frame_tx.delta(&self.start.0),
+            stats.frame_rx.delta(&self.start.1),
+        )
+    }
+
+    /// The frames sent and received from when the goal started until the connection closed.
+    fn delta(&self) -> (FrameStats, FrameStats) {
+        let (sent, received) = self.end.as_ref().expect("connection closed");
+        (sent.delta(&self.start.0), received.delta(&self.start.1))
+    }
+}
+
+/// How much the client sends on its stream, which is more than the server allows at first.
+const EVERY_FRAME_DATA: usize = 6000;
+/// The flow control limit that the server sets for the stream and the connection.
+const EVERY_FRAME_WINDOW: u64 = 4096;
+/// The client sends a PING after it has been idle for this long.
+const EVERY_FRAME_KEEP_ALIVE: Duration = Duration::from_secs(1);
+
+/// The client end of `frame_stats_every_type`.
+#[derive(Debug)]
+struct EveryFrameClient {
+    counts: Rc<RefCell<FrameCounts>>,
+    stream_id: Option<StreamId>,
+    remaining: usize,
+}
+
+impl EveryFrameClient {
+    fn send(&mut self, c: &mut Connection) {
+        let Some(stream_id) = self.stream_id else {
+            return;
+        };
+        while self.remaining > 0 {
+            let sent = c
+                .stream_send(stream_id, &[0; EVERY_FRAME_DATA][..self.remaining])
+                .unwrap();
+            if sent == 0 {
+                return;
+            }
+            self.remaining -= sent;
+        }
+        c.stream_close_send(stream_id).unwrap();
+        self.stream_id = None;
+    }
+}
+
+impl ConnectionGoal for EveryFrameClient {
+    fn init(&mut self, c: &mut Connection, _now: Instant) {
+        self.counts.borrow_mut().record_start(c);
+
+        // PING, but only once everything else is done.
+        c.set_keep_alive(Some(EVERY_FRAME_KEEP_ALIVE));
+
+        // STREAM, followed by DATA_BLOCKED and STREAM_DATA_BLOCKED, as this
+        // is more than the server allows.  The server answers with MAX_DATA
+        // and MAX_STREAM_DATA.
+        self.stream_id = Some(c.stream_create(StreamType::UniDi).unwrap());
+        self.send(c);
+
+        // RESET_STREAM and STOP_SENDING.  The server answers STOP_SENDING
+        // with a RESET_STREAM of its own.
+        for _ in 0..2 {
+            let stream_id = c.stream_create(StreamType::BiDi).unwrap();
+            c.stream_reset_send(stream_id, 0).unwrap();
+            c.stream_stop_sending(stream_id, 0).unwrap();
+        }
+        // STREAMS_BLOCKED.  The server answers with MAX_STREAMS once it
+        // has retired the two streams.
+        assert!(c.stream_create(StreamType::BiDi).is_err());
+
+        c.send_datagram(b"client", None).unwrap();
+    }
+
+    fn handle_event(
+        &mut self,
+        c: &mut Connection,
+        e: &ConnectionEvent,
+        now: Instant,
+    ) -> GoalStatus {
+        match e {
+            ConnectionEvent::SendStreamWritable { .. } => {
+                self.send(c);
+                GoalStatus::Active
+            }
+            // The server sends a DATAGRAM once it has seen everything.
+            // Nothing that it sends after this would be counted.
+            ConnectionEvent::Datagram(_) => {
+                c.close(now, 0, "");
+                GoalStatus::Active
+            }
+            ConnectionEvent::StateChange(State::Closed(_)) => {
+                self.counts.borrow_mut().record_end(c);
+                GoalStatus::Done
+            }
+            _ => GoalStatus::Waiting,
+        }
+    }
+}
+
+/// The server end of `frame_stats_every_type`.
+#[derive(Debug)]
+struct EveryFrameServer {
+    counts: Rc<RefCell<FrameCounts>>,
+    stream_id: Option<StreamId>,
+    fin: bool,
+    datagram: bool,
+    done: bool,
+}
+
+impl EveryFrameServer {
+    fn read(&mut self, c: &mut Connection) {
+        let Some(stream_id) = self.stream_id.filter(|_| !self.fin) else {
+            return;
+        };
+        // Don't read, and so don't raise any limits, until the client
+        // reports that it is blocked.
+        let (_, received) = self.counts.borrow().since_start(c);
+        if received.data_blocked == 0 || received.stream_data_blocked == 0 {
+            return;
+        }
+        let mut buf = [0; EVERY_FRAME_DATA];
+        loop {
+            let (read, fin) = c.stream_recv(stream_id, &mut buf).unwrap();
+            self.fin = fin;
+            if fin || read == 0 {
+                return;
+            }
+        }
+    }
+}
+
+impl ConnectionGoal for EveryFrameServer {
+    fn init(&mut self, c: &mut Connection, now: Instant) {
+        self.counts.borrow_mut().record_start(c);
+        // CRYPTO.
+        c.send_ticket(now, &[]).unwrap();
+    }
+
+    fn process(&mut self, c: &mut Connection, _now: Instant) -> GoalStatus {
+        self.read(c);
+        let (sent, received) = self.counts.borrow().since_start(c);
+        // The client only sends PING when it has nothing else to do, and
+        // MAX_STREAMS is the last thing that the server needs to send.
+        if self.done || !self.fin || !self.datagram || sent.max_streams == 0 || received.ping == 0 {
+            return GoalStatus::Waiting;
+        }
+        c.send_datagram(b"server", None).unwrap();
+        self.done = true;
+        GoalStatus::Active
+    }
+
+    fn handle_event(
+        &mut self,
+        c: &mut Connection,
+        e: &ConnectionEvent,
+        now: Instant,
+    ) -> GoalStatus {
+        match e {
+            ConnectionEvent::RecvStreamReadable { stream_id } if stream_id.is_uni() => {
+                self.stream_id = Some(*stream_id);
+            }
+            ConnectionEvent::Datagram(_) => self.datagram = true,
+            ConnectionEvent::StateChange(State::Closed(_)) => {
+                self.counts.borrow_mut().record_end(c);
+                return GoalStatus::Done;
+            }
+            _ => {}
+        }
+        self.process(c, now)
+    }
+}
+
+/// Check that the peer received every frame that was sent.
+fn assert_frames_delivered(sent: &FrameStats, received: &FrameStats) {
+    macro_rules! assert_same {
+        ($($f:ident),+ $(,)?) => {
+            $(assert_eq!(sent.$f, received.$f, concat!("sent and received ", stringify!($f)));)+
+        };
+    }
+    assert_same!(
+        all,
+        ack,
+        largest_acknowledged,
+        crypto,
+        stream,
+        reset_stream,
+        stop_sending,
+        ping,
+        padding,
+        max_streams,
+        streams_blocked,
+        max_data,
+        data_blocked,
+        max_stream_data,
+        stream_data_blocked,
+        new_connection_id,
+        retire_connection_id,
+        path_challenge,
+        path_response,
+        connection_close,
+        handshake_done,
+        new_token,
+        ack_frequency,
+        datagram,
+        unknown,
+    );
+}
+
+/// Check the frames that `EveryFrameClient` sent.
+fn assert_client_sent(sent: &FrameStats) {
+    for (name, count) in [
+        ("ack", sent.ack),
+        ("stream", sent.stream),
+        ("ping", sent.ping),
+        ("padding", sent.padding),
+        ("data_blocked", sent.data_blocked),
+        ("stream_data_blocked", sent.stream_data_blocked),
+        ("path_challenge", sent.path_challenge),
+        ("retire_connection_id", sent.retire_connection_id),
+    ] {
+        assert!(count > 0, "client sent no {name} frames");
+    }
+    assert_eq!(sent.reset_stream, 2);
+    assert_eq!(sent.stop_sending, 2);
+    assert_eq!(sent.streams_blocked, 1);
+    assert_eq!(sent.datagram, 1);
+    assert_eq!(sent.connection_close, 1);
+    for (name, count) in [
+        ("crypto", sent.crypto),
+        ("max_streams", sent.max_streams),
+        ("max_data", sent.max_data),
+        ("max_stream_data", sent.max_stream_data),
+        ("handshake_done", sent.handshake_done),
+        ("new_token", sent.new_token),
+        ("unknown", sent.unknown),
+    ] {
+        assert_eq!(count, 0, "client sent {name} frames");
+    }
+}
+
+/// Check the frames that `EveryFrameServer` sent.
+fn assert_server_sent(sent: &FrameStats) {
+    for (name, count) in [
+        ("ack", sent.ack),
+        ("padding", sent.padding),
+        ("max_data", sent.max_data),
+        ("max_stream_data", sent.max_stream_data),
+        ("path_response", sent.path_response),
+        ("new_connection_id", sent.new_connection_id),
+    ] {
+        assert!(count > 0, "server sent no {name} frames");
+    }
+    assert_eq!(sent.crypto, 1);
+    assert_eq!(sent.reset_stream, 2);
+    assert_eq!(sent.max_streams, 1);
+    assert_eq!(sent.datagram, 1);
+    for (name, count) in [
+        ("stream", sent.stream),
+        ("stop_sending", sent.stop_sending),
+        ("streams_blocked", sent.streams_blocked),
+        ("data_blocked", sent.data_blocked),
+        ("stream_data_blocked", sent.stream_data_blocked),
+        ("connection_close", sent.connection_close),
+        ("handshake_done", sent.handshake_done),
+        ("new_token", sent.new_token),
+        ("unknown", sent.unknown),
+    ] {
+        assert_eq!(count, 0, "server sent {name} frames");
+    }
+}
+
+/// Send every frame type that a `Connection` can send, without loss, then
+/// check the frame counts at both ends.  The counts start after the handshake,
+/// except for `HANDSHAKE_DONE`.  `NEW_TOKEN` is not sent, as only a `Server`
+/// sends that.  Whether `ACK_FREQUENCY` is sent depends on how the RTT estimate
+/// changes, so that is only checked for delivery, as are the `PATH_CHALLENGE`
+/// frames that the server might send.
+#[test]
+fn frame_stats_every_type() {
+    let client_counts = Rc::new(RefCell::new(FrameCounts::default()));
+    let server_counts = Rc::new(RefCell::new(FrameCounts::default()));
+    let local = SocketAddr::new(DEFAULT_ADDR.ip(), DEFAULT_ADDR.port() + 1);
+    Simulator::new(
+        "frame_stats_every_type",
+        boxed![
+            ConnectionNode::new_client(
+                ConnectionParameters::default().datagram_size(1200),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![
+                    // PATH_CHALLENGE and PATH_RESPONSE, padded.  The client
+                    // retires the old path, so RETIRE_CONNECTION_ID and
+                    // NEW_CONNECTION_ID follow.
+                    Migrate::new(local),
+                    EveryFrameClient {
+                        counts: Rc::clone(&client_counts),
+                        stream_id: None,
+                        remaining: EVERY_FRAME_DATA,
+                    }
+                ]
+            ),
+            ConnectionNode::new_server(
+                ConnectionParameters::default()
+                    .datagram_size(1200)
+                    .max_data(EVERY_FRAME_WINDOW)
+                    .max_stream_data(StreamType::UniDi, true, EVERY_FRAME_WINDOW)
+                    .max_streams(StreamType::BiDi, 2),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![EveryFrameServer {
+                    counts: Rc::clone(&server_counts),
+                    stream_id: None,
+                    fin: false,
+                    datagram: false,
+                    done: false,
+                }]
+            ),
+        ],
+    )
+    .run();
+
+    let (client_sent, client_received) = client_counts.borrow().delta();
+    let (server_sent, server_received) = server_counts.borrow().delta();
+    assert_frames_delivered(&client_sent, &server_received);
+    assert_frames_delivered(&server_sent, &client_received);
+    assert_client_sent(&client_sent);
+    assert_server_sent(&server_sent);
+
+    // HANDSHAKE_DONE was sent during the handshake.
+    let (server_total, _) = server_counts.borrow().end.clone().unwrap();
+    let (_, client_total) = client_counts.borrow().end.clone().unwrap();
+    assert_eq!(server_total.handshake_done, 1);
+    assert_eq!(client_total.handshake_done, 1);
+}
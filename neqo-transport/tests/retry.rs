@@ -9,16 +9,21 @@
 mod common;
 
 use std::{
+    cell::RefCell,
     mem,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    rc::Rc,
     time::Duration,
 };
 
 use common::{connected_server, default_server, generate_ticket};
-use neqo_common::{hex_with_len, qdebug, qtrace, Datagram, Encoder, Role};
+use neqo_common::{
+    event::Provider, hex_with_len, qdebug, qtrace, Datagram, Encoder, IpTosDscp, IpTosEcn, Role,
+};
 use neqo_crypto::AuthenticationStatus;
 use neqo_transport::{
-    server::ValidateAddress, CloseReason, Error, State, StreamType, MIN_INITIAL_PACKET_SIZE,
+    server::ValidateAddress, CloseReason, ConnectionEvent, Error, State, StreamType,
+    MIN_INITIAL_PACKET_SIZE,
 };
 use test_fixture::{
     assertions, datagram, default_client,
@@ -56,6 +61,90 @@ fn retry_basic() {
     connected_server(&mut server);
 }
 
+/// The address is validated as soon as the Initial with the Retry token arrives,
+/// and the application hears about that only once.
+#[test]
+fn retry_address_validated() {
+    let mut server = default_server();
+    server.set_validation(ValidateAddress::Always);
+    let validated = Rc::new(RefCell::new(Vec::new()));
+    let validated_copy = Rc::clone(&validated);
+    server.set_on_address_validated(Box::new(move |c, addr| {
+        validated_copy.borrow_mut().push((c.original_dcid(), addr));
+    }));
+    let mut client = default_client();
+
+    let dgram = client.process(None, now()).dgram(); // Initial
+    let (_, client_dcid, _, _) =
+        decode_initial_header(dgram.as_ref().unwrap(), Role::Client).unwrap();
+    let client_dcid = client_dcid.to_vec();
+    let dgram = server.process(dgram.as_ref(), now()).dgram(); // Retry
+    assertions::assert_retry(dgram.as_ref().unwrap());
+    assert!(validated.borrow().is_empty());
+
+    let dgram = client.process(dgram.as_ref(), now()).dgram(); // Initial w/token
+    let client_addr = dgram.as_ref().unwrap().source();
+    let dgram = server.process(dgram.as_ref(), now()).dgram(); // Initial, HS
+    assert_eq!(validated.borrow().len(), 1);
+    assert_eq!(&validated.borrow()[0].0[..], &client_dcid[..]);
+    assert_eq!(validated.borrow()[0].1, client_addr);
+
+    // Completing the handshake does not report the address again.
+    mem::drop(client.process(dgram.as_ref(), now()).dgram());
+    client.authenticated(AuthenticationStatus::Ok, now());
+    let dgram = client.process(None, now()).dgram(); // Send Finished
+    mem::drop(server.process(dgram.as_ref(), now()).dgram());
+    connected_server(&mut server);
+    assert_eq!(validated.borrow().len(), 1);
+}
+
+/// Initials in datagrams smaller than the configured limit are dropped rather than
+/// being sent a Retry.
+#[test]
+fn retry_only_for_larger_initials() {
+    let mut server = default_server();
+    server.set_validation(ValidateAddress::Always);
+    let mut client = default_client();
+
+    let dgram = client.process(None, now()).dgram().unwrap(); // Initial
+    assert!(dgram.len() >= MIN_INITIAL_PACKET_SIZE);
+
+    // Anything smaller than the limit is dropped without a Retry.
+    server.set_retry_only_for_larger_initials(dgram.len() + 1);
+    assert!(server.process(Some(&dgram), now()).dgram().is_none());
+    assert!(server.active_connections().is_empty());
+
+    // At the limit, the Initial gets a Retry.
+    server.set_retry_only_for_larger_initials(dgram.len());
+    let dgram = server.process(Some(&dgram), now()).dgram(); // Retry
+    assertions::assert_retry(dgram.as_ref().unwrap());
+}
+
+/// The server reports the connection ID that the client chose, not the one from the Retry.
+#[test]
+fn retry_original_dcid() {
+    let mut server = default_server();
+    server.set_validation(ValidateAddress::Always);
+    let mut client = default_client();
+
+    let dgram = client.process(None, now()).dgram(); // Initial
+    let (_, client_dcid, _, _) =
+        decode_initial_header(dgram.as_ref().unwrap(), Role::Client).unwrap();
+    let client_dcid = client_dcid.to_vec();
+    let dgram = server.process(dgram.as_ref(), now()).dgram(); // Retry
+    assertions::assert_retry(dgram.as_ref().unwrap());
+
+    let dgram = client.process(dgram.as_ref(), now()).dgram(); // Initial w/token
+    let (_, retry_dcid, _, _) =
+        decode_initial_header(dgram.as_ref().unwrap(), Role::Client).unwrap();
+    assert_ne!(retry_dcid, &client_dcid[..]);
+    mem::drop(server.process(dgram.as_ref(), now()).dgram()); // Initial, HS
+
+    let active = server.active_connections();
+    assert_eq!(active.len(), 1);
+    assert_eq!(&active[0].original_dcid()[..], &client_dcid[..]);
+}
+
 /// Receiving a Retry is enough to infer something about the RTT.
 /// Probably.
 #[test]
@@ -76,6 +165,58 @@ fn implicit_rtt_retry() {
     assert_eq!(client.stats().rtt, RTT);
 }
 
+/// How long the client takes to complete the handshake, with and without a Retry.
+fn handshake_time(validation: ValidateAddress) -> Duration {
+    const RTT: Duration = Duration::from_millis(50);
+    let mut server = default_server();
+    server.set_validation(validation);
+    let mut client = default_client();
+    let mut now = now();
+
+    let mut dgram = client.process(None, now).dgram();
+    while *client.state() != State::Connected {
+        now += RTT / 2;
+        let response = server.process(dgram.as_ref(), now).dgram();
+        now += RTT / 2;
+        dgram = client.process(response.as_ref(), now).dgram();
+        if client
+            .events()
+            .any(|e| matches!(e, ConnectionEvent::AuthenticationNeeded))
+        {
+            client.authenticated(AuthenticationStatus::Ok, now);
+        }
+    }
+
+    let timings = client.stats().timings;
+    timings.handshake_completed.unwrap() - timings.first_initial_sent.unwrap()
+}
+
+/// A Retry adds exactly one round trip to the handshake.
+#[test]
+fn retry_handshake_timings() {
+    let without = handshake_time(ValidateAddress::Never);
+    let with = handshake_time(ValidateAddress::Always);
+    assert_eq!(without, Duration::from_millis(50));
+    assert_eq!(with, without + Duration::from_millis(50));
+}
+
+/// A Retry uses the DSCP value set on the server, not the marking on the Initial.
+#[test]
+fn retry_dscp() {
+    let mut server = default_server();
+    server.set_validation(ValidateAddress::Always);
+    server.set_dscp(IpTosDscp::Cs1);
+    let mut client = default_client();
+    client.set_dscp(IpTosDscp::Af41);
+
+    let initial = client.process(None, now()).dgram().unwrap();
+    assert_eq!(IpTosDscp::from(initial.tos()), IpTosDscp::Af41);
+    let retry = server.process(Some(&initial), now()).dgram().unwrap();
+    assertions::assert_retry(&retry);
+    assert_eq!(IpTosDscp::from(retry.tos()), IpTosDscp::Cs1);
+    assert_eq!(IpTosEcn::from(retry.tos()), IpTosEcn::NotEct);
+}
+
 #[test]
 fn retry_expired() {
     let mut server = default_server();
@@ -98,6 +239,27 @@ fn retry_expired() {
     assert!(dgram.is_none());
 }
 
+#[test]
+fn retry_token_lifetime() {
+    const LIFETIME: Duration = Duration::from_secs(60);
+    let mut server = default_server();
+    server.set_validation(ValidateAddress::Always);
+    server.set_retry_token_lifetime(LIFETIME);
+    let mut client = default_client();
+    let mut now = now();
+
+    let dgram = client.process(None, now).dgram(); // Initial
+    let dgram = server.process(dgram.as_ref(), now).dgram(); // Retry
+    assertions::assert_retry(dgram.as_ref().unwrap());
+    let dgram = client.process(dgram.as_ref(), now).dgram(); // Initial w/token
+    assert!(dgram.is_some());
+
+    // This would be too late with the default lifetime.
+    now += LIFETIME / 2;
+    let dgram = server.process(dgram.as_ref(), now).dgram(); // Initial, HS
+    assert!(dgram.is_some());
+}
+
 // Attempt a retry with 0-RTT, and have 0-RTT packets sent with the second ClientHello.
 #[test]
 fn retry_0rtt() {
@@ -6,25 +6,41 @@
 
 mod common;
 
-use std::{cell::RefCell, mem, net::SocketAddr, rc::Rc, time::Duration};
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    mem,
+    net::{Ipv4Addr, SocketAddr},
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use common::{connect, connected_server, default_server, find_ticket, generate_ticket, new_server};
-use neqo_common::{qtrace, Datagram, Decoder, Encoder, Role};
+use neqo_common::{event::Provider, qlog::QlogFormat, qtrace, Datagram, Decoder, Encoder, Role};
 use neqo_crypto::{
+    constants::{TLS_AES_128_GCM_SHA256, TLS_CHACHA20_POLY1305_SHA256},
     generate_ech_keys, AllowZeroRtt, AuthenticationStatus, ZeroRttCheckResult, ZeroRttChecker,
 };
 use neqo_transport::{
-    server::{ActiveConnectionRef, Server, ValidateAddress},
-    CloseReason, Connection, ConnectionParameters, Error, Output, State, StreamType, Version,
+    server::{
+        ActiveConnectionRef, DropReason, PostClosePolicy, Server, ValidateAddress,
+        WeightedRoundRobin,
+    },
+    CloseReason, CongestionPhase, Connection, ConnectionEvent, ConnectionId, ConnectionIdDecoder,
+    ConnectionIdGenerator, ConnectionIdRef, ConnectionParameters, DatagramStats, Error,
+    FlowControlState, HandshakePhase, Output, PacketDropReason, State, StreamType, Version,
     MIN_INITIAL_PACKET_SIZE,
 };
+use serde_json::Value;
 use test_fixture::{
     assertions, datagram, default_client,
     header_protection::{
         apply_header_protection, decode_initial_header, initial_aead_and_hp,
         remove_header_protection,
     },
-    new_client, now, split_datagram, CountingConnectionIdGenerator,
+    new_client, new_neqo_qlog, now, split_datagram, CountingConnectionIdGenerator, SharedVec,
+    ANTI_REPLAY_WINDOW, DEFAULT_ADDR,
 };
 
 /// Take a pair of connections in any state and complete the handshake.
@@ -127,6 +143,484 @@ fn connect_single_version_server() {
     }
 }
 
+#[test]
+fn handshake_phase() {
+    let mut server = default_server();
+    let mut client = default_client();
+    assert_eq!(client.handshake_phase(), HandshakePhase::Start);
+
+    let out = client.process(None, now()); // ClientHello
+    assert_eq!(client.handshake_phase(), HandshakePhase::InitialSent);
+
+    // The server receives the Initial, then sends Initial and Handshake packets.
+    let out = server.process(out.as_dgram_ref(), now());
+    let server_conn = server.active_connections().pop().unwrap();
+    assert_eq!(server_conn.handshake_phase(), HandshakePhase::HandshakeSent);
+
+    // The client receives the server's Initial and Handshake packets,
+    // but is still waiting on authentication.
+    let out = client.process(out.as_dgram_ref(), now());
+    assert_eq!(client.handshake_phase(), HandshakePhase::HandshakeReceived);
+    let out = server.process(out.as_dgram_ref(), now());
+    assert!(out.as_dgram_ref().is_none());
+    assert_eq!(
+        server_conn.handshake_phase(),
+        HandshakePhase::HandshakeReceived
+    );
+
+    client.authenticated(AuthenticationStatus::Ok, now());
+    let out = client.process(None, now());
+    assert_eq!(client.handshake_phase(), HandshakePhase::Connected);
+
+    // The server confirms the handshake as soon as it completes.
+    let out = server.process(out.as_dgram_ref(), now());
+    assert_eq!(server_conn.handshake_phase(), HandshakePhase::Confirmed);
+
+    // The client confirms the handshake on receiving HANDSHAKE_DONE.
+    client.process_input(out.as_dgram_ref().unwrap(), now());
+    assert_eq!(client.handshake_phase(), HandshakePhase::Confirmed);
+
+    // Closing the connection doesn't change the phase.
+    client.close(now(), 0, "");
+    assert_eq!(client.handshake_phase(), HandshakePhase::Confirmed);
+}
+
+#[test]
+fn process_into() {
+    let mut server = default_server();
+    server.set_validation(ValidateAddress::Always);
+    let mut client = default_client();
+
+    // A Retry is not associated with a connection.
+    let dgram = client.process(None, now()).dgram();
+    let (out, c) = server.process_into(dgram.as_ref(), now());
+    assertions::assert_retry(out.as_dgram_ref().unwrap());
+    assert!(c.is_none());
+
+    // The Initial with a token results in a new connection.
+    let dgram = client.process(out.as_dgram_ref(), now()).dgram();
+    let (out, created) = server.process_into(dgram.as_ref(), now());
+    let created = created.expect("a connection was created");
+    let server_conn = complete_connection(&mut client, &mut server, out.dgram());
+    assert_eq!(created, server_conn);
+
+    // Datagrams for the connection are routed to it.
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, &[0; 10]).unwrap();
+    let dgram = client.process_output(now()).dgram();
+    let (_, routed) = server.process_into(dgram.as_ref(), now());
+    assert_eq!(routed, Some(server_conn));
+
+    // Without input, there is no connection.
+    let (_, c) = server.process_into(None, now());
+    assert!(c.is_none());
+}
+
+/// A qlog writer that collects output in memory.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn qlog_writer_factory() {
+    let buffer = SharedBuffer::default();
+    let odcids = Rc::new(RefCell::new(Vec::new()));
+    let mut server = default_server();
+    let (b, o) = (buffer.clone(), Rc::clone(&odcids));
+    server.set_qlog_writer_factory(Box::new(move |odcid| {
+        o.borrow_mut().push(odcid.to_vec());
+        Box::new(b.clone())
+    }));
+
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+    assert_eq!(odcids.borrow().len(), 1);
+
+    let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    assert!(log.contains("\"qlog_format\":\"JSON-SEQ\""));
+    assert!(log.contains("Neqo server qlog"));
+    assert!(log.contains("transport:packet_received"));
+}
+
+/// A qlog writer that fails once it has accepted `limit` bytes.
+struct FailingBuffer {
+    limit: usize,
+}
+
+impl Write for FailingBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.limit = self
+            .limit
+            .checked_sub(buf.len())
+            .ok_or_else(|| io::Error::other("qlog sink is full"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A qlog writer that fails, either immediately or part way through the
+/// handshake, only disables qlog for the connection.
+#[test]
+fn qlog_writer_factory_error() {
+    for limit in [0, 2000] {
+        let mut server = default_server();
+        server.set_qlog_writer_factory(Box::new(move |_| Box::new(FailingBuffer { limit })));
+
+        let mut client = default_client();
+        connect(&mut client, &mut server);
+        assert_eq!(*client.state(), State::Confirmed);
+    }
+}
+
+#[test]
+fn close_idle() {
+    const THRESHOLD: Duration = Duration::from_secs(10);
+    let mut server = default_server();
+    let mut client = default_client();
+    let server_conn = connect(&mut client, &mut server);
+
+    // Nothing is closed until the threshold is exceeded.
+    assert!(server.close_idle(THRESHOLD, now() + THRESHOLD).is_empty());
+    assert!(!server_conn.borrow().state().closed());
+
+    let later = now() + THRESHOLD + Duration::from_secs(1);
+    let closes = server.close_idle(THRESHOLD, later);
+    assert_eq!(closes.len(), 1);
+    assert!(server_conn.borrow().state().closed());
+
+    client.process_input(&closes[0], later);
+    assert!(matches!(
+        client.state(),
+        State::Draining {
+            error: CloseReason::Application(0),
+            ..
+        }
+    ));
+
+    // Connections are only closed once.
+    assert!(server.close_idle(THRESHOLD, later).is_empty());
+}
+
+#[test]
+fn shutdown_with_reason() {
+    const REASON: &str = "server maintenance";
+    let mut server = default_server();
+    let mut client = default_client();
+    let (log, contents) = new_neqo_qlog();
+    client.set_qlog(log);
+    let server_conn = connect(&mut client, &mut server);
+
+    let closes = server.initiate_shutdown_with_reason(77, REASON, now());
+    assert_eq!(closes.len(), 1);
+    assert!(server.is_shutting_down());
+    assert!(server_conn.borrow().state().closed());
+
+    // The client sees the error code and the reason.
+    client.process_input(&closes[0], now());
+    assert!(matches!(
+        client.state(),
+        State::Draining {
+            error: CloseReason::Application(77),
+            ..
+        }
+    ));
+    assert!(contents.to_string().contains(REASON));
+
+    // New connections are refused.
+    mem::drop(server.active_connections());
+    let mut client = default_client();
+    let initial = client.process_output(now()).dgram();
+    assert!(server.process(initial.as_ref(), now()).dgram().is_none());
+    assert!(server.active_connections().is_empty());
+}
+
+#[test]
+fn weighted_output() {
+    // Without pacing, connections are only limited by their congestion windows.
+    let mut server = new_server(ConnectionParameters::default().pacing(false));
+    server.set_output_scheduler(Box::<WeightedRoundRobin>::default());
+
+    let mut clients = [default_client(), default_client()];
+    let mut conns = clients
+        .iter_mut()
+        .map(|client| connect(client, &mut server))
+        .collect::<Vec<_>>();
+    conns[0].set_priority(3);
+    for c in &mut conns {
+        let stream_id = c.borrow_mut().stream_create(StreamType::UniDi).unwrap();
+        c.borrow_mut()
+            .stream_send(stream_id, &[0; 100_000])
+            .unwrap();
+        server.add_to_waiting(c);
+    }
+
+    let mut sent = [0; 2];
+    for _ in 0..8 {
+        let dgram = server.process(None, now()).dgram().unwrap();
+        // Only the intended recipient can decrypt the packet.
+        let i = clients
+            .iter_mut()
+            .position(|client| {
+                let before = client.stats().frame_rx.all;
+                client.process_input(&dgram, now());
+                client.stats().frame_rx.all > before
+            })
+            .unwrap();
+        sent[i] += 1;
+    }
+    assert_eq!(sent, [6, 2]);
+}
+
+#[test]
+fn pause_sending() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let mut server_conn = connect(&mut client, &mut server);
+
+    let stream_id = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    server_conn
+        .borrow_mut()
+        .stream_send(stream_id, &[0; 10])
+        .unwrap();
+
+    server_conn.pause_sending(true);
+    server.add_to_waiting(&server_conn);
+    assert!(server.process(None, now()).dgram().is_none());
+
+    // Packets from the client are still processed while paused.
+    let client_stream = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(client_stream, &[1, 2, 3]).unwrap();
+    let dgram = client.process_output(now()).dgram();
+    assert!(dgram.is_some());
+    let before = server_conn.borrow().stats().packets_rx;
+    assert!(server.process(dgram.as_ref(), now()).dgram().is_none());
+    assert_eq!(server_conn.borrow().stats().packets_rx, before + 1);
+
+    // Resuming sends what was held, without needing `add_to_waiting`.
+    server_conn.pause_sending(false);
+    let dgram = server.process(None, now()).dgram();
+    assert!(dgram.is_some());
+    client.process_input(&dgram.unwrap(), now());
+    assert!(client.events().any(|e| matches!(
+        e,
+        ConnectionEvent::NewStream { stream_id: id } if id == stream_id
+    )));
+}
+
+/// The timers of a paused connection keep running.
+#[test]
+fn pause_sending_timers() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let mut server_conn = connect(&mut client, &mut server);
+    server_conn.pause_sending(true);
+
+    let idle_timeout = ConnectionParameters::default().get_idle_timeout();
+    let mut t = now();
+    while t < now() + idle_timeout * 2 && !server_conn.borrow().state().closed() {
+        match server.process(None, t) {
+            Output::Datagram(_) => panic!("a paused connection sent a datagram"),
+            Output::Callback(delay) => t += delay,
+            Output::None => t += idle_timeout,
+        }
+    }
+    assert!(matches!(
+        server_conn.borrow().state(),
+        State::Closed(CloseReason::Transport(Error::IdleTimeout))
+    ));
+}
+
+/// Exchange short header packets that carry stream data in both directions and
+/// return the spin bit of each packet, alternating client then server.
+fn spin_exchange(enabled: bool) -> (Vec<bool>, ActiveConnectionRef) {
+    const SPIN: u8 = 0x20;
+
+    let mut server = default_server();
+    server.set_spin_bit_enabled(enabled);
+    let mut client = new_client(ConnectionParameters::default().spin_bit(enabled));
+    let mut server_conn = connect(&mut client, &mut server);
+
+    let client_stream = client.stream_create(StreamType::UniDi).unwrap();
+    let server_stream = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    let mut bits = Vec::new();
+    for _ in 0..4 {
+        client.stream_send(client_stream, &[0; 10]).unwrap();
+        let c = client.process_output(now()).dgram().unwrap();
+        bits.push(c[0] & SPIN == SPIN);
+
+        server_conn
+            .borrow_mut()
+            .stream_send(server_stream, &[0; 10])
+            .unwrap();
+        let s = server.process(Some(&c), now()).dgram().unwrap();
+        bits.push(s[0] & SPIN == SPIN);
+        assert_eq!(server_conn.spin_bit(), s[0] & SPIN == SPIN);
+        client.process_input(&s, now());
+    }
+    (bits, server_conn)
+}
+
+#[test]
+fn spin_bit_enabled() {
+    let (bits, _) = spin_exchange(true);
+    for pair in bits.chunks(2) {
+        // The server reflects the spin bit that the client sent.
+        assert_eq!(pair[0], pair[1]);
+    }
+    for pair in bits[1..].chunks(2) {
+        // The client inverts the spin bit that the server sent.
+        assert_ne!(pair[0], pair[1]);
+    }
+}
+
+#[test]
+fn spin_bit_disabled() {
+    let (bits, server_conn) = spin_exchange(false);
+    let server_bits = bits.iter().skip(1).step_by(2).collect::<Vec<_>>();
+    assert!(server_bits.iter().all(|&&b| b == server_conn.spin_bit()));
+}
+
+#[test]
+fn start_qlog() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let mut server_conn = connect(&mut client, &mut server);
+
+    let contents = SharedVec::default();
+    server_conn.start_qlog(Box::new(contents.clone())).unwrap();
+    let names = |contents: &SharedVec| {
+        contents
+            .to_string()
+            .split_terminator('\n')
+            .skip(1) // The header.
+            .map(|r| {
+                let ev = serde_json::from_str::<Value>(r.strip_prefix('\u{1e}').unwrap()).unwrap();
+                ev["name"].as_str().unwrap().to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+    // Attaching writes a summary of the connection after the header.
+    assert_eq!(
+        names(&contents),
+        [
+            "connectivity:connection_started",
+            "transport:parameters_set",
+            "connectivity:connection_state_updated",
+            "recovery:metrics_updated",
+        ]
+    );
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, &[0; 10]).unwrap();
+    let dgram = client.process_output(now()).dgram();
+    server.process(dgram.as_ref(), now());
+    let names = names(&contents);
+    assert!(names[4..].iter().any(|n| n == "transport:packet_received"));
+}
+
+/// A trace that is started later uses the qlog settings of the server.
+#[test]
+fn start_qlog_format() {
+    let mut server = default_server();
+    server.set_qlog_format(QlogFormat::Json);
+    let mut client = default_client();
+    let mut server_conn = connect(&mut client, &mut server);
+
+    let contents = SharedVec::default();
+    server_conn.start_qlog(Box::new(contents.clone())).unwrap();
+    // The JSON format is only written when the trace is finished.
+    drop(server_conn);
+    drop(server);
+
+    let trace = serde_json::from_str::<Value>(&contents.to_string()).unwrap();
+    assert_eq!(trace["qlog_format"], "JSON");
+    assert_eq!(trace["title"], "Neqo server qlog");
+    assert!(!trace["traces"][0]["events"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn original_dcid() {
+    let mut server = default_server();
+    let mut client = default_client();
+
+    let initial = client.process_output(now()).dgram().unwrap();
+    let (_, client_dcid, _, _) = decode_initial_header(&initial, Role::Client).unwrap();
+    let client_dcid = client_dcid.to_vec();
+    let server_initial = server.process(Some(&initial), now()).dgram();
+
+    let active = server.active_connections();
+    assert_eq!(active.len(), 1);
+    assert_eq!(&active[0].original_dcid()[..], &client_dcid[..]);
+    complete_connection(&mut client, &mut server, server_initial);
+}
+
+#[test]
+fn client_initial_scid() {
+    let mut server = default_server();
+    let mut client = default_client();
+
+    let initial = client.process_output(now()).dgram().unwrap();
+    let (_, _, client_scid, _) = decode_initial_header(&initial, Role::Client).unwrap();
+    let client_scid = client_scid.to_vec();
+    let server_initial = server.process(Some(&initial), now()).dgram();
+
+    let active = server.active_connections();
+    assert_eq!(active.len(), 1);
+    assert_eq!(&active[0].client_initial_scid()[..], &client_scid[..]);
+    let server_conn = complete_connection(&mut client, &mut server, server_initial);
+    // This doesn't change when the client uses other connection IDs.
+    assert_eq!(&server_conn.client_initial_scid()[..], &client_scid[..]);
+}
+
+#[test]
+fn active_connections_sorted() {
+    let mut server = default_server();
+    let mut expected = Vec::new();
+    // Connect from ports in descending order, so that the order isn't just the
+    // order in which the connections were made.
+    for port in (1000..1005).rev() {
+        let mut client = default_client();
+        let initial = client.process_output(now()).dgram().unwrap();
+        let src = SocketAddr::new(initial.source().ip(), port);
+        let initial = Datagram::new(
+            src,
+            initial.destination(),
+            initial.tos(),
+            initial.ttl(),
+            &initial[..],
+        );
+        server.process(Some(&initial), now());
+        expected.push(src);
+    }
+    expected.sort();
+
+    let remotes = server
+        .active_connections_sorted()
+        .iter()
+        .map(|c| c.borrow().paths()[0].remote)
+        .collect::<Vec<_>>();
+    assert_eq!(remotes, expected);
+}
+
 #[test]
 fn duplicate_initial() {
     let mut server = default_server();
@@ -147,6 +641,61 @@ fn duplicate_initial() {
     complete_connection(&mut client, &mut server, server_initial);
 }
 
+/// The same Initial from an IPv4 address, and then from the IPv4-mapped IPv6
+/// form of the same address, belongs to the same connection attempt.
+#[test]
+fn duplicate_initial_ipv4_mapped() {
+    let mut server = default_server();
+    let mut client = default_client();
+
+    let initial = client.process(None, now()).dgram().unwrap();
+    let v4 = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 443);
+    let mapped = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped().into(), 443);
+    let from = |src| {
+        Datagram::new(
+            src,
+            initial.destination(),
+            initial.tos(),
+            initial.ttl(),
+            &initial[..],
+        )
+    };
+
+    let server_initial = server.process(Some(&from(v4)), now()).dgram();
+    assert!(server_initial.is_some());
+    let dgram = server.process(Some(&from(mapped)), now()).dgram();
+    assert!(dgram.is_none());
+    assert_eq!(server.active_connections().len(), 1);
+}
+
+#[test]
+fn max_handshake_packets() {
+    const LIMIT: u32 = 3;
+    let mut server = default_server();
+    server.set_max_handshake_packets(LIMIT);
+    let mut client = default_client();
+
+    let initial = client.process(None, now()).dgram();
+    assert!(initial.is_some());
+    let server_initial = server.process(initial.as_ref(), now()).dgram();
+    assert!(server_initial.is_some());
+
+    // Repeats of the Initial go to the same attempt, up to the limit.
+    for _ in 1..LIMIT {
+        let dgram = server.process(initial.as_ref(), now()).dgram();
+        assert!(dgram.is_none());
+    }
+
+    // The next one exceeds the limit, so the attempt is abandoned.
+    let dgram = server.process(initial.as_ref(), now()).dgram();
+    assert!(dgram.is_none());
+
+    // With the old attempt gone, the same Initial starts a new attempt.
+    let server_initial = server.process(initial.as_ref(), now()).dgram();
+    assert!(server_initial.is_some());
+    assert_eq!(server.active_connections().len(), 1);
+}
+
 #[test]
 fn duplicate_initial_new_path() {
     let mut server = default_server();
@@ -327,44 +876,236 @@ fn zero_rtt() {
 }
 
 #[test]
-fn new_token_0rtt() {
+fn zero_rtt_buffered() {
     let mut server = default_server();
+    server.set_zero_rtt_buffer(1, Duration::from_secs(1));
     let token = generate_ticket(&mut server);
-    server.set_validation(ValidateAddress::NoToken);
+
+    let mut now = now();
+    let t = server.process(None, now).callback();
+    now += t;
+    assert_eq!(server.process(None, now), Output::None);
 
     let mut client = default_client();
-    client.enable_resumption(now(), &token).unwrap();
+    client.enable_resumption(now, &token).unwrap();
 
-    let client_stream = client.stream_create(StreamType::UniDi).unwrap();
-    client.stream_send(client_stream, &[1, 2, 3]).unwrap();
+    let mut client_send = || {
+        let client_stream = client.stream_create(StreamType::UniDi).unwrap();
+        client.stream_send(client_stream, &[1, 2, 3]).unwrap();
+        match client.process(None, now) {
+            Output::Datagram(d) => d,
+            Output::Callback(t) => {
+                now += t;
+                client.process(None, now).dgram().unwrap()
+            }
+            Output::None => panic!(),
+        }
+    };
 
-    let out = client.process(None, now()); // Initial w/0-RTT
-    assert!(out.as_dgram_ref().is_some());
-    assertions::assert_initial(out.as_dgram_ref().unwrap(), true);
-    assertions::assert_coalesced_0rtt(out.as_dgram_ref().unwrap());
-    let out = server.process(out.as_dgram_ref(), now()); // Initial
-    assert!(out.as_dgram_ref().is_some());
-    assertions::assert_initial(out.as_dgram_ref().unwrap(), false);
+    let c1 = client_send();
+    assertions::assert_coalesced_0rtt(&c1);
+    let c2 = client_send();
+    let c3 = client_send();
 
-    let dgram = client.process(out.as_dgram_ref(), now());
-    // Note: the client doesn't need to authenticate the server here
-    // as there is no certificate; authentication is based on the ticket.
-    assert!(out.as_dgram_ref().is_some());
-    assert_eq!(*client.state(), State::Connected);
-    let dgram = server.process(dgram.as_dgram_ref(), now()); // (done)
-    assert!(dgram.as_dgram_ref().is_some());
-    connected_server(&mut server);
-    assert!(client.tls_info().unwrap().resumed());
+    // The first 0-RTT packet that arrives early is buffered, the second doesn't fit.
+    assert_eq!(server.process(Some(&c2), now), Output::None);
+    assert_eq!(server.process(Some(&c3), now), Output::None);
+
+    // The buffered packet is processed along with the Initial.
+    let shs = server.process(Some(&c1), now);
+    assert!(shs.as_dgram_ref().is_some());
+    let active = server.active_connections();
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].borrow().stats().frame_rx.stream, 2);
 }
 
 #[test]
-fn new_token_different_port() {
+fn was_resumed() {
     let mut server = default_server();
-    let token = generate_ticket(&mut server);
-    server.set_validation(ValidateAddress::NoToken);
-
     let mut client = default_client();
-    client.enable_resumption(now(), &token).unwrap();
+    let mut server_conn = connect(&mut client, &mut server);
+    assert!(!server_conn.was_resumed());
+
+    server_conn.borrow_mut().send_ticket(now(), &[]).unwrap();
+    let out = server.process(None, now());
+    client.process_input(out.as_dgram_ref().unwrap(), now());
+    let token = find_ticket(&mut client);
+    mem::drop(server.active_connections());
+
+    // Resume without sending any 0-RTT.
+    let mut client = default_client();
+    client.enable_resumption(now(), &token).unwrap();
+    let resumed_conn = complete_connection(&mut client, &mut server, None);
+    assert!(resumed_conn.was_resumed());
+    assert!(!server_conn.was_resumed());
+}
+
+#[test]
+fn disable_resumption() {
+    let mut server = default_server();
+    let token = generate_ticket(&mut server);
+    server.disable_resumption();
+
+    // The client presents its ticket and tries 0-RTT, but gets a full handshake.
+    let mut client = default_client();
+    client.enable_resumption(now(), &token).unwrap();
+    let client_stream = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(client_stream, &[1, 2, 3]).unwrap();
+    let mut server_conn = complete_connection(&mut client, &mut server, None);
+    assert!(!server_conn.was_resumed());
+    assert!(!client.tls_info().unwrap().resumed());
+    assert!(!client.tls_info().unwrap().early_data_accepted());
+
+    // No new ticket is issued either.
+    server_conn.borrow_mut().send_ticket(now(), &[]).unwrap();
+    let out = server.process(None, now());
+    client.process_input(out.as_dgram_ref().unwrap(), now());
+    assert!(!client
+        .events()
+        .any(|e| matches!(e, ConnectionEvent::ResumptionToken(_))));
+}
+
+#[test]
+fn flow_control_blocked() {
+    let mut server = default_server();
+    let mut client = new_client(
+        ConnectionParameters::default()
+            .max_data(1000)
+            .max_stream_data(StreamType::UniDi, true, 100),
+    );
+    let mut server_conn = connect(&mut client, &mut server);
+    assert!(!server_conn.flow_control_blocked().is_blocked());
+
+    // The stream limit is reached first.
+    let s1 = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    assert_eq!(
+        server_conn.borrow_mut().stream_send(s1, &[0; 200]).unwrap(),
+        100
+    );
+    assert_eq!(
+        server_conn.flow_control_blocked(),
+        FlowControlState {
+            connection: false,
+            streams: vec![s1],
+        }
+    );
+    let out = server.process(None, now());
+    client.process_input(out.as_dgram_ref().unwrap(), now());
+
+    // Then the connection limit.
+    let s2 = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    assert_eq!(
+        server_conn
+            .borrow_mut()
+            .stream_send(s2, &[0; 1000])
+            .unwrap(),
+        100
+    );
+    assert_eq!(
+        server_conn.flow_control_blocked(),
+        FlowControlState {
+            connection: true,
+            streams: vec![s1, s2],
+        }
+    );
+}
+
+#[test]
+fn stream_filter() {
+    const QUOTA: usize = 2;
+    let mut server = default_server();
+    let mut opened = 0;
+    server.set_stream_filter(Box::new(move |_, _| {
+        opened += 1;
+        opened <= QUOTA
+    }));
+    let mut client = default_client();
+    let mut server_conn = connect(&mut client, &mut server);
+
+    let streams = (0..4)
+        .map(|_| {
+            let stream_id = client.stream_create(StreamType::BiDi).unwrap();
+            client.stream_send(stream_id, &[1]).unwrap();
+            stream_id
+        })
+        .collect::<Vec<_>>();
+    let out = client.process_output(now()).dgram();
+    let out = server.process(out.as_ref(), now()).dgram();
+    client.process_input(out.as_ref().unwrap(), now());
+
+    // The server application only learns about the streams within the quota.
+    let opened = server_conn
+        .borrow_mut()
+        .events()
+        .filter_map(|e| match e {
+            ConnectionEvent::NewStream { stream_id } => Some(stream_id),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(opened, streams[..QUOTA]);
+
+    // The client sees the other streams refused.
+    let refused = client
+        .events()
+        .filter_map(|e| match e {
+            ConnectionEvent::SendStreamStopSending {
+                stream_id,
+                app_error,
+            } => {
+                assert_eq!(app_error, Error::StreamStateError.code());
+                Some(stream_id)
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(refused, streams[QUOTA..]);
+}
+
+#[test]
+fn new_token_0rtt() {
+    let mut server = default_server();
+    let token = generate_ticket(&mut server);
+    server.set_validation(ValidateAddress::NoToken);
+
+    let mut client = default_client();
+    client.enable_resumption(now(), &token).unwrap();
+
+    let client_stream = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(client_stream, &[1, 2, 3]).unwrap();
+
+    let out = client.process(None, now()); // Initial w/0-RTT
+    assert!(out.as_dgram_ref().is_some());
+    assertions::assert_initial(out.as_dgram_ref().unwrap(), true);
+    assertions::assert_coalesced_0rtt(out.as_dgram_ref().unwrap());
+    let out = server.process(out.as_dgram_ref(), now()); // Initial
+    assert!(out.as_dgram_ref().is_some());
+    assertions::assert_initial(out.as_dgram_ref().unwrap(), false);
+
+    let dgram = client.process(out.as_dgram_ref(), now());
+    // Note: the client doesn't need to authenticate the server here
+    // as there is no certificate; authentication is based on the ticket.
+    assert!(out.as_dgram_ref().is_some());
+    assert_eq!(*client.state(), State::Connected);
+    let dgram = server.process(dgram.as_dgram_ref(), now()); // (done)
+    assert!(dgram.as_dgram_ref().is_some());
+    connected_server(&mut server);
+    assert!(client.tls_info().unwrap().resumed());
+}
+
+#[test]
+fn new_token_different_port() {
+    let mut server = default_server();
+    let token = generate_ticket(&mut server);
+    server.set_validation(ValidateAddress::NoToken);
+
+    let mut client = default_client();
+    client.enable_resumption(now(), &token).unwrap();
 
     let dgram = client.process(None, now()).dgram(); // Initial
     assert!(dgram.is_some());
@@ -385,6 +1126,43 @@ fn new_token_different_port() {
     assertions::assert_initial(dgram.as_ref().unwrap(), false);
 }
 
+/// Make an Initial packet with the given token.  Only the header is valid,
+/// which is all that the server looks at before deciding to send a Retry.
+fn initial_with_token(token: &[u8]) -> Datagram {
+    let mut enc = Encoder::with_capacity(MIN_INITIAL_PACKET_SIZE);
+    enc.encode_byte(0xc0)
+        .encode_uint(4, Version::default().wire_version())
+        .encode_vec(1, &[0xdc; 8])
+        .encode_vec(1, &[0x5c; 8])
+        .encode_vvec(token);
+    // Leave two bytes for the length field.
+    let remaining = MIN_INITIAL_PACKET_SIZE - enc.len() - 2;
+    enc.encode_varint(u64::try_from(remaining).unwrap());
+    let mut packet = enc.as_ref().to_vec();
+    packet.resize(MIN_INITIAL_PACKET_SIZE, 0);
+    datagram(packet)
+}
+
+#[test]
+fn oversized_token() {
+    let mut server = default_server();
+    server.set_validation(ValidateAddress::Always);
+    server.set_max_token_length(100);
+
+    // A junk token at the limit is checked, fails to decrypt, and results in a Retry.
+    let dgram = server
+        .process(Some(&initial_with_token(&[0; 100])), now())
+        .dgram();
+    assertions::assert_retry(dgram.as_ref().unwrap());
+
+    // A token that is one byte longer is dropped without being checked.
+    let dgram = server
+        .process(Some(&initial_with_token(&[0; 101])), now())
+        .dgram();
+    assert!(dgram.is_none());
+    assert!(server.active_connections().is_empty());
+}
+
 #[test]
 fn bad_client_initial() {
     let mut client = default_client();
@@ -580,6 +1358,134 @@ fn version_negotiation_ignored() {
     assert_eq!(client.state(), &State::WaitInitial);
 }
 
+#[test]
+fn version_negotiation_callback() {
+    let mut server = default_server();
+    let attempts = Rc::new(RefCell::new(Vec::new()));
+    let attempts_copy = Rc::clone(&attempts);
+    server.set_on_version_negotiation(Box::new(move |version, addr| {
+        attempts_copy.borrow_mut().push((version, addr));
+    }));
+
+    let mut client = default_client();
+    let dgram = client.process(None, now()).dgram().expect("a datagram");
+    let mut input = dgram.to_vec();
+    input[1] ^= 0x12;
+    let version = u32::from_be_bytes(input[1..5].try_into().unwrap());
+    let damaged = Datagram::new(
+        dgram.source(),
+        dgram.destination(),
+        dgram.tos(),
+        dgram.ttl(),
+        input,
+    );
+    let vn = server.process(Some(&damaged), now()).dgram();
+    assertions::assert_vn(vn.as_ref().unwrap());
+
+    assert_eq!(*attempts.borrow(), vec![(version, dgram.source())]);
+}
+
+#[test]
+fn anti_replay_window() {
+    let mut server = default_server();
+    // The anti-replay context was created one window before the server.
+    let w = server.anti_replay_window();
+    assert_eq!(w.window, ANTI_REPLAY_WINDOW);
+    assert_eq!(w.low, now() - ANTI_REPLAY_WINDOW);
+    assert_eq!(w.high, now() + ANTI_REPLAY_WINDOW);
+
+    // The bounds move once a window has passed, the size does not change.
+    let later = now() + ANTI_REPLAY_WINDOW + ANTI_REPLAY_WINDOW / 2;
+    mem::drop(server.process(None, later));
+    let w = server.anti_replay_window();
+    assert_eq!(w.window, ANTI_REPLAY_WINDOW);
+    assert_eq!(w.low, now());
+    assert_eq!(w.high, now() + ANTI_REPLAY_WINDOW * 2);
+}
+
+/// Without a Retry, the address of the client is validated by the handshake.
+#[test]
+fn address_validated_by_handshake() {
+    let mut server = default_server();
+    let validated = Rc::new(RefCell::new(Vec::new()));
+    let validated_copy = Rc::clone(&validated);
+    server.set_on_address_validated(Box::new(move |_, addr| {
+        validated_copy.borrow_mut().push(addr);
+    }));
+
+    let mut client = default_client();
+    let dgram = client.process(None, now()).dgram(); // ClientHello
+    let client_addr = dgram.as_ref().unwrap().source();
+    let dgram = server.process(dgram.as_ref(), now()).dgram(); // ServerHello...
+    let dgram = client.process(dgram.as_ref(), now()).dgram(); // ACK
+    mem::drop(server.process(dgram.as_ref(), now()));
+    assert!(validated.borrow().is_empty());
+
+    client.authenticated(AuthenticationStatus::Ok, now());
+    let dgram = client.process(None, now()).dgram(); // Finished
+    mem::drop(server.process(dgram.as_ref(), now()));
+    connected_server(&mut server);
+    assert_eq!(*validated.borrow(), vec![client_addr]);
+}
+
+/// A connection ID generator that stops producing connection IDs after a while.
+struct LimitedConnectionIdGenerator {
+    inner: CountingConnectionIdGenerator,
+    remaining: usize,
+}
+
+impl ConnectionIdDecoder for LimitedConnectionIdGenerator {
+    fn decode_cid<'a>(&self, dec: &mut Decoder<'a>) -> Option<ConnectionIdRef<'a>> {
+        self.inner.decode_cid(dec)
+    }
+}
+
+impl ConnectionIdGenerator for LimitedConnectionIdGenerator {
+    fn generate_cid(&mut self) -> Option<ConnectionId> {
+        self.remaining = self.remaining.checked_sub(1)?;
+        self.inner.generate_cid()
+    }
+
+    fn as_decoder(&self) -> &dyn ConnectionIdDecoder {
+        self
+    }
+}
+
+#[test]
+fn cid_exhaustion() {
+    let mut server = Server::new(
+        now(),
+        test_fixture::DEFAULT_KEYS,
+        test_fixture::DEFAULT_ALPN,
+        test_fixture::anti_replay(),
+        Box::new(AllowZeroRtt {}),
+        Rc::new(RefCell::new(LimitedConnectionIdGenerator {
+            inner: CountingConnectionIdGenerator::default(),
+            remaining: 0,
+        })),
+        ConnectionParameters::default(),
+    )
+    .unwrap();
+    let exhausted = Rc::new(RefCell::new(0));
+    let exhausted_copy = Rc::clone(&exhausted);
+    server.set_on_cid_exhaustion(Box::new(move || *exhausted_copy.borrow_mut() += 1));
+
+    // The connection is refused without a response.
+    let mut client = default_client();
+    let initial = client.process_output(now()).dgram();
+    assert!(server.process(initial.as_ref(), now()).dgram().is_none());
+    assert!(server.active_connections().is_empty());
+    assert_eq!(*exhausted.borrow(), 1);
+
+    // The same happens when the server would send a Retry.
+    server.set_validation(ValidateAddress::Always);
+    let mut client = default_client();
+    let initial = client.process_output(now()).dgram();
+    assert!(server.process(initial.as_ref(), now()).dgram().is_none());
+    assert!(server.active_connections().is_empty());
+    assert_eq!(*exhausted.borrow(), 2);
+}
+
 /// Test that if the server doesn't support a version it will signal with a
 /// Version Negotiation packet and the client will use that version.
 #[test]
@@ -604,6 +1510,16 @@ fn version_negotiation() {
     assert_eq!(sconn.borrow().version(), VN_VERSION);
 }
 
+#[test]
+fn supported_versions() {
+    assert_eq!(default_server().supported_versions(), Version::all());
+
+    let versions = vec![Version::Version1, Version::Draft29];
+    let server =
+        new_server(ConnectionParameters::default().versions(Version::Version1, versions.clone()));
+    assert_eq!(server.supported_versions(), versions);
+}
+
 /// Test that the client can pick a version from a Version Negotiation packet,
 /// which is then subsequently upgraded to a compatible version by the server.
 #[test]
@@ -721,6 +1637,68 @@ fn closed() {
     assert_eq!(res, Output::None);
 }
 
+/// Connect, then have the server close the connection and forget about it.
+/// This returns the client, the server, a packet from the client that the
+/// server hasn't received yet, the server's `CONNECTION_CLOSE`, and the time.
+fn closed_with_late_packet(
+    policy: PostClosePolicy,
+) -> (Connection, Server, Datagram, Datagram, Instant) {
+    let mut server = default_server();
+    server.set_post_close_policy(policy);
+    let mut client = default_client();
+    let mut server_conn = connect(&mut client, &mut server);
+    let mut now = now();
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, &[7; 100]).unwrap();
+    let late = client.process_output(now).dgram().unwrap();
+
+    server_conn.borrow_mut().close(now, 0, "closing");
+    server.add_to_waiting(&server_conn);
+    let close = server.process(None, now).dgram().unwrap();
+
+    // Run the server until the connection is gone.
+    while let Output::Callback(t) = server.process(None, now) {
+        now += t;
+    }
+    (client, server, late, close, now)
+}
+
+#[test]
+fn post_close_drop() {
+    let (_, mut server, late, _, now) = closed_with_late_packet(PostClosePolicy::Drop);
+    assert!(server.process(Some(&late), now).dgram().is_none());
+}
+
+#[test]
+fn post_close_stateless_reset() {
+    let (mut client, mut server, late, _, now) =
+        closed_with_late_packet(PostClosePolicy::StatelessReset);
+    let reset = server.process(Some(&late), now).dgram().unwrap();
+    assert!(reset.len() < late.len());
+
+    client.process_input(&reset, now);
+    assert!(matches!(
+        client.state(),
+        State::Draining {
+            error: CloseReason::Transport(Error::StatelessReset),
+            ..
+        }
+    ));
+}
+
+#[test]
+fn post_close_replay_close() {
+    const COUNT: usize = 2;
+    let (_, mut server, late, close, now) =
+        closed_with_late_packet(PostClosePolicy::ReplayClose { count: COUNT });
+    for _ in 0..COUNT {
+        let replay = server.process(Some(&late), now).dgram().unwrap();
+        assert_eq!(replay, close);
+    }
+    assert!(server.process(Some(&late), now).dgram().is_none());
+}
+
 fn can_create_streams(c: &mut Connection, t: StreamType, n: u64) {
     for _ in 0..n {
         c.stream_create(t).unwrap();
@@ -836,6 +1814,23 @@ fn ech() {
         .unwrap());
 }
 
+#[test]
+fn ech_accepted() {
+    let mut server = default_server();
+    let (sk, pk) = generate_ech_keys().unwrap();
+    server.enable_ech(0x4a, "public.example", &sk, &pk).unwrap();
+
+    let mut client = default_client();
+    client.client_enable_ech(server.ech_config()).unwrap();
+    let with_ech = connect(&mut client, &mut server);
+    assert_eq!(with_ech.ech_accepted(), Some(true));
+
+    let mut client = default_client();
+    let without_ech = connect(&mut client, &mut server);
+    // NSS might not report anything for a client that didn't offer ECH.
+    assert_ne!(without_ech.ech_accepted(), Some(true));
+}
+
 #[test]
 fn has_active_connections() {
     let mut server = default_server();
@@ -848,3 +1843,946 @@ fn has_active_connections() {
 
     assert!(server.has_active_connections());
 }
+
+#[test]
+fn pto_count() {
+    let mut client = default_client();
+    let mut server = default_server();
+    let server_conn = connect(&mut client, &mut server);
+    let mut now = now();
+
+    let stream_id = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    server_conn
+        .borrow_mut()
+        .stream_send(stream_id, &[6; 100])
+        .unwrap();
+    server.add_to_waiting(&server_conn);
+    assert_eq!(server_conn.pto_count(), 0);
+
+    // Drop everything the server sends until the PTO has fired twice.
+    let mut probe = None;
+    while server_conn.pto_count() < 2 {
+        match server.process(None, now) {
+            Output::Datagram(d) => probe = Some(d),
+            Output::Callback(t) => now += t,
+            Output::None => panic!("the server should be waiting for a PTO"),
+        }
+    }
+
+    // Deliver the last probe and the acknowledgment resets the count.
+    client.process_input(&probe.unwrap(), now);
+    let ack = loop {
+        match client.process_output(now) {
+            Output::Datagram(d) => break d,
+            Output::Callback(t) => now += t,
+            Output::None => panic!("the client should acknowledge the probe"),
+        }
+    };
+    mem::drop(server.process(Some(&ack), now));
+    assert_eq!(server_conn.pto_count(), 0);
+}
+
+/// Datagrams that the server drops before they reach a connection are logged to the server trace.
+#[test]
+fn dropped_datagrams() {
+    let mut server = default_server();
+    let (log, contents) = new_neqo_qlog();
+    server.set_server_qlog(log);
+    let assert_dropped = |server: &mut Server, dgram: Option<Datagram>| {
+        assert!(server.process(dgram.as_ref(), now()).dgram().is_none());
+    };
+
+    // A truncated long header.
+    assert_dropped(&mut server, Some(datagram(vec![0xc0])));
+    // An unknown version, in a datagram too small for Version Negotiation.
+    assert_dropped(
+        &mut server,
+        Some(datagram(vec![0xc0, 0x1a, 0x2a, 0x3a, 0x4a, 0, 0])),
+    );
+
+    // A short header packet for a connection with a different server.
+    let mut client = default_client();
+    let mut other = default_server();
+    connect(&mut client, &mut other);
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, &[1; 10]).unwrap();
+    assert_dropped(&mut server, client.process_output(now()).dgram());
+
+    // An Initial that is too small for a Retry.
+    server.set_validation(ValidateAddress::Always);
+    server.set_retry_only_for_larger_initials(2000);
+    assert_dropped(&mut server, default_client().process_output(now()).dgram());
+
+    // An Initial while shutting down.
+    server.set_validation(ValidateAddress::Never);
+    mem::drop(server.initiate_shutdown_with_reason(0, "", now()));
+    assert_dropped(&mut server, default_client().process_output(now()).dgram());
+
+    let events = contents
+        .to_string()
+        .split_terminator('\n')
+        .skip(1) // The header.
+        .map(|r| serde_json::from_str::<Value>(r.strip_prefix('\u{1e}').unwrap()).unwrap())
+        .filter(|ev| ev["name"] == "transport:packet_dropped")
+        .map(|ev| {
+            (
+                ev["data"]["trigger"].as_str().unwrap().to_string(),
+                ev["data"]["details"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        [
+            ("invalid", "Undecodable"),
+            ("unsupported", "UnsupportedVersion"),
+            ("connection_unknown", "UnknownConnection"),
+            ("rejected", "RetryTooShort"),
+            ("rejected", "ShuttingDown"),
+        ]
+        .map(|(t, d)| (t.to_string(), d.to_string()))
+    );
+
+    for reason in [
+        DropReason::Undecodable,
+        DropReason::UnsupportedVersion,
+        DropReason::UnknownConnection,
+        DropReason::RetryTooShort,
+        DropReason::ShuttingDown,
+    ] {
+        assert_eq!(server.dropped(reason), 1, "{reason:?}");
+    }
+    assert_eq!(server.dropped(DropReason::InitialTooShort), 0);
+}
+
+/// Changing the ciphers only affects connections that are accepted afterwards.
+#[test]
+fn change_ciphers() {
+    let mut server = default_server();
+    assert!(server.ciphers().is_empty());
+
+    server.set_ciphers([TLS_AES_128_GCM_SHA256]);
+    let mut client = default_client();
+    let first = connect(&mut client, &mut server);
+
+    server.set_ciphers([TLS_CHACHA20_POLY1305_SHA256]);
+    assert_eq!(server.ciphers(), [TLS_CHACHA20_POLY1305_SHA256]);
+    let mut client = default_client();
+    let second = connect(&mut client, &mut server);
+
+    let cipher = |c: &ActiveConnectionRef| c.borrow().tls_info().unwrap().cipher_suite();
+    assert_eq!(cipher(&first), TLS_AES_128_GCM_SHA256);
+    assert_eq!(cipher(&second), TLS_CHACHA20_POLY1305_SHA256);
+}
+
+#[test]
+fn client_auth() {
+    let mut server = default_server();
+    server.require_client_auth(&[String::from("key")]);
+    let mut client = default_client();
+    client.client_set_certificate("key").unwrap();
+    let server_conn = connect(&mut client, &mut server);
+
+    let mut chain = server_conn.peer_certificate().unwrap();
+    let client_cert = (&mut chain).next().unwrap();
+    let mut server_chain = client.peer_certificate().unwrap();
+    // The client and server use the same certificate.
+    assert_eq!(client_cert, (&mut server_chain).next().unwrap());
+}
+
+#[test]
+fn client_auth_missing() {
+    let mut server = default_server();
+    server.require_client_auth(&[String::from("key")]);
+    server.set_validation(ValidateAddress::Never);
+    let mut client = default_client();
+
+    let out = client.process(None, now());
+    let out = server.process(out.as_dgram_ref(), now());
+    let out = client.process(out.as_dgram_ref(), now());
+    let out = server.process(out.as_dgram_ref(), now());
+    assert!(out.as_dgram_ref().is_none());
+    client.authenticated(AuthenticationStatus::Ok, now());
+    let out = client.process(None, now());
+    assert_eq!(*client.state(), State::Connected);
+
+    // The server rejects the handshake, because there is no client certificate.
+    let out = server.process(out.as_dgram_ref(), now());
+    assert!(out.as_dgram_ref().is_some());
+    mem::drop(client.process(out.as_dgram_ref(), now()));
+    assert!(matches!(
+        client.state(),
+        State::Draining { error: CloseReason::Transport(Error::PeerError(code)), .. }
+            if *code == Error::CryptoAlert(116).code()
+    ));
+}
+
+/// A client that changes address as fast as it can keeps retiring connection IDs,
+/// but the server only replaces them at the configured rate.
+#[test]
+fn cid_issuance_rate() {
+    const RATE: usize = 2;
+    let mut server = default_server();
+    server.set_cid_issuance_rate(u32::try_from(RATE).unwrap());
+    let mut client =
+        new_client(ConnectionParameters::default().path_idle_timeout(Duration::from_millis(10)));
+    let server_conn = connect(&mut client, &mut server);
+    let initial = server_conn.borrow().stats().frame_tx.new_connection_id;
+
+    let mut now = now();
+    let end = now + Duration::from_secs(1);
+    let mut port = test_fixture::DEFAULT_ADDR.port();
+    while now < end {
+        port += 1;
+        let local = SocketAddr::new(test_fixture::DEFAULT_ADDR.ip(), port);
+        // This fails when the client has no spare connection IDs left.
+        mem::drop(client.migrate(Some(local), None, true, now));
+        let mut out = client.process_output(now).dgram();
+        while let Some(d) = out {
+            let reply = server.process(Some(&d), now).dgram();
+            out = client.process(reply.as_ref(), now).dgram();
+        }
+        now += Duration::from_millis(20);
+    }
+
+    assert!(client.stats().frame_tx.retire_connection_id > RATE + 1);
+    // One replacement comes from what is left of the allowance for the initial set.
+    let issued = server_conn.borrow().stats().frame_tx.new_connection_id - initial;
+    assert!(issued <= RATE + 1);
+}
+
+#[test]
+fn server_name() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let server_conn = connect(&mut client, &mut server);
+
+    assert_eq!(
+        server_conn.server_name().as_deref(),
+        Some(test_fixture::DEFAULT_SERVER_NAME)
+    );
+    assert_eq!(client.server_name(), None);
+}
+
+/// Send data from the server with the given loss applied to what it sends.
+/// This returns how long the transfer took and how many packets were lost.
+#[cfg(feature = "test-network")]
+fn lossy_transfer(loss: f64) -> (Duration, usize) {
+    const DATA: usize = 100_000;
+    let mut server = default_server();
+    let mut client = default_client();
+    let mut server_conn = connect(&mut client, &mut server);
+    server_conn.set_test_network(loss, Duration::from_millis(10));
+
+    let stream_id = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    let sent = server_conn
+        .borrow_mut()
+        .stream_send(stream_id, &[0; DATA])
+        .unwrap();
+    assert_eq!(sent, DATA);
+    server_conn
+        .borrow_mut()
+        .stream_close_send(stream_id)
+        .unwrap();
+
+    let start = now();
+    let mut now = start;
+    let mut buf = vec![0; DATA];
+    let mut received = 0;
+    let mut to_server = None;
+    while received < DATA {
+        let out = server.process(to_server.take().as_ref(), now);
+        let server_wait = out.callback();
+        let to_client = out.dgram();
+        let server_sent = to_client.is_some();
+
+        let out = client.process(to_client.as_ref(), now);
+        let client_wait = out.callback();
+        to_server = out.dgram();
+        while let Some(e) = client.next_event() {
+            if let ConnectionEvent::RecvStreamReadable { stream_id } = e {
+                received += client.stream_recv(stream_id, &mut buf).unwrap().0;
+            }
+        }
+
+        if !server_sent && to_server.is_none() {
+            now += server_wait.min(client_wait);
+        }
+    }
+    let lost = server_conn.borrow().stats().lost;
+    (now - start, lost)
+}
+
+/// A connection can be made to lose packets, which slows it down.
+#[cfg(feature = "test-network")]
+#[test]
+fn test_network_loss() {
+    let (clean_time, clean_lost) = lossy_transfer(0.0);
+    assert_eq!(clean_lost, 0);
+
+    let (lossy_time, lossy_lost) = lossy_transfer(0.5);
+    assert!(lossy_lost > 0);
+    assert!(lossy_time > clean_time * 2);
+}
+
+/// The recovery statistics for a connection are consistent after a packet is lost.
+#[test]
+fn recovery_stats() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let mut server_conn = connect(&mut client, &mut server);
+    let before = server_conn.recovery_stats();
+    assert_eq!(before.packets_lost, 0);
+
+    let stream_id = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    server_conn
+        .borrow_mut()
+        .stream_send(stream_id, &[0; 10_000])
+        .unwrap();
+    let mut flight = Vec::new();
+    while let Some(d) = server.process(None, now()).dgram() {
+        flight.push(d);
+    }
+    assert!(flight.len() > 4);
+
+    // Drop the first datagram, so that the acknowledgment of the others leads to it being lost.
+    let later = now() + Duration::from_millis(10);
+    for d in &flight[1..] {
+        client.process_input(d, later);
+    }
+    let ack = client.process_output(later).dgram().unwrap();
+    let later = later + Duration::from_millis(10);
+    mem::drop(server.process(Some(&ack), later));
+
+    let stats = server_conn.recovery_stats();
+    assert_eq!(stats.packets_lost, 1);
+    assert_eq!(stats.spurious_losses, 0);
+    assert_eq!(stats.packets_sent, before.packets_sent + flight.len());
+    assert!(stats.packets_acked > before.packets_acked);
+    assert!(stats.packets_acked + stats.packets_lost <= stats.packets_sent);
+    assert_eq!(stats.ssthresh, stats.cwnd);
+    assert!(stats.cwnd < before.cwnd);
+    assert!(stats.min_rtt <= stats.latest_rtt);
+    assert_eq!(stats.latest_rtt, Duration::from_millis(20));
+}
+
+/// Congestion control leaves slow start when a packet is lost and moves to congestion
+/// avoidance once a packet sent after the loss is acknowledged.
+#[test]
+fn cc_phase() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let mut server_conn = connect(&mut client, &mut server);
+    assert_eq!(server_conn.cc_phase(), CongestionPhase::SlowStart);
+
+    let stream_id = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    server_conn
+        .borrow_mut()
+        .stream_send(stream_id, &[0; 10_000])
+        .unwrap();
+    let mut flight = Vec::new();
+    while let Some(d) = server.process(None, now()).dgram() {
+        flight.push(d);
+    }
+
+    // Drop the first datagram, so that it is declared lost.
+    let mut now = now() + Duration::from_millis(10);
+    for d in &flight[1..] {
+        client.process_input(d, now);
+    }
+    let ack = client.process_output(now).dgram().unwrap();
+    now += Duration::from_millis(10);
+    let mut out = server.process(Some(&ack), now).dgram();
+    assert_eq!(server_conn.cc_phase(), CongestionPhase::Recovery);
+
+    // Everything that the server sends now was sent after recovery started,
+    // so acknowledging it ends recovery.
+    server_conn
+        .borrow_mut()
+        .stream_send(stream_id, &[0; 1_000])
+        .unwrap();
+    // Step past the client's ACK delay each time, so that it always acknowledges.
+    for _ in 0..10 {
+        now += Duration::from_millis(30);
+        let ack = client.process(out.as_ref(), now).dgram();
+        out = server.process(ack.as_ref(), now).dgram();
+        if server_conn.cc_phase() != CongestionPhase::Recovery {
+            break;
+        }
+    }
+    assert_eq!(server_conn.cc_phase(), CongestionPhase::CongestionAvoidance);
+}
+
+/// A datagram from `client` that carries stream data.
+fn client_stream_datagram(client: &mut Connection) -> Datagram {
+    let stream_id = client.stream_create(StreamType::BiDi).unwrap();
+    client.stream_send(stream_id, &[1, 2, 3]).unwrap();
+    client.process_output(now()).dgram().unwrap()
+}
+
+/// Closed connections are removed from the connection table, and sweeping when
+/// nothing has closed leaves the other connections alone.
+#[test]
+fn sweep_closed() {
+    const CLIENTS: usize = 20;
+    let mut server = default_server();
+    let mut clients = (0..CLIENTS).map(|_| default_client()).collect::<Vec<_>>();
+    let server_conns = clients
+        .iter_mut()
+        .map(|c| connect(c, &mut server))
+        .collect::<Vec<_>>();
+    assert_eq!(server.sweep_closed(), 0);
+
+    let dgrams = clients
+        .iter_mut()
+        .map(client_stream_datagram)
+        .collect::<Vec<_>>();
+    for (d, c) in dgrams.iter().zip(&server_conns) {
+        let (_, routed) = server.process_into(Some(d), now());
+        assert_eq!(routed.as_ref(), Some(c));
+    }
+    assert_eq!(server.sweep_closed(), 0);
+
+    // Close the first connection from the client and wait for the server to
+    // finish draining.
+    clients[0].close(now(), 0, "");
+    let close = clients[0].process_output(now()).dgram();
+    mem::drop(server.process(close.as_ref(), now()));
+    let later = now() + Duration::from_secs(10);
+    while let Output::Datagram(_) = server.process(None, later) {}
+    assert!(matches!(server_conns[0].borrow().state(), State::Closed(_)));
+    // The sweep at the end of `process` already removed it.
+    assert_eq!(server.sweep_closed(), 0);
+
+    // Packets for the closed connection are no longer routed to it,
+    // but the others still work.
+    let (_, routed) = server.process_into(Some(&dgrams[0]), later);
+    assert!(routed.is_none());
+    let (_, routed) = server.process_into(Some(&dgrams[1]), later);
+    assert_eq!(routed.as_ref(), Some(&server_conns[1]));
+}
+
+/// Send datagrams that carry stream data from `client` on the paths from `sources`
+/// and return the number of paths the server is using.
+fn send_on_paths(
+    client: &mut Connection,
+    server: &mut Server,
+    server_conn: &ActiveConnectionRef,
+    sources: &[SocketAddr],
+) -> usize {
+    for source in sources {
+        let d = client_stream_datagram(client);
+        let d = Datagram::new(*source, d.destination(), d.tos(), d.ttl(), &d[..]);
+        mem::drop(server.process(Some(&d), now()));
+    }
+    server_conn
+        .borrow()
+        .paths()
+        .iter()
+        .filter(|p| p.active)
+        .count()
+}
+
+#[test]
+fn multipath() {
+    let other = SocketAddr::new(DEFAULT_ADDR.ip(), DEFAULT_ADDR.port() + 1);
+
+    let mut server = default_server();
+    server.set_multipath(2);
+    let mut client = new_client(ConnectionParameters::default().multipath(2));
+    let server_conn = connect(&mut client, &mut server);
+    assert!(server_conn.borrow().multipath_enabled());
+
+    // Packets that arrive on two paths, in any order, keep both paths active.
+    let sources = [other, DEFAULT_ADDR, other, DEFAULT_ADDR];
+    assert_eq!(
+        send_on_paths(&mut client, &mut server, &server_conn, &sources),
+        2
+    );
+    let paths = server_conn.borrow().paths();
+    assert!(paths.iter().any(|p| p.active && p.remote == DEFAULT_ADDR));
+    assert!(paths.iter().any(|p| p.active && p.remote == other));
+
+    // A third path is more than the server allows, so the peer migrates.
+    let third = SocketAddr::new(DEFAULT_ADDR.ip(), DEFAULT_ADDR.port() + 2);
+    assert_eq!(
+        send_on_paths(&mut client, &mut server, &server_conn, &[third]),
+        2
+    );
+    let paths = server_conn.borrow().paths();
+    assert!(paths.iter().any(|p| p.active && p.remote == third));
+}
+
+#[test]
+fn multipath_not_negotiated() {
+    let other = SocketAddr::new(DEFAULT_ADDR.ip(), DEFAULT_ADDR.port() + 1);
+
+    // The client does not offer multipath.
+    let mut server = default_server();
+    server.set_multipath(2);
+    let mut client = default_client();
+    let server_conn = connect(&mut client, &mut server);
+    assert!(!server_conn.borrow().multipath_enabled());
+
+    // Each change of address is a migration.
+    let sources = [other, DEFAULT_ADDR, other];
+    assert_eq!(
+        send_on_paths(&mut client, &mut server, &server_conn, &sources),
+        1
+    );
+}
+
+#[test]
+fn packet_overhead() {
+    const DATAGRAM_SIZE: u64 = 1200;
+    let params = ConnectionParameters::default().datagram_size(DATAGRAM_SIZE);
+    let mut server = new_server(params.clone());
+    let mut client = new_client(params);
+    let server_conn = connect(&mut client, &mut server);
+
+    let overhead = server_conn.packet_overhead();
+    assert!(overhead > 0);
+
+    // A datagram of the largest size fills a packet, so the only other bytes
+    // are the packet overhead and the DATAGRAM frame type.
+    let size = usize::try_from(server_conn.borrow().max_datagram_size().unwrap()).unwrap();
+    let payload = vec![0xd4; size];
+    server_conn
+        .borrow_mut()
+        .send_datagram(&payload, None)
+        .unwrap();
+    let dgram = server.process(None, now()).dgram().unwrap();
+    assert_eq!(dgram.len(), overhead + 1 + payload.len());
+
+    client.process_input(&dgram, now());
+    assert!(client
+        .events()
+        .any(|e| e == ConnectionEvent::Datagram(payload.clone())));
+}
+
+#[test]
+fn max_output_queue() {
+    const PACKETS: usize = 4;
+    let mut server = default_server();
+    server.set_max_output_queue(PACKETS);
+    let mut client = default_client();
+    let server_conn = connect(&mut client, &mut server);
+
+    // Write much more than the queue holds, without taking any output.
+    let stream_id = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    let data = [0x5a; 100_000];
+    let written = server_conn
+        .borrow_mut()
+        .stream_send(stream_id, &data)
+        .unwrap();
+    // The path MTU here is no more than the 1500 bytes of an Ethernet frame.
+    assert!(written > 0);
+    assert!(written <= PACKETS * 1500);
+
+    // Once the queue is full, writes are refused.
+    assert_eq!(
+        server_conn
+            .borrow_mut()
+            .stream_send(stream_id, &data[written..])
+            .unwrap(),
+        0
+    );
+    assert!(!server_conn
+        .borrow_mut()
+        .stream_send_atomic(stream_id, &data[..1])
+        .unwrap());
+    assert_eq!(
+        server_conn
+            .borrow()
+            .stream_avail_send_space(stream_id)
+            .unwrap(),
+        0
+    );
+
+    // Sending a packet makes room, which the stream is told about.
+    assert!(server.process(None, now()).dgram().is_some());
+    assert!(server_conn
+        .borrow_mut()
+        .events()
+        .any(|e| e == ConnectionEvent::SendStreamWritable { stream_id }));
+    assert!(
+        server_conn
+            .borrow_mut()
+            .stream_send(stream_id, &data[written..])
+            .unwrap()
+            > 0
+    );
+}
+
+#[test]
+fn reassembly_gaps() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let server_conn = connect(&mut client, &mut server);
+    assert!(server_conn.reassembly_gaps().is_empty());
+
+    // Send enough to need two packets, then deliver them out of order.
+    let stream_id = client.stream_create(StreamType::BiDi).unwrap();
+    let data = [0x11; 2000];
+    assert_eq!(client.stream_send(stream_id, &data).unwrap(), data.len());
+    let first = client.process(None, now()).dgram().unwrap();
+    let second = client.process(None, now()).dgram().unwrap();
+
+    mem::drop(server.process(Some(&second), now()));
+    let gaps = server_conn.reassembly_gaps();
+    assert_eq!(gaps.len(), 1);
+    assert_eq!(gaps[0].0, stream_id);
+    // The first packet carried the start of the stream.
+    assert!(gaps[0].1 > 0 && gaps[0].1 < 2000);
+
+    mem::drop(server.process(Some(&first), now()));
+    assert!(server_conn.reassembly_gaps().is_empty());
+}
+
+#[test]
+fn close_connection_transport() {
+    const FRAME_TYPE: u64 = 0x08;
+    let mut server = default_server();
+    let mut client = default_client();
+    let server_conn = connect(&mut client, &mut server);
+
+    // Take the connection ID from a packet that the client sends.
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, &[0]).unwrap();
+    let dgram = client.process(None, now()).dgram().unwrap();
+    let cid = CountingConnectionIdGenerator::default()
+        .decode_cid(&mut Decoder::from(&dgram[1..]))
+        .unwrap();
+
+    let close = server
+        .close_connection_transport(
+            cid,
+            Error::ProtocolViolation.code(),
+            Some(FRAME_TYPE),
+            "misbehaving",
+            now(),
+        )
+        .unwrap();
+    assert!(matches!(
+        server_conn.borrow().state(),
+        State::Closing {
+            error: CloseReason::Transport(Error::LocalError(10)),
+            ..
+        }
+    ));
+
+    // The client sees a transport error, not an application error.
+    client.process_input(&close, now());
+    assert!(matches!(
+        client.state(),
+        State::Draining {
+            error: CloseReason::Transport(Error::PeerError(10)),
+            ..
+        }
+    ));
+
+    // A connection is only closed once.
+    assert!(server
+        .close_connection_transport(cid, 10, None, "", now())
+        .is_none());
+}
+
+#[test]
+fn zero_rtt_flow_control() {
+    const MAX_DATA: u64 = 2000;
+    const MAX_STREAM_DATA: u64 = 1000;
+    const LEN: usize = 10_000;
+    let mut server = default_server();
+    server.set_zero_rtt_flow_control(MAX_DATA, MAX_STREAM_DATA);
+    let token = generate_ticket(&mut server);
+
+    let mut now = now();
+    let mut client = default_client();
+    client.enable_resumption(now, &token).unwrap();
+
+    // In 0-RTT, the client is limited by the remembered transport parameters.
+    let data = vec![0; LEN];
+    let streams = [0; 3].map(|_| client.stream_create(StreamType::BiDi).unwrap());
+    let sent = streams.map(|id| client.stream_send(id, &data).unwrap());
+    assert_eq!(sent, [1000, 1000, 0]);
+
+    let mut total = sent[2];
+    let mut dgram = None;
+    for _ in 0..100 {
+        dgram = client.process(dgram.as_ref(), now).dgram();
+        if client
+            .events()
+            .any(|e| matches!(e, ConnectionEvent::AuthenticationNeeded))
+        {
+            client.authenticated(AuthenticationStatus::Ok, now);
+        }
+        dgram = server.process(dgram.as_ref(), now).dgram();
+        if *client.state() == State::Confirmed {
+            total += client.stream_send(streams[2], &data[total..]).unwrap();
+            if total == LEN {
+                break;
+            }
+        }
+        now += Duration::from_millis(1);
+    }
+    // Once the handshake is done, the server allows the larger default limits.
+    assert_eq!(total, LEN);
+}
+
+#[test]
+fn process_with_diag() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let initial = client.process(None, now()).dgram().unwrap();
+
+    // Coalesce a copy of the Initial with its authentication tag damaged.
+    let mut payload = initial.to_vec();
+    let mut corrupt = initial.to_vec();
+    *corrupt.last_mut().unwrap() ^= 0x01;
+    payload.extend_from_slice(&corrupt);
+    let dgram = Datagram::new(
+        initial.source(),
+        initial.destination(),
+        initial.tos(),
+        initial.ttl(),
+        payload,
+    );
+
+    let (out, diag) = server.process_with_diag(&dgram, now());
+    assert!(out.dgram().is_some());
+    assert_eq!(diag.processed, 1);
+    assert_eq!(diag.saved, 0);
+    assert_eq!(diag.dropped, [PacketDropReason::DecryptionFailure]);
+    assert!(diag.server_dropped.is_empty());
+
+    // A datagram that doesn't reach a connection reports why.
+    let (_, diag) = server.process_with_diag(&datagram(vec![0xc0]), now());
+    assert_eq!(diag.processed, 0);
+    assert!(diag.dropped.is_empty());
+    assert_eq!(diag.server_dropped, [DropReason::Undecodable]);
+}
+
+const PSK_IDENTITY: &[u8] = b"sensor-17";
+const PSK: &[u8] = &[0x5a; 32];
+
+/// Run a handshake with a client that has an external PSK, without ever
+/// authenticating the server certificate.
+fn external_psk_handshake(client_identity: &[u8]) -> (Connection, Server) {
+    let mut server = default_server();
+    server.set_external_psk(PSK_IDENTITY, PSK, TLS_AES_128_GCM_SHA256);
+    psk_client_handshake(client_identity, server)
+}
+
+fn psk_client_handshake(client_identity: &[u8], mut server: Server) -> (Connection, Server) {
+    let mut client = default_client();
+    client
+        .add_external_psk(client_identity, PSK, TLS_AES_128_GCM_SHA256)
+        .unwrap();
+
+    let mut dgram = None;
+    for _ in 0..10 {
+        dgram = client.process(dgram.as_ref(), now()).dgram();
+        dgram = server.process(dgram.as_ref(), now()).dgram();
+    }
+    (client, server)
+}
+
+#[test]
+fn external_psk() {
+    let (mut client, mut server) = external_psk_handshake(PSK_IDENTITY);
+    assert_eq!(*client.state(), State::Confirmed);
+    // The server was authenticated by the PSK and sent no certificate.
+    assert!(!client
+        .events()
+        .any(|e| matches!(e, ConnectionEvent::AuthenticationNeeded)));
+    assert!(client.peer_certificate().is_none());
+    connected_server(&mut server);
+}
+
+#[test]
+fn external_psk_mismatched_identity() {
+    let (client, _server) = external_psk_handshake(b"sensor-18");
+    // Without the PSK, the handshake needs the server certificate,
+    // which this client never authenticates.
+    assert!(!matches!(
+        client.state(),
+        State::Connected | State::Confirmed
+    ));
+}
+
+/// Setting a second key replaces the first.
+#[test]
+fn external_psk_replaced() {
+    let mut server = default_server();
+    server.set_external_psk(b"sensor-18", PSK, TLS_AES_128_GCM_SHA256);
+    server.set_external_psk(PSK_IDENTITY, PSK, TLS_AES_128_GCM_SHA256);
+    let (client, _server) = psk_client_handshake(b"sensor-18", server);
+    assert!(!matches!(
+        client.state(),
+        State::Connected | State::Confirmed
+    ));
+
+    let mut server = default_server();
+    server.set_external_psk(b"sensor-18", PSK, TLS_AES_128_GCM_SHA256);
+    server.set_external_psk(PSK_IDENTITY, PSK, TLS_AES_128_GCM_SHA256);
+    let (client, _server) = psk_client_handshake(PSK_IDENTITY, server);
+    assert_eq!(*client.state(), State::Confirmed);
+}
+
+#[test]
+fn datagram_stats() {
+    const DATAGRAM_SIZE: u64 = 10_000;
+    let params = ConnectionParameters::default().datagram_size(DATAGRAM_SIZE);
+    let mut server = new_server(params.clone());
+    let mut client = new_client(params);
+    let mut server_conn = connect(&mut client, &mut server);
+    assert_eq!(server_conn.datagram_stats(), DatagramStats::default());
+
+    // DATAGRAM frames cannot be split across packets, so only the datagrams
+    // that fit are sent.
+    for (i, size) in [10, 1000, 1500, 5000].into_iter().enumerate() {
+        server_conn
+            .borrow_mut()
+            .send_datagram(&vec![0; size], Some(u64::try_from(i).unwrap()))
+            .unwrap();
+    }
+    server.add_to_waiting(&server_conn);
+    while let Some(dgram) = server.process_output(now()).dgram() {
+        client.process_input(&dgram, now());
+    }
+    let stats = server_conn.datagram_stats();
+    assert_eq!(stats.sent, 2);
+    assert_eq!(stats.dropped_too_big, 2);
+    assert_eq!(client.stats().datagram_tx.received, 2);
+
+    client.send_datagram(&[1; 20], None).unwrap();
+    let dgram = client.process_output(now()).dgram();
+    server.process(dgram.as_ref(), now());
+    assert_eq!(server_conn.datagram_stats().received, 1);
+
+    // Resetting starts the counters again without changing the totals.
+    server_conn.reset_datagram_stats();
+    assert_eq!(server_conn.datagram_stats(), DatagramStats::default());
+    assert_eq!(server_conn.borrow().stats().datagram_tx.dropped_too_big, 2);
+}
+
+/// A connection with a keep-alive interval sends PING when it is idle,
+/// and outlives its idle timeout.
+#[test]
+fn keep_alive() {
+    const INTERVAL: Duration = Duration::from_secs(5);
+    let mut server = default_server();
+    server.set_keep_alive(Some(INTERVAL));
+    let mut client = default_client();
+    let server_conn = connect(&mut client, &mut server);
+
+    let pings_before = client.stats().frame_rx.ping;
+    let idle_timeout = ConnectionParameters::default().get_idle_timeout();
+    let mut now = now();
+    let end = now + idle_timeout * 2;
+    let mut dgram = None;
+    while now < end {
+        match server.process(dgram.take().as_ref(), now) {
+            Output::Datagram(d) => dgram = client.process(Some(&d), now).dgram(),
+            Output::Callback(delay) => {
+                assert!(delay <= INTERVAL);
+                now += delay;
+                // Let the client send its acknowledgments.
+                dgram = client.process(None, now).dgram();
+            }
+            Output::None => panic!("the server has no connections"),
+        }
+    }
+
+    let pings = client.stats().frame_rx.ping - pings_before;
+    // At least one PING for every two intervals, allowing for the time spent waiting for ACKs.
+    assert!(pings >= 6);
+    assert_eq!(*client.state(), State::Confirmed);
+    assert_eq!(*server_conn.borrow().state(), State::Confirmed);
+}
+
+/// The pacer spreads out the packets that fill the congestion window,
+/// and the connection reports how long it is waiting for the pacer.
+#[test]
+fn pacing_delay() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let mut server_conn = connect(&mut client, &mut server);
+    assert_eq!(server_conn.pacing_delay(now()), Duration::ZERO);
+
+    let stream_id = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    server_conn
+        .borrow_mut()
+        .stream_send(stream_id, &[0; 100_000])
+        .unwrap();
+
+    let mut now = now();
+    let mut paced = 0;
+    loop {
+        match server.process(None, now) {
+            Output::Datagram(_) => {}
+            Output::Callback(delay) => {
+                let pacing = server_conn.pacing_delay(now);
+                if pacing.is_zero() {
+                    // Blocked by the congestion window instead.
+                    break;
+                }
+                assert!(delay <= pacing);
+                paced += 1;
+                now += pacing;
+            }
+            Output::None => panic!("the server has no connections"),
+        }
+    }
+
+    assert!(paced > 0);
+    let stats = server_conn.recovery_stats();
+    assert!(stats.bytes_in_flight > stats.cwnd / 2);
+}
+
+/// Connections that start with different versions negotiate ALPN from the
+/// values that are configured for each version.
+#[test]
+fn protocols_for_version() {
+    let mut server = default_server();
+    server.set_protocols_for_version(Version::Version2, &["alpn-v2"]);
+
+    for (version, expected) in [(Version::Version1, "alpn"), (Version::Version2, "alpn-v2")] {
+        let mut client =
+            new_client(ConnectionParameters::default().versions(version, vec![version]));
+        client.set_alpn(&["alpn", "alpn-v2"]).unwrap();
+        let server_conn = connect(&mut client, &mut server);
+        assert_eq!(client.version(), version);
+        assert_eq!(
+            client.tls_info().unwrap().alpn().map(String::as_str),
+            Some(expected)
+        );
+        assert_eq!(
+            server_conn
+                .borrow()
+                .tls_info()
+                .unwrap()
+                .alpn()
+                .map(String::as_str),
+            Some(expected)
+        );
+    }
+}
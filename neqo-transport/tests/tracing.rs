@@ -0,0 +1,103 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![cfg(feature = "tracing")]
+
+mod common;
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use common::{connect, default_server};
+use neqo_common::tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use test_fixture::default_client;
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+/// An event that was collected, with the names of the spans it was in, innermost first.
+#[derive(Debug)]
+struct Collected {
+    spans: Vec<String>,
+    fields: Vec<(String, String)>,
+}
+
+impl Collected {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find_map(|(n, v)| (n == name).then_some(v.as_str()))
+    }
+}
+
+#[derive(Default)]
+struct Fields(Vec<(String, String)>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .push((field.name().to_string(), format!("{value:?}")));
+    }
+}
+
+#[derive(Clone, Default)]
+struct Collector(Arc<Mutex<Vec<Collected>>>);
+
+impl<S> Layer<S> for Collector
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let spans = ctx
+            .event_scope(event)
+            .map(|scope| scope.map(|s| s.name().to_string()).collect())
+            .unwrap_or_default();
+        self.0.lock().unwrap().push(Collected {
+            spans,
+            fields: fields.0,
+        });
+    }
+}
+
+#[test]
+fn events_have_context_and_spans() {
+    let collector = Collector::default();
+    let subscriber = tracing_subscriber::registry().with(collector.clone());
+    neqo_common::tracing::subscriber::with_default(subscriber, || {
+        let mut client = default_client();
+        let mut server = default_server();
+        connect(&mut client, &mut server);
+    });
+
+    let events = collector.0.lock().unwrap();
+    assert!(!events.is_empty());
+
+    // Events from a connection carry the connection as a field, not as a prefix.
+    let conn_event = events
+        .iter()
+        .find(|e| e.field("context").is_some_and(|c| c.starts_with("Server ")))
+        .expect("an event from the server connection");
+    assert!(!conn_event.field("message").unwrap().starts_with('['));
+
+    // Work done by the server connection is nested within the server's span.
+    let nested = events.iter().find(|e| {
+        e.spans.first().map(String::as_str) == Some("handshake")
+            && e.spans.iter().any(|s| s.starts_with("Connection::"))
+            && e.spans.last().map(String::as_str) == Some("Server::process")
+    });
+    assert!(nested.is_some(), "no nested events in {events:?}");
+
+    // The client connection has its own spans, outside of any server span.
+    assert!(events.iter().any(|e| {
+        e.spans.iter().any(|s| s.starts_with("Connection::"))
+            && !e.spans.iter().any(|s| s == "Server::process")
+    }));
+}
@@ -25,7 +25,7 @@ use neqo_common::{
     qtrace, Datagram, Decoder, IpTosEcn, Role,
 };
 use neqo_crypto::{init_db, random, AllowZeroRtt, AntiReplay, AuthenticationStatus};
-use neqo_http3::{Http3Client, Http3Parameters, Http3Server};
+use neqo_http3::{Http3Client, Http3ClientEvent, Http3Parameters, Http3Server};
 use neqo_transport::{
     version::WireVersion, Connection, ConnectionEvent, ConnectionId, ConnectionIdDecoder,
     ConnectionIdGenerator, ConnectionIdRef, ConnectionParameters, State, Version,
@@ -324,6 +324,74 @@ pub fn default_http3_server() -> Http3Server {
     .expect("create a default server")
 }
 
+/// Create a http3 server.
+///
+/// # Panics
+///
+/// When the server can't be created.
+#[must_use]
+pub fn http3_server_with_params(params: Http3Parameters) -> Http3Server {
+    fixture_init();
+    Http3Server::new(
+        now(),
+        DEFAULT_KEYS,
+        DEFAULT_ALPN_H3,
+        anti_replay(),
+        Rc::new(RefCell::new(CountingConnectionIdGenerator::default())),
+        params,
+        None,
+    )
+    .expect("create a server")
+}
+
+fn http3_exchange(
+    client: &mut Http3Client,
+    server: &mut Http3Server,
+    now: &mut Instant,
+    step: Duration,
+) {
+    let mut out = None;
+    loop {
+        *now += step;
+        out = client.process(out.as_ref(), *now).dgram();
+        out = server.process(out.as_ref(), *now).dgram();
+        if out.is_none() {
+            break;
+        }
+    }
+}
+
+/// Exchange packets between a http3 client and server until neither has
+/// anything more to send.  This all happens at `now()`.
+pub fn http3_exchange_packets(client: &mut Http3Client, server: &mut Http3Server) {
+    http3_exchange(client, server, &mut now(), Duration::ZERO);
+}
+
+/// Exchange packets between a http3 client and server until neither has
+/// anything more to send.  Time moves on from `now` a little each round,
+/// so that pacing does not hold packets back.
+pub fn http3_exchange_packets_paced(
+    client: &mut Http3Client,
+    server: &mut Http3Server,
+    now: &mut Instant,
+) {
+    http3_exchange(client, server, now, Duration::from_millis(1));
+}
+
+/// Complete the handshake between a http3 client and server, with the client
+/// accepting the server's certificate.
+///
+/// # Panics
+///
+/// When the client doesn't ask to authenticate the server.
+pub fn http3_connect(client: &mut Http3Client, server: &mut Http3Server) {
+    http3_exchange_packets(client, server);
+    let authentication_needed = |e| matches!(e, Http3ClientEvent::AuthenticationNeeded);
+    assert!(client.events().any(authentication_needed));
+    client.authenticated(AuthenticationStatus::Ok, now());
+    http3_exchange_packets(client, server);
+}
+
 /// Split the first packet off a coalesced packet.
 fn split_packet(buf: &[u8]) -> (&[u8], Option<&[u8]>) {
     const TYPE_MASK: u8 = 0b1011_0000;
@@ -8,11 +8,13 @@
 
 use std::{
     cmp::min,
+    collections::HashSet,
     fmt::{self, Debug},
+    net::SocketAddr,
     time::Instant,
 };
 
-use neqo_common::{event::Provider, qdebug, qinfo, qtrace, Datagram};
+use neqo_common::{event::Provider, qdebug, qinfo, qlog::NeqoQlog, qtrace, Datagram};
 use neqo_crypto::AuthenticationStatus;
 use neqo_transport::{
     Connection, ConnectionEvent, ConnectionParameters, Output, State, StreamId, StreamType,
@@ -105,6 +107,11 @@ impl ConnectionNode {
         self.goals.push(goal);
     }
 
+    /// Attach a qlog to the connection.
+    pub fn set_qlog(&mut self, qlog: NeqoQlog) {
+        self.c.set_qlog(qlog);
+    }
+
     /// On the first call to this method, the setup goals will turn into the active goals.
     /// On the second call, they will be swapped back and the main goals will run.
     fn setup_goals(&mut self, now: Instant) {
@@ -216,6 +223,161 @@ impl ConnectionGoal for ReachState {
     }
 }
 
+/// A target for a connection that involves reaching a given path MTU.
+#[derive(Debug, Clone)]
+pub struct ReachMtu {
+    target: usize,
+}
+
+impl ReachMtu {
+    #[must_use]
+    pub fn new(target: usize) -> Self {
+        Self { target }
+    }
+}
+
+impl ConnectionGoal for ReachMtu {
+    fn process(&mut self, c: &mut Connection, _now: Instant) -> GoalStatus {
+        if c.stats().pmtu == self.target {
+            GoalStatus::Done
+        } else {
+            GoalStatus::Waiting
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        c: &mut Connection,
+        _e: &ConnectionEvent,
+        now: Instant,
+    ) -> GoalStatus {
+        self.process(c, now)
+    }
+}
+
+/// Check that the connection never tracks more than `limit` paths.
+/// This is done once the connection has seen more than `limit` distinct remote addresses.
+#[derive(Debug, Clone)]
+pub struct PathLimit {
+    limit: usize,
+    seen: HashSet<SocketAddr>,
+}
+
+impl PathLimit {
+    #[must_use]
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            seen: HashSet::new(),
+        }
+    }
+}
+
+impl ConnectionGoal for PathLimit {
+    fn process(&mut self, c: &mut Connection, _now: Instant) -> GoalStatus {
+        let paths = c.paths();
+        assert!(
+            paths.len() <= self.limit,
+            "{} paths exceeds the limit of {}",
+            paths.len(),
+            self.limit
+        );
+        self.seen.extend(paths.iter().map(|p| p.remote));
+        if self.seen.len() > self.limit {
+            GoalStatus::Done
+        } else {
+            GoalStatus::Waiting
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        c: &mut Connection,
+        _e: &ConnectionEvent,
+        now: Instant,
+    ) -> GoalStatus {
+        self.process(c, now)
+    }
+}
+
+/// Have the connection migrate to a new local address.
+/// This goal is done once the new path is the active path.
+#[derive(Debug, Clone)]
+pub struct Migrate {
+    local: SocketAddr,
+    started: bool,
+}
+
+impl Migrate {
+    #[must_use]
+    pub fn new(local: SocketAddr) -> Self {
+        Self {
+            local,
+            started: false,
+        }
+    }
+}
+
+impl ConnectionGoal for Migrate {
+    fn process(&mut self, c: &mut Connection, now: Instant) -> GoalStatus {
+        if !self.started {
+            // This can fail if the peer hasn't provided spare connection IDs yet.
+            if c.migrate(Some(self.local), None, false, now).is_err() {
+                return GoalStatus::Waiting;
+            }
+            self.started = true;
+            return GoalStatus::Active;
+        }
+        if c.paths().iter().any(|p| p.active && p.local == self.local) {
+            GoalStatus::Done
+        } else {
+            GoalStatus::Waiting
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        c: &mut Connection,
+        _e: &ConnectionEvent,
+        now: Instant,
+    ) -> GoalStatus {
+        self.process(c, now)
+    }
+}
+
+/// Have the connection update its keys each time it sends the given number of packets.
+/// This goal is done as soon as the policy is in place.
+#[derive(Debug, Clone)]
+pub struct UpdateKeys {
+    after_packets: u64,
+}
+
+impl UpdateKeys {
+    #[must_use]
+    pub fn new(after_packets: u64) -> Self {
+        Self { after_packets }
+    }
+}
+
+impl ConnectionGoal for UpdateKeys {
+    fn init(&mut self, c: &mut Connection, _now: Instant) {
+        c.set_key_update_policy(Some(self.after_packets), None);
+    }
+
+    fn process(&mut self, _c: &mut Connection, _now: Instant) -> GoalStatus {
+        GoalStatus::Done
+    }
+
+    fn handle_event(
+        &mut self,
+        _c: &mut Connection,
+        _e: &ConnectionEvent,
+        _now: Instant,
+    ) -> GoalStatus {
+        GoalStatus::Done
+    }
+}
+
 /// A target for a connection that involves sending a given amount of data on the indicated stream.
 #[derive(Debug, Clone)]
 pub struct SendData {
@@ -8,7 +8,9 @@
 pub mod connection;
 mod delay;
 mod drop;
+mod mtu;
 pub mod rng;
+mod spray;
 mod taildrop;
 
 use std::{
@@ -28,7 +30,13 @@ use NodeState::{Active, Idle, Waiting};
 use crate::now;
 
 pub mod network {
-    pub use super::{delay::Delay, drop::Drop, taildrop::TailDrop};
+    pub use super::{
+        delay::Delay,
+        drop::Drop,
+        mtu::Mtu,
+        spray::{PortRestore, PortSpray},
+        taildrop::TailDrop,
+    };
 }
 
 type Rng = Rc<RefCell<Random>>;
@@ -0,0 +1,81 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![allow(clippy::module_name_repetitions)]
+
+use std::{
+    fmt::{self, Debug},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use neqo_common::{qtrace, Datagram};
+use neqo_transport::Output;
+
+use super::Node;
+
+/// A link with a limited MTU, which drops any datagram that doesn't fit.
+/// The MTU includes IP and UDP headers.
+pub struct Mtu {
+    mtu: usize,
+    /// An optional change to the MTU, which happens a fixed time after setup completes.
+    change: Option<(Duration, usize)>,
+    start: Option<Instant>,
+}
+
+impl Mtu {
+    #[must_use]
+    pub fn new(mtu: usize) -> Self {
+        Self {
+            mtu,
+            change: None,
+            start: None,
+        }
+    }
+
+    /// Change the MTU to `mtu` once `after` has passed since setup completed.
+    #[must_use]
+    pub fn change_after(mut self, after: Duration, mtu: usize) -> Self {
+        self.change = Some((after, mtu));
+        self
+    }
+
+    fn current(&self, now: Instant) -> usize {
+        match (self.change, self.start) {
+            (Some((after, mtu)), Some(start)) if now >= start + after => mtu,
+            _ => self.mtu,
+        }
+    }
+}
+
+impl Node for Mtu {
+    fn prepare(&mut self, now: Instant) {
+        self.start = Some(now);
+    }
+
+    fn process(&mut self, d: Option<Datagram>, now: Instant) -> Output {
+        if let Some(dgram) = d {
+            let header = match dgram.destination().ip() {
+                IpAddr::V4(_) => 20 + 8,
+                IpAddr::V6(_) => 40 + 8,
+            };
+            if dgram.len() + header > self.current(now) {
+                qtrace!("mtu drop {}", dgram.len());
+                Output::None
+            } else {
+                Output::Datagram(dgram)
+            }
+        } else {
+            Output::None
+        }
+    }
+}
+
+impl Debug for Mtu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mtu {}", self.mtu)
+    }
+}
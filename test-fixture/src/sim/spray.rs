@@ -0,0 +1,122 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![allow(clippy::module_name_repetitions)]
+
+use std::{
+    fmt::{self, Debug},
+    net::SocketAddr,
+    time::Instant,
+};
+
+use neqo_common::{qtrace, Datagram};
+use neqo_transport::Output;
+
+use super::{Node, Rng};
+
+/// Rewrites the source port of every `every`th datagram to a random value.
+/// This looks like an on-path attacker spraying spoofed addresses
+/// (or a very unstable NAT) to the receiver.
+/// Spraying only starts once setup is complete.
+pub struct PortSpray {
+    every: usize,
+    count: usize,
+    active: bool,
+    rng: Option<Rng>,
+}
+
+impl PortSpray {
+    /// # Panics
+    /// If `every` is zero.
+    #[must_use]
+    pub fn new(every: usize) -> Self {
+        assert_ne!(every, 0);
+        Self {
+            every,
+            count: 0,
+            active: false,
+            rng: None,
+        }
+    }
+}
+
+impl Node for PortSpray {
+    fn init(&mut self, rng: Rng, _now: Instant) {
+        self.rng = Some(rng);
+    }
+
+    fn prepare(&mut self, _now: Instant) {
+        self.active = true;
+    }
+
+    fn process(&mut self, d: Option<Datagram>, _now: Instant) -> Output {
+        let Some(dgram) = d else {
+            return Output::None;
+        };
+        if !self.active {
+            return Output::Datagram(dgram);
+        }
+        self.count += 1;
+        if self.count % self.every != 0 {
+            return Output::Datagram(dgram);
+        }
+        let port = self
+            .rng
+            .as_ref()
+            .unwrap()
+            .borrow_mut()
+            .random_from(1024..0x1_0000);
+        let src = SocketAddr::new(dgram.source().ip(), u16::try_from(port).unwrap());
+        qtrace!("spray {} -> {}", dgram.source(), src);
+        Output::Datagram(Datagram::new(
+            src,
+            dgram.destination(),
+            dgram.tos(),
+            dgram.ttl(),
+            &dgram[..],
+        ))
+    }
+}
+
+impl Debug for PortSpray {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "port spray 1/{}", self.every)
+    }
+}
+
+/// Rewrites the destination of every datagram to a fixed address.
+/// This undoes the effect of `PortSpray` for datagrams going the other way,
+/// so that the sprayed endpoint still reaches its peer.
+pub struct PortRestore {
+    addr: SocketAddr,
+}
+
+impl PortRestore {
+    #[must_use]
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr }
+    }
+}
+
+impl Node for PortRestore {
+    fn process(&mut self, d: Option<Datagram>, _now: Instant) -> Output {
+        d.map_or(Output::None, |dgram| {
+            Output::Datagram(Datagram::new(
+                dgram.source(),
+                self.addr,
+                dgram.tos(),
+                dgram.ttl(),
+                &dgram[..],
+            ))
+        })
+    }
+}
+
+impl Debug for PortRestore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "restore {}", self.addr)
+    }
+}